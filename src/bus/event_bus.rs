@@ -1,4 +1,4 @@
-use super::message::{Message, PublishMessage};
+use super::message::{AckMessage, Message, PublishMessage};
 use crate::datamodel::batch::Batch;
 use anyhow::Result;
 use std::sync::Arc;
@@ -8,6 +8,8 @@ pub struct EventBus {
     pub name: String,
     pub main_bus_sender: async_broadcast::Sender<Message>,
     pub main_bus_receiver: async_broadcast::InactiveReceiver<Message>,
+    pub acks_sender: async_broadcast::Sender<AckMessage>,
+    pub acks_receiver: async_broadcast::InactiveReceiver<AckMessage>,
 }
 
 impl EventBus {
@@ -17,10 +19,20 @@ impl EventBus {
     pub fn init(name: String) -> Self {
         let (s, r) = async_broadcast::broadcast(128);
         let r = r.deactivate();
+
+        // The acknowledgements are best effort, they are dropped when
+        // nobody listens and the oldest ones are dropped for the slow listeners.
+        let (mut acks_sender, acks_receiver) = async_broadcast::broadcast(128);
+        acks_sender.set_overflow(true);
+        acks_sender.set_await_active(false);
+        let acks_receiver = acks_receiver.deactivate();
+
         Self {
             name,
             main_bus_sender: s,
             main_bus_receiver: r,
+            acks_sender,
+            acks_receiver,
         }
     }
 
@@ -29,24 +41,33 @@ impl EventBus {
         Ok(())
     }
 
-    pub async fn publish(&self, batch: Batch) -> Result<async_broadcast::InactiveReceiver<()>> {
+    /// Publishes the batch on the main bus.
+    ///
+    /// The returned receiver is already active, so the storage backends
+    /// can't skip the sync of a batch published before it is awaited.
+    /// It is closed without a message when the batch isn't stored.
+    pub async fn publish(&self, batch: Batch) -> Result<async_broadcast::Receiver<()>> {
         // We create a new broadcast channel to receive the sync message.
         // It can technically have multiple emitters and multiple receivers.
         // In most cases, it should be a one to one relationship, but
         // it could be possible to have multiple storage backends and a single
         // receiver that waits for the first one to sync, or all.
         let (sync_sender, sync_receiver) = async_broadcast::broadcast(1);
-        let sync_receiver = sync_receiver.deactivate();
 
         self.broadcast(Message::Publish(PublishMessage {
             batch: Arc::new(batch),
             sync_sender,
-            sync_receiver: sync_receiver.clone(),
+            sync_receiver: sync_receiver.clone().deactivate(),
         }))
         .await?;
 
         Ok(sync_receiver)
     }
+
+    /// Broadcasts an acknowledgement to the listeners, if any.
+    pub fn ack(&self, ack: AckMessage) {
+        let _ = self.acks_sender.try_broadcast(ack);
+    }
 }
 
 pub fn init_event_bus() -> Arc<EventBus> {
@@ -96,9 +117,9 @@ mod tests {
             }
         });
 
-        let sync_receiver = event_bus.publish(batch).await.unwrap();
+        let mut sync_receiver = event_bus.publish(batch).await.unwrap();
 
-        sync_receiver.clone().activate().recv().await.unwrap();
+        sync_receiver.recv().await.unwrap();
 
         assert!(*has_received.lock().await);
     }
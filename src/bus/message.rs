@@ -1,5 +1,7 @@
 use crate::datamodel::batch::Batch;
+use serde::Serialize;
 use std::sync::Arc;
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub enum Message {
@@ -17,3 +19,20 @@ pub struct PublishMessage {
     pub sync_sender: async_broadcast::Sender<()>,
     pub sync_receiver: async_broadcast::InactiveReceiver<()>,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AckStatus {
+    /// The storage backends have committed and synced the batch.
+    Stored,
+    /// The batch couldn't be confirmed as stored.
+    Failed,
+}
+
+/// Acknowledgement of an asynchronous ingestion.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AckMessage {
+    pub batch_id: Uuid,
+    pub status: AckStatus,
+    pub sample_count: usize,
+}
@@ -1,13 +1,22 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
+use crate::storage::sync_timeout::StorageError;
 use anyhow::Result;
 use async_broadcast::{InactiveReceiver, Receiver, Sender};
 use tokio::sync::Mutex;
 
+/// Waits for the syncs of the published batches.
+///
+/// A sync channel closed without a message means that its batch
+/// wasn't stored, and fails the wait with [`StorageError::NotStored`].
 #[derive(Debug)]
 pub struct WaitForAll {
     nb_started: Arc<Mutex<usize>>,
     nb_finished: Arc<Mutex<usize>>,
+    failed: Arc<AtomicBool>,
     finished_sender: Sender<()>,
     finished_receiver: InactiveReceiver<()>,
 }
@@ -18,6 +27,7 @@ impl WaitForAll {
         Self {
             nb_started: Arc::new(Mutex::new(0)),
             nb_finished: Arc::new(Mutex::new(0)),
+            failed: Arc::new(AtomicBool::new(false)),
             finished_sender: s,
             finished_receiver: r.deactivate(),
         }
@@ -30,10 +40,13 @@ impl WaitForAll {
         }
         let nb_started_clone = self.nb_started.clone();
         let nb_finished_clone = self.nb_finished.clone();
+        let failed_clone = self.failed.clone();
         let finished_sender_clone = self.finished_sender.clone();
 
         tokio::spawn(async move {
-            let _ = receiver.recv().await;
+            if receiver.recv().await.is_err() {
+                failed_clone.store(true, Ordering::SeqCst);
+            }
             {
                 let mut nb_finished = nb_finished_clone.lock().await;
                 *nb_finished += 1;
@@ -53,13 +66,14 @@ impl WaitForAll {
 
     pub async fn wait(&mut self) -> Result<()> {
         // If already finished, return immediately
-        if *self.nb_started.lock().await == *self.nb_finished.lock().await {
-            return Ok(());
+        if *self.nb_started.lock().await != *self.nb_finished.lock().await {
+            let mut receiver = self.finished_receiver.activate_cloned();
+            receiver.recv().await?;
         }
 
-        let mut receiver = self.finished_receiver.activate_cloned();
-        receiver.recv().await?;
-
+        if self.failed.load(Ordering::SeqCst) {
+            return Err(StorageError::NotStored.into());
+        }
         Ok(())
     }
 }
@@ -105,6 +119,26 @@ mod tests {
         assert!(s2_clone.broadcast(()).await.is_err());
     }
 
+    #[tokio::test]
+    async fn test_not_stored() {
+        let mut wfa = WaitForAll::new();
+
+        let (s1, r1) = async_broadcast::broadcast(1);
+        let (s2, r2) = async_broadcast::broadcast::<()>(1);
+        wfa.add(r1).await;
+        wfa.add(r2).await;
+
+        s1.broadcast(()).await.unwrap();
+        // Dropped without a sync, as by a failed publish
+        drop(s2);
+
+        let error = wfa.wait().await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StorageError>(),
+            Some(StorageError::NotStored)
+        ));
+    }
+
     #[tokio::test]
     async fn test_without_waiting() {
         let mut wfa = WaitForAll::new();
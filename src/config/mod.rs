@@ -6,6 +6,13 @@ use std::{
 };
 
 use self::{mqtt::MqttConfig, opcua::OpcuaConfig};
+use crate::datamodel::future_timestamp_policy::FutureTimestampPolicy;
+use crate::datamodel::range_violation_policy::RangeViolationPolicy;
+use crate::datamodel::SensorType;
+use crate::ingestors::http::request_log::RequestLogFormat;
+use crate::parsing::senml::SenMLVersionPolicy;
+use crate::storage::sync_timeout::SyncTimeoutPolicy;
+use crate::storage::type_conflict::TypeConflictPolicy;
 pub mod mqtt;
 pub mod opcua;
 
@@ -19,24 +26,343 @@ pub struct SensAppConfig {
     #[config(env = "SENSAPP_ENDPOINT", default = "127.0.0.1")]
     pub endpoint: IpAddr,
 
+    /// Public base URL of SensApp, used in the links of the DCAT catalog.
+    /// Defaults to `http://{endpoint}:{port}{base_path}`.
+    #[config(env = "SENSAPP_PUBLIC_URL")]
+    pub public_url: Option<String>,
+
+    /// Prefix of all the routes, like `/sensapp` behind a reverse proxy
+    /// serving SensApp under a subpath. Empty for the root.
+    #[config(env = "SENSAPP_BASE_PATH", default = "")]
+    pub base_path: String,
+
     #[config(env = "SENSAPP_HTTP_BODY_LIMIT", default = "10mb")]
     pub http_body_limit: String,
 
+    /// Bytes a compressed body can decompress to, 0 for no limit.
+    #[config(env = "SENSAPP_MAX_DECOMPRESSED_BYTES", default = 104857600)]
+    pub max_decompressed_bytes: usize,
+
     #[config(env = "SENSAPP_HTTP_SERVER_TIMEOUT_SECONDS", default = 30)]
     pub http_server_timeout_seconds: u64,
 
+    /// Connections served at the same time, 0 for no limit.
+    #[config(env = "SENSAPP_HTTP_MAX_CONNECTIONS", default = 0)]
+    pub http_max_connections: usize,
+
+    /// Queries and exports handled at the same time, 0 for no limit.
+    #[config(env = "SENSAPP_MAX_CONCURRENT_QUERIES", default = 0)]
+    pub max_concurrent_queries: usize,
+
+    /// How long the sensor listings are cached, 0 to disable the cache.
+    #[config(env = "SENSAPP_CATALOG_CACHE_SECONDS", default = 0)]
+    pub catalog_cache_seconds: u64,
+
+    /// How often the sensors with a downsampling policy are rolled up,
+    /// 0 to only roll them up with POST /internal/rollup.
+    #[config(env = "SENSAPP_ROLLUP_TASK_INTERVAL_SECONDS", default = 0)]
+    pub rollup_task_interval_seconds: u64,
+
+    /// Queries waiting for their turn above the limit,
+    /// the others are rejected with a 503.
+    #[config(env = "SENSAPP_MAX_QUEUED_QUERIES", default = 64)]
+    pub max_queued_queries: usize,
+
+    /// Ingestion requests handled at the same time, 0 for no limit.
+    #[config(env = "SENSAPP_MAX_CONCURRENT_INGESTIONS", default = 0)]
+    pub max_concurrent_ingestions: usize,
+
+    /// Ingestion requests waiting for their turn above the limit,
+    /// the others are rejected with a 503.
+    #[config(env = "SENSAPP_MAX_QUEUED_INGESTIONS", default = 64)]
+    pub max_queued_ingestions: usize,
+
+    /// Time to receive the request headers, 0 to wait forever.
+    #[config(env = "SENSAPP_HTTP_HEADER_TIMEOUT_SECONDS", default = 30)]
+    pub http_header_timeout_seconds: u64,
+
+    /// Idle time before the TCP keep-alive probes, 0 to disable them.
+    #[config(env = "SENSAPP_HTTP_KEEPALIVE_SECONDS", default = 0)]
+    pub http_keepalive_seconds: u64,
+
+    /// Bytes of a streamed ingestion parsed before the samples are stored.
+    /// A line can't be longer.
+    #[config(env = "SENSAPP_STREAM_CHUNK_SIZE", default = 1048576)]
+    pub stream_chunk_size: usize,
+
+    /// Time after which the samples of a streamed ingestion are stored,
+    /// even when the chunk isn't full.
+    #[config(env = "SENSAPP_STREAM_FLUSH_INTERVAL_MS", default = 1000)]
+    pub stream_flush_interval_ms: u64,
+
+    /// Ingestion requests per second per client IP, 0 to disable.
+    #[config(env = "SENSAPP_RATE_LIMIT_RPS", default = 0.0)]
+    pub rate_limit_rps: f64,
+
+    /// Requests allowed in a burst, 0 for one second of requests.
+    #[config(env = "SENSAPP_RATE_LIMIT_BURST", default = 0)]
+    pub rate_limit_burst: u32,
+
     #[config(env = "SENSAPP_MAX_INFERENCES_ROWS", default = 128)]
     pub max_inference_rows: usize,
 
+    #[config(env = "SENSAPP_MAX_FUTURE_SKEW_SECONDS", default = 300)]
+    pub max_future_skew_seconds: u64,
+
+    #[config(env = "SENSAPP_FUTURE_TIMESTAMP_POLICY", default = "allow")]
+    pub future_timestamp_policy: FutureTimestampPolicy,
+
+    /// Precision and scale of the numeric values, `DECIMAL(18, 6)` by default.
+    #[config(env = "SENSAPP_NUMERIC_PRECISION", default = 18)]
+    pub numeric_precision: u32,
+
+    #[config(env = "SENSAPP_NUMERIC_SCALE", default = 6)]
+    pub numeric_scale: u32,
+
+    /// What to do with the values outside the `min_value`/`max_value` of their sensor.
+    #[config(env = "SENSAPP_RANGE_VIOLATION_POLICY", default = "store")]
+    pub range_violation_policy: RangeViolationPolicy,
+
+    /// `reject` for the SenML records of a version above the supported one,
+    /// or `warn` to log them and parse them as the supported version.
+    #[config(env = "SENSAPP_SENML_VERSION_POLICY", default = "reject")]
+    pub senml_version_policy: SenMLVersionPolicy,
+
+    /// Label names of the first segments of the Graphite metric paths,
+    /// for example `datacenter.host`. `_` skips a segment.
+    #[config(env = "SENSAPP_GRAPHITE_SEGMENT_LABELS")]
+    pub graphite_segment_labels: Option<String>,
+
+    /// How long the finished bulk ingestion jobs are kept, in seconds.
+    #[config(env = "SENSAPP_JOBS_TTL_SECONDS", default = 3600)]
+    pub jobs_ttl_seconds: u64,
+
+    /// How long the unfinished bulk ingestion jobs are kept
+    /// without progress, in seconds.
+    #[config(env = "SENSAPP_JOBS_STALLED_TTL_SECONDS", default = 86400)]
+    pub jobs_stalled_ttl_seconds: u64,
+
+    /// Units of the InfluxDB and Graphite sensors by name pattern,
+    /// for example `*_celsius => °C, *_percent => %`.
+    #[config(env = "SENSAPP_UNIT_MAPPING")]
+    pub unit_mapping: Option<String>,
+
+    /// Precision of the InfluxDB timestamps when the request doesn't set it,
+    /// one of ns, us, ms, s, m, h.
+    #[config(env = "SENSAPP_INFLUXDB_DEFAULT_PRECISION", default = "ns")]
+    pub influxdb_default_precision: String,
+
+    /// Stores the InfluxDB floats as numeric, to keep their decimal
+    /// representation. The `X-SensApp-Floats-As-Numeric` header overrides it.
+    #[config(env = "SENSAPP_INFLUXDB_FLOATS_AS_NUMERIC", default = true)]
+    pub influxdb_floats_as_numeric: bool,
+
     #[config(env = "SENSAPP_BATCH_SIZE", default = 8192)]
     pub batch_size: usize,
 
+    /// Distinct sensors a single request can publish to, 0 for no limit.
+    #[config(env = "SENSAPP_MAX_SENSORS_PER_BATCH", default = 0)]
+    pub max_sensors_per_batch: usize,
+
+    /// Only ingests to the existing sensors, and rejects the samples
+    /// of the unknown sensors instead of creating them.
+    #[config(env = "SENSAPP_STRICT_SENSORS", default = false)]
+    pub strict_sensors: bool,
+
+    /// `reject`, `new_series` or `coerce` the samples of a sensor published
+    /// with another type than the existing sensor of the same name and labels.
+    #[config(env = "SENSAPP_TYPE_CONFLICT_POLICY", default = "new_series")]
+    pub type_conflict_policy: TypeConflictPolicy,
+
+    /// Rounds the sample timestamps to the nearest multiple of a step,
+    /// such as `1s` or `100ms`, to remove the jitter of the sensor clocks.
+    #[config(env = "SENSAPP_TIMESTAMP_ROUND_TO")]
+    pub timestamp_round_to: Option<String>,
+
+    #[config(env = "SENSAPP_SORT_SAMPLES_BEFORE_INSERT", default = false)]
+    pub sort_samples_before_insert: bool,
+
+    /// Drops the samples identical to the previous sample of the sensor.
+    #[config(env = "SENSAPP_COLLAPSE_UNCHANGED", default = false)]
+    pub collapse_unchanged: bool,
+
+    /// Keeps an unchanged sample every N seconds when collapsing, 0 to disable.
+    #[config(env = "SENSAPP_COLLAPSE_UNCHANGED_HEARTBEAT_SECONDS", default = 0)]
+    pub collapse_unchanged_heartbeat_seconds: u64,
+
+    /// Number of sensors whose previous sample is remembered when collapsing.
+    #[config(env = "SENSAPP_COLLAPSE_UNCHANGED_MAX_SENSORS", default = 100000)]
+    pub collapse_unchanged_max_sensors: usize,
+
+    /// Regular expression the sensor names must match, anything by default.
+    #[config(env = "SENSAPP_SENSOR_NAME_PATTERN")]
+    pub sensor_name_pattern: Option<String>,
+
+    /// Sample types stored by this instance, for example `float,integer`,
+    /// all of them by default. The samples of the other types are rejected.
+    #[config(env = "SENSAPP_ENABLED_SAMPLE_TYPES")]
+    pub enabled_sample_types: Option<String>,
+
+    /// Drops the labels with an empty key or value from the ingested sensors.
+    #[config(env = "SENSAPP_DROP_EMPTY_LABELS", default = true)]
+    pub drop_empty_labels: bool,
+
+    /// Maximum length of the label keys and values, in characters, 0 for no limit.
+    #[config(env = "SENSAPP_MAX_LABEL_KEY_LENGTH", default = 1024)]
+    pub max_label_key_length: usize,
+
+    #[config(env = "SENSAPP_MAX_LABEL_VALUE_LENGTH", default = 1024)]
+    pub max_label_value_length: usize,
+
+    /// Truncates the labels above the maximum lengths, instead of rejecting them.
+    #[config(env = "SENSAPP_TRUNCATE_LONG_LABELS", default = false)]
+    pub truncate_long_labels: bool,
+
+    /// Lowercases the label keys of the ingested sensors, so `Env` and `env` are the same label.
+    #[config(env = "SENSAPP_LABELS_LOWERCASE_KEYS", default = false)]
+    pub labels_lowercase_keys: bool,
+
+    /// Lowercases the label values too, with the label keys.
+    #[config(env = "SENSAPP_LABELS_LOWERCASE_VALUES", default = false)]
+    pub labels_lowercase_values: bool,
+
+    /// Directory of the audit log, keeping the raw payload of every
+    /// ingestion request as received, disabled when unset.
+    #[config(env = "SENSAPP_AUDIT_RAW_PAYLOADS")]
+    pub audit_raw_payloads: Option<String>,
+
+    /// Audit records waiting for the disk, the ingestion waits
+    /// when it is full.
+    #[config(env = "SENSAPP_AUDIT_QUEUE_CAPACITY", default = 1024)]
+    pub audit_queue_capacity: usize,
+
+    /// `text` or `json` to log every HTTP request on the standard output,
+    /// with the number of sensors and samples of the ingestion requests.
+    #[config(env = "SENSAPP_REQUEST_LOG", default = "off")]
+    pub request_log: RequestLogFormat,
+
+    /// Round-trips a known fixture through each parser and the exporter
+    /// it reads back on startup, and aborts the startup when one fails.
+    #[config(env = "SENSAPP_STARTUP_SELFTEST", default = false)]
+    pub startup_selftest: bool,
+
     #[config(env = "SENSAPP_SENSOR_SALT", default = "sensapp")]
     pub sensor_salt: String,
 
+    /// Consecutive storage failures opening the circuit breaker, 0 to disable it.
+    #[config(env = "SENSAPP_CIRCUIT_BREAKER_FAILURE_THRESHOLD", default = 5)]
+    pub circuit_breaker_failure_threshold: usize,
+
+    #[config(env = "SENSAPP_CIRCUIT_BREAKER_WINDOW_SECONDS", default = 60)]
+    pub circuit_breaker_window_seconds: u64,
+
+    #[config(env = "SENSAPP_CIRCUIT_BREAKER_COOLDOWN_SECONDS", default = 30)]
+    pub circuit_breaker_cooldown_seconds: u64,
+
+    /// Time the ingestion requests wait for the storage to store the samples,
+    /// 0 to wait forever.
+    #[config(env = "SENSAPP_STORAGE_SYNC_TIMEOUT_SECONDS", default = 60)]
+    pub storage_sync_timeout_seconds: u64,
+
+    /// `error` to fail the requests with a 504 when the storage sync times out,
+    /// or `warn` to log it and answer as if the samples were stored.
+    #[config(env = "SENSAPP_SYNC_TIMEOUT_POLICY", default = "error")]
+    pub sync_timeout_policy: SyncTimeoutPolicy,
+
+    /// Only check that the storage schema is up to date at boot, without
+    /// migrating it, when the schema is provisioned separately.
+    #[config(env = "SENSAPP_SKIP_MIGRATIONS", default = false)]
+    pub skip_migrations: bool,
+
+    /// Batches buffered before the storage, 0 to publish synchronously.
+    #[config(env = "SENSAPP_WRITE_BUFFER_CAPACITY", default = 0)]
+    pub write_buffer_capacity: usize,
+
+    /// Write buffer depth above which the health endpoint reports degraded,
+    /// 0 for three quarters of the capacity.
+    #[config(env = "SENSAPP_WRITE_BUFFER_HIGH_WATER_MARK", default = 0)]
+    pub write_buffer_high_water_mark: usize,
+
+    /// Enables `POST /query/sql`, which also requires `SENSAPP_RAW_SQL_TOKEN`.
+    #[config(env = "SENSAPP_ENABLE_RAW_SQL", default = false)]
+    pub enable_raw_sql: bool,
+
+    /// Bearer token of the raw SQL queries.
+    #[config(env = "SENSAPP_RAW_SQL_TOKEN")]
+    pub raw_sql_token: Option<String>,
+
+    #[config(env = "SENSAPP_RAW_SQL_MAX_ROWS", default = 10000)]
+    pub raw_sql_max_rows: usize,
+
+    #[config(env = "SENSAPP_RAW_SQL_TIMEOUT_SECONDS", default = 10)]
+    pub raw_sql_timeout_seconds: u64,
+
+    /// Samples returned by a sensor query without a limit, 0 for no limit.
+    #[config(env = "SENSAPP_DEFAULT_QUERY_LIMIT", default = 0)]
+    pub default_query_limit: usize,
+
+    /// Default query limits of the sensor types, 0 for `SENSAPP_DEFAULT_QUERY_LIMIT`.
+    /// The blob and JSON samples are heavy, so fewer are returned by default.
+    #[config(env = "SENSAPP_DEFAULT_LIMIT_INTEGER", default = 0)]
+    pub default_limit_integer: usize,
+
+    #[config(env = "SENSAPP_DEFAULT_LIMIT_NUMERIC", default = 0)]
+    pub default_limit_numeric: usize,
+
+    #[config(env = "SENSAPP_DEFAULT_LIMIT_FLOAT", default = 0)]
+    pub default_limit_float: usize,
+
+    #[config(env = "SENSAPP_DEFAULT_LIMIT_STRING", default = 0)]
+    pub default_limit_string: usize,
+
+    #[config(env = "SENSAPP_DEFAULT_LIMIT_BOOLEAN", default = 0)]
+    pub default_limit_boolean: usize,
+
+    #[config(env = "SENSAPP_DEFAULT_LIMIT_LOCATION", default = 0)]
+    pub default_limit_location: usize,
+
+    #[config(env = "SENSAPP_DEFAULT_LIMIT_JSON", default = 10000)]
+    pub default_limit_json: usize,
+
+    #[config(env = "SENSAPP_DEFAULT_LIMIT_BLOB", default = 1000)]
+    pub default_limit_blob: usize,
+
+    /// Adds the sensor, its type, unit and labels to the schema metadata
+    /// of the Arrow exports. Disable to keep the bare schema of the older versions.
+    #[config(env = "SENSAPP_ARROW_SCHEMA_METADATA", default = true)]
+    pub arrow_schema_metadata: bool,
+
+    /// Sensors published per storage transaction, 0 for the whole batch.
+    /// With a limit, a failing batch may be partially committed.
+    #[config(env = "SENSAPP_PUBLISH_SENSORS_PER_TX", default = 0)]
+    pub publish_sensors_per_tx: usize,
+
+    /// Retries of the PostgreSQL and TimescaleDB publish transactions
+    /// failing with a transient error, like a deadlock.
+    #[config(env = "SENSAPP_PUBLISH_RETRIES", default = 3)]
+    pub publish_retries: usize,
+
+    /// Delay before the first retry, doubled at each retry.
+    #[config(env = "SENSAPP_PUBLISH_RETRY_BACKOFF_MS", default = 100)]
+    pub publish_retry_backoff_ms: u64,
+
     #[config(env = "SENSAPP_SQLITE_CONNECTION_STRING")]
     pub sqlite_connection_string: Option<String>,
 
+    /// How the SQLite string values are stored, `dictionary` to deduplicate
+    /// them, or `inline` for high-cardinality strings like log lines.
+    #[config(env = "SENSAPP_STRING_STORAGE", default = "dictionary")]
+    pub string_storage: String,
+
+    /// Compression of the stored blobs in the SQL backends, one of none, zstd, lz4.
+    /// The blobs are decompressed on read, whatever the current setting.
+    #[config(env = "SENSAPP_BLOB_COMPRESSION", default = "none")]
+    pub blob_compression: String,
+
+    /// Store the SQLite timestamps in nanoseconds instead of milliseconds.
+    #[config(env = "SENSAPP_SQLITE_NANOSECOND_TIME", default = false)]
+    pub sqlite_nanosecond_time: bool,
+
     #[config(env = "SENSAPP_POSTGRES_CONNECTION_STRING")]
     pub postgres_connection_string: Option<String>,
 
@@ -68,6 +394,63 @@ impl SensAppConfig {
         Ok(c)
     }
 
+    pub fn public_url(&self) -> String {
+        match &self.public_url {
+            Some(public_url) => public_url.trim_end_matches('/').to_string(),
+            None => format!(
+                "http://{}{}",
+                std::net::SocketAddr::new(self.endpoint, self.port),
+                self.base_path()
+            ),
+        }
+    }
+
+    /// The base path with a leading slash and without a trailing slash,
+    /// or empty for the root.
+    pub fn base_path(&self) -> String {
+        let base_path = self.base_path.trim_matches('/');
+        if base_path.is_empty() {
+            return String::new();
+        }
+        format!("/{}", base_path)
+    }
+
+    /// The limit of the sensor queries without a limit, by sensor type.
+    pub fn default_query_limit(&self, sensor_type: SensorType) -> Option<usize> {
+        let type_limit = match sensor_type {
+            SensorType::Integer => self.default_limit_integer,
+            SensorType::Numeric => self.default_limit_numeric,
+            SensorType::Float => self.default_limit_float,
+            SensorType::String => self.default_limit_string,
+            SensorType::Boolean => self.default_limit_boolean,
+            SensorType::Location => self.default_limit_location,
+            SensorType::Json => self.default_limit_json,
+            SensorType::Blob => self.default_limit_blob,
+        };
+        match (type_limit, self.default_query_limit) {
+            (0, 0) => None,
+            (0, limit) | (limit, _) => Some(limit),
+        }
+    }
+
+    /// The enabled sample types, all of them when not configured.
+    pub fn enabled_sample_types(&self) -> Result<Vec<SensorType>, Error> {
+        let Some(enabled_sample_types) = &self.enabled_sample_types else {
+            return Ok(SensorType::ALL.to_vec());
+        };
+        enabled_sample_types
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                SensorType::ALL
+                    .into_iter()
+                    .find(|sensor_type| sensor_type.to_string().eq_ignore_ascii_case(name))
+                    .ok_or_else(|| anyhow::anyhow!("Unknown sample type: {}", name))
+            })
+            .collect()
+    }
+
     pub fn parse_http_body_limit(&self) -> Result<usize, Error> {
         let size = byte_unit::Byte::parse_str(self.http_body_limit.clone(), true)?.as_u64();
         if size > 128 * 1024 * 1024 * 1024 {
@@ -151,6 +534,40 @@ mod tests {
         assert!(config.parse_http_body_limit().is_err());
     }
 
+    #[test]
+    fn test_default_query_limit() {
+        let mut config = SensAppConfig::load().unwrap();
+        config.default_query_limit = 0;
+        config.default_limit_float = 0;
+        config.default_limit_blob = 100;
+        assert_eq!(config.default_query_limit(SensorType::Float), None);
+        assert_eq!(config.default_query_limit(SensorType::Blob), Some(100));
+
+        // The global default applies to the types without their own
+        config.default_query_limit = 5000;
+        assert_eq!(config.default_query_limit(SensorType::Float), Some(5000));
+        assert_eq!(config.default_query_limit(SensorType::Blob), Some(100));
+    }
+
+    #[test]
+    fn test_base_path() {
+        let mut config = SensAppConfig::load().unwrap();
+        config.public_url = None;
+        for (base_path, expected) in [
+            ("", ""),
+            ("/", ""),
+            ("sensapp", "/sensapp"),
+            ("/sensapp/", "/sensapp"),
+            ("/tools/sensapp", "/tools/sensapp"),
+        ] {
+            config.base_path = base_path.to_string();
+            assert_eq!(config.base_path(), expected);
+        }
+        // The other tests may change the port
+        config.port = 3000;
+        assert_eq!(config.public_url(), "http://127.0.0.1:3000/tools/sensapp");
+    }
+
     #[test]
     fn test_load_configuration() {
         assert!(SENSAPP_CONFIG.get().is_none());
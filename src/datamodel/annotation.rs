@@ -0,0 +1,26 @@
+use super::SensAppDateTime;
+
+/// A discrete event on the timeline of a sensor, like a deployment or an alarm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub datetime: SensAppDateTime,
+    pub title: String,
+    pub text: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl Annotation {
+    pub fn new(
+        datetime: SensAppDateTime,
+        title: String,
+        text: Option<String>,
+        tags: Vec<String>,
+    ) -> Self {
+        Self {
+            datetime,
+            title,
+            text,
+            tags,
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use super::{
+    sensapp_datetime::SensAppDateTimeExt, Sample, SensAppDateTime, SensAppVec, SensorType,
+    TypedSamples,
+};
+use anyhow::{anyhow, Result};
+use arrow::array::{
+    ArrayRef, AsArray, BinaryArray, BooleanArray, Float64Array, Int64Array, StringArray,
+    TimestampMicrosecondArray,
+};
+use arrow::datatypes::{
+    DataType, Field, Float64Type, Int64Type, Schema, SchemaRef, TimeUnit, TimestampMicrosecondType,
+};
+use arrow::record_batch::RecordBatch;
+use hifitime::Unit;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Converts samples to and from Arrow record batches.
+///
+/// The first column is the `datetime` in microseconds, in UTC.
+/// Numeric values are stored as strings to not lose precision,
+/// JSON values are serialised, and locations use a `latitude`
+/// and a `longitude` column instead of the `value` column.
+pub struct ArrowConverter;
+
+pub const DATETIME_COLUMN: &str = "datetime";
+
+fn datetime_to_microseconds(datetime: &SensAppDateTime) -> i64 {
+    datetime.to_unix(Unit::Microsecond).floor() as i64
+}
+
+fn datetimes<T>(samples: &[Sample<T>]) -> ArrayRef {
+    Arc::new(
+        TimestampMicrosecondArray::from_iter_values(
+            samples
+                .iter()
+                .map(|sample| datetime_to_microseconds(&sample.datetime)),
+        )
+        .with_timezone("UTC"),
+    )
+}
+
+impl ArrowConverter {
+    pub fn schema(sensor_type: SensorType) -> SchemaRef {
+        let datetime_field = Field::new(
+            DATETIME_COLUMN,
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        );
+        let value_fields = match sensor_type {
+            SensorType::Integer => vec![Field::new("value", DataType::Int64, false)],
+            SensorType::Numeric | SensorType::String | SensorType::Json => {
+                vec![Field::new("value", DataType::Utf8, false)]
+            }
+            SensorType::Float => vec![Field::new("value", DataType::Float64, false)],
+            SensorType::Boolean => vec![Field::new("value", DataType::Boolean, false)],
+            SensorType::Location => vec![
+                Field::new("latitude", DataType::Float64, false),
+                Field::new("longitude", DataType::Float64, false),
+            ],
+            SensorType::Blob => vec![Field::new("value", DataType::Binary, false)],
+        };
+        let mut fields = vec![datetime_field];
+        fields.extend(value_fields);
+        Arc::new(Schema::new(fields))
+    }
+
+    pub fn sensor_type_of(samples: &TypedSamples) -> SensorType {
+        match samples {
+            TypedSamples::Integer(_) => SensorType::Integer,
+            TypedSamples::Numeric(_) => SensorType::Numeric,
+            TypedSamples::Float(_) => SensorType::Float,
+            TypedSamples::String(_) => SensorType::String,
+            TypedSamples::Boolean(_) => SensorType::Boolean,
+            TypedSamples::Location(_) => SensorType::Location,
+            TypedSamples::Blob(_) => SensorType::Blob,
+            TypedSamples::Json(_) => SensorType::Json,
+        }
+    }
+
+    pub fn typed_samples_to_record_batch(samples: &TypedSamples) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = match samples {
+            TypedSamples::Integer(samples) => vec![
+                datetimes(samples),
+                Arc::new(Int64Array::from_iter_values(
+                    samples.iter().map(|sample| sample.value),
+                )),
+            ],
+            TypedSamples::Numeric(samples) => vec![
+                datetimes(samples),
+                Arc::new(StringArray::from_iter_values(
+                    samples.iter().map(|sample| sample.value.to_string()),
+                )),
+            ],
+            TypedSamples::Float(samples) => vec![
+                datetimes(samples),
+                Arc::new(Float64Array::from_iter_values(
+                    samples.iter().map(|sample| sample.value),
+                )),
+            ],
+            TypedSamples::String(samples) => vec![
+                datetimes(samples),
+                Arc::new(StringArray::from_iter_values(
+                    samples.iter().map(|sample| sample.value.as_str()),
+                )),
+            ],
+            TypedSamples::Boolean(samples) => vec![
+                datetimes(samples),
+                Arc::new(BooleanArray::from(
+                    samples
+                        .iter()
+                        .map(|sample| sample.value)
+                        .collect::<Vec<_>>(),
+                )),
+            ],
+            TypedSamples::Location(samples) => vec![
+                datetimes(samples),
+                Arc::new(Float64Array::from_iter_values(
+                    samples.iter().map(|sample| sample.value.y()),
+                )),
+                Arc::new(Float64Array::from_iter_values(
+                    samples.iter().map(|sample| sample.value.x()),
+                )),
+            ],
+            TypedSamples::Blob(samples) => vec![
+                datetimes(samples),
+                Arc::new(BinaryArray::from_iter_values(
+                    samples.iter().map(|sample| sample.value.as_slice()),
+                )),
+            ],
+            TypedSamples::Json(samples) => vec![
+                datetimes(samples),
+                Arc::new(StringArray::from_iter_values(
+                    samples.iter().map(|sample| sample.value.to_string()),
+                )),
+            ],
+        };
+        let schema = Self::schema(Self::sensor_type_of(samples));
+        Ok(RecordBatch::try_new(schema, columns)?)
+    }
+
+    pub fn record_batch_to_typed_samples(
+        sensor_type: SensorType,
+        record_batch: &RecordBatch,
+    ) -> Result<TypedSamples> {
+        let column = |index: usize| -> Result<&ArrayRef> {
+            if index < record_batch.num_columns() {
+                Ok(record_batch.column(index))
+            } else {
+                Err(anyhow!("Missing column {} in the record batch", index))
+            }
+        };
+        let invalid_column = |index: usize| anyhow!("Invalid type for column {}", index);
+
+        let datetimes = column(0)?
+            .as_primitive_opt::<TimestampMicrosecondType>()
+            .ok_or_else(|| invalid_column(0))?
+            .values()
+            .iter()
+            .map(|microseconds| SensAppDateTime::from_unix_microseconds_i64(*microseconds))
+            .collect::<Vec<_>>();
+
+        fn zip_samples<V>(
+            datetimes: Vec<SensAppDateTime>,
+            values: impl Iterator<Item = V>,
+        ) -> SensAppVec<Sample<V>> {
+            datetimes
+                .into_iter()
+                .zip(values)
+                .map(|(datetime, value)| Sample { datetime, value })
+                .collect()
+        }
+
+        Ok(match sensor_type {
+            SensorType::Integer => {
+                let values = column(1)?
+                    .as_primitive_opt::<Int64Type>()
+                    .ok_or_else(|| invalid_column(1))?;
+                TypedSamples::Integer(zip_samples(datetimes, values.values().iter().copied()))
+            }
+            SensorType::Numeric => {
+                let values = column(1)?
+                    .as_string_opt::<i32>()
+                    .ok_or_else(|| invalid_column(1))?;
+                let values = values
+                    .iter()
+                    .map(|value| Ok(rust_decimal::Decimal::from_str(value.unwrap_or_default())?))
+                    .collect::<Result<Vec<_>>>()?;
+                TypedSamples::Numeric(zip_samples(datetimes, values.into_iter()))
+            }
+            SensorType::Float => {
+                let values = column(1)?
+                    .as_primitive_opt::<Float64Type>()
+                    .ok_or_else(|| invalid_column(1))?;
+                TypedSamples::Float(zip_samples(datetimes, values.values().iter().copied()))
+            }
+            SensorType::String => {
+                let values = column(1)?
+                    .as_string_opt::<i32>()
+                    .ok_or_else(|| invalid_column(1))?;
+                TypedSamples::String(zip_samples(
+                    datetimes,
+                    values
+                        .iter()
+                        .map(|value| value.unwrap_or_default().to_string()),
+                ))
+            }
+            SensorType::Boolean => {
+                let values = column(1)?
+                    .as_boolean_opt()
+                    .ok_or_else(|| invalid_column(1))?;
+                TypedSamples::Boolean(zip_samples(
+                    datetimes,
+                    values.iter().map(|value| value.unwrap_or_default()),
+                ))
+            }
+            SensorType::Location => {
+                let latitudes = column(1)?
+                    .as_primitive_opt::<Float64Type>()
+                    .ok_or_else(|| invalid_column(1))?;
+                let longitudes = column(2)?
+                    .as_primitive_opt::<Float64Type>()
+                    .ok_or_else(|| invalid_column(2))?;
+                TypedSamples::Location(zip_samples(
+                    datetimes,
+                    latitudes
+                        .values()
+                        .iter()
+                        .zip(longitudes.values().iter())
+                        .map(|(latitude, longitude)| geo::Point::new(*longitude, *latitude)),
+                ))
+            }
+            SensorType::Blob => {
+                let values = column(1)?
+                    .as_binary_opt::<i32>()
+                    .ok_or_else(|| invalid_column(1))?;
+                TypedSamples::Blob(zip_samples(
+                    datetimes,
+                    values
+                        .iter()
+                        .map(|value| value.unwrap_or_default().to_vec()),
+                ))
+            }
+            SensorType::Json => {
+                let values = column(1)?
+                    .as_string_opt::<i32>()
+                    .ok_or_else(|| invalid_column(1))?;
+                let values = values
+                    .iter()
+                    .map(|value| Ok(serde_json::from_str(value.unwrap_or("null"))?))
+                    .collect::<Result<Vec<_>>>()?;
+                TypedSamples::Json(zip_samples(datetimes, values.into_iter()))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use smallvec::smallvec;
+
+    fn roundtrip(samples: TypedSamples) {
+        let sensor_type = ArrowConverter::sensor_type_of(&samples);
+        let record_batch = ArrowConverter::typed_samples_to_record_batch(&samples).unwrap();
+        assert_eq!(record_batch.num_rows(), samples.len());
+        let converted =
+            ArrowConverter::record_batch_to_typed_samples(sensor_type, &record_batch).unwrap();
+        assert_eq!(converted, samples);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let datetime = SensAppDateTime::from_unix_microseconds_i64(1_700_000_000_123_456);
+        roundtrip(TypedSamples::one_integer(42, datetime));
+        roundtrip(TypedSamples::one_numeric(
+            rust_decimal::Decimal::new(12345, 3),
+            datetime,
+        ));
+        roundtrip(TypedSamples::one_float(42.5, datetime));
+        roundtrip(TypedSamples::one_string("hello".to_string(), datetime));
+        roundtrip(TypedSamples::one_boolean(true, datetime));
+        roundtrip(TypedSamples::one_location(
+            geo::Point::new(10.7, 59.9),
+            datetime,
+        ));
+        roundtrip(TypedSamples::one_blob(vec![1, 2, 3], datetime));
+        roundtrip(TypedSamples::one_json(json!({"a": [1, 2]}), datetime));
+        roundtrip(TypedSamples::Float(smallvec![]));
+    }
+}
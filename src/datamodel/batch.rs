@@ -25,6 +25,19 @@ impl Batch {
         }
     }
 
+    /// Splits the sensors in chunks of `sensors_per_chunk` sensors,
+    /// or in a single chunk when it's 0.
+    pub fn sensor_chunks(
+        &self,
+        sensors_per_chunk: usize,
+    ) -> std::slice::Chunks<'_, SingleSensorBatch> {
+        let chunk_size = match sensors_per_chunk {
+            0 => self.sensors.len().max(1),
+            sensors_per_chunk => sensors_per_chunk,
+        };
+        self.sensors.chunks(chunk_size)
+    }
+
     pub async fn len(&self) -> usize {
         let sensors_len = self.sensors.len();
         if sensors_len == 0 {
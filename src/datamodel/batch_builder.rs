@@ -1,26 +1,74 @@
 use super::{
+    arrow_converter::ArrowConverter,
     batch::{Batch, SingleSensorBatch},
-    Sensor, TypedSamples,
+    collapse_unchanged::{CollapseUnchanged, PendingLastSamples},
+    future_timestamp_policy::FutureTimestampPolicy,
+    numeric_precision::NumericPrecision,
+    range_violation_policy::RangeViolationPolicy,
+    timestamp_rounding::TimestampRounding,
+    SensAppDateTime, Sensor, SensorType, TypedSamples,
 };
 use crate::{
     bus::{wait_for_all::WaitForAll, EventBus},
     datamodel::SensAppVec,
+    storage::storage::StorageInstance,
 };
 use anyhow::{anyhow, Error};
+use async_broadcast::Receiver;
+use hifitime::Duration;
 use hybridmap::HybridMap;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// The error returned when the added samples don't pass the ingestion policies.
+#[derive(Debug)]
+pub struct InvalidSamplesError(pub String);
+
+impl std::fmt::Display for InvalidSamplesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSamplesError {}
+
+fn invalid_samples(error: Error) -> Error {
+    InvalidSamplesError(error.to_string()).into()
+}
+
 /// A batch builder is used to build a batch from a stream of samples.
 pub struct BatchBuilder {
     batch_size: usize,
+    /// Distinct sensors accepted by the builder, 0 for no limit.
+    max_sensors: usize,
+    /// The sensors added since the builder creation, when limited,
+    /// including the ones already sent.
+    seen_sensors: HashSet<Uuid>,
+    future_timestamp_policy: FutureTimestampPolicy,
+    max_future_skew: Duration,
+    timestamp_rounding: Option<TimestampRounding>,
+    sort_samples: bool,
+    numeric_precision: NumericPrecision,
+    range_violation_policy: RangeViolationPolicy,
+    /// Loads the stored value range of the existing sensors,
+    /// for the range violation policy.
+    storage: Option<Arc<dyn StorageInstance>>,
+    stored_ranges: HashMap<Uuid, (Option<f64>, Option<f64>)>,
+    sensor_name_pattern: Option<Regex>,
+    enabled_sample_types: Vec<SensorType>,
+    collapse_unchanged: Option<Arc<CollapseUnchanged>>,
+    /// Committed to the shared previous samples once the batches are stored.
+    collapse_pending: PendingLastSamples,
     single_sensor_batches: RwLock<HybridMap<Uuid, SingleSensorBatch>>,
 }
 
 impl BatchBuilder {
     pub fn new() -> Result<Self, Error> {
-        let batch_size = crate::config::get()?.batch_size;
+        let config = crate::config::get()?;
+        let batch_size = config.batch_size;
 
         if batch_size == 0 {
             return Err(anyhow::anyhow!("Batch size is 0"));
@@ -28,12 +76,181 @@ impl BatchBuilder {
 
         Ok(Self {
             batch_size,
+            max_sensors: config.max_sensors_per_batch,
+            seen_sensors: HashSet::new(),
+            future_timestamp_policy: config.future_timestamp_policy,
+            max_future_skew: Duration::from_seconds(config.max_future_skew_seconds as f64),
+            timestamp_rounding: config
+                .timestamp_round_to
+                .as_deref()
+                .map(TimestampRounding::parse)
+                .transpose()?,
+            sort_samples: config.sort_samples_before_insert,
+            numeric_precision: NumericPrecision::new(
+                config.numeric_precision,
+                config.numeric_scale,
+            )?,
+            range_violation_policy: config.range_violation_policy,
+            storage: None,
+            stored_ranges: HashMap::new(),
+            sensor_name_pattern: config
+                .sensor_name_pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()?,
+            enabled_sample_types: config.enabled_sample_types()?,
+            collapse_unchanged: config.collapse_unchanged.then(|| {
+                CollapseUnchanged::shared(
+                    config.collapse_unchanged_heartbeat_seconds,
+                    config.collapse_unchanged_max_sensors,
+                )
+            }),
+            collapse_pending: PendingLastSamples::default(),
             single_sensor_batches: RwLock::new(HybridMap::new()),
         })
     }
 
-    pub async fn add(&mut self, sensor: Arc<Sensor>, samples: TypedSamples) -> Result<(), Error> {
+    /// A batch builder keeping the samples as they are added,
+    /// without the ingestion policies of the configuration.
+    pub fn without_policies() -> Self {
+        Self {
+            batch_size: usize::MAX,
+            max_sensors: 0,
+            seen_sensors: HashSet::new(),
+            future_timestamp_policy: FutureTimestampPolicy::Allow,
+            max_future_skew: Duration::ZERO,
+            timestamp_rounding: None,
+            sort_samples: false,
+            numeric_precision: NumericPrecision::default(),
+            range_violation_policy: RangeViolationPolicy::Store,
+            storage: None,
+            stored_ranges: HashMap::new(),
+            sensor_name_pattern: None,
+            enabled_sample_types: SensorType::ALL.to_vec(),
+            collapse_unchanged: None,
+            collapse_pending: PendingLastSamples::default(),
+            single_sensor_batches: RwLock::new(HybridMap::new()),
+        }
+    }
+
+    /// Uses the value range stored with the existing sensors
+    /// when the added sensors have none.
+    pub fn with_storage(mut self, storage: Arc<dyn StorageInstance>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// The value range of the sensor, or the stored one.
+    async fn value_range(&mut self, sensor: &Sensor) -> (Option<f64>, Option<f64>) {
+        if sensor.min_value.is_some() || sensor.max_value.is_some() {
+            return (sensor.min_value, sensor.max_value);
+        }
+        let Some(storage) = &self.storage else {
+            return (None, None);
+        };
+        if let Some(range) = self.stored_ranges.get(&sensor.uuid) {
+            return *range;
+        }
+        let range = match storage.get_sensor(sensor.uuid).await {
+            Ok(stored_sensor) => stored_sensor
+                .map(|stored_sensor| (stored_sensor.min_value, stored_sensor.max_value))
+                .unwrap_or_default(),
+            Err(error) => {
+                tracing::debug!(
+                    "Failed to load the value range of sensor {}: {:?}",
+                    sensor.name,
+                    error
+                );
+                (None, None)
+            }
+        };
+        self.stored_ranges.insert(sensor.uuid, range);
+        range
+    }
+
+    /// Adds samples to the batch.
+    ///
+    /// Fails with an [`InvalidSamplesError`] when the samples are too far
+    /// in the future and the future timestamp policy is to reject them,
+    /// when numeric values don't fit the configured precision,
+    /// when the sensor name doesn't match the configured pattern,
+    /// when the sample type isn't enabled,
+    /// when values are outside the sensor range and the range
+    /// violation policy is to reject them, or when the sensor
+    /// is above the maximum number of sensors per batch.
+    ///
+    /// The datetimes are rounded when timestamp rounding is enabled,
+    /// and the unchanged samples are dropped when collapsing is enabled.
+    pub async fn add(
+        &mut self,
+        sensor: Arc<Sensor>,
+        mut samples: TypedSamples,
+    ) -> Result<(), Error> {
+        if let Some(pattern) = &self.sensor_name_pattern {
+            if !pattern.is_match(&sensor.name) {
+                return Err(InvalidSamplesError(format!(
+                    "Sensor name {:?} doesn't match the pattern {}",
+                    sensor.name, pattern
+                ))
+                .into());
+            }
+        }
+        let sample_type = ArrowConverter::sensor_type_of(&samples);
+        if !self.enabled_sample_types.contains(&sample_type) {
+            return Err(InvalidSamplesError(format!(
+                "Samples of type {} are disabled, the enabled types are: {}",
+                sample_type.to_string(),
+                self.enabled_sample_types
+                    .iter()
+                    .map(|sensor_type| sensor_type.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .into());
+        }
+        if self.max_sensors > 0
+            && !self.seen_sensors.contains(&sensor.uuid)
+            && self.seen_sensors.len() >= self.max_sensors
+        {
+            return Err(InvalidSamplesError(format!(
+                "Too many sensors in the batch, the maximum is {}",
+                self.max_sensors
+            ))
+            .into());
+        }
+        if let Some(timestamp_rounding) = &self.timestamp_rounding {
+            timestamp_rounding.apply(&mut samples);
+        }
+        if self.future_timestamp_policy != FutureTimestampPolicy::Allow {
+            self.future_timestamp_policy
+                .apply(&mut samples, SensAppDateTime::now()?, self.max_future_skew)
+                .map_err(invalid_samples)?;
+        }
+        if let TypedSamples::Numeric(numeric_samples) = &samples {
+            for sample in numeric_samples.iter() {
+                self.numeric_precision
+                    .validate(&sample.value)
+                    .map_err(invalid_samples)?;
+            }
+        }
+        if self.range_violation_policy != RangeViolationPolicy::Store {
+            let (min_value, max_value) = self.value_range(&sensor).await;
+            self.range_violation_policy
+                .apply(&sensor, min_value, max_value, &mut samples)
+                .map_err(invalid_samples)?;
+            if samples.len() == 0 {
+                return Ok(());
+            }
+        }
+        if let Some(collapse_unchanged) = &self.collapse_unchanged {
+            // The sensor is kept even when all its samples are collapsed,
+            // so the storage still records that it was seen.
+            collapse_unchanged.apply(sensor.uuid, &mut samples, &mut self.collapse_pending);
+        }
         let uuid = sensor.uuid;
+        if self.max_sensors > 0 {
+            self.seen_sensors.insert(uuid);
+        }
         let mut write_guard = self.single_sensor_batches.write().await;
         let single_sensor_batches = &mut *write_guard;
         if let Some(sensor_batch) = single_sensor_batches.get_mut(&uuid) {
@@ -45,13 +262,20 @@ impl BatchBuilder {
         Ok(())
     }
 
-    async fn build_batch(&mut self) -> Batch {
+    /// Takes the samples added so far, as a single batch.
+    pub async fn build_batch(&mut self) -> Batch {
         let tmp_sensors;
         {
             let mut write_guard = self.single_sensor_batches.write().await;
             tmp_sensors = std::mem::replace(&mut *write_guard, HybridMap::new());
         }
-        let sensors_iter = tmp_sensors.into_iter().map(|(_, v)| v);
+        let sort_samples = self.sort_samples;
+        let sensors_iter = tmp_sensors.into_iter().map(|(_, mut v)| {
+            if sort_samples {
+                v.samples.get_mut().sort_by_datetime();
+            }
+            v
+        });
         let sensors = SensAppVec::from_iter(sensors_iter);
         Batch { sensors }
     }
@@ -62,6 +286,7 @@ impl BatchBuilder {
     // used to solve this problem.
     async fn build_batches(&mut self) -> Vec<Batch> {
         let batch_size = self.batch_size;
+        let sort_samples = self.sort_samples;
 
         let tmp_single_sensor_batches;
         {
@@ -74,7 +299,10 @@ impl BatchBuilder {
                 .into_iter()
                 .map(|(_, mut single_sensor_batch)| async move {
                     let sensor = single_sensor_batch.sensor.clone();
-                    let samples = single_sensor_batch.take_samples().await;
+                    let mut samples = single_sensor_batch.take_samples().await;
+                    if sort_samples {
+                        samples.sort_by_datetime();
+                    }
                     let chunks = samples.into_chunks(batch_size);
                     chunks.map(move |chunk| {
                         let len = chunk.len();
@@ -139,7 +367,8 @@ impl BatchBuilder {
         batches
     }
 
-    async fn len(&self) -> usize {
+    /// Number of samples not sent yet.
+    pub async fn len(&self) -> usize {
         let read_guard = self.single_sensor_batches.read().await;
         let single_sensor_batches = &*read_guard;
         let sensors_len = single_sensor_batches.len();
@@ -154,17 +383,35 @@ impl BatchBuilder {
         results.into_iter().sum()
     }
 
+    /// Number of distinct sensors not sent yet.
+    pub async fn sensors_len(&self) -> usize {
+        self.single_sensor_batches.read().await.len()
+    }
+
+    /// The distinct sensors not sent yet.
+    pub async fn sensors(&self) -> Vec<Arc<Sensor>> {
+        self.single_sensor_batches
+            .read()
+            .await
+            .iter()
+            .map(|(_, single_sensor_batch)| single_sensor_batch.sensor.clone())
+            .collect()
+    }
+
     async fn send_multiple_batch(
         &mut self,
         event_bus: Arc<EventBus>,
     ) -> Result<Option<WaitForAll>, Error> {
         let mut all_batches_waiter = WaitForAll::new();
+        let mut receivers = Vec::new();
 
         let batches_iter = self.build_batches().await;
         for batch in batches_iter {
             let receiver = event_bus.publish(batch).await?;
-            all_batches_waiter.add(receiver.activate()).await;
+            receivers.push(receiver.clone());
+            all_batches_waiter.add(receiver).await;
         }
+        self.commit_collapse_pending(receivers);
         Ok(Some(all_batches_waiter))
     }
 
@@ -183,10 +430,40 @@ impl BatchBuilder {
         let mut one_waiter = WaitForAll::new();
         let batch = self.build_batch().await;
         let receiver = event_bus.publish(batch).await?;
-        one_waiter.add(receiver.activate()).await;
+        self.commit_collapse_pending(vec![receiver.clone()]);
+        one_waiter.add(receiver).await;
         Ok(Some(one_waiter))
     }
 
+    /// Sends the samples not sent yet in batches of at most the batch size,
+    /// each with its waiter and number of samples, to follow the storage
+    /// batch by batch.
+    pub async fn send_batches(
+        &mut self,
+        event_bus: Arc<EventBus>,
+    ) -> Result<Vec<(WaitForAll, usize)>, Error> {
+        let mut sent = Vec::new();
+        let mut receivers = Vec::new();
+        for batch in self.build_batches().await {
+            let len = batch.len().await;
+            let receiver = event_bus.publish(batch).await?;
+            receivers.push(receiver.clone());
+            let mut waiter = WaitForAll::new();
+            waiter.add(receiver).await;
+            sent.push((waiter, len));
+        }
+        self.commit_collapse_pending(receivers);
+        Ok(sent)
+    }
+
+    /// The previous samples of the collapsing are only updated
+    /// once the samples are stored.
+    fn commit_collapse_pending(&mut self, receivers: Vec<Receiver<()>>) {
+        if let Some(collapse_unchanged) = &self.collapse_unchanged {
+            collapse_unchanged.commit_after(std::mem::take(&mut self.collapse_pending), receivers);
+        }
+    }
+
     pub async fn send_if_batch_full(
         &mut self,
         event_bus: Arc<EventBus>,
@@ -215,13 +492,20 @@ impl BatchBuilder {
 
 #[cfg(test)]
 mod tests {
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use smallvec::smallvec;
+    use std::str::FromStr;
     use tokio::{spawn, sync::Mutex};
 
     use super::*;
     use crate::{
         bus::message::Message,
         config::load_configuration,
-        datamodel::{sensapp_vec::SensAppLabels, Sample, SensorType},
+        datamodel::{
+            sensapp_datetime::SensAppDateTimeExt, sensapp_vec::SensAppLabels,
+            sensor_metadata::SensorMetadata, Sample, SensorType,
+        },
+        storage::{sqlite::SqliteStorage, storage::StorageInstance},
     };
 
     // Utility function to create a test sensor
@@ -232,6 +516,13 @@ mod tests {
             unit: None,
             sensor_type: SensorType::Integer,
             labels: SensAppLabels::new(),
+            min_value: None,
+            max_value: None,
+            nominal_interval_seconds: None,
+            downsampling: None,
+            sensor_id: None,
+            created_at: None,
+            last_seen: None,
         })
     }
 
@@ -270,6 +561,526 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_sensor_name_pattern() {
+        _ = load_configuration();
+
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        batch_builder.sensor_name_pattern = Some(Regex::new("^[a-z_]+$").unwrap());
+
+        let sensor = |name: &str| {
+            Arc::new(
+                Sensor::new_without_uuid(name.to_string(), SensorType::Integer, None, None)
+                    .unwrap(),
+            )
+        };
+        batch_builder
+            .add(sensor("room_temperature"), create_test_samples(1))
+            .await
+            .unwrap();
+        // The InfluxDB sensor names are URL encoded
+        let error = batch_builder
+            .add(sensor("cpu%20load usage_system"), create_test_samples(1))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("doesn't match the pattern"));
+        assert_eq!(batch_builder.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_enabled_sample_types() {
+        _ = load_configuration();
+
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        batch_builder.enabled_sample_types = vec![SensorType::Float];
+
+        let sensor = |sensor_type| {
+            Arc::new(
+                Sensor::new_without_uuid(
+                    "enabled_sample_types".to_string(),
+                    sensor_type,
+                    None,
+                    None,
+                )
+                .unwrap(),
+            )
+        };
+        let datetime = SensAppDateTime::from_unix_seconds_i64(1_700_000_000);
+        batch_builder
+            .add(
+                sensor(SensorType::Float),
+                TypedSamples::Float(smallvec![Sample {
+                    datetime,
+                    value: 21.5
+                }]),
+            )
+            .await
+            .unwrap();
+        let error = batch_builder
+            .add(
+                sensor(SensorType::String),
+                TypedSamples::String(smallvec![Sample {
+                    datetime,
+                    value: "open".to_string()
+                }]),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Samples of type String are disabled, the enabled types are: Float"
+        );
+        assert_eq!(batch_builder.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_future_samples() {
+        _ = load_configuration();
+
+        // 2099-01-01T00:00:00Z
+        let future_samples =
+            || TypedSamples::one_integer(42, hifitime::Epoch::from_unix_seconds(4_070_908_800.0));
+
+        for policy in [
+            FutureTimestampPolicy::Allow,
+            FutureTimestampPolicy::Clamp,
+            FutureTimestampPolicy::Reject,
+        ] {
+            let mut batch_builder = BatchBuilder::new().unwrap();
+            batch_builder.future_timestamp_policy = policy;
+            let sensor = create_test_sensor(Uuid::new_v4());
+            let result = batch_builder.add(sensor.clone(), future_samples()).await;
+
+            let single_sensor_batches = batch_builder.single_sensor_batches.read().await;
+            match policy {
+                FutureTimestampPolicy::Reject => {
+                    assert!(result.unwrap_err().is::<InvalidSamplesError>());
+                    assert!(single_sensor_batches.get(&sensor.uuid).is_none());
+                }
+                FutureTimestampPolicy::Clamp => {
+                    result.unwrap();
+                    let samples = single_sensor_batches
+                        .get(&sensor.uuid)
+                        .unwrap()
+                        .samples
+                        .read()
+                        .await;
+                    let datetime = samples.last_datetime().unwrap();
+                    assert!(datetime <= SensAppDateTime::now().unwrap());
+                }
+                FutureTimestampPolicy::Allow => {
+                    result.unwrap();
+                    let samples = single_sensor_batches
+                        .get(&sensor.uuid)
+                        .unwrap()
+                        .samples
+                        .read()
+                        .await;
+                    assert_eq!(*samples, future_samples());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sort_samples_before_insert() {
+        _ = load_configuration();
+
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        batch_builder.sort_samples = true;
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_sort_samples_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let shuffled = [3, 0, 4, 1, 2]
+            .into_iter()
+            .map(|i| Sample {
+                datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                value: i,
+            })
+            .collect();
+        batch_builder
+            .add(sensor.clone(), TypedSamples::Integer(shuffled))
+            .await
+            .unwrap();
+
+        let batch = batch_builder.build_batch().await;
+        let expected = TypedSamples::Integer(
+            (0..5)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                    value: i,
+                })
+                .collect(),
+        );
+        assert_eq!(*batch.sensors[0].samples.read().await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_rounding() {
+        _ = load_configuration();
+
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        batch_builder.timestamp_rounding = Some(TimestampRounding::parse("1s").unwrap());
+        let sensor = create_test_sensor(Uuid::new_v4());
+        let base = 1_700_000_000_000_000_000;
+        let jittered = [12_000_000, 1_004_000_000, 1_996_000_000, 3_000_000_001]
+            .into_iter()
+            .enumerate()
+            .map(|(i, jitter)| Sample {
+                datetime: SensAppDateTime::from_unix_nanoseconds_i64(base + jitter),
+                value: i as i64,
+            })
+            .collect();
+        batch_builder
+            .add(sensor.clone(), TypedSamples::Integer(jittered))
+            .await
+            .unwrap();
+
+        let batch = batch_builder.build_batch().await;
+        let datetimes = match &*batch.sensors[0].samples.read().await {
+            TypedSamples::Integer(samples) => samples
+                .iter()
+                .map(|sample| sample.datetime.to_unix_nanoseconds_i64())
+                .collect::<Vec<_>>(),
+            _ => panic!("Expected integer samples"),
+        };
+        assert_eq!(
+            datetimes,
+            vec![
+                base,
+                base + 1_000_000_000,
+                base + 2_000_000_000,
+                base + 3_000_000_000
+            ]
+        );
+
+        // Without rounding, the timestamps are kept
+        let jittered = || {
+            TypedSamples::one_integer(
+                1,
+                SensAppDateTime::from_unix_nanoseconds_i64(base + 12_000_000),
+            )
+        };
+        let mut batch_builder = BatchBuilder::without_policies();
+        batch_builder.add(sensor.clone(), jittered()).await.unwrap();
+        let batch = batch_builder.build_batch().await;
+        assert_eq!(*batch.sensors[0].samples.read().await, jittered());
+    }
+
+    #[tokio::test]
+    async fn test_numeric_precision() {
+        _ = load_configuration();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_numeric_precision_{}", Uuid::new_v4()),
+                SensorType::Numeric,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let numeric_samples = |values: &[&str]| {
+            TypedSamples::Numeric(
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i as i64),
+                        value: rust_decimal::Decimal::from_str(value).unwrap(),
+                    })
+                    .collect(),
+            )
+        };
+
+        // Beyond DECIMAL(18, 6)
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        for value in [
+            "1234567890123.123456",
+            "0.0000001",
+            "-12345678901234567890.1",
+        ] {
+            assert!(batch_builder
+                .add(sensor.clone(), numeric_samples(&[value]))
+                .await
+                .is_err());
+        }
+
+        // Within DECIMAL(18, 6), they must round-trip exactly
+        let values = ["123456789012.123456", "-999999999999.999999", "0.000001"];
+        batch_builder
+            .add(sensor.clone(), numeric_samples(&values))
+            .await
+            .unwrap();
+        let batch = batch_builder.build_batch().await;
+
+        let storage = create_test_storage().await;
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let sensor_data = storage
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.samples, numeric_samples(&values));
+    }
+
+    #[tokio::test]
+    async fn test_collapse_unchanged() {
+        _ = load_configuration();
+
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        batch_builder.collapse_unchanged = Some(Arc::new(CollapseUnchanged::new(60, 16)));
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_collapse_unchanged_{}", Uuid::new_v4()),
+                SensorType::Boolean,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        // Off for 2 minutes, on for 10 seconds, and off again, every 10 seconds
+        let value = |i: i64| i == 12;
+        let samples = |range: std::ops::Range<i64>| {
+            TypedSamples::Boolean(
+                range
+                    .map(|i| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i * 10),
+                        value: value(i),
+                    })
+                    .collect(),
+            )
+        };
+        // In two requests, the second one starts with an unchanged sample
+        batch_builder
+            .add(sensor.clone(), samples(0..10))
+            .await
+            .unwrap();
+        batch_builder
+            .add(sensor.clone(), samples(10..20))
+            .await
+            .unwrap();
+        let batch = batch_builder.build_batch().await;
+
+        // The first sample, the heartbeats every 60 seconds, and the transitions
+        let expected = [0, 6, 12, 13, 19];
+        assert_eq!(
+            *batch.sensors[0].samples.read().await,
+            TypedSamples::Boolean(
+                expected
+                    .into_iter()
+                    .map(|i| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i * 10),
+                        value: value(i),
+                    })
+                    .collect()
+            )
+        );
+    }
+
+    /// Sends a sample to the bus, and returns whether it was kept.
+    async fn send_collapsed_sample(
+        collapse_unchanged: &Arc<CollapseUnchanged>,
+        event_bus: &Arc<EventBus>,
+        receiver: &mut async_broadcast::Receiver<Message>,
+        sensor: &Arc<Sensor>,
+        stored: bool,
+    ) -> bool {
+        let mut batch_builder = BatchBuilder::without_policies();
+        batch_builder.collapse_unchanged = Some(collapse_unchanged.clone());
+        batch_builder
+            .add(sensor.clone(), create_test_samples(1))
+            .await
+            .unwrap();
+        let kept = batch_builder.len().await == 1;
+        if !kept {
+            return false;
+        }
+        batch_builder
+            .send_what_is_left(event_bus.clone())
+            .await
+            .unwrap();
+        let Message::Publish(publish_message) = receiver.recv().await.unwrap();
+        if stored {
+            publish_message.sync_sender.broadcast(()).await.unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        kept
+    }
+
+    #[tokio::test]
+    async fn test_collapse_unchanged_not_stored() {
+        _ = load_configuration();
+        let collapse_unchanged = Arc::new(CollapseUnchanged::new(0, 16));
+        let event_bus = Arc::new(EventBus::init("TestBus".to_string()));
+        let mut receiver = event_bus.main_bus_receiver.clone().activate();
+        let sensor = create_test_sensor(Uuid::new_v4());
+        let collapse = &collapse_unchanged;
+
+        assert!(send_collapsed_sample(collapse, &event_bus, &mut receiver, &sensor, false).await);
+        // Not stored, so the sample isn't collapsed
+        assert!(send_collapsed_sample(collapse, &event_bus, &mut receiver, &sensor, true).await);
+        assert!(!send_collapsed_sample(collapse, &event_bus, &mut receiver, &sensor, true).await);
+    }
+
+    #[tokio::test]
+    async fn test_range_violation_policy() {
+        _ = load_configuration();
+
+        let storage = create_test_storage().await;
+
+        let datetime = SensAppDateTime::from_unix_seconds_i64(1_700_000_000);
+        for (policy, expected) in [
+            (RangeViolationPolicy::Reject, None),
+            (RangeViolationPolicy::Skip, Some(vec![21.5])),
+            (RangeViolationPolicy::Clamp, Some(vec![21.5, 60.0])),
+            (RangeViolationPolicy::Store, Some(vec![21.5, 10000.0])),
+        ] {
+            let mut batch_builder = BatchBuilder::new().unwrap();
+            batch_builder.range_violation_policy = policy;
+            let sensor = Arc::new(
+                Sensor::new_without_uuid(
+                    format!("test_range_violation_policy_{}", Uuid::new_v4()),
+                    SensorType::Float,
+                    None,
+                    None,
+                )
+                .unwrap()
+                .with_value_range(Some(-50.0), Some(60.0)),
+            );
+            let samples = TypedSamples::Float(
+                [21.5, 10000.0]
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, value)| Sample {
+                        datetime: datetime + hifitime::Duration::from_seconds(i as f64),
+                        value,
+                    })
+                    .collect(),
+            );
+            let result = batch_builder.add(sensor.clone(), samples).await;
+
+            let Some(expected) = expected else {
+                assert!(result.is_err(), "{:?}", policy);
+                assert_eq!(batch_builder.len().await, 0);
+                continue;
+            };
+            result.unwrap();
+            let batch = batch_builder.build_batch().await;
+            let (sync_sender, _) = async_broadcast::broadcast(1);
+            storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+            let sensor_data = storage
+                .query_sensor_data(sensor.uuid, None, None, None)
+                .await
+                .unwrap()
+                .unwrap();
+            let TypedSamples::Float(samples) = sensor_data.samples else {
+                panic!("Expected float samples");
+            };
+            assert_eq!(
+                samples
+                    .iter()
+                    .map(|sample| sample.value)
+                    .collect::<Vec<_>>(),
+                expected,
+                "{:?}",
+                policy
+            );
+            // The range is persisted
+            assert_eq!(sensor_data.sensor.min_value, Some(-50.0));
+            assert_eq!(sensor_data.sensor.max_value, Some(60.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stored_value_range() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        // Created without a range, as the parsers do
+        let sensor = Arc::new(
+            Sensor::new_without_uuid("thermometer".to_string(), SensorType::Float, None, None)
+                .unwrap(),
+        );
+        let samples = |values: &[f64]| {
+            TypedSamples::Float(
+                values
+                    .iter()
+                    .map(|value| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                        value: *value,
+                    })
+                    .collect(),
+            )
+        };
+        storage
+            .publish(
+                Arc::new(Batch::new(smallvec![SingleSensorBatch::new(
+                    sensor.clone(),
+                    samples(&[21.5])
+                )])),
+                async_broadcast::broadcast(1).0,
+            )
+            .await
+            .unwrap();
+        assert!(storage
+            .update_sensor_metadata(
+                sensor.uuid,
+                &SensorMetadata {
+                    min_value: Some(-50.0),
+                    max_value: Some(60.0),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap());
+
+        let mut batch_builder = BatchBuilder::new().unwrap().with_storage(Arc::new(storage));
+        batch_builder.range_violation_policy = RangeViolationPolicy::Skip;
+        batch_builder
+            .add(sensor, samples(&[22.0, 10000.0]))
+            .await
+            .unwrap();
+        assert_eq!(batch_builder.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_sensors_per_batch() {
+        _ = load_configuration();
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        batch_builder.max_sensors = 3;
+
+        let sensors: Vec<Arc<Sensor>> =
+            (0..4).map(|_| create_test_sensor(Uuid::new_v4())).collect();
+        let samples = || create_test_samples(1);
+
+        for sensor in &sensors[..3] {
+            batch_builder.add(sensor.clone(), samples()).await.unwrap();
+        }
+        batch_builder.build_batch().await;
+        // The sent sensors still count, the limit is per request.
+        let error = batch_builder
+            .add(sensors[3].clone(), samples())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Too many sensors"));
+        // The known sensors are still accepted.
+        batch_builder
+            .add(sensors[0].clone(), samples())
+            .await
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn test_build_batch() {
         _ = load_configuration();
@@ -404,9 +1215,7 @@ mod tests {
         let sensor = create_test_sensor(Uuid::new_v4());
         let samples = create_test_samples(3);
 
-        spawn(async move {
-
-        });
+        spawn(async move {});
     }
 
     /*
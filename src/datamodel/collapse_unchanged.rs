@@ -0,0 +1,313 @@
+use super::{Sample, SensAppDateTime, SensAppVec, TypedSamples};
+use async_broadcast::Receiver;
+use clru::CLruCache;
+use hifitime::Duration;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, OnceLock};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+enum LastValue {
+    Integer(i64),
+    Numeric(Decimal),
+    /// The bits, so identical NaN values are collapsed too.
+    Float(u64),
+    Boolean(bool),
+}
+
+#[derive(Debug, Clone)]
+struct LastSample {
+    value: LastValue,
+    datetime: SensAppDateTime,
+}
+
+/// Drops the samples identical to the previous sample of the same sensor,
+/// a deadband compression for the sensors reporting the same value for
+/// long stretches.
+///
+/// The first sample of a sensor and the value transitions are kept.
+/// With a heartbeat, an unchanged sample is also kept when the previous
+/// kept sample is older than the heartbeat, so the series doesn't look
+/// like a dead sensor.
+///
+/// The previous values are kept in memory for the integer, numeric,
+/// float, and boolean sensors only. The other types are left untouched.
+/// They are updated once the samples are stored, see [`Self::commit`],
+/// and only the most recently updated sensors are remembered.
+#[derive(Debug)]
+pub struct CollapseUnchanged {
+    heartbeat: Option<Duration>,
+    last_samples: Mutex<CLruCache<Uuid, LastSample>>,
+}
+
+/// The last kept samples of the sensors, not stored yet.
+#[derive(Debug, Default)]
+pub struct PendingLastSamples(HashMap<Uuid, LastSample>);
+
+impl PendingLastSamples {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+static COLLAPSE_UNCHANGED: OnceLock<Arc<CollapseUnchanged>> = OnceLock::new();
+
+impl CollapseUnchanged {
+    /// A heartbeat of 0 seconds disables the heartbeat samples.
+    /// The previous samples of at most `max_sensors` sensors are remembered.
+    pub fn new(heartbeat_seconds: u64, max_sensors: usize) -> Self {
+        Self {
+            heartbeat: (heartbeat_seconds > 0)
+                .then(|| Duration::from_seconds(heartbeat_seconds as f64)),
+            last_samples: Mutex::new(CLruCache::new(
+                NonZeroUsize::new(max_sensors).unwrap_or(NonZeroUsize::MIN),
+            )),
+        }
+    }
+
+    /// The instance shared by all the batch builders,
+    /// as the previous sample often comes from a previous request.
+    pub fn shared(heartbeat_seconds: u64, max_sensors: usize) -> Arc<Self> {
+        COLLAPSE_UNCHANGED
+            .get_or_init(|| Arc::new(Self::new(heartbeat_seconds, max_sensors)))
+            .clone()
+    }
+
+    /// Removes the unchanged samples of the sensor.
+    ///
+    /// The previous sample is taken from `pending` first, and the last
+    /// kept sample is recorded there until it is committed.
+    pub fn apply(
+        &self,
+        sensor_uuid: Uuid,
+        samples: &mut TypedSamples,
+        pending: &mut PendingLastSamples,
+    ) {
+        match samples {
+            TypedSamples::Integer(samples) => {
+                self.collapse(sensor_uuid, samples, pending, |value| {
+                    LastValue::Integer(*value)
+                })
+            }
+            TypedSamples::Numeric(samples) => {
+                self.collapse(sensor_uuid, samples, pending, |value| {
+                    LastValue::Numeric(*value)
+                })
+            }
+            TypedSamples::Float(samples) => self.collapse(sensor_uuid, samples, pending, |value| {
+                LastValue::Float(value.to_bits())
+            }),
+            TypedSamples::Boolean(samples) => {
+                self.collapse(sensor_uuid, samples, pending, |value| {
+                    LastValue::Boolean(*value)
+                })
+            }
+            _ => {}
+        }
+    }
+
+    /// Remembers the pending last samples, once they are stored.
+    ///
+    /// A previous sample more recent than the pending one is kept.
+    pub fn commit(&self, pending: PendingLastSamples) {
+        let mut last_samples = self
+            .last_samples
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (sensor_uuid, last) in pending.0 {
+            if last_samples
+                .peek(&sensor_uuid)
+                .is_some_and(|previous| previous.datetime > last.datetime)
+            {
+                continue;
+            }
+            last_samples.put(sensor_uuid, last);
+        }
+    }
+
+    /// Commits the pending last samples once all the syncs are received.
+    /// Nothing is committed when a batch isn't stored.
+    pub fn commit_after(
+        self: &Arc<Self>,
+        pending: PendingLastSamples,
+        receivers: Vec<Receiver<()>>,
+    ) {
+        if pending.is_empty() {
+            return;
+        }
+        let collapse_unchanged = self.clone();
+        tokio::spawn(async move {
+            for mut receiver in receivers {
+                if receiver.recv().await.is_err() {
+                    return;
+                }
+            }
+            collapse_unchanged.commit(pending);
+        });
+    }
+
+    fn collapse<T>(
+        &self,
+        sensor_uuid: Uuid,
+        samples: &mut SensAppVec<Sample<T>>,
+        pending: &mut PendingLastSamples,
+        to_last_value: impl Fn(&T) -> LastValue,
+    ) {
+        let mut last = match pending.0.remove(&sensor_uuid) {
+            Some(last) => Some(last),
+            None => self
+                .last_samples
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get(&sensor_uuid)
+                .cloned(),
+        };
+
+        samples.retain(|sample| {
+            let value = to_last_value(&sample.value);
+            let Some(previous) = &last else {
+                last = Some(LastSample {
+                    value,
+                    datetime: sample.datetime,
+                });
+                return true;
+            };
+            // Late samples are kept, without changing the previous sample
+            if sample.datetime < previous.datetime {
+                return true;
+            }
+            let heartbeat = self
+                .heartbeat
+                .is_some_and(|heartbeat| sample.datetime - previous.datetime >= heartbeat);
+            if previous.value == value && !heartbeat {
+                return false;
+            }
+            last = Some(LastSample {
+                value,
+                datetime: sample.datetime,
+            });
+            true
+        });
+
+        if let Some(last) = last {
+            pending.0.insert(sensor_uuid, last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::sensapp_datetime::SensAppDateTimeExt;
+
+    fn integer_samples(values: &[(i64, i64)]) -> TypedSamples {
+        TypedSamples::Integer(
+            values
+                .iter()
+                .map(|(seconds, value)| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(*seconds),
+                    value: *value,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_collapse_unchanged() {
+        let collapse = CollapseUnchanged::new(0, 16);
+        let uuid = Uuid::new_v4();
+        let mut pending = PendingLastSamples::default();
+
+        let mut samples = integer_samples(&[(0, 1), (1, 1), (2, 2), (3, 2), (4, 1)]);
+        collapse.apply(uuid, &mut samples, &mut pending);
+        assert_eq!(samples, integer_samples(&[(0, 1), (2, 2), (4, 1)]));
+
+        // The previous value is remembered across calls
+        let mut samples = integer_samples(&[(5, 1), (6, 3)]);
+        collapse.apply(uuid, &mut samples, &mut pending);
+        assert_eq!(samples, integer_samples(&[(6, 3)]));
+
+        // But not across sensors
+        let mut samples = integer_samples(&[(5, 3)]);
+        collapse.apply(Uuid::new_v4(), &mut samples, &mut pending);
+        assert_eq!(samples.len(), 1);
+
+        // Late samples are kept
+        let mut samples = integer_samples(&[(1, 3)]);
+        collapse.apply(uuid, &mut samples, &mut pending);
+        assert_eq!(samples.len(), 1);
+
+        // Other types are untouched
+        let mut samples = TypedSamples::String(
+            (0..3)
+                .map(|seconds| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(seconds),
+                    value: "same".to_string(),
+                })
+                .collect(),
+        );
+        collapse.apply(uuid, &mut samples, &mut pending);
+        assert_eq!(samples.len(), 3);
+    }
+
+    #[test]
+    fn test_collapse_unchanged_commit() {
+        let collapse = CollapseUnchanged::new(0, 2);
+        let uuid = Uuid::new_v4();
+
+        // Not committed, as if the samples weren't stored
+        let mut samples = integer_samples(&[(0, 1)]);
+        collapse.apply(uuid, &mut samples, &mut PendingLastSamples::default());
+        let mut samples = integer_samples(&[(1, 1)]);
+        let mut pending = PendingLastSamples::default();
+        collapse.apply(uuid, &mut samples, &mut pending);
+        assert_eq!(samples.len(), 1);
+
+        collapse.commit(pending);
+        let mut samples = integer_samples(&[(2, 1)]);
+        collapse.apply(uuid, &mut samples, &mut PendingLastSamples::default());
+        assert_eq!(samples.len(), 0);
+
+        // The least recently used sensors are forgotten
+        for _ in 0..2 {
+            let mut pending = PendingLastSamples::default();
+            collapse.apply(
+                Uuid::new_v4(),
+                &mut integer_samples(&[(0, 1)]),
+                &mut pending,
+            );
+            collapse.commit(pending);
+        }
+        let mut samples = integer_samples(&[(3, 1)]);
+        collapse.apply(uuid, &mut samples, &mut PendingLastSamples::default());
+        assert_eq!(samples.len(), 1);
+    }
+
+    #[test]
+    fn test_collapse_unchanged_heartbeat() {
+        let collapse = CollapseUnchanged::new(10, 16);
+        let uuid = Uuid::new_v4();
+
+        let mut samples = TypedSamples::Float(
+            (0..=25)
+                .map(|seconds| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(seconds),
+                    value: 21.5,
+                })
+                .collect(),
+        );
+        collapse.apply(uuid, &mut samples, &mut PendingLastSamples::default());
+        let TypedSamples::Float(samples) = samples else {
+            unreachable!()
+        };
+        assert_eq!(
+            samples
+                .iter()
+                .map(|sample| sample.datetime.to_unix_seconds() as i64)
+                .collect::<Vec<_>>(),
+            vec![0, 10, 20]
+        );
+    }
+}
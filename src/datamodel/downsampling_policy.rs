@@ -0,0 +1,113 @@
+use super::SensAppDateTime;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+/// How the samples of a sensor are downsampled once they are older
+/// than its raw retention window.
+///
+/// The averages are stored in a companion sensor, see [`Self::rollup_name`].
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DownsamplingPolicy {
+    /// How long the raw samples are kept before being rolled up, in days.
+    pub raw_retention_days: f64,
+    /// Width of the rollup buckets, in seconds.
+    pub rollup_interval_seconds: u64,
+}
+
+impl DownsamplingPolicy {
+    pub fn new(raw_retention_days: f64, rollup_interval_seconds: u64) -> Result<Self> {
+        if !raw_retention_days.is_finite() || raw_retention_days < 0.0 {
+            bail!(
+                "The raw retention must be a positive number of days, not {}",
+                raw_retention_days
+            );
+        }
+        if rollup_interval_seconds == 0 {
+            bail!("The rollup interval must be at least one second");
+        }
+        Ok(Self {
+            raw_retention_days,
+            rollup_interval_seconds,
+        })
+    }
+
+    /// The policy stored in the columns of the sensors table,
+    /// only when both are set.
+    pub fn from_columns(
+        raw_retention_days: Option<f64>,
+        rollup_interval_seconds: Option<i64>,
+    ) -> Option<Self> {
+        Self::new(
+            raw_retention_days?,
+            u64::try_from(rollup_interval_seconds?).ok()?,
+        )
+        .ok()
+    }
+
+    /// Name of the companion sensor, such as `temperature__rollup_1h__`.
+    pub fn rollup_name(&self, name: &str) -> String {
+        format!(
+            "{}__rollup_{}__",
+            name,
+            format_interval(self.rollup_interval_seconds)
+        )
+    }
+
+    /// The end of the rollups at `now`, aligned down on the rollup interval
+    /// so only the complete buckets are rolled up.
+    pub fn cutoff(&self, now: SensAppDateTime) -> SensAppDateTime {
+        let interval = self.rollup_interval_seconds as f64;
+        let cutoff = now.to_unix_seconds() - self.raw_retention_days * 86400.0;
+        SensAppDateTime::from_unix_seconds((cutoff / interval).floor() * interval)
+    }
+}
+
+/// Formats the interval with its largest exact unit, such as 1h or 90s.
+fn format_interval(seconds: u64) -> String {
+    for (unit, unit_seconds) in [("d", 86400), ("h", 3600), ("m", 60)] {
+        if seconds.is_multiple_of(unit_seconds) {
+            return format!("{}{}", seconds / unit_seconds, unit);
+        }
+    }
+    format!("{}s", seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::sensapp_datetime::SensAppDateTimeExt;
+
+    #[test]
+    fn test_rollup_name() {
+        let name = |seconds| {
+            DownsamplingPolicy::new(7.0, seconds)
+                .unwrap()
+                .rollup_name("temp")
+        };
+        assert_eq!(name(3600), "temp__rollup_1h__");
+        assert_eq!(name(900), "temp__rollup_15m__");
+        assert_eq!(name(86400), "temp__rollup_1d__");
+        assert_eq!(name(90), "temp__rollup_90s__");
+    }
+
+    #[test]
+    fn test_policy() {
+        assert!(DownsamplingPolicy::new(-1.0, 60).is_err());
+        assert!(DownsamplingPolicy::new(f64::NAN, 60).is_err());
+        assert!(DownsamplingPolicy::new(1.0, 0).is_err());
+        assert_eq!(DownsamplingPolicy::from_columns(Some(1.0), None), None);
+        assert_eq!(
+            DownsamplingPolicy::from_columns(Some(1.0), Some(60)),
+            Some(DownsamplingPolicy::new(1.0, 60).unwrap())
+        );
+
+        // One day before, aligned down on the hour
+        let policy = DownsamplingPolicy::new(1.0, 3600).unwrap();
+        let now = SensAppDateTime::from_unix_seconds_i64(1_700_000_000);
+        assert_eq!(
+            policy.cutoff(now),
+            SensAppDateTime::from_unix_seconds_i64(1_699_912_800)
+        );
+    }
+}
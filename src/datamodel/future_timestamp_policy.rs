@@ -0,0 +1,94 @@
+use super::{SensAppDateTime, TypedSamples};
+use anyhow::{bail, Result};
+use hifitime::Duration;
+use serde::Deserialize;
+
+/// What to do with samples dated too far in the future,
+/// usually because of a device with a wrong clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FutureTimestampPolicy {
+    /// Reject the samples with an error.
+    Reject,
+    /// Replace the datetime of the samples by the current datetime.
+    Clamp,
+    /// Keep the samples as they are.
+    Allow,
+}
+
+impl FutureTimestampPolicy {
+    /// Applies the policy to the samples, with `now + max_skew` as the limit.
+    pub fn apply(
+        &self,
+        samples: &mut TypedSamples,
+        now: SensAppDateTime,
+        max_skew: Duration,
+    ) -> Result<()> {
+        if *self == FutureTimestampPolicy::Allow {
+            return Ok(());
+        }
+        let limit = now + max_skew;
+        let mut too_far = None;
+        samples.for_each_datetime_mut(|datetime| {
+            if *datetime > limit {
+                too_far.get_or_insert(*datetime);
+                *datetime = now;
+            }
+        });
+        match (self, too_far) {
+            (FutureTimestampPolicy::Reject, Some(datetime)) => bail!(
+                "Sample datetime {} is more than {} in the future",
+                datetime,
+                max_skew
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::sensapp_datetime::SensAppDateTimeExt;
+
+    #[test]
+    fn test_apply() {
+        let now = SensAppDateTime::from_unix_seconds_i64(1_700_000_000);
+        let max_skew = Duration::from_seconds(60.0);
+        let samples = || {
+            let mut samples =
+                TypedSamples::one_integer(1, SensAppDateTime::from_unix_seconds_i64(1_700_000_030));
+            if let TypedSamples::Integer(ref mut vec) = samples {
+                vec.push(crate::datamodel::Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(4_070_908_800),
+                    value: 2,
+                });
+            }
+            samples
+        };
+
+        let mut allowed = samples();
+        FutureTimestampPolicy::Allow
+            .apply(&mut allowed, now, max_skew)
+            .unwrap();
+        assert_eq!(allowed, samples());
+
+        let mut clamped = samples();
+        FutureTimestampPolicy::Clamp
+            .apply(&mut clamped, now, max_skew)
+            .unwrap();
+        let TypedSamples::Integer(vec) = clamped else {
+            panic!("Expected integer samples");
+        };
+        assert_eq!(
+            vec[0].datetime,
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_030)
+        );
+        assert_eq!(vec[1].datetime, now);
+
+        let mut rejected = samples();
+        assert!(FutureTimestampPolicy::Reject
+            .apply(&mut rejected, now, max_skew)
+            .is_err());
+    }
+}
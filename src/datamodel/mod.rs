@@ -1,16 +1,29 @@
+pub mod annotation;
+pub mod arrow_converter;
 pub mod batch;
 pub mod batch_builder;
+pub mod collapse_unchanged;
+pub mod downsampling_policy;
+pub mod future_timestamp_policy;
+pub mod numeric_precision;
+pub mod range_violation_policy;
 pub mod sample;
 pub mod sensapp_datetime;
 pub mod sensapp_vec;
 pub mod sensor;
+pub mod sensor_data;
+pub mod sensor_metadata;
 pub mod sensor_type;
+pub mod timestamp_rounding;
 pub mod typed_samples;
 pub mod unit;
+pub mod unit_mapping;
 
+pub use annotation::Annotation;
 pub use sample::Sample;
 pub use sensapp_datetime::SensAppDateTime;
 pub use sensapp_vec::SensAppVec;
 pub use sensor::Sensor;
+pub use sensor_data::SensorData;
 pub use sensor_type::SensorType;
 pub use typed_samples::TypedSamples;
@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use rust_decimal::Decimal;
+
+/// The maximum number of significant digits of a `rust_decimal::Decimal`.
+const MAX_PRECISION: u32 = 28;
+
+/// Precision and scale of the numeric values, like SQL `DECIMAL(precision, scale)`.
+///
+/// The default is `DECIMAL(18, 6)`, the type of the DuckDB numeric values
+/// before it was configurable. At most 28 significant digits, the maximum
+/// of `rust_decimal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumericPrecision {
+    pub precision: u32,
+    pub scale: u32,
+}
+
+impl Default for NumericPrecision {
+    fn default() -> Self {
+        Self {
+            precision: 18,
+            scale: 6,
+        }
+    }
+}
+
+impl NumericPrecision {
+    pub fn new(precision: u32, scale: u32) -> Result<Self> {
+        if precision == 0 || precision > MAX_PRECISION {
+            bail!(
+                "Numeric precision must be between 1 and {}, got {}",
+                MAX_PRECISION,
+                precision
+            );
+        }
+        if scale > precision {
+            bail!(
+                "Numeric scale ({}) must not be greater than the precision ({})",
+                scale,
+                precision
+            );
+        }
+        Ok(Self { precision, scale })
+    }
+
+    pub fn from_config() -> Result<Self> {
+        let config = crate::config::get()?;
+        Self::new(config.numeric_precision, config.numeric_scale)
+    }
+
+    /// Checks that the value fits without rounding.
+    ///
+    /// Trailing zeros are ignored, so `1.50` fits in a scale of 1.
+    pub fn validate(&self, value: &Decimal) -> Result<()> {
+        let normalized = value.normalize();
+        if normalized.scale() > self.scale {
+            bail!(
+                "Numeric value {} has more than {} digits after the decimal point",
+                value,
+                self.scale
+            );
+        }
+        let digits = normalized.mantissa().unsigned_abs().to_string().len() as u32;
+        let integer_digits = digits.saturating_sub(normalized.scale());
+        if integer_digits > self.precision - self.scale {
+            bail!(
+                "Numeric value {} has more than {} digits before the decimal point",
+                value,
+                self.precision - self.scale
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_new() {
+        assert!(NumericPrecision::new(28, 10).is_ok());
+        assert!(NumericPrecision::new(0, 0).is_err());
+        assert!(NumericPrecision::new(38, 18).is_err());
+        assert!(NumericPrecision::new(10, 11).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let precision = NumericPrecision::new(8, 3).unwrap();
+        let validate = |value: &str| precision.validate(&Decimal::from_str(value).unwrap());
+        assert!(validate("12345.678").is_ok());
+        assert!(validate("-12345.678").is_ok());
+        assert!(validate("0.001").is_ok());
+        assert!(validate("1.5000").is_ok());
+        assert!(validate("12345.6789").is_err());
+        assert!(validate("123456.7").is_err());
+
+        let precision = NumericPrecision::default();
+        assert!(precision
+            .validate(&Decimal::from_str("123456789012.123456").unwrap())
+            .is_ok());
+        assert!(precision
+            .validate(&Decimal::from_str("123456789012.1234567").unwrap())
+            .is_err());
+        assert!(precision
+            .validate(&Decimal::from_str("1234567890123.1").unwrap())
+            .is_err());
+    }
+}
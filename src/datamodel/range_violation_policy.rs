@@ -0,0 +1,239 @@
+use super::{sensapp_vec::SensAppVec, Sample, Sensor, TypedSamples};
+use anyhow::{bail, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::fmt::Display;
+
+/// What to do with the samples outside the value range of their sensor,
+/// usually a faulty device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RangeViolationPolicy {
+    /// Reject the samples with an error.
+    Reject,
+    /// Drop the samples outside the range.
+    Skip,
+    /// Replace the values by the closest bound.
+    Clamp,
+    /// Keep the samples as they are.
+    Store,
+}
+
+impl RangeViolationPolicy {
+    /// Applies the policy to the integer, numeric, and float samples,
+    /// with the value range of the sensor as bounds, usually the stored one.
+    ///
+    /// The violations are logged, the other sample types are left untouched.
+    pub fn apply(
+        &self,
+        sensor: &Sensor,
+        min_value: Option<f64>,
+        max_value: Option<f64>,
+        samples: &mut TypedSamples,
+    ) -> Result<()> {
+        if min_value.is_none() && max_value.is_none() {
+            return Ok(());
+        }
+        let violations = match samples {
+            TypedSamples::Integer(vec) => self.apply_to(
+                sensor,
+                vec,
+                min_value.map(|min| min.ceil() as i64),
+                max_value.map(|max| max.floor() as i64),
+            )?,
+            TypedSamples::Numeric(vec) => self.apply_to(
+                sensor,
+                vec,
+                min_value.and_then(|min| Decimal::try_from(min).ok()),
+                max_value.and_then(|max| Decimal::try_from(max).ok()),
+            )?,
+            TypedSamples::Float(vec) => self.apply_to(sensor, vec, min_value, max_value)?,
+            _ => 0,
+        };
+        if violations > 0 {
+            tracing::warn!(
+                "{} samples of sensor {} are outside its value range, policy: {:?}",
+                violations,
+                sensor.name,
+                self
+            );
+        }
+        Ok(())
+    }
+
+    fn apply_to<T: Copy + PartialOrd + Display>(
+        &self,
+        sensor: &Sensor,
+        samples: &mut SensAppVec<Sample<T>>,
+        min: Option<T>,
+        max: Option<T>,
+    ) -> Result<usize> {
+        let below = |value: T| min.is_some_and(|min| value < min);
+        let above = |value: T| max.is_some_and(|max| value > max);
+        let violations = samples
+            .iter()
+            .filter(|sample| below(sample.value) || above(sample.value))
+            .count();
+        if violations == 0 {
+            return Ok(0);
+        }
+        match self {
+            RangeViolationPolicy::Reject => {
+                let sample = samples
+                    .iter()
+                    .find(|sample| below(sample.value) || above(sample.value))
+                    .expect("a violation was counted");
+                bail!(
+                    "Sample value {} of sensor {} is outside its value range",
+                    sample.value,
+                    sensor.name
+                );
+            }
+            RangeViolationPolicy::Skip => {
+                samples.retain(|sample| !below(sample.value) && !above(sample.value));
+            }
+            RangeViolationPolicy::Clamp => {
+                for sample in samples.iter_mut() {
+                    if below(sample.value) {
+                        sample.value = min.expect("below a minimum");
+                    } else if above(sample.value) {
+                        sample.value = max.expect("above a maximum");
+                    }
+                }
+            }
+            RangeViolationPolicy::Store => {}
+        }
+        Ok(violations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{sensapp_datetime::SensAppDateTimeExt, SensAppDateTime, SensorType};
+    use smallvec::smallvec;
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    fn thermometer(sensor_type: SensorType) -> Sensor {
+        Sensor::new(
+            Uuid::nil(),
+            "temperature".to_string(),
+            sensor_type,
+            None,
+            None,
+        )
+        .with_value_range(Some(-50.0), Some(60.0))
+    }
+
+    fn float_samples() -> TypedSamples {
+        let datetime = SensAppDateTime::from_unix_seconds_i64(1_700_000_000);
+        TypedSamples::Float(smallvec![
+            Sample {
+                datetime,
+                value: 21.5,
+            },
+            Sample {
+                datetime,
+                value: 10000.0,
+            },
+            Sample {
+                datetime,
+                value: -273.15,
+            },
+        ])
+    }
+
+    #[test]
+    fn test_apply() {
+        let sensor = thermometer(SensorType::Float);
+
+        let mut stored = float_samples();
+        RangeViolationPolicy::Store
+            .apply(&sensor, sensor.min_value, sensor.max_value, &mut stored)
+            .unwrap();
+        assert_eq!(stored, float_samples());
+
+        let mut skipped = float_samples();
+        RangeViolationPolicy::Skip
+            .apply(&sensor, sensor.min_value, sensor.max_value, &mut skipped)
+            .unwrap();
+        let TypedSamples::Float(vec) = skipped else {
+            panic!("Expected float samples");
+        };
+        assert_eq!(vec.len(), 1);
+        assert_eq!(vec[0].value, 21.5);
+
+        let mut clamped = float_samples();
+        RangeViolationPolicy::Clamp
+            .apply(&sensor, sensor.min_value, sensor.max_value, &mut clamped)
+            .unwrap();
+        let TypedSamples::Float(vec) = clamped else {
+            panic!("Expected float samples");
+        };
+        assert_eq!(
+            vec.iter().map(|sample| sample.value).collect::<Vec<_>>(),
+            vec![21.5, 60.0, -50.0]
+        );
+
+        let mut rejected = float_samples();
+        let error = RangeViolationPolicy::Reject
+            .apply(&sensor, sensor.min_value, sensor.max_value, &mut rejected)
+            .unwrap_err();
+        assert!(error.to_string().contains("outside its value range"));
+
+        // Without range, nothing is a violation
+        let mut unbounded = float_samples();
+        let sensor = Sensor::new(
+            Uuid::nil(),
+            "temperature".to_string(),
+            SensorType::Float,
+            None,
+            None,
+        );
+        RangeViolationPolicy::Reject
+            .apply(&sensor, sensor.min_value, sensor.max_value, &mut unbounded)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_apply_integer_and_numeric() {
+        let datetime = SensAppDateTime::from_unix_seconds_i64(1_700_000_000);
+
+        let mut integers = TypedSamples::one_integer(100, datetime);
+        RangeViolationPolicy::Clamp
+            .apply(
+                &thermometer(SensorType::Integer),
+                Some(-50.0),
+                Some(60.0),
+                &mut integers,
+            )
+            .unwrap();
+        assert_eq!(integers, TypedSamples::one_integer(60, datetime));
+
+        let mut numerics = TypedSamples::one_numeric(Decimal::from_str("-60.5").unwrap(), datetime);
+        RangeViolationPolicy::Clamp
+            .apply(
+                &thermometer(SensorType::Numeric),
+                Some(-50.0),
+                Some(60.0),
+                &mut numerics,
+            )
+            .unwrap();
+        assert_eq!(
+            numerics,
+            TypedSamples::one_numeric(Decimal::from(-50), datetime)
+        );
+
+        // Other types are ignored
+        let mut strings = TypedSamples::one_string("hot".to_string(), datetime);
+        RangeViolationPolicy::Reject
+            .apply(
+                &thermometer(SensorType::String),
+                Some(-50.0),
+                Some(60.0),
+                &mut strings,
+            )
+            .unwrap();
+    }
+}
@@ -6,6 +6,9 @@ pub trait SensAppDateTimeExt {
     fn from_unix_microseconds_i64(timestamp: i64) -> Self;
     fn from_unix_milliseconds_i64(timestamp: i64) -> Self;
     fn from_unix_seconds_i64(timestamp: i64) -> Self;
+    /// Exact number of nanoseconds since the unix epoch,
+    /// saturating outside of the i64 range (years 1677 to 2262).
+    fn to_unix_nanoseconds_i64(&self) -> i64;
 }
 
 impl SensAppDateTimeExt for SensAppDateTime {
@@ -21,6 +24,11 @@ impl SensAppDateTimeExt for SensAppDateTime {
     fn from_unix_seconds_i64(timestamp: i64) -> Self {
         Self::from_utc_duration(UNIX_REF_EPOCH.to_utc_duration() + timestamp * Unit::Second)
     }
+    fn to_unix_nanoseconds_i64(&self) -> i64 {
+        let nanoseconds =
+            (self.to_utc_duration() - UNIX_REF_EPOCH.to_utc_duration()).total_nanoseconds();
+        nanoseconds.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
 }
 
 use hifitime::{Unit, UNIX_REF_EPOCH};
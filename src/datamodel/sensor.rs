@@ -1,6 +1,9 @@
 use crate::config;
 
-use super::{sensapp_vec::SensAppLabels, unit::Unit, SensorType};
+use super::{
+    downsampling_policy::DownsamplingPolicy, sensapp_vec::SensAppLabels, unit::Unit,
+    SensAppDateTime, SensorType,
+};
 use anyhow::{anyhow, Error};
 use cached::proc_macro::cached;
 use once_cell::sync::OnceCell;
@@ -16,6 +19,22 @@ pub struct Sensor {
     pub sensor_type: SensorType,
     pub unit: Option<Unit>,
     pub labels: SensAppLabels,
+    /// Physical bounds of the values, for the range violation policy.
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    /// Expected time between two samples of an evenly sampled sensor,
+    /// to detect the gaps.
+    pub nominal_interval_seconds: Option<f64>,
+    /// How the old samples are rolled up, for the numeric sensors.
+    pub downsampling: Option<DownsamplingPolicy>,
+    /// Integer id of the sensor in the SQL storages, when loaded from them.
+    /// It isn't part of the UUID, and can be used for their faster queries.
+    pub sensor_id: Option<i64>,
+    /// When the sensor was first stored, for the storages that record it.
+    pub created_at: Option<SensAppDateTime>,
+    /// When the sensor was last published, even without new samples,
+    /// for the storages that record it.
+    pub last_seen: Option<SensAppDateTime>,
 }
 
 impl fmt::Display for Sensor {
@@ -34,6 +53,22 @@ impl fmt::Display for Sensor {
             write!(f, ", labels: {:?}", self.labels)?;
         }
 
+        if let Some(min_value) = self.min_value {
+            write!(f, ", min_value: {}", min_value)?;
+        }
+
+        if let Some(max_value) = self.max_value {
+            write!(f, ", max_value: {}", max_value)?;
+        }
+
+        if let Some(nominal_interval_seconds) = self.nominal_interval_seconds {
+            write!(
+                f,
+                ", nominal_interval_seconds: {}",
+                nominal_interval_seconds
+            )?;
+        }
+
         write!(f, " }}")
     }
 }
@@ -50,6 +85,148 @@ fn sort_labels(labels: &mut SensAppLabels) {
     });
 }
 
+/// A label key or value above the maximum length, rejected by the ingestion.
+#[derive(Debug)]
+pub struct LabelTooLongError {
+    /// The start of the key, for the error message.
+    pub key: String,
+    pub is_value: bool,
+    pub max_length: usize,
+}
+
+impl fmt::Display for LabelTooLongError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_value {
+            write!(
+                f,
+                "The value of the label {:?} is longer than the maximum of {} characters",
+                self.key, self.max_length
+            )
+        } else {
+            write!(
+                f,
+                "The label key {:?}... is longer than the maximum of {} characters",
+                self.key, self.max_length
+            )
+        }
+    }
+}
+
+impl std::error::Error for LabelTooLongError {}
+
+/// How the labels of the ingested sensors are normalised, before computing their UUIDs.
+#[derive(Debug, Clone, Copy)]
+struct LabelsNormalisation {
+    drop_empty_labels: bool,
+    lowercase_keys: bool,
+    lowercase_values: bool,
+    /// Maximum lengths in characters, 0 for no limit.
+    max_key_length: usize,
+    max_value_length: usize,
+    truncate_long_labels: bool,
+}
+
+impl LabelsNormalisation {
+    fn from_config() -> Self {
+        match config::get() {
+            Ok(config) => Self {
+                drop_empty_labels: config.drop_empty_labels,
+                lowercase_keys: config.labels_lowercase_keys,
+                lowercase_values: config.labels_lowercase_keys && config.labels_lowercase_values,
+                max_key_length: config.max_label_key_length,
+                max_value_length: config.max_label_value_length,
+                truncate_long_labels: config.truncate_long_labels,
+            },
+            Err(_) => Self {
+                drop_empty_labels: true,
+                lowercase_keys: false,
+                lowercase_values: false,
+                max_key_length: 0,
+                max_value_length: 0,
+                truncate_long_labels: false,
+            },
+        }
+    }
+}
+
+/// The byte index where the string goes above the maximum length in characters.
+fn overlong_index(s: &str, max_length: usize) -> Option<usize> {
+    if max_length == 0 {
+        return None;
+    }
+    s.char_indices().nth(max_length).map(|(index, _)| index)
+}
+
+/// The byte index to truncate the label key or value at, when it's above
+/// its maximum length and the long labels are truncated, or an error.
+fn overlong_label(
+    key: &str,
+    label: &str,
+    is_value: bool,
+    normalisation: LabelsNormalisation,
+) -> Result<Option<usize>, LabelTooLongError> {
+    let max_length = if is_value {
+        normalisation.max_value_length
+    } else {
+        normalisation.max_key_length
+    };
+    match overlong_index(label, max_length) {
+        Some(index) if normalisation.truncate_long_labels => Ok(Some(index)),
+        Some(_) => Err(LabelTooLongError {
+            key: key[..overlong_index(key, 64).unwrap_or(key.len())].to_string(),
+            is_value,
+            max_length,
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Sorts the labels and optionally drops the labels with an empty key or value,
+/// and lowercases their keys and values.
+///
+/// Some sources send empty labels, like `env=""`, or are inconsistent
+/// about the casing, like `Env` and `env`, that would otherwise
+/// create distinct series for the same sensor.
+///
+/// Fails with a [`LabelTooLongError`] on the keys or values above the maximum
+/// lengths, unless they are truncated.
+fn prepare_labels(
+    labels: Option<SensAppLabels>,
+    normalisation: LabelsNormalisation,
+) -> Result<Option<SensAppLabels>, Error> {
+    let Some(mut labels) = labels else {
+        return Ok(None);
+    };
+    for (key, value) in labels.iter_mut() {
+        if let Some(index) = overlong_label(key, key, false, normalisation)? {
+            key.truncate(index);
+        }
+        if let Some(index) = overlong_label(key, value, true, normalisation)? {
+            value.truncate(index);
+        }
+    }
+    Ok(Some({
+        if normalisation.drop_empty_labels {
+            labels.retain(|(key, value)| !key.is_empty() && !value.is_empty());
+        }
+        if normalisation.lowercase_keys {
+            for (key, value) in labels.iter_mut() {
+                *key = key.to_lowercase();
+                if normalisation.lowercase_values {
+                    *value = value.to_lowercase();
+                }
+            }
+        }
+        sort_labels(&mut labels);
+        if normalisation.lowercase_keys || normalisation.truncate_long_labels {
+            // The casing variants, or the truncated variants,
+            // of the same label are now duplicates
+            labels.dedup();
+        }
+        labels
+    }))
+}
+
 /// Checks if the given string contains any of the special ASCII characters.
 ///
 /// The special characters checked are:
@@ -199,6 +376,13 @@ impl Sensor {
                     }
                 }
             },
+            min_value: None,
+            max_value: None,
+            nominal_interval_seconds: None,
+            downsampling: None,
+            sensor_id: None,
+            created_at: None,
+            last_seen: None,
         }
     }
 
@@ -208,13 +392,23 @@ impl Sensor {
         unit: Option<Unit>,
         labels: Option<SensAppLabels>,
     ) -> Result<Self, Error> {
-        let sorted_labels = match labels {
-            None => None,
-            Some(mut labels) => {
-                sort_labels(&mut labels);
-                Some(labels)
-            }
-        };
+        Self::new_without_uuid_normalised(
+            name,
+            sensor_type,
+            unit,
+            labels,
+            LabelsNormalisation::from_config(),
+        )
+    }
+
+    fn new_without_uuid_normalised(
+        name: String,
+        sensor_type: SensorType,
+        unit: Option<Unit>,
+        labels: Option<SensAppLabels>,
+        normalisation: LabelsNormalisation,
+    ) -> Result<Self, Error> {
+        let sorted_labels = prepare_labels(labels, normalisation)?;
         let uuid_buffer = compute_uuid_buffer(&name, &sensor_type, &unit, &sorted_labels)?;
         let uuid = uuid_v8_blake3(&name, uuid_buffer)?;
         Ok(Self {
@@ -223,8 +417,52 @@ impl Sensor {
             sensor_type,
             unit,
             labels: sorted_labels.unwrap_or_else(SmallVec::new),
+            min_value: None,
+            max_value: None,
+            nominal_interval_seconds: None,
+            downsampling: None,
+            sensor_id: None,
+            created_at: None,
+            last_seen: None,
         })
     }
+
+    /// Sets the value range. It isn't part of the UUID.
+    pub fn with_value_range(mut self, min_value: Option<f64>, max_value: Option<f64>) -> Self {
+        self.min_value = min_value;
+        self.max_value = max_value;
+        self
+    }
+
+    /// Sets the expected sampling interval. It isn't part of the UUID.
+    pub fn with_nominal_interval(mut self, nominal_interval_seconds: Option<f64>) -> Self {
+        self.nominal_interval_seconds = nominal_interval_seconds;
+        self
+    }
+
+    /// Sets the downsampling policy. It isn't part of the UUID.
+    pub fn with_downsampling(mut self, downsampling: Option<DownsamplingPolicy>) -> Self {
+        self.downsampling = downsampling;
+        self
+    }
+
+    /// Sets the integer id of the sensor in the storage.
+    pub fn with_sensor_id(mut self, sensor_id: i64) -> Self {
+        self.sensor_id = Some(sensor_id);
+        self
+    }
+
+    /// Sets when the sensor was first stored.
+    pub fn with_created_at(mut self, created_at: Option<SensAppDateTime>) -> Self {
+        self.created_at = created_at;
+        self
+    }
+
+    /// Sets when the sensor was last published.
+    pub fn with_last_seen(mut self, last_seen: Option<SensAppDateTime>) -> Self {
+        self.last_seen = last_seen;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -259,6 +497,187 @@ mod tests {
         assert!(empty_labels.is_empty());
     }
 
+    #[test]
+    fn test_prepare_labels() {
+        let labels: SensAppLabels = smallvec::smallvec![
+            ("env".to_string(), "".to_string()),
+            ("room".to_string(), "kitchen".to_string()),
+            ("".to_string(), "orphan".to_string()),
+        ];
+
+        let normalisation =
+            |drop_empty_labels, lowercase_keys, lowercase_values| LabelsNormalisation {
+                drop_empty_labels,
+                lowercase_keys,
+                lowercase_values,
+                max_key_length: 0,
+                max_value_length: 0,
+                truncate_long_labels: false,
+            };
+        let dropped = prepare_labels(Some(labels.clone()), normalisation(true, false, false))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            dropped.to_vec(),
+            vec![("room".to_string(), "kitchen".to_string())]
+        );
+
+        let kept = prepare_labels(Some(labels), normalisation(false, false, false))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            kept.to_vec(),
+            vec![
+                ("".to_string(), "orphan".to_string()),
+                ("env".to_string(), "".to_string()),
+                ("room".to_string(), "kitchen".to_string()),
+            ]
+        );
+
+        assert!(prepare_labels(None, normalisation(true, false, false))
+            .unwrap()
+            .is_none());
+
+        let mixed_case: SensAppLabels = smallvec::smallvec![
+            ("Env".to_string(), "Prod".to_string()),
+            ("env".to_string(), "prod".to_string()),
+            ("ROOM".to_string(), "Kitchen".to_string()),
+        ];
+        let lowercase_keys =
+            prepare_labels(Some(mixed_case.clone()), normalisation(true, true, false))
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            lowercase_keys.to_vec(),
+            vec![
+                ("env".to_string(), "Prod".to_string()),
+                ("env".to_string(), "prod".to_string()),
+                ("room".to_string(), "Kitchen".to_string()),
+            ]
+        );
+        let lowercase_all = prepare_labels(Some(mixed_case), normalisation(true, true, true))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            lowercase_all.to_vec(),
+            vec![
+                ("env".to_string(), "prod".to_string()),
+                ("room".to_string(), "kitchen".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_new_without_uuid_drops_empty_labels() {
+        _ = load_configuration();
+        let with_empty_label = Sensor::new_without_uuid(
+            "test".to_string(),
+            SensorType::Float,
+            None,
+            Some(smallvec::smallvec![
+                ("env".to_string(), "".to_string()),
+                ("room".to_string(), "kitchen".to_string()),
+            ]),
+        )
+        .unwrap();
+        let without_empty_label = Sensor::new_without_uuid(
+            "test".to_string(),
+            SensorType::Float,
+            None,
+            Some(smallvec::smallvec![(
+                "room".to_string(),
+                "kitchen".to_string()
+            )]),
+        )
+        .unwrap();
+        assert_eq!(with_empty_label.labels.len(), 1);
+        assert_eq!(with_empty_label.uuid, without_empty_label.uuid);
+    }
+
+    #[test]
+    fn test_lowercase_label_keys_unify_series() {
+        _ = load_configuration();
+        let sensor = |labels: SensAppLabels, lowercase_keys| {
+            Sensor::new_without_uuid_normalised(
+                "test".to_string(),
+                SensorType::Float,
+                None,
+                Some(labels),
+                LabelsNormalisation {
+                    drop_empty_labels: true,
+                    lowercase_keys,
+                    lowercase_values: false,
+                    max_key_length: 0,
+                    max_value_length: 0,
+                    truncate_long_labels: false,
+                },
+            )
+            .unwrap()
+        };
+        let upper_case = || smallvec::smallvec![("Env".to_string(), "prod".to_string())];
+        let lower_case = || smallvec::smallvec![("env".to_string(), "prod".to_string())];
+
+        let enabled = sensor(upper_case(), true);
+        assert_eq!(enabled.uuid, sensor(lower_case(), true).uuid);
+        assert_eq!(enabled.labels[0].0, "env");
+
+        let disabled = sensor(upper_case(), false);
+        assert_ne!(disabled.uuid, sensor(lower_case(), false).uuid);
+        assert_eq!(disabled.labels[0].0, "Env");
+    }
+
+    #[test]
+    fn test_label_length_limits() {
+        let sensor = |truncate_long_labels| {
+            Sensor::new_without_uuid_normalised(
+                "test".to_string(),
+                SensorType::Float,
+                None,
+                Some(smallvec::smallvec![
+                    ("room".to_string(), "k".repeat(2000)),
+                    ("env".to_string(), "prod".to_string()),
+                ]),
+                LabelsNormalisation {
+                    drop_empty_labels: true,
+                    lowercase_keys: false,
+                    lowercase_values: false,
+                    max_key_length: 16,
+                    max_value_length: 1024,
+                    truncate_long_labels,
+                },
+            )
+        };
+
+        let truncated = sensor(true).unwrap();
+        assert_eq!(truncated.labels[0], ("env".to_string(), "prod".to_string()));
+        assert_eq!(truncated.labels[1].1, "k".repeat(1024));
+
+        let error = sensor(false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The value of the label \"room\" is longer than the maximum of 1024 characters"
+        );
+        assert!(matches!(
+            crate::ingestors::http::app_error::AppError::from(error),
+            crate::ingestors::http::app_error::AppError::BadRequest(_)
+        ));
+
+        // The keys are limited too, and the characters aren't split
+        let labels = smallvec::smallvec![("é".repeat(20), "value".to_string())];
+        let normalisation = LabelsNormalisation {
+            drop_empty_labels: true,
+            lowercase_keys: false,
+            lowercase_values: false,
+            max_key_length: 16,
+            max_value_length: 1024,
+            truncate_long_labels: true,
+        };
+        let truncated = prepare_labels(Some(labels), normalisation)
+            .unwrap()
+            .unwrap();
+        assert_eq!(truncated[0].0, "é".repeat(16));
+    }
+
     #[test]
     fn test_contains_special_chars() {
         assert!(contains_special_chars("\x0Btest"));
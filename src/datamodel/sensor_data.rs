@@ -0,0 +1,14 @@
+use super::{Sensor, TypedSamples};
+
+/// The samples of a sensor, as returned by the storage backends when querying.
+#[derive(Debug)]
+pub struct SensorData {
+    pub sensor: Sensor,
+    pub samples: TypedSamples,
+}
+
+impl SensorData {
+    pub fn new(sensor: Sensor, samples: TypedSamples) -> Self {
+        Self { sensor, samples }
+    }
+}
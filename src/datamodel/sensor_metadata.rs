@@ -0,0 +1,87 @@
+use super::downsampling_policy::DownsamplingPolicy;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+/// The metadata of a sensor that aren't part of its UUID,
+/// so they can be set once the sensor exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SensorMetadata {
+    /// Physical bounds of the values, for the range violation policy.
+    pub min_value: Option<f64>,
+    pub max_value: Option<f64>,
+    /// Expected time between two samples, to detect the gaps.
+    pub nominal_interval_seconds: Option<f64>,
+    /// Rollup of the samples older than the raw retention window.
+    pub downsampling: Option<DownsamplingPolicy>,
+}
+
+impl SensorMetadata {
+    pub fn validate(&self) -> Result<()> {
+        for bound in [self.min_value, self.max_value].into_iter().flatten() {
+            if !bound.is_finite() {
+                bail!("The value range bounds must be finite, not {}", bound);
+            }
+        }
+        if let (Some(min_value), Some(max_value)) = (self.min_value, self.max_value) {
+            if min_value > max_value {
+                bail!(
+                    "The minimum value {} is above the maximum value {}",
+                    min_value,
+                    max_value
+                );
+            }
+        }
+        if let Some(interval) = self.nominal_interval_seconds {
+            if !interval.is_finite() || interval <= 0.0 {
+                bail!(
+                    "The nominal interval must be a positive number of seconds, not {}",
+                    interval
+                );
+            }
+        }
+        if let Some(policy) = self.downsampling {
+            DownsamplingPolicy::new(policy.raw_retention_days, policy.rollup_interval_seconds)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let metadata = |min_value, max_value| SensorMetadata {
+            min_value,
+            max_value,
+            ..Default::default()
+        };
+        assert!(metadata(None, None).validate().is_ok());
+        assert!(metadata(Some(-50.0), Some(60.0)).validate().is_ok());
+        assert!(metadata(Some(60.0), None).validate().is_ok());
+        assert!(metadata(Some(60.0), Some(-50.0)).validate().is_err());
+        assert!(metadata(Some(f64::NAN), None).validate().is_err());
+        assert!(metadata(None, Some(f64::INFINITY)).validate().is_err());
+
+        let interval = |nominal_interval_seconds| SensorMetadata {
+            nominal_interval_seconds,
+            ..Default::default()
+        };
+        assert!(interval(Some(60.0)).validate().is_ok());
+        assert!(interval(Some(0.0)).validate().is_err());
+        assert!(interval(Some(f64::NAN)).validate().is_err());
+
+        let downsampling = |raw_retention_days, rollup_interval_seconds| SensorMetadata {
+            downsampling: Some(DownsamplingPolicy {
+                raw_retention_days,
+                rollup_interval_seconds,
+            }),
+            ..Default::default()
+        };
+        assert!(downsampling(7.0, 3600).validate().is_ok());
+        assert!(downsampling(-1.0, 3600).validate().is_err());
+        assert!(downsampling(7.0, 0).validate().is_err());
+    }
+}
@@ -1,6 +1,8 @@
+use anyhow::{bail, Error};
 use std::{
     hash::Hash,
     io::{self, Write},
+    str::FromStr,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -31,7 +33,36 @@ impl ToString for SensorType {
     }
 }
 
+impl FromStr for SensorType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Integer" => Ok(SensorType::Integer),
+            "Numeric" => Ok(SensorType::Numeric),
+            "Float" => Ok(SensorType::Float),
+            "String" => Ok(SensorType::String),
+            "Boolean" => Ok(SensorType::Boolean),
+            "Location" => Ok(SensorType::Location),
+            "JSON" => Ok(SensorType::Json),
+            "Blob" => Ok(SensorType::Blob),
+            _ => bail!("Unknown sensor type: {}", s),
+        }
+    }
+}
+
 impl SensorType {
+    pub const ALL: [SensorType; 8] = [
+        SensorType::Integer,
+        SensorType::Numeric,
+        SensorType::Float,
+        SensorType::String,
+        SensorType::Boolean,
+        SensorType::Location,
+        SensorType::Json,
+        SensorType::Blob,
+    ];
+
     fn to_u8(self) -> u8 {
         self as u8
     }
@@ -58,6 +89,17 @@ mod tests {
         assert_eq!(SensorType::Blob.to_string(), "Blob");
     }
 
+    #[test]
+    fn test_sensor_type_from_str() {
+        for sensor_type in SensorType::ALL {
+            assert_eq!(
+                SensorType::from_str(&sensor_type.to_string()).unwrap(),
+                sensor_type
+            );
+        }
+        assert!(SensorType::from_str("Unknown").is_err());
+    }
+
     #[test]
     fn test_sensor_type_to_u8() {
         assert_eq!(SensorType::Integer.to_u8(), 1);
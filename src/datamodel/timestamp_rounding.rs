@@ -0,0 +1,126 @@
+use super::{sensapp_datetime::SensAppDateTimeExt, SensAppDateTime, TypedSamples};
+use anyhow::{bail, Context, Result};
+
+/// Rounds the datetimes of the samples to the nearest multiple of a step,
+/// counted from the unix epoch, to remove the jitter of the sensor clocks.
+///
+/// The samples exactly between two multiples are rounded up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampRounding {
+    step_nanoseconds: i64,
+}
+
+impl TimestampRounding {
+    pub fn new(step_nanoseconds: i64) -> Result<Self> {
+        if step_nanoseconds <= 0 {
+            bail!("The timestamp rounding step must be positive");
+        }
+        Ok(Self { step_nanoseconds })
+    }
+
+    /// Parses a step like `1s`, `100ms`, `500us`, `5m` or `1h`.
+    pub fn parse(step: &str) -> Result<Self> {
+        let step = step.trim();
+        let unit_start = step
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(step.len());
+        let (value, unit) = step.split_at(unit_start);
+        let value: i64 = value
+            .parse()
+            .with_context(|| format!("Invalid timestamp rounding step: {}", step))?;
+        let unit_nanoseconds = match unit.trim() {
+            "ns" => 1,
+            "us" | "µs" => 1_000,
+            "ms" => 1_000_000,
+            "s" => 1_000_000_000,
+            "m" => 60_000_000_000,
+            "h" => 3_600_000_000_000,
+            _ => bail!(
+                "Invalid timestamp rounding step: {}, use ns, us, ms, s, m or h",
+                step
+            ),
+        };
+        Self::new(
+            value
+                .checked_mul(unit_nanoseconds)
+                .with_context(|| format!("Timestamp rounding step too large: {}", step))?,
+        )
+    }
+
+    pub fn round(&self, datetime: SensAppDateTime) -> SensAppDateTime {
+        let step = self.step_nanoseconds as i128;
+        let nanoseconds = datetime.to_unix_nanoseconds_i64() as i128;
+        let rounded = (nanoseconds + step / 2).div_euclid(step) * step;
+        SensAppDateTime::from_unix_nanoseconds_i64(rounded as i64)
+    }
+
+    pub fn apply(&self, samples: &mut TypedSamples) {
+        samples.for_each_datetime_mut(|datetime| *datetime = self.round(*datetime));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nanoseconds(datetime: SensAppDateTime) -> i64 {
+        datetime.to_unix_nanoseconds_i64()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            TimestampRounding::parse("1s").unwrap(),
+            TimestampRounding::new(1_000_000_000).unwrap()
+        );
+        assert_eq!(
+            TimestampRounding::parse("100ms").unwrap(),
+            TimestampRounding::new(100_000_000).unwrap()
+        );
+        assert_eq!(
+            TimestampRounding::parse(" 5m ").unwrap(),
+            TimestampRounding::new(300_000_000_000).unwrap()
+        );
+        assert_eq!(
+            TimestampRounding::parse("250us").unwrap(),
+            TimestampRounding::new(250_000).unwrap()
+        );
+        for invalid in ["", "s", "0s", "-1s", "1.5s", "10", "1d", "99999999999h"] {
+            assert!(TimestampRounding::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_round() {
+        let second = TimestampRounding::parse("1s").unwrap();
+        let base = 1_700_000_000_000_000_000;
+        for (jittered, expected) in [
+            (base + 12_000_000, base),
+            (base + 999_000_000, base + 1_000_000_000),
+            (base - 3_000_000, base),
+            (base + 500_000_000, base + 1_000_000_000),
+            (base + 499_999_999, base),
+        ] {
+            assert_eq!(
+                nanoseconds(second.round(SensAppDateTime::from_unix_nanoseconds_i64(jittered))),
+                expected
+            );
+        }
+
+        // Before the unix epoch
+        assert_eq!(
+            nanoseconds(second.round(SensAppDateTime::from_unix_nanoseconds_i64(-1_200_000_000))),
+            -1_000_000_000
+        );
+
+        let hundred_milliseconds = TimestampRounding::parse("100ms").unwrap();
+        assert_eq!(
+            nanoseconds(
+                hundred_milliseconds.round(SensAppDateTime::from_unix_nanoseconds_i64(
+                    base + 1_234_567_890
+                ))
+            ),
+            base + 1_200_000_000
+        );
+    }
+}
@@ -52,6 +52,78 @@ impl TypedSamples {
         }
     }
 
+    /// Returns the datetime of the first sample, if any.
+    pub fn first_datetime(&self) -> Option<SensAppDateTime> {
+        match self {
+            TypedSamples::Integer(vec) => vec.first().map(|sample| sample.datetime),
+            TypedSamples::Numeric(vec) => vec.first().map(|sample| sample.datetime),
+            TypedSamples::Float(vec) => vec.first().map(|sample| sample.datetime),
+            TypedSamples::String(vec) => vec.first().map(|sample| sample.datetime),
+            TypedSamples::Boolean(vec) => vec.first().map(|sample| sample.datetime),
+            TypedSamples::Location(vec) => vec.first().map(|sample| sample.datetime),
+            TypedSamples::Blob(vec) => vec.first().map(|sample| sample.datetime),
+            TypedSamples::Json(vec) => vec.first().map(|sample| sample.datetime),
+        }
+    }
+
+    /// Returns the datetime of the last sample, if any.
+    pub fn last_datetime(&self) -> Option<SensAppDateTime> {
+        match self {
+            TypedSamples::Integer(vec) => vec.last().map(|sample| sample.datetime),
+            TypedSamples::Numeric(vec) => vec.last().map(|sample| sample.datetime),
+            TypedSamples::Float(vec) => vec.last().map(|sample| sample.datetime),
+            TypedSamples::String(vec) => vec.last().map(|sample| sample.datetime),
+            TypedSamples::Boolean(vec) => vec.last().map(|sample| sample.datetime),
+            TypedSamples::Location(vec) => vec.last().map(|sample| sample.datetime),
+            TypedSamples::Blob(vec) => vec.last().map(|sample| sample.datetime),
+            TypedSamples::Json(vec) => vec.last().map(|sample| sample.datetime),
+        }
+    }
+
+    /// Calls the function on the datetime of every sample.
+    pub fn for_each_datetime_mut(&mut self, mut f: impl FnMut(&mut SensAppDateTime)) {
+        match self {
+            TypedSamples::Integer(vec) => vec.iter_mut().for_each(|s| f(&mut s.datetime)),
+            TypedSamples::Numeric(vec) => vec.iter_mut().for_each(|s| f(&mut s.datetime)),
+            TypedSamples::Float(vec) => vec.iter_mut().for_each(|s| f(&mut s.datetime)),
+            TypedSamples::String(vec) => vec.iter_mut().for_each(|s| f(&mut s.datetime)),
+            TypedSamples::Boolean(vec) => vec.iter_mut().for_each(|s| f(&mut s.datetime)),
+            TypedSamples::Location(vec) => vec.iter_mut().for_each(|s| f(&mut s.datetime)),
+            TypedSamples::Blob(vec) => vec.iter_mut().for_each(|s| f(&mut s.datetime)),
+            TypedSamples::Json(vec) => vec.iter_mut().for_each(|s| f(&mut s.datetime)),
+        }
+    }
+
+    /// Keeps the samples whose datetime satisfies the predicate.
+    pub fn retain_by_datetime(&mut self, mut f: impl FnMut(&SensAppDateTime) -> bool) {
+        match self {
+            TypedSamples::Integer(vec) => vec.retain(|s| f(&s.datetime)),
+            TypedSamples::Numeric(vec) => vec.retain(|s| f(&s.datetime)),
+            TypedSamples::Float(vec) => vec.retain(|s| f(&s.datetime)),
+            TypedSamples::String(vec) => vec.retain(|s| f(&s.datetime)),
+            TypedSamples::Boolean(vec) => vec.retain(|s| f(&s.datetime)),
+            TypedSamples::Location(vec) => vec.retain(|s| f(&s.datetime)),
+            TypedSamples::Blob(vec) => vec.retain(|s| f(&s.datetime)),
+            TypedSamples::Json(vec) => vec.retain(|s| f(&s.datetime)),
+        }
+    }
+
+    /// Sorts the samples by datetime.
+    ///
+    /// The sort is stable, so samples with the same datetime keep their order.
+    pub fn sort_by_datetime(&mut self) {
+        match self {
+            TypedSamples::Integer(vec) => vec.sort_by_key(|s| s.datetime),
+            TypedSamples::Numeric(vec) => vec.sort_by_key(|s| s.datetime),
+            TypedSamples::Float(vec) => vec.sort_by_key(|s| s.datetime),
+            TypedSamples::String(vec) => vec.sort_by_key(|s| s.datetime),
+            TypedSamples::Boolean(vec) => vec.sort_by_key(|s| s.datetime),
+            TypedSamples::Location(vec) => vec.sort_by_key(|s| s.datetime),
+            TypedSamples::Blob(vec) => vec.sort_by_key(|s| s.datetime),
+            TypedSamples::Json(vec) => vec.sort_by_key(|s| s.datetime),
+        }
+    }
+
     pub fn clone_empty(&self) -> Self {
         match self {
             TypedSamples::Integer(_) => TypedSamples::Integer(smallvec![]),
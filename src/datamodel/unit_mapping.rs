@@ -0,0 +1,96 @@
+use super::unit::Unit;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use std::sync::{Arc, OnceLock};
+
+static UNIT_MAPPING: OnceLock<Arc<UnitMapping>> = OnceLock::new();
+
+/// Units of the sensors by name pattern, for the formats
+/// without units like InfluxDB and Graphite.
+///
+/// The mapping is a comma separated list of `pattern => unit` rules,
+/// for example `*_celsius => °C, *_percent => %`. In the patterns,
+/// `*` matches anything and `?` matches a character. The first
+/// matching rule wins.
+#[derive(Debug, Default)]
+pub struct UnitMapping {
+    rules: Vec<(Regex, String)>,
+}
+
+impl UnitMapping {
+    pub fn parse(mapping: &str) -> Result<Self> {
+        let rules = mapping
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                let (pattern, unit) = rule
+                    .split_once("=>")
+                    .ok_or_else(|| anyhow!("Invalid unit mapping rule: {:?}", rule))?;
+                let (pattern, unit) = (pattern.trim(), unit.trim());
+                if pattern.is_empty() || unit.is_empty() {
+                    return Err(anyhow!("Invalid unit mapping rule: {:?}", rule));
+                }
+                Ok((glob_to_regex(pattern)?, unit.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// The mapping of `SENSAPP_UNIT_MAPPING`, parsed once.
+    pub fn shared() -> Result<Arc<Self>> {
+        if let Some(mapping) = UNIT_MAPPING.get() {
+            return Ok(mapping.clone());
+        }
+        let config = crate::config::get()?;
+        let mapping = match &config.unit_mapping {
+            Some(mapping) => Self::parse(mapping)?,
+            None => Self::default(),
+        };
+        Ok(UNIT_MAPPING.get_or_init(|| Arc::new(mapping)).clone())
+    }
+
+    /// The unit of the first rule matching the sensor name.
+    pub fn unit_for(&self, name: &str) -> Option<Unit> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(name))
+            .map(|(_, unit)| Unit::new(unit.clone(), None))
+    }
+}
+
+fn glob_to_regex(pattern: &str) -> Result<Regex> {
+    let mut regex = String::with_capacity(pattern.len() + 8);
+    regex.push('^');
+    for character in pattern.chars() {
+        match character {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(&character.to_string())),
+        }
+    }
+    regex.push('$');
+    Ok(Regex::new(&regex)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_mapping() {
+        let mapping =
+            UnitMapping::parse("*_celsius => °C, cpu.load? => %,\n*.bytes => By").unwrap();
+        assert_eq!(mapping.unit_for("room_celsius").unwrap().name, "°C");
+        assert_eq!(mapping.unit_for("cpu.load1").unwrap().name, "%");
+        assert_eq!(mapping.unit_for("disk.bytes").unwrap().name, "By");
+        // Anchored, and the dots aren't wildcards
+        assert!(mapping.unit_for("room_celsius_max").is_none());
+        assert!(mapping.unit_for("cpu_load1").is_none());
+        assert!(mapping.unit_for("disk_bytes").is_none());
+
+        assert!(UnitMapping::parse("").unwrap().unit_for("a").is_none());
+        assert!(UnitMapping::parse("*_celsius").is_err());
+        assert!(UnitMapping::parse("=> °C").is_err());
+    }
+}
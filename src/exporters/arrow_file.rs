@@ -0,0 +1,192 @@
+use super::{label_column_values, labels_to_json};
+use crate::datamodel::{arrow_converter::ArrowConverter, Sensor, SensorData};
+use anyhow::Result;
+use arrow::array::{ArrayRef, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const SENSOR_UUID_METADATA: &str = "sensapp.sensor.uuid";
+pub const SENSOR_NAME_METADATA: &str = "sensapp.sensor.name";
+pub const SENSOR_TYPE_METADATA: &str = "sensapp.sensor.type";
+pub const SENSOR_UNIT_METADATA: &str = "sensapp.sensor.unit";
+/// The labels, as a JSON object.
+pub const SENSOR_LABELS_METADATA: &str = "sensapp.sensor.labels";
+
+/// The key/value metadata of the schema, describing the sensor.
+fn sensor_metadata(sensor: &Sensor) -> HashMap<String, String> {
+    let labels = labels_to_json(sensor);
+    let mut metadata = HashMap::from([
+        (SENSOR_UUID_METADATA.to_string(), sensor.uuid.to_string()),
+        (SENSOR_NAME_METADATA.to_string(), sensor.name.clone()),
+        (
+            SENSOR_TYPE_METADATA.to_string(),
+            sensor.sensor_type.to_string(),
+        ),
+        (
+            SENSOR_LABELS_METADATA.to_string(),
+            serde_json::Value::Object(labels).to_string(),
+        ),
+    ]);
+    if let Some(unit) = &sensor.unit {
+        metadata.insert(SENSOR_UNIT_METADATA.to_string(), unit.name.clone());
+    }
+    metadata
+}
+
+/// Adds the sensor to the metadata of the schema, and its unit
+/// to the metadata of the value fields.
+fn with_sensor_metadata(record_batch: RecordBatch, sensor: &Sensor) -> Result<RecordBatch> {
+    let schema = record_batch.schema();
+    let fields = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match &sensor.unit {
+            // The first field is the datetime.
+            Some(unit) if index > 0 => field.as_ref().clone().with_metadata(HashMap::from([(
+                SENSOR_UNIT_METADATA.to_string(),
+                unit.name.clone(),
+            )])),
+            _ => field.as_ref().clone(),
+        })
+        .collect::<Vec<Field>>();
+    let schema = Schema::new_with_metadata(fields, sensor_metadata(sensor));
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        record_batch.columns().to_vec(),
+    )?)
+}
+
+/// Appends a string column per label column, repeating the label value.
+fn with_label_columns(
+    record_batch: RecordBatch,
+    sensor: &Sensor,
+    label_columns: &[String],
+) -> Result<RecordBatch> {
+    let schema = record_batch.schema();
+    let mut fields = schema
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .collect::<Vec<_>>();
+    let mut columns = record_batch.columns().to_vec();
+    for (name, value) in label_columns
+        .iter()
+        .zip(label_column_values(sensor, label_columns))
+    {
+        fields.push(Field::new(name, DataType::Utf8, false));
+        columns.push(Arc::new(StringArray::from(vec![value; record_batch.num_rows()])) as ArrayRef);
+    }
+    let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// Exports the samples as an Arrow IPC file, followed by the label columns.
+///
+/// The sensor is described in the schema metadata,
+/// unless disabled in the configuration.
+pub fn to_arrow_file(sensor_data: &SensorData, label_columns: &[String]) -> Result<Vec<u8>> {
+    let schema_metadata = crate::config::get()
+        .map(|config| config.arrow_schema_metadata)
+        .unwrap_or(true);
+    to_arrow_file_with_options(sensor_data, schema_metadata, label_columns)
+}
+
+pub fn to_arrow_file_with_options(
+    sensor_data: &SensorData,
+    schema_metadata: bool,
+    label_columns: &[String],
+) -> Result<Vec<u8>> {
+    let mut record_batch = ArrowConverter::typed_samples_to_record_batch(&sensor_data.samples)?;
+    if schema_metadata {
+        record_batch = with_sensor_metadata(record_batch, &sensor_data.sensor)?;
+    }
+    if !label_columns.is_empty() {
+        record_batch = with_label_columns(record_batch, &sensor_data.sensor, label_columns)?;
+    }
+
+    let mut buffer = Vec::new();
+    {
+        let mut writer = FileWriter::try_new(&mut buffer, &record_batch.schema())?;
+        writer.write(&record_batch)?;
+        writer.finish()?;
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{
+        sensapp_datetime::SensAppDateTimeExt, unit::Unit, SensAppDateTime, SensorType, TypedSamples,
+    };
+    use arrow::ipc::reader::FileReader;
+    use smallvec::smallvec;
+    use std::io::Cursor;
+    use uuid::Uuid;
+
+    fn sensor_data(unit: Option<Unit>) -> SensorData {
+        SensorData::new(
+            Sensor::new(
+                Uuid::nil(),
+                "temperature".to_string(),
+                SensorType::Float,
+                unit,
+                Some(smallvec![
+                    ("room".to_string(), "kitchen".to_string()),
+                    ("floor".to_string(), "1".to_string())
+                ]),
+            ),
+            TypedSamples::one_float(21.5, SensAppDateTime::from_unix_seconds_i64(1_700_000_000)),
+        )
+    }
+
+    fn read_schema(body: Vec<u8>) -> arrow::datatypes::SchemaRef {
+        let reader = FileReader::try_new(Cursor::new(body), None).unwrap();
+        let schema = reader.schema();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 1);
+        // Only the datetime and the value, the sensor isn't repeated
+        assert_eq!(batches[0].num_columns(), 2);
+        schema
+    }
+
+    #[test]
+    fn test_schema_metadata() {
+        let with_unit = sensor_data(Some(Unit::new("Cel".to_string(), None)));
+        let schema = read_schema(to_arrow_file_with_options(&with_unit, true, &[]).unwrap());
+
+        let metadata = schema.metadata();
+        assert_eq!(
+            metadata[SENSOR_UUID_METADATA],
+            "00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(metadata[SENSOR_NAME_METADATA], "temperature");
+        assert_eq!(metadata[SENSOR_TYPE_METADATA], "Float");
+        assert_eq!(metadata[SENSOR_UNIT_METADATA], "Cel");
+        let labels: serde_json::Value =
+            serde_json::from_str(&metadata[SENSOR_LABELS_METADATA]).unwrap();
+        assert_eq!(labels, serde_json::json!({"room": "kitchen", "floor": "1"}));
+
+        assert!(schema.field(0).metadata().is_empty());
+        assert_eq!(schema.field(1).metadata()[SENSOR_UNIT_METADATA], "Cel");
+
+        // Without unit
+        let schema =
+            read_schema(to_arrow_file_with_options(&sensor_data(None), true, &[]).unwrap());
+        assert!(!schema.metadata().contains_key(SENSOR_UNIT_METADATA));
+        assert!(schema.field(1).metadata().is_empty());
+    }
+
+    #[test]
+    fn test_without_schema_metadata() {
+        let sensor_data = sensor_data(Some(Unit::new("Cel".to_string(), None)));
+        let schema = read_schema(to_arrow_file_with_options(&sensor_data, false, &[]).unwrap());
+        assert!(schema.metadata().is_empty());
+        assert!(schema.field(1).metadata().is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+use super::json::typed_samples_to_json_values;
+use super::label_column_values;
+use crate::datamodel::SensorData;
+use anyhow::Result;
+use serde_json::Value;
+use std::io::Write;
+
+/// Quotes a CSV field when it contains a separator, a quote, or a new line.
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Exports the samples as CSV, with a `datetime,value` header,
+/// followed by the label columns.
+pub fn to_csv(sensor_data: &SensorData, label_columns: &[String]) -> Result<Vec<u8>> {
+    to_csv_with_headers(sensor_data, label_columns, "datetime", "value")
+}
+
+/// Exports the samples as CSV, with custom names for the datetime and value columns.
+pub fn to_csv_with_headers(
+    sensor_data: &SensorData,
+    label_columns: &[String],
+    datetime_header: &str,
+    value_header: &str,
+) -> Result<Vec<u8>> {
+    let mut header = format!(
+        "{},{}",
+        escape_csv_field(datetime_header),
+        escape_csv_field(value_header)
+    );
+    let mut labels = String::new();
+    for (name, value) in label_columns
+        .iter()
+        .zip(label_column_values(&sensor_data.sensor, label_columns))
+    {
+        header.push(',');
+        header.push_str(&escape_csv_field(name));
+        labels.push(',');
+        labels.push_str(&escape_csv_field(value));
+    }
+
+    let mut buffer = Vec::new();
+    writeln!(buffer, "{}", header)?;
+    for (datetime, value) in typed_samples_to_json_values(&sensor_data.samples) {
+        let value = match value {
+            Value::String(value) => value,
+            Value::Null => String::new(),
+            value => value.to_string(),
+        };
+        writeln!(
+            buffer,
+            "{},{}{}",
+            datetime.to_rfc3339(),
+            escape_csv_field(&value),
+            labels
+        )?;
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{
+        sensapp_datetime::SensAppDateTimeExt, SensAppDateTime, Sensor, SensorType, TypedSamples,
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn test_custom_headers() {
+        let sensor_data = SensorData::new(
+            Sensor::new(
+                Uuid::nil(),
+                "temperature".to_string(),
+                SensorType::Float,
+                None,
+                None,
+            ),
+            TypedSamples::one_float(21.5, SensAppDateTime::from_unix_seconds_i64(1_700_000_000)),
+        );
+        let csv = String::from_utf8(
+            to_csv_with_headers(&sensor_data, &[], "ts", "reading, Cel").unwrap(),
+        )
+        .unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "ts,\"reading, Cel\"");
+        assert!(lines[1].ends_with(",21.5"));
+    }
+
+    #[test]
+    fn test_escape_csv_field() {
+        assert_eq!(escape_csv_field("abc"), "abc");
+        assert_eq!(escape_csv_field("a,b"), "\"a,b\"");
+        assert_eq!(escape_csv_field("a\"b"), "\"a\"\"b\"");
+    }
+}
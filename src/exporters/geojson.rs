@@ -0,0 +1,289 @@
+use crate::datamodel::{Sample, SensorData, TypedSamples};
+use anyhow::{bail, Result};
+use geo::{Coord, LineString};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::str::FromStr;
+
+/// The content type of the GeoJSON documents.
+pub const GEOJSON_CONTENT_TYPE: &str = "application/geo+json";
+
+/// How the samples of a location sensor are exported as GeoJSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GeoJsonMode {
+    /// A `Point` feature per sample, with its timestamp in the properties.
+    #[default]
+    Points,
+    /// A single trajectory feature, see [`to_geojson_linestring`].
+    LineString,
+}
+
+impl GeoJsonMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GeoJsonMode::Points => "points",
+            GeoJsonMode::LineString => "linestring",
+        }
+    }
+}
+
+impl FromStr for GeoJsonMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "points" => Ok(GeoJsonMode::Points),
+            "linestring" => Ok(GeoJsonMode::LineString),
+            _ => bail!("Unknown GeoJSON mode: {}, use points or linestring", mode),
+        }
+    }
+}
+
+fn location_samples(sensor_data: &SensorData) -> Result<&[Sample<geo::Point>]> {
+    match &sensor_data.samples {
+        TypedSamples::Location(samples) => Ok(samples),
+        _ => bail!(
+            "Only location sensors can be exported as GeoJSON, {} is a {} sensor",
+            sensor_data.sensor.name,
+            sensor_data.sensor.sensor_type.to_string()
+        ),
+    }
+}
+
+/// The features of the samples of a location sensor, one per sample
+/// or a single trajectory.
+fn to_features(sensor_data: &SensorData, mode: GeoJsonMode) -> Result<Vec<Value>> {
+    if mode == GeoJsonMode::LineString {
+        return Ok(vec![linestring_feature(sensor_data)?]);
+    }
+    let sensor = &sensor_data.sensor;
+    Ok(location_samples(sensor_data)?
+        .iter()
+        .map(|sample| {
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [sample.value.x(), sample.value.y()],
+                },
+                "properties": {
+                    "uuid": sensor.uuid.to_string(),
+                    "name": sensor.name,
+                    "timestamp": sample.datetime.to_rfc3339(),
+                },
+            })
+        })
+        .collect())
+}
+
+/// Exports the samples of a location sensor as a GeoJSON document,
+/// a `FeatureCollection` of points or a single trajectory `Feature`.
+pub fn to_geojson(sensor_data: &SensorData, mode: GeoJsonMode) -> Result<Vec<u8>> {
+    let mut features = to_features(sensor_data, mode)?;
+    let document = match mode {
+        GeoJsonMode::Points => json!({
+            "type": "FeatureCollection",
+            "features": features,
+        }),
+        GeoJsonMode::LineString => features.remove(0),
+    };
+    Ok(serde_json::to_vec(&document)?)
+}
+
+/// Exports the samples of a location sensor as line-delimited GeoJSON,
+/// one feature per line.
+pub fn to_geojsonl(sensor_data: &SensorData, mode: GeoJsonMode) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    for feature in to_features(sensor_data, mode)? {
+        serde_json::to_writer(&mut buffer, &feature)?;
+        buffer.write_all(b"\n")?;
+    }
+    Ok(buffer)
+}
+
+/// Converts the samples of a location sensor to a trajectory,
+/// a GeoJSON `Feature` with a `LineString` geometry.
+///
+/// The timestamps of the points are in the `timestamps` property,
+/// in the order of the coordinates. As a `LineString` needs two points,
+/// a single sample is a `Point`, and no samples is a `null` geometry.
+pub fn to_geojson_linestring(sensor_data: &SensorData) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&linestring_feature(sensor_data)?)?)
+}
+
+fn linestring_feature(sensor_data: &SensorData) -> Result<Value> {
+    let samples = location_samples(sensor_data)?;
+
+    let line_string = samples
+        .iter()
+        .map(|sample| Coord::from(sample.value))
+        .collect::<LineString>();
+    let geometry = match line_string.0.as_slice() {
+        [] => Value::Null,
+        [point] => json!({
+            "type": "Point",
+            "coordinates": [point.x, point.y],
+        }),
+        _ => json!({
+            "type": "LineString",
+            "coordinates": line_string
+                .coords()
+                .map(|coord| [coord.x, coord.y])
+                .collect::<Vec<_>>(),
+        }),
+    };
+    let timestamps = samples
+        .iter()
+        .map(|sample| sample.datetime.to_rfc3339())
+        .collect::<Vec<_>>();
+
+    let sensor = &sensor_data.sensor;
+    Ok(json!({
+        "type": "Feature",
+        "id": sensor.uuid.to_string(),
+        "geometry": geometry,
+        "properties": {
+            "uuid": sensor.uuid.to_string(),
+            "name": sensor.name,
+            "timestamps": timestamps,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{
+        sensapp_datetime::SensAppDateTimeExt, Sample, SensAppDateTime, Sensor, SensorType,
+    };
+    use uuid::Uuid;
+
+    fn sensor_data(points: &[(f64, f64)]) -> SensorData {
+        let sensor = Sensor::new(
+            Uuid::nil(),
+            "boat".to_string(),
+            SensorType::Location,
+            None,
+            None,
+        );
+        let samples = TypedSamples::Location(
+            points
+                .iter()
+                .enumerate()
+                .map(|(i, (longitude, latitude))| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i as i64),
+                    value: geo::Point::new(*longitude, *latitude),
+                })
+                .collect(),
+        );
+        SensorData::new(sensor, samples)
+    }
+
+    #[test]
+    fn test_to_geojson_linestring() {
+        let geojson =
+            to_geojson_linestring(&sensor_data(&[(10.39, 63.43), (10.4, 63.44)])).unwrap();
+        let geojson: Value = serde_json::from_slice(&geojson).unwrap();
+        assert_eq!(geojson["type"], "Feature");
+        assert_eq!(geojson["geometry"]["type"], "LineString");
+        assert_eq!(
+            geojson["geometry"]["coordinates"],
+            json!([[10.39, 63.43], [10.4, 63.44]])
+        );
+        assert_eq!(
+            geojson["properties"]["timestamps"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let geojson = to_geojson_linestring(&sensor_data(&[(10.39, 63.43)])).unwrap();
+        let geojson: Value = serde_json::from_slice(&geojson).unwrap();
+        assert_eq!(geojson["geometry"]["type"], "Point");
+
+        let geojson = to_geojson_linestring(&sensor_data(&[])).unwrap();
+        let geojson: Value = serde_json::from_slice(&geojson).unwrap();
+        assert!(geojson["geometry"].is_null());
+
+        let not_location = SensorData::new(
+            Sensor::new(
+                Uuid::nil(),
+                "temperature".to_string(),
+                SensorType::Float,
+                None,
+                None,
+            ),
+            TypedSamples::Float(Default::default()),
+        );
+        assert!(to_geojson_linestring(&not_location).is_err());
+    }
+
+    #[test]
+    fn test_to_geojson() {
+        let points = [(10.39, 63.43), (10.4, 63.44)];
+        let geojson = to_geojson(&sensor_data(&points), GeoJsonMode::Points).unwrap();
+        let geojson: Value = serde_json::from_slice(&geojson).unwrap();
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(features[1]["type"], "Feature");
+        assert_eq!(features[1]["geometry"]["type"], "Point");
+        assert_eq!(features[1]["geometry"]["coordinates"], json!([10.4, 63.44]));
+        assert_eq!(features[1]["properties"]["name"], "boat");
+        assert_eq!(
+            features[1]["properties"]["timestamp"],
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_001).to_rfc3339()
+        );
+
+        let geojson = to_geojson(&sensor_data(&points), GeoJsonMode::LineString).unwrap();
+        assert_eq!(
+            geojson,
+            to_geojson_linestring(&sensor_data(&points)).unwrap()
+        );
+
+        let geojson = to_geojson(&sensor_data(&[]), GeoJsonMode::Points).unwrap();
+        let geojson: Value = serde_json::from_slice(&geojson).unwrap();
+        assert_eq!(geojson["features"], json!([]));
+    }
+
+    #[test]
+    fn test_to_geojsonl() {
+        let points = [(10.39, 63.43), (10.4, 63.44), (10.41, 63.45)];
+        let lines = |mode| {
+            String::from_utf8(to_geojsonl(&sensor_data(&points), mode).unwrap())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<Value>(line).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let features = lines(GeoJsonMode::Points);
+        assert_eq!(features.len(), 3);
+        assert!(features
+            .iter()
+            .all(|feature| feature["geometry"]["type"] == "Point"));
+        assert_eq!(
+            features[2]["geometry"]["coordinates"],
+            json!([10.41, 63.45])
+        );
+
+        let features = lines(GeoJsonMode::LineString);
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0]["geometry"]["type"], "LineString");
+        assert_eq!(
+            features[0]["geometry"]["coordinates"]
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_geojson_mode() {
+        for mode in [GeoJsonMode::Points, GeoJsonMode::LineString] {
+            assert_eq!(GeoJsonMode::from_str(mode.name()).unwrap(), mode);
+        }
+        assert!(GeoJsonMode::from_str("polygon").is_err());
+    }
+}
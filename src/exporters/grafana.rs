@@ -0,0 +1,107 @@
+use super::json::typed_samples_to_json_values;
+use crate::datamodel::{SensorData, TypedSamples};
+use anyhow::Result;
+use rust_decimal::prelude::ToPrimitive;
+use serde_json::{json, Value};
+
+/// Converts the samples of a sensor to a target of the Grafana JSON datasources,
+/// `{"target": name, "datapoints": [[value, timestamp_ms], ...]}`.
+///
+/// Grafana plots numbers, so the numeric values are converted to floats
+/// and the booleans to 0 and 1. The other values are kept as in JSON.
+pub fn to_grafana_target(sensor_data: &SensorData) -> Value {
+    let datapoints = match &sensor_data.samples {
+        TypedSamples::Numeric(samples) => samples
+            .iter()
+            .map(|sample| {
+                json!([
+                    sample.value.to_f64(),
+                    sample.datetime.to_unix_milliseconds().floor() as i64
+                ])
+            })
+            .collect::<Vec<_>>(),
+        TypedSamples::Boolean(samples) => samples
+            .iter()
+            .map(|sample| {
+                json!([
+                    sample.value as u8,
+                    sample.datetime.to_unix_milliseconds().floor() as i64
+                ])
+            })
+            .collect(),
+        samples => typed_samples_to_json_values(samples)
+            .into_iter()
+            .map(|(datetime, value)| json!([value, datetime.to_unix_milliseconds().floor() as i64]))
+            .collect(),
+    };
+    json!({
+        "target": sensor_data.sensor.name,
+        "datapoints": datapoints,
+    })
+}
+
+/// Exports the samples of several sensors, one target per sensor.
+pub fn to_grafana(sensors_data: &[SensorData]) -> Result<Vec<u8>> {
+    let targets = sensors_data
+        .iter()
+        .map(to_grafana_target)
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_vec(&targets)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{
+        sensapp_datetime::SensAppDateTimeExt, Sample, SensAppDateTime, Sensor, SensorType,
+    };
+    use smallvec::smallvec;
+    use uuid::Uuid;
+
+    fn sensor_data(name: &str, sensor_type: SensorType, samples: TypedSamples) -> SensorData {
+        let sensor = Sensor::new(Uuid::new_v4(), name.to_string(), sensor_type, None, None);
+        SensorData::new(sensor, samples)
+    }
+
+    #[test]
+    fn test_to_grafana() {
+        let temperature = sensor_data(
+            "temperature",
+            SensorType::Float,
+            TypedSamples::Float(smallvec![
+                Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                    value: 21.5,
+                },
+                Sample {
+                    datetime: SensAppDateTime::from_unix_milliseconds_i64(1_700_000_060_250),
+                    value: 22.0,
+                },
+            ]),
+        );
+        let door = sensor_data(
+            "door_open",
+            SensorType::Boolean,
+            TypedSamples::Boolean(smallvec![Sample {
+                datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                value: true,
+            }]),
+        );
+
+        let body = to_grafana(&[temperature, door]).unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            json!([
+                {
+                    "target": "temperature",
+                    "datapoints": [[21.5, 1_700_000_000_000i64], [22.0, 1_700_000_060_250i64]],
+                },
+                {
+                    "target": "door_open",
+                    "datapoints": [[1, 1_700_000_000_000i64]],
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use super::labels_to_json;
+use crate::datamodel::{SensAppDateTime, Sensor, SensorData, TypedSamples};
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hifitime::Duration;
+use serde_json::{json, Value};
+
+/// Converts the samples to datetime and JSON value pairs.
+///
+/// Numeric values are kept as strings to not lose precision,
+/// and blobs are encoded in base64.
+pub fn typed_samples_to_json_values(samples: &TypedSamples) -> Vec<(SensAppDateTime, Value)> {
+    match samples {
+        TypedSamples::Integer(samples) => samples
+            .iter()
+            .map(|sample| (sample.datetime, Value::from(sample.value)))
+            .collect(),
+        TypedSamples::Numeric(samples) => samples
+            .iter()
+            .map(|sample| (sample.datetime, Value::from(sample.value.to_string())))
+            .collect(),
+        TypedSamples::Float(samples) => samples
+            .iter()
+            .map(|sample| (sample.datetime, Value::from(sample.value)))
+            .collect(),
+        TypedSamples::String(samples) => samples
+            .iter()
+            .map(|sample| (sample.datetime, Value::from(sample.value.clone())))
+            .collect(),
+        TypedSamples::Boolean(samples) => samples
+            .iter()
+            .map(|sample| (sample.datetime, Value::from(sample.value)))
+            .collect(),
+        TypedSamples::Location(samples) => samples
+            .iter()
+            .map(|sample| {
+                (
+                    sample.datetime,
+                    json!({
+                        "latitude": sample.value.y(),
+                        "longitude": sample.value.x(),
+                    }),
+                )
+            })
+            .collect(),
+        TypedSamples::Blob(samples) => samples
+            .iter()
+            .map(|sample| (sample.datetime, Value::from(STANDARD.encode(&sample.value))))
+            .collect(),
+        TypedSamples::Json(samples) => samples
+            .iter()
+            .map(|sample| (sample.datetime, sample.value.clone()))
+            .collect(),
+    }
+}
+
+/// Two samples further apart than this many nominal intervals have a gap between them.
+const GAP_TOLERANCE: f64 = 1.5;
+
+/// Inserts a null value at the first missing timestamp of each gap,
+/// enough for the charts to not draw a line over it.
+///
+/// Only the numeric sensors with a nominal interval are filled,
+/// the values of the others are returned as they are.
+pub fn fill_gaps(
+    sensor_data: &SensorData,
+    values: Vec<(SensAppDateTime, Value)>,
+) -> Vec<(SensAppDateTime, Value)> {
+    let Some(interval) = sensor_data.sensor.nominal_interval_seconds else {
+        return values;
+    };
+    let numeric = matches!(
+        sensor_data.samples,
+        TypedSamples::Integer(_) | TypedSamples::Numeric(_) | TypedSamples::Float(_)
+    );
+    if !numeric || interval <= 0.0 {
+        return values;
+    }
+    let interval = Duration::from_seconds(interval);
+    let mut filled = Vec::with_capacity(values.len());
+    let mut previous: Option<SensAppDateTime> = None;
+    for (datetime, value) in values {
+        if let Some(previous) = previous {
+            if datetime - previous > interval * GAP_TOLERANCE {
+                filled.push((previous + interval, Value::Null));
+            }
+        }
+        previous = Some(datetime);
+        filled.push((datetime, value));
+    }
+    filled
+}
+
+/// Converts one sample to the JSON object used by the JSON based exporters.
+pub fn sample_to_json_object(datetime: &SensAppDateTime, value: Value) -> Value {
+    json!({
+        "datetime": datetime.to_rfc3339(),
+        "value": value,
+    })
+}
+
+pub fn sensor_to_json(sensor: &Sensor) -> Value {
+    let labels = labels_to_json(sensor);
+    let mut value = json!({
+        "uuid": sensor.uuid.to_string(),
+        "name": sensor.name,
+        "type": sensor.sensor_type.to_string(),
+        "unit": sensor.unit.as_ref().map(|unit| unit.name.clone()),
+        "labels": labels,
+    });
+    // Only known for the sensors loaded from the SQL storages
+    if let Some(sensor_id) = sensor.sensor_id {
+        value["sensor_id"] = Value::from(sensor_id);
+    }
+    if let Some(created_at) = sensor.created_at {
+        value["created_at"] = Value::from(created_at.to_rfc3339());
+    }
+    if let Some(last_seen) = sensor.last_seen {
+        value["last_seen"] = Value::from(last_seen.to_rfc3339());
+    }
+    if let Some(nominal_interval_seconds) = sensor.nominal_interval_seconds {
+        value["nominal_interval_seconds"] = Value::from(nominal_interval_seconds);
+    }
+    if let Some(downsampling) = sensor.downsampling {
+        value["downsampling"] = json!({
+            "raw_retention_days": downsampling.raw_retention_days,
+            "rollup_interval_seconds": downsampling.rollup_interval_seconds,
+        });
+    }
+    value
+}
+
+pub fn sensor_data_to_json(sensor_data: &SensorData) -> Value {
+    samples_to_json(
+        sensor_data,
+        typed_samples_to_json_values(&sensor_data.samples),
+    )
+}
+
+/// Like `sensor_data_to_json`, with null values in the gaps.
+pub fn sensor_data_to_json_with_gaps(sensor_data: &SensorData) -> Value {
+    samples_to_json(
+        sensor_data,
+        fill_gaps(
+            sensor_data,
+            typed_samples_to_json_values(&sensor_data.samples),
+        ),
+    )
+}
+
+fn samples_to_json(sensor_data: &SensorData, values: Vec<(SensAppDateTime, Value)>) -> Value {
+    let samples = values
+        .into_iter()
+        .map(|(datetime, value)| sample_to_json_object(&datetime, value))
+        .collect::<Vec<_>>();
+    json!({
+        "sensor": sensor_to_json(&sensor_data.sensor),
+        "samples": samples,
+    })
+}
+
+/// Converts the samples to parallel `timestamps` and `values` arrays.
+///
+/// More compact than the objects per sample, and faster to parse.
+/// The values are the same as in the row layout.
+pub fn sensor_data_to_columnar_json(sensor_data: &SensorData) -> Value {
+    let (timestamps, values): (Vec<_>, Vec<_>) = typed_samples_to_json_values(&sensor_data.samples)
+        .into_iter()
+        .map(|(datetime, value)| (Value::from(datetime.to_rfc3339()), value))
+        .unzip();
+    json!({
+        "sensor": sensor_to_json(&sensor_data.sensor),
+        "timestamps": timestamps,
+        "values": values,
+    })
+}
+
+pub fn to_json(sensor_data: &SensorData) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&sensor_data_to_json(sensor_data))?)
+}
+
+pub fn to_columnar_json(sensor_data: &SensorData) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&sensor_data_to_columnar_json(
+        sensor_data,
+    ))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{sensapp_datetime::SensAppDateTimeExt, Sample, SensorType};
+    use smallvec::smallvec;
+    use uuid::Uuid;
+
+    fn sensor_data(sensor_type: SensorType, samples: TypedSamples) -> SensorData {
+        let sensor = Sensor::new(Uuid::nil(), "test".to_string(), sensor_type, None, None);
+        SensorData::new(sensor, samples)
+    }
+
+    #[test]
+    fn test_columnar_layout() {
+        let datetimes = [1_700_000_000, 1_700_000_060].map(SensAppDateTime::from_unix_seconds_i64);
+        let sensors_data = [
+            sensor_data(
+                SensorType::Integer,
+                TypedSamples::Integer(smallvec![
+                    Sample {
+                        datetime: datetimes[0],
+                        value: 1
+                    },
+                    Sample {
+                        datetime: datetimes[1],
+                        value: 2
+                    },
+                ]),
+            ),
+            sensor_data(
+                SensorType::Float,
+                TypedSamples::Float(smallvec![
+                    Sample {
+                        datetime: datetimes[0],
+                        value: 21.0
+                    },
+                    Sample {
+                        datetime: datetimes[1],
+                        value: 21.5
+                    },
+                ]),
+            ),
+            sensor_data(
+                SensorType::String,
+                TypedSamples::String(smallvec![
+                    Sample {
+                        datetime: datetimes[0],
+                        value: "on".to_string()
+                    },
+                    Sample {
+                        datetime: datetimes[1],
+                        value: "off".to_string()
+                    },
+                ]),
+            ),
+        ];
+
+        for sensor_data in &sensors_data {
+            let rows = sensor_data_to_json(sensor_data);
+            let columns = sensor_data_to_columnar_json(sensor_data);
+            assert_eq!(rows["sensor"], columns["sensor"]);
+            let rows = rows["samples"].as_array().unwrap();
+            assert_eq!(rows.len(), 2);
+            assert_eq!(columns["timestamps"].as_array().unwrap().len(), 2);
+            for (i, row) in rows.iter().enumerate() {
+                assert_eq!(row["datetime"], columns["timestamps"][i]);
+                assert_eq!(row["value"], columns["values"][i]);
+            }
+        }
+
+        // The integers stay integers, and the floats stay floats
+        let columns = sensor_data_to_columnar_json(&sensors_data[0]);
+        assert!(columns["values"][0].is_i64());
+        let columns = sensor_data_to_columnar_json(&sensors_data[1]);
+        assert!(columns["values"][0].is_f64());
+        let body = String::from_utf8(to_columnar_json(&sensors_data[1]).unwrap()).unwrap();
+        assert!(body.contains("\"values\":[21.0,21.5]"));
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        // Every 10 seconds, with 20 seconds missing after the third sample
+        let samples = TypedSamples::Float(
+            [0, 10, 20, 50, 60, 74]
+                .into_iter()
+                .map(|seconds| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + seconds),
+                    value: seconds as f64,
+                })
+                .collect(),
+        );
+        let mut floats = sensor_data(SensorType::Float, samples);
+        let values = |sensor_data: &SensorData| {
+            sensor_data_to_json_with_gaps(sensor_data)["samples"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|sample| sample["value"].clone())
+                .collect::<Vec<_>>()
+        };
+        // Nothing is known about the sampling
+        assert_eq!(values(&floats).len(), 6);
+
+        floats.sensor.nominal_interval_seconds = Some(10.0);
+        let json = sensor_data_to_json_with_gaps(&floats);
+        assert_eq!(json["sensor"]["nominal_interval_seconds"], 10.0);
+        assert_eq!(json["samples"][3]["value"], Value::Null);
+        assert_eq!(
+            json["samples"][3]["datetime"],
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_030).to_rfc3339()
+        );
+        // Some jitter isn't a gap
+        assert_eq!(
+            values(&floats),
+            vec![
+                json!(0.0),
+                json!(10.0),
+                json!(20.0),
+                Value::Null,
+                json!(50.0),
+                json!(60.0),
+                json!(74.0),
+            ]
+        );
+        // Without fill_gaps, the values are the samples
+        assert_eq!(
+            sensor_data_to_json(&floats)["samples"]
+                .as_array()
+                .unwrap()
+                .len(),
+            6
+        );
+
+        // The strings aren't evenly sampled measurements
+        let mut strings = sensor_data(
+            SensorType::String,
+            TypedSamples::String(smallvec![
+                Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(0),
+                    value: "on".to_string()
+                },
+                Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(100),
+                    value: "off".to_string()
+                },
+            ]),
+        );
+        strings.sensor.nominal_interval_seconds = Some(10.0);
+        assert_eq!(values(&strings).len(), 2);
+    }
+}
@@ -0,0 +1,22 @@
+use super::json::{sample_to_json_object, typed_samples_to_json_values};
+use super::label_column_values;
+use crate::datamodel::SensorData;
+use anyhow::Result;
+use serde_json::Value;
+use std::io::Write;
+
+/// Exports the samples as newline-delimited JSON, one sample per line,
+/// with the label columns as additional keys.
+pub fn to_jsonl(sensor_data: &SensorData, label_columns: &[String]) -> Result<Vec<u8>> {
+    let labels = label_column_values(&sensor_data.sensor, label_columns);
+    let mut buffer = Vec::new();
+    for (datetime, value) in typed_samples_to_json_values(&sensor_data.samples) {
+        let mut object = sample_to_json_object(&datetime, value);
+        for (name, value) in label_columns.iter().zip(&labels) {
+            object[name] = Value::from(*value);
+        }
+        serde_json::to_writer(&mut buffer, &object)?;
+        buffer.write_all(b"\n")?;
+    }
+    Ok(buffer)
+}
@@ -0,0 +1,503 @@
+use crate::datamodel::{Sensor, SensorData, SensorType};
+use anyhow::{bail, Result};
+
+pub mod arrow_file;
+pub mod csv;
+pub mod geojson;
+pub mod grafana;
+pub mod json;
+pub mod jsonl;
+pub mod senml;
+
+/// The names of the columns of the exports, that the label columns can't use.
+const RESERVED_COLUMNS: [&str; 4] = ["datetime", "value", "latitude", "longitude"];
+
+/// Parses the comma separated labels to promote to their own columns, like `env,region`.
+pub fn parse_label_columns(labels: &str) -> Result<Vec<String>> {
+    let mut label_columns: Vec<String> = Vec::new();
+    for label in labels
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+    {
+        if RESERVED_COLUMNS.contains(&label) {
+            bail!("The label {} conflicts with the {} column", label, label);
+        }
+        if label_columns.iter().any(|column| column == label) {
+            bail!("The label {} is repeated", label);
+        }
+        label_columns.push(label.to_string());
+    }
+    Ok(label_columns)
+}
+
+/// The media types of an `Accept` header, lowercased and by decreasing quality.
+///
+/// The media types with a quality of 0 aren't accepted, and are left out.
+pub fn accepted_media_types(accept: &str) -> Vec<String> {
+    let mut media_ranges = accept
+        .split(',')
+        .filter_map(|media_range| {
+            let mut parts = media_range.split(';');
+            let media_type = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((media_type, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect::<Vec<_>>();
+
+    // The sort is stable, so the order of the header is kept for equal qualities
+    media_ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    media_ranges
+        .into_iter()
+        .map(|(media_type, _)| media_type)
+        .collect()
+}
+
+/// The labels of a sensor sorted by key, then by value, so identical
+/// sensors are exported byte for byte identically.
+pub fn sorted_labels(sensor: &Sensor) -> Vec<(&str, &str)> {
+    let mut labels = sensor
+        .labels
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
+    labels.sort_unstable();
+    labels
+}
+
+/// The labels of a sensor as a JSON object, with the keys in order.
+pub fn labels_to_json(sensor: &Sensor) -> serde_json::Map<String, serde_json::Value> {
+    sorted_labels(sensor)
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), serde_json::Value::from(value)))
+        .collect()
+}
+
+/// The values of the label columns of a sensor, empty when it doesn't have the label.
+pub fn label_column_values<'a>(sensor: &'a Sensor, label_columns: &[String]) -> Vec<&'a str> {
+    label_columns
+        .iter()
+        .map(|name| {
+            sensor
+                .labels
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// The error returned when none of the media types of the `Accept` header can be exported.
+#[derive(Debug)]
+pub struct NotAcceptableError(pub String);
+
+impl std::fmt::Display for NotAcceptableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "None of the accepted media types can be exported: {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for NotAcceptableError {}
+
+/// The formats SensApp can export sensor data to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Jsonl,
+    SenML,
+    Arrow,
+    /// The Grafana JSON datasources format, `[{target, datapoints}]`.
+    Grafana,
+    /// The samples of a location sensor, as a GeoJSON `FeatureCollection` of points.
+    GeoJson,
+    /// The samples of a location sensor, as line-delimited GeoJSON features.
+    GeoJsonl,
+}
+
+impl ExportFormat {
+    /// All the formats, in the order of preference
+    /// when a client accepts several of them with the same quality.
+    pub const ALL: [ExportFormat; 8] = [
+        ExportFormat::Json,
+        ExportFormat::Csv,
+        ExportFormat::Jsonl,
+        ExportFormat::SenML,
+        ExportFormat::Arrow,
+        ExportFormat::Grafana,
+        ExportFormat::GeoJson,
+        ExportFormat::GeoJsonl,
+    ];
+
+    /// The name used in the `format=` query parameter.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::SenML => "senml",
+            ExportFormat::Arrow => "arrow",
+            ExportFormat::Grafana => "grafana",
+            ExportFormat::GeoJson => "geojson",
+            ExportFormat::GeoJsonl => "geojsonl",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json | ExportFormat::Grafana => "application/json",
+            ExportFormat::Csv => "text/csv",
+            // The line-delimited GeoJSON is newline-delimited JSON too
+            ExportFormat::Jsonl | ExportFormat::GeoJsonl => "application/x-ndjson",
+            ExportFormat::SenML => "application/senml+json",
+            ExportFormat::Arrow => "application/vnd.apache.arrow.file",
+            ExportFormat::GeoJson => geojson::GEOJSON_CONTENT_TYPE,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "ndjson" => Ok(ExportFormat::Jsonl),
+            name => match Self::ALL.iter().find(|format| format.name() == name) {
+                Some(format) => Ok(*format),
+                None => bail!("Unknown export format: {}", name),
+            },
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "*/*" | "application/*" => Some(ExportFormat::Json),
+            "text/*" => Some(ExportFormat::Csv),
+            media_type => Self::ALL
+                .iter()
+                .find(|format| format.content_type() == media_type)
+                .copied(),
+        }
+    }
+
+    /// Picks the preferred format from an `Accept` header.
+    ///
+    /// Returns `None` when none of the accepted media types can be exported.
+    pub fn from_accept_header(accept: &str) -> Option<Self> {
+        accepted_media_types(accept)
+            .iter()
+            .find_map(|media_type| Self::from_media_type(media_type))
+    }
+
+    /// Selects the export format of a request.
+    ///
+    /// The `format=` query parameter has the priority over the `Accept` header,
+    /// and JSON is the default when there is no `Accept` header. Fails with
+    /// a [`NotAcceptableError`] when none of the accepted media types can be exported.
+    pub fn negotiate(format: Option<&str>, accept: Option<&str>) -> Result<Self> {
+        if let Some(format) = format {
+            return Self::from_name(format);
+        }
+        match accept.map(str::trim).filter(|accept| !accept.is_empty()) {
+            None => Ok(ExportFormat::Json),
+            Some(accept) => Self::from_accept_header(accept)
+                .ok_or_else(|| NotAcceptableError(accept.to_string()).into()),
+        }
+    }
+
+    /// Whether the format can export the sensors of this type.
+    pub fn supports_sensor_type(&self, sensor_type: SensorType) -> bool {
+        match self {
+            ExportFormat::GeoJson | ExportFormat::GeoJsonl => sensor_type == SensorType::Location,
+            _ => true,
+        }
+    }
+
+    /// Whether the format can have label columns.
+    pub fn supports_label_columns(&self) -> bool {
+        matches!(
+            self,
+            ExportFormat::Csv | ExportFormat::Jsonl | ExportFormat::Arrow
+        )
+    }
+
+    /// Exports the samples, with the given labels promoted to their own columns.
+    pub fn export(&self, sensor_data: &SensorData, label_columns: &[String]) -> Result<Vec<u8>> {
+        if !label_columns.is_empty() && !self.supports_label_columns() {
+            bail!(
+                "The label columns aren't available for the {} format",
+                self.name()
+            );
+        }
+        match self {
+            ExportFormat::Json => json::to_json(sensor_data),
+            ExportFormat::Csv => csv::to_csv(sensor_data, label_columns),
+            ExportFormat::Jsonl => jsonl::to_jsonl(sensor_data, label_columns),
+            ExportFormat::SenML => senml::to_senml(sensor_data),
+            ExportFormat::Arrow => arrow_file::to_arrow_file(sensor_data, label_columns),
+            ExportFormat::Grafana => grafana::to_grafana(std::slice::from_ref(sensor_data)),
+            ExportFormat::GeoJson => geojson::to_geojson(sensor_data, Default::default()),
+            ExportFormat::GeoJsonl => geojson::to_geojsonl(sensor_data, Default::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{
+        sensapp_datetime::SensAppDateTimeExt, unit::Unit, Sample, SensAppDateTime, Sensor,
+        SensorType, TypedSamples,
+    };
+    use arrow::ipc::reader::FileReader;
+    use smallvec::smallvec;
+    use std::io::Cursor;
+    use uuid::Uuid;
+
+    fn sensor_data() -> SensorData {
+        let sensor = Sensor::new(
+            Uuid::nil(),
+            "temperature".to_string(),
+            SensorType::Float,
+            Some(Unit::new("Cel".to_string(), None)),
+            Some(smallvec![("room".to_string(), "kitchen".to_string())]),
+        );
+        let samples = TypedSamples::Float(smallvec![
+            Sample {
+                datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                value: 21.5,
+            },
+            Sample {
+                datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_060),
+                value: 22.0,
+            },
+        ]);
+        SensorData::new(sensor, samples)
+    }
+
+    fn export(accept: &str) -> (ExportFormat, Vec<u8>) {
+        let format = ExportFormat::negotiate(None, Some(accept)).unwrap();
+        let body = format.export(&sensor_data(), &[]).unwrap();
+        (format, body)
+    }
+
+    #[test]
+    fn test_accept_csv() {
+        let (format, body) = export("text/csv");
+        assert_eq!(format.content_type(), "text/csv");
+        let body = String::from_utf8(body).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "datetime,value");
+        assert!(lines[1].ends_with(",21.5"));
+    }
+
+    #[test]
+    fn test_accept_arrow() {
+        let (format, body) = export("application/vnd.apache.arrow.file");
+        assert_eq!(format.content_type(), "application/vnd.apache.arrow.file");
+        let reader = FileReader::try_new(Cursor::new(body), None).unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 2);
+        assert_eq!(batches[0].num_columns(), 2);
+    }
+
+    #[test]
+    fn test_accept_jsonl() {
+        let (format, body) = export("application/x-ndjson");
+        assert_eq!(format.content_type(), "application/x-ndjson");
+        let body = String::from_utf8(body).unwrap();
+        let lines = body
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["value"], 22.0);
+    }
+
+    #[test]
+    fn test_accept_senml() {
+        let (format, body) = export("application/senml+json");
+        assert_eq!(format.content_type(), "application/senml+json");
+        let records = sindit_senml::parse_json(std::str::from_utf8(&body).unwrap(), None).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "temperature");
+        assert_eq!(records[0].unit.as_deref(), Some("Cel"));
+        assert_eq!(records[1].time.timestamp(), 1_700_000_060);
+    }
+
+    #[test]
+    fn test_accept_json_and_default() {
+        for accept in ["application/json", "*/*", "image/png, */*;q=0.1", ""] {
+            let (format, body) = export(accept);
+            assert_eq!(format.content_type(), "application/json");
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["sensor"]["name"], "temperature");
+            assert_eq!(json["samples"].as_array().unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_accept_quality() {
+        assert_eq!(
+            ExportFormat::from_accept_header("application/json;q=0.5, text/csv"),
+            Some(ExportFormat::Csv)
+        );
+        assert_eq!(
+            ExportFormat::from_accept_header("text/csv;q=0, application/x-ndjson"),
+            Some(ExportFormat::Jsonl)
+        );
+        assert_eq!(ExportFormat::from_accept_header("image/png"), None);
+    }
+
+    #[test]
+    fn test_format_overrides_accept() {
+        assert_eq!(
+            ExportFormat::negotiate(Some("csv"), Some("application/json")).unwrap(),
+            ExportFormat::Csv
+        );
+        assert_eq!(
+            ExportFormat::negotiate(Some("ndjson"), None).unwrap(),
+            ExportFormat::Jsonl
+        );
+        assert!(ExportFormat::negotiate(Some("xml"), None).is_err());
+        assert_eq!(
+            ExportFormat::negotiate(Some("csv"), Some("image/png")).unwrap(),
+            ExportFormat::Csv
+        );
+    }
+
+    #[test]
+    fn test_accept_not_acceptable() {
+        for accept in ["image/png", "application/xml, text/html;q=0.5", "*/*;q=0"] {
+            let error = ExportFormat::negotiate(None, Some(accept)).unwrap_err();
+            assert!(error.is::<NotAcceptableError>());
+        }
+    }
+
+    #[test]
+    fn test_label_columns() {
+        let label_columns = parse_label_columns("room, env,").unwrap();
+        assert_eq!(label_columns, vec!["room", "env"]);
+        assert!(parse_label_columns("value").is_err());
+        assert!(parse_label_columns("env,env").is_err());
+
+        let body = ExportFormat::Csv
+            .export(&sensor_data(), &label_columns)
+            .unwrap();
+        let body = String::from_utf8(body).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "datetime,value,room,env");
+        assert!(lines[1].ends_with(",21.5,kitchen,"), "{}", lines[1]);
+
+        let body = ExportFormat::Jsonl
+            .export(&sensor_data(), &label_columns)
+            .unwrap();
+        let line: serde_json::Value =
+            serde_json::from_str(String::from_utf8(body).unwrap().lines().next().unwrap()).unwrap();
+        assert_eq!(line["room"], "kitchen");
+        assert_eq!(line["env"], "");
+
+        let body = ExportFormat::Arrow
+            .export(&sensor_data(), &label_columns)
+            .unwrap();
+        let reader = FileReader::try_new(Cursor::new(body), None).unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches[0].num_columns(), 4);
+        let room = batches[0]
+            .column_by_name("room")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<arrow::array::StringArray>()
+            .unwrap();
+        assert_eq!(room.value(0), "kitchen");
+        assert_eq!(room.value(1), "kitchen");
+
+        assert!(ExportFormat::Json
+            .export(&sensor_data(), &label_columns)
+            .is_err());
+    }
+
+    #[test]
+    fn test_sorted_labels() {
+        let sensor_data = |labels: [(&str, &str); 3]| {
+            let mut sensor_data = sensor_data();
+            // Like the sensors loaded from a storage, in the order of the query
+            sensor_data.sensor.labels = labels
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            sensor_data
+        };
+        let unsorted = sensor_data([("room", "kitchen"), ("floor", "1"), ("building", "a")]);
+        let reversed = sensor_data([("building", "a"), ("room", "kitchen"), ("floor", "1")]);
+
+        assert_eq!(
+            labels_to_json(&unsorted.sensor).keys().collect::<Vec<_>>(),
+            vec!["building", "floor", "room"]
+        );
+        let json = ExportFormat::Json.export(&unsorted, &[]).unwrap();
+        assert!(String::from_utf8(json.clone())
+            .unwrap()
+            .contains(r#""labels":{"building":"a","floor":"1","room":"kitchen"}"#));
+        assert_eq!(json, ExportFormat::Json.export(&reversed, &[]).unwrap());
+
+        let metadata = |sensor_data: &SensorData| {
+            let body = ExportFormat::Arrow.export(sensor_data, &[]).unwrap();
+            let reader = FileReader::try_new(Cursor::new(body), None).unwrap();
+            reader.schema().metadata()[arrow_file::SENSOR_LABELS_METADATA].clone()
+        };
+        assert_eq!(
+            metadata(&unsorted),
+            r#"{"building":"a","floor":"1","room":"kitchen"}"#
+        );
+        assert_eq!(metadata(&unsorted), metadata(&reversed));
+    }
+
+    #[test]
+    fn test_format_grafana() {
+        let format = ExportFormat::negotiate(Some("grafana"), None).unwrap();
+        assert_eq!(format, ExportFormat::Grafana);
+        assert_eq!(format.content_type(), "application/json");
+        // The JSON media type is still the plain JSON export
+        assert_eq!(
+            ExportFormat::from_accept_header("application/json"),
+            Some(ExportFormat::Json)
+        );
+        let json: serde_json::Value =
+            serde_json::from_slice(&format.export(&sensor_data(), &[]).unwrap()).unwrap();
+        assert_eq!(json[0]["target"], "temperature");
+        assert_eq!(json[0]["datapoints"][1][0], 22.0);
+        assert_eq!(json[0]["datapoints"][1][1], 1_700_000_060_000i64);
+    }
+
+    #[test]
+    fn test_format_geojson() {
+        assert_eq!(
+            ExportFormat::from_accept_header("application/geo+json"),
+            Some(ExportFormat::GeoJson)
+        );
+        // The newline-delimited JSON media type is still the JSONL export
+        assert_eq!(
+            ExportFormat::from_accept_header("application/x-ndjson"),
+            Some(ExportFormat::Jsonl)
+        );
+        let format = ExportFormat::negotiate(Some("geojsonl"), None).unwrap();
+        assert_eq!(format, ExportFormat::GeoJsonl);
+        assert!(format.supports_sensor_type(SensorType::Location));
+        assert!(!format.supports_sensor_type(SensorType::Float));
+        assert!(ExportFormat::Csv.supports_sensor_type(SensorType::Location));
+
+        let error = ExportFormat::GeoJson
+            .export(&sensor_data(), &[])
+            .unwrap_err();
+        assert!(error.to_string().contains("Only location sensors"));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::datamodel::{SensAppDateTime, SensorData, TypedSamples};
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rust_decimal::prelude::ToPrimitive;
+use serde_json::{json, Map, Value};
+
+fn senml_record(datetime: &SensAppDateTime, value_field: &str, value: Value) -> Map<String, Value> {
+    let mut record = Map::new();
+    record.insert("t".to_string(), Value::from(datetime.to_unix_seconds()));
+    record.insert(value_field.to_string(), value);
+    record
+}
+
+/// Exports the samples as a SenML JSON pack.
+///
+/// The sensor name is the base name of the pack. Locations are exported
+/// as two records per sample, named `latitude` and `longitude`.
+pub fn to_senml(sensor_data: &SensorData) -> Result<Vec<u8>> {
+    let mut records: Vec<Map<String, Value>> = match &sensor_data.samples {
+        TypedSamples::Integer(samples) => samples
+            .iter()
+            .map(|sample| senml_record(&sample.datetime, "v", Value::from(sample.value)))
+            .collect(),
+        TypedSamples::Numeric(samples) => samples
+            .iter()
+            .map(|sample| {
+                senml_record(
+                    &sample.datetime,
+                    "v",
+                    Value::from(sample.value.to_f64().unwrap_or(f64::NAN)),
+                )
+            })
+            .collect(),
+        TypedSamples::Float(samples) => samples
+            .iter()
+            .map(|sample| senml_record(&sample.datetime, "v", Value::from(sample.value)))
+            .collect(),
+        TypedSamples::String(samples) => samples
+            .iter()
+            .map(|sample| senml_record(&sample.datetime, "vs", Value::from(sample.value.clone())))
+            .collect(),
+        TypedSamples::Boolean(samples) => samples
+            .iter()
+            .map(|sample| senml_record(&sample.datetime, "vb", Value::from(sample.value)))
+            .collect(),
+        TypedSamples::Location(samples) => samples
+            .iter()
+            .flat_map(|sample| {
+                let mut latitude =
+                    senml_record(&sample.datetime, "v", Value::from(sample.value.y()));
+                latitude.insert("n".to_string(), Value::from("latitude"));
+                let mut longitude =
+                    senml_record(&sample.datetime, "v", Value::from(sample.value.x()));
+                longitude.insert("n".to_string(), Value::from("longitude"));
+                [latitude, longitude]
+            })
+            .collect(),
+        TypedSamples::Blob(samples) => samples
+            .iter()
+            .map(|sample| {
+                senml_record(
+                    &sample.datetime,
+                    "vd",
+                    Value::from(URL_SAFE_NO_PAD.encode(&sample.value)),
+                )
+            })
+            .collect(),
+        TypedSamples::Json(samples) => samples
+            .iter()
+            .map(|sample| {
+                senml_record(
+                    &sample.datetime,
+                    "vs",
+                    Value::from(sample.value.to_string()),
+                )
+            })
+            .collect(),
+    };
+
+    let sensor = &sensor_data.sensor;
+    let base_name = match sensor_data.samples {
+        TypedSamples::Location(_) => format!("{}:", sensor.name),
+        _ => sensor.name.clone(),
+    };
+
+    // The base fields are set on the first record
+    if records.is_empty() {
+        records.push(Map::new());
+    }
+    let first_record = &mut records[0];
+    first_record.insert("bn".to_string(), Value::from(base_name));
+    if let Some(unit) = &sensor.unit {
+        first_record.insert("bu".to_string(), Value::from(unit.name.clone()));
+    }
+
+    Ok(serde_json::to_vec(&json!(records))?)
+}
@@ -1,88 +1,358 @@
 use crate::{
     bus::{wait_for_all::WaitForAll, EventBus},
-    datamodel::{
-        batch::{Batch, SingleSensorBatch},
-        //batch_builder::BatchBuilder,
-        Sample,
-        SensAppDateTime,
-        Sensor,
-        TypedSamples,
-    },
+    datamodel::{batch_builder::BatchBuilder, SensAppDateTime, Sensor, SensorType, TypedSamples},
+    infer::parsing::{parse_iso8601_datetime, InferedValue},
 };
-use anyhow::Result;
-use csv_async::AsyncReader;
+use anyhow::{anyhow, bail, Context, Result};
+use csv_async::{AsyncReader, StringRecord};
 use futures::{io, StreamExt};
-use smallvec::smallvec;
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
 
+/// Format of the timestamp column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// Unix seconds for the numbers, ISO 8601 otherwise.
+    #[default]
+    Auto,
+    UnixSeconds,
+    UnixMilliseconds,
+    Iso8601,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "auto" => Ok(Self::Auto),
+            "unix" | "unix_s" => Ok(Self::UnixSeconds),
+            "unix_ms" => Ok(Self::UnixMilliseconds),
+            "iso8601" => Ok(Self::Iso8601),
+            _ => bail!(
+                "Unknown timestamp format: {}, expected auto, unix, unix_ms, or iso8601",
+                format
+            ),
+        }
+    }
+}
+
+impl TimestampFormat {
+    pub fn parse(&self, timestamp: &str) -> Result<SensAppDateTime> {
+        let timestamp = timestamp.trim();
+        let number = || {
+            timestamp
+                .parse::<f64>()
+                .with_context(|| format!("Invalid timestamp: {}", timestamp))
+        };
+        let iso8601 = || match parse_iso8601_datetime(timestamp) {
+            Ok((_, InferedValue::DateTime(datetime))) => Ok(datetime),
+            _ => bail!("Invalid ISO 8601 timestamp: {}", timestamp),
+        };
+        match self {
+            Self::Auto if timestamp.parse::<f64>().is_ok() => {
+                Ok(SensAppDateTime::from_unix_seconds(number()?))
+            }
+            Self::Auto | Self::Iso8601 => iso8601(),
+            Self::UnixSeconds => Ok(SensAppDateTime::from_unix_seconds(number()?)),
+            Self::UnixMilliseconds => Ok(SensAppDateTime::from_unix_milliseconds(number()?)),
+        }
+    }
+}
+
+/// Columns of the long format CSV, with one sample per row.
+#[derive(Debug, Clone)]
+pub struct CsvColumnMapping {
+    pub timestamp: String,
+    pub value: String,
+    /// Column of the sensor names. When the CSV doesn't have it,
+    /// all the samples belong to the default sensor.
+    pub name: String,
+    pub timestamp_format: TimestampFormat,
+}
+
+impl Default for CsvColumnMapping {
+    fn default() -> Self {
+        Self {
+            timestamp: "timestamp".to_string(),
+            value: "value".to_string(),
+            name: "name".to_string(),
+            timestamp_format: TimestampFormat::Auto,
+        }
+    }
+}
+
+/// Positions of the mapped columns in the CSV headers.
+struct CsvColumns {
+    timestamp: usize,
+    value: usize,
+    name: Option<usize>,
+}
+
+impl CsvColumns {
+    fn new(headers: &StringRecord, mapping: &CsvColumnMapping) -> Result<Self> {
+        let position = |column: &str| headers.iter().position(|header| header.trim() == column);
+        Ok(Self {
+            timestamp: position(&mapping.timestamp)
+                .ok_or_else(|| anyhow!("Missing timestamp column: {}", mapping.timestamp))?,
+            value: position(&mapping.value)
+                .ok_or_else(|| anyhow!("Missing value column: {}", mapping.value))?,
+            name: position(&mapping.name),
+        })
+    }
+}
+
+/// The numbers are floats, `true` and `false` are booleans,
+/// and anything else is a string.
+fn csv_value_to_samples(value: &str, datetime: SensAppDateTime) -> TypedSamples {
+    let trimmed = value.trim();
+    if let Ok(value) = trimmed.parse::<f64>() {
+        return TypedSamples::one_float(value, datetime);
+    }
+    match trimmed {
+        "true" => TypedSamples::one_boolean(true, datetime),
+        "false" => TypedSamples::one_boolean(false, datetime),
+        _ => TypedSamples::one_string(value.to_string(), datetime),
+    }
+}
+
+fn sensor_type_of(samples: &TypedSamples) -> SensorType {
+    match samples {
+        TypedSamples::Boolean(_) => SensorType::Boolean,
+        TypedSamples::String(_) => SensorType::String,
+        _ => SensorType::Float,
+    }
+}
+
+/// Publishes a long format CSV, one sample per row, on the event bus.
+///
+/// The columns are found in the headers using the mapping, and the
+/// rows without a sensor name column belong to the default sensor.
+/// Returns the number of samples, and the waiter of the published batches.
 pub async fn publish_csv_async<R: io::AsyncRead + Unpin + Send>(
     mut csv_reader: AsyncReader<R>,
-    batch_size: usize,
+    mapping: &CsvColumnMapping,
+    default_sensor_name: &str,
     event_bus: Arc<EventBus>,
-) -> Result<()> {
-    println!("{:?}", csv_reader.has_headers());
-    println!("{:?}", csv_reader.headers().await.unwrap());
+) -> Result<(usize, Vec<WaitForAll>)> {
+    let columns = CsvColumns::new(csv_reader.headers().await?, mapping)?;
     let mut records = csv_reader.records();
 
-    let mut current_samples: Vec<Sample<i64>> = vec![];
-
-    //let mut batch_builder = BatchBuilder::new()?;
-    let mut all_batches_waiter = WaitForAll::new();
-
-    let mut i = 0;
+    let mut batch_builder = BatchBuilder::new()?;
+    let mut sensors: HashMap<(String, SensorType), Arc<Sensor>> = HashMap::new();
+    let mut waiters = Vec::new();
+    let mut sample_count = 0;
 
-    println!("Reading CSV");
     while let Some(record) = records.next().await {
-        let record = record.unwrap();
-        //println!("{:?}", record);
+        let record = record?;
+        // Counted from 1, the header being the first line
+        let line = record
+            .position()
+            .map(|position| position.line())
+            .unwrap_or_default();
+        let field = |index: usize| {
+            record
+                .get(index)
+                .ok_or_else(|| anyhow!("Missing field on line {}", line))
+        };
 
-        current_samples.push(Sample {
-            datetime: SensAppDateTime::from_unix_seconds(0.0),
-            value: i,
-        });
+        let datetime = mapping
+            .timestamp_format
+            .parse(field(columns.timestamp)?)
+            .with_context(|| format!("Invalid CSV line {}", line))?;
+        let samples = csv_value_to_samples(field(columns.value)?, datetime);
+        let name = match columns.name {
+            Some(index) => field(index)?,
+            None => default_sensor_name,
+        };
+        let sensor_type = sensor_type_of(&samples);
+        let sensor = match sensors.get(&(name.to_string(), sensor_type)) {
+            Some(sensor) => sensor.clone(),
+            None => {
+                let sensor = Arc::new(
+                    Sensor::new_without_uuid(name.to_string(), sensor_type, None, None)
+                        .with_context(|| format!("Invalid CSV line {}", line))?,
+                );
+                sensors.insert((name.to_string(), sensor_type), sensor.clone());
+                sensor
+            }
+        };
 
-        i += 1;
-
-        if current_samples.len() >= batch_size {
-            let single_sensor_batch = SingleSensorBatch::new(
-                Arc::new(Sensor::new_without_uuid(
-                    "test".to_string(),
-                    crate::datamodel::SensorType::Integer,
-                    None,
-                    None,
-                )?),
-                TypedSamples::Integer(current_samples.into()),
-            );
-            let batch = Batch {
-                sensors: smallvec![single_sensor_batch],
-            };
-            let sync_receiver = event_bus.publish(batch).await?;
-            //sync_receiver.activate().recv().await?;
-            current_samples = vec![];
-            all_batches_waiter.add(sync_receiver.activate()).await;
+        batch_builder
+            .add(sensor, samples)
+            .await
+            .with_context(|| format!("Invalid CSV line {}", line))?;
+        sample_count += 1;
+        if let Some(waiter) = batch_builder.send_if_batch_full(event_bus.clone()).await? {
+            waiters.push(waiter);
         }
     }
 
-    if !current_samples.is_empty() {
-        let single_sensor_batch = SingleSensorBatch::new(
-            Arc::new(Sensor::new_without_uuid(
-                "test".to_string(),
-                crate::datamodel::SensorType::Integer,
-                None,
-                None,
-            )?),
-            TypedSamples::Integer(current_samples.into()),
+    if let Some(waiter) = batch_builder.send_what_is_left(event_bus).await? {
+        waiters.push(waiter);
+    }
+    Ok((sample_count, waiters))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{self, message};
+    use crate::config::load_configuration;
+    use crate::datamodel::sensapp_datetime::SensAppDateTimeExt;
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use crate::storage::storage::StorageInstance;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_timestamp_formats() {
+        let expected = SensAppDateTime::from_unix_seconds_i64(1_704_067_200);
+        assert_eq!(TimestampFormat::Auto.parse("1704067200").unwrap(), expected);
+        assert_eq!(
+            TimestampFormat::Auto.parse("2024-01-01T00:00:00Z").unwrap(),
+            expected
         );
-        let batch = Batch {
-            sensors: smallvec![single_sensor_batch],
-        };
-        let sync_receiver = event_bus.publish(batch).await?;
-        all_batches_waiter.add(sync_receiver.activate()).await;
+        assert_eq!(
+            TimestampFormat::UnixMilliseconds
+                .parse("1704067200000")
+                .unwrap(),
+            expected
+        );
+        assert_eq!(
+            TimestampFormat::Iso8601
+                .parse(" 2024-01-01T00:00:00Z ")
+                .unwrap(),
+            expected
+        );
+        assert!(TimestampFormat::Iso8601.parse("1704067200").is_err());
+        assert!(TimestampFormat::UnixSeconds
+            .parse("2024-01-01T00:00:00Z")
+            .is_err());
+        assert!(TimestampFormat::Auto.parse("yesterday").is_err());
+
+        assert_eq!(
+            "unix_ms".parse::<TimestampFormat>().unwrap(),
+            TimestampFormat::UnixMilliseconds
+        );
+        assert!("unknown".parse::<TimestampFormat>().is_err());
     }
 
-    // Wait for all batches to sync
-    all_batches_waiter.wait().await?;
+    #[tokio::test]
+    async fn test_publish_csv_with_column_mapping() {
+        _ = load_configuration();
+        let storage = Arc::new(create_test_storage().await);
+        let event_bus = bus::event_bus::init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let storage_for_publish = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(message::Message::Publish(message::PublishMessage {
+                batch,
+                sync_sender,
+                ..
+            })) = receiver.recv().await
+            {
+                storage_for_publish
+                    .publish(batch, sync_sender)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let name = format!("test_csv_{}", Uuid::new_v4());
+        let csv = format!(
+            "ts;reading;sensor\n\
+             2024-01-01T00:00:00Z;21.5;{name}_temperature\n\
+             2024-01-01T00:01:00Z;22;{name}_temperature\n\
+             2024-01-01T00:00:00Z;open;{name}_door\n"
+        );
+        let mapping = CsvColumnMapping {
+            timestamp: "ts".to_string(),
+            value: "reading".to_string(),
+            name: "sensor".to_string(),
+            timestamp_format: TimestampFormat::Iso8601,
+        };
+        let csv_reader = csv_async::AsyncReaderBuilder::new()
+            .delimiter(b';')
+            .create_reader(csv.as_bytes());
+        let (sample_count, waiters) =
+            publish_csv_async(csv_reader, &mapping, "default", event_bus.clone())
+                .await
+                .unwrap();
+        assert_eq!(sample_count, 3);
+        for mut waiter in waiters {
+            waiter.wait().await.unwrap();
+        }
+
+        let temperature =
+            Sensor::new_without_uuid(format!("{name}_temperature"), SensorType::Float, None, None)
+                .unwrap();
+        let sensor_data = storage
+            .query_sensor_data(temperature.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            sensor_data.samples,
+            TypedSamples::Float(smallvec::smallvec![
+                crate::datamodel::Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_704_067_200),
+                    value: 21.5,
+                },
+                crate::datamodel::Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_704_067_260),
+                    value: 22.0,
+                },
+            ])
+        );
+        let door = Sensor::new_without_uuid(format!("{name}_door"), SensorType::String, None, None)
+            .unwrap();
+        let sensor_data = storage
+            .query_sensor_data(door.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            sensor_data.samples,
+            TypedSamples::one_string(
+                "open".to_string(),
+                SensAppDateTime::from_unix_seconds_i64(1_704_067_200)
+            )
+        );
 
-    println!("Done reading CSV");
-    Ok(())
+        // Without the name column, the samples go to the default sensor
+        let csv = "ts;reading\n1704067200;1\n";
+        let csv_reader = csv_async::AsyncReaderBuilder::new()
+            .delimiter(b';')
+            .create_reader(csv.as_bytes());
+        let (sample_count, _) = publish_csv_async(
+            csv_reader,
+            &CsvColumnMapping {
+                timestamp_format: TimestampFormat::Auto,
+                ..mapping.clone()
+            },
+            &name,
+            event_bus.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(sample_count, 1);
+
+        // The mapped columns must exist
+        let csv_reader = csv_async::AsyncReaderBuilder::new()
+            .delimiter(b';')
+            .create_reader("timestamp;value\n1704067200;1\n".as_bytes());
+        let error = publish_csv_async(csv_reader, &mapping, &name, event_bus.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Missing timestamp column: ts");
+
+        let csv_reader = csv_async::AsyncReaderBuilder::new()
+            .delimiter(b';')
+            .create_reader("ts;reading\nnot a date;1\n".as_bytes());
+        let error = publish_csv_async(csv_reader, &mapping, &name, event_bus)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid CSV line 2");
+    }
 }
@@ -4,4 +4,5 @@ pub mod datetime_guesser;
 pub mod geo_guesser;
 pub mod is_header;
 pub mod parsing;
+pub mod sensor_type;
 pub mod uuid;
@@ -0,0 +1,84 @@
+use super::columns::{infer_column, InferedColumn};
+use crate::{config, datamodel::SensorType};
+use anyhow::Result;
+
+/// Infers the narrowest sensor type for a column of string values.
+///
+/// Only the first `max_rows` values are looked at. A column made only of
+/// integers is an Integer column, and a column mixing integers and decimals
+/// is a Float column. Booleans and JSON are detected, everything else is
+/// a String.
+pub fn infer_sensor_type(column: &[String], max_rows: usize) -> SensorType {
+    let sample = column.iter().take(max_rows).cloned().collect::<Vec<_>>();
+
+    // Infer without the numeric mode, as it would parse
+    // integers as numerics and we want the narrowest type.
+    match infer_column(sample, true, false) {
+        InferedColumn::Integer(_) => SensorType::Integer,
+        InferedColumn::Float(_) => SensorType::Float,
+        InferedColumn::Numeric(_) => SensorType::Numeric,
+        InferedColumn::Boolean(_) => SensorType::Boolean,
+        InferedColumn::Json(_) => SensorType::Json,
+        // There is no datetime sensor type, so datetimes are kept as strings
+        InferedColumn::DateTime(_) | InferedColumn::String(_) => SensorType::String,
+    }
+}
+
+/// Infers the sensor type using the limit from the configuration.
+pub fn infer_sensor_type_with_config(column: &[String]) -> Result<SensorType> {
+    let config = config::get()?;
+    Ok(infer_sensor_type(column, config.max_inference_rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_infer_sensor_type_integers() {
+        let values = column(&["1", "42", "-3", "78953678389071"]);
+        assert_eq!(infer_sensor_type(&values, 128), SensorType::Integer);
+    }
+
+    #[test]
+    fn test_infer_sensor_type_mixed_numbers() {
+        let values = column(&["1", "2.5", "3", "4.25"]);
+        assert_eq!(infer_sensor_type(&values, 128), SensorType::Float);
+    }
+
+    #[test]
+    fn test_infer_sensor_type_booleans() {
+        let values = column(&["true", "FALSE", " true "]);
+        assert_eq!(infer_sensor_type(&values, 128), SensorType::Boolean);
+    }
+
+    #[test]
+    fn test_infer_sensor_type_strings() {
+        let values = column(&["abc", "42", "true"]);
+        assert_eq!(infer_sensor_type(&values, 128), SensorType::String);
+
+        let values = column(&["2020-01-01T00:00:00Z"]);
+        assert_eq!(infer_sensor_type(&values, 128), SensorType::String);
+    }
+
+    #[test]
+    fn test_infer_sensor_type_max_rows() {
+        let values = column(&["1", "2", "3.5"]);
+        assert_eq!(infer_sensor_type(&values, 2), SensorType::Integer);
+        assert_eq!(infer_sensor_type(&values, 3), SensorType::Float);
+    }
+
+    #[test]
+    fn test_infer_sensor_type_with_config() {
+        _ = config::load_configuration();
+        let values = column(&["1", "2"]);
+        assert_eq!(
+            infer_sensor_type_with_config(&values).unwrap(),
+            SensorType::Integer
+        );
+    }
+}
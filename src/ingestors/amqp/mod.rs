@@ -0,0 +1,41 @@
+use anyhow::Result;
+use futures::stream::StreamExt;
+use lapin::{options::*, types::FieldTable, Connection, ConnectionProperties};
+use tracing::{event, Level};
+
+/// Consumes the messages of a local AMQP queue and logs them.
+pub async fn amqp_example() -> Result<()> {
+    let addr = std::env::var("AMQP_ADDR").unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+    let connection = Connection::connect(&addr, ConnectionProperties::default()).await?;
+    let channel = connection.create_channel().await?;
+
+    let queue = channel
+        .queue_declare(
+            "sensapp",
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    event!(Level::INFO, "Declared AMQP queue {:?}", queue.name());
+
+    let mut consumer = channel
+        .basic_consume(
+            "sensapp",
+            "sensapp",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    while let Some(delivery) = consumer.next().await {
+        let delivery = delivery?;
+        event!(
+            Level::INFO,
+            "Received AMQP message: {}",
+            String::from_utf8_lossy(&delivery.data)
+        );
+        delivery.ack(BasicAckOptions::default()).await?;
+    }
+
+    Ok(())
+}
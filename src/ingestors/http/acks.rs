@@ -0,0 +1,140 @@
+use super::state::HttpServerState;
+use crate::bus::{
+    message::{AckMessage, AckStatus},
+    wait_for_all::WaitForAll,
+    EventBus,
+};
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::{Stream, StreamExt};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Acknowledges the batch in the background, once the storage backends synced it.
+///
+/// Returns the batch id sent in the acknowledgement.
+pub fn ack_in_background(
+    event_bus: Arc<EventBus>,
+    waiter: Option<WaitForAll>,
+    sample_count: usize,
+) -> Uuid {
+    let batch_id = Uuid::new_v4();
+    tokio::spawn(async move {
+        let status = match waiter {
+            Some(mut waiter) => match waiter.wait().await {
+                Ok(()) => AckStatus::Stored,
+                Err(_) => AckStatus::Failed,
+            },
+            None => AckStatus::Stored,
+        };
+        event_bus.ack(AckMessage {
+            batch_id,
+            status,
+            sample_count,
+        });
+    });
+    batch_id
+}
+
+/// Stream of the acknowledgements of the asynchronous ingestions.
+///
+/// Each `ack` event has the `batch_id` returned by the ingestion, the `status`,
+/// either `stored` or `failed`, and the `sample_count`. The acknowledgements
+/// sent before the subscription are not replayed.
+#[utoipa::path(
+    get,
+    path = "/acks",
+    tag = "SensApp",
+    responses(
+        (status = 200, description = "Server-Sent Events stream of acknowledgements", content_type = "text/event-stream"),
+    )
+)]
+pub async fn acks(
+    State(state): State<HttpServerState>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let receiver = state.event_bus.acks_receiver.activate_cloned();
+    let stream = receiver.map(|ack| Event::default().event("ack").json_data(ack));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::message::{Message, PublishMessage};
+    use crate::config::load_configuration;
+    use crate::ingestors::http::publish::{publish_with_parser, PublishQueryParams};
+    use crate::storage::sqlite::SqliteStorage;
+    use axum::{
+        extract::{Path, Query},
+        http::StatusCode,
+        response::IntoResponse,
+    };
+    use tokio_util::bytes::Bytes;
+
+    #[tokio::test]
+    async fn test_async_ack() {
+        _ = load_configuration();
+        let event_bus = crate::bus::event_bus::init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        tokio::spawn(async move {
+            while let Ok(Message::Publish(PublishMessage { sync_sender, .. })) =
+                receiver.recv().await
+            {
+                sync_sender.broadcast(()).await.unwrap();
+            }
+        });
+        let state = HttpServerState {
+            event_bus,
+            ..HttpServerState::for_tests(Arc::new(
+                SqliteStorage::connect("sqlite::memory:").await.unwrap(),
+            ))
+        };
+
+        let mut events = acks(State(state.clone()))
+            .await
+            .into_response()
+            .into_body()
+            .into_data_stream();
+
+        let response = publish_with_parser(
+            State(state),
+            Path("graphite".to_string()),
+            Query(PublishQueryParams {
+                async_ack: true,
+                ..Default::default()
+            }),
+            Bytes::from("test.acks 1 1700000000\ntest.acks 2 1700000010\n"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let batch_id = body["batch_id"].as_str().unwrap().to_string();
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let event = std::str::from_utf8(&event).unwrap();
+        assert!(event.starts_with("event: ack\n"));
+        let data = event
+            .lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .unwrap();
+        let ack: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(
+            ack,
+            serde_json::json!({
+                "batch_id": batch_id,
+                "status": "stored",
+                "sample_count": 2,
+            })
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::SensAppDateTime;
+use crate::exporters::json::sensor_to_json;
+use crate::storage::query::{AggregateFunction, SensorSelector, TimeRange};
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct AggregateQueryParams {
+    /// Duration of the buckets, in seconds.
+    pub step: f64,
+    pub func: AggregateFunction,
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+}
+
+/// Aggregate the samples of a numeric sensor in time buckets.
+///
+/// The buckets of `step` seconds are aligned on the unix epoch, and the
+/// empty buckets are left out. Each bucket has its `datetime` and a value
+/// per aggregate, named after the function. With `func=minmax`, each bucket
+/// has both a `min` and a `max`, so the rendered charts keep the spikes.
+#[utoipa::path(
+    get,
+    path = "/sensors/{sensor_uuid}/aggregate",
+    tag = "SensApp",
+    params(
+        ("sensor_uuid" = String, Path, description = "UUID of the sensor"),
+        ("step" = f64, Query, description = "Duration of the buckets, in seconds", example = 60.0),
+        ("func" = String, Query, description = "One of avg, min, max, count, minmax", example = "minmax"),
+        ("start" = Option<f64>, Query, description = "Start of the time range, in unix seconds"),
+        ("end" = Option<f64>, Query, description = "End of the time range, in unix seconds, exclusive"),
+    ),
+    responses(
+        (status = 200, description = "The aggregated buckets", content_type = "application/json"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 404, description = "Sensor Not Found", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn aggregate_sensor(
+    State(state): State<HttpServerState>,
+    Path(sensor_uuid): Path<String>,
+    Query(AggregateQueryParams {
+        step,
+        func,
+        start,
+        end,
+    }): Query<AggregateQueryParams>,
+) -> Result<Json<Value>, AppError> {
+    let sensor_uuid = Uuid::parse_str(&sensor_uuid)
+        .map_err(|_| AppError::BadRequest(anyhow!("Invalid sensor UUID: {}", sensor_uuid)))?;
+    if !step.is_finite() || step < 0.001 {
+        return Err(AppError::BadRequest(anyhow!(
+            "The step must be at least a millisecond"
+        )));
+    }
+
+    let not_found = || AppError::NotFound(anyhow!("Sensor not found: {}", sensor_uuid));
+    let sensor = state
+        .storage
+        .get_sensor(sensor_uuid)
+        .await?
+        .ok_or_else(not_found)?;
+    if !SensorSelector::is_numeric(sensor.sensor_type) {
+        return Err(AppError::BadRequest(anyhow!(
+            "Only the numeric sensors can be aggregated"
+        )));
+    }
+
+    let time_range = TimeRange::new(
+        start.map(SensAppDateTime::from_unix_seconds),
+        end.map(SensAppDateTime::from_unix_seconds),
+    );
+    let buckets = state
+        .storage
+        .aggregate_sensor_data(
+            sensor_uuid,
+            time_range,
+            hifitime::Duration::from_seconds(step),
+        )
+        .await?
+        .ok_or_else(not_found)?;
+
+    let buckets = buckets
+        .iter()
+        .map(|bucket| {
+            let mut object = Map::new();
+            object.insert(
+                "datetime".to_string(),
+                Value::from(bucket.start.to_rfc3339()),
+            );
+            for (name, value) in func.values(bucket) {
+                object.insert(name.to_string(), Value::from(value));
+            }
+            Value::Object(object)
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(json!({
+        "sensor": sensor_to_json(&sensor),
+        "step": step,
+        "buckets": buckets,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_aggregate_minmax() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        // A flat signal at 10, with a spike in each minute
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                "test_aggregate_minmax".to_string(),
+                SensorType::Float,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let values = (0..120).map(|second| match second {
+            15 => 95.0,
+            30 => -40.0,
+            75 => 250.0,
+            _ => 10.0,
+        });
+        let samples = TypedSamples::Float(
+            values
+                .enumerate()
+                .map(|(second, value)| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_040 + second as i64),
+                    value,
+                })
+                .collect(),
+        );
+        let string_sensor = Arc::new(
+            Sensor::new_without_uuid(
+                "test_aggregate_string".to_string(),
+                SensorType::String,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(sensor.clone(), samples),
+            SingleSensorBatch::new(
+                string_sensor.clone(),
+                TypedSamples::one_string(
+                    "open".to_string(),
+                    SensAppDateTime::from_unix_seconds_i64(1_700_000_040)
+                ),
+            ),
+        ]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let aggregate = |uuid: Uuid, func, step| {
+            aggregate_sensor(
+                State(state.clone()),
+                Path(uuid.to_string()),
+                Query(AggregateQueryParams {
+                    step,
+                    func,
+                    start: None,
+                    end: None,
+                }),
+            )
+        };
+
+        let Json(response) = aggregate(sensor.uuid, AggregateFunction::MinMax, 60.0)
+            .await
+            .unwrap();
+        assert_eq!(response["sensor"]["name"], "test_aggregate_minmax");
+        // 1_700_000_040 is aligned on the minute
+        assert_eq!(
+            response["buckets"],
+            json!([
+                {"datetime": "2023-11-14T22:14:00+00:00", "min": -40.0, "max": 95.0},
+                {"datetime": "2023-11-14T22:15:00+00:00", "min": 10.0, "max": 250.0},
+            ])
+        );
+
+        // The averages hide the spikes
+        let Json(response) = aggregate(sensor.uuid, AggregateFunction::Avg, 60.0)
+            .await
+            .unwrap();
+        let averages = response["buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| bucket["avg"].as_f64().unwrap())
+            .collect::<Vec<_>>();
+        assert!((averages[0] - 635.0 / 60.0).abs() < 1e-9);
+        assert!((averages[1] - 840.0 / 60.0).abs() < 1e-9);
+        assert!(response["buckets"][0].get("min").is_none());
+
+        let Json(response) = aggregate(sensor.uuid, AggregateFunction::Count, 3600.0)
+            .await
+            .unwrap();
+        assert_eq!(response["buckets"][0]["count"], 120.0);
+
+        assert!(matches!(
+            aggregate(string_sensor.uuid, AggregateFunction::MinMax, 60.0).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            aggregate(sensor.uuid, AggregateFunction::MinMax, 0.0).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            aggregate(Uuid::new_v4(), AggregateFunction::MinMax, 60.0).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,241 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::{Annotation, SensAppDateTime};
+use crate::storage::query::TimeRange;
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct AnnotationRequest {
+    /// Unix seconds.
+    pub time: f64,
+    pub title: String,
+    pub text: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AnnotationsQueryParams {
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+}
+
+fn parse_sensor_uuid(sensor_uuid: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(sensor_uuid)
+        .map_err(|_| AppError::BadRequest(anyhow!("Invalid sensor UUID: {}", sensor_uuid)))
+}
+
+fn annotation_to_json(annotation: &Annotation) -> Value {
+    json!({
+        "datetime": annotation.datetime.to_rfc3339(),
+        "title": annotation.title,
+        "text": annotation.text,
+        "tags": annotation.tags,
+    })
+}
+
+/// Add an annotation to the timeline of a sensor.
+///
+/// Annotations record discrete events, like deployments or alarms.
+#[utoipa::path(
+    post,
+    path = "/sensors/{sensor_uuid}/annotations",
+    tag = "SensApp",
+    params(
+        ("sensor_uuid" = String, Path, description = "UUID of the sensor"),
+    ),
+    request_body(
+        content = String,
+        content_type = "application/json",
+        description = "Time in unix seconds, title, and optional text and tags.",
+        example = json!({
+            "time": 1700000000.0,
+            "title": "Deployment",
+            "text": "Firmware 1.2.0",
+            "tags": ["deploy", "firmware"]
+        })
+    ),
+    responses(
+        (status = 201, description = "Annotation created"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 404, description = "Sensor Not Found", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn add_annotation(
+    State(state): State<HttpServerState>,
+    Path(sensor_uuid): Path<String>,
+    Json(AnnotationRequest {
+        time,
+        title,
+        text,
+        tags,
+    }): Json<AnnotationRequest>,
+) -> Result<(StatusCode, Json<Value>), AppError> {
+    let sensor_uuid = parse_sensor_uuid(&sensor_uuid)?;
+    if title.is_empty() {
+        return Err(AppError::BadRequest(anyhow!("The title can't be empty")));
+    }
+    state.ensure_storage_available()?;
+
+    let annotation = Annotation::new(SensAppDateTime::from_unix_seconds(time), title, text, tags);
+    if !state
+        .storage
+        .add_annotation(sensor_uuid, &annotation)
+        .await?
+    {
+        return Err(AppError::NotFound(anyhow!(
+            "Sensor not found: {}",
+            sensor_uuid
+        )));
+    }
+    Ok((StatusCode::CREATED, Json(annotation_to_json(&annotation))))
+}
+
+/// List the annotations of a sensor, sorted by datetime.
+#[utoipa::path(
+    get,
+    path = "/sensors/{sensor_uuid}/annotations",
+    tag = "SensApp",
+    params(
+        ("sensor_uuid" = String, Path, description = "UUID of the sensor"),
+        ("start" = Option<f64>, Query, description = "Start of the time range, in unix seconds, inclusive"),
+        ("end" = Option<f64>, Query, description = "End of the time range, in unix seconds, exclusive"),
+    ),
+    responses(
+        (status = 200, description = "Annotations of the sensor, possibly none"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 404, description = "Sensor Not Found", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn query_annotations(
+    State(state): State<HttpServerState>,
+    Path(sensor_uuid): Path<String>,
+    Query(AnnotationsQueryParams { start, end }): Query<AnnotationsQueryParams>,
+) -> Result<Json<Vec<Value>>, AppError> {
+    let sensor_uuid = parse_sensor_uuid(&sensor_uuid)?;
+    let time_range = TimeRange::new(
+        start.map(SensAppDateTime::from_unix_seconds),
+        end.map(SensAppDateTime::from_unix_seconds),
+    );
+    let annotations = state
+        .storage
+        .query_annotations(sensor_uuid, time_range)
+        .await?
+        .ok_or_else(|| AppError::NotFound(anyhow!("Sensor not found: {}", sensor_uuid)))?;
+    Ok(Json(annotations.iter().map(annotation_to_json).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_annotations() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid("annotated".to_string(), SensorType::Integer, None, None)
+                .unwrap(),
+        );
+        let samples = TypedSamples::Integer(smallvec![Sample {
+            datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+            value: 1,
+        }]);
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+
+        // Added out of order, to check the sorting.
+        for (time, title, tags) in [
+            (1_700_000_200.0, "Alarm", vec!["alarm".to_string()]),
+            (1_700_000_100.0, "Deployment", vec!["deploy".to_string()]),
+            (1_700_000_300.0, "Maintenance", vec![]),
+        ] {
+            let (status_code, _) = add_annotation(
+                State(state.clone()),
+                Path(sensor.uuid.to_string()),
+                Json(AnnotationRequest {
+                    time,
+                    title: title.to_string(),
+                    text: None,
+                    tags,
+                }),
+            )
+            .await
+            .unwrap();
+            assert_eq!(status_code, StatusCode::CREATED);
+        }
+
+        let Json(annotations) = query_annotations(
+            State(state.clone()),
+            Path(sensor.uuid.to_string()),
+            Query(AnnotationsQueryParams {
+                start: Some(1_700_000_100.0),
+                end: Some(1_700_000_300.0),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0]["title"], "Deployment");
+        assert_eq!(annotations[0]["tags"], json!(["deploy"]));
+        assert_eq!(annotations[1]["title"], "Alarm");
+
+        let Json(annotations) = query_annotations(
+            State(state.clone()),
+            Path(sensor.uuid.to_string()),
+            Query(Default::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(annotations.len(), 3);
+
+        let result = add_annotation(
+            State(state.clone()),
+            Path(Uuid::new_v4().to_string()),
+            Json(AnnotationRequest {
+                time: 1_700_000_000.0,
+                title: "Lost".to_string(),
+                text: None,
+                tags: vec![],
+            }),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        let result = query_annotations(
+            State(state),
+            Path(Uuid::new_v4().to_string()),
+            Query(Default::default()),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+}
@@ -1,8 +1,16 @@
-use axum::http::StatusCode;
+use super::audit::AuditError;
+use crate::datamodel::batch_builder::InvalidSamplesError;
+use crate::parsing::compressed::is_decompressed_too_large;
+use crate::storage::circuit_breaker::{is_client_error, CircuitOpenError};
+use crate::storage::raw_sql::RawSqlTimeoutError;
+use crate::storage::storage::UnsupportedError;
+use crate::storage::sync_timeout::StorageError;
+use axum::http::{header, StatusCode};
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::Json;
 use serde_json::json;
+use std::time::Duration;
 
 // Anyhow error handling with axum
 // https://github.com/tokio-rs/axum/blob/d3112a40d55f123bc5e65f995e2068e245f12055/examples/anyhow-error-response/src/main.rs
@@ -10,10 +18,20 @@ use serde_json::json;
 pub enum AppError {
     InternalServerError(anyhow::Error),
     BadRequest(anyhow::Error),
+    Unauthorized(anyhow::Error),
+    NotFound(anyhow::Error),
+    NotAcceptable(anyhow::Error),
+    ServiceUnavailable(anyhow::Error),
+    PayloadTooLarge(anyhow::Error),
+    GatewayTimeout(anyhow::Error),
+    NotImplemented(anyhow::Error),
+    /// With the duration to wait before retrying.
+    TooManyRequests(anyhow::Error, Duration),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let mut retry_after = None;
         let (status, message) = match self {
             AppError::InternalServerError(error) => {
                 eprintln!("Internal Server Error: {}", error.backtrace());
@@ -23,16 +41,65 @@ impl IntoResponse for AppError {
                 )
             }
             AppError::BadRequest(error) => (StatusCode::BAD_REQUEST, error.to_string()),
+            AppError::Unauthorized(error) => (StatusCode::UNAUTHORIZED, error.to_string()),
+            AppError::NotFound(error) => (StatusCode::NOT_FOUND, error.to_string()),
+            AppError::NotAcceptable(error) => (StatusCode::NOT_ACCEPTABLE, error.to_string()),
+            AppError::ServiceUnavailable(error) => {
+                (StatusCode::SERVICE_UNAVAILABLE, error.to_string())
+            }
+            AppError::PayloadTooLarge(error) => (StatusCode::PAYLOAD_TOO_LARGE, error.to_string()),
+            AppError::GatewayTimeout(error) => (StatusCode::GATEWAY_TIMEOUT, error.to_string()),
+            AppError::NotImplemented(error) => (StatusCode::NOT_IMPLEMENTED, error.to_string()),
+            AppError::TooManyRequests(error, duration) => {
+                // Retry-After is in whole seconds
+                retry_after = Some(duration.as_secs_f64().ceil().max(1.0) as u64);
+                (StatusCode::TOO_MANY_REQUESTS, error.to_string())
+            }
         };
         let body = Json(json!({ "error": message }));
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.into());
+        }
+        response
+    }
+}
+
+impl AppError {
+    /// The client sent invalid data, or a body decompressing to too much data.
+    pub fn invalid_body(error: anyhow::Error) -> Self {
+        if is_decompressed_too_large(&error) {
+            return Self::PayloadTooLarge(error);
+        }
+        Self::BadRequest(error)
     }
 }
+
 impl<E> From<E> for AppError
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self::InternalServerError(err.into())
+        let err = err.into();
+        if err.is::<CircuitOpenError>() || err.is::<AuditError>() {
+            return Self::ServiceUnavailable(err);
+        }
+        if err.is::<UnsupportedError>() {
+            return Self::NotImplemented(err);
+        }
+        if err.is::<RawSqlTimeoutError>() {
+            return Self::GatewayTimeout(err);
+        }
+        if is_client_error(&err) || err.is::<InvalidSamplesError>() {
+            return Self::BadRequest(err);
+        }
+        match err.downcast_ref::<StorageError>() {
+            Some(StorageError::SyncTimeout(_)) => return Self::GatewayTimeout(err),
+            Some(StorageError::NotStored) => return Self::ServiceUnavailable(err),
+            None => {}
+        }
+        Self::InternalServerError(err)
     }
 }
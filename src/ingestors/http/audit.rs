@@ -0,0 +1,312 @@
+use crate::datamodel::{batch_builder::BatchBuilder, SensAppDateTime};
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::bytes::Bytes;
+
+static AUDIT_LOG: OnceLock<Option<Arc<AuditLog>>> = OnceLock::new();
+
+/// Name of the append-only file in the audit directory.
+pub const AUDIT_LOG_FILE_NAME: &str = "sensapp-audit.jsonl";
+
+/// The error returned when the record of an ingestion request can't be
+/// written, so the request is refused rather than stored without it.
+#[derive(Debug)]
+pub struct AuditError(pub String);
+
+impl std::fmt::Display for AuditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+#[derive(Debug)]
+struct AuditRecord {
+    datetime: SensAppDateTime,
+    parser: String,
+    sensors: usize,
+    samples: usize,
+    payload: Bytes,
+}
+
+/// A record, and where to report once it is written.
+type QueuedRecord = (AuditRecord, oneshot::Sender<std::io::Result<()>>);
+
+impl AuditRecord {
+    /// One JSON object per line, with the payload in base64
+    /// as it can be compressed or binary.
+    fn to_json_line(&self) -> String {
+        let mut line = json!({
+            "datetime": self.datetime.to_rfc3339(),
+            "parser": self.parser,
+            "sensors": self.sensors,
+            "samples": self.samples,
+            "payload_bytes": self.payload.len(),
+            "payload": STANDARD.encode(&self.payload),
+        })
+        .to_string();
+        line.push('\n');
+        line
+    }
+}
+
+/// Keeps the raw payloads of the ingestion requests, as received,
+/// with the parser and the resulting sensor and sample counts.
+///
+/// The records are appended to a JSON lines file by a background task,
+/// and the ingestion requests wait until their record is written.
+/// A full queue slows the ingestion down, and a record that can't be
+/// written fails the request, so no payload is stored without its record.
+#[derive(Debug)]
+pub struct AuditLog {
+    sender: mpsc::Sender<QueuedRecord>,
+}
+
+impl AuditLog {
+    /// Starts the background writer, appending to the audit file of the directory,
+    /// with a queue of `capacity` records.
+    pub async fn new(directory: &Path, capacity: usize) -> Result<Self> {
+        tokio::fs::create_dir_all(directory).await?;
+        let path = directory.join(AUDIT_LOG_FILE_NAME);
+        // Opened right away, to fail early on a wrong directory.
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        Ok(Self::with_file(file, path, capacity))
+    }
+
+    fn with_file(file: tokio::fs::File, path: PathBuf, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        tokio::spawn(write_records(file, path, receiver));
+        Self { sender }
+    }
+
+    /// The audit log of `SENSAPP_AUDIT_RAW_PAYLOADS`, started once.
+    pub async fn shared() -> Result<Option<Arc<Self>>> {
+        if let Some(audit_log) = AUDIT_LOG.get() {
+            return Ok(audit_log.clone());
+        }
+        let config = crate::config::get()?;
+        let audit_log = match &config.audit_raw_payloads {
+            Some(directory) => Some(Arc::new(
+                Self::new(&PathBuf::from(directory), config.audit_queue_capacity).await?,
+            )),
+            None => None,
+        };
+        Ok(AUDIT_LOG.get_or_init(|| audit_log).clone())
+    }
+
+    /// Writes the record of an ingestion request, waiting for room
+    /// in the queue and for the record to be written.
+    pub async fn record(
+        &self,
+        parser: &str,
+        sensors: usize,
+        samples: usize,
+        payload: Bytes,
+    ) -> Result<()> {
+        let record = AuditRecord {
+            datetime: SensAppDateTime::now().unwrap_or_default(),
+            parser: parser.to_string(),
+            sensors,
+            samples,
+            payload,
+        };
+        let stopped = || AuditError("The audit log writer stopped".to_string());
+        let (written_sender, written_receiver) = oneshot::channel();
+        self.sender
+            .send((record, written_sender))
+            .await
+            .map_err(|_| stopped())?;
+        written_receiver
+            .await
+            .map_err(|_| stopped())?
+            .map_err(|error| AuditError(format!("Failed to write the audit log: {}", error)))?;
+        Ok(())
+    }
+}
+
+async fn write_records(
+    mut file: tokio::fs::File,
+    path: PathBuf,
+    mut receiver: mpsc::Receiver<QueuedRecord>,
+) {
+    while let Some((record, written_sender)) = receiver.recv().await {
+        let line = record.to_json_line();
+        let result = async {
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await
+        }
+        .await;
+        if let Err(error) = &result {
+            tracing::error!(
+                "Failed to write the audit log {}: {}",
+                path.display(),
+                error
+            );
+        }
+        // The request may have been cancelled in the meantime.
+        let _ = written_sender.send(result);
+    }
+}
+
+/// Audits the raw payload of a parsed ingestion request, when enabled.
+pub async fn audit_payload(
+    parser: &str,
+    payload: &Bytes,
+    batch_builder: &BatchBuilder,
+) -> Result<()> {
+    if let Some(audit_log) = AuditLog::shared().await? {
+        audit_log
+            .record(
+                parser,
+                batch_builder.sensors_len().await,
+                batch_builder.len().await,
+                payload.clone(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::ingestors::http::app_error::AppError;
+    use crate::parsing::get_parser_from_name;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    async fn read_records(path: &Path, count: usize) -> Vec<serde_json::Value> {
+        // Written in the background
+        for _ in 0..200 {
+            let content = tokio::fs::read_to_string(path).await.unwrap_or_default();
+            let records = content
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect::<Vec<_>>();
+            if records.len() >= count {
+                return records;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("The audit records weren't written");
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        _ = load_configuration();
+        let directory = std::env::temp_dir().join(format!("sensapp-audit-{}", Uuid::new_v4()));
+        let audit_log = AuditLog::new(&directory, 16).await.unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(
+                br#"[{"bn":"room_","n":"temperature","v":21.5,"t":1700000000},
+                {"n":"room_humidity","v":40,"t":1700000000},
+                {"n":"room_humidity","v":41,"t":1700000001}]"#,
+            )
+            .unwrap();
+        let payload = Bytes::from(encoder.finish().unwrap());
+
+        let parser = get_parser_from_name("senml_json").unwrap();
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        parser
+            .parse_data(&payload, &mut batch_builder)
+            .await
+            .unwrap();
+        audit_log
+            .record(
+                "senml_json",
+                batch_builder.sensors_len().await,
+                batch_builder.len().await,
+                payload.clone(),
+            )
+            .await
+            .unwrap();
+        audit_log
+            .record("graphite", 0, 0, Bytes::from("second"))
+            .await
+            .unwrap();
+
+        let records = read_records(&directory.join(AUDIT_LOG_FILE_NAME), 2).await;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["parser"], "senml_json");
+        assert_eq!(records[0]["sensors"], 2);
+        assert_eq!(records[0]["samples"], 3);
+        assert_eq!(records[0]["payload_bytes"], payload.len());
+        // Stored as received, still compressed
+        assert_eq!(
+            STANDARD
+                .decode(records[0]["payload"].as_str().unwrap())
+                .unwrap(),
+            payload.to_vec()
+        );
+        assert!(records[0]["datetime"].as_str().unwrap().starts_with("20"));
+        assert_eq!(records[1]["parser"], "graphite");
+
+        // Appended, not truncated, when reopened
+        let audit_log = AuditLog::new(&directory, 16).await.unwrap();
+        audit_log
+            .record("graphite", 0, 0, Bytes::from("third"))
+            .await
+            .unwrap();
+        let records = read_records(&directory.join(AUDIT_LOG_FILE_NAME), 3).await;
+        assert_eq!(records.len(), 3);
+
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_full_queue() {
+        let directory = std::env::temp_dir().join(format!("sensapp-audit-{}", Uuid::new_v4()));
+        let audit_log = AuditLog::new(&directory, 1).await.unwrap();
+
+        // The requests wait for room in the queue, nothing is dropped.
+        let records = (0..5).map(|index| {
+            audit_log.record("graphite", 0, 0, Bytes::from(format!("payload {}", index)))
+        });
+        for result in futures::future::join_all(records).await {
+            result.unwrap();
+        }
+
+        let records = read_records(&directory.join(AUDIT_LOG_FILE_NAME), 5).await;
+        assert_eq!(records.len(), 5);
+
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_audit_log_write_error() {
+        let path = PathBuf::from("/dev/full");
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .await
+            .unwrap();
+        let audit_log = AuditLog::with_file(file, path, 16);
+
+        let error = audit_log
+            .record("graphite", 0, 0, Bytes::from("lost"))
+            .await
+            .unwrap_err();
+        assert!(error.is::<AuditError>());
+        assert!(matches!(
+            AppError::from(error),
+            AppError::ServiceUnavailable(_)
+        ));
+    }
+}
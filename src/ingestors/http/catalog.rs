@@ -0,0 +1,186 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::config;
+use crate::datamodel::Sensor;
+use crate::exporters::{labels_to_json, sorted_labels, ExportFormat};
+use axum::{
+    debug_handler,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde_json::{json, Value};
+
+/// Builds the DCAT catalog of the sensors, as JSON-LD.
+///
+/// Each sensor is a dataset, with a distribution per export format of its type.
+pub fn dcat_catalog(base_url: &str, title: &str, sensors: &[Sensor]) -> Value {
+    let datasets = sensors
+        .iter()
+        .map(|sensor| {
+            let export_url = format!("{}/sensors/{}/export", base_url, sensor.uuid);
+            let distributions = ExportFormat::ALL
+                .iter()
+                .filter(|format| format.supports_sensor_type(sensor.sensor_type))
+                .map(|format| {
+                    let url = format!("{}?format={}", export_url, format.name());
+                    json!({
+                        "@type": "dcat:Distribution",
+                        "dct:format": format.name(),
+                        "dcat:mediaType": format.content_type(),
+                        "dcat:accessURL": { "@id": url },
+                        "dcat:downloadURL": { "@id": url },
+                    })
+                })
+                .collect::<Vec<_>>();
+            let keywords = sorted_labels(sensor)
+                .into_iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>();
+            let labels = labels_to_json(sensor);
+
+            let mut dataset = json!({
+                "@id": format!("{}/sensors/{}", base_url, sensor.uuid),
+                "@type": "dcat:Dataset",
+                "dct:identifier": sensor.uuid.to_string(),
+                "dct:title": sensor.name,
+                "sensapp:type": sensor.sensor_type.to_string(),
+                "sensapp:labels": labels,
+                "dcat:keyword": keywords,
+                "dcat:distribution": distributions,
+            });
+            if let Some(unit) = &sensor.unit {
+                dataset["sensapp:unit"] = Value::String(unit.name.clone());
+            }
+            dataset
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "@context": {
+            "dcat": "http://www.w3.org/ns/dcat#",
+            "dct": "http://purl.org/dc/terms/",
+            "sensapp": "https://github.com/SINTEF/sensapp#",
+            "sensapp:labels": { "@type": "@json" },
+        },
+        "@id": format!("{}/catalog.jsonld", base_url),
+        "@type": "dcat:Catalog",
+        "dct:title": title,
+        "dcat:dataset": datasets,
+    })
+}
+
+/// The catalog of all the sensors, as a DCAT JSON-LD document.
+///
+/// The links use the `SENSAPP_PUBLIC_URL` base URL, and the sensors
+/// may be cached for `SENSAPP_CATALOG_CACHE_SECONDS`.
+#[utoipa::path(
+    get,
+    path = "/catalog.jsonld",
+    tag = "SensApp",
+    responses(
+        (status = 200, description = "DCAT catalog", content_type = "application/ld+json"),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn catalog(State(state): State<HttpServerState>) -> Result<Response, AppError> {
+    state.ensure_storage_available()?;
+    let config = config::get()?;
+
+    // All the sensors, without their samples
+    let sensors = state.catalog_cache.sensors(state.storage.as_ref()).await?;
+
+    let catalog = dcat_catalog(&config.public_url(), &state.name, &sensors);
+    Ok((
+        [(header::CONTENT_TYPE, "application/ld+json")],
+        catalog.to_string(),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{batch::Batch, batch::SingleSensorBatch, unit::Unit};
+    use crate::datamodel::{SensAppDateTime, SensorType, TypedSamples};
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_catalog() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensors = [
+            Arc::new(
+                Sensor::new_without_uuid(
+                    format!("test_catalog_{}", Uuid::new_v4()),
+                    SensorType::Float,
+                    Some(Unit::new("°C".to_string(), None)),
+                    Some(smallvec![("building".to_string(), "main".to_string())]),
+                )
+                .unwrap(),
+            ),
+            Arc::new(
+                Sensor::new_without_uuid(
+                    format!("test_catalog_{}", Uuid::new_v4()),
+                    SensorType::String,
+                    None,
+                    None,
+                )
+                .unwrap(),
+            ),
+        ];
+        let datetime = SensAppDateTime::from_unix_seconds(1_700_000_000.0);
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(sensors[0].clone(), TypedSamples::one_float(21.5, datetime)),
+            SingleSensorBatch::new(
+                sensors[1].clone(),
+                TypedSamples::one_string("on".to_string(), datetime)
+            ),
+        ]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let response = catalog(State(state)).await.unwrap();
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/ld+json"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let catalog: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(
+            catalog["@context"]["dcat"],
+            json!("http://www.w3.org/ns/dcat#")
+        );
+        assert_eq!(catalog["@type"], json!("dcat:Catalog"));
+        let datasets = catalog["dcat:dataset"].as_array().unwrap();
+        assert_eq!(datasets.len(), 2);
+        assert!(datasets
+            .iter()
+            .all(|dataset| dataset["@type"] == json!("dcat:Dataset")));
+
+        let dataset = datasets
+            .iter()
+            .find(|dataset| dataset["dct:title"] == json!(sensors[0].name))
+            .unwrap();
+        assert_eq!(dataset["sensapp:type"], json!("Float"));
+        assert_eq!(dataset["sensapp:unit"], json!("°C"));
+        assert_eq!(dataset["sensapp:labels"], json!({"building": "main"}));
+        assert_eq!(dataset["dcat:keyword"], json!(["building=main"]));
+        let distribution = &dataset["dcat:distribution"][0];
+        assert_eq!(distribution["dcat:mediaType"], json!("application/json"));
+        assert!(distribution["dcat:downloadURL"]["@id"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!("/sensors/{}/export?format=json", sensors[0].uuid)));
+    }
+}
@@ -0,0 +1,342 @@
+use crate::datamodel::{batch::Batch, Sensor};
+use crate::storage::{
+    query::{SensorSelector, TimeRange},
+    storage::StorageInstance,
+};
+use anyhow::Result;
+use std::{
+    collections::HashSet,
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// A cached listing, with the keys to tell the new sensors apart.
+#[derive(Debug)]
+struct CachedListing<T, K> {
+    loaded_at: Instant,
+    value: Arc<Vec<T>>,
+    keys: HashSet<K>,
+}
+
+#[derive(Debug)]
+struct Listings {
+    /// Incremented on every invalidation, so a listing loaded
+    /// during an invalidation isn't cached.
+    generation: u64,
+    sensor_names: Option<CachedListing<String, String>>,
+    sensors: Option<CachedListing<Sensor, Uuid>>,
+}
+
+/// A cache of the sensor listings, as the catalog changes slowly
+/// while the dashboards refresh it often.
+///
+/// The listings are kept for `SENSAPP_CATALOG_CACHE_SECONDS`, and dropped
+/// when new sensors are published or when sensors are deleted.
+/// The cache is disabled by default.
+#[derive(Debug)]
+pub struct CatalogCache {
+    ttl: Duration,
+    listings: Mutex<Listings>,
+}
+
+impl Default for CatalogCache {
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+impl CatalogCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            listings: Mutex::new(Listings {
+                generation: 0,
+                sensor_names: None,
+                sensors: None,
+            }),
+        }
+    }
+
+    pub fn from_config(config: &crate::config::SensAppConfig) -> Self {
+        Self::new(Duration::from_secs(config.catalog_cache_seconds))
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Listings> {
+        self.listings
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The names of the sensors, as listed by the storage.
+    pub async fn sensor_names(&self, storage: &dyn StorageInstance) -> Result<Arc<Vec<String>>> {
+        if !self.is_enabled() {
+            return Ok(Arc::new(storage.list_sensors().await?));
+        }
+        let generation = {
+            let listings = self.lock();
+            if let Some(cached) = fresh(&listings.sensor_names, self.ttl) {
+                return Ok(cached);
+            }
+            listings.generation
+        };
+        let sensor_names = storage.list_sensors().await?;
+        let keys = sensor_names.iter().cloned().collect();
+        Ok(self.store(generation, sensor_names, keys, |listings| {
+            &mut listings.sensor_names
+        }))
+    }
+
+    /// The metadata of all the sensors, without their samples.
+    pub async fn sensors(&self, storage: &dyn StorageInstance) -> Result<Arc<Vec<Sensor>>> {
+        let load = || async {
+            Ok::<_, anyhow::Error>(
+                storage
+                    .query(&SensorSelector::default(), TimeRange::default(), Some(0))
+                    .await?
+                    .into_iter()
+                    .map(|sensor_data| sensor_data.sensor)
+                    .collect::<Vec<_>>(),
+            )
+        };
+        if !self.is_enabled() {
+            return Ok(Arc::new(load().await?));
+        }
+        let generation = {
+            let listings = self.lock();
+            if let Some(cached) = fresh(&listings.sensors, self.ttl) {
+                return Ok(cached);
+            }
+            listings.generation
+        };
+        let sensors = load().await?;
+        let keys = sensors.iter().map(|sensor| sensor.uuid).collect();
+        Ok(self.store(generation, sensors, keys, |listings| &mut listings.sensors))
+    }
+
+    fn store<T, K>(
+        &self,
+        generation: u64,
+        value: Vec<T>,
+        keys: HashSet<K>,
+        slot: impl FnOnce(&mut Listings) -> &mut Option<CachedListing<T, K>>,
+    ) -> Arc<Vec<T>> {
+        let value = Arc::new(value);
+        let mut listings = self.lock();
+        if listings.generation == generation {
+            *slot(&mut listings) = Some(CachedListing {
+                loaded_at: Instant::now(),
+                value: value.clone(),
+                keys,
+            });
+        }
+        value
+    }
+
+    /// Drops the cached listings.
+    pub fn invalidate(&self) {
+        let mut listings = self.lock();
+        listings.generation += 1;
+        listings.sensor_names = None;
+        listings.sensors = None;
+    }
+
+    /// Drops the cached listings when the batch has sensors they don't know.
+    ///
+    /// Returns whether the listings were dropped.
+    pub fn invalidate_on_new_sensors(&self, batch: &Batch) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let listings = self.lock();
+        let new_sensors = batch.sensors.iter().any(|single_sensor_batch| {
+            let sensor = &single_sensor_batch.sensor;
+            !is_known(&listings.sensor_names, &sensor.name)
+                || !is_known(&listings.sensors, &sensor.uuid)
+        });
+        drop(listings);
+        if new_sensors {
+            self.invalidate();
+        }
+        new_sensors
+    }
+}
+
+fn fresh<T, K>(cached: &Option<CachedListing<T, K>>, ttl: Duration) -> Option<Arc<Vec<T>>> {
+    cached
+        .as_ref()
+        .filter(|cached| cached.loaded_at.elapsed() < ttl)
+        .map(|cached| cached.value.clone())
+}
+
+/// Whether the key is in the cached listing, true when nothing is cached.
+fn is_known<T, K: Eq + Hash>(cached: &Option<CachedListing<T, K>>, key: &K) -> bool {
+    cached
+        .as_ref()
+        .is_none_or(|cached| cached.keys.contains(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{
+        batch::SingleSensorBatch, sensapp_datetime::SensAppDateTimeExt, SensAppDateTime,
+        SensorData, SensorType, TypedSamples,
+    };
+    use async_trait::async_trait;
+    use smallvec::smallvec;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts the listing queries, on a catalog that tests can change.
+    #[derive(Debug, Default)]
+    struct CountingStorage {
+        sensors: Mutex<Vec<(Uuid, String)>>,
+        queries: AtomicUsize,
+    }
+
+    impl CountingStorage {
+        fn sensors(&self) -> Vec<Sensor> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            self.sensors
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(uuid, name)| Sensor::new(*uuid, name.clone(), SensorType::Float, None, None))
+                .collect()
+        }
+
+        fn queries(&self) -> usize {
+            self.queries.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl StorageInstance for CountingStorage {
+        async fn create_or_migrate(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn publish(
+            &self,
+            batch: Arc<Batch>,
+            _sync_sender: async_broadcast::Sender<()>,
+        ) -> Result<()> {
+            let mut sensors = self.sensors.lock().unwrap();
+            for single_sensor_batch in batch.sensors.iter() {
+                let sensor = &single_sensor_batch.sensor;
+                if !sensors.iter().any(|(uuid, _)| *uuid == sensor.uuid) {
+                    sensors.push((sensor.uuid, sensor.name.clone()));
+                }
+            }
+            Ok(())
+        }
+        async fn sync(&self, _sync_sender: async_broadcast::Sender<()>) -> Result<()> {
+            Ok(())
+        }
+        async fn vacuum(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn list_sensors(&self) -> Result<Vec<String>> {
+            Ok(self
+                .sensors()
+                .into_iter()
+                .map(|sensor| sensor.name)
+                .collect())
+        }
+        async fn query(
+            &self,
+            _selector: &SensorSelector,
+            _time_range: TimeRange,
+            _limit: Option<usize>,
+        ) -> Result<Vec<SensorData>> {
+            Ok(self
+                .sensors()
+                .into_iter()
+                .map(|sensor| SensorData::new(sensor, TypedSamples::Float(smallvec![])))
+                .collect())
+        }
+    }
+
+    fn batch(name: &str) -> Batch {
+        let sensor =
+            Sensor::new_without_uuid(name.to_string(), SensorType::Float, None, None).unwrap();
+        Batch::new(smallvec![SingleSensorBatch::new(
+            Arc::new(sensor),
+            TypedSamples::one_float(21.5, SensAppDateTime::from_unix_seconds_i64(1_700_000_000)),
+        )])
+    }
+
+    async fn publish(cache: &CatalogCache, storage: &CountingStorage, batch: Batch) {
+        let batch = Arc::new(batch);
+        cache.invalidate_on_new_sensors(&batch);
+        storage
+            .publish(batch.clone(), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        cache.invalidate_on_new_sensors(&batch);
+    }
+
+    #[tokio::test]
+    async fn test_catalog_cache() {
+        let storage = CountingStorage::default();
+        let cache = CatalogCache::new(Duration::from_secs(60));
+        publish(&cache, &storage, batch("temperature")).await;
+
+        // The second call is served from the cache
+        assert_eq!(
+            *cache.sensor_names(&storage).await.unwrap(),
+            ["temperature"]
+        );
+        assert_eq!(
+            *cache.sensor_names(&storage).await.unwrap(),
+            ["temperature"]
+        );
+        assert_eq!(storage.queries(), 1);
+        assert_eq!(cache.sensors(&storage).await.unwrap().len(), 1);
+        assert_eq!(cache.sensors(&storage).await.unwrap().len(), 1);
+        assert_eq!(storage.queries(), 2);
+
+        // The known sensors don't invalidate the cache
+        publish(&cache, &storage, batch("temperature")).await;
+        cache.sensor_names(&storage).await.unwrap();
+        cache.sensors(&storage).await.unwrap();
+        assert_eq!(storage.queries(), 2);
+
+        // A new sensor does
+        publish(&cache, &storage, batch("humidity")).await;
+        assert_eq!(
+            *cache.sensor_names(&storage).await.unwrap(),
+            ["temperature", "humidity"]
+        );
+        assert_eq!(cache.sensors(&storage).await.unwrap().len(), 2);
+        assert_eq!(storage.queries(), 4);
+
+        // And so does a deletion
+        cache.invalidate();
+        cache.sensor_names(&storage).await.unwrap();
+        assert_eq!(storage.queries(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_catalog_cache_ttl() {
+        let storage = CountingStorage::default();
+        let cache = CatalogCache::new(Duration::from_millis(50));
+        cache.sensor_names(&storage).await.unwrap();
+        cache.sensor_names(&storage).await.unwrap();
+        assert_eq!(storage.queries(), 1);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        cache.sensor_names(&storage).await.unwrap();
+        assert_eq!(storage.queries(), 2);
+
+        // Disabled by default
+        let cache = CatalogCache::default();
+        cache.sensor_names(&storage).await.unwrap();
+        cache.sensor_names(&storage).await.unwrap();
+        assert_eq!(storage.queries(), 4);
+        assert!(!cache.invalidate_on_new_sensors(&batch("temperature")));
+    }
+}
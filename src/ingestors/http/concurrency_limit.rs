@@ -0,0 +1,254 @@
+use super::app_error::AppError;
+use anyhow::anyhow;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits the number of requests handled at the same time.
+///
+/// Above the limit, up to `max_queued` requests wait for their turn
+/// and the others are rejected with a 503 Service Unavailable.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    max_concurrent: usize,
+    max_queued: usize,
+    semaphore: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+/// The requests of a concurrency limiter, as reported by `/health`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ConcurrencyStatus {
+    pub in_flight: usize,
+    pub queued: usize,
+    pub max_concurrent: usize,
+    pub max_queued: usize,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max_concurrent: usize, max_queued: usize) -> Self {
+        Self {
+            max_concurrent,
+            max_queued,
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes a slot, waiting in the queue when they are all taken.
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, AppError> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        let queued = self
+            .queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                (queued < self.max_queued).then_some(queued + 1)
+            });
+        if queued.is_err() {
+            return Err(AppError::ServiceUnavailable(anyhow!(
+                "Too many concurrent requests, try again later"
+            )));
+        }
+        // Leaves the queue even when the client goes away while waiting
+        let _queued = Queued(&self.queued);
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|error| AppError::InternalServerError(error.into()))
+    }
+
+    pub fn status(&self) -> ConcurrencyStatus {
+        ConcurrencyStatus {
+            in_flight: self.max_concurrent - self.semaphore.available_permits(),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_concurrent: self.max_concurrent,
+            max_queued: self.max_queued,
+        }
+    }
+}
+
+/// A place in the queue, left when dropped.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The concurrency limits of the ingestion and of the queries,
+/// separate so a burst of exports doesn't block the ingestion.
+#[derive(Debug, Default)]
+pub struct ConcurrencyLimits {
+    pub ingestion: Option<Arc<ConcurrencyLimiter>>,
+    pub queries: Option<Arc<ConcurrencyLimiter>>,
+}
+
+impl ConcurrencyLimits {
+    pub fn from_config(config: &crate::config::SensAppConfig) -> Self {
+        let limiter = |max_concurrent: usize, max_queued: usize| {
+            (max_concurrent > 0)
+                .then(|| Arc::new(ConcurrencyLimiter::new(max_concurrent, max_queued)))
+        };
+        Self {
+            ingestion: limiter(
+                config.max_concurrent_ingestions,
+                config.max_queued_ingestions,
+            ),
+            queries: limiter(config.max_concurrent_queries, config.max_queued_queries),
+        }
+    }
+}
+
+/// Middleware holding a slot of the concurrency limiter
+/// while the request is handled.
+pub async fn limit_concurrency(
+    State(limiter): State<Option<Arc<ConcurrencyLimiter>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let _permit = match limiter {
+        Some(limiter) => match limiter.acquire().await {
+            Ok(permit) => Some(permit),
+            Err(error) => return error.into_response(),
+        },
+        None => None,
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::SensorData;
+    use crate::ingestors::http::query::query_sensors;
+    use crate::ingestors::http::state::HttpServerState;
+    use crate::storage::query::{SensorSelector, TimeRange};
+    use crate::storage::storage::StorageInstance;
+    use async_trait::async_trait;
+    use axum::{
+        body::Body,
+        http::{header, StatusCode},
+        middleware,
+        routing::post,
+        Router,
+    };
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// A storage whose queries wait for a permit,
+    /// and keep track of how many run at the same time.
+    #[derive(Debug)]
+    struct SlowQueryStorage {
+        permits: Semaphore,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StorageInstance for SlowQueryStorage {
+        async fn create_or_migrate(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn publish(
+            &self,
+            _batch: Arc<crate::datamodel::batch::Batch>,
+            _sync_sender: async_broadcast::Sender<()>,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn sync(&self, _sync_sender: async_broadcast::Sender<()>) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn vacuum(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn list_sensors(&self) -> anyhow::Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+        async fn query(
+            &self,
+            _selector: &SensorSelector,
+            _time_range: TimeRange,
+            _limit: Option<usize>,
+        ) -> anyhow::Result<Vec<SensorData>> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            self.permits.acquire().await?.forget();
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limit_concurrency() {
+        let storage = Arc::new(SlowQueryStorage {
+            permits: Semaphore::new(0),
+            running: AtomicUsize::new(0),
+            max_running: AtomicUsize::new(0),
+        });
+        let limiter = Arc::new(ConcurrencyLimiter::new(2, 3));
+        let state = HttpServerState {
+            concurrency: Arc::new(ConcurrencyLimits {
+                ingestion: None,
+                queries: Some(limiter.clone()),
+            }),
+            ..HttpServerState::for_tests(storage.clone())
+        };
+        let app = Router::new()
+            .route("/query", post(query_sensors))
+            .route_layer(middleware::from_fn_with_state(
+                Some(limiter.clone()),
+                limit_concurrency,
+            ))
+            .with_state(state);
+
+        let request = || {
+            axum::http::Request::builder()
+                .method("POST")
+                .uri("/query")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    r#"{"matchers": [{"name": "room", "value": "kitchen", "type": "="}]}"#,
+                ))
+                .unwrap()
+        };
+
+        // 2 running, 3 queued, and the others are rejected
+        let requests = (0..8)
+            .map(|_| tokio::spawn(app.clone().oneshot(request())))
+            .collect::<Vec<_>>();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            limiter.status(),
+            ConcurrencyStatus {
+                in_flight: 2,
+                queued: 3,
+                max_concurrent: 2,
+                max_queued: 3,
+            }
+        );
+
+        storage.permits.add_permits(8);
+        let mut statuses = Vec::new();
+        for request in requests {
+            statuses.push(request.await.unwrap().unwrap().status());
+        }
+        let count = |status| statuses.iter().filter(|s| **s == status).count();
+        assert_eq!(count(StatusCode::SERVICE_UNAVAILABLE), 3, "{:?}", statuses);
+        assert_eq!(count(StatusCode::OK), 5, "{:?}", statuses);
+        assert_eq!(storage.max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(limiter.status().in_flight, 0);
+        assert_eq!(limiter.status().queued, 0);
+    }
+}
@@ -1,20 +1,286 @@
+use crate::datamodel::SensAppDateTime;
+use crate::exporters::json::sensor_to_json;
 use crate::ingestors::http::app_error::AppError;
 use crate::ingestors::http::state::HttpServerState;
-use axum::extract::State;
+use crate::storage::query::{SensorSelector, TimeRange};
+use crate::storage::rrdcached::parse_duration_seconds;
+use anyhow::anyhow;
+use axum::extract::{Path, Query, State};
 use axum::Json;
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListSensorsParams {
+    /// Only the sensors created after this unix time, in seconds.
+    pub created_after: Option<f64>,
+    /// Only the sensors not seen for this duration, like `5m` or `PT1H`.
+    pub stale_after: Option<String>,
+}
 
 /// List all the sensors.
+///
+/// With `created_after`, only the sensors first stored after this time
+/// are listed, for the clients following the new sensors.
+/// With `stale_after`, only the sensors without any publish during
+/// this duration are listed, to find the silent devices.
+///
+/// Without filters, the list may be cached for `SENSAPP_CATALOG_CACHE_SECONDS`.
 #[utoipa::path(
     get,
     path = "/sensors",
     tag = "SensApp",
+    params(
+        ("created_after" = Option<f64>, Query, description = "Only the sensors created after this unix time, in seconds"),
+        ("stale_after" = Option<String>, Query, description = "Only the sensors not seen for this duration, like 5m"),
+    ),
     responses(
-        (status = 200, description = "List of sensors", body = Vec<String>)
+        (status = 200, description = "List of sensors", body = Vec<String>),
+        (status = 400, description = "Bad Request", body = AppError),
     )
 )]
 pub async fn list_sensors(
     State(state): State<HttpServerState>,
+    Query(ListSensorsParams {
+        created_after,
+        stale_after,
+    }): Query<ListSensorsParams>,
 ) -> Result<Json<Vec<String>>, AppError> {
-    let sensors = state.storage.list_sensors().await?;
+    let last_seen_before = match stale_after {
+        Some(stale_after) => {
+            let stale_after = parse_duration_seconds(&stale_after).map_err(AppError::BadRequest)?;
+            let now = SensAppDateTime::now()?;
+            Some(now.to_unix_seconds() - stale_after as f64)
+        }
+        None => None,
+    };
+    if created_after.is_none() && last_seen_before.is_none() {
+        let sensor_names = state
+            .catalog_cache
+            .sensor_names(state.storage.as_ref())
+            .await?;
+        return Ok(Json(sensor_names.to_vec()));
+    }
+    let selector = SensorSelector {
+        created_after,
+        last_seen_before,
+        ..Default::default()
+    };
+    // Only the metadata of the sensors is needed, not their samples.
+    let sensors = state
+        .storage
+        .query(&selector, TimeRange::default(), Some(0))
+        .await?
+        .into_iter()
+        .map(|sensor_data| sensor_data.sensor.name)
+        .collect();
     Ok(Json(sensors))
 }
+
+/// Get the metadata of a sensor, without its samples.
+///
+/// Returns the UUID, name, type, unit, and labels of the sensor,
+/// for browsing the catalog without loading the samples.
+#[utoipa::path(
+    get,
+    path = "/sensors/{sensor_uuid}",
+    tag = "SensApp",
+    params(
+        ("sensor_uuid" = String, Path, description = "UUID of the sensor"),
+    ),
+    responses(
+        (status = 200, description = "Metadata of the sensor", content_type = "application/json"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 404, description = "Sensor Not Found", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+pub async fn get_sensor(
+    State(state): State<HttpServerState>,
+    Path(sensor_uuid): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let sensor_uuid = Uuid::parse_str(&sensor_uuid)
+        .map_err(|_| AppError::BadRequest(anyhow!("Invalid sensor UUID: {}", sensor_uuid)))?;
+    let sensor = state
+        .storage
+        .get_sensor(sensor_uuid)
+        .await?
+        .ok_or_else(|| AppError::NotFound(anyhow!("Sensor not found: {}", sensor_uuid)))?;
+    Ok(Json(sensor_to_json(&sensor)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        unit::Unit,
+        SensAppDateTime, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use async_trait::async_trait;
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    fn kitchen_temperature(uuid: Uuid) -> Sensor {
+        Sensor::new(
+            uuid,
+            "temperature".to_string(),
+            SensorType::Float,
+            Some(Unit::new("Cel".to_string(), None)),
+            Some(smallvec![("room".to_string(), "kitchen".to_string())]),
+        )
+    }
+
+    /// Knows a single sensor, and has no samples to query.
+    #[derive(Debug)]
+    struct MetadataOnlyStorage {
+        sensor_uuid: Uuid,
+    }
+
+    #[async_trait]
+    impl StorageInstance for MetadataOnlyStorage {
+        async fn create_or_migrate(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn publish(
+            &self,
+            _batch: Arc<Batch>,
+            _sync_sender: async_broadcast::Sender<()>,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn sync(&self, _sync_sender: async_broadcast::Sender<()>) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn vacuum(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn list_sensors(&self) -> anyhow::Result<Vec<String>> {
+            Ok(vec![])
+        }
+        async fn get_sensor(&self, sensor_uuid: Uuid) -> anyhow::Result<Option<Sensor>> {
+            Ok((sensor_uuid == self.sensor_uuid).then(|| kitchen_temperature(sensor_uuid)))
+        }
+        // The sample queries are left to the default implementations, failing.
+    }
+
+    #[tokio::test]
+    async fn test_get_sensor() {
+        _ = load_configuration();
+        let sensor_uuid = Uuid::new_v4();
+        let state = HttpServerState::for_tests(Arc::new(MetadataOnlyStorage { sensor_uuid }));
+
+        let Json(response) = get_sensor(State(state.clone()), Path(sensor_uuid.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "uuid": sensor_uuid.to_string(),
+                "name": "temperature",
+                "type": "Float",
+                "unit": "Cel",
+                "labels": {"room": "kitchen"},
+            })
+        );
+
+        let error = get_sensor(State(state.clone()), Path(Uuid::new_v4().to_string()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::NotFound(_)));
+        let error = get_sensor(State(state), Path("not-a-uuid".to_string()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn test_created_at() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let before = SensAppDateTime::now().unwrap();
+        let sensor = Arc::new(kitchen_temperature(Uuid::new_v4()));
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            TypedSamples::one_float(21.5, before),
+        )]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        let state = HttpServerState::for_tests(Arc::new(storage));
+
+        let Json(response) = get_sensor(State(state.clone()), Path(sensor.uuid.to_string()))
+            .await
+            .unwrap();
+        let created_at = state
+            .storage
+            .get_sensor(sensor.uuid)
+            .await
+            .unwrap()
+            .unwrap()
+            .created_at
+            .unwrap();
+        assert_eq!(response["created_at"], created_at.to_rfc3339());
+        // Stored in milliseconds
+        assert!(created_at.to_unix_seconds() > before.to_unix_seconds() - 0.001);
+
+        let list = |created_after: f64| {
+            list_sensors(
+                State(state.clone()),
+                Query(ListSensorsParams {
+                    created_after: Some(created_after),
+                    ..Default::default()
+                }),
+            )
+        };
+        let Json(sensors) = list(before.to_unix_seconds() - 1.0).await.unwrap();
+        assert_eq!(sensors, vec!["temperature".to_string()]);
+        let Json(sensors) = list(before.to_unix_seconds() + 3600.0).await.unwrap();
+        assert!(sensors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stale_after() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let publish = |name: &str| {
+            let sensor = Arc::new(
+                Sensor::new_without_uuid(name.to_string(), SensorType::Float, None, None).unwrap(),
+            );
+            let batch = Batch::new(smallvec![SingleSensorBatch::new(
+                sensor,
+                TypedSamples::one_float(21.5, SensAppDateTime::now().unwrap()),
+            )]);
+            state
+                .storage
+                .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+        };
+        publish("quiet").await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        publish("active").await.unwrap();
+
+        let list = |stale_after: &str| {
+            list_sensors(
+                State(state.clone()),
+                Query(ListSensorsParams {
+                    stale_after: Some(stale_after.to_string()),
+                    ..Default::default()
+                }),
+            )
+        };
+        let Json(sensors) = list("1s").await.unwrap();
+        assert_eq!(sensors, vec!["quiet".to_string()]);
+        let Json(sensors) = list("1h").await.unwrap();
+        assert!(sensors.is_empty());
+        let error = list("soon").await.unwrap_err();
+        assert!(matches!(error, AppError::BadRequest(_)));
+    }
+}
@@ -0,0 +1,1298 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::{SensAppDateTime, SensorData};
+use crate::exporters::{
+    csv::to_csv_with_headers,
+    geojson::{to_geojson, to_geojson_linestring, to_geojsonl, GeoJsonMode, GEOJSON_CONTENT_TYPE},
+    json::to_columnar_json,
+    parse_label_columns, ExportFormat, NotAcceptableError,
+};
+use crate::storage::query::{SensorSelector, SortOrder};
+use crate::storage::rrdcached::parse_duration_seconds;
+use anyhow::{anyhow, Result};
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQueryParams {
+    pub format: Option<String>,
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub limit: Option<usize>,
+    /// `geojson` exports a location sensor as a GeoJSON trajectory.
+    #[serde(rename = "as")]
+    pub export_as: Option<String>,
+    /// `columnar` returns the JSON samples as parallel arrays.
+    pub layout: Option<String>,
+    /// Comma separated labels to promote to their own columns.
+    pub labels: Option<String>,
+    /// Header of the CSV datetime column.
+    pub col_timestamp: Option<String>,
+    /// Header of the CSV value column.
+    pub col_value: Option<String>,
+    /// Duration of the time range, such as `1h`, instead of `start` and `end`.
+    pub window: Option<String>,
+    /// `now` by default, or `latest` for the window before the latest sample.
+    pub relative_to: Option<String>,
+    /// `time` by default, or `value` for the samples sorted by value.
+    pub sort_by: Option<String>,
+    /// `desc` by default, or `asc`, for the samples sorted by value.
+    pub order: Option<String>,
+    /// `points` by default, or `linestring`, for the GeoJSON formats.
+    pub geojson_mode: Option<String>,
+}
+
+/// Computes a strong ETag for an export.
+///
+/// Historical data doesn't change, so the query parameters,
+/// the number of samples, and the last datetime are enough
+/// to identify the content without hashing it. The bounds of
+/// a relative window are the resolved ones, as the window moves.
+fn compute_etag(
+    sensor_data: &SensorData,
+    format_name: &str,
+    start: Option<f64>,
+    end: Option<f64>,
+    limit: Option<usize>,
+) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(sensor_data.sensor.uuid.as_bytes());
+    hasher.update(format_name.as_bytes());
+    for bound in [start, end] {
+        match bound {
+            Some(bound) => hasher.update(&bound.to_le_bytes()),
+            None => hasher.update(b"-"),
+        };
+    }
+    hasher.update(&(limit.unwrap_or(usize::MAX) as u64).to_le_bytes());
+    hasher.update(&(sensor_data.samples.len() as u64).to_le_bytes());
+    if let Some(last_datetime) = sensor_data.samples.last_datetime() {
+        hasher.update(&last_datetime.to_unix_milliseconds().to_le_bytes());
+    }
+    let hash = hasher.finalize();
+    format!("\"{}\"", hex::encode(&hash.as_bytes()[..16]))
+}
+
+/// Checks whether the `If-None-Match` header matches the given ETag.
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim())
+        .any(|value| value == "*" || value.trim_start_matches("W/") == etag)
+}
+
+/// Export the samples of a sensor.
+///
+/// The format is selected using the `Accept` header,
+/// or the `format` query parameter that has the priority.
+/// JSON is used by default.
+///
+/// The response has an ETag, and a request with a matching `If-None-Match`
+/// header gets a 304 Not Modified response without body.
+///
+/// An unknown sensor is a 404 Not Found, while a known sensor without
+/// samples in the time range is a 200 OK with the sensor and no samples.
+///
+/// With `as=geojson`, a location sensor is exported as a GeoJSON `Feature`
+/// with a `LineString` geometry, and the timestamps in its properties.
+///
+/// With `layout=columnar`, the JSON export has parallel `timestamps`
+/// and `values` arrays instead of one object per sample.
+///
+/// With `labels=env,region`, the CSV, JSONL and Arrow exports have
+/// an `env` and a `region` column, empty when the sensor doesn't have the label.
+///
+/// With `col_timestamp` and `col_value`, the CSV export has custom headers
+/// instead of `datetime` and `value`.
+///
+/// With `window=1h`, the time range is the last hour before now. With
+/// `relative_to=latest` too, it's the last hour before the latest sample
+/// of the sensor, inclusive, so a sensor that stopped reporting days ago
+/// still has the last hour of its data.
+///
+/// With `sort_by=value&order=desc&limit=3`, the samples are the three
+/// highest of the time range, sorted by value. Only the integer, numeric
+/// and float sensors can be sorted by value.
+///
+/// With `format=geojson`, a location sensor is exported as a GeoJSON
+/// `FeatureCollection` with a `Point` feature per sample, and with
+/// `format=geojsonl` as one feature per line. `geojson_mode=linestring`
+/// exports a single trajectory feature instead.
+#[utoipa::path(
+    get,
+    path = "/sensors/{sensor_uuid}/export",
+    tag = "SensApp",
+    params(
+        ("sensor_uuid" = String, Path, description = "UUID of the sensor"),
+        ("format" = Option<String>, Query, description = "Export format, overrides the Accept header. One of json, csv, jsonl, senml, arrow, grafana, geojson, geojsonl"),
+        ("start" = Option<f64>, Query, description = "Start of the time range, in unix seconds, inclusive"),
+        ("end" = Option<f64>, Query, description = "End of the time range, in unix seconds, exclusive"),
+        ("limit" = Option<usize>, Query, description = "Maximum number of samples"),
+        ("as" = Option<String>, Query, description = "geojson to export a location sensor as a GeoJSON LineString"),
+        ("layout" = Option<String>, Query, description = "row by default, or columnar for the JSON format"),
+        ("labels" = Option<String>, Query, description = "Comma separated labels to export as columns, for the csv, jsonl and arrow formats"),
+        ("col_timestamp" = Option<String>, Query, description = "Header of the datetime column, for the csv format"),
+        ("col_value" = Option<String>, Query, description = "Header of the value column, for the csv format"),
+        ("window" = Option<String>, Query, description = "Duration of the time range instead of start and end, such as 90s, 5m, 1h, 7d or PT1H"),
+        ("relative_to" = Option<String>, Query, description = "now by default, or latest for the window before the latest sample"),
+        ("sort_by" = Option<String>, Query, description = "time by default, or value to sort the samples by value"),
+        ("order" = Option<String>, Query, description = "desc by default, or asc, when sorting by value"),
+        ("geojson_mode" = Option<String>, Query, description = "points by default, or linestring, for the geojson and geojsonl formats"),
+    ),
+    responses(
+        (status = 200, description = "Samples in the negotiated format, possibly none"),
+        (status = 304, description = "Not Modified"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 404, description = "Sensor Not Found", body = AppError),
+        (status = 406, description = "None of the accepted media types can be exported", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn export_sensor(
+    State(state): State<HttpServerState>,
+    Path(sensor_uuid): Path<String>,
+    Query(ExportQueryParams {
+        format,
+        start,
+        end,
+        limit,
+        export_as,
+        layout,
+        labels,
+        col_timestamp,
+        col_value,
+        window,
+        relative_to,
+        sort_by,
+        order,
+        geojson_mode,
+    }): Query<ExportQueryParams>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let sensor_uuid = Uuid::parse_str(&sensor_uuid)
+        .map_err(|_| AppError::BadRequest(anyhow!("Invalid sensor UUID: {}", sensor_uuid)))?;
+
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok());
+    let export_format = ExportFormat::negotiate(format.as_deref(), accept).map_err(|error| {
+        if error.is::<NotAcceptableError>() {
+            AppError::NotAcceptable(error)
+        } else {
+            AppError::BadRequest(error)
+        }
+    })?;
+    let columnar = match layout.as_deref() {
+        None | Some("row") => false,
+        Some("columnar") if export_format == ExportFormat::Json && export_as.is_none() => true,
+        Some("columnar") => {
+            return Err(AppError::BadRequest(anyhow!(
+                "The columnar layout is only available for the JSON format"
+            )))
+        }
+        Some(layout) => {
+            return Err(AppError::BadRequest(anyhow!("Unknown layout: {}", layout)));
+        }
+    };
+    let label_columns = match labels.as_deref() {
+        Some(labels) => parse_label_columns(labels).map_err(AppError::BadRequest)?,
+        None => Vec::new(),
+    };
+    if !label_columns.is_empty() && (!export_format.supports_label_columns() || export_as.is_some())
+    {
+        return Err(AppError::BadRequest(anyhow!(
+            "The label columns are only available for the csv, jsonl and arrow formats"
+        )));
+    }
+
+    let csv_headers = match (col_timestamp, col_value) {
+        (None, None) => None,
+        _ if export_format != ExportFormat::Csv || export_as.is_some() => {
+            return Err(AppError::BadRequest(anyhow!(
+                "The column headers are only available for the csv format"
+            )));
+        }
+        (col_timestamp, col_value) => Some((
+            col_timestamp.unwrap_or_else(|| "datetime".to_string()),
+            col_value.unwrap_or_else(|| "value".to_string()),
+        )),
+    };
+
+    let geojson = matches!(
+        export_format,
+        ExportFormat::GeoJson | ExportFormat::GeoJsonl
+    ) && export_as.is_none();
+    let geojson_mode = match geojson_mode.as_deref() {
+        None => GeoJsonMode::default(),
+        Some(mode) if geojson => GeoJsonMode::from_str(mode).map_err(AppError::BadRequest)?,
+        Some(_) => {
+            return Err(AppError::BadRequest(anyhow!(
+                "The GeoJSON mode is only available for the geojson and geojsonl formats"
+            )))
+        }
+    };
+
+    let sort_order = match (sort_by.as_deref(), order.as_deref()) {
+        (None | Some("time"), None) => None,
+        (None | Some("time"), Some(_)) => {
+            return Err(AppError::BadRequest(anyhow!(
+                "The order requires sort_by=value"
+            )))
+        }
+        (Some("value"), _) if window.is_some() => {
+            return Err(AppError::BadRequest(anyhow!(
+                "Sorting by value can't be combined with a window"
+            )))
+        }
+        (Some("value"), order) => Some(
+            order
+                .map(SortOrder::from_str)
+                .transpose()
+                .map_err(AppError::BadRequest)?
+                .unwrap_or(SortOrder::Desc),
+        ),
+        (Some(sort_by), _) => {
+            return Err(AppError::BadRequest(anyhow!(
+                "Unknown sort_by: {}, use time or value",
+                sort_by
+            )))
+        }
+    };
+
+    // The time range of the ETag, resolved when the window is relative
+    let mut etag_range = (start, end);
+    let sensor_data = match (window.as_deref(), relative_to.as_deref()) {
+        (None, None) if sort_order.is_some() => {
+            let sensor = state
+                .storage
+                .get_sensor(sensor_uuid)
+                .await?
+                .ok_or_else(|| AppError::NotFound(anyhow!("Sensor not found: {}", sensor_uuid)))?;
+            if !SensorSelector::is_numeric(sensor.sensor_type) {
+                return Err(AppError::BadRequest(anyhow!(
+                    "Only the integer, numeric and float sensors can be sorted by value"
+                )));
+            }
+            state
+                .storage
+                .query_sensor_data_by_value(
+                    sensor_uuid,
+                    start.map(SensAppDateTime::from_unix_seconds),
+                    end.map(SensAppDateTime::from_unix_seconds),
+                    sort_order.unwrap_or(SortOrder::Desc),
+                    limit,
+                )
+                .await?
+        }
+        (None, None) => {
+            state
+                .storage
+                .query_sensor_data(
+                    sensor_uuid,
+                    start.map(SensAppDateTime::from_unix_seconds),
+                    end.map(SensAppDateTime::from_unix_seconds),
+                    limit,
+                )
+                .await?
+        }
+        (None, Some(_)) => {
+            return Err(AppError::BadRequest(anyhow!(
+                "relative_to requires a window"
+            )))
+        }
+        (Some(_), _) if start.is_some() || end.is_some() => {
+            return Err(AppError::BadRequest(anyhow!(
+                "The window can't be combined with start or end"
+            )))
+        }
+        (Some(window), relative_to) => {
+            let window = parse_duration_seconds(window).map_err(AppError::BadRequest)?;
+            let window = hifitime::Duration::from_seconds(window as f64);
+            match relative_to {
+                None | Some("now") => {
+                    let now = SensAppDateTime::now()
+                        .map_err(|error| AppError::InternalServerError(anyhow!(error)))?;
+                    etag_range = (Some((now - window).to_unix_seconds()), None);
+                    state
+                        .storage
+                        .query_sensor_data(sensor_uuid, Some(now - window), None, limit)
+                        .await?
+                }
+                Some("latest") => {
+                    let sensor_data = state
+                        .storage
+                        .query_sensor_data_window(sensor_uuid, window, limit)
+                        .await?;
+                    etag_range = sensor_data
+                        .as_ref()
+                        .and_then(|sensor_data| sensor_data.samples.last_datetime())
+                        .map(|latest| {
+                            (
+                                Some((latest - window).to_unix_seconds()),
+                                Some(latest.to_unix_seconds()),
+                            )
+                        })
+                        .unwrap_or_default();
+                    sensor_data
+                }
+                Some(relative_to) => {
+                    return Err(AppError::BadRequest(anyhow!(
+                        "Unknown relative_to: {}, use now or latest",
+                        relative_to
+                    )))
+                }
+            }
+        }
+    }
+    .ok_or_else(|| AppError::NotFound(anyhow!("Sensor not found: {}", sensor_uuid)))?;
+    if !export_format.supports_sensor_type(sensor_data.sensor.sensor_type) && export_as.is_none() {
+        return Err(AppError::BadRequest(anyhow!(
+            "Only location sensors can be exported as {}, {} is a {} sensor",
+            export_format.name(),
+            sensor_data.sensor.name,
+            sensor_data.sensor.sensor_type.to_string()
+        )));
+    }
+
+    let (format_name, content_type) = match export_as.as_deref() {
+        None if columnar => ("json-columnar", export_format.content_type()),
+        None => (export_format.name(), export_format.content_type()),
+        Some("geojson") => ("geojson", GEOJSON_CONTENT_TYPE),
+        Some(export_as) => {
+            return Err(AppError::BadRequest(anyhow!(
+                "Unknown export as: {}",
+                export_as
+            )))
+        }
+    };
+
+    let mut format_name = match label_columns.is_empty() {
+        true => format_name.to_string(),
+        false => format!("{}+labels={}", format_name, label_columns.join(",")),
+    };
+    if let Some(sort_order) = sort_order {
+        format_name = format!("{}+sort=value-{:?}", format_name, sort_order);
+    }
+    if geojson {
+        format_name = format!("{}+mode={}", format_name, geojson_mode.name());
+    }
+    if let Some((datetime_header, value_header)) = &csv_headers {
+        format_name = format!(
+            "{}+headers={},{}",
+            format_name, datetime_header, value_header
+        );
+    }
+    let etag = compute_etag(
+        &sensor_data,
+        &format_name,
+        etag_range.0,
+        etag_range.1,
+        limit,
+    );
+    if if_none_match(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    let body = match export_as {
+        Some(_) => to_geojson_linestring(&sensor_data).map_err(AppError::BadRequest)?,
+        None if columnar => to_columnar_json(&sensor_data)?,
+        None if export_format == ExportFormat::GeoJson => to_geojson(&sensor_data, geojson_mode)?,
+        None if export_format == ExportFormat::GeoJsonl => to_geojsonl(&sensor_data, geojson_mode)?,
+        None => match &csv_headers {
+            Some((datetime_header, value_header)) => {
+                to_csv_with_headers(&sensor_data, &label_columns, datetime_header, value_header)?
+            }
+            None => export_format.export(&sensor_data, &label_columns)?,
+        },
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use axum::http::HeaderValue;
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    async fn export(
+        state: &HttpServerState,
+        sensor: &Sensor,
+        if_none_match: Option<&str>,
+    ) -> Response {
+        let mut headers = HeaderMap::new();
+        if let Some(if_none_match) = if_none_match {
+            headers.insert(
+                header::IF_NONE_MATCH,
+                HeaderValue::from_str(if_none_match).unwrap(),
+            );
+        }
+        export_sensor(
+            State(state.clone()),
+            Path(sensor.uuid.to_string()),
+            Query(ExportQueryParams {
+                format: None,
+                start: Some(1_700_000_000.0),
+                end: Some(1_700_000_003.0),
+                limit: None,
+                export_as: None,
+                layout: None,
+                labels: None,
+                col_timestamp: None,
+                col_value: None,
+                window: None,
+                relative_to: None,
+                sort_by: None,
+                order: None,
+                geojson_mode: None,
+            }),
+            headers,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_etag() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_export_etag_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Integer(
+            (0..5)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                    value: i,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+
+        let response = export(&state, &sensor, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = export(&state, &sensor, Some(&etag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+
+        let response = export(&state, &sensor, Some("\"something else\"")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_export_unknown_and_empty() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        // The only sample is before the exported time range
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_export_empty_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Integer(smallvec![Sample {
+            datetime: SensAppDateTime::from_unix_seconds_i64(1_600_000_000),
+            value: 1,
+        }]);
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+
+        let response = export(&state, &sensor, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["sensor"]["uuid"], sensor.uuid.to_string());
+        assert_eq!(body["sensor"]["name"], sensor.name);
+        assert_eq!(body["samples"], serde_json::json!([]));
+
+        let result = export_sensor(
+            State(state),
+            Path(Uuid::new_v4().to_string()),
+            Query(ExportQueryParams {
+                format: None,
+                start: None,
+                end: None,
+                limit: None,
+                export_as: None,
+                layout: None,
+                labels: None,
+                col_timestamp: None,
+                col_value: None,
+                window: None,
+                relative_to: None,
+                sort_by: None,
+                order: None,
+                geojson_mode: None,
+            }),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_export_not_acceptable() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+        let state = HttpServerState::for_tests(Arc::new(storage));
+
+        let export_with_accept = |accept: &'static str, format: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+            export_sensor(
+                State(state.clone()),
+                Path(Uuid::new_v4().to_string()),
+                Query(ExportQueryParams {
+                    format: format.map(str::to_string),
+                    start: None,
+                    end: None,
+                    limit: None,
+                    export_as: None,
+                    layout: None,
+                    labels: None,
+                    col_timestamp: None,
+                    col_value: None,
+                    window: None,
+                    relative_to: None,
+                    sort_by: None,
+                    order: None,
+                    geojson_mode: None,
+                }),
+                headers,
+            )
+        };
+
+        let response = export_with_accept("application/xml", None)
+            .await
+            .unwrap_err()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+
+        // The format parameter overrides the Accept header,
+        // and the unknown sensor isn't found
+        let response = export_with_accept("application/xml", Some("csv"))
+            .await
+            .unwrap_err()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = export_with_accept("*/*", None)
+            .await
+            .unwrap_err()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_export_window_relative_to_latest() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        // A sample every 10 minutes, for 3 hours, that ended 5 days ago
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_export_window_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let now = SensAppDateTime::now().unwrap().to_unix_seconds().floor() as i64;
+        let last = now - 5 * 86_400;
+        let samples = TypedSamples::Integer(
+            (0..=18)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(last - 3 * 3600 + i * 600),
+                    value: i,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let export_window = |window: &str, relative_to: Option<&str>, start: Option<f64>| {
+            export_sensor(
+                State(state.clone()),
+                Path(sensor.uuid.to_string()),
+                Query(ExportQueryParams {
+                    format: None,
+                    start,
+                    end: None,
+                    limit: None,
+                    export_as: None,
+                    layout: None,
+                    labels: None,
+                    col_timestamp: None,
+                    col_value: None,
+                    window: Some(window.to_string()),
+                    relative_to: relative_to.map(str::to_string),
+                    sort_by: None,
+                    order: None,
+                    geojson_mode: None,
+                }),
+                HeaderMap::new(),
+            )
+        };
+        let values = |response: Response| async {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            body["samples"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|sample| sample["value"].as_i64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // The last hour of the data, both ends included
+        let response = export_window("1h", Some("latest"), None).await.unwrap();
+        assert_eq!(values(response).await, vec![12, 13, 14, 15, 16, 17, 18]);
+        let response = export_window("PT20M", Some("latest"), None).await.unwrap();
+        assert_eq!(values(response).await, vec![16, 17, 18]);
+
+        // The ETag follows the resolved range, not the window parameters
+        let etag = |response: Response| response.headers()[header::ETAG].clone();
+        let latest_etag = etag(export_window("1h", Some("latest"), None).await.unwrap());
+        assert_eq!(
+            etag(export_window("PT60M", Some("latest"), None).await.unwrap()),
+            latest_etag
+        );
+        assert_ne!(
+            etag(export_window("PT20M", Some("latest"), None).await.unwrap()),
+            latest_etag
+        );
+        assert_ne!(
+            etag(export_window("6d", Some("now"), None).await.unwrap()),
+            etag(export_window("6d", Some("latest"), None).await.unwrap())
+        );
+
+        // Nothing in the last hour before now
+        let response = export_window("1h", Some("now"), None).await.unwrap();
+        assert!(values(response).await.is_empty());
+        let response = export_window("1h", None, None).await.unwrap();
+        assert!(values(response).await.is_empty());
+        let response = export_window("6d", None, None).await.unwrap();
+        assert_eq!(values(response).await.len(), 19);
+
+        for (window, relative_to, start) in [
+            ("1h", Some("yesterday"), None),
+            ("one hour", Some("latest"), None),
+            ("1h", Some("latest"), Some(1_700_000_000.0)),
+        ] {
+            assert!(matches!(
+                export_window(window, relative_to, start).await,
+                Err(AppError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_as_geojson() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_export_geojson_{}", Uuid::new_v4()),
+                SensorType::Location,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let points = [(10.3951, 63.4305), (10.4012, 63.4321), (10.4105, 63.4350)];
+        let samples = TypedSamples::Location(
+            points
+                .iter()
+                .enumerate()
+                .map(|(i, (longitude, latitude))| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i as i64),
+                    value: geo::Point::new(*longitude, *latitude),
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let export_as = |export_as: &str| {
+            export_sensor(
+                State(state.clone()),
+                Path(sensor.uuid.to_string()),
+                Query(ExportQueryParams {
+                    format: None,
+                    start: None,
+                    end: None,
+                    limit: None,
+                    export_as: Some(export_as.to_string()),
+                    layout: None,
+                    labels: None,
+                    col_timestamp: None,
+                    col_value: None,
+                    window: None,
+                    relative_to: None,
+                    sort_by: None,
+                    order: None,
+                    geojson_mode: None,
+                }),
+                HeaderMap::new(),
+            )
+        };
+
+        let response = export_as("geojson").await.unwrap();
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/geo+json"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let geojson: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(geojson["type"], "Feature");
+        assert_eq!(geojson["geometry"]["type"], "LineString");
+        let coordinates = geojson["geometry"]["coordinates"].as_array().unwrap();
+        assert_eq!(coordinates.len(), 3);
+        for (coordinate, (longitude, latitude)) in coordinates.iter().zip(points) {
+            assert!((coordinate[0].as_f64().unwrap() - longitude).abs() < 1e-9);
+            assert!((coordinate[1].as_f64().unwrap() - latitude).abs() < 1e-9);
+        }
+        assert_eq!(
+            geojson["properties"]["timestamps"][0],
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_000).to_rfc3339()
+        );
+
+        assert!(matches!(
+            export_as("kml").await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_export_format_geojson() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_export_format_geojson_{}", Uuid::new_v4()),
+                SensorType::Location,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let string_sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_export_format_geojson_string_{}", Uuid::new_v4()),
+                SensorType::String,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let points = [(10.3951, 63.4305), (10.4012, 63.4321), (10.4105, 63.4350)];
+        let samples = TypedSamples::Location(
+            points
+                .iter()
+                .enumerate()
+                .map(|(i, (longitude, latitude))| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i as i64),
+                    value: geo::Point::new(*longitude, *latitude),
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(sensor.clone(), samples),
+            SingleSensorBatch::new(
+                string_sensor.clone(),
+                TypedSamples::one_string(
+                    "harbour".to_string(),
+                    SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                ),
+            ),
+        ]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let export = |sensor: &Sensor, format: &str, geojson_mode: Option<&str>| {
+            export_sensor(
+                State(state.clone()),
+                Path(sensor.uuid.to_string()),
+                Query(ExportQueryParams {
+                    format: Some(format.to_string()),
+                    start: None,
+                    end: None,
+                    limit: None,
+                    export_as: None,
+                    layout: None,
+                    labels: None,
+                    col_timestamp: None,
+                    col_value: None,
+                    window: None,
+                    relative_to: None,
+                    sort_by: None,
+                    order: None,
+                    geojson_mode: geojson_mode.map(str::to_string),
+                }),
+                HeaderMap::new(),
+            )
+        };
+        let body = |response: Response| async {
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let response = export(&sensor, "geojson", None).await.unwrap();
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/geo+json"
+        );
+        let points_etag = response.headers().get(header::ETAG).unwrap().clone();
+        let geojson: serde_json::Value = serde_json::from_slice(&body(response).await).unwrap();
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+        for (feature, (longitude, latitude)) in features.iter().zip(points) {
+            assert_eq!(feature["type"], "Feature");
+            assert_eq!(feature["geometry"]["type"], "Point");
+            let coordinate = &feature["geometry"]["coordinates"];
+            assert!((coordinate[0].as_f64().unwrap() - longitude).abs() < 1e-9);
+            assert!((coordinate[1].as_f64().unwrap() - latitude).abs() < 1e-9);
+        }
+        assert_eq!(
+            features[0]["properties"]["timestamp"],
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_000).to_rfc3339()
+        );
+
+        let response = export(&sensor, "geojson", Some("linestring"))
+            .await
+            .unwrap();
+        assert_ne!(response.headers().get(header::ETAG).unwrap(), points_etag);
+        let geojson: serde_json::Value = serde_json::from_slice(&body(response).await).unwrap();
+        assert_eq!(geojson["type"], "Feature");
+        assert_eq!(geojson["geometry"]["type"], "LineString");
+        assert_eq!(
+            geojson["geometry"]["coordinates"].as_array().unwrap().len(),
+            3
+        );
+
+        let response = export(&sensor, "geojsonl", None).await.unwrap();
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        let body = String::from_utf8(body(response).await.to_vec()).unwrap();
+        let features = body
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(features.len(), 3);
+        assert!(features
+            .iter()
+            .all(|feature| feature["geometry"]["type"] == "Point"));
+
+        for (sensor, format, geojson_mode) in [
+            (&*string_sensor, "geojson", None),
+            (&*string_sensor, "geojsonl", Some("linestring")),
+            (&*sensor, "geojson", Some("polygon")),
+            (&*sensor, "csv", Some("points")),
+        ] {
+            assert!(matches!(
+                export(sensor, format, geojson_mode).await,
+                Err(AppError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_sort_by_value() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_export_sort_by_value_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let string_sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_export_sort_by_value_string_{}", Uuid::new_v4()),
+                SensorType::String,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Integer(
+            [15, -3, 42, 8, 27, 0, 33]
+                .iter()
+                .enumerate()
+                .map(|(i, value)| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i as i64),
+                    value: *value,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(sensor.clone(), samples),
+            SingleSensorBatch::new(
+                string_sensor.clone(),
+                TypedSamples::one_string(
+                    "high".to_string(),
+                    SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+                )
+            ),
+        ]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let export_sorted = |sensor: &Sensor, sort_by: Option<&str>, order: Option<&str>| {
+            export_sensor(
+                State(state.clone()),
+                Path(sensor.uuid.to_string()),
+                Query(ExportQueryParams {
+                    format: Some("json".to_string()),
+                    start: None,
+                    end: None,
+                    limit: Some(3),
+                    export_as: None,
+                    layout: Some("columnar".to_string()),
+                    labels: None,
+                    col_timestamp: None,
+                    col_value: None,
+                    window: None,
+                    relative_to: None,
+                    sort_by: sort_by.map(str::to_string),
+                    order: order.map(str::to_string),
+                    geojson_mode: None,
+                }),
+                HeaderMap::new(),
+            )
+        };
+        let values = |response: Response| async move {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["values"].clone()
+        };
+
+        let response = export_sorted(&sensor, Some("value"), Some("desc"))
+            .await
+            .unwrap();
+        let desc_etag = response.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(values(response).await, serde_json::json!([42, 33, 27]));
+
+        // Descending by default
+        let response = export_sorted(&sensor, Some("value"), None).await.unwrap();
+        assert_eq!(values(response).await, serde_json::json!([42, 33, 27]));
+
+        let response = export_sorted(&sensor, Some("value"), Some("asc"))
+            .await
+            .unwrap();
+        assert_ne!(response.headers().get(header::ETAG).unwrap(), desc_etag);
+        assert_eq!(values(response).await, serde_json::json!([-3, 0, 8]));
+
+        let response = export_sorted(&sensor, Some("time"), None).await.unwrap();
+        assert_ne!(response.headers().get(header::ETAG).unwrap(), desc_etag);
+        assert_eq!(values(response).await, serde_json::json!([15, -3, 42]));
+
+        for (sensor, sort_by, order) in [
+            (&string_sensor, Some("value"), Some("desc")),
+            (&sensor, Some("value"), Some("down")),
+            (&sensor, Some("name"), None),
+            (&sensor, None, Some("asc")),
+        ] {
+            assert!(
+                matches!(
+                    export_sorted(sensor, sort_by, order).await,
+                    Err(AppError::BadRequest(_))
+                ),
+                "{:?} {:?}",
+                sort_by,
+                order
+            );
+        }
+        assert!(matches!(
+            export_sorted(
+                &Sensor::new_without_uuid("unknown".to_string(), SensorType::Float, None, None)
+                    .unwrap(),
+                Some("value"),
+                None
+            )
+            .await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_export_columnar_layout() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid("columnar".to_string(), SensorType::Integer, None, None)
+                .unwrap(),
+        );
+        let samples = TypedSamples::Integer(
+            (0..3)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                    value: i * 10,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let export_layout = |format: &str, layout: &str| {
+            export_sensor(
+                State(state.clone()),
+                Path(sensor.uuid.to_string()),
+                Query(ExportQueryParams {
+                    format: Some(format.to_string()),
+                    start: None,
+                    end: None,
+                    limit: None,
+                    export_as: None,
+                    layout: Some(layout.to_string()),
+                    labels: None,
+                    col_timestamp: None,
+                    col_value: None,
+                    window: None,
+                    relative_to: None,
+                    sort_by: None,
+                    order: None,
+                    geojson_mode: None,
+                }),
+                HeaderMap::new(),
+            )
+        };
+
+        let mut bodies = Vec::new();
+        let mut etags = Vec::new();
+        for layout in ["row", "columnar"] {
+            let response = export_layout("json", layout).await.unwrap();
+            etags.push(response.headers().get(header::ETAG).unwrap().clone());
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            bodies.push(serde_json::from_slice::<serde_json::Value>(&body).unwrap());
+        }
+        let (rows, columns) = (&bodies[0], &bodies[1]);
+        assert_ne!(etags[0], etags[1]);
+        assert_eq!(columns["values"], serde_json::json!([0, 10, 20]));
+        for (i, row) in rows["samples"].as_array().unwrap().iter().enumerate() {
+            assert_eq!(row["datetime"], columns["timestamps"][i]);
+            assert_eq!(row["value"], columns["values"][i]);
+        }
+
+        assert!(matches!(
+            export_layout("csv", "columnar").await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            export_layout("json", "diagonal").await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_export_label_columns() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                "labelled".to_string(),
+                SensorType::Integer,
+                None,
+                Some(smallvec![
+                    ("env".to_string(), "production".to_string()),
+                    ("room".to_string(), "kitchen".to_string()),
+                ]),
+            )
+            .unwrap(),
+        );
+        let samples =
+            TypedSamples::one_integer(42, SensAppDateTime::from_unix_seconds_i64(1_700_000_000));
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let export_labels = |format: &str, labels: Option<&str>| {
+            export_sensor(
+                State(state.clone()),
+                Path(sensor.uuid.to_string()),
+                Query(ExportQueryParams {
+                    format: Some(format.to_string()),
+                    start: None,
+                    end: None,
+                    limit: None,
+                    export_as: None,
+                    layout: None,
+                    labels: labels.map(str::to_string),
+                    col_timestamp: None,
+                    col_value: None,
+                    window: None,
+                    relative_to: None,
+                    sort_by: None,
+                    order: None,
+                    geojson_mode: None,
+                }),
+                HeaderMap::new(),
+            )
+        };
+
+        let response = export_labels("csv", Some("env,region")).await.unwrap();
+        let with_labels_etag = response.headers().get(header::ETAG).unwrap().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "datetime,value,env,region");
+        assert!(lines[1].ends_with(",42,production,"), "{}", lines[1]);
+
+        let response = export_labels("csv", None).await.unwrap();
+        assert_ne!(
+            response.headers().get(header::ETAG).unwrap(),
+            with_labels_etag
+        );
+
+        let response = export_labels("jsonl", Some("env")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let line: serde_json::Value = serde_json::from_slice(body.trim_ascii()).unwrap();
+        assert_eq!(line["env"], "production");
+        assert_eq!(line["value"], 42);
+
+        assert!(matches!(
+            export_labels("json", Some("env")).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            export_labels("csv", Some("value")).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        // Custom CSV headers
+        let export_headers = |format: &str| {
+            export_sensor(
+                State(state.clone()),
+                Path(sensor.uuid.to_string()),
+                Query(ExportQueryParams {
+                    format: Some(format.to_string()),
+                    start: None,
+                    end: None,
+                    limit: None,
+                    export_as: None,
+                    layout: None,
+                    labels: Some("env".to_string()),
+                    col_timestamp: Some("ts".to_string()),
+                    col_value: None,
+                    window: None,
+                    relative_to: None,
+                    sort_by: None,
+                    order: None,
+                    geojson_mode: None,
+                }),
+                HeaderMap::new(),
+            )
+        };
+        let response = export_headers("csv").await.unwrap();
+        assert_ne!(
+            response.headers().get(header::ETAG).unwrap(),
+            with_labels_etag
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body.lines().next().unwrap(), "ts,value,env");
+        assert!(matches!(
+            export_headers("jsonl").await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
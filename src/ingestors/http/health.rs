@@ -0,0 +1,91 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::storage::circuit_breaker::CircuitBreakerState;
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+
+/// Health of SensApp and its storage backend.
+///
+/// Returns 503 Service Unavailable while the circuit breaker
+/// of the storage backend is open, or while the write buffer
+/// is above its high-water mark. The requests in flight and queued
+/// are reported for the ingestion and query concurrency limits.
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "SensApp",
+    responses(
+        (status = 200, description = "SensApp is healthy"),
+        (status = 503, description = "The storage backend is unavailable or can't keep up"),
+    )
+)]
+pub async fn health(
+    State(state): State<HttpServerState>,
+) -> Result<(StatusCode, Json<Value>), AppError> {
+    let circuit_breaker = state.storage.circuit_breaker_state();
+    let write_buffer = state.storage.write_buffer_status();
+    let (status_code, status) = match (circuit_breaker, write_buffer) {
+        (Some(CircuitBreakerState::Open), _) => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+        (_, Some(write_buffer)) if write_buffer.is_degraded() => {
+            (StatusCode::SERVICE_UNAVAILABLE, "degraded")
+        }
+        _ => (StatusCode::OK, "ok"),
+    };
+    Ok((
+        status_code,
+        Json(json!({
+            "status": status,
+            "storage": {
+                "circuit_breaker": circuit_breaker,
+                "write_buffer": write_buffer,
+            },
+            "concurrency": {
+                "ingestion": state.concurrency.ingestion.as_ref().map(|limiter| limiter.status()),
+                "queries": state.concurrency.queries.as_ref().map(|limiter| limiter.status()),
+            },
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::write_buffer::{
+        tests::{publish_batches, SlowStorage},
+        WriteBufferOptions, WriteBufferStorage,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::Semaphore;
+
+    #[tokio::test]
+    async fn test_health_write_buffer() {
+        let slow_storage = Arc::new(SlowStorage {
+            permits: Semaphore::new(0),
+        });
+        let storage = Arc::new(WriteBufferStorage::new(
+            slow_storage.clone(),
+            WriteBufferOptions {
+                capacity: 4,
+                high_water_mark: 2,
+            },
+        ));
+        let state = HttpServerState::for_tests(storage.clone());
+
+        let (status_code, Json(body)) = health(State(state.clone())).await.unwrap();
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body["storage"]["write_buffer"]["depth"], 0);
+        assert!(body["concurrency"]["queries"].is_null());
+
+        // The storage is stuck on the first batch, the buffer fills up.
+        publish_batches(&storage, 1).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(publish_batches(&storage, 5).await, 1);
+
+        let (status_code, Json(body)) = health(State(state)).await.unwrap();
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["storage"]["write_buffer"]["depth"], 4);
+        assert_eq!(body["storage"]["write_buffer"]["max_depth"], 4);
+        assert_eq!(body["storage"]["write_buffer"]["dropped"], 1);
+    }
+}
@@ -1,20 +1,24 @@
-use super::{app_error::AppError, state::HttpServerState};
+use super::{
+    app_error::AppError, audit::audit_payload, request_log::record_ingestion,
+    state::HttpServerState,
+};
 use crate::datamodel::{
     batch_builder::BatchBuilder, sensapp_datetime::SensAppDateTimeExt, sensapp_vec::SensAppLabels,
-    SensAppDateTime, Sensor, SensorType, TypedSamples,
+    unit_mapping::UnitMapping, SensAppDateTime, Sensor, SensorType, TypedSamples,
 };
+use crate::parsing::compressed::{decompress_if_compressed, Compression};
+use crate::storage::sync_timeout::sync_with_timeout;
 use anyhow::Result;
 use axum::{
     debug_handler,
     extract::{Query, State},
     http::{HeaderMap, StatusCode},
 };
-use flate2::read::GzDecoder;
 use influxdb_line_protocol::{parse_lines, FieldValue};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use std::str::FromStr;
-use std::{io::Read, str::from_utf8};
+use std::{borrow::Cow, str::from_utf8};
 use std::{str, sync::Arc};
 use tokio_util::bytes::Bytes;
 
@@ -27,27 +31,99 @@ pub struct InfluxDBQueryParams {
     pub precision: Option<String>,
 }
 
-fn bytes_to_string(headers: &HeaderMap, bytes: &Bytes) -> Result<String, AppError> {
-    match headers.get("content-encoding") {
+#[derive(Debug, Deserialize)]
+pub struct InfluxDBV1QueryParams {
+    pub db: Option<String>,
+    pub rp: Option<String>,
+    pub precision: Option<String>,
+}
+
+/// The parser options the clients can set per request, with the
+/// `X-SensApp-Compression` and `X-SensApp-Floats-As-Numeric` headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct InfluxDBOptions {
+    /// Overrides the content-encoding and the detected compression.
+    pub compression: Option<Compression>,
+    /// Stores the floats as numeric, to keep their decimal representation.
+    pub floats_as_numeric: bool,
+}
+
+impl InfluxDBOptions {
+    /// The configured defaults, without a compression override.
+    pub(super) fn from_config() -> Result<Self, AppError> {
+        Ok(Self {
+            compression: None,
+            floats_as_numeric: crate::config::get()?.influxdb_floats_as_numeric,
+        })
+    }
+
+    /// The options of the request headers, or the configured defaults.
+    fn from_headers(headers: &HeaderMap) -> Result<Self, AppError> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| {
+                    value.to_str().map_err(|_| {
+                        AppError::BadRequest(anyhow::anyhow!("Invalid {} header", name))
+                    })
+                })
+                .transpose()
+        };
+        let mut options = Self::from_config()?;
+        if let Some(compression) = header("x-sensapp-compression")? {
+            options.compression = Some(match compression {
+                "gzip" => Compression::Gzip,
+                "zstd" => Compression::Zstd,
+                _ => {
+                    return Err(AppError::BadRequest(anyhow::anyhow!(
+                        "Invalid X-SensApp-Compression: {}, use gzip or zstd",
+                        compression
+                    )))
+                }
+            });
+        }
+        if let Some(floats_as_numeric) = header("x-sensapp-floats-as-numeric")? {
+            options.floats_as_numeric = floats_as_numeric.parse().map_err(|_| {
+                AppError::BadRequest(anyhow::anyhow!(
+                    "Invalid X-SensApp-Floats-As-Numeric: {}, use true or false",
+                    floats_as_numeric
+                ))
+            })?;
+        }
+        Ok(options)
+    }
+}
+
+fn bytes_to_string(
+    headers: &HeaderMap,
+    compression: Option<Compression>,
+    bytes: &Bytes,
+) -> Result<String, AppError> {
+    if let Some(compression) = compression {
+        let data = compression
+            .decompress(bytes)
+            .map_err(AppError::invalid_body)?;
+        return String::from_utf8(data).map_err(|e| AppError::BadRequest(anyhow::anyhow!(e)));
+    }
+    let data = match headers.get("content-encoding") {
         Some(value) => match value.to_str() {
-            Ok("gzip") => {
-                let mut d = GzDecoder::new(&bytes[..]);
-                let mut s = String::new();
-                d.read_to_string(&mut s)
-                    .map_err(|e| AppError::BadRequest(anyhow::anyhow!(e)))?;
-                Ok(s)
+            Ok("gzip") => Cow::Owned(
+                Compression::Gzip
+                    .decompress(bytes)
+                    .map_err(AppError::invalid_body)?,
+            ),
+            _ => {
+                return Err(AppError::BadRequest(anyhow::anyhow!(
+                    "Unsupported content-encoding: {:?}",
+                    value
+                )))
             }
-            _ => Err(AppError::BadRequest(anyhow::anyhow!(
-                "Unsupported content-encoding: {:?}",
-                value
-            ))),
         },
-        // No content-encoding header
-        None => {
-            let str = from_utf8(bytes).map_err(|e| AppError::BadRequest(anyhow::anyhow!(e)))?;
-            Ok(str.to_string())
-        }
-    }
+        // No content-encoding header, but the body may still be compressed
+        None => decompress_if_compressed(bytes).map_err(AppError::invalid_body)?,
+    };
+    let str = from_utf8(&data).map_err(|e| AppError::BadRequest(anyhow::anyhow!(e)))?;
+    Ok(str.to_string())
 }
 
 fn compute_field_name(url_encoded_measurement_name: &str, field_key: &str) -> String {
@@ -63,6 +139,7 @@ fn compute_field_name(url_encoded_measurement_name: &str, field_key: &str) -> St
 fn influxdb_field_to_sensapp(
     field_value: FieldValue,
     datetime: SensAppDateTime,
+    floats_as_numeric: bool,
 ) -> Result<(SensorType, TypedSamples)> {
     match field_value {
         FieldValue::I64(value) => Ok((
@@ -76,7 +153,9 @@ fn influxdb_field_to_sensapp(
             )),
             Err(_) => anyhow::bail!("U64 value is too big to be converted to i64"),
         },
-        //FieldValue::F64(value) => Ok((SensorType::Float, TypedSamples::one_float(value, datetime))),
+        FieldValue::F64(value) if !floats_as_numeric => {
+            Ok((SensorType::Float, TypedSamples::one_float(value, datetime)))
+        }
         FieldValue::F64(value) => Ok((
             SensorType::Numeric,
             TypedSamples::one_numeric(
@@ -97,31 +176,77 @@ fn influxdb_field_to_sensapp(
 }
 
 #[derive(Debug, Default, PartialEq)]
-enum Precision {
+pub(super) enum Precision {
     #[default]
     Nanoseconds,
     Microseconds,
     Milliseconds,
     Seconds,
+    /// Only in the InfluxDB 1.x API.
+    Minutes,
+    /// Only in the InfluxDB 1.x API.
+    Hours,
 }
 
 impl FromStr for Precision {
     type Err = ();
 
+    /// Parses the precisions of both APIs, the 1.x API uses `n` and `u`
+    /// for the nanoseconds and the microseconds.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "ns" => Ok(Precision::Nanoseconds),
-            "us" => Ok(Precision::Microseconds),
+            "ns" | "n" => Ok(Precision::Nanoseconds),
+            "us" | "u" | "µ" => Ok(Precision::Microseconds),
             "ms" => Ok(Precision::Milliseconds),
             "s" => Ok(Precision::Seconds),
+            "m" => Ok(Precision::Minutes),
+            "h" => Ok(Precision::Hours),
             _ => Err(()),
         }
     }
 }
 
+impl Precision {
+    /// Parses the precision query parameter,
+    /// or the configured default precision when missing.
+    pub(super) fn from_query(precision: Option<String>) -> Result<Self, AppError> {
+        let precision = match precision {
+            Some(precision) => precision,
+            None => crate::config::get()?.influxdb_default_precision.clone(),
+        };
+        precision
+            .parse()
+            .map_err(|_| AppError::BadRequest(anyhow::anyhow!("Invalid precision: {}", precision)))
+    }
+
+    fn to_datetime(&self, timestamp: i64) -> Result<SensAppDateTime> {
+        Ok(match self {
+            Precision::Nanoseconds => SensAppDateTime::from_unix_nanoseconds_i64(timestamp),
+            Precision::Microseconds => SensAppDateTime::from_unix_microseconds_i64(timestamp),
+            Precision::Milliseconds => SensAppDateTime::from_unix_milliseconds_i64(timestamp),
+            Precision::Seconds => SensAppDateTime::from_unix_seconds_i64(timestamp),
+            Precision::Minutes => SensAppDateTime::from_unix_seconds_i64(
+                timestamp
+                    .checked_mul(60)
+                    .ok_or_else(|| anyhow::anyhow!("Timestamp out of range: {}m", timestamp))?,
+            ),
+            Precision::Hours => SensAppDateTime::from_unix_seconds_i64(
+                timestamp
+                    .checked_mul(3600)
+                    .ok_or_else(|| anyhow::anyhow!("Timestamp out of range: {}h", timestamp))?,
+            ),
+        })
+    }
+}
+
 /// InfluxDB Compatible Write API.
 ///
 /// Allows you to write data from InfluxDB or Telegraf to SensApp.
+/// The bucket and the organisation are in the `influxdb_bucket`
+/// and `influxdb_org` labels. The timestamps are in nanoseconds
+/// by default, see `SENSAPP_INFLUXDB_DEFAULT_PRECISION`. The
+/// `X-SensApp-Compression` and `X-SensApp-Floats-As-Numeric` headers
+/// override the compression and the type of the floats of the request.
 /// [More information.](https://github.com/SINTEF/sensapp/blob/main/docs/INFLUX_DB.md)
 #[utoipa::path(
     post,
@@ -138,6 +263,8 @@ impl FromStr for Precision {
         ("org" = Option<String>, Query, description = "Organization name", example = "sensapp"),
         ("org_id" = Option<String>, Query, description = "Organization ID"),
         ("precision" = Option<String>, Query, description = "Precision of the timestamps. One of ns, us, ms, s"),
+        ("X-SensApp-Compression" = Option<String>, Header, description = "Compression of the body, gzip or zstd, overriding the content-encoding"),
+        ("X-SensApp-Floats-As-Numeric" = Option<bool>, Header, description = "Store the floats as numeric, see SENSAPP_INFLUXDB_FLOATS_AS_NUMERIC"),
     ),
     responses(
         (status = 204, description = "No Content"),
@@ -165,6 +292,8 @@ pub async fn publish_influxdb(
     // println!("bytes: {:?}", bytes);
     // println!("headers: {:?}", headers);
 
+    state.ensure_storage_available()?;
+
     // Requires org or org_id
     if org.is_none() && org_id.is_none() {
         return Err(AppError::BadRequest(anyhow::anyhow!(
@@ -178,57 +307,100 @@ pub async fn publish_influxdb(
         None => org_id.unwrap_or_default(),
     };
 
-    // Convert the precision string to a Precision enum
-    let precision_enum = match precision {
-        Some(precision) => match precision.parse() {
-            Ok(precision) => precision,
-            Err(_) => {
-                return Err(AppError::BadRequest(anyhow::anyhow!(
-                    "Invalid precision: {}",
-                    precision
-                )));
-            }
-        },
-        None => Precision::default(),
+    let precision = Precision::from_query(precision)?;
+
+    let mut injected_labels = SensAppLabels::new();
+    injected_labels.push(("influxdb_bucket".to_string(), bucket));
+    injected_labels.push(("influxdb_org".to_string(), common_org_name));
+
+    write_line_protocol(state, &headers, injected_labels, precision, &bytes).await
+}
+
+/// InfluxDB 1.x Compatible Write API.
+///
+/// Allows you to write data from the InfluxDB 1.x clients and Telegraf to SensApp.
+/// The database, and the retention policy when set, are in the `influxdb_bucket`
+/// label, like the buckets mapped by the InfluxDB 2.x compatibility API.
+/// The credentials are ignored.
+#[utoipa::path(
+    post,
+    path = "/write",
+    tag = "InfluxDB",
+    request_body(
+        content = String,
+        content_type = "text/plain",
+        description = "InfluxDB Line Protocol endpoint. [Reference](https://docs.influxdata.com/influxdb/v1/tools/api/#write-http-endpoint).",
+        example = "cpu,host=A,region=west usage_system=64.2 1590488773254420000"
+    ),
+    params(
+        ("db" = String, Query, description = "Database name", example = "sensapp"),
+        ("rp" = Option<String>, Query, description = "Retention policy name"),
+        ("precision" = Option<String>, Query, description = "Precision of the timestamps. One of n, ns, u, us, ms, s, m, h"),
+        ("X-SensApp-Compression" = Option<String>, Header, description = "Compression of the body, gzip or zstd, overriding the content-encoding"),
+        ("X-SensApp-Floats-As-Numeric" = Option<bool>, Header, description = "Store the floats as numeric, see SENSAPP_INFLUXDB_FLOATS_AS_NUMERIC"),
+    ),
+    responses(
+        (status = 204, description = "No Content"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn publish_influxdb_v1(
+    State(state): State<HttpServerState>,
+    headers: HeaderMap,
+    Query(InfluxDBV1QueryParams { db, rp, precision }): Query<InfluxDBV1QueryParams>,
+    bytes: Bytes,
+) -> Result<StatusCode, AppError> {
+    state.ensure_storage_available()?;
+
+    let bucket = match (db, rp) {
+        (None, _) => {
+            return Err(AppError::BadRequest(anyhow::anyhow!(
+                "database is required"
+            )));
+        }
+        (Some(db), None) => db,
+        (Some(db), Some(rp)) => format!("{}/{}", db, rp),
     };
+    let precision = Precision::from_query(precision)?;
 
-    let bytes_string = bytes_to_string(&headers, &bytes)?;
-    let parser = parse_lines(&bytes_string);
+    let mut injected_labels = SensAppLabels::new();
+    injected_labels.push(("influxdb_bucket".to_string(), bucket));
 
-    let mut batch_builder = BatchBuilder::new()?;
+    write_line_protocol(state, &headers, injected_labels, precision, &bytes).await
+}
+
+/// Adds the samples of the line protocol lines to the batch builder,
+/// with the injected labels added to the tags of every line.
+pub(super) async fn add_line_protocol(
+    batch_builder: &mut BatchBuilder,
+    lines: &str,
+    injected_labels: &SensAppLabels,
+    precision: &Precision,
+    floats_as_numeric: bool,
+) -> Result<(), AppError> {
+    // Matched on the sensor names, `measurement field`
+    let unit_mapping = UnitMapping::shared()?;
 
-    for line in parser {
+    for line in parse_lines(lines) {
         match line {
             Ok(line) => {
                 let measurement = line.series.measurement;
 
-                let tags = match &line.series.tag_set {
-                    None => None,
-                    Some(tags) => {
-                        let mut tags_vec = SensAppLabels::new();
-                        tags_vec.push(("influxdb_bucket".to_string(), bucket.clone()));
-                        tags_vec.push(("influxdb_org".to_string(), common_org_name.clone()));
-
-                        for (key, value) in tags.iter() {
-                            tags_vec.push((key.to_string(), value.to_string()));
-                        }
-                        Some(tags_vec)
+                // The injected labels are on the lines without tags too.
+                let mut tags_vec = injected_labels.clone();
+                if let Some(tags) = &line.series.tag_set {
+                    for (key, value) in tags.iter() {
+                        tags_vec.push((key.to_string(), value.to_string()));
                     }
-                };
+                }
+                let tags = Some(tags_vec);
 
                 let datetime = match line.timestamp {
-                    Some(timestamp) => match precision_enum {
-                        Precision::Nanoseconds => {
-                            SensAppDateTime::from_unix_nanoseconds_i64(timestamp)
-                        }
-                        Precision::Microseconds => {
-                            SensAppDateTime::from_unix_microseconds_i64(timestamp)
-                        }
-                        Precision::Milliseconds => {
-                            SensAppDateTime::from_unix_milliseconds_i64(timestamp)
-                        }
-                        Precision::Seconds => SensAppDateTime::from_unix_seconds_i64(timestamp),
-                    },
+                    Some(timestamp) => precision
+                        .to_datetime(timestamp)
+                        .map_err(AppError::BadRequest)?,
                     None => match SensAppDateTime::now() {
                         Ok(datetime) => datetime,
                         Err(error) => {
@@ -240,15 +412,15 @@ pub async fn publish_influxdb(
                 let url_encoded_field_name = urlencoding::encode(&measurement).to_string();
 
                 for (field_key, field_value) in line.field_set {
-                    let unit = None;
                     let (sensor_type, value) =
-                        match influxdb_field_to_sensapp(field_value, datetime) {
+                        match influxdb_field_to_sensapp(field_value, datetime, floats_as_numeric) {
                             Ok((sensor_type, value)) => (sensor_type, value),
                             Err(error) => {
                                 return Err(AppError::BadRequest(anyhow::anyhow!(error)));
                             }
                         };
                     let name = compute_field_name(&url_encoded_field_name, &field_key);
+                    let unit = unit_mapping.unit_for(&name);
                     let sensor = Sensor::new_without_uuid(name, sensor_type, unit, tags.clone())?;
                     batch_builder.add(Arc::new(sensor), value).await?;
                 }
@@ -258,6 +430,34 @@ pub async fn publish_influxdb(
             }
         }
     }
+    Ok(())
+}
+
+/// Publishes the line protocol body, with the injected labels
+/// added to the tags of every line.
+async fn write_line_protocol(
+    state: HttpServerState,
+    headers: &HeaderMap,
+    injected_labels: SensAppLabels,
+    precision: Precision,
+    bytes: &Bytes,
+) -> Result<StatusCode, AppError> {
+    let options = InfluxDBOptions::from_headers(headers)?;
+    let bytes_string = bytes_to_string(headers, options.compression, bytes)?;
+
+    let mut batch_builder = BatchBuilder::new()?.with_storage(state.storage.clone());
+    add_line_protocol(
+        &mut batch_builder,
+        &bytes_string,
+        &injected_labels,
+        &precision,
+        options.floats_as_numeric,
+    )
+    .await?;
+
+    state.ensure_accepted_sensors(&batch_builder).await?;
+    audit_payload("influxdb", bytes, &batch_builder).await?;
+    record_ingestion(&batch_builder).await;
 
     // TODO: Remove this println once debugged
     println!("INfluxDB: Sending to the event bus soon");
@@ -265,7 +465,7 @@ pub async fn publish_influxdb(
     match batch_builder.send_what_is_left(state.event_bus).await {
         Ok(Some(mut receiver)) => {
             println!("INfluxDB: Waiting for the receiver");
-            receiver.wait().await?;
+            sync_with_timeout(&mut receiver).await?;
             println!("INfluxDB: Receiver done");
         }
         Ok(None) => {
@@ -284,6 +484,7 @@ pub async fn publish_influxdb(
 #[cfg(test)]
 mod tests {
     use crate::bus::{self, message};
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
     use crate::storage::sqlite::SqliteStorage;
 
     use super::*;
@@ -296,7 +497,7 @@ mod tests {
     fn test_bytes_to_string() {
         let headers = HeaderMap::new();
         let bytes = Bytes::from("test");
-        let result = bytes_to_string(&headers, &bytes).unwrap();
+        let result = bytes_to_string(&headers, None, &bytes).unwrap();
         assert_eq!(result, "test".to_string());
 
         // Gziped bytes
@@ -306,24 +507,55 @@ mod tests {
         let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
         encoder.write_all(raw_bytes).unwrap();
         let bytes = Bytes::from(encoder.finish().unwrap());
-        let result = bytes_to_string(&headers, &bytes).unwrap();
+        let result = bytes_to_string(&headers, None, &bytes).unwrap();
+        assert_eq!(result, "test".to_string());
+
+        // Gziped bytes detected without content-encoding
+        let result = bytes_to_string(&HeaderMap::new(), None, &bytes).unwrap();
         assert_eq!(result, "test".to_string());
 
         // Unsupported content-encoding
         let mut headers = HeaderMap::new();
         headers.insert("content-encoding", "deflate".parse().unwrap());
         let bytes = Bytes::from("test");
-        let result = bytes_to_string(&headers, &bytes);
+        let result = bytes_to_string(&headers, None, &bytes);
         assert!(result.is_err());
 
         // Invalid UTF-8 bytes
         let headers = HeaderMap::new();
         // Starts with a 0
         let bytes = Bytes::from(&[0, 159, 146, 150][..]);
-        let result = bytes_to_string(&headers, &bytes);
+        let result = bytes_to_string(&headers, None, &bytes);
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_publish_influxdb_not_stored() {
+        let event_bus = bus::event_bus::init_event_bus();
+        let mut wololo = event_bus.main_bus_receiver.activate_cloned();
+        tokio::spawn(async move {
+            // Fails every publish, dropping the sync sender without a sync
+            while let Ok(message::Message::Publish(publish_message)) = wololo.recv().await {
+                drop(publish_message);
+            }
+        });
+        let state = State(HttpServerState {
+            event_bus: event_bus.clone(),
+            ..HttpServerState::for_tests(Arc::new(
+                SqliteStorage::connect("sqlite::memory:").await.unwrap(),
+            ))
+        });
+        let query = Query(InfluxDBQueryParams {
+            bucket: "test".to_string(),
+            org: Some("test".to_string()),
+            org_id: None,
+            precision: None,
+        });
+        let bytes = Bytes::from("cpu,host=A,region=west usage_system=64i 1590488773254420000");
+        let result = publish_influxdb(state, HeaderMap::new(), query, bytes).await;
+        assert!(matches!(result, Err(AppError::ServiceUnavailable(_))));
+    }
+
     #[tokio::test]
     async fn test_publish_influxdb() {
         let event_bus = bus::event_bus::init_event_bus();
@@ -343,9 +575,10 @@ mod tests {
             }
         });
         let state = State(HttpServerState {
-            name: Arc::new("influxdb test".to_string()),
             event_bus: event_bus.clone(),
-            storage: Arc::new(SqliteStorage::connect("sqlite::memory:").await.unwrap()),
+            ..HttpServerState::for_tests(Arc::new(
+                SqliteStorage::connect("sqlite::memory:").await.unwrap(),
+            ))
         });
         let headers = HeaderMap::new();
         let query = Query(InfluxDBQueryParams {
@@ -510,30 +743,399 @@ mod tests {
         assert!(matches!(result, Err(AppError::BadRequest(_))));
     }
 
+    #[tokio::test]
+    async fn test_influxdb_type_conflict() {
+        use crate::storage::storage::StorageInstance;
+        use crate::storage::type_conflict::TypeConflictPolicy;
+        use axum::{body::Body, http::Request, routing::post, Router};
+        use tower::ServiceExt;
+
+        _ = crate::config::load_configuration();
+        let storage = Arc::new(SqliteStorage::connect("sqlite::memory:").await.unwrap());
+        storage.create_or_migrate().await.unwrap();
+        let event_bus = bus::event_bus::init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let storage_for_publish = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(message::Message::Publish(message::PublishMessage {
+                batch,
+                sync_sender,
+                ..
+            })) = receiver.recv().await
+            {
+                _ = storage_for_publish.publish(batch, sync_sender).await;
+            }
+        });
+        let app = Router::new()
+            .route("/api/v2/write", post(publish_influxdb))
+            .with_state(HttpServerState {
+                event_bus,
+                type_conflict_policy: TypeConflictPolicy::Reject,
+                ..HttpServerState::for_tests(storage.clone())
+            });
+        let write = |body: &'static str| {
+            Request::post("/api/v2/write?org=my-org&bucket=my-bucket&precision=s")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(write("conflict,host=A usage=64i 1700000000"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // The same sensor with another type is rejected before being published
+        let response = app
+            .clone()
+            .oneshot(write("conflict,host=A usage=64.5 1700000060"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Not with other labels
+        let response = app
+            .clone()
+            .oneshot(write("conflict,host=B usage=64.5 1700000060"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_invalid_samples() {
+        use crate::storage::storage::StorageInstance;
+        use axum::{body::Body, http::Request, routing::post, Router};
+        use tower::ServiceExt;
+
+        _ = crate::config::load_configuration();
+        let storage = Arc::new(create_test_storage().await);
+        let event_bus = bus::event_bus::init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let storage_for_publish = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(message::Message::Publish(message::PublishMessage {
+                batch,
+                sync_sender,
+                ..
+            })) = receiver.recv().await
+            {
+                _ = storage_for_publish.publish(batch, sync_sender).await;
+            }
+        });
+        let app = Router::new()
+            .route("/api/v2/write", post(publish_influxdb))
+            .with_state(HttpServerState {
+                event_bus,
+                ..HttpServerState::for_tests(storage)
+            });
+        let write = |body: &'static str| {
+            Request::post("/api/v2/write?org=my-org&bucket=my-bucket&precision=s")
+                .header("x-sensapp-floats-as-numeric", "true")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(write("invalid,host=A value=12.5 1700000000"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Doesn't fit the numeric precision
+        let response = app
+            .clone()
+            .oneshot(write("invalid,host=A value=1e25 1700000060"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .contains("digits before the decimal point"));
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_client_requests() {
+        use crate::storage::query::{SensorSelector, TimeRange};
+        use crate::storage::storage::StorageInstance;
+        use axum::{body::Body, http::Request, routing::post, Router};
+        use tower::ServiceExt;
+
+        _ = crate::config::load_configuration();
+        let storage = Arc::new(create_test_storage().await);
+        let event_bus = bus::event_bus::init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let storage_for_publish = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(message::Message::Publish(message::PublishMessage {
+                batch,
+                sync_sender,
+                ..
+            })) = receiver.recv().await
+            {
+                storage_for_publish
+                    .publish(batch, sync_sender)
+                    .await
+                    .unwrap();
+            }
+        });
+        let app = Router::new()
+            .route("/write", post(publish_influxdb_v1))
+            .route("/api/v2/write", post(publish_influxdb))
+            .with_state(HttpServerState {
+                event_bus,
+                ..HttpServerState::for_tests(storage.clone())
+            });
+
+        // As sent by the InfluxDB 1.x clients and Telegraf
+        let request = Request::post("/write?db=telegraf&rp=autogen&precision=s&u=user&p=secret")
+            .header("content-type", "text/plain; charset=utf-8")
+            .header("user-agent", "Telegraf/1.30.0")
+            .body(Body::from(
+                "v1_cpu,host=A usage_idle=98i 1700000000\nv1_mem used=42i 1700000060\n",
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // The 1.x API defaults to nanoseconds too
+        let request = Request::post("/write?db=telegraf")
+            .body(Body::from("v1_disk free=7i 1700000000000000000"))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let request = Request::post("/write?precision=s")
+            .body(Body::from("v1_disk free=7i 1700000000"))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // As sent by the InfluxDB 2.x clients
+        let request = Request::post("/api/v2/write?org=my-org&bucket=my-bucket&precision=ms")
+            .header("authorization", "Token my-token")
+            .header("content-type", "text/plain; charset=utf-8")
+            .body(Body::from(
+                "v2_weather,station=oslo temperature=4i 1700000000123",
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let results = storage
+            .query(&SensorSelector::default(), TimeRange::default(), None)
+            .await
+            .unwrap();
+        let find = |name: &str| {
+            results
+                .iter()
+                .find(|sensor_data| sensor_data.sensor.name == name)
+                .unwrap_or_else(|| panic!("Missing sensor {}", name))
+        };
+        let label = |sensor: &Sensor, name: &str| {
+            sensor
+                .labels
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let cpu = find("v1_cpu usage_idle");
+        assert_eq!(
+            label(&cpu.sensor, "influxdb_bucket").as_deref(),
+            Some("telegraf/autogen")
+        );
+        assert_eq!(label(&cpu.sensor, "influxdb_org"), None);
+        assert_eq!(label(&cpu.sensor, "host").as_deref(), Some("A"));
+        assert_eq!(
+            cpu.samples.last_datetime(),
+            Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_000))
+        );
+
+        // The lines without tags have the injected labels too
+        let mem = find("v1_mem used");
+        assert_eq!(
+            label(&mem.sensor, "influxdb_bucket").as_deref(),
+            Some("telegraf/autogen")
+        );
+
+        let disk = find("v1_disk free");
+        assert_eq!(
+            label(&disk.sensor, "influxdb_bucket").as_deref(),
+            Some("telegraf")
+        );
+        assert_eq!(
+            disk.samples.last_datetime(),
+            Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_000))
+        );
+
+        let weather = find("v2_weather temperature");
+        assert_eq!(
+            label(&weather.sensor, "influxdb_bucket").as_deref(),
+            Some("my-bucket")
+        );
+        assert_eq!(
+            label(&weather.sensor, "influxdb_org").as_deref(),
+            Some("my-org")
+        );
+        assert_eq!(
+            weather.samples.last_datetime(),
+            Some(SensAppDateTime::from_unix_milliseconds_i64(
+                1_700_000_000_123
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_options_headers() {
+        use crate::storage::query::{SensorSelector, TimeRange};
+        use crate::storage::storage::StorageInstance;
+        use axum::{body::Body, http::Request, routing::post, Router};
+        use tower::ServiceExt;
+
+        _ = crate::config::load_configuration();
+        let storage = Arc::new(create_test_storage().await);
+        let event_bus = bus::event_bus::init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let storage_for_publish = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(message::Message::Publish(message::PublishMessage {
+                batch,
+                sync_sender,
+                ..
+            })) = receiver.recv().await
+            {
+                storage_for_publish
+                    .publish(batch, sync_sender)
+                    .await
+                    .unwrap();
+            }
+        });
+        let app = Router::new()
+            .route("/api/v2/write", post(publish_influxdb))
+            .with_state(HttpServerState {
+                event_bus,
+                ..HttpServerState::for_tests(storage.clone())
+            });
+        let write = |bucket: &str, headers: &[(&str, &str)], body: Body| {
+            let mut request = Request::post(format!(
+                "/api/v2/write?org=test&bucket={}&precision=s",
+                bucket
+            ));
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            app.clone().oneshot(request.body(body).unwrap())
+        };
+
+        // The same float payload, with the header on and off
+        let payload = "options_weather temperature=4.25 1700000000";
+        let response = write(
+            "numeric",
+            &[("X-SensApp-Floats-As-Numeric", "true")],
+            Body::from(payload),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload.as_bytes()).unwrap();
+        let response = write(
+            "float",
+            &[
+                ("X-SensApp-Floats-As-Numeric", "false"),
+                ("X-SensApp-Compression", "gzip"),
+            ],
+            Body::from(encoder.finish().unwrap()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let results = storage
+            .query(&SensorSelector::default(), TimeRange::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        for result in &results {
+            let bucket = result
+                .sensor
+                .labels
+                .iter()
+                .find(|(name, _)| name == "influxdb_bucket")
+                .map(|(_, value)| value.as_str())
+                .unwrap();
+            match bucket {
+                "numeric" => assert_eq!(
+                    result.samples,
+                    TypedSamples::one_numeric(
+                        Decimal::new(425, 2),
+                        SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+                    )
+                ),
+                _ => assert_eq!(
+                    result.samples,
+                    TypedSamples::one_float(
+                        4.25,
+                        SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+                    )
+                ),
+            }
+        }
+
+        for (name, value) in [
+            ("X-SensApp-Floats-As-Numeric", "maybe"),
+            ("X-SensApp-Compression", "brotli"),
+        ] {
+            let response = write("garbage", &[(name, value)], Body::from(payload))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
     #[test]
     fn test_influxdb_field_to_sensapp() {
         let datetime = SensAppDateTime::from_unix_seconds(0.0);
-        let result = influxdb_field_to_sensapp(FieldValue::I64(42), datetime).unwrap();
+        let result = influxdb_field_to_sensapp(FieldValue::I64(42), datetime, false).unwrap();
         assert_eq!(
             result,
             (SensorType::Integer, TypedSamples::one_integer(42, datetime))
         );
 
-        let result = influxdb_field_to_sensapp(FieldValue::U64(42), datetime).unwrap();
+        let result = influxdb_field_to_sensapp(FieldValue::U64(42), datetime, false).unwrap();
         assert_eq!(
             result,
             (SensorType::Integer, TypedSamples::one_integer(42, datetime))
         );
 
-        let result = influxdb_field_to_sensapp(FieldValue::F64(42.0), datetime).unwrap();
+        let result = influxdb_field_to_sensapp(FieldValue::F64(42.0), datetime, false).unwrap();
         assert_eq!(
             result,
             (SensorType::Float, TypedSamples::one_float(42.0, datetime))
         );
+        let result = influxdb_field_to_sensapp(FieldValue::F64(42.5), datetime, true).unwrap();
+        assert_eq!(
+            result,
+            (
+                SensorType::Numeric,
+                TypedSamples::one_numeric(Decimal::new(425, 1), datetime)
+            )
+        );
 
-        let result =
-            influxdb_field_to_sensapp(FieldValue::String(EscapedStr::from("test")), datetime)
-                .unwrap();
+        let result = influxdb_field_to_sensapp(
+            FieldValue::String(EscapedStr::from("test")),
+            datetime,
+            false,
+        )
+        .unwrap();
         assert_eq!(
             result,
             (
@@ -542,7 +1144,7 @@ mod tests {
             )
         );
 
-        let result = influxdb_field_to_sensapp(FieldValue::Boolean(true), datetime).unwrap();
+        let result = influxdb_field_to_sensapp(FieldValue::Boolean(true), datetime, false).unwrap();
         assert_eq!(
             result,
             (
@@ -555,7 +1157,8 @@ mod tests {
     #[test]
     fn test_convert_too_high_u64_to_i64() {
         let datetime = SensAppDateTime::from_unix_seconds(0.0);
-        let result = influxdb_field_to_sensapp(FieldValue::U64(i64::MAX as u64 + 1), datetime);
+        let result =
+            influxdb_field_to_sensapp(FieldValue::U64(i64::MAX as u64 + 1), datetime, false);
         assert!(result.is_err());
     }
 
@@ -576,6 +1179,19 @@ mod tests {
         let result = Precision::from_str("wrong");
         assert!(result.is_err());
 
+        // The 1.x API precisions
+        assert_eq!(Precision::from_str("n").unwrap(), Precision::Nanoseconds);
+        assert_eq!(Precision::from_str("u").unwrap(), Precision::Microseconds);
+        assert_eq!(
+            Precision::from_str("m").unwrap().to_datetime(2).unwrap(),
+            SensAppDateTime::from_unix_seconds_i64(120)
+        );
+        assert_eq!(
+            Precision::from_str("h").unwrap().to_datetime(1).unwrap(),
+            SensAppDateTime::from_unix_seconds_i64(3600)
+        );
+        assert!(Precision::Hours.to_datetime(i64::MAX).is_err());
+
         let result = Precision::default();
         assert_eq!(result, Precision::Nanoseconds);
     }
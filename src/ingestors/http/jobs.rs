@@ -0,0 +1,315 @@
+use super::{app_error::AppError, state::HttpServerState};
+use anyhow::anyhow;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// How long the jobs are kept once finished, by default.
+const DEFAULT_JOBS_TTL: Duration = Duration::from_secs(3600);
+/// How long the unfinished jobs are kept without progress, by default.
+const DEFAULT_JOBS_STALLED_TTL: Duration = Duration::from_secs(86400);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// The payload is being parsed.
+    Parsing,
+    /// The batches are sent, waiting for the storage.
+    Storing,
+    Completed,
+    Failed,
+}
+
+/// Progress of a bulk ingestion.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub status: JobStatus,
+    /// Samples stored so far.
+    pub samples_ingested: usize,
+    /// Sensors that didn't exist before the ingestion.
+    pub sensors_created: usize,
+    pub error: Option<String>,
+    #[serde(skip)]
+    updated_at: Instant,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        matches!(self.status, JobStatus::Completed | JobStatus::Failed)
+    }
+}
+
+/// The bulk ingestion jobs, forgotten `ttl` after they finished,
+/// or `stalled_ttl` after their last progress when they never finish.
+#[derive(Debug)]
+pub struct Jobs {
+    ttl: Duration,
+    stalled_ttl: Duration,
+    jobs: Mutex<HashMap<Uuid, Job>>,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new(DEFAULT_JOBS_TTL, DEFAULT_JOBS_STALLED_TTL)
+    }
+}
+
+impl Jobs {
+    pub fn new(ttl: Duration, stalled_ttl: Duration) -> Self {
+        Self {
+            ttl,
+            stalled_ttl,
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_expired(&self, job: &Job) -> bool {
+        let ttl = if job.is_finished() {
+            self.ttl
+        } else {
+            self.stalled_ttl
+        };
+        job.updated_at.elapsed() >= ttl
+    }
+
+    /// Creates a job in the parsing status, and returns its id.
+    pub fn create(&self) -> Uuid {
+        let id = Uuid::new_v4();
+        let mut jobs = self.lock();
+        jobs.retain(|_, job| !self.is_expired(job));
+        jobs.insert(
+            id,
+            Job {
+                status: JobStatus::Parsing,
+                samples_ingested: 0,
+                sensors_created: 0,
+                error: None,
+                updated_at: Instant::now(),
+            },
+        );
+        id
+    }
+
+    /// Updates the job, if it's still known.
+    pub fn update(&self, id: Uuid, update: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.lock().get_mut(&id) {
+            update(job);
+            job.updated_at = Instant::now();
+        }
+    }
+
+    /// Marks the job as failed with the error.
+    pub fn fail(&self, id: Uuid, error: &anyhow::Error) {
+        self.update(id, |job| {
+            job.status = JobStatus::Failed;
+            job.error = Some(error.to_string());
+        });
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        self.lock()
+            .get(&id)
+            .filter(|job| !self.is_expired(job))
+            .cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, Job>> {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Status of a bulk ingestion job.
+///
+/// The `status` is `parsing`, `storing`, `completed`, or `failed`,
+/// with the `error` of the failed jobs. The finished jobs are
+/// forgotten after a while.
+#[utoipa::path(
+    get,
+    path = "/jobs/{job_id}",
+    tag = "SensApp",
+    params(
+        ("job_id" = Uuid, Path, description = "Id returned by the ingestion"),
+    ),
+    responses(
+        (status = 200, description = "Status of the job"),
+        (status = 404, description = "Unknown job", body = AppError),
+    )
+)]
+pub async fn get_job(
+    State(state): State<HttpServerState>,
+    Path(job_id): Path<Uuid>,
+) -> Result<Json<Job>, AppError> {
+    state
+        .jobs
+        .get(job_id)
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(anyhow!("Job {} not found", job_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::message::{Message, PublishMessage};
+    use crate::config::load_configuration;
+    use crate::ingestors::http::publish::{publish_with_parser, PublishQueryParams};
+    use crate::storage::sqlite::SqliteStorage;
+    use crate::storage::storage::StorageInstance;
+    use axum::{extract::Query, http::StatusCode};
+    use std::sync::Arc;
+    use tokio_util::bytes::Bytes;
+
+    #[test]
+    fn test_jobs_ttl() {
+        let jobs = Jobs::new(Duration::from_millis(50), Duration::from_millis(200));
+        let running = jobs.create();
+        let finished = jobs.create();
+        jobs.update(finished, |job| job.status = JobStatus::Completed);
+        std::thread::sleep(Duration::from_millis(100));
+
+        // Only the finished jobs expire
+        assert_eq!(jobs.get(running).unwrap().status, JobStatus::Parsing);
+        assert!(jobs.get(finished).is_none());
+        assert!(jobs.get(Uuid::new_v4()).is_none());
+
+        // Until the running jobs stall
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(jobs.get(running).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_import_job() {
+        _ = load_configuration();
+        let event_bus = crate::bus::event_bus::init_event_bus();
+        let storage: Arc<dyn StorageInstance> =
+            Arc::new(SqliteStorage::connect("sqlite::memory:").await.unwrap());
+        storage.create_or_migrate().await.unwrap();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let bus_storage = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(Message::Publish(PublishMessage {
+                batch, sync_sender, ..
+            })) = receiver.recv().await
+            {
+                bus_storage.publish(batch, sync_sender).await.unwrap();
+            }
+        });
+        let state = HttpServerState {
+            event_bus,
+            ..HttpServerState::for_tests(storage)
+        };
+
+        let response = publish_with_parser(
+            State(state.clone()),
+            Path("graphite".to_string()),
+            Query(PublishQueryParams {
+                job: true,
+                ..Default::default()
+            }),
+            Bytes::from(
+                "test.jobs.a 1 1700000000\ntest.jobs.b 2 1700000000\ntest.jobs.a 3 1700000010\n",
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let job_id: Uuid = body["job_id"].as_str().unwrap().parse().unwrap();
+
+        let job = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let Json(job) = get_job(State(state.clone()), Path(job_id)).await.unwrap();
+                if job.is_finished() {
+                    return job;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(job.status, JobStatus::Completed);
+        assert_eq!(job.samples_ingested, 3);
+        assert_eq!(job.sensors_created, 2);
+        assert!(job.error.is_none());
+
+        // Only the new sensors are created
+        let response = publish_with_parser(
+            State(state.clone()),
+            Path("graphite".to_string()),
+            Query(PublishQueryParams {
+                job: true,
+                ..Default::default()
+            }),
+            Bytes::from("test.jobs.a 4 1700000020\ntest.jobs.c 5 1700000020\n"),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let job_id: Uuid = body["job_id"].as_str().unwrap().parse().unwrap();
+        let job = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let Json(job) = get_job(State(state.clone()), Path(job_id)).await.unwrap();
+                if job.is_finished() {
+                    return job;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(job.status, JobStatus::Completed);
+        assert_eq!(job.samples_ingested, 2);
+        assert_eq!(job.sensors_created, 1);
+
+        // A failed import
+        let response = publish_with_parser(
+            State(state.clone()),
+            Path("senml_json".to_string()),
+            Query(PublishQueryParams {
+                job: true,
+                ..Default::default()
+            }),
+            Bytes::from("not json"),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let job_id: Uuid = body["job_id"].as_str().unwrap().parse().unwrap();
+        let job = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let Json(job) = get_job(State(state.clone()), Path(job_id)).await.unwrap();
+                if job.is_finished() {
+                    return job;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        assert!(job.error.is_some());
+
+        assert!(matches!(
+            get_job(State(state), Path(Uuid::new_v4())).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::exporters::json::sensor_data_to_json;
+use axum::{
+    debug_handler,
+    extract::{Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LatestQueryParams {
+    /// Restricts the sensors to a name.
+    pub metric: Option<String>,
+}
+
+/// Get the most recent sample of every sensor.
+///
+/// Made for the dashboard overviews, in a single call instead of
+/// one query per sensor. The sensors without samples are left out.
+#[utoipa::path(
+    get,
+    path = "/sensors/latest",
+    tag = "SensApp",
+    params(
+        ("metric" = Option<String>, Query, description = "Name of the sensors, all the sensors by default"),
+    ),
+    responses(
+        (status = 200, description = "Each sensor with its most recent sample"),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn latest_samples(
+    State(state): State<HttpServerState>,
+    Query(LatestQueryParams { metric }): Query<LatestQueryParams>,
+) -> Result<Json<Vec<Value>>, AppError> {
+    let sensors_data = state.storage.latest_samples_all(metric.as_deref()).await?;
+    Ok(Json(sensors_data.iter().map(sensor_data_to_json).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, SensAppDateTime, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use smallvec::{smallvec, SmallVec};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_latest_samples() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let temperature = Arc::new(
+            Sensor::new_without_uuid("temperature".to_string(), SensorType::Float, None, None)
+                .unwrap(),
+        );
+        let humidity = Arc::new(
+            Sensor::new_without_uuid("humidity".to_string(), SensorType::Integer, None, None)
+                .unwrap(),
+        );
+        let status = Arc::new(
+            Sensor::new_without_uuid("status".to_string(), SensorType::String, None, None).unwrap(),
+        );
+        let empty = Arc::new(
+            Sensor::new_without_uuid("empty".to_string(), SensorType::Float, None, None).unwrap(),
+        );
+        // Published out of order, the newest sample isn't the last one.
+        let datetimes = [1_700_000_000, 1_700_000_020, 1_700_000_010]
+            .map(SensAppDateTime::from_unix_seconds_i64);
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(
+                temperature.clone(),
+                TypedSamples::Float(
+                    datetimes
+                        .iter()
+                        .zip([20.0, 22.5, 21.0])
+                        .map(|(datetime, value)| Sample {
+                            datetime: *datetime,
+                            value,
+                        })
+                        .collect(),
+                ),
+            ),
+            SingleSensorBatch::new(
+                humidity.clone(),
+                TypedSamples::Integer(
+                    datetimes
+                        .iter()
+                        .zip([40, 45, 42])
+                        .map(|(datetime, value)| Sample {
+                            datetime: *datetime,
+                            value,
+                        })
+                        .collect(),
+                ),
+            ),
+            SingleSensorBatch::new(
+                status.clone(),
+                TypedSamples::String(
+                    datetimes
+                        .iter()
+                        .zip(["starting", "running", "idle"])
+                        .map(|(datetime, value)| Sample {
+                            datetime: *datetime,
+                            value: value.to_string(),
+                        })
+                        .collect(),
+                ),
+            ),
+            SingleSensorBatch::new(empty.clone(), TypedSamples::Float(SmallVec::new())),
+        ]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+
+        let Json(latest) = latest_samples(State(state.clone()), Query(Default::default()))
+            .await
+            .unwrap();
+        assert_eq!(latest.len(), 3);
+        for (sensor, expected_value) in [
+            (&temperature, serde_json::json!(22.5)),
+            (&humidity, serde_json::json!(45)),
+            (&status, serde_json::json!("running")),
+        ] {
+            let sensor_latest = latest
+                .iter()
+                .filter(|sensor_data| sensor_data["sensor"]["uuid"] == sensor.uuid.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(sensor_latest.len(), 1);
+            let samples = sensor_latest[0]["samples"].as_array().unwrap();
+            assert_eq!(samples.len(), 1);
+            assert_eq!(samples[0]["value"], expected_value);
+        }
+
+        let Json(latest) = latest_samples(
+            State(state),
+            Query(LatestQueryParams {
+                metric: Some("humidity".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0]["sensor"]["name"], "humidity");
+    }
+}
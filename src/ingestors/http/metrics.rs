@@ -0,0 +1,128 @@
+use super::{app_error::AppError, state::HttpServerState};
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    Json,
+};
+use serde_json::{json, Value};
+
+/// Delete all the series of a metric.
+///
+/// The sensors with the given name are deleted, whatever their labels,
+/// with their samples, labels, and annotations.
+#[utoipa::path(
+    delete,
+    path = "/metrics/{name}",
+    tag = "SensApp",
+    params(
+        ("name" = String, Path, description = "Name of the metric"),
+    ),
+    responses(
+        (status = 200, description = "Number of deleted sensors", content_type = "application/json"),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn delete_metric(
+    State(state): State<HttpServerState>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    state.ensure_storage_available()?;
+    let deleted_sensors = state.storage.delete_metric(&name).await?;
+    if deleted_sensors > 0 {
+        state.catalog_cache.invalidate();
+    }
+    Ok(Json(json!({
+        "name": name,
+        "deleted_sensors": deleted_sensors,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        SensAppDateTime, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_delete_metric() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = |name: &str, room: &str| {
+            Arc::new(
+                Sensor::new_without_uuid(
+                    name.to_string(),
+                    SensorType::Integer,
+                    None,
+                    Some(smallvec![("room".to_string(), room.to_string())]),
+                )
+                .unwrap(),
+            )
+        };
+        let sensors = [
+            sensor("temperature", "kitchen"),
+            sensor("temperature", "bedroom"),
+            sensor("temperature", "garage"),
+            sensor("humidity", "kitchen"),
+        ];
+        let batch = Batch::new(
+            sensors
+                .iter()
+                .map(|sensor| {
+                    SingleSensorBatch::new(
+                        sensor.clone(),
+                        TypedSamples::one_integer(
+                            42,
+                            SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                        ),
+                    )
+                })
+                .collect(),
+        );
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        let storage = Arc::new(storage);
+
+        let state = HttpServerState::for_tests(storage.clone());
+        let Json(response) = delete_metric(State(state.clone()), Path("temperature".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(response["name"], "temperature");
+        assert_eq!(response["deleted_sensors"], 3);
+
+        let uuids = sensors.iter().map(|sensor| sensor.uuid).collect::<Vec<_>>();
+        assert_eq!(
+            storage.unknown_sensors(&uuids).await.unwrap(),
+            uuids[..3].to_vec()
+        );
+        for sensor in &sensors[..3] {
+            assert!(storage
+                .query_sensor_data(sensor.uuid, None, None, None)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        assert!(storage
+            .query_sensor_data(sensors[3].uuid, None, None, None)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Nothing left to delete
+        let Json(response) = delete_metric(State(state), Path("temperature".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(response["deleted_sensors"], 0);
+    }
+}
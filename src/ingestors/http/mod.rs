@@ -1,6 +1,32 @@
+pub mod acks;
+pub mod aggregate;
+pub mod annotations;
 pub mod app_error;
+pub mod audit;
+pub mod catalog;
+pub mod catalog_cache;
+pub mod concurrency_limit;
 pub mod crud;
+pub mod export;
+pub mod health;
 pub mod influxdb;
+pub mod jobs;
+pub mod latest;
+pub mod metrics;
+pub mod msgpack;
 pub mod prometheus;
+pub mod prometheus_query;
+pub mod prometheus_read;
+pub mod publish;
+pub mod query;
+pub mod rate_limit;
+pub mod raw_sql;
+pub mod request_log;
+pub mod retention;
+pub mod rollup;
+pub mod sensor_metadata;
+pub mod serve;
 pub mod server;
 pub mod state;
+pub mod stream;
+pub mod verify;
@@ -0,0 +1,187 @@
+use super::app_error::AppError;
+use crate::exporters::accepted_media_types;
+use anyhow::anyhow;
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// The MessagePack media types, the official one and the older ones.
+const MSGPACK_MEDIA_TYPES: [&str; 3] = [
+    MSGPACK_CONTENT_TYPE,
+    "application/vnd.msgpack",
+    "application/x-msgpack",
+];
+
+/// Whether the client prefers MessagePack to JSON.
+fn prefers_msgpack(accept: &str) -> bool {
+    accepted_media_types(accept)
+        .iter()
+        .find_map(|media_type| match media_type.as_str() {
+            "application/json" | "application/*" | "*/*" => Some(false),
+            media_type if MSGPACK_MEDIA_TYPES.contains(&media_type) => Some(true),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+/// Middleware encoding the JSON responses in MessagePack,
+/// when the client prefers it in its `Accept` header.
+///
+/// The response is the same document, and JSON stays the default.
+pub async fn msgpack_responses(request: Request, next: Next) -> Response {
+    let msgpack = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(prefers_msgpack);
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !msgpack || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let encoded = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => serde_json::from_slice::<Value>(&bytes)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| rmp_serde::to_vec_named(&value).map_err(anyhow::Error::from)),
+        Err(error) => Err(anyhow!("Failed to read the response: {}", error)),
+    };
+    match encoded {
+        Ok(encoded) => {
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(MSGPACK_CONTENT_TYPE),
+            );
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(encoded))
+        }
+        Err(error) => AppError::InternalServerError(error).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, SensAppDateTime, Sensor, SensorType, TypedSamples,
+    };
+    use crate::ingestors::http::{query::query_sensors, state::HttpServerState};
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use crate::storage::storage::StorageInstance;
+    use axum::{http::StatusCode, middleware, routing::post, Router};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_prefers_msgpack() {
+        assert!(prefers_msgpack("application/msgpack"));
+        assert!(prefers_msgpack(
+            "application/x-msgpack, application/json;q=0.5"
+        ));
+        assert!(prefers_msgpack(
+            "application/json;q=0.5, application/vnd.msgpack"
+        ));
+        assert!(!prefers_msgpack("application/json, application/msgpack"));
+        assert!(!prefers_msgpack("*/*"));
+        assert!(!prefers_msgpack("application/msgpack;q=0"));
+        assert!(!prefers_msgpack("text/csv"));
+    }
+
+    #[tokio::test]
+    async fn test_msgpack_responses() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_msgpack_{}", Uuid::new_v4()),
+                SensorType::Float,
+                None,
+                Some(smallvec![("room".to_string(), "kitchen".to_string())]),
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Float(smallvec![
+            Sample {
+                datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                value: 21.5,
+            },
+            Sample {
+                datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_060),
+                value: 22.0,
+            },
+        ]);
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let app = Router::new()
+            .route("/query", post(query_sensors))
+            .route_layer(middleware::from_fn(msgpack_responses))
+            .with_state(HttpServerState::for_tests(Arc::new(storage)));
+        let query = |accept: Option<&str>| {
+            let mut request = axum::http::Request::builder()
+                .method("POST")
+                .uri("/query")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(accept) = accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+            let body = serde_json::json!({ "uuids": [sensor.uuid] }).to_string();
+            app.clone().oneshot(request.body(Body::from(body)).unwrap())
+        };
+
+        let response = query(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let json: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                .unwrap();
+        assert_eq!(json[0]["samples"].as_array().unwrap().len(), 2);
+
+        let response = query(Some("application/msgpack")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            MSGPACK_CONTENT_TYPE
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let decoded: Value = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(decoded, json);
+
+        // The errors too
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri("/query")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/msgpack")
+            .body(Body::from(
+                r#"{"matchers": [{"name": "room", "value": "(", "type": "=~"}]}"#,
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let decoded: Value = rmp_serde::from_slice(&body).unwrap();
+        assert!(decoded.is_object());
+    }
+}
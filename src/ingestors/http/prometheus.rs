@@ -6,10 +6,17 @@ use crate::{
         sensapp_vec::SensAppLabels, unit::Unit, Sample, SensAppDateTime, Sensor, SensorType,
         TypedSamples,
     },
-    parsing::prometheus::remote_write_parser::parse_remote_write_request,
+    parsing::prometheus::{
+        remote_write_parser::{parse_remote_write_request, parse_remote_write_v2_request},
+        remote_write_v2_models as v2,
+    },
+    storage::sync_timeout::sync_with_timeout,
 };
 
-use super::{app_error::AppError, state::HttpServerState};
+use super::{
+    app_error::AppError, audit::audit_payload, request_log::record_ingestion,
+    state::HttpServerState,
+};
 use anyhow::Result;
 use axum::{
     debug_handler,
@@ -18,7 +25,14 @@ use axum::{
 };
 use tokio_util::bytes::Bytes;
 
-fn verify_headers(headers: &HeaderMap) -> Result<(), AppError> {
+/// Version of the Prometheus Remote Write protocol of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RemoteWriteVersion {
+    V1,
+    V2,
+}
+
+fn verify_headers(headers: &HeaderMap) -> Result<RemoteWriteVersion, AppError> {
     // Check that we have the right content encoding, that must be snappy
     match headers.get("content-encoding") {
         Some(content_encoding) => match content_encoding.to_str() {
@@ -36,41 +50,47 @@ fn verify_headers(headers: &HeaderMap) -> Result<(), AppError> {
         }
     }
 
-    // Check that the content type is protocol buffer
-    match headers.get("content-type") {
-        Some(content_type) => match content_type.to_str() {
-            Ok("application/x-protobuf") | Ok("APPLICATION/X-PROTOBUF") => {}
-            _ => {
-                return Err(AppError::BadRequest(anyhow::anyhow!(
-                    "Unsupported content-type, must be application/x-protobuf"
-                )));
-            }
-        },
+    // Check that the content type is protocol buffer,
+    // its proto parameter selects the version of the protocol.
+    let content_type = match headers.get("content-type") {
+        Some(content_type) => content_type.to_str().unwrap_or_default().to_lowercase(),
         None => {
             return Err(AppError::BadRequest(anyhow::anyhow!(
                 "Missing content-type header"
             )));
         }
+    };
+    let mut parameters = content_type.split(';').map(str::trim);
+    if parameters.next() != Some("application/x-protobuf") {
+        return Err(AppError::BadRequest(anyhow::anyhow!(
+            "Unsupported content-type, must be application/x-protobuf"
+        )));
     }
+    let proto = parameters.find_map(|parameter| parameter.strip_prefix("proto="));
 
-    // Check that the remote write version is supported
-    match headers.get("x-prometheus-remote-write-version") {
-        Some(version) => match version.to_str() {
-            Ok("0.1.0") => {}
-            _ => {
-                return Err(AppError::BadRequest(anyhow::anyhow!(
-                    "Unsupported x-prometheus-remote-write-version, must be 0.1.0"
-                )));
-            }
-        },
-        None => {
-            return Err(AppError::BadRequest(anyhow::anyhow!(
-                "Missing x-prometheus-remote-write-version header"
-            )));
+    let version = headers
+        .get("x-prometheus-remote-write-version")
+        .map(|version| version.to_str().unwrap_or_default());
+
+    match (proto, version) {
+        (None | Some("prometheus.writerequest"), Some("0.1.0")) => Ok(RemoteWriteVersion::V1),
+        (None | Some("prometheus.writerequest"), None) => Err(AppError::BadRequest(
+            anyhow::anyhow!("Missing x-prometheus-remote-write-version header"),
+        )),
+        (Some("io.prometheus.write.v2.request"), None) => Ok(RemoteWriteVersion::V2),
+        (Some("io.prometheus.write.v2.request"), Some(version)) if version.starts_with("2.") => {
+            Ok(RemoteWriteVersion::V2)
         }
+        (None | Some("prometheus.writerequest" | "io.prometheus.write.v2.request"), Some(_)) => {
+            Err(AppError::BadRequest(anyhow::anyhow!(
+                "Unsupported x-prometheus-remote-write-version, must be 0.1.0 or 2.0.0"
+            )))
+        }
+        (Some(proto), _) => Err(AppError::BadRequest(anyhow::anyhow!(
+            "Unsupported proto content-type parameter: {}",
+            proto
+        ))),
     }
-
-    Ok(())
 }
 
 /// Prometheus Remote Write API.
@@ -78,6 +98,8 @@ fn verify_headers(headers: &HeaderMap) -> Result<(), AppError> {
 /// Allows you to write data from Prometheus to SensApp.
 ///
 /// It follows the [Prometheus Remote Write specification](https://prometheus.io/docs/concepts/remote_write_spec/).
+/// The [2.0 version](https://prometheus.io/docs/specs/remote_write_spec_2_0/) is supported too,
+/// its metric metadata is kept in the `__type__` and `__help__` labels, and the sensor unit.
 #[utoipa::path(
     post,
     path = "/api/v1/prometheus_remote_write",
@@ -89,8 +111,8 @@ fn verify_headers(headers: &HeaderMap) -> Result<(), AppError> {
     ),
     params(
         ("content-encoding" = String, Header, format = "snappy", description = "Content encoding, must be snappy"),
-        ("content-type" = String, Header, format = "application/x-protobuf", description = "Content type, must be application/x-protobuf"),
-        ("x-prometheus-remote-write-version" = String, Header, format = "0.1.0", description = "Prometheus Remote Write version, must be 0.1.0"),
+        ("content-type" = String, Header, format = "application/x-protobuf", description = "Content type, must be application/x-protobuf, or application/x-protobuf;proto=io.prometheus.write.v2.Request for Remote Write 2.0"),
+        ("x-prometheus-remote-write-version" = String, Header, format = "0.1.0", description = "Prometheus Remote Write version, must be 0.1.0, or 2.0.0 for Remote Write 2.0"),
     ),
     responses(
         (status = 204, description = "No Content"),
@@ -113,8 +135,17 @@ pub async fn publish_prometheus(
 
     println!("Received {} bytes", bytes.len());
 
+    state.ensure_storage_available()?;
+
     // Verify headers
-    verify_headers(&headers)?;
+    let version = verify_headers(&headers)?;
+
+    let mut batch_builder = BatchBuilder::new()?.with_storage(state.storage.clone());
+    if version == RemoteWriteVersion::V2 {
+        let request = parse_remote_write_v2_request(&bytes)?;
+        add_remote_write_v2_request(&mut batch_builder, request).await?;
+        return send_batch(batch_builder, state, &bytes).await;
+    }
 
     // Parse the content
     let write_request = parse_remote_write_request(&bytes)?;
@@ -127,7 +158,6 @@ pub async fn publish_prometheus(
 
     println!("Received {} timeseries", write_request.timeseries.len());
 
-    let mut batch_builder = BatchBuilder::new()?;
     for time_serie in write_request.timeseries {
         let mut labels = SensAppLabels::with_capacity(time_serie.labels.len());
         let mut name: Option<String> = None;
@@ -172,9 +202,20 @@ pub async fn publish_prometheus(
         // batch_builder.send_if_batch_full(event_bus.clone()).await?;
     }
 
+    send_batch(batch_builder, state, &bytes).await
+}
+
+async fn send_batch(
+    mut batch_builder: BatchBuilder,
+    state: HttpServerState,
+    bytes: &Bytes,
+) -> Result<StatusCode, AppError> {
+    state.ensure_accepted_sensors(&batch_builder).await?;
+    audit_payload("prometheus_remote_write", bytes, &batch_builder).await?;
+    record_ingestion(&batch_builder).await;
     match batch_builder.send_what_is_left(state.event_bus).await {
         Ok(Some(mut receiver)) => {
-            receiver.wait().await?;
+            sync_with_timeout(&mut receiver).await?;
         }
         Ok(None) => {}
         Err(error) => {
@@ -185,3 +226,254 @@ pub async fn publish_prometheus(
     // OK no content
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Adds the time series of a Remote Write 2.0 request to the batch.
+///
+/// The metric type is stored in the `__type__` label, and the help text
+/// in the `__help__` label. The unit becomes the unit of the sensor.
+/// They are metadata of the series, so they don't change the sensor UUID:
+/// a series keeps its sensor when its help text or unit changes.
+async fn add_remote_write_v2_request(
+    batch_builder: &mut BatchBuilder,
+    request: v2::Request,
+) -> Result<(), AppError> {
+    for time_serie in &request.timeseries {
+        if time_serie.labels_refs.len() % 2 != 0 {
+            return Err(AppError::BadRequest(anyhow::anyhow!(
+                "A time serie has an odd number of label references"
+            )));
+        }
+        let mut labels = SensAppLabels::with_capacity(time_serie.labels_refs.len() / 2 + 2);
+        let mut name: Option<String> = None;
+        for label_refs in time_serie.labels_refs.chunks_exact(2) {
+            let label_name = request.symbol(label_refs[0])?;
+            let label_value = request.symbol(label_refs[1])?;
+            if label_name == "__name__" {
+                name = Some(label_value.to_string());
+            }
+            labels.push((label_name.to_string(), label_value.to_string()));
+        }
+        let name = match name {
+            Some(name) => name,
+            None => {
+                return Err(AppError::BadRequest(anyhow::anyhow!(
+                    "A time serie is missing its __name__ label"
+                )));
+            }
+        };
+
+        // Native histograms aren't supported, only the float samples are kept.
+        if time_serie.samples.is_empty() {
+            continue;
+        }
+
+        // The UUID only depends on the name and the labels of the series.
+        let identity = Sensor::new_without_uuid(name, SensorType::Float, None, Some(labels))?;
+        let mut labels = identity.labels;
+
+        // The references of the metadata are 0, the empty string, when unset.
+        let mut unit: Option<Unit> = None;
+        if let Some(metadata) = &time_serie.metadata {
+            if let Some(metric_type) = metadata.r#type().as_str() {
+                labels.push(("__type__".to_string(), metric_type.to_string()));
+            }
+            let help = request.symbol(metadata.help_ref)?;
+            if !help.is_empty() {
+                labels.push(("__help__".to_string(), help.to_string()));
+            }
+            let unit_name = request.symbol(metadata.unit_ref)?;
+            if !unit_name.is_empty() {
+                unit = Some(Unit::new(unit_name.to_string(), None));
+            }
+        }
+
+        let sensor = Sensor::new(
+            identity.uuid,
+            identity.name,
+            SensorType::Float,
+            unit,
+            Some(labels),
+        );
+        let samples = TypedSamples::Float(
+            time_serie
+                .samples
+                .iter()
+                .map(|sample| Sample {
+                    datetime: SensAppDateTime::from_unix_milliseconds_i64(sample.timestamp),
+                    value: sample.value,
+                })
+                .collect(),
+        );
+
+        batch_builder.add(Arc::new(sensor), samples).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::message::{Message, PublishMessage};
+    use crate::config::load_configuration;
+    use crate::storage::query::{SensorSelector, TimeRange};
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use crate::storage::storage::StorageInstance;
+    use axum::http::HeaderValue;
+    use prost::Message as _;
+
+    fn headers(content_type: &'static str, version: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-encoding", HeaderValue::from_static("snappy"));
+        headers.insert("content-type", HeaderValue::from_static(content_type));
+        if let Some(version) = version {
+            headers.insert(
+                "x-prometheus-remote-write-version",
+                HeaderValue::from_static(version),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_verify_headers() {
+        assert_eq!(
+            verify_headers(&headers("application/x-protobuf", Some("0.1.0"))).unwrap(),
+            RemoteWriteVersion::V1
+        );
+        assert_eq!(
+            verify_headers(&headers(
+                "application/x-protobuf;proto=prometheus.WriteRequest",
+                Some("0.1.0")
+            ))
+            .unwrap(),
+            RemoteWriteVersion::V1
+        );
+        assert_eq!(
+            verify_headers(&headers(
+                "application/x-protobuf;proto=io.prometheus.write.v2.Request",
+                Some("2.0.0")
+            ))
+            .unwrap(),
+            RemoteWriteVersion::V2
+        );
+        assert_eq!(
+            verify_headers(&headers(
+                "application/x-protobuf; proto=io.prometheus.write.v2.Request",
+                None
+            ))
+            .unwrap(),
+            RemoteWriteVersion::V2
+        );
+        assert!(verify_headers(&headers("application/x-protobuf", None)).is_err());
+        assert!(verify_headers(&headers("application/x-protobuf", Some("2.0.0"))).is_err());
+        assert!(verify_headers(&headers(
+            "application/x-protobuf;proto=io.prometheus.write.v3.Request",
+            Some("3.0.0")
+        ))
+        .is_err());
+        assert!(verify_headers(&headers("application/json", Some("0.1.0"))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_publish_prometheus_v2() {
+        _ = load_configuration();
+        let storage = Arc::new(create_test_storage().await);
+
+        let event_bus = crate::bus::event_bus::init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let storage_for_publish = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(Message::Publish(PublishMessage {
+                batch, sync_sender, ..
+            })) = receiver.recv().await
+            {
+                storage_for_publish
+                    .publish(batch, sync_sender)
+                    .await
+                    .unwrap();
+            }
+        });
+        let state = HttpServerState {
+            event_bus,
+            ..HttpServerState::for_tests(storage.clone())
+        };
+
+        let request = v2::Request {
+            symbols: vec![
+                "".to_string(),
+                "__name__".to_string(),
+                "http_requests_total".to_string(),
+                "job".to_string(),
+                "api".to_string(),
+                "Total number of HTTP requests".to_string(),
+                "requests".to_string(),
+            ],
+            timeseries: vec![v2::TimeSeries {
+                labels_refs: vec![1, 2, 3, 4],
+                samples: vec![
+                    v2::Sample {
+                        value: 1.0,
+                        timestamp: 1_700_000_000_000,
+                    },
+                    v2::Sample {
+                        value: 3.0,
+                        timestamp: 1_700_000_015_000,
+                    },
+                ],
+                metadata: Some(v2::Metadata {
+                    r#type: v2::MetricType::Counter as i32,
+                    help_ref: 5,
+                    unit_ref: 6,
+                }),
+            }],
+        };
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .unwrap();
+
+        let status = publish_prometheus(
+            State(state),
+            headers(
+                "application/x-protobuf;proto=io.prometheus.write.v2.Request",
+                Some("2.0.0"),
+            ),
+            Bytes::from(body),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let results = storage
+            .query(&SensorSelector::default(), TimeRange::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        let sensor = &results[0].sensor;
+        assert_eq!(sensor.name, "http_requests_total");
+        assert_eq!(sensor.unit.as_ref().unwrap().name, "requests");
+        let label = |name: &str| {
+            sensor
+                .labels
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(label("__type__"), Some("counter"));
+        assert_eq!(label("__help__"), Some("Total number of HTTP requests"));
+        assert_eq!(label("job"), Some("api"));
+        assert_eq!(results[0].samples.len(), 2);
+
+        // The metadata aren't part of the UUID
+        let identity = Sensor::new_without_uuid(
+            "http_requests_total".to_string(),
+            SensorType::Float,
+            None,
+            Some(smallvec::smallvec![
+                ("__name__".to_string(), "http_requests_total".to_string()),
+                ("job".to_string(), "api".to_string()),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(sensor.uuid, identity.uuid);
+    }
+}
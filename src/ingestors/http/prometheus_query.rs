@@ -0,0 +1,468 @@
+use super::prometheus_read::{float_samples, sensor_labels};
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::SensAppDateTime;
+use crate::parsing::prometheus::promql::{parse_promql, parse_promql_duration, PromQLQuery};
+use crate::storage::query::{SensorSelector, TimeRange, NAME_LABEL};
+use anyhow::{anyhow, bail, Result};
+use axum::{debug_handler, extract::State, Form, Json};
+use hifitime::Duration;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+
+/// How far back an instant selector looks for the latest sample,
+/// like the default lookback delta of Prometheus.
+const LOOKBACK_DELTA_SECONDS: f64 = 300.0;
+
+/// The maximum number of points per series of a range query, like Prometheus.
+const MAX_POINTS_PER_SERIES: f64 = 11_000.0;
+
+#[derive(Debug, Deserialize)]
+pub struct InstantQueryParams {
+    pub query: String,
+    /// Evaluation time, now by default.
+    pub time: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RangeQueryParams {
+    pub query: String,
+    pub start: String,
+    pub end: String,
+    pub step: String,
+}
+
+/// Parses a Prometheus timestamp, in unix seconds or RFC 3339, to unix seconds.
+fn parse_time(value: &str) -> Result<f64> {
+    if let Ok(seconds) = value.parse::<f64>() {
+        if seconds.is_finite() {
+            return Ok(seconds);
+        }
+    }
+    SensAppDateTime::from_str(value)
+        .map(|datetime| datetime.to_unix_seconds())
+        .map_err(|_| {
+            anyhow!(
+                "Invalid time {:?}, expected unix seconds or RFC 3339",
+                value
+            )
+        })
+}
+
+/// Parses a Prometheus step, in seconds or as a duration like `1m`.
+fn parse_step(value: &str) -> Result<f64> {
+    let seconds = match value.parse::<f64>() {
+        Ok(seconds) => seconds,
+        Err(_) => parse_promql_duration(value)?.to_seconds(),
+    };
+    if !seconds.is_finite() || seconds <= 0.0 {
+        bail!("The step must be positive");
+    }
+    Ok(seconds)
+}
+
+/// Formats a sample value like Prometheus, as a string.
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// The value of the series at the evaluation time, in unix seconds.
+///
+/// The samples are sorted `(unix seconds, value)` pairs. A selector takes
+/// the latest sample in the lookback delta, and a rate takes the per-second
+/// increase over its window, with the counter resets, between the first and
+/// last samples of the window. Unlike Prometheus, the rate isn't
+/// extrapolated to the window boundaries.
+fn evaluate(query: &PromQLQuery, samples: &[(f64, f64)], time: f64) -> Option<f64> {
+    let range = query
+        .rate
+        .map(|window| window.to_seconds())
+        .unwrap_or(LOOKBACK_DELTA_SECONDS);
+    let first = samples.partition_point(|(timestamp, _)| *timestamp <= time - range);
+    let last = samples.partition_point(|(timestamp, _)| *timestamp <= time);
+    let window = &samples[first..last];
+    if query.rate.is_none() {
+        return window.last().map(|(_, value)| *value);
+    }
+    let (first_timestamp, _) = window.first()?;
+    let (last_timestamp, _) = window.last()?;
+    if window.len() < 2 || last_timestamp <= first_timestamp {
+        return None;
+    }
+    let increase = window
+        .windows(2)
+        .map(|pair| {
+            let (previous, current) = (pair[0].1, pair[1].1);
+            if current < previous {
+                current
+            } else {
+                current - previous
+            }
+        })
+        .sum::<f64>();
+    Some(increase / (last_timestamp - first_timestamp))
+}
+
+/// Queries the series of the PromQL query, with their labels and samples
+/// in unix seconds, from `range` before the start to the end, inclusive.
+async fn query_series(
+    state: &HttpServerState,
+    query: &PromQLQuery,
+    start: f64,
+    end: f64,
+) -> Result<Vec<(Map<String, Value>, Vec<(f64, f64)>)>, AppError> {
+    let selector = SensorSelector {
+        uuids: None,
+        matchers: query.matchers.clone(),
+        numeric_only: true,
+        created_after: None,
+        last_seen_before: None,
+    };
+    selector.validate().map_err(AppError::BadRequest)?;
+    let range = query
+        .rate
+        .map(|window| window.to_seconds())
+        .unwrap_or(LOOKBACK_DELTA_SECONDS);
+    let time_range = TimeRange::new(
+        Some(SensAppDateTime::from_unix_seconds(start - range)),
+        Some(SensAppDateTime::from_unix_seconds(end) + Duration::from_milliseconds(1.0)),
+    );
+    let sensors_data = state.storage.query(&selector, time_range, None).await?;
+    Ok(sensors_data
+        .iter()
+        .map(|sensor_data| {
+            // The rate is no longer the metric, so it loses its name
+            let metric = sensor_labels(&sensor_data.sensor)
+                .into_iter()
+                .filter(|label| query.rate.is_none() || label.name != NAME_LABEL)
+                .map(|label| (label.name, Value::from(label.value)))
+                .collect::<Map<_, _>>();
+            let samples = float_samples(&sensor_data.samples)
+                .into_iter()
+                .map(|(timestamp, value)| (timestamp as f64 / 1000.0, value))
+                .collect();
+            (metric, samples)
+        })
+        .collect())
+}
+
+fn success(result_type: &str, result: Vec<Value>) -> Json<Value> {
+    Json(json!({
+        "status": "success",
+        "data": {
+            "resultType": result_type,
+            "result": result,
+        },
+    }))
+}
+
+/// Prometheus instant query API.
+///
+/// Evaluates a PromQL query at a single point in time, for the
+/// Prometheus datasources of Grafana. Only a subset of PromQL is
+/// supported: the selectors with label matchers, like
+/// `temperature{room="kitchen"}`, and the `rate()` of a range selector,
+/// like `rate(requests_total[5m])`, on the numeric sensors.
+///
+/// The form parameters are read from the query string of the GET requests.
+#[utoipa::path(
+    post,
+    path = "/api/v1/query",
+    tag = "Prometheus",
+    params(
+        ("query" = String, Query, description = "PromQL query", example = "temperature{room=\"kitchen\"}"),
+        ("time" = Option<String>, Query, description = "Evaluation time, in unix seconds or RFC 3339, now by default"),
+    ),
+    responses(
+        (status = 200, description = "The vector result", content_type = "application/json"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn prometheus_query(
+    State(state): State<HttpServerState>,
+    Form(params): Form<InstantQueryParams>,
+) -> Result<Json<Value>, AppError> {
+    state.ensure_storage_available()?;
+    let query = parse_promql(&params.query).map_err(AppError::BadRequest)?;
+    let time = match &params.time {
+        Some(time) => parse_time(time).map_err(AppError::BadRequest)?,
+        None => SensAppDateTime::now()?.to_unix_seconds(),
+    };
+
+    let result = query_series(&state, &query, time, time)
+        .await?
+        .into_iter()
+        .filter_map(|(metric, samples)| {
+            let value = evaluate(&query, &samples, time)?;
+            Some(json!({
+                "metric": metric,
+                "value": [time, format_value(value)],
+            }))
+        })
+        .collect();
+    Ok(success("vector", result))
+}
+
+/// Prometheus range query API.
+///
+/// Evaluates a PromQL query at every step of a time range, with
+/// the same subset of PromQL as the instant queries.
+#[utoipa::path(
+    post,
+    path = "/api/v1/query_range",
+    tag = "Prometheus",
+    params(
+        ("query" = String, Query, description = "PromQL query", example = "rate(requests_total[5m])"),
+        ("start" = String, Query, description = "Start of the range, in unix seconds or RFC 3339"),
+        ("end" = String, Query, description = "End of the range, inclusive"),
+        ("step" = String, Query, description = "Resolution, in seconds or as a duration like 1m"),
+    ),
+    responses(
+        (status = 200, description = "The matrix result", content_type = "application/json"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn prometheus_query_range(
+    State(state): State<HttpServerState>,
+    Form(params): Form<RangeQueryParams>,
+) -> Result<Json<Value>, AppError> {
+    state.ensure_storage_available()?;
+    let query = parse_promql(&params.query).map_err(AppError::BadRequest)?;
+    let start = parse_time(&params.start).map_err(AppError::BadRequest)?;
+    let end = parse_time(&params.end).map_err(AppError::BadRequest)?;
+    let step = parse_step(&params.step).map_err(AppError::BadRequest)?;
+    if end < start {
+        return Err(AppError::BadRequest(anyhow!(
+            "The end of the range must not be before its start"
+        )));
+    }
+    if (end - start) / step > MAX_POINTS_PER_SERIES {
+        return Err(AppError::BadRequest(anyhow!(
+            "Exceeded the maximum of {} points per series, try a larger step",
+            MAX_POINTS_PER_SERIES
+        )));
+    }
+    let steps = ((end - start) / step).floor() as usize;
+
+    let result = query_series(&state, &query, start, end)
+        .await?
+        .into_iter()
+        .filter_map(|(metric, samples)| {
+            let values = (0..=steps)
+                .filter_map(|index| {
+                    let time = start + index as f64 * step;
+                    let value = evaluate(&query, &samples, time)?;
+                    Some(json!([time, format_value(value)]))
+                })
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                return None;
+            }
+            Some(json!({
+                "metric": metric,
+                "values": values,
+            }))
+        })
+        .collect();
+    Ok(success("matrix", result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use crate::storage::storage::StorageInstance;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    async fn state_with_counters(name: &str) -> HttpServerState {
+        let storage = create_test_storage().await;
+
+        // A counter increasing by 2 every 10 seconds, per room
+        let mut sensors = smallvec![];
+        for (room, offset) in [("kitchen", 0), ("garage", 1000)] {
+            let sensor = Arc::new(
+                Sensor::new_without_uuid(
+                    name.to_string(),
+                    SensorType::Integer,
+                    None,
+                    Some(smallvec![("room".to_string(), room.to_string())]),
+                )
+                .unwrap(),
+            );
+            let samples = TypedSamples::Integer(
+                (0..10)
+                    .map(|i| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i * 10),
+                        value: offset + i * 2,
+                    })
+                    .collect(),
+            );
+            sensors.push(SingleSensorBatch::new(sensor, samples));
+        }
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage
+            .publish(Arc::new(Batch::new(sensors)), sync_sender)
+            .await
+            .unwrap();
+
+        HttpServerState::for_tests(Arc::new(storage))
+    }
+
+    async fn get_json(app: &Router, uri: String) -> Value {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_query() {
+        _ = load_configuration();
+        let name = format!("test_promql_{}", Uuid::new_v4().simple());
+        let app = Router::new()
+            .route(
+                "/api/v1/query",
+                get(prometheus_query).post(prometheus_query),
+            )
+            .route(
+                "/api/v1/query_range",
+                get(prometheus_query_range).post(prometheus_query_range),
+            )
+            .with_state(state_with_counters(&name).await);
+
+        // Instant query, with the latest sample before the time
+        let query = format!("{}{{room=\"kitchen\"}}", name);
+        let response = get_json(
+            &app,
+            format!(
+                "/api/v1/query?query={}&time=1700000055",
+                urlencoding::encode(&query)
+            ),
+        )
+        .await;
+        assert_eq!(
+            response,
+            json!({
+                "status": "success",
+                "data": {
+                    "resultType": "vector",
+                    "result": [{
+                        "metric": {NAME_LABEL: name, "room": "kitchen"},
+                        "value": [1700000055.0, "10"],
+                    }],
+                },
+            })
+        );
+
+        // Range query of the rate, as a form like Grafana
+        let query = format!("rate({}{{room=~\"kitchen|garage\"}}[30s])", name);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/api/v1/query_range")
+                    .header("content-type", "application/x-www-form-urlencoded")
+                    .body(Body::from(format!(
+                        "query={}&start=1700000030&end=1700000090&step=30s",
+                        urlencoding::encode(&query)
+                    )))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["status"], "success");
+        assert_eq!(response["data"]["resultType"], "matrix");
+        let result = response["data"]["result"].as_array().unwrap();
+        assert_eq!(result.len(), 2);
+        for series in result {
+            assert!(series["metric"].get(NAME_LABEL).is_none());
+            assert_eq!(
+                series["values"],
+                json!([
+                    [1700000030.0, "0.2"],
+                    [1700000060.0, "0.2"],
+                    [1700000090.0, "0.2"],
+                ])
+            );
+        }
+
+        // Invalid queries
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/api/v1/query?query=sum(up)")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+        let response = app
+            .oneshot(
+                Request::get(format!(
+                    "/api/v1/query_range?query={}&start=0&end=100000&step=1",
+                    name
+                ))
+                .body(Body::empty())
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let samples = [(10.0, 5.0), (20.0, 8.0), (30.0, 2.0), (40.0, 4.0)];
+        let selector = parse_promql("counter").unwrap();
+        assert_eq!(evaluate(&selector, &samples, 25.0), Some(8.0));
+        assert_eq!(evaluate(&selector, &samples, 5.0), None);
+        assert_eq!(evaluate(&selector, &samples, 40.0 + 299.0), Some(4.0));
+        assert_eq!(evaluate(&selector, &samples, 40.0 + 300.0), None);
+
+        // 3 + 2 after the reset + 2, over 30 seconds
+        let rate = parse_promql("rate(counter[1m])").unwrap();
+        assert_eq!(evaluate(&rate, &samples, 40.0), Some(7.0 / 30.0));
+        assert_eq!(evaluate(&rate, &samples, 10.0), None);
+
+        assert_eq!(parse_time("1700000000.5").unwrap(), 1_700_000_000.5);
+        assert_eq!(parse_time("2023-11-14T22:13:20Z").unwrap(), 1_700_000_000.0);
+        assert!(parse_time("yesterday").is_err());
+        assert_eq!(parse_step("1m").unwrap(), 60.0);
+        assert!(parse_step("0").is_err());
+
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NAN), "NaN");
+        assert_eq!(format_value(0.5), "0.5");
+    }
+}
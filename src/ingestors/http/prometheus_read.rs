@@ -0,0 +1,411 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::{
+    sensapp_datetime::SensAppDateTimeExt, SensAppDateTime, Sensor, TypedSamples,
+};
+use crate::parsing::prometheus::{
+    chunk_encoder::encode_xor_chunks,
+    remote_read_models::{
+        ChunkedReadResponse, ChunkedSeries, MatcherType, Query, QueryResult, ReadRequest,
+        ReadResponse, ResponseType,
+    },
+    remote_write_models::{Label, Sample, TimeSeries},
+    stream_writer::{encode_frame, MAX_FRAME_BYTES, STREAMED_CONTENT_TYPE},
+};
+use crate::storage::query::{
+    LabelMatcher, LabelMatcherType, SensorSelector, TimeRange, NAME_LABEL,
+};
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Body,
+    debug_handler,
+    extract::State,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+};
+use futures::{StreamExt, TryStreamExt};
+use num_traits::ToPrimitive;
+use prost::Message;
+use tokio_util::bytes::Bytes;
+
+fn parse_read_request(headers: &HeaderMap, bytes: &[u8]) -> Result<ReadRequest> {
+    match headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some("snappy") | Some("SNAPPY") => {}
+        _ => return Err(anyhow!("Unsupported content-encoding, must be snappy")),
+    }
+    let decompressed = snap::raw::Decoder::new().decompress_vec(bytes)?;
+    Ok(ReadRequest::decode(&decompressed[..])?)
+}
+
+/// Converts a remote read query to a selector of the numeric sensors.
+fn query_to_selector(query: &Query) -> Result<(SensorSelector, TimeRange)> {
+    let matchers = query
+        .matchers
+        .iter()
+        .map(|matcher| {
+            let matcher_type = match MatcherType::try_from(matcher.r#type)? {
+                MatcherType::Eq => LabelMatcherType::Equal,
+                MatcherType::Neq => LabelMatcherType::NotEqual,
+                MatcherType::Re => LabelMatcherType::Regex,
+                MatcherType::Nre => LabelMatcherType::NotRegex,
+            };
+            Ok(LabelMatcher::new(
+                matcher.name.clone(),
+                matcher.value.clone(),
+                matcher_type,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let selector = SensorSelector {
+        uuids: None,
+        matchers,
+        numeric_only: true,
+        created_after: None,
+        last_seen_before: None,
+    };
+    selector.validate()?;
+
+    // The end of a remote read query is inclusive
+    let time_range = TimeRange::new(
+        Some(SensAppDateTime::from_unix_milliseconds_i64(
+            query.start_timestamp_ms,
+        )),
+        Some(SensAppDateTime::from_unix_milliseconds_i64(
+            query.end_timestamp_ms.saturating_add(1),
+        )),
+    );
+    Ok((selector, time_range))
+}
+
+/// The labels of the sensor, with its name, sorted like Prometheus expects.
+pub(super) fn sensor_labels(sensor: &Sensor) -> Vec<Label> {
+    let mut labels = sensor
+        .labels
+        .iter()
+        .filter(|(name, _)| name != NAME_LABEL)
+        .map(|(name, value)| Label {
+            name: name.clone(),
+            value: value.clone(),
+        })
+        .collect::<Vec<_>>();
+    labels.push(Label {
+        name: NAME_LABEL.to_string(),
+        value: sensor.name.clone(),
+    });
+    labels.sort_by(|a, b| a.name.cmp(&b.name));
+    labels
+}
+
+/// The samples as millisecond timestamps and float values.
+pub(super) fn float_samples(samples: &TypedSamples) -> Vec<(i64, f64)> {
+    let timestamp = |datetime: &SensAppDateTime| datetime.to_unix_milliseconds().floor() as i64;
+    match samples {
+        TypedSamples::Integer(samples) => samples
+            .iter()
+            .map(|sample| (timestamp(&sample.datetime), sample.value as f64))
+            .collect(),
+        TypedSamples::Numeric(samples) => samples
+            .iter()
+            .map(|sample| {
+                (
+                    timestamp(&sample.datetime),
+                    sample.value.to_f64().unwrap_or(f64::NAN),
+                )
+            })
+            .collect(),
+        TypedSamples::Float(samples) => samples
+            .iter()
+            .map(|sample| (timestamp(&sample.datetime), sample.value))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Encodes the samples of a sensor in frames of about [`MAX_FRAME_BYTES`].
+fn encode_series_frames(
+    query_index: i64,
+    labels: Vec<Label>,
+    samples: &[(i64, f64)],
+) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let mut chunks = Vec::new();
+    let mut size = 0;
+    for chunk in encode_xor_chunks(samples) {
+        size += chunk.data.len();
+        chunks.push(chunk);
+        if size >= MAX_FRAME_BYTES {
+            frames.push(encode_frame(&ChunkedReadResponse {
+                chunked_series: vec![ChunkedSeries {
+                    labels: labels.clone(),
+                    chunks: std::mem::take(&mut chunks),
+                }],
+                query_index,
+            }));
+            size = 0;
+        }
+    }
+    if !chunks.is_empty() {
+        frames.push(encode_frame(&ChunkedReadResponse {
+            chunked_series: vec![ChunkedSeries { labels, chunks }],
+            query_index,
+        }));
+    }
+    frames
+}
+
+/// Prometheus Remote Read API.
+///
+/// Allows Prometheus to read the numeric sensors of SensApp.
+///
+/// When the client accepts the streamed response type, the samples are
+/// streamed as XOR encoded chunks, one sensor at a time, to bound the memory
+/// usage. Otherwise, all the samples are sent in a single response.
+///
+/// It follows the [Prometheus Remote Read specification](https://prometheus.io/docs/prometheus/latest/querying/remote_read_api/).
+#[utoipa::path(
+    post,
+    path = "/api/v1/prometheus_remote_read",
+    tag = "Prometheus",
+    request_body(
+        content = Bytes,
+        content_type = "application/x-protobuf",
+        description = "Snappy compressed ReadRequest.",
+    ),
+    params(
+        ("content-encoding" = String, Header, format = "snappy", description = "Content encoding, must be snappy"),
+    ),
+    responses(
+        (status = 200, description = "Snappy compressed ReadResponse, or a stream of ChunkedReadResponse frames"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn prometheus_remote_read(
+    State(state): State<HttpServerState>,
+    headers: HeaderMap,
+    bytes: Bytes,
+) -> Result<Response, AppError> {
+    state.ensure_storage_available()?;
+
+    let read_request = parse_read_request(&headers, &bytes).map_err(AppError::BadRequest)?;
+    let queries = read_request
+        .queries
+        .iter()
+        .map(query_to_selector)
+        .collect::<Result<Vec<_>>>()
+        .map_err(AppError::BadRequest)?;
+
+    // The first supported response type in the order of preference of the client
+    let streamed = read_request
+        .accepted_response_types
+        .iter()
+        .find_map(
+            |response_type| match ResponseType::try_from(*response_type) {
+                Ok(ResponseType::StreamedXorChunks) => Some(true),
+                Ok(ResponseType::Samples) => Some(false),
+                Err(_) => None,
+            },
+        )
+        .unwrap_or(false);
+
+    if !streamed {
+        let mut results = Vec::with_capacity(queries.len());
+        for (selector, time_range) in &queries {
+            let sensors_data = state.storage.query(selector, *time_range, None).await?;
+            let timeseries = sensors_data
+                .iter()
+                .map(|sensor_data| TimeSeries {
+                    labels: sensor_labels(&sensor_data.sensor),
+                    samples: float_samples(&sensor_data.samples)
+                        .into_iter()
+                        .map(|(timestamp, value)| Sample { value, timestamp })
+                        .collect(),
+                })
+                .collect();
+            results.push(QueryResult { timeseries });
+        }
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&ReadResponse { results }.encode_to_vec())
+            .map_err(|error| AppError::InternalServerError(error.into()))?;
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "application/x-protobuf"),
+                (header::CONTENT_ENCODING, "snappy"),
+            ],
+            body,
+        )
+            .into_response());
+    }
+
+    // Only the sensors are listed first, their samples are
+    // queried one sensor at a time while streaming.
+    let mut series = Vec::new();
+    for (query_index, (selector, time_range)) in queries.iter().enumerate() {
+        for sensor_data in state.storage.query(selector, *time_range, Some(0)).await? {
+            series.push((query_index as i64, sensor_data.sensor, *time_range));
+        }
+    }
+
+    let storage = state.storage.clone();
+    let stream = futures::stream::iter(series)
+        .then(move |(query_index, sensor, time_range)| {
+            let storage = storage.clone();
+            async move {
+                let samples = match storage
+                    .query_sensor_data(sensor.uuid, time_range.start, time_range.end, None)
+                    .await?
+                {
+                    Some(sensor_data) => float_samples(&sensor_data.samples),
+                    None => Vec::new(),
+                };
+                let frames = encode_series_frames(query_index, sensor_labels(&sensor), &samples);
+                Ok::<_, anyhow::Error>(futures::stream::iter(
+                    frames.into_iter().map(Ok::<_, anyhow::Error>),
+                ))
+            }
+        })
+        .try_flatten();
+
+    Ok((
+        [(header::CONTENT_TYPE, STREAMED_CONTENT_TYPE)],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        SensorType,
+    };
+    use crate::parsing::prometheus::{
+        chunk_encoder::decoder::decode_xor_chunk, remote_read_models, stream_writer::decode_frames,
+    };
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use crate::storage::storage::StorageInstance;
+    use axum::extract::State;
+    use smallvec::smallvec;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn read_request(name: &str, response_type: ResponseType) -> Bytes {
+        let request = ReadRequest {
+            queries: vec![Query {
+                start_timestamp_ms: 1_700_000_000_000,
+                end_timestamp_ms: 1_700_000_299_000,
+                matchers: vec![remote_read_models::LabelMatcher {
+                    r#type: MatcherType::Eq as i32,
+                    name: NAME_LABEL.to_string(),
+                    value: name.to_string(),
+                }],
+            }],
+            accepted_response_types: vec![response_type as i32],
+        };
+        let compressed = snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .unwrap();
+        Bytes::from(compressed)
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_remote_read() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        // 500 samples every second, so 300 in the query range and 3 chunks
+        let name = format!("test_remote_read_{}", Uuid::new_v4());
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                name.clone(),
+                SensorType::Float,
+                None,
+                Some(smallvec![("job".to_string(), "sensapp".to_string())]),
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Float(
+            (0..500)
+                .map(|i| crate::datamodel::Sample {
+                    datetime: SensAppDateTime::from_unix_milliseconds_i64(
+                        1_700_000_000_000 + i * 1000,
+                    ),
+                    value: (i % 10) as f64 * 0.5,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_ENCODING, "snappy".parse().unwrap());
+        let expected = (0..300)
+            .map(|i| (1_700_000_000_000 + i * 1000, (i % 10) as f64 * 0.5))
+            .collect::<Vec<_>>();
+
+        // Streamed
+        let response = prometheus_remote_read(
+            State(state.clone()),
+            headers.clone(),
+            read_request(&name, ResponseType::StreamedXorChunks),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            STREAMED_CONTENT_TYPE
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let responses = decode_frames(&body).unwrap();
+        assert_eq!(responses.len(), 1);
+        let series = &responses[0].chunked_series[0];
+        assert_eq!(
+            series
+                .labels
+                .iter()
+                .map(|label| (label.name.as_str(), label.value.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(NAME_LABEL, name.as_str()), ("job", "sensapp")]
+        );
+        assert_eq!(series.chunks.len(), 3);
+        let decoded = series
+            .chunks
+            .iter()
+            .flat_map(|chunk| decode_xor_chunk(&chunk.data).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, expected);
+
+        // Samples
+        let response = prometheus_remote_read(
+            State(state),
+            headers,
+            read_request(&name, ResponseType::Samples),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let decompressed = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+        let read_response = ReadResponse::decode(&decompressed[..]).unwrap();
+        let timeseries = &read_response.results[0].timeseries;
+        assert_eq!(timeseries.len(), 1);
+        assert_eq!(
+            timeseries[0]
+                .samples
+                .iter()
+                .map(|sample| (sample.timestamp, sample.value))
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use super::{
+    acks::ack_in_background,
+    app_error::AppError,
+    audit::audit_payload,
+    jobs::{JobStatus, Jobs},
+    request_log::{log_job, record_ingestion},
+    state::{check_accepted_sensors, HttpServerState},
+};
+use crate::{
+    datamodel::batch_builder::BatchBuilder,
+    parsing::{get_parser_from_name, ParseData},
+    storage::sync_timeout::sync_with_timeout,
+};
+use anyhow::Result;
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tokio_util::bytes::Bytes;
+use uuid::Uuid;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PublishQueryParams {
+    /// Returns before the data is stored, and sends the acknowledgement on `/acks`.
+    #[serde(default, rename = "async")]
+    pub async_ack: bool,
+    /// Returns before the data is parsed, with a job id to follow on `/jobs/{job_id}`.
+    #[serde(default)]
+    pub job: bool,
+}
+
+/// Publish data using one of the SensApp parsers.
+///
+/// The parser is selected by name: `senml_json`, `senml_ndjson`, `graphite`
+/// for the Graphite plaintext protocol, `simple_json` for an array of
+/// `{"name", "value", "time", "unit", "labels"}` records, or `arrow` for an
+/// Arrow IPC file (`application/vnd.apache.arrow.file`), as exported by SensApp
+/// or with `timestamp`, `value`, `sensor_name`, and optional `type` columns,
+/// or `nagios_perfdata` for the performance data of Nagios plugins.
+/// Gzip and zstd payloads are decompressed, and the `_gzip` and `_zstd`
+/// suffixes make it mandatory.
+///
+/// By default, the response is sent once the data is stored. With `async=true`,
+/// the response is a 202 Accepted with a `batch_id`, and an acknowledgement
+/// with the same `batch_id` is sent on `/acks` once the data is stored.
+///
+/// For bulk loads, `job=true` returns a 202 Accepted with a `job_id` right away,
+/// and the progress of the ingestion is available on `/jobs/{job_id}`.
+#[utoipa::path(
+    post,
+    path = "/publish/{parser_name}",
+    tag = "SensApp",
+    request_body(
+        content = String,
+        description = "Payload in the format understood by the selected parser.",
+        example = "[{\"n\": \"temperature\", \"u\": \"Cel\", \"v\": 21.5}]"
+    ),
+    params(
+        ("parser_name" = String, Path, description = "Name of the parser", example = "senml_json"),
+        ("async" = Option<bool>, Query, description = "Acknowledge the storage asynchronously on /acks"),
+        ("job" = Option<bool>, Query, description = "Ingest in the background, and follow the progress on /jobs/{job_id}"),
+    ),
+    responses(
+        (status = 202, description = "Accepted, with the batch_id of the acknowledgement or the job_id"),
+        (status = 204, description = "No Content"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn publish_with_parser(
+    State(state): State<HttpServerState>,
+    Path(parser_name): Path<String>,
+    Query(PublishQueryParams { async_ack, job }): Query<PublishQueryParams>,
+    bytes: Bytes,
+) -> Result<Response, AppError> {
+    state.ensure_storage_available()?;
+    let parser = get_parser_from_name(&parser_name).map_err(AppError::BadRequest)?;
+
+    if job {
+        let job_id = state.jobs.create();
+        let jobs = state.jobs.clone();
+        tokio::spawn(async move {
+            let path = format!("/publish/{}", parser_name);
+            let job = run_job(parser, &parser_name, bytes, state, &jobs, job_id);
+            if let Err(error) = log_job(path, job_id, job).await {
+                jobs.fail(job_id, &error);
+            }
+        });
+        return Ok((StatusCode::ACCEPTED, Json(json!({ "job_id": job_id }))).into_response());
+    }
+
+    let mut batch_builder = BatchBuilder::new()?.with_storage(state.storage.clone());
+    parser
+        .parse_data(&bytes, &mut batch_builder)
+        .await
+        .map_err(AppError::invalid_body)?;
+
+    state.ensure_accepted_sensors(&batch_builder).await?;
+    audit_payload(&parser_name, &bytes, &batch_builder).await?;
+    record_ingestion(&batch_builder).await;
+
+    let sample_count = batch_builder.len().await;
+    let waiter = match batch_builder
+        .send_what_is_left(state.event_bus.clone())
+        .await
+    {
+        Ok(waiter) => waiter,
+        Err(error) => {
+            return Err(AppError::InternalServerError(anyhow::anyhow!(error)));
+        }
+    };
+
+    if async_ack {
+        let batch_id = ack_in_background(state.event_bus, waiter, sample_count);
+        return Ok((StatusCode::ACCEPTED, Json(json!({ "batch_id": batch_id }))).into_response());
+    }
+
+    if let Some(mut receiver) = waiter {
+        sync_with_timeout(&mut receiver).await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Parses and stores the data, updating the job as the batches are stored.
+async fn run_job(
+    parser: Box<dyn ParseData>,
+    parser_name: &str,
+    bytes: Bytes,
+    state: HttpServerState,
+    jobs: &Jobs,
+    job_id: Uuid,
+) -> Result<()> {
+    let mut batch_builder = BatchBuilder::new()?.with_storage(state.storage.clone());
+    parser.parse_data(&bytes, &mut batch_builder).await?;
+    let sensors = batch_builder.sensors().await;
+    check_accepted_sensors(state.storage.as_ref(), &sensors, state.type_conflict_policy).await?;
+    audit_payload(parser_name, &bytes, &batch_builder).await?;
+    record_ingestion(&batch_builder).await;
+
+    let uuids = sensors.iter().map(|sensor| sensor.uuid).collect::<Vec<_>>();
+    let sensors_created = state.storage.unknown_sensors(&uuids).await?.len();
+    jobs.update(job_id, |job| job.status = JobStatus::Storing);
+    for (mut waiter, sample_count) in batch_builder.send_batches(state.event_bus).await? {
+        sync_with_timeout(&mut waiter).await?;
+        jobs.update(job_id, |job| job.samples_ingested += sample_count);
+    }
+    jobs.update(job_id, |job| {
+        job.status = JobStatus::Completed;
+        job.sensors_created = sensors_created;
+    });
+    Ok(())
+}
@@ -0,0 +1,425 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::SensAppDateTime;
+use crate::exporters::grafana::to_grafana_target;
+use crate::exporters::json::{sensor_data_to_json, sensor_data_to_json_with_gaps, sensor_to_json};
+use crate::storage::query::{SensorSelector, TimeRange};
+use anyhow::{anyhow, Result};
+use axum::{
+    debug_handler,
+    extract::{Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    #[serde(flatten)]
+    pub selector: SensorSelector,
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub limit: Option<usize>,
+    /// Returns the number of samples of each sensor instead of the samples.
+    #[serde(default)]
+    pub count_only: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct QueryFormatParams {
+    /// `grafana` for the Grafana JSON datasources format.
+    pub format: Option<String>,
+    /// Marks the gaps of the numeric sensors with a nominal interval.
+    #[serde(default)]
+    pub fill_gaps: bool,
+}
+
+/// Query the samples of the sensors matching a selector.
+///
+/// The selector combines an optional list of sensor UUIDs, Prometheus style
+/// label matchers, and a numeric only flag. All the constraints must match,
+/// and the UUIDs or the label matchers are required.
+///
+/// With `count_only`, the samples aren't loaded and each sensor
+/// comes with its `sample_count` in the time range instead.
+///
+/// With `format=grafana`, each sensor is a Grafana target,
+/// `{"target": name, "datapoints": [[value, timestamp_ms], ...]}`.
+///
+/// With `fill_gaps=true`, a null value is inserted in the gaps longer than
+/// the nominal interval of the numeric sensors that have one.
+#[utoipa::path(
+    post,
+    path = "/query",
+    tag = "SensApp",
+    params(
+        ("format" = Option<String>, Query, description = "json by default, or grafana"),
+        ("fill_gaps" = Option<bool>, Query, description = "Null values in the gaps of the evenly sampled sensors"),
+    ),
+    request_body(
+        content = String,
+        content_type = "application/json",
+        description = "Sensor selector, time range in unix seconds, and limit of samples per sensor.",
+        example = json!({
+            "uuids": ["018f1b8a-6b0e-7c8e-9b1a-2f0a8e6f6b3c"],
+            "matchers": [{"name": "room", "value": "kitchen", "type": "="}],
+            "numeric_only": true,
+            "start": 1700000000.0,
+            "end": 1700003600.0,
+            "limit": 1000
+        })
+    ),
+    responses(
+        (status = 200, description = "Samples, or number of samples, of the matching sensors"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn query_sensors(
+    State(state): State<HttpServerState>,
+    Query(QueryFormatParams { format, fill_gaps }): Query<QueryFormatParams>,
+    Json(QueryRequest {
+        selector,
+        start,
+        end,
+        limit,
+        count_only,
+    }): Json<QueryRequest>,
+) -> Result<Json<Vec<Value>>, AppError> {
+    selector.validate().map_err(AppError::BadRequest)?;
+    let grafana = match format.as_deref() {
+        None | Some("json") => false,
+        Some("grafana") => true,
+        Some(format) => {
+            return Err(AppError::BadRequest(anyhow!(
+                "Unsupported query format: {}",
+                format
+            )))
+        }
+    };
+
+    let time_range = TimeRange::new(
+        start.map(SensAppDateTime::from_unix_seconds),
+        end.map(SensAppDateTime::from_unix_seconds),
+    );
+    if fill_gaps && (count_only || grafana) {
+        return Err(AppError::BadRequest(anyhow!(
+            "The gaps can only be filled in the json format"
+        )));
+    }
+    if count_only && grafana {
+        return Err(AppError::BadRequest(anyhow!(
+            "The sample counts can't be returned in the grafana format"
+        )));
+    }
+    if count_only {
+        let counts = state
+            .storage
+            .count_series_by_labels(&selector, time_range)
+            .await?;
+        return Ok(Json(
+            counts
+                .iter()
+                .map(|(sensor, sample_count)| {
+                    json!({
+                        "sensor": sensor_to_json(sensor),
+                        "sample_count": sample_count,
+                    })
+                })
+                .collect(),
+        ));
+    }
+
+    let sensors_data = state.storage.query(&selector, time_range, limit).await?;
+
+    if grafana {
+        return Ok(Json(sensors_data.iter().map(to_grafana_target).collect()));
+    }
+    if fill_gaps {
+        return Ok(Json(
+            sensors_data
+                .iter()
+                .map(sensor_data_to_json_with_gaps)
+                .collect(),
+        ));
+    }
+    Ok(Json(sensors_data.iter().map(sensor_data_to_json).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        sensor_metadata::SensorMetadata,
+        Sample, Sensor, SensorType, TypedSamples,
+    };
+    use crate::ingestors::http::sensor_metadata::update_sensor_metadata;
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use crate::storage::storage::StorageInstance;
+    use axum::extract::Path;
+    use smallvec::smallvec;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_query_uuids_and_matchers() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensors = [
+            ("north", SensorType::Float),
+            ("south", SensorType::Float),
+            ("north", SensorType::String),
+            ("north", SensorType::Float),
+        ]
+        .into_iter()
+        .map(|(zone, sensor_type)| {
+            Arc::new(
+                Sensor::new_without_uuid(
+                    format!("test_query_{}", Uuid::new_v4()),
+                    sensor_type,
+                    None,
+                    Some(smallvec![("zone".to_string(), zone.to_string())]),
+                )
+                .unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+        let datetime = SensAppDateTime::from_unix_seconds_i64(1_700_000_000);
+        let batch = Batch::new(
+            sensors
+                .iter()
+                .map(|sensor| {
+                    let samples = match sensor.sensor_type {
+                        SensorType::Float => TypedSamples::Float(smallvec![Sample {
+                            datetime,
+                            value: 1.0
+                        }]),
+                        _ => TypedSamples::String(smallvec![Sample {
+                            datetime,
+                            value: "one".to_string()
+                        }]),
+                    };
+                    SingleSensorBatch::new(sensor.clone(), samples)
+                })
+                .collect(),
+        );
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+
+        // The last sensor is a numeric sensor in the north zone,
+        // but it's not in the UUID subset.
+        let request = serde_json::json!({
+            "uuids": sensors[..3].iter().map(|sensor| sensor.uuid).collect::<Vec<_>>(),
+            "matchers": [{"name": "zone", "value": "north", "type": "="}],
+            "numeric_only": true,
+        });
+        let Json(result) = query_sensors(
+            State(state.clone()),
+            Query(Default::default()),
+            Json(serde_json::from_value(request).unwrap()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0]["sensor"]["uuid"], sensors[0].uuid.to_string());
+        assert_eq!(result[0]["samples"].as_array().unwrap().len(), 1);
+
+        let request = serde_json::json!({
+            "uuids": sensors.iter().map(|sensor| sensor.uuid).collect::<Vec<_>>(),
+            "matchers": [{"name": "zone", "value": "nor.*", "type": "=~"}],
+        });
+        let Json(result) = query_sensors(
+            State(state.clone()),
+            Query(Default::default()),
+            Json(serde_json::from_value(request).unwrap()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.len(), 3);
+
+        // The counts, without the samples
+        let request = serde_json::json!({
+            "matchers": [{"name": "zone", "value": "north", "type": "="}],
+            "count_only": true,
+        });
+        let Json(result) = query_sensors(
+            State(state.clone()),
+            Query(Default::default()),
+            Json(serde_json::from_value(request).unwrap()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.len(), 3);
+        for sensor in result {
+            assert_eq!(sensor["sample_count"], 1);
+            assert!(sensor.get("samples").is_none());
+        }
+
+        let request = serde_json::json!({
+            "matchers": [{"name": "zone", "value": "(", "type": "=~"}],
+        });
+        let result = query_sensors(
+            State(state.clone()),
+            Query(Default::default()),
+            Json(serde_json::from_value(request).unwrap()),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+
+        // One Grafana target per sensor
+        let request = serde_json::json!({
+            "uuids": sensors.iter().map(|sensor| sensor.uuid).collect::<Vec<_>>(),
+            "numeric_only": true,
+        });
+        let Json(result) = query_sensors(
+            State(state.clone()),
+            Query(QueryFormatParams {
+                format: Some("grafana".to_string()),
+                fill_gaps: false,
+            }),
+            Json(serde_json::from_value(request).unwrap()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.len(), 3);
+        for target in &result {
+            assert!(target["target"]
+                .as_str()
+                .unwrap()
+                .starts_with("test_query_"));
+            assert_eq!(
+                target["datapoints"],
+                serde_json::json!([[1.0, 1_700_000_000_000i64]])
+            );
+        }
+
+        let result = query_sensors(
+            State(state.clone()),
+            Query(QueryFormatParams {
+                format: Some("xml".to_string()),
+                fill_gaps: false,
+            }),
+            Json(serde_json::from_value(serde_json::json!({"uuids": []})).unwrap()),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+
+        // Neither UUIDs nor matchers, it would return every sensor
+        for request in [
+            serde_json::json!({}),
+            serde_json::json!({"numeric_only": true, "count_only": true}),
+        ] {
+            let result = query_sensors(
+                State(state.clone()),
+                Query(Default::default()),
+                Json(serde_json::from_value(request).unwrap()),
+            )
+            .await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_fill_gaps() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_fill_gaps_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        // One sample per minute, the fourth and fifth minutes are missing
+        let samples = TypedSamples::Integer(
+            [0, 1, 2, 5, 6]
+                .into_iter()
+                .map(|minute| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + minute * 60),
+                    value: minute,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        // Set once the sensor exists, as the parsers don't know it
+        update_sensor_metadata(
+            State(state.clone()),
+            Path(sensor.uuid.to_string()),
+            Json(SensorMetadata {
+                nominal_interval_seconds: Some(60.0),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        let request =
+            || Json(serde_json::from_value(serde_json::json!({ "uuids": [sensor.uuid] })).unwrap());
+        let values = |result: &[Value]| {
+            result[0]["samples"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|sample| sample["value"].clone())
+                .collect::<Vec<_>>()
+        };
+
+        let Json(result) =
+            query_sensors(State(state.clone()), Query(Default::default()), request())
+                .await
+                .unwrap();
+        // The nominal interval is persisted
+        assert_eq!(result[0]["sensor"]["nominal_interval_seconds"], 60.0);
+        assert_eq!(values(&result), [0, 1, 2, 5, 6].map(Value::from));
+
+        let Json(result) = query_sensors(
+            State(state.clone()),
+            Query(QueryFormatParams {
+                format: None,
+                fill_gaps: true,
+            }),
+            request(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            values(&result),
+            vec![
+                json!(0),
+                json!(1),
+                json!(2),
+                Value::Null,
+                json!(5),
+                json!(6)
+            ]
+        );
+        assert_eq!(
+            result[0]["samples"][3]["datetime"],
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_180).to_rfc3339()
+        );
+
+        let result = query_sensors(
+            State(state),
+            Query(QueryFormatParams {
+                format: Some("grafana".to_string()),
+                fill_gaps: true,
+            }),
+            request(),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+}
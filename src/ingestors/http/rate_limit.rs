@@ -0,0 +1,211 @@
+use super::app_error::AppError;
+use anyhow::anyhow;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use clru::CLruCache;
+use std::{
+    net::SocketAddr,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Above this number of clients, the least recently seen buckets are forgotten.
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket rate limiter, with one bucket per client.
+///
+/// Each request takes a token, and the buckets are refilled
+/// at `requests_per_second` up to `burst` tokens.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    buckets: Mutex<CLruCache<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// A burst of 0 defaults to one second of requests.
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        let burst = if burst == 0 {
+            requests_per_second.ceil().max(1.0)
+        } else {
+            burst as f64
+        };
+        Self {
+            requests_per_second,
+            burst,
+            buckets: Mutex::new(CLruCache::new(
+                NonZeroUsize::new(MAX_BUCKETS).unwrap_or(NonZeroUsize::MIN),
+            )),
+        }
+    }
+
+    /// Takes a token for the client, or returns how long to wait for one.
+    pub fn check(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut bucket = buckets.get(key).copied().unwrap_or(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        let tokens = self.refill(&bucket, now);
+        bucket.updated_at = now;
+        let result = if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            Ok(())
+        } else {
+            bucket.tokens = tokens;
+            Err(Duration::from_secs_f64(
+                (1.0 - tokens) / self.requests_per_second,
+            ))
+        };
+        buckets.put(key.to_string(), bucket);
+        result
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.requests_per_second).min(self.burst)
+    }
+}
+
+/// The client of the request, by IP address.
+///
+/// The bearer tokens aren't verified on the ingestion routes,
+/// so a client could get a new bucket with each new token.
+fn client_key(request: &Request) -> String {
+    match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(address)) => format!("ip:{}", address.ip()),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Middleware rejecting the requests above the rate limit
+/// with a 429 Too Many Requests and a `Retry-After` header.
+pub async fn rate_limit(
+    State(rate_limiter): State<Option<Arc<RateLimiter>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(rate_limiter) = rate_limiter {
+        if let Err(retry_after) = rate_limiter.check(&client_key(&request), Instant::now()) {
+            return AppError::TooManyRequests(anyhow!("Rate limit exceeded"), retry_after)
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header, StatusCode},
+        middleware,
+        routing::post,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_token_bucket() {
+        let rate_limiter = RateLimiter::new(2.0, 3);
+        let now = Instant::now();
+
+        // The burst, then nothing
+        for _ in 0..3 {
+            rate_limiter.check("a", now).unwrap();
+        }
+        let retry_after = rate_limiter.check("a", now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(500));
+        // Other clients have their own bucket
+        rate_limiter.check("b", now).unwrap();
+
+        // Refilled at 2 requests per second
+        let later = now + Duration::from_millis(500);
+        rate_limiter.check("a", later).unwrap();
+        assert!(rate_limiter.check("a", later).is_err());
+        let much_later = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            rate_limiter.check("a", much_later).unwrap();
+        }
+        assert!(rate_limiter.check("a", much_later).is_err());
+    }
+
+    #[test]
+    fn test_bounded_buckets() {
+        let rate_limiter = RateLimiter::new(1.0, 1);
+        let now = Instant::now();
+        rate_limiter.check("runaway", now).unwrap();
+        for client in 0..MAX_BUCKETS * 2 {
+            if client % 2 == 0 {
+                // Still recently seen, so still limited
+                assert!(rate_limiter.check("runaway", now).is_err());
+            }
+            rate_limiter.check(&client.to_string(), now).unwrap();
+        }
+        let buckets = rate_limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), MAX_BUCKETS);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_layer() {
+        let rate_limiter = Some(Arc::new(RateLimiter::new(1.0, 5)));
+        let app = Router::new()
+            .route("/publish", post(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(rate_limiter, rate_limit));
+
+        let request = |ip: &str, token: &str| {
+            let mut request = axum::http::Request::builder()
+                .method("POST")
+                .uri("/publish")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            let address = SocketAddr::new(ip.parse().unwrap(), 4242);
+            request.extensions_mut().insert(ConnectInfo(address));
+            request
+        };
+
+        let mut statuses = Vec::new();
+        for attempt in 0..10 {
+            // A new token for each request doesn't reset the limit
+            let token = format!("runaway-{}", attempt);
+            let response = app
+                .clone()
+                .oneshot(request("192.0.2.1", &token))
+                .await
+                .unwrap();
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "1");
+            }
+            statuses.push(response.status());
+        }
+        let too_many = statuses
+            .iter()
+            .filter(|status| **status == StatusCode::TOO_MANY_REQUESTS)
+            .count();
+        assert!(too_many >= 4, "{:?}", statuses);
+        assert_eq!(statuses[0], StatusCode::OK);
+
+        // Another client isn't limited
+        let response = app
+            .oneshot(request("192.0.2.2", "runaway-0"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,213 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::config;
+use crate::storage::raw_sql::{validate_read_only_select, RawSqlResult};
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::State,
+    http::{header, HeaderMap},
+    Json,
+};
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize)]
+pub struct RawSqlRequest {
+    pub sql: String,
+}
+
+/// Checks the bearer token, comparing hashes to not leak the token length or prefix.
+fn check_token(headers: &HeaderMap, token: &str) -> Result<(), AppError> {
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| AppError::Unauthorized(anyhow!("Missing bearer token")))?;
+    if blake3::hash(provided.as_bytes()) != blake3::hash(token.as_bytes()) {
+        return Err(AppError::Unauthorized(anyhow!("Invalid bearer token")));
+    }
+    Ok(())
+}
+
+/// Run a read-only SQL query on the storage backend.
+///
+/// Only a single `SELECT` statement is allowed, and it runs on a read-only
+/// connection with a row limit and a timeout, failing with a 504 status
+/// when it takes longer. The endpoint must be enabled with
+/// `SENSAPP_ENABLE_RAW_SQL` and requires the `SENSAPP_RAW_SQL_TOKEN`
+/// bearer token. The SQLite, PostgreSQL, TimescaleDB and DuckDB backends
+/// support it.
+#[utoipa::path(
+    post,
+    path = "/query/sql",
+    tag = "SensApp",
+    request_body(
+        content = String,
+        content_type = "application/json",
+        description = "The SQL query.",
+        example = json!({"sql": "SELECT name, type FROM sensors"})
+    ),
+    responses(
+        (status = 200, description = "Columns and rows of the result"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 401, description = "Unauthorized", body = AppError),
+        (status = 404, description = "Raw SQL queries are disabled", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+        (status = 501, description = "The storage backend doesn't support raw SQL queries", body = AppError),
+        (status = 504, description = "The query timed out", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn query_raw_sql(
+    State(state): State<HttpServerState>,
+    headers: HeaderMap,
+    Json(RawSqlRequest { sql }): Json<RawSqlRequest>,
+) -> Result<Json<RawSqlResult>, AppError> {
+    let config = config::get()?;
+    let token = match (config.enable_raw_sql, &config.raw_sql_token) {
+        (true, Some(token)) if !token.is_empty() => token,
+        _ => return Err(AppError::NotFound(anyhow!("Raw SQL queries are disabled"))),
+    };
+    check_token(&headers, token)?;
+    state.ensure_storage_available()?;
+
+    validate_read_only_select(&sql).map_err(AppError::BadRequest)?;
+
+    // The backends stop the query itself at the timeout,
+    // not only the request.
+    let timeout = Duration::from_secs(config.raw_sql_timeout_seconds);
+    let result = state
+        .storage
+        .raw_sql_query(&sql, config.raw_sql_max_rows, timeout)
+        .await?;
+
+    Ok(Json(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::storage::query::InvalidQueryError;
+    use crate::storage::raw_sql::RawSqlTimeoutError;
+    use crate::storage::storage::UnsupportedError;
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use axum::http::HeaderValue;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_check_token() {
+        let mut headers = HeaderMap::new();
+        assert!(check_token(&headers, "secret").is_err());
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer nope"),
+        );
+        assert!(check_token(&headers, "secret").is_err());
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        assert!(check_token(&headers, "secret").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_query_raw_sql() {
+        _ = load_configuration();
+        // Disabled by default
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+        let state = HttpServerState::for_tests(Arc::new(storage));
+        let result = query_raw_sql(
+            State(state.clone()),
+            HeaderMap::new(),
+            Json(RawSqlRequest {
+                sql: "SELECT 1".to_string(),
+            }),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        // The storage runs the SELECT queries
+        let result = state
+            .storage
+            .raw_sql_query(
+                "SELECT 1 AS one, 'two' AS two, NULL AS three",
+                10,
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.columns, vec!["one", "two", "three"]);
+        assert_eq!(result.rows, vec![vec![json!(1), json!("two"), json!(null)]]);
+        assert!(!result.truncated);
+
+        let result = state
+            .storage
+            .raw_sql_query(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 5) SELECT x FROM n",
+                3,
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.rows.len(), 3);
+        assert!(result.truncated);
+
+        // And rejects the others
+        for sql in [
+            "DELETE FROM sensors",
+            "UPDATE sensors SET name = 'hacked'",
+            "SELECT * FROM missing_table",
+        ] {
+            let error = state
+                .storage
+                .raw_sql_query(sql, 10, Duration::from_secs(5))
+                .await
+                .unwrap_err();
+            assert!(error.is::<InvalidQueryError>(), "{}", sql);
+            assert!(matches!(AppError::from(error), AppError::BadRequest(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_raw_sql_interrupted() {
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        // Never ends, and returns no row before the end
+        let start = Instant::now();
+        let error = storage
+            .raw_sql_query(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) SELECT COUNT(*) FROM n",
+                10,
+                Duration::from_millis(100),
+            )
+            .await
+            .unwrap_err();
+        assert!(error.is::<RawSqlTimeoutError>());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(matches!(AppError::from(error), AppError::GatewayTimeout(_)));
+
+        // The storage is still usable
+        let result = storage
+            .raw_sql_query("SELECT 1", 10, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![json!(1)]]);
+    }
+
+    #[test]
+    fn test_query_raw_sql_errors() {
+        // The backends without raw SQL, and the internal failures
+        let error = anyhow::Error::from(UnsupportedError("Running raw SQL queries"));
+        assert!(matches!(AppError::from(error), AppError::NotImplemented(_)));
+        let error = anyhow::Error::from(sqlx::Error::PoolTimedOut);
+        assert!(matches!(
+            AppError::from(error),
+            AppError::InternalServerError(_)
+        ));
+    }
+}
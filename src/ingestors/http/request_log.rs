@@ -0,0 +1,336 @@
+use crate::datamodel::batch_builder::BatchBuilder;
+use axum::{
+    extract::{OriginalUri, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::cell::Cell;
+use std::future::Future;
+use std::io::Write;
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing_subscriber::{fmt::MakeWriter, layer::Context, Layer};
+use uuid::Uuid;
+
+/// Target of the request log events.
+pub const REQUEST_LOG_TARGET: &str = "sensapp::requests";
+
+/// Format of the request log, one line per request on the standard output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestLogFormat {
+    Off,
+    /// `POST /api/v2/write 204 3.2ms sensors=2 samples=10`
+    Text,
+    /// One JSON object per line, for the log aggregators.
+    Json,
+}
+
+/// The sensors and samples parsed from an ingestion request,
+/// in the extensions of its response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestionCounts {
+    pub sensors: usize,
+    pub samples: usize,
+}
+
+tokio::task_local! {
+    static INGESTION_COUNTS: Cell<Option<IngestionCounts>>;
+}
+
+/// Records the counts of the parsed batch, for the request log.
+///
+/// The counts of the streams add up over their batches.
+/// Does nothing outside of a request or a [`log_job`].
+pub async fn record_ingestion(batch_builder: &BatchBuilder) {
+    let sensors = batch_builder.sensors_len().await;
+    let samples = batch_builder.len().await;
+    _ = INGESTION_COUNTS.try_with(|counts| {
+        let recorded = counts.get().unwrap_or_default();
+        counts.set(Some(IngestionCounts {
+            sensors: recorded.sensors + sensors,
+            samples: recorded.samples + samples,
+        }));
+    });
+}
+
+/// Adds the [`IngestionCounts`] to the response extensions, and logs the
+/// request with its method, path, status, duration, and counts.
+pub async fn log_requests(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    // The full path, when the routes are nested under the base path
+    let path = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let start = Instant::now();
+
+    let (mut response, counts) = INGESTION_COUNTS
+        .scope(Cell::new(None), async {
+            let response = next.run(request).await;
+            (response, INGESTION_COUNTS.with(Cell::get))
+        })
+        .await;
+    let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let status = response.status().as_u16();
+
+    match counts {
+        Some(counts) => {
+            response.extensions_mut().insert(counts);
+            tracing::info!(
+                target: REQUEST_LOG_TARGET,
+                method = method.as_str(),
+                path,
+                status,
+                duration_ms,
+                sensors = counts.sensors,
+                samples = counts.samples,
+            );
+        }
+        None => tracing::info!(
+            target: REQUEST_LOG_TARGET,
+            method = method.as_str(),
+            path,
+            status,
+            duration_ms,
+        ),
+    }
+    response
+}
+
+/// Runs a background ingestion job and logs it like a request once
+/// it's done, with the counts it recorded, as its request returned
+/// before the ingestion. The status is the final status of the job.
+pub async fn log_job<F>(path: String, job_id: Uuid, job: F) -> anyhow::Result<()>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    let start = Instant::now();
+    let (result, counts) = INGESTION_COUNTS
+        .scope(Cell::new(None), async {
+            let result = job.await;
+            (result, INGESTION_COUNTS.with(Cell::get))
+        })
+        .await;
+    let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let status = if result.is_ok() {
+        "completed"
+    } else {
+        "failed"
+    };
+    let counts = counts.unwrap_or_default();
+    tracing::info!(
+        target: REQUEST_LOG_TARGET,
+        method = "JOB",
+        path,
+        status,
+        duration_ms,
+        sensors = counts.sensors,
+        samples = counts.samples,
+        job_id = %job_id,
+    );
+    result
+}
+
+/// The fields of a request log event, in their order.
+#[derive(Default)]
+struct RequestLogFields(Map<String, Value>);
+
+impl Visit for RequestLogFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        // Rounded to the microsecond
+        let value = (value * 1000.0).round() / 1000.0;
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(
+            field.name().to_string(),
+            Value::from(format!("{:?}", value)),
+        );
+    }
+}
+
+impl RequestLogFields {
+    fn to_text(&self) -> String {
+        let field = |name: &str| match self.0.get(name) {
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => "-".to_string(),
+        };
+        let mut line = format!(
+            "{} {} {} {}ms",
+            field("method"),
+            field("path"),
+            field("status"),
+            field("duration_ms")
+        );
+        for name in ["sensors", "samples"] {
+            if let Some(value) = self.0.get(name) {
+                line.push_str(&format!(" {}={}", name, value));
+            }
+        }
+        line
+    }
+}
+
+/// A tracing layer writing the request log events, as text or JSON lines.
+pub struct RequestLogLayer<W> {
+    format: RequestLogFormat,
+    make_writer: W,
+}
+
+impl<W> RequestLogLayer<W> {
+    pub fn new(format: RequestLogFormat, make_writer: W) -> Self {
+        Self {
+            format,
+            make_writer,
+        }
+    }
+}
+
+impl<S, W> Layer<S> for RequestLogLayer<W>
+where
+    S: tracing::Subscriber,
+    W: for<'writer> MakeWriter<'writer> + 'static,
+{
+    fn on_event(&self, event: &tracing::Event<'_>, _context: Context<'_, S>) {
+        if event.metadata().target() != REQUEST_LOG_TARGET {
+            return;
+        }
+        let mut fields = RequestLogFields::default();
+        event.record(&mut fields);
+        let line = match self.format {
+            RequestLogFormat::Off => return,
+            RequestLogFormat::Text => fields.to_text(),
+            RequestLogFormat::Json => Value::Object(fields.0).to_string(),
+        };
+        _ = writeln!(self.make_writer.make_writer(), "{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::EventBus;
+    use crate::config::load_configuration;
+    use crate::ingestors::http::{publish::publish_with_parser, state::HttpServerState};
+    use crate::storage::sqlite::SqliteStorage;
+    use axum::{body::Body, routing::post, Router};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct CapturedLines(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for CapturedLines {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLines {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    async fn ingest(format: RequestLogFormat) -> Vec<String> {
+        let captured = CapturedLines::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(RequestLogLayer::new(format, move || writer.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let event_bus = Arc::new(EventBus::init("test".to_string()));
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        tokio::spawn(async move {
+            while let Ok(crate::bus::message::Message::Publish(message)) = receiver.recv().await {
+                message.sync_sender.broadcast(()).await.unwrap();
+            }
+        });
+        let app = Router::new()
+            .route("/publish/:parser_name", post(publish_with_parser))
+            .layer(axum::middleware::from_fn(log_requests))
+            .with_state(HttpServerState {
+                event_bus,
+                ..HttpServerState::for_tests(Arc::new(
+                    SqliteStorage::connect("sqlite::memory:").await.unwrap(),
+                ))
+            });
+
+        let payload = r#"[
+            {"bn": "request_log:", "n": "temperature", "v": 21.5, "t": 1700000000},
+            {"n": "temperature", "v": 21.6, "t": 1700000001},
+            {"n": "door", "vb": true, "t": 1700000000}
+        ]"#;
+        let request = Request::post("/publish/senml_json")
+            .body(Body::from(payload))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.extensions().get::<IngestionCounts>(),
+            Some(&IngestionCounts {
+                sensors: 2,
+                samples: 3
+            })
+        );
+
+        let request = Request::post("/publish/unknown")
+            .body(Body::from("nothing"))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.extensions().get::<IngestionCounts>().is_none());
+
+        captured.lines()
+    }
+
+    #[tokio::test]
+    async fn test_request_log() {
+        _ = load_configuration();
+
+        let lines = ingest(RequestLogFormat::Json).await;
+        assert_eq!(lines.len(), 2);
+        let line: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(line["method"], "POST");
+        assert_eq!(line["path"], "/publish/senml_json");
+        assert_eq!(line["status"], 204);
+        assert!(line["duration_ms"].as_f64().unwrap() >= 0.0);
+        assert_eq!(line["sensors"], 2);
+        assert_eq!(line["samples"], 3);
+        let line: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(line["status"], 400);
+        assert!(line.get("samples").is_none());
+
+        let lines = ingest(RequestLogFormat::Text).await;
+        assert!(lines[0].starts_with("POST /publish/senml_json 204 "));
+        assert!(lines[0].ends_with("ms sensors=2 samples=3"));
+
+        assert!(ingest(RequestLogFormat::Off).await.is_empty());
+    }
+}
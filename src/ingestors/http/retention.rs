@@ -0,0 +1,109 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::SensAppDateTime;
+use axum::{debug_handler, extract::State, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Deserialize)]
+pub struct RetentionRequest {
+    /// Unix seconds.
+    pub older_than: f64,
+}
+
+/// Delete the samples older than a cutoff, for all the sensors.
+///
+/// TimescaleDB drops the chunks entirely before the cutoff,
+/// and deletes the remaining old samples row by row.
+#[utoipa::path(
+    post,
+    path = "/retention",
+    tag = "SensApp",
+    request_body(
+        content = String,
+        content_type = "application/json",
+        description = "Cutoff in unix seconds, the samples before it are deleted.",
+        example = json!({
+            "older_than": 1700000000.0
+        })
+    ),
+    responses(
+        (status = 200, description = "Samples deleted"),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn apply_retention(
+    State(state): State<HttpServerState>,
+    Json(RetentionRequest { older_than }): Json<RetentionRequest>,
+) -> Result<Json<Value>, AppError> {
+    let older_than = SensAppDateTime::from_unix_seconds(older_than);
+    state.ensure_storage_available()?;
+    state.storage.apply_retention(older_than).await?;
+    Ok(Json(json!({ "older_than": older_than.to_rfc3339() })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_apply_retention() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid("retention".to_string(), SensorType::Integer, None, None)
+                .unwrap(),
+        );
+        let samples = |seconds: std::ops::Range<i64>| {
+            TypedSamples::Integer(
+                seconds
+                    .map(|second| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + second),
+                        value: second,
+                    })
+                    .collect(),
+            )
+        };
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            samples(0..10)
+        )]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        let storage = Arc::new(storage);
+
+        let state = HttpServerState::for_tests(storage.clone());
+        let Json(response) = apply_retention(
+            State(state),
+            Json(RetentionRequest {
+                older_than: 1_700_000_004.0,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response["older_than"],
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_004).to_rfc3339()
+        );
+
+        let sensor_data = storage
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.samples, samples(4..10));
+    }
+}
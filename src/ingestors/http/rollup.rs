@@ -0,0 +1,86 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::SensAppDateTime;
+use crate::storage::rollup::apply_rollups;
+use axum::{debug_handler, extract::State, Json};
+use serde_json::{json, Value};
+
+/// Roll up the samples of the sensors with a downsampling policy.
+///
+/// The averages of the samples older than the raw retention window are
+/// published in the companion `<name>__rollup_<interval>__` sensors.
+/// The same maintenance task runs every `SENSAPP_ROLLUP_TASK_INTERVAL_SECONDS`.
+#[utoipa::path(
+    post,
+    path = "/internal/rollup",
+    tag = "SensApp",
+    responses(
+        (status = 200, description = "Rollups written", body = Value, example = json!({
+            "sensors": 1,
+            "buckets": 24
+        })),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn apply_rollup(State(state): State<HttpServerState>) -> Result<Json<Value>, AppError> {
+    state.ensure_storage_available()?;
+    let report = apply_rollups(state.storage.as_ref(), SensAppDateTime::now()?).await?;
+    if report.buckets > 0 {
+        // The first rollups create the companion sensors
+        state.catalog_cache.invalidate();
+    }
+    Ok(Json(json!(report)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        downsampling_policy::DownsamplingPolicy,
+        sensapp_datetime::SensAppDateTimeExt,
+        Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::{rollup::rollup_sensor, sqlite::SqliteStorage, storage::StorageInstance};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_apply_rollup() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid("rollup".to_string(), SensorType::Integer, None, None)
+                .unwrap()
+                .with_downsampling(Some(DownsamplingPolicy::new(30.0, 900).unwrap())),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            TypedSamples::one_integer(42, SensAppDateTime::from_unix_seconds_i64(1_700_000_000)),
+        )]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        let storage = Arc::new(storage);
+
+        let state = HttpServerState::for_tests(storage.clone());
+        let Json(response) = apply_rollup(State(state)).await.unwrap();
+        assert_eq!(response, json!({ "sensors": 1, "buckets": 1 }));
+
+        let rollup_sensor = rollup_sensor(&sensor, &sensor.downsampling.unwrap()).unwrap();
+        assert_eq!(rollup_sensor.name, "rollup__rollup_15m__");
+        let rollups = storage
+            .query_sensor_data(rollup_sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            rollups.samples,
+            TypedSamples::one_float(42.0, SensAppDateTime::from_unix_seconds_i64(1_699_999_200))
+        );
+    }
+}
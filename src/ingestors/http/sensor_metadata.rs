@@ -0,0 +1,172 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::datamodel::sensor_metadata::SensorMetadata;
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use uuid::Uuid;
+
+/// Replace the metadata of a sensor.
+///
+/// The metadata aren't part of the sensor UUID, so they can be set once
+/// the sensor exists. The missing fields are cleared.
+#[utoipa::path(
+    put,
+    path = "/sensors/{sensor_uuid}/metadata",
+    tag = "SensApp",
+    params(
+        ("sensor_uuid" = String, Path, description = "UUID of the sensor"),
+    ),
+    request_body(
+        content = String,
+        content_type = "application/json",
+        description = "Value range of the sensor, for the range violation policy, \
+            expected seconds between two samples, to detect the gaps, \
+            and downsampling policy of the samples older than the raw retention.",
+        example = json!({
+            "min_value": -50.0,
+            "max_value": 60.0,
+            "nominal_interval_seconds": 60.0,
+            "downsampling": {
+                "raw_retention_days": 7.0,
+                "rollup_interval_seconds": 3600
+            }
+        })
+    ),
+    responses(
+        (status = 204, description = "Metadata updated"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 404, description = "Sensor Not Found", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn update_sensor_metadata(
+    State(state): State<HttpServerState>,
+    Path(sensor_uuid): Path<String>,
+    Json(metadata): Json<SensorMetadata>,
+) -> Result<StatusCode, AppError> {
+    let sensor_uuid = Uuid::parse_str(&sensor_uuid)
+        .map_err(|_| AppError::BadRequest(anyhow!("Invalid sensor UUID: {}", sensor_uuid)))?;
+    metadata.validate().map_err(AppError::BadRequest)?;
+    state.ensure_storage_available()?;
+
+    if !state
+        .storage
+        .update_sensor_metadata(sensor_uuid, &metadata)
+        .await?
+    {
+        return Err(AppError::NotFound(anyhow!(
+            "Sensor not found: {}",
+            sensor_uuid
+        )));
+    }
+    state.catalog_cache.invalidate();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{
+        event_bus::init_event_bus,
+        message::{Message, PublishMessage},
+    };
+    use crate::config::load_configuration;
+    use crate::datamodel::{downsampling_policy::DownsamplingPolicy, Sensor, SensorType};
+    use crate::ingestors::http::publish::{publish_with_parser, PublishQueryParams};
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use axum::{body::Bytes, extract::Query};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_update_sensor_metadata() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+        let storage = Arc::new(storage);
+        let event_bus = init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let storage_for_publish = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(Message::Publish(PublishMessage {
+                batch, sync_sender, ..
+            })) = receiver.recv().await
+            {
+                _ = storage_for_publish.publish(batch, sync_sender).await;
+            }
+        });
+        let state = HttpServerState {
+            event_bus,
+            ..HttpServerState::for_tests(storage.clone())
+        };
+
+        publish_with_parser(
+            State(state.clone()),
+            Path("senml_json".to_string()),
+            Query(PublishQueryParams::default()),
+            Bytes::from(r#"[{"n": "temperature", "v": 21.5}]"#),
+        )
+        .await
+        .unwrap();
+        let sensor =
+            Sensor::new_without_uuid("temperature".to_string(), SensorType::Float, None, None)
+                .unwrap();
+
+        let metadata = SensorMetadata {
+            min_value: Some(-50.0),
+            max_value: Some(60.0),
+            nominal_interval_seconds: Some(60.0),
+            downsampling: Some(DownsamplingPolicy::new(7.0, 3600).unwrap()),
+        };
+        let status_code = update_sensor_metadata(
+            State(state.clone()),
+            Path(sensor.uuid.to_string()),
+            Json(metadata),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status_code, StatusCode::NO_CONTENT);
+        let stored_sensor = storage.get_sensor(sensor.uuid).await.unwrap().unwrap();
+        assert_eq!(stored_sensor.min_value, Some(-50.0));
+        assert_eq!(stored_sensor.max_value, Some(60.0));
+        assert_eq!(stored_sensor.nominal_interval_seconds, Some(60.0));
+        assert_eq!(stored_sensor.downsampling, metadata.downsampling);
+
+        let result = update_sensor_metadata(
+            State(state.clone()),
+            Path(Uuid::new_v4().to_string()),
+            Json(metadata),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        let result = update_sensor_metadata(
+            State(state.clone()),
+            Path(sensor.uuid.to_string()),
+            Json(SensorMetadata {
+                min_value: Some(60.0),
+                max_value: Some(-50.0),
+                ..Default::default()
+            }),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+
+        let result = update_sensor_metadata(
+            State(state),
+            Path(sensor.uuid.to_string()),
+            Json(
+                serde_json::from_str(
+                    r#"{"downsampling": {"raw_retention_days": 7, "rollup_interval_seconds": 0}}"#,
+                )
+                .unwrap(),
+            ),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::Result;
+use axum::{body::Body, extract::ConnectInfo, http::Request, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{conn::auto, graceful::GracefulShutdown},
+};
+use socket2::{SockRef, TcpKeepalive};
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, sync::Semaphore};
+use tower::ServiceExt;
+
+/// Connection limits of the HTTP server.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServeOptions {
+    /// Connections served at the same time, the others wait
+    /// in the listen backlog. 0 for no limit.
+    pub max_connections: usize,
+    /// Time for the clients to send the request headers, against
+    /// slowloris clients. `None` to wait forever.
+    pub header_timeout: Option<Duration>,
+    /// Idle time before the TCP keep-alive probes, `None` to not probe.
+    pub keepalive: Option<Duration>,
+}
+
+impl ServeOptions {
+    pub fn from_config(config: &crate::config::SensAppConfig) -> Self {
+        let seconds = |seconds: u64| (seconds > 0).then(|| Duration::from_secs(seconds));
+        Self {
+            max_connections: config.http_max_connections,
+            header_timeout: seconds(config.http_header_timeout_seconds),
+            keepalive: seconds(config.http_keepalive_seconds),
+        }
+    }
+}
+
+/// Serves the application like `axum::serve`, with the connection limits.
+///
+/// The connected address is available with the
+/// `ConnectInfo<SocketAddr>` extractor. The open connections
+/// are drained when the shutdown future completes.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    options: ServeOptions,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let semaphore =
+        (options.max_connections > 0).then(|| Arc::new(Semaphore::new(options.max_connections)));
+
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(options.header_timeout);
+
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let permit = match &semaphore {
+            Some(semaphore) => tokio::select! {
+                permit = semaphore.clone().acquire_owned() => Some(permit?),
+                _ = &mut shutdown => break,
+            },
+            None => None,
+        };
+        let (stream, address) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    // Usually too many open files, it's worth waiting a bit
+                    tracing::warn!("Failed to accept a connection: {}", error);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        if let Some(keepalive) = options.keepalive {
+            let keepalive = TcpKeepalive::new().with_time(keepalive);
+            if let Err(error) = SockRef::from(&stream).set_tcp_keepalive(&keepalive) {
+                tracing::warn!("Failed to set the TCP keep-alive: {}", error);
+            }
+        }
+
+        let app = app.clone();
+        let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+            let mut request = request.map(Body::new);
+            request.extensions_mut().insert(ConnectInfo(address));
+            app.clone().oneshot(request)
+        });
+        let connection = builder
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(error) = connection.await {
+                tracing::debug!("HTTP connection from {} closed: {}", address, error);
+            }
+            drop(permit);
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+
+    async fn start_server(options: ServeOptions) -> (SocketAddr, oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app =
+            Router::new().route(
+                "/",
+                get(|ConnectInfo(address): ConnectInfo<SocketAddr>| async move {
+                    address.ip().to_string()
+                }),
+            );
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        tokio::spawn(serve(listener, app, options, async {
+            let _ = shutdown_receiver.await;
+        }));
+        (address, shutdown_sender)
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let (address, _shutdown) = start_server(ServeOptions::default()).await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("127.0.0.1"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_header_timeout() {
+        let (address, _shutdown) = start_server(ServeOptions {
+            header_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        })
+        .await;
+
+        // A slowloris client, sending the headers one byte at a time
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHo").await.unwrap();
+        let started = tokio::time::Instant::now();
+        let mut buffer = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buffer))
+            .await
+            .expect("the connection should be dropped");
+        // Closed, or reset, without a response
+        assert!(read.map(|_| buffer.is_empty()).unwrap_or(true));
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let (address, _shutdown) = start_server(ServeOptions {
+            max_connections: 1,
+            ..Default::default()
+        })
+        .await;
+
+        // The first connection is served, and stays open
+        let mut first = TcpStream::connect(address).await.unwrap();
+        first
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buffer = [0; 12];
+        first.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"HTTP/1.1 200");
+
+        // The second one waits for the first one to close
+        let mut second = TcpStream::connect(address).await.unwrap();
+        second
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buffer = [0; 12];
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), second.read_exact(&mut buffer))
+                .await
+                .is_err()
+        );
+        drop(first);
+        let mut response = String::new();
+        second.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+}
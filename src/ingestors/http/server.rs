@@ -1,28 +1,77 @@
+use super::acks::acks;
+use super::aggregate::aggregate_sensor;
+use super::annotations::{add_annotation, query_annotations};
 use super::app_error::AppError;
-use super::crud::list_sensors;
-use super::influxdb::publish_influxdb;
+use super::catalog::catalog;
+use super::concurrency_limit::limit_concurrency;
+use super::crud::{get_sensor, list_sensors};
+use super::export::export_sensor;
+use super::health::health;
+use super::influxdb::{publish_influxdb, publish_influxdb_v1};
+use super::jobs::get_job;
+use super::latest::latest_samples;
+use super::metrics::delete_metric;
+use super::msgpack::msgpack_responses;
 use super::prometheus::publish_prometheus;
+use super::prometheus_query::{prometheus_query, prometheus_query_range};
+use super::prometheus_read::prometheus_remote_read;
+use super::publish::publish_with_parser;
+use super::query::query_sensors;
+use super::rate_limit::{rate_limit, RateLimiter};
+use super::raw_sql::query_raw_sql;
+use super::request_log::log_requests;
+use super::retention::apply_retention;
+use super::rollup::apply_rollup;
+use super::sensor_metadata::update_sensor_metadata;
+use super::serve::{serve, ServeOptions};
 use super::state::HttpServerState;
-use crate::config;
-use crate::importers::csv::publish_csv_async;
+use super::stream::publish_stream;
+use super::verify::verify_sensor;
+use crate::config::{self, SensAppConfig};
+use crate::importers::csv::{publish_csv_async, CsvColumnMapping};
+use crate::parsing::compressed::decompress_reader_if_compressed;
+use crate::storage::sync_timeout::sync_with_timeout;
 use anyhow::Result;
 use axum::extract::DefaultBodyLimit;
 use axum::extract::Request;
 //use axum::extract::Multipart;
-//use axum::extract::Path;
-use crate::ingestors::http::crud::__path_list_sensors;
-use crate::ingestors::http::influxdb::__path_publish_influxdb;
+use crate::ingestors::http::acks::__path_acks;
+use crate::ingestors::http::aggregate::__path_aggregate_sensor;
+use crate::ingestors::http::annotations::{__path_add_annotation, __path_query_annotations};
+use crate::ingestors::http::catalog::__path_catalog;
+use crate::ingestors::http::crud::{__path_get_sensor, __path_list_sensors};
+use crate::ingestors::http::export::__path_export_sensor;
+use crate::ingestors::http::health::__path_health;
+use crate::ingestors::http::influxdb::{__path_publish_influxdb, __path_publish_influxdb_v1};
+use crate::ingestors::http::jobs::__path_get_job;
+use crate::ingestors::http::latest::__path_latest_samples;
+use crate::ingestors::http::metrics::__path_delete_metric;
 use crate::ingestors::http::prometheus::__path_publish_prometheus;
-use axum::extract::State;
+use crate::ingestors::http::prometheus_query::{
+    __path_prometheus_query, __path_prometheus_query_range,
+};
+use crate::ingestors::http::prometheus_read::__path_prometheus_remote_read;
+use crate::ingestors::http::publish::__path_publish_with_parser;
+use crate::ingestors::http::query::__path_query_sensors;
+use crate::ingestors::http::raw_sql::__path_query_raw_sql;
+use crate::ingestors::http::retention::__path_apply_retention;
+use crate::ingestors::http::rollup::__path_apply_rollup;
+use crate::ingestors::http::sensor_metadata::__path_update_sensor_metadata;
+use crate::ingestors::http::stream::__path_publish_stream;
+use crate::ingestors::http::verify::__path_verify_sensor;
+use axum::extract::{Path, Query, State};
 use axum::http::header;
 use axum::http::StatusCode;
+use axum::routing::delete;
 use axum::routing::get;
 use axum::routing::post;
+use axum::routing::put;
 use axum::Json;
 use axum::Router;
 use futures::TryStreamExt;
 use polars::prelude::*;
 use sentry::integrations::tower::NewSentryLayer;
+use serde::Deserialize;
 use std::io;
 use std::io::Cursor;
 use std::net::SocketAddr;
@@ -41,17 +90,82 @@ use utoipa_scalar::{Scalar, Servable as ScalarServable};
     tags(
         (name = "SensApp", description = "SensApp API"),
         (name = "InfluxDB", description = "InfluxDB Write API"),
-        (name = "Prometheus", description = "Prometheus Remote Write API"),
+        (name = "Prometheus", description = "Prometheus Remote Write, Read, and Query APIs"),
+    ),
+    paths(
+        frontpage,
+        health,
+        list_sensors,
+        get_sensor,
+        update_sensor_metadata,
+        latest_samples,
+        catalog,
+        export_sensor,
+        verify_sensor,
+        aggregate_sensor,
+        add_annotation,
+        query_annotations,
+        query_sensors,
+        query_raw_sql,
+        apply_retention,
+        apply_rollup,
+        delete_metric,
+        publish_with_parser,
+        publish_stream,
+        acks,
+        get_job,
+        publish_influxdb,
+        publish_influxdb_v1,
+        publish_prometheus,
+        prometheus_remote_read,
+        prometheus_query,
+        prometheus_query_range
     ),
-    paths(frontpage, list_sensors, publish_influxdb, publish_prometheus),
 )]
 struct ApiDoc;
 
 pub async fn run_http_server(state: HttpServerState, address: SocketAddr) -> Result<()> {
     let config = config::get()?;
+    let app = app(state, &config)?;
+
+    // Run our application
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    serve(
+        listener,
+        app,
+        ServeOptions::from_config(&config),
+        shutdown_signal(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The routes of SensApp, under the base path of the configuration.
+fn app(state: HttpServerState, config: &SensAppConfig) -> Result<Router> {
     let max_body_layer = DefaultBodyLimit::max(config.parse_http_body_limit()?);
     let timeout_seconds = config.http_server_timeout_seconds;
 
+    // The ingestion rate limiter, per client IP
+    let rate_limiter = (config.rate_limit_rps > 0.0).then(|| {
+        Arc::new(RateLimiter::new(
+            config.rate_limit_rps,
+            config.rate_limit_burst,
+        ))
+    });
+    let rate_limit_layer = axum::middleware::from_fn_with_state(rate_limiter, rate_limit);
+
+    // Separate concurrency limits for the ingestion and the queries
+    let ingestion_limit_layer = axum::middleware::from_fn_with_state(
+        state.concurrency.ingestion.clone(),
+        limit_concurrency,
+    );
+    let query_limit_layer =
+        axum::middleware::from_fn_with_state(state.concurrency.queries.clone(), limit_concurrency);
+
+    // MessagePack responses for the clients asking for them
+    let msgpack_layer = axum::middleware::from_fn(msgpack_responses);
+
     // Initialize tracing
     /*tracing_subscriber::fmt()
     .with_target(false)
@@ -78,42 +192,149 @@ pub async fn run_http_server(state: HttpServerState, address: SocketAddr) -> Res
     // Create our application with a single route
     let app = Router::new()
         .route("/", get(frontpage))
+        .route("/health", get(health))
         //.route("/api-docs/openapi.json", get(openapi))
         .merge(Scalar::with_url("/docs", ApiDoc::openapi()))
         .route(
             "/publish",
-            post(publish_handler).layer(max_body_layer.clone()),
+            post(publish_handler)
+                .layer(max_body_layer.clone())
+                .layer(ingestion_limit_layer.clone())
+                .layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/publish/:parser_name",
+            post(publish_with_parser)
+                .layer(max_body_layer.clone())
+                .layer(ingestion_limit_layer.clone())
+                .layer(rate_limit_layer.clone()),
         )
+        .route("/acks", get(acks))
+        .route("/jobs/:job_id", get(get_job))
         .route(
             "/sensors/:sensor_name_or_uuid/publish_csv",
-            post(publish_csv),
+            post(publish_csv)
+                .layer(ingestion_limit_layer.clone())
+                .layer(rate_limit_layer.clone()),
         )
         .route(
             "/sensors/:sensor_name_or_uuid/publish_multipart",
-            post(publish_multipart).layer(max_body_layer.clone()),
+            post(publish_multipart)
+                .layer(max_body_layer.clone())
+                .layer(ingestion_limit_layer.clone())
+                .layer(rate_limit_layer.clone()),
         )
         // Boring Sensor CRUD
-        .route("/sensors", get(list_sensors))
+        .route("/sensors", get(list_sensors).layer(msgpack_layer.clone()))
+        .route(
+            "/sensors/latest",
+            get(latest_samples)
+                .layer(query_limit_layer.clone())
+                .layer(msgpack_layer.clone()),
+        )
+        .route(
+            "/sensors/:sensor_uuid",
+            get(get_sensor).layer(msgpack_layer.clone()),
+        )
+        .route(
+            "/sensors/:sensor_uuid/metadata",
+            put(update_sensor_metadata),
+        )
+        .route(
+            "/catalog.jsonld",
+            get(catalog).layer(query_limit_layer.clone()),
+        )
+        .route(
+            "/sensors/:sensor_uuid/export",
+            get(export_sensor).layer(query_limit_layer.clone()),
+        )
+        .route(
+            "/sensors/:sensor_uuid/verify",
+            get(verify_sensor)
+                .layer(query_limit_layer.clone())
+                .layer(msgpack_layer.clone()),
+        )
+        .route(
+            "/sensors/:sensor_uuid/aggregate",
+            get(aggregate_sensor)
+                .layer(query_limit_layer.clone())
+                .layer(msgpack_layer.clone()),
+        )
+        .route(
+            "/sensors/:sensor_uuid/annotations",
+            get(query_annotations)
+                .post(add_annotation)
+                .layer(msgpack_layer.clone()),
+        )
+        .route(
+            "/query",
+            post(query_sensors)
+                .layer(query_limit_layer.clone())
+                .layer(msgpack_layer.clone()),
+        )
+        .route(
+            "/query/sql",
+            post(query_raw_sql).layer(query_limit_layer.clone()),
+        )
+        .route("/retention", post(apply_retention))
+        .route("/internal/rollup", post(apply_rollup))
+        .route("/metrics/:name", delete(delete_metric))
         // InfluxDB Write API
         .route(
             "/api/v2/write",
-            post(publish_influxdb).layer(max_body_layer.clone()),
+            post(publish_influxdb)
+                .layer(max_body_layer.clone())
+                .layer(ingestion_limit_layer.clone())
+                .layer(rate_limit_layer.clone()),
+        )
+        .route(
+            "/write",
+            post(publish_influxdb_v1)
+                .layer(max_body_layer.clone())
+                .layer(ingestion_limit_layer.clone())
+                .layer(rate_limit_layer.clone()),
         )
         // Prometheus Remote Write API
         .route(
             "/api/v1/prometheus_remote_write",
-            post(publish_prometheus).layer(max_body_layer.clone()),
+            post(publish_prometheus)
+                .layer(max_body_layer.clone())
+                .layer(ingestion_limit_layer.clone())
+                .layer(rate_limit_layer.clone()),
+        )
+        // Prometheus Remote Read API
+        .route(
+            "/api/v1/prometheus_remote_read",
+            post(prometheus_remote_read).layer(query_limit_layer.clone()),
+        )
+        // Prometheus Query API
+        .route(
+            "/api/v1/query",
+            get(prometheus_query)
+                .post(prometheus_query)
+                .layer(query_limit_layer.clone()),
+        )
+        .route(
+            "/api/v1/query_range",
+            get(prometheus_query_range)
+                .post(prometheus_query_range)
+                .layer(query_limit_layer.clone()),
         )
         .layer(middleware)
+        // The streams are long-lived, so they are outside of the timeout
+        // and the body limit, and bounded by the stream chunk size instead.
+        .route(
+            "/publish/:parser_name/stream",
+            post(publish_stream).layer(rate_limit_layer.clone()),
+        )
+        .layer(axum::middleware::from_fn(log_requests))
         .with_state(state);
 
-    // Run our application
-    let listener = tokio::net::TcpListener::bind(address).await?;
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
-
-    Ok(())
+    let base_path = config.base_path();
+    if base_path.is_empty() {
+        return Ok(app);
+    }
+    Ok(Router::new().nest(&base_path, app))
 }
 
 async fn shutdown_signal() {
@@ -147,27 +368,66 @@ async fn frontpage(State(state): State<HttpServerState>) -> Result<Json<String>,
 //     Json(ApiDoc::openapi())
 // }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct PublishCsvQueryParams {
+    /// Column of the timestamps, `timestamp` by default.
+    pub col_timestamp: Option<String>,
+    /// Column of the values, `value` by default.
+    pub col_value: Option<String>,
+    /// Column of the sensor names, `name` by default.
+    pub col_name: Option<String>,
+    /// `auto`, `unix`, `unix_ms`, or `iso8601`.
+    pub timestamp_format: Option<String>,
+}
+
+impl PublishCsvQueryParams {
+    fn column_mapping(self) -> Result<CsvColumnMapping> {
+        let default = CsvColumnMapping::default();
+        Ok(CsvColumnMapping {
+            timestamp: self.col_timestamp.unwrap_or(default.timestamp),
+            value: self.col_value.unwrap_or(default.value),
+            name: self.col_name.unwrap_or(default.name),
+            timestamp_format: match self.timestamp_format {
+                Some(format) => format.parse()?,
+                None => default.timestamp_format,
+            },
+        })
+    }
+}
+
+/// Publishes a long format CSV, separated by semicolons, with one sample per row.
+///
+/// The columns are mapped with the `col_timestamp`, `col_value`, and `col_name`
+/// query parameters. Without a sensor name column, the samples belong to
+/// the sensor of the path. Gzip and zstd bodies are decompressed.
 async fn publish_csv(
     State(state): State<HttpServerState>,
-    //Path(sensor_name_or_uuid): Path<String>,
+    Path(sensor_name): Path<String>,
+    Query(params): Query<PublishCsvQueryParams>,
     body: axum::body::Body,
 ) -> Result<String, AppError> {
-    // let uuid = name_to_uuid(sensor_name_or_uuid.as_str())?;
+    let mapping = params.column_mapping().map_err(AppError::BadRequest)?;
     // Convert the body in a stream
     let stream = body.into_data_stream();
     let stream = stream.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
-    let reader = stream.into_async_read();
-    //let reader = BufReader::new(stream.into_async_read());
+    let reader = decompress_reader_if_compressed(stream.into_async_read())
+        .await
+        .map_err(|error| AppError::BadRequest(error.into()))?;
     // csv_async already uses a BufReader internally
     let csv_reader = csv_async::AsyncReaderBuilder::new()
         .has_headers(true)
         .delimiter(b';')
         .create_reader(reader);
 
-    //publish_csv_async(csv_reader, 100, state.event_bus.clone()).await?;
-    publish_csv_async(csv_reader, 8192, state.event_bus.clone()).await?;
+    let (sample_count, waiters) =
+        publish_csv_async(csv_reader, &mapping, &sensor_name, state.event_bus.clone())
+            .await
+            .map_err(AppError::invalid_body)?;
+    for mut waiter in waiters {
+        sync_with_timeout(&mut waiter).await?;
+    }
 
-    Ok("ok".to_string())
+    Ok(format!("{} samples", sample_count))
 }
 
 async fn publish_handler(bytes: Bytes) -> Result<Json<String>, (StatusCode, String)> {
@@ -202,14 +462,15 @@ mod tests {
     use tower::ServiceExt;
 
     use super::*;
-    use crate::{bus::EventBus, storage::sqlite::SqliteStorage};
+    use crate::storage::sqlite::SqliteStorage;
 
     #[tokio::test]
     async fn test_handler() {
         let state = HttpServerState {
             name: Arc::new("hello world".to_string()),
-            event_bus: Arc::new(EventBus::init("test".to_string())),
-            storage: Arc::new(SqliteStorage::connect("sqlite::memory:").await.unwrap()),
+            ..HttpServerState::for_tests(Arc::new(
+                SqliteStorage::connect("sqlite::memory:").await.unwrap(),
+            ))
         };
         let app = Router::new().route("/", get(frontpage)).with_state(state);
         let request = Request::builder().uri("/").body(Body::empty()).unwrap();
@@ -222,4 +483,73 @@ mod tests {
             String::from_utf8(to_bytes(response.into_body(), 128).await.unwrap().to_vec()).unwrap();
         assert_eq!(body_str, "\"hello world\"");
     }
+
+    #[tokio::test]
+    async fn test_base_path() {
+        _ = crate::config::load_configuration();
+        let state = HttpServerState::for_tests(Arc::new(
+            SqliteStorage::connect("sqlite::memory:").await.unwrap(),
+        ));
+        let mut config = SensAppConfig::load().unwrap();
+        // The other tests change the environment
+        config.http_body_limit = "10mb".to_string();
+        config.base_path = "/sensapp/".to_string();
+        let app = app(state, &config).unwrap();
+
+        let status = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+        assert_eq!(status("/sensapp").await, StatusCode::OK);
+        assert_eq!(status("/sensapp/health").await, StatusCode::OK);
+        assert_eq!(status("/").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("/health").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_publish_compressed_csv() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        _ = crate::config::load_configuration();
+        let event_bus = crate::bus::event_bus::init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        tokio::spawn(async move {
+            while let Ok(crate::bus::message::Message::Publish(message)) = receiver.recv().await {
+                message.sync_sender.broadcast(()).await.unwrap();
+            }
+        });
+        let state = HttpServerState {
+            event_bus,
+            ..HttpServerState::for_tests(Arc::new(
+                SqliteStorage::connect("sqlite::memory:").await.unwrap(),
+            ))
+        };
+        let mut config = SensAppConfig::load().unwrap();
+        // The other tests change the environment
+        config.http_body_limit = "10mb".to_string();
+        config.base_path = String::new();
+        let app = app(state, &config).unwrap();
+
+        let csv = b"timestamp;value\n1700000000;1.5\n1700000001;2.5\n1700000002;3.5\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(csv).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let zstded = zstd::encode_all(&csv[..], 0).unwrap();
+
+        for body in [gzipped, zstded] {
+            let request = Request::post("/sensors/compressed_csv/publish_csv")
+                .body(Body::from(body))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, "3 samples");
+        }
+    }
 }
@@ -1,4 +1,17 @@
-use crate::{bus::EventBus, storage::storage::StorageInstance};
+use super::{
+    app_error::AppError, catalog_cache::CatalogCache, concurrency_limit::ConcurrencyLimits,
+    jobs::Jobs,
+};
+use crate::{
+    bus::EventBus,
+    datamodel::{batch_builder::BatchBuilder, Sensor},
+    storage::{
+        circuit_breaker::{CircuitBreakerState, CircuitOpenError},
+        storage::StorageInstance,
+        strict_sensors::{check_known_sensors, strict_sensors},
+        type_conflict::{check_type_conflicts, TypeConflictPolicy},
+    },
+};
 use std::sync::Arc;
 
 #[derive(Clone, Debug)]
@@ -6,4 +19,66 @@ pub struct HttpServerState {
     pub name: Arc<String>,
     pub event_bus: Arc<EventBus>,
     pub storage: Arc<dyn StorageInstance>,
+    pub jobs: Arc<Jobs>,
+    pub concurrency: Arc<ConcurrencyLimits>,
+    pub catalog_cache: Arc<CatalogCache>,
+    /// Checked before publishing, to answer the conflicts with a 400.
+    pub type_conflict_policy: TypeConflictPolicy,
+}
+
+impl HttpServerState {
+    /// A state with the default limits and caches, for the tests.
+    #[cfg(test)]
+    pub fn for_tests(storage: Arc<dyn StorageInstance>) -> Self {
+        Self {
+            name: Arc::new("SensApp".to_string()),
+            event_bus: Arc::new(EventBus::init("test".to_string())),
+            storage,
+            jobs: Default::default(),
+            concurrency: Default::default(),
+            catalog_cache: Default::default(),
+            type_conflict_policy: Default::default(),
+        }
+    }
+
+    /// Fails fast when the circuit breaker of the storage is open.
+    pub fn ensure_storage_available(&self) -> Result<(), AppError> {
+        match self.storage.circuit_breaker_state() {
+            Some(CircuitBreakerState::Open) => {
+                Err(AppError::ServiceUnavailable(CircuitOpenError.into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Rejects the samples of the unknown sensors in strict mode, and of the
+    /// sensors conflicting with the type of an existing one with the reject
+    /// policy, before they are published.
+    pub async fn ensure_accepted_sensors(
+        &self,
+        batch_builder: &BatchBuilder,
+    ) -> Result<(), AppError> {
+        check_accepted_sensors(
+            self.storage.as_ref(),
+            &batch_builder.sensors().await,
+            self.type_conflict_policy,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// See [`HttpServerState::ensure_accepted_sensors`].
+pub async fn check_accepted_sensors(
+    storage: &dyn StorageInstance,
+    sensors: &[Arc<Sensor>],
+    type_conflict_policy: TypeConflictPolicy,
+) -> anyhow::Result<()> {
+    if strict_sensors() {
+        check_known_sensors(storage, sensors).await?;
+    }
+    if type_conflict_policy == TypeConflictPolicy::Reject {
+        check_type_conflicts(storage, sensors).await?;
+    }
+    Ok(())
 }
@@ -0,0 +1,341 @@
+use super::{
+    app_error::AppError,
+    audit::audit_payload,
+    influxdb::{add_line_protocol, InfluxDBOptions, Precision},
+    request_log::record_ingestion,
+    state::HttpServerState,
+};
+use crate::config;
+use crate::datamodel::{batch_builder::BatchBuilder, sensapp_vec::SensAppLabels};
+use crate::parsing::{get_parser_from_name, ParseData};
+use crate::storage::sync_timeout::sync_with_timeout;
+use anyhow::anyhow;
+use axum::{
+    body::Body,
+    debug_handler,
+    extract::{Path, Query, State},
+    Json,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::str::from_utf8;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+use tokio_util::bytes::Bytes;
+
+/// The parsers of the line based formats, that can parse a stream line by line.
+pub const STREAM_PARSERS: [&str; 3] = ["influxdb", "senml_ndjson", "graphite"];
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamQueryParams {
+    /// Bucket of the InfluxDB lines, in the `influxdb_bucket` label.
+    pub bucket: Option<String>,
+    /// Precision of the InfluxDB timestamps.
+    pub precision: Option<String>,
+}
+
+enum StreamParser {
+    LineProtocol {
+        injected_labels: Box<SensAppLabels>,
+        precision: Precision,
+        floats_as_numeric: bool,
+    },
+    Parser(Box<dyn ParseData>),
+}
+
+impl StreamParser {
+    fn new(parser_name: &str, params: StreamQueryParams) -> Result<Self, AppError> {
+        match parser_name {
+            "influxdb" => {
+                let mut injected_labels = SensAppLabels::new();
+                if let Some(bucket) = params.bucket {
+                    injected_labels.push(("influxdb_bucket".to_string(), bucket));
+                }
+                Ok(Self::LineProtocol {
+                    injected_labels: Box::new(injected_labels),
+                    precision: Precision::from_query(params.precision)?,
+                    floats_as_numeric: InfluxDBOptions::from_config()?.floats_as_numeric,
+                })
+            }
+            "senml_ndjson" | "graphite" => Ok(Self::Parser(
+                get_parser_from_name(parser_name).map_err(AppError::BadRequest)?,
+            )),
+            _ => Err(AppError::BadRequest(anyhow!(
+                "The {} parser can't parse streams, use one of: {}",
+                parser_name,
+                STREAM_PARSERS.join(", ")
+            ))),
+        }
+    }
+
+    async fn parse(&self, lines: &[u8], batch_builder: &mut BatchBuilder) -> Result<(), AppError> {
+        match self {
+            Self::LineProtocol {
+                injected_labels,
+                precision,
+                floats_as_numeric,
+            } => {
+                let lines =
+                    from_utf8(lines).map_err(|error| AppError::BadRequest(anyhow!(error)))?;
+                add_line_protocol(
+                    batch_builder,
+                    lines,
+                    injected_labels,
+                    precision,
+                    *floats_as_numeric,
+                )
+                .await
+            }
+            Self::Parser(parser) => parser
+                .parse_data(lines, batch_builder)
+                .await
+                .map_err(AppError::invalid_body),
+        }
+    }
+}
+
+/// The samples parsed from a stream, and not yet stored.
+struct StreamIngestion<'a> {
+    state: &'a HttpServerState,
+    parser_name: &'a str,
+    parser: StreamParser,
+    batch_builder: BatchBuilder,
+    /// The lines parsed since the last flush, for the audit log.
+    parsed: Vec<u8>,
+    sample_count: usize,
+}
+
+impl StreamIngestion<'_> {
+    async fn parse(&mut self, lines: &[u8]) -> Result<(), AppError> {
+        self.parser.parse(lines, &mut self.batch_builder).await?;
+        self.parsed.extend_from_slice(lines);
+        Ok(())
+    }
+
+    /// Stores the parsed samples, and waits for them to be stored
+    /// before reading more of the stream.
+    async fn flush(&mut self) -> Result<(), AppError> {
+        if self.parsed.is_empty() {
+            return Ok(());
+        }
+        self.state
+            .ensure_accepted_sensors(&self.batch_builder)
+            .await?;
+        let parsed = Bytes::from(std::mem::take(&mut self.parsed));
+        audit_payload(self.parser_name, &parsed, &self.batch_builder).await?;
+        record_ingestion(&self.batch_builder).await;
+
+        self.sample_count += self.batch_builder.len().await;
+        let waiter = self
+            .batch_builder
+            .send_what_is_left(self.state.event_bus.clone())
+            .await
+            .map_err(|error| AppError::InternalServerError(anyhow!(error)))?;
+        if let Some(mut waiter) = waiter {
+            sync_with_timeout(&mut waiter).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Publish a stream of lines over a long-lived connection.
+///
+/// The body is read as it arrives, for instance with a chunked transfer
+/// encoding, and parsed line by line with `influxdb` for the InfluxDB line
+/// protocol, `senml_ndjson`, or `graphite`. The samples are stored once
+/// `SENSAPP_STREAM_CHUNK_SIZE` bytes are parsed, or every
+/// `SENSAPP_STREAM_FLUSH_INTERVAL_MS`, so the memory stays bounded.
+///
+/// The compressed streams aren't supported. When a line is invalid, the
+/// stream stops with an error, and the samples stored before are kept.
+#[utoipa::path(
+    post,
+    path = "/publish/{parser_name}/stream",
+    tag = "SensApp",
+    request_body(
+        content = String,
+        content_type = "text/plain",
+        description = "Stream of lines in the format understood by the selected parser.",
+        example = "cpu,host=A usage_system=64.2 1590488773254420000\n"
+    ),
+    params(
+        ("parser_name" = String, Path, description = "One of influxdb, senml_ndjson, graphite", example = "influxdb"),
+        ("bucket" = Option<String>, Query, description = "Bucket of the InfluxDB lines"),
+        ("precision" = Option<String>, Query, description = "Precision of the InfluxDB timestamps. One of ns, us, ms, s"),
+    ),
+    responses(
+        (status = 200, description = "The number of samples stored", content_type = "application/json"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn publish_stream(
+    State(state): State<HttpServerState>,
+    Path(parser_name): Path<String>,
+    Query(params): Query<StreamQueryParams>,
+    body: Body,
+) -> Result<Json<Value>, AppError> {
+    state.ensure_storage_available()?;
+    let config = config::get()?;
+    let chunk_size = config.stream_chunk_size.max(1);
+    let flush_interval = Duration::from_millis(config.stream_flush_interval_ms);
+
+    let mut ingestion = StreamIngestion {
+        state: &state,
+        parser_name: &parser_name,
+        parser: StreamParser::new(&parser_name, params)?,
+        batch_builder: BatchBuilder::new()?.with_storage(state.storage.clone()),
+        parsed: Vec::new(),
+        sample_count: 0,
+    };
+
+    let mut stream = body.into_data_stream();
+    // The beginning of a line that isn't complete yet
+    let mut pending = Vec::new();
+    let mut next_flush = Instant::now() + flush_interval;
+    loop {
+        let chunk = match timeout_at(next_flush, stream.next()).await {
+            Ok(Some(chunk)) => chunk.map_err(|error| AppError::BadRequest(anyhow!(error)))?,
+            Ok(None) => break,
+            // Nothing arrived during the interval
+            Err(_) => {
+                ingestion.flush().await?;
+                next_flush = Instant::now() + flush_interval;
+                continue;
+            }
+        };
+        pending.extend_from_slice(&chunk);
+        if let Some(end) = pending.iter().rposition(|byte| *byte == b'\n') {
+            let rest = pending.split_off(end + 1);
+            ingestion.parse(&pending).await?;
+            pending = rest;
+        }
+        if pending.len() > chunk_size {
+            return Err(AppError::BadRequest(anyhow!(
+                "A line is longer than the stream chunk size of {} bytes",
+                chunk_size
+            )));
+        }
+        if ingestion.parsed.len() >= chunk_size || Instant::now() >= next_flush {
+            ingestion.flush().await?;
+            next_flush = Instant::now() + flush_interval;
+        }
+    }
+
+    // The last line may not end with a newline
+    if !pending.is_empty() {
+        ingestion.parse(&pending).await?;
+    }
+    ingestion.flush().await?;
+
+    Ok(Json(json!({ "samples": ingestion.sample_count })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{event_bus::init_event_bus, message};
+    use crate::config::load_configuration;
+    use crate::storage::sqlite::sqlite::tests::create_test_storage;
+    use crate::storage::{
+        query::{SensorSelector, TimeRange},
+        storage::StorageInstance,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_publish_stream() {
+        _ = load_configuration();
+        let storage = Arc::new(create_test_storage().await);
+        let event_bus = init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let storage_for_publish = storage.clone();
+        tokio::spawn(async move {
+            while let Ok(message::Message::Publish(message::PublishMessage {
+                batch,
+                sync_sender,
+                ..
+            })) = receiver.recv().await
+            {
+                storage_for_publish
+                    .publish(batch, sync_sender)
+                    .await
+                    .unwrap();
+            }
+        });
+        let state = HttpServerState {
+            event_bus,
+            ..HttpServerState::for_tests(storage.clone())
+        };
+
+        // Many lines, sent in chunks that split the lines
+        let lines = (0..5000)
+            .map(|index| {
+                format!(
+                    "stream,host=gateway value={}i {}\n",
+                    index,
+                    1_700_000_000 + index
+                )
+            })
+            .collect::<String>();
+        let chunks = lines
+            .into_bytes()
+            .chunks(777)
+            .map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let Json(response) = publish_stream(
+            State(state.clone()),
+            Path("influxdb".to_string()),
+            Query(StreamQueryParams {
+                bucket: Some("gateways".to_string()),
+                precision: Some("s".to_string()),
+            }),
+            Body::from_stream(futures::stream::iter(chunks)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["samples"], 5000);
+
+        let results = storage
+            .query(&SensorSelector::default(), TimeRange::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].sensor.name, "stream value");
+        assert_eq!(results[0].samples.len(), 5000);
+
+        // The last line doesn't need a newline
+        let Json(response) = publish_stream(
+            State(state.clone()),
+            Path("influxdb".to_string()),
+            Query(StreamQueryParams::default()),
+            Body::from("last value=1i\nlast value=2i"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["samples"], 2);
+
+        assert!(matches!(
+            publish_stream(
+                State(state.clone()),
+                Path("senml_json".to_string()),
+                Query(StreamQueryParams::default()),
+                Body::from("[]"),
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            publish_stream(
+                State(state),
+                Path("influxdb".to_string()),
+                Query(StreamQueryParams::default()),
+                Body::from("not line protocol\n"),
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+use super::{app_error::AppError, state::HttpServerState};
+use crate::storage::verify::VerifyReport;
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    Json,
+};
+use uuid::Uuid;
+
+/// Verify the samples of a sensor.
+///
+/// Counts the duplicated and out of order timestamps, the NaN and
+/// infinite floats, and the invalid UTF-8 strings.
+/// The whole sensor is scanned, so it can be slow on large sensors.
+#[utoipa::path(
+    get,
+    path = "/sensors/{sensor_uuid}/verify",
+    tag = "SensApp",
+    params(
+        ("sensor_uuid" = String, Path, description = "UUID of the sensor"),
+    ),
+    responses(
+        (status = 200, description = "Counts of the anomalies", content_type = "application/json"),
+        (status = 400, description = "Bad Request", body = AppError),
+        (status = 404, description = "Sensor Not Found", body = AppError),
+        (status = 500, description = "Internal Server Error", body = AppError),
+    )
+)]
+#[debug_handler]
+pub async fn verify_sensor(
+    State(state): State<HttpServerState>,
+    Path(sensor_uuid): Path<String>,
+) -> Result<Json<VerifyReport>, AppError> {
+    let sensor_uuid = Uuid::parse_str(&sensor_uuid)
+        .map_err(|_| AppError::BadRequest(anyhow!("Invalid sensor UUID: {}", sensor_uuid)))?;
+    let report = state
+        .storage
+        .verify_sensor(sensor_uuid)
+        .await?
+        .ok_or_else(|| AppError::NotFound(anyhow!("Sensor not found: {}", sensor_uuid)))?;
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, SensAppDateTime, Sensor, SensorType, TypedSamples,
+    };
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_verify_sensor() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid("verify_test".to_string(), SensorType::Integer, None, None)
+                .unwrap(),
+        );
+        let samples = TypedSamples::Integer(
+            [0, 1, 1, 2]
+                .into_iter()
+                .map(|second| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + second),
+                    value: second,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let state = HttpServerState::for_tests(Arc::new(storage));
+
+        let Json(report) = verify_sensor(State(state.clone()), Path(sensor.uuid.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(report.sample_count, 4);
+        assert_eq!(report.duplicate_timestamps, 1);
+        assert_eq!(report.out_of_order_timestamps, 0);
+
+        let result = verify_sensor(State(state.clone()), Path(Uuid::new_v4().to_string())).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+        let result = verify_sensor(State(state), Path("not-a-uuid".to_string())).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+}
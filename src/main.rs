@@ -2,33 +2,68 @@
 use crate::bus::message;
 use crate::config::load_configuration;
 use crate::ingestors::amqp::amqp_example;
+use crate::ingestors::http::catalog_cache::CatalogCache;
+use crate::ingestors::http::concurrency_limit::ConcurrencyLimits;
+use crate::ingestors::http::jobs::Jobs;
+use crate::ingestors::http::request_log::{RequestLogFormat, RequestLogLayer};
 use crate::ingestors::http::server::run_http_server;
 use crate::ingestors::http::state::HttpServerState;
 use axum::http::StatusCode;
+use clap::{Parser, Subcommand};
 use futures::stream::StreamExt;
 use futures::TryStreamExt;
 use rustls::crypto::CryptoProvider;
 use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use storage::circuit_breaker::{CircuitBreakerOptions, CircuitBreakerStorage};
+use storage::schema::prepare_storage;
 use storage::storage::StorageInstance;
 use storage::storage_factory::create_storage_from_connection_string;
+use storage::write_buffer::{WriteBufferOptions, WriteBufferStorage};
 //use storage::duckdb::DuckDBStorage;
 //use storage::postgresql::postgresql::PostgresStorage;
 use storage::sqlite::sqlite::SqliteStorage;
 use tracing::event;
 use tracing::Level;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 mod bus;
 mod config;
 mod datamodel;
+mod exporters;
 mod importers;
 mod infer;
 mod ingestors;
 mod name_to_uuid;
 mod parsing;
+mod selftest;
 mod storage;
 
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Copies all the sensors and samples from one storage to another.
+    Migrate {
+        /// Connection string of the storage to read.
+        source: String,
+        /// Connection string of the storage to write.
+        target: String,
+        /// Number of samples read at once.
+        #[arg(long, default_value_t = 10000)]
+        page_size: usize,
+    },
+}
+
 fn main() {
+    let cli = Cli::parse();
+
     let _sentry = sentry::init((
         "https://94bc3d0bd0424707898d420ed4ad6a3d@feil.sintef.cloud/5",
         sentry::ClientOptions {
@@ -46,7 +81,42 @@ fn main() {
         .enable_all()
         .build()
         .expect("Failed to create Tokio runtime")
-        .block_on(async_main());
+        .block_on(async move {
+            match cli.command {
+                Some(Command::Migrate {
+                    source,
+                    target,
+                    page_size,
+                }) => migrate_main(&source, &target, page_size).await,
+                None => async_main().await,
+            }
+        });
+}
+
+async fn migrate_main(source: &str, target: &str, page_size: usize) {
+    load_configuration().expect("Failed to load configuration");
+    let source = create_storage_from_connection_string(source)
+        .await
+        .expect("Failed to create the source storage");
+    let target = create_storage_from_connection_string(target)
+        .await
+        .expect("Failed to create the target storage");
+    target
+        .create_or_migrate()
+        .await
+        .expect("Failed to create or migrate the target storage");
+    match storage::migrate::migrate_all(source, target, page_size).await {
+        Ok((sensor_count, sample_count)) => {
+            println!(
+                "Migrated {} samples of {} sensors",
+                sample_count, sensor_count
+            );
+        }
+        Err(err) => {
+            eprintln!("Migration failed: {:?}", err);
+            std::process::exit(1);
+        }
+    }
 }
 
 async fn async_main() {
@@ -58,11 +128,28 @@ async fn async_main() {
     load_configuration().expect("Failed to load configuration");
     let config = config::get().expect("Failed to get configuration");
 
+    if config.request_log != RequestLogFormat::Off {
+        tracing_subscriber::registry()
+            .with(RequestLogLayer::new(config.request_log, std::io::stdout))
+            .init();
+    }
+
     sinteflake::set_instance_id(config.instance_id).unwrap();
     sinteflake::set_instance_id_async(config.instance_id)
         .await
         .unwrap();
 
+    if config.startup_selftest {
+        let results = selftest::run_selftest().await;
+        for result in &results {
+            println!("Self-test {}", result.summary());
+        }
+        if results.iter().any(|result| result.result.is_err()) {
+            eprintln!("Startup self-test failed");
+            std::process::exit(1);
+        }
+    }
+
     /*let (tx, rx) = tokio::sync::mpsc::channel(100); // Channel with buffer size 100
 
     // Simulate event emitter
@@ -108,10 +195,28 @@ async fn async_main() {
         .await
         .expect("Failed to create storage");
 
-    /*storage
-    .create_or_migrate()
-    .await
-    .expect("Failed to create or migrate database");*/
+    let circuit_breaker_options =
+        CircuitBreakerOptions::from_config().expect("Failed to get circuit breaker options");
+    let storage: Arc<dyn StorageInstance> = if circuit_breaker_options.failure_threshold > 0 {
+        Arc::new(CircuitBreakerStorage::new(storage, circuit_breaker_options))
+    } else {
+        storage
+    };
+
+    // The buffer wraps the circuit breaker, so the buffered batches
+    // still fail fast while the backend is down.
+    let write_buffer_options =
+        WriteBufferOptions::from_config().expect("Failed to get write buffer options");
+    let storage: Arc<dyn StorageInstance> = if write_buffer_options.capacity > 0 {
+        Arc::new(WriteBufferStorage::new(storage, write_buffer_options))
+    } else {
+        storage
+    };
+
+    if let Err(error) = prepare_storage(storage.as_ref(), config.skip_migrations).await {
+        eprintln!("{:#}", error);
+        std::process::exit(1);
+    }
 
     /*let duckdb_storage = DuckDBStorage::connect("sensapp.db")
         .await
@@ -173,6 +278,34 @@ async fn async_main() {
     }));
 
     let storage_for_publish = storage.clone();
+    let catalog_cache = Arc::new(CatalogCache::from_config(&config));
+    let catalog_cache_for_publish = catalog_cache.clone();
+
+    if config.rollup_task_interval_seconds > 0 {
+        let storage_for_rollup = storage.clone();
+        let catalog_cache_for_rollup = catalog_cache.clone();
+        let period = Duration::from_secs(config.rollup_task_interval_seconds);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let report = match datamodel::SensAppDateTime::now() {
+                    Ok(now) => {
+                        storage::rollup::apply_rollups(storage_for_rollup.as_ref(), now).await
+                    }
+                    Err(error) => Err(error.into()),
+                };
+                match report {
+                    Ok(report) if report.buckets > 0 => {
+                        catalog_cache_for_rollup.invalidate();
+                        event!(Level::INFO, "Rolled up {:?}", report);
+                    }
+                    Ok(_) => {}
+                    Err(error) => event!(Level::ERROR, "Rollup failed: {:?}", error),
+                }
+            }
+        });
+    }
 
     // spawn a task that prints the events to stdout
     tokio::spawn(async move {
@@ -190,9 +323,18 @@ async fn async_main() {
                     sync_sender,
                 }) => {
                     let start_time = std::time::Instant::now();
-                    toto.publish(batch, sync_sender)
-                        .await
-                        .expect("Failed to publish batch sqlite");
+                    // Before and after, as the listings may be loaded
+                    // while the new sensors are being stored.
+                    catalog_cache_for_publish.invalidate_on_new_sensors(&batch);
+                    // The circuit breaker handles the failing storage,
+                    // so a failed publish must not stop the loop.
+                    // Dropping the sync sender without a sync tells
+                    // the waiting clients that the batch isn't stored.
+                    if let Err(error) = toto.publish(batch.clone(), sync_sender).await {
+                        event!(Level::ERROR, "Failed to publish batch: {:?}", error);
+                        continue;
+                    }
+                    catalog_cache_for_publish.invalidate_on_new_sensors(&batch);
                     let elapsed = start_time.elapsed();
                     //println!("Published batch sqlite: {:?}", elapsed);
                     println!("Published batch bigquery: {:?}", elapsed);
@@ -291,6 +433,13 @@ async fn async_main() {
             event_bus,
             //storage: storage.clone(),
             storage,
+            jobs: Arc::new(Jobs::new(
+                Duration::from_secs(config.jobs_ttl_seconds),
+                Duration::from_secs(config.jobs_stalled_ttl_seconds),
+            )),
+            concurrency: Arc::new(ConcurrencyLimits::from_config(&config)),
+            catalog_cache,
+            type_conflict_policy: config.type_conflict_policy,
         },
         SocketAddr::from((endpoint, port)),
     )
@@ -0,0 +1,423 @@
+use super::ParseData;
+use crate::datamodel::{
+    arrow_converter::ArrowConverter, batch_builder::BatchBuilder,
+    sensapp_datetime::SensAppDateTimeExt, sensapp_vec::SensAppLabels, unit::Unit, SensAppDateTime,
+    Sensor, SensorType, TypedSamples,
+};
+use crate::exporters::arrow_file::{
+    SENSOR_LABELS_METADATA, SENSOR_NAME_METADATA, SENSOR_TYPE_METADATA, SENSOR_UNIT_METADATA,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use arrow::array::{Array, ArrayRef, AsArray};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    DataType, Float64Type, Int64Type, Schema, TimeUnit, TimestampMicrosecondType,
+};
+use arrow::ipc::reader::FileReader;
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Parses an Arrow IPC file.
+///
+/// The files of the Arrow exporter, describing their sensor in the schema
+/// metadata, are read back as they were exported. The other files have one
+/// sample per row, in a `timestamp` column, a `value` column, a `sensor_name`
+/// column, and an optional `type` column. The timestamps are Arrow timestamps
+/// or unix seconds, and the sensor type follows the Arrow type of the values,
+/// unless the `type` column says otherwise for the string values.
+pub struct ArrowParser;
+
+/// The sensor described in the schema metadata by the Arrow exporter.
+fn sensor_from_metadata(schema: &Schema) -> Result<Option<Sensor>> {
+    let metadata = schema.metadata();
+    let (name, sensor_type) = match (
+        metadata.get(SENSOR_NAME_METADATA),
+        metadata.get(SENSOR_TYPE_METADATA),
+    ) {
+        (Some(name), Some(sensor_type)) => (name, SensorType::from_str(sensor_type)?),
+        _ => return Ok(None),
+    };
+    let unit = metadata
+        .get(SENSOR_UNIT_METADATA)
+        .map(|unit| Unit::new(unit.clone(), None));
+    let labels = match metadata.get(SENSOR_LABELS_METADATA) {
+        Some(labels) => {
+            let labels: HashMap<String, String> =
+                serde_json::from_str(labels).context("Invalid labels in the schema metadata")?;
+            Some(labels.into_iter().collect::<SensAppLabels>())
+        }
+        None => None,
+    };
+    Ok(Some(Sensor::new_without_uuid(
+        name.clone(),
+        sensor_type,
+        unit,
+        labels,
+    )?))
+}
+
+/// The datetimes of an Arrow timestamp column, or of unix seconds.
+fn datetimes(column: &ArrayRef) -> Result<Vec<SensAppDateTime>> {
+    match column.data_type() {
+        DataType::Timestamp(_, _) => {
+            let microseconds = cast(column, &DataType::Timestamp(TimeUnit::Microsecond, None))?;
+            Ok(microseconds
+                .as_primitive::<TimestampMicrosecondType>()
+                .values()
+                .iter()
+                .map(|microseconds| SensAppDateTime::from_unix_microseconds_i64(*microseconds))
+                .collect())
+        }
+        DataType::Int64 | DataType::Float64 => {
+            let seconds = cast(column, &DataType::Float64)?;
+            Ok(seconds
+                .as_primitive::<Float64Type>()
+                .values()
+                .iter()
+                .map(|seconds| SensAppDateTime::from_unix_seconds(*seconds))
+                .collect())
+        }
+        data_type => bail!("Unsupported timestamp type: {}", data_type),
+    }
+}
+
+fn value_sensor_type(data_type: &DataType) -> Result<SensorType> {
+    Ok(match data_type {
+        DataType::Int64 => SensorType::Integer,
+        DataType::Float64 => SensorType::Float,
+        DataType::Utf8 => SensorType::String,
+        DataType::Boolean => SensorType::Boolean,
+        DataType::Binary => SensorType::Blob,
+        data_type => bail!("Unsupported value type: {}", data_type),
+    })
+}
+
+/// The sample of a row, the string values being parsed for their sensor type.
+fn row_samples(
+    values: &ArrayRef,
+    row: usize,
+    sensor_type: SensorType,
+    datetime: SensAppDateTime,
+) -> Result<TypedSamples> {
+    Ok(match (values.data_type(), sensor_type) {
+        (DataType::Int64, SensorType::Integer) => {
+            TypedSamples::one_integer(values.as_primitive::<Int64Type>().value(row), datetime)
+        }
+        (DataType::Float64, SensorType::Float) => {
+            TypedSamples::one_float(values.as_primitive::<Float64Type>().value(row), datetime)
+        }
+        (DataType::Boolean, SensorType::Boolean) => {
+            TypedSamples::one_boolean(values.as_boolean().value(row), datetime)
+        }
+        (DataType::Binary, SensorType::Blob) => {
+            TypedSamples::one_blob(values.as_binary::<i32>().value(row).to_vec(), datetime)
+        }
+        (DataType::Utf8, SensorType::String) => {
+            TypedSamples::one_string(values.as_string::<i32>().value(row).to_string(), datetime)
+        }
+        (DataType::Utf8, SensorType::Numeric) => TypedSamples::one_numeric(
+            rust_decimal::Decimal::from_str(values.as_string::<i32>().value(row))?,
+            datetime,
+        ),
+        (DataType::Utf8, SensorType::Json) => TypedSamples::one_json(
+            serde_json::from_str(values.as_string::<i32>().value(row))?,
+            datetime,
+        ),
+        (data_type, sensor_type) => bail!(
+            "The {} values can't be {} samples",
+            data_type,
+            sensor_type.to_string()
+        ),
+    })
+}
+
+/// Adds the rows of a record batch with a sample per row.
+async fn add_rows(record_batch: &RecordBatch, batch_builder: &mut BatchBuilder) -> Result<()> {
+    let column = |name: &str| {
+        record_batch
+            .column_by_name(name)
+            .ok_or_else(|| anyhow!("Missing column: {}", name))
+    };
+    let datetimes = datetimes(column("timestamp")?)?;
+    let values = column("value")?;
+    let names = column("sensor_name")?
+        .as_string_opt::<i32>()
+        .ok_or_else(|| anyhow!("The sensor_name column must be strings"))?;
+    let types = match record_batch.column_by_name("type") {
+        Some(types) => Some(
+            types
+                .as_string_opt::<i32>()
+                .ok_or_else(|| anyhow!("The type column must be strings"))?,
+        ),
+        None => None,
+    };
+    let default_sensor_type = value_sensor_type(values.data_type())?;
+
+    let mut sensors: HashMap<(&str, SensorType), Arc<Sensor>> = HashMap::new();
+    for (row, datetime) in datetimes.into_iter().enumerate() {
+        if values.is_null(row) || names.is_null(row) {
+            bail!("Null value or sensor name on row {}", row + 1);
+        }
+        let sensor_type = match types {
+            Some(types) if types.is_valid(row) => SensorType::from_str(types.value(row))?,
+            _ => default_sensor_type,
+        };
+        let samples = row_samples(values, row, sensor_type, datetime)
+            .with_context(|| format!("Invalid value on row {}", row + 1))?;
+        let name = names.value(row);
+        let sensor = match sensors.get(&(name, sensor_type)) {
+            Some(sensor) => sensor.clone(),
+            None => {
+                let sensor = Arc::new(Sensor::new_without_uuid(
+                    name.to_string(),
+                    sensor_type,
+                    None,
+                    None,
+                )?);
+                sensors.insert((name, sensor_type), sensor.clone());
+                sensor
+            }
+        };
+        batch_builder.add(sensor, samples).await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl ParseData for ArrowParser {
+    async fn parse_data(&self, data: &[u8], batch_builder: &mut BatchBuilder) -> Result<()> {
+        let reader = FileReader::try_new(Cursor::new(data), None)?;
+        let sensor = sensor_from_metadata(&reader.schema())?.map(Arc::new);
+        for record_batch in reader {
+            let record_batch = record_batch?;
+            match &sensor {
+                Some(sensor) => {
+                    let samples = ArrowConverter::record_batch_to_typed_samples(
+                        sensor.sensor_type,
+                        &record_batch,
+                    )?;
+                    batch_builder.add(sensor.clone(), samples).await?;
+                }
+                None => add_rows(&record_batch, batch_builder).await?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{event_bus::init_event_bus, message::Message};
+    use crate::config::load_configuration;
+    use crate::datamodel::{batch::Batch, Sample, SensorData};
+    use crate::exporters::arrow_file::to_arrow_file_with_options;
+    use crate::storage::{sqlite::SqliteStorage, storage::StorageInstance};
+    use arrow::array::{Float64Array, Int64Array, StringArray, TimestampMillisecondArray};
+    use arrow::datatypes::Field;
+    use arrow::ipc::writer::FileWriter;
+    use serde_json::json;
+    use smallvec::smallvec;
+
+    /// Parses the data and returns the batch sent to the event bus.
+    async fn parse(data: &[u8]) -> Arc<Batch> {
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        ArrowParser
+            .parse_data(data, &mut batch_builder)
+            .await
+            .unwrap();
+        let event_bus = init_event_bus();
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        batch_builder.send_what_is_left(event_bus).await.unwrap();
+        let Message::Publish(message) = receiver.recv().await.unwrap();
+        message.batch
+    }
+
+    fn write_arrow_file(record_batch: &RecordBatch) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let mut writer = FileWriter::try_new(&mut buffer, &record_batch.schema()).unwrap();
+        writer.write(record_batch).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        buffer
+    }
+
+    #[tokio::test]
+    async fn test_exporter_round_trip() {
+        _ = load_configuration();
+        let datetime =
+            |i: i64| SensAppDateTime::from_unix_microseconds_i64(1_700_000_000_000_000 + i);
+        let cases = [
+            TypedSamples::Integer(smallvec![
+                Sample {
+                    datetime: datetime(0),
+                    value: 1
+                },
+                Sample {
+                    datetime: datetime(1),
+                    value: -2
+                },
+            ]),
+            TypedSamples::Float(smallvec![Sample {
+                datetime: datetime(0),
+                value: 21.5
+            }]),
+            TypedSamples::one_numeric(
+                rust_decimal::Decimal::from_str("123456789012.123456").unwrap(),
+                datetime(0),
+            ),
+            TypedSamples::one_string("open".to_string(), datetime(0)),
+            TypedSamples::one_boolean(true, datetime(0)),
+            TypedSamples::one_location(geo::Point::new(10.75, 59.91), datetime(0)),
+            TypedSamples::one_blob(vec![0, 1, 255], datetime(0)),
+            TypedSamples::one_json(json!({"state": "idle"}), datetime(0)),
+        ];
+        for samples in cases {
+            let sensor = Sensor::new_without_uuid(
+                "round_trip".to_string(),
+                ArrowConverter::sensor_type_of(&samples),
+                Some(Unit::new("Cel".to_string(), None)),
+                Some(smallvec![("room".to_string(), "kitchen".to_string())]),
+            )
+            .unwrap();
+            let sensor_data = SensorData::new(sensor, samples);
+            let exported =
+                to_arrow_file_with_options(&sensor_data, true, &["room".to_string()]).unwrap();
+
+            let batch = parse(&exported).await;
+            assert_eq!(batch.sensors.len(), 1);
+            let imported = &batch.sensors[0];
+            assert_eq!(imported.sensor.uuid, sensor_data.sensor.uuid);
+            assert_eq!(imported.sensor.name, "round_trip");
+            assert_eq!(imported.sensor.unit.as_ref().unwrap().name, "Cel");
+            assert_eq!(
+                imported.sensor.labels.to_vec(),
+                vec![("room".to_string(), "kitchen".to_string())]
+            );
+            assert_eq!(*imported.samples.read().await, sensor_data.samples);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simple_schema() {
+        _ = load_configuration();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("value", DataType::Utf8, false),
+            Field::new("sensor_name", DataType::Utf8, false),
+            Field::new("type", DataType::Utf8, true),
+        ]));
+        let record_batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![
+                    1_700_000_000_000,
+                    1_700_000_001_000,
+                    1_700_000_000_000,
+                ])),
+                Arc::new(StringArray::from(vec!["open", "closed", "1.50"])),
+                Arc::new(StringArray::from(vec!["door", "door", "price"])),
+                Arc::new(StringArray::from(vec![None, None, Some("Numeric")])),
+            ],
+        )
+        .unwrap();
+        let batch = parse(&write_arrow_file(&record_batch)).await;
+        assert_eq!(batch.sensors.len(), 2);
+        for single_sensor_batch in batch.sensors.iter() {
+            let samples = single_sensor_batch.samples.read().await;
+            match single_sensor_batch.sensor.name.as_str() {
+                "door" => assert_eq!(
+                    *samples,
+                    TypedSamples::String(smallvec![
+                        Sample {
+                            datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                            value: "open".to_string(),
+                        },
+                        Sample {
+                            datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_001),
+                            value: "closed".to_string(),
+                        },
+                    ])
+                ),
+                "price" => assert_eq!(
+                    *samples,
+                    TypedSamples::one_numeric(
+                        rust_decimal::Decimal::from_str("1.50").unwrap(),
+                        SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+                    )
+                ),
+                name => panic!("Unexpected sensor: {}", name),
+            }
+        }
+
+        // Unix seconds, and the type following the values
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("timestamp", DataType::Float64, false),
+            Field::new("value", DataType::Int64, false),
+            Field::new("sensor_name", DataType::Utf8, false),
+        ]));
+        let record_batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Float64Array::from(vec![1_700_000_000.5])),
+                Arc::new(Int64Array::from(vec![42])),
+                Arc::new(StringArray::from(vec!["counter"])),
+            ],
+        )
+        .unwrap();
+        let batch = parse(&write_arrow_file(&record_batch)).await;
+        assert_eq!(batch.sensors[0].sensor.sensor_type, SensorType::Integer);
+        assert_eq!(
+            *batch.sensors[0].samples.read().await,
+            TypedSamples::one_integer(42, SensAppDateTime::from_unix_seconds(1_700_000_000.5))
+        );
+
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        assert!(ArrowParser
+            .parse_data(b"not arrow", &mut batch_builder)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_through_storage() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+        let sensor = Sensor::new_without_uuid(
+            "arrow_storage_round_trip".to_string(),
+            SensorType::Float,
+            None,
+            None,
+        )
+        .unwrap();
+        let samples = TypedSamples::Float(
+            (0..10)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                    value: i as f64 / 3.0,
+                })
+                .collect(),
+        );
+        let exported = SensorData::new(sensor, samples);
+        let batch = parse(&to_arrow_file_with_options(&exported, true, &[]).unwrap()).await;
+        storage
+            .publish(batch, async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        let imported = storage
+            .query_sensor_data(exported.sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(imported.samples, exported.samples);
+    }
+}
@@ -0,0 +1,322 @@
+use super::ParseData;
+use crate::datamodel::batch_builder::BatchBuilder;
+use anyhow::{Context, Result};
+use async_compression::futures::bufread::{GzipDecoder, ZstdDecoder};
+use async_trait::async_trait;
+use flate2::read::GzDecoder;
+use futures::io::{AsyncRead, AsyncReadExt, BufReader, Cursor};
+use std::borrow::Cow;
+use std::io::Read;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+
+/// The error returned when the data decompresses to more than the limit.
+#[derive(Debug)]
+pub struct DecompressedTooLargeError {
+    pub limit: usize,
+}
+
+impl std::fmt::Display for DecompressedTooLargeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The body decompresses to more than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for DecompressedTooLargeError {}
+
+/// Whether the error, or one of its causes, is a [`DecompressedTooLargeError`],
+/// including when a streaming reader wrapped it in an I/O error.
+pub fn is_decompressed_too_large(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let io_error = match cause.downcast_ref::<csv_async::Error>() {
+            Some(error) => match error.kind() {
+                csv_async::ErrorKind::Io(error) => Some(error),
+                _ => None,
+            },
+            None => cause.downcast_ref::<std::io::Error>(),
+        };
+        cause.is::<DecompressedTooLargeError>()
+            || io_error
+                .and_then(|error| error.get_ref())
+                .is_some_and(|inner| inner.is::<DecompressedTooLargeError>())
+    })
+}
+
+/// The configured limit of the decompressed data, 0 for no limit.
+fn max_decompressed_bytes() -> usize {
+    crate::config::get()
+        .map(|config| config.max_decompressed_bytes)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression from the magic bytes at the start of the data.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// The suffix of the parser names forcing the compression.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Self::Gzip => "_gzip",
+            Self::Zstd => "_zstd",
+        }
+    }
+
+    /// Decompresses the data, up to the configured limit.
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.decompress_with_limit(data, max_decompressed_bytes())
+    }
+
+    /// Decompresses the data, failing with a [`DecompressedTooLargeError`]
+    /// as soon as the output exceeds the limit. 0 for no limit.
+    pub fn decompress_with_limit(&self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Self::Gzip => Box::new(GzDecoder::new(data)),
+            Self::Zstd => Box::new(zstd::Decoder::new(data)?),
+        };
+        // One more byte than the limit, to know when it's exceeded
+        let mut decoder = match limit {
+            0 => decoder,
+            limit => Box::new(decoder.take(limit as u64 + 1)),
+        };
+
+        let mut decompressed = Vec::new();
+        decoder
+            .read_to_end(&mut decompressed)
+            .with_context(|| match self {
+                Self::Gzip => "Invalid gzip data",
+                Self::Zstd => "Invalid zstd data",
+            })?;
+        if limit > 0 && decompressed.len() > limit {
+            return Err(DecompressedTooLargeError { limit }.into());
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Decompresses the data when it starts with a known magic number,
+/// or returns it as is.
+pub fn decompress_if_compressed(data: &[u8]) -> Result<Cow<'_, [u8]>> {
+    match Compression::detect(data) {
+        Some(compression) => Ok(Cow::Owned(compression.decompress(data)?)),
+        None => Ok(Cow::Borrowed(data)),
+    }
+}
+
+/// A reader failing with a [`DecompressedTooLargeError`] once more than
+/// `limit` bytes are read.
+struct LimitedReader<R> {
+    inner: R,
+    limit: usize,
+    read: usize,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for LimitedReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = poll {
+            self.read += read;
+            if self.read > self.limit {
+                let limit = self.limit;
+                return Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    DecompressedTooLargeError { limit },
+                )));
+            }
+        }
+        poll
+    }
+}
+
+/// Decompresses a streamed body when it starts with a known magic number,
+/// up to the configured limit, or streams it as is.
+pub async fn decompress_reader_if_compressed<'a, R>(
+    reader: R,
+) -> std::io::Result<Box<dyn AsyncRead + Unpin + Send + 'a>>
+where
+    R: AsyncRead + Unpin + Send + 'a,
+{
+    decompress_reader_with_limit(reader, max_decompressed_bytes()).await
+}
+
+/// Like [`decompress_reader_if_compressed`], with the reads failing with
+/// a [`DecompressedTooLargeError`] once the decompressed output exceeds
+/// the limit. 0 for no limit.
+pub async fn decompress_reader_with_limit<'a, R>(
+    mut reader: R,
+    limit: usize,
+) -> std::io::Result<Box<dyn AsyncRead + Unpin + Send + 'a>>
+where
+    R: AsyncRead + Unpin + Send + 'a,
+{
+    // The first bytes can arrive in several chunks
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    let mut byte = [0u8; 1];
+    while magic.len() < ZSTD_MAGIC.len() && reader.read(&mut byte).await? == 1 {
+        magic.push(byte[0]);
+    }
+    let compression = Compression::detect(&magic);
+    let reader = BufReader::new(Cursor::new(magic).chain(reader));
+    let decoder: Box<dyn AsyncRead + Unpin + Send + 'a> = match compression {
+        Some(Compression::Gzip) => Box::new(GzipDecoder::new(reader)),
+        Some(Compression::Zstd) => Box::new(ZstdDecoder::new(reader)),
+        None => return Ok(Box::new(reader)),
+    };
+    Ok(match limit {
+        0 => decoder,
+        limit => Box::new(LimitedReader {
+            inner: decoder,
+            limit,
+            read: 0,
+        }),
+    })
+}
+
+/// Wraps a parser to decompress its input.
+///
+/// Without an explicit compression, the compression is detected
+/// from the magic bytes and uncompressed data is parsed as is.
+pub struct CompressedParser {
+    inner: Box<dyn ParseData>,
+    compression: Option<Compression>,
+}
+
+impl CompressedParser {
+    pub fn new(inner: Box<dyn ParseData>, compression: Option<Compression>) -> Self {
+        Self { inner, compression }
+    }
+}
+
+#[async_trait]
+impl ParseData for CompressedParser {
+    async fn parse_data(&self, data: &[u8], batch_builder: &mut BatchBuilder) -> Result<()> {
+        let data = match self.compression {
+            Some(compression) => Cow::Owned(compression.decompress(data)?),
+            None => decompress_if_compressed(data)?,
+        };
+        self.inner.parse_data(&data, batch_builder).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_detect_and_decompress() {
+        let data = b"hello";
+        assert_eq!(Compression::detect(data), None);
+        assert_eq!(decompress_if_compressed(data).unwrap().as_ref(), data);
+
+        let gzipped = gzip(data);
+        assert_eq!(Compression::detect(&gzipped), Some(Compression::Gzip));
+        assert_eq!(decompress_if_compressed(&gzipped).unwrap().as_ref(), data);
+
+        let zstded = zstd::encode_all(&data[..], 0).unwrap();
+        assert_eq!(Compression::detect(&zstded), Some(Compression::Zstd));
+        assert_eq!(decompress_if_compressed(&zstded).unwrap().as_ref(), data);
+
+        assert!(Compression::Gzip.decompress(data).is_err());
+        assert!(Compression::Zstd.decompress(data).is_err());
+    }
+
+    #[test]
+    fn test_decompression_bomb() {
+        // 64 MiB of zeros, compressed to less than 100 KiB
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+        let mut zstd_encoder = zstd::Encoder::new(Vec::new(), 3).unwrap();
+        let chunk = vec![0u8; 1024 * 1024];
+        for _ in 0..64 {
+            encoder.write_all(&chunk).unwrap();
+            zstd_encoder.write_all(&chunk).unwrap();
+        }
+        let gzipped = encoder.finish().unwrap();
+        let zstded = zstd_encoder.finish().unwrap();
+
+        let limit = 10 * 1024 * 1024;
+        for (compression, bomb) in [(Compression::Gzip, &gzipped), (Compression::Zstd, &zstded)] {
+            let error = compression.decompress_with_limit(bomb, limit).unwrap_err();
+            assert_eq!(
+                error
+                    .downcast_ref::<DecompressedTooLargeError>()
+                    .unwrap()
+                    .limit,
+                limit
+            );
+        }
+
+        // Exactly at the limit
+        let gzipped = gzip(&chunk);
+        assert_eq!(
+            Compression::Gzip
+                .decompress_with_limit(&gzipped, chunk.len())
+                .unwrap()
+                .len(),
+            chunk.len()
+        );
+        assert!(Compression::Gzip
+            .decompress_with_limit(&gzipped, chunk.len() - 1)
+            .is_err());
+        assert_eq!(
+            Compression::Gzip
+                .decompress_with_limit(&gzipped, 0)
+                .unwrap()
+                .len(),
+            chunk.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decompress_reader() {
+        let data = b"timestamp;value\n1700000000;1\n".repeat(1000);
+        let gzipped = gzip(&data);
+        let zstded = zstd::encode_all(&data[..], 0).unwrap();
+        for body in [&data, &gzipped, &zstded] {
+            let mut reader = decompress_reader_with_limit(&body[..], 0).await.unwrap();
+            let mut decompressed = Vec::new();
+            reader.read_to_end(&mut decompressed).await.unwrap();
+            assert_eq!(decompressed, data);
+        }
+
+        // Shorter than the magic numbers
+        let mut reader = decompress_reader_with_limit(&b"a"[..], 0).await.unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"a");
+
+        // Over the limit
+        let mut reader = decompress_reader_with_limit(&gzipped[..], 1024)
+            .await
+            .unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert!(is_decompressed_too_large(&error.into()));
+    }
+}
@@ -0,0 +1,230 @@
+use super::ParseData;
+use crate::datamodel::{
+    batch_builder::BatchBuilder, sensapp_vec::SensAppLabels, unit_mapping::UnitMapping,
+    SensAppDateTime, Sensor, SensorType, TypedSamples,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use std::{str::from_utf8, sync::Arc};
+
+/// Parses the Graphite plaintext protocol.
+///
+/// Each line is `metric.path value timestamp`, with the timestamp in unix
+/// seconds, or `-1` or nothing for now. Tagged metrics like
+/// `metric.path;tag1=value1;tag2=value2` have their tags as labels.
+#[derive(Debug, Default)]
+pub struct GraphiteParser {
+    /// Label names of the first dot-separated segments of the path.
+    /// An empty name or `_` skips the segment.
+    segment_labels: Vec<String>,
+    /// Units of the metrics, by metric path.
+    unit_mapping: Arc<UnitMapping>,
+}
+
+impl GraphiteParser {
+    pub fn new(segment_labels: Vec<String>) -> Self {
+        Self {
+            segment_labels,
+            unit_mapping: Default::default(),
+        }
+    }
+
+    pub fn with_unit_mapping(mut self, unit_mapping: Arc<UnitMapping>) -> Self {
+        self.unit_mapping = unit_mapping;
+        self
+    }
+
+    /// Uses the `SENSAPP_GRAPHITE_SEGMENT_LABELS` template,
+    /// for example `datacenter.host`, and the `SENSAPP_UNIT_MAPPING`.
+    pub fn from_config() -> Result<Self> {
+        let config = crate::config::get()?;
+        let segment_labels = match &config.graphite_segment_labels {
+            Some(template) => template.split('.').map(str::to_string).collect(),
+            None => Vec::new(),
+        };
+        Ok(Self::new(segment_labels).with_unit_mapping(UnitMapping::shared()?))
+    }
+
+    fn parse_line(&self, line: &str, now: SensAppDateTime) -> Result<(Sensor, TypedSamples)> {
+        let mut fields = line.split_whitespace();
+        let metric = fields.next().ok_or_else(|| anyhow!("Missing metric"))?;
+        let value = fields.next().ok_or_else(|| anyhow!("Missing value"))?;
+        let timestamp = fields.next();
+        if fields.next().is_some() {
+            bail!("Too many fields");
+        }
+
+        let value = value
+            .parse::<f64>()
+            .with_context(|| format!("Invalid value: {}", value))?;
+        let datetime = match timestamp {
+            None | Some("-1") => now,
+            Some(timestamp) => SensAppDateTime::from_unix_seconds(
+                timestamp
+                    .parse::<f64>()
+                    .with_context(|| format!("Invalid timestamp: {}", timestamp))?,
+            ),
+        };
+
+        let mut parts = metric.split(';');
+        let name = parts.next().unwrap_or_default();
+        if name.is_empty() {
+            bail!("Empty metric name");
+        }
+
+        let mut labels = SensAppLabels::new();
+        for (label, segment) in self.segment_labels.iter().zip(name.split('.')) {
+            if !label.is_empty() && label != "_" {
+                labels.push((label.clone(), segment.to_string()));
+            }
+        }
+        for tag in parts {
+            let (key, value) = tag
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid tag: {}", tag))?;
+            if key.is_empty() {
+                bail!("Invalid tag: {}", tag);
+            }
+            labels.push((key.to_string(), value.to_string()));
+        }
+
+        let unit = self.unit_mapping.unit_for(name);
+        let sensor =
+            Sensor::new_without_uuid(name.to_string(), SensorType::Float, unit, Some(labels))?;
+        Ok((sensor, TypedSamples::one_float(value, datetime)))
+    }
+}
+
+#[async_trait]
+impl ParseData for GraphiteParser {
+    async fn parse_data(&self, data: &[u8], batch_builder: &mut BatchBuilder) -> Result<()> {
+        let text = from_utf8(data)?;
+        let now = SensAppDateTime::now()?;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (sensor, samples) = self
+                .parse_line(line, now)
+                .with_context(|| format!("Invalid Graphite line {}", index + 1))?;
+            batch_builder.add(Arc::new(sensor), samples).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::sensapp_datetime::SensAppDateTimeExt;
+
+    fn now() -> SensAppDateTime {
+        SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+    }
+
+    #[test]
+    fn test_plain_line() {
+        let parser = GraphiteParser::default();
+        let (sensor, samples) = parser
+            .parse_line("servers.web01.cpu.load 0.75 1600000000", now())
+            .unwrap();
+        assert_eq!(sensor.name, "servers.web01.cpu.load");
+        assert_eq!(sensor.sensor_type, SensorType::Float);
+        assert!(sensor.labels.is_empty());
+        assert_eq!(
+            samples,
+            TypedSamples::one_float(0.75, SensAppDateTime::from_unix_seconds_i64(1_600_000_000))
+        );
+
+        // -1 and a missing timestamp mean now
+        for line in ["servers.web01.cpu.load 1 -1", "servers.web01.cpu.load 1"] {
+            let (_, samples) = parser.parse_line(line, now()).unwrap();
+            assert_eq!(samples, TypedSamples::one_float(1.0, now()));
+        }
+
+        assert!(parser.parse_line("servers.web01.cpu.load", now()).is_err());
+        assert!(parser
+            .parse_line("servers.web01.cpu.load abc 1600000000", now())
+            .is_err());
+        assert!(parser.parse_line("a 1 2 3", now()).is_err());
+    }
+
+    #[test]
+    fn test_segment_labels() {
+        let parser = GraphiteParser::new(vec![
+            "_".to_string(),
+            "host".to_string(),
+            "subsystem".to_string(),
+        ]);
+        let (sensor, _) = parser
+            .parse_line("servers.web01.cpu.load 0.75 1600000000", now())
+            .unwrap();
+        assert_eq!(sensor.name, "servers.web01.cpu.load");
+        let mut labels = sensor.labels.to_vec();
+        labels.sort();
+        assert_eq!(
+            labels,
+            vec![
+                ("host".to_string(), "web01".to_string()),
+                ("subsystem".to_string(), "cpu".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tagged_line() {
+        let parser = GraphiteParser::default();
+        let (sensor, samples) = parser
+            .parse_line("disk.used;datacenter=dc1;server=web01 42 1600000000", now())
+            .unwrap();
+        assert_eq!(sensor.name, "disk.used");
+        let mut labels = sensor.labels.to_vec();
+        labels.sort();
+        assert_eq!(
+            labels,
+            vec![
+                ("datacenter".to_string(), "dc1".to_string()),
+                ("server".to_string(), "web01".to_string()),
+            ]
+        );
+        assert_eq!(
+            samples,
+            TypedSamples::one_float(42.0, SensAppDateTime::from_unix_seconds_i64(1_600_000_000))
+        );
+
+        assert!(parser.parse_line("disk.used;invalid 42", now()).is_err());
+        assert!(parser.parse_line(";a=b 42", now()).is_err());
+    }
+
+    #[test]
+    fn test_unit_mapping() {
+        let parser = GraphiteParser::default()
+            .with_unit_mapping(Arc::new(UnitMapping::parse("*_celsius => °C").unwrap()));
+        let (sensor, _) = parser
+            .parse_line("office.temperature_celsius 21.5 1600000000", now())
+            .unwrap();
+        assert_eq!(sensor.unit.unwrap().name, "°C");
+        let (sensor, _) = parser
+            .parse_line("office.humidity 40 1600000000", now())
+            .unwrap();
+        assert!(sensor.unit.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_parse_data() {
+        _ = crate::config::load_configuration();
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        let data = b"a.b 1 1600000000\n\na.b 2 1600000010\nc;x=y 3 1600000000\n";
+        GraphiteParser::default()
+            .parse_data(data, &mut batch_builder)
+            .await
+            .unwrap();
+
+        let error = GraphiteParser::default()
+            .parse_data(b"a.b 1 1600000000\nbroken\n", &mut batch_builder)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+    }
+}
@@ -1 +1,109 @@
+use crate::datamodel::batch_builder::BatchBuilder;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use compressed::{CompressedParser, Compression};
+
+pub mod arrow;
+pub mod compressed;
+pub mod graphite;
+pub mod nagios;
 pub mod prometheus;
+pub mod senml;
+pub mod simple_json;
+
+/// A parser converts a raw payload into samples,
+/// and adds them to the given batch builder.
+#[async_trait]
+pub trait ParseData: Send + Sync {
+    async fn parse_data(&self, data: &[u8], batch_builder: &mut BatchBuilder) -> Result<()>;
+}
+
+/// Returns the parser registered under the given name.
+///
+/// The parsers decompress the gzip and zstd data detected by magic bytes,
+/// and the `_gzip` and `_zstd` suffixes force the decompression.
+pub fn get_parser_from_name(name: &str) -> Result<Box<dyn ParseData>> {
+    let compression = [Compression::Gzip, Compression::Zstd]
+        .into_iter()
+        .find(|compression| name.ends_with(compression.suffix()));
+    let base_name = match compression {
+        Some(compression) => name.trim_end_matches(compression.suffix()),
+        None => name,
+    };
+    Ok(Box::new(CompressedParser::new(
+        get_base_parser_from_name(base_name)?,
+        compression,
+    )))
+}
+
+fn get_base_parser_from_name(name: &str) -> Result<Box<dyn ParseData>> {
+    match name {
+        "senml_json" => Ok(Box::new(senml::SenMLParser)),
+        "senml_ndjson" => Ok(Box::new(senml::SenMLNdjsonParser)),
+        "graphite" => Ok(Box::new(graphite::GraphiteParser::from_config()?)),
+        "simple_json" => Ok(Box::new(simple_json::SimpleJsonParser)),
+        "arrow" => Ok(Box::new(arrow::ArrowParser)),
+        "nagios_perfdata" => Ok(Box::new(nagios::NagiosPerfdataParser)),
+        _ => bail!("Unknown parser: {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+
+    #[test]
+    fn test_get_parser_from_name() {
+        _ = load_configuration();
+        assert!(get_parser_from_name("senml_json").is_ok());
+        assert!(get_parser_from_name("senml_ndjson").is_ok());
+        assert!(get_parser_from_name("graphite").is_ok());
+        assert!(get_parser_from_name("simple_json").is_ok());
+        assert!(get_parser_from_name("arrow").is_ok());
+        assert!(get_parser_from_name("nagios_perfdata").is_ok());
+        assert!(get_parser_from_name("senml_json_gzip").is_ok());
+        assert!(get_parser_from_name("graphite_zstd").is_ok());
+        assert!(get_parser_from_name("unknown").is_err());
+        assert!(get_parser_from_name("unknown_gzip").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_senml() {
+        _ = load_configuration();
+        let senml = br#"[{"n":"urn:dev:ow:10e2073a01080063","u":"Cel","v":23.1}]"#;
+        let gzipped = {
+            use std::io::Write;
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(senml).unwrap();
+            encoder.finish().unwrap()
+        };
+        let zstded = zstd::encode_all(&senml[..], 0).unwrap();
+
+        let parser = get_parser_from_name("senml_json").unwrap();
+        for data in [&senml[..], &gzipped, &zstded] {
+            let mut batch_builder = BatchBuilder::new().unwrap();
+            parser.parse_data(data, &mut batch_builder).await.unwrap();
+            assert_eq!(batch_builder.len().await, 1);
+        }
+
+        // The explicit variants require compressed data
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        let parser = get_parser_from_name("senml_json_gzip").unwrap();
+        parser
+            .parse_data(&gzipped, &mut batch_builder)
+            .await
+            .unwrap();
+        assert!(parser.parse_data(senml, &mut batch_builder).await.is_err());
+        let parser = get_parser_from_name("senml_json_zstd").unwrap();
+        parser
+            .parse_data(&zstded, &mut batch_builder)
+            .await
+            .unwrap();
+        assert!(parser
+            .parse_data(&gzipped, &mut batch_builder)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use super::ParseData;
+use crate::datamodel::{
+    batch_builder::BatchBuilder, sensapp_vec::SensAppLabels, unit::Unit, SensAppDateTime, Sensor,
+    SensorType, TypedSamples,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use std::{str::from_utf8, sync::Arc};
+
+/// Names of the labels of the thresholds and bounds, in perfdata order.
+const THRESHOLD_LABELS: [&str; 4] = ["warn", "crit", "min", "max"];
+
+/// Parses the Nagios plugins performance data.
+///
+/// Each line has space-separated metrics, `'label'=value[UOM];warn;crit;min;max`,
+/// optionally after the plugin output and a `|`. The label is the sensor name,
+/// the unit of measurement the sensor unit, and the thresholds and bounds
+/// are kept as the `warn`, `crit`, `min` and `max` labels. Integer values
+/// are integer sensors, and the unknown `U` values are skipped.
+#[derive(Debug, Default)]
+pub struct NagiosPerfdataParser;
+
+impl NagiosPerfdataParser {
+    fn parse_line(&self, line: &str, now: SensAppDateTime) -> Result<Vec<(Sensor, TypedSamples)>> {
+        let perfdata = match line.split_once('|') {
+            Some((_, perfdata)) => perfdata,
+            None => line,
+        };
+        let mut metrics = Vec::new();
+        let mut rest = perfdata.trim_start();
+        while !rest.is_empty() {
+            let (label, after_label) = Self::label(rest)?;
+            let (metric, remaining) = after_label
+                .split_once(char::is_whitespace)
+                .unwrap_or((after_label, ""));
+            if let Some(parsed) = Self::parse_metric(&label, metric, now)
+                .with_context(|| format!("Invalid perfdata for '{}'", label))?
+            {
+                metrics.push(parsed);
+            }
+            rest = remaining.trim_start();
+        }
+        Ok(metrics)
+    }
+
+    /// The label and the text after its `=`.
+    ///
+    /// Quoted labels can have spaces and `=`, and `''` is an escaped quote.
+    fn label(text: &str) -> Result<(String, &str)> {
+        let (label, rest) = match text.strip_prefix('\'') {
+            Some(quoted) => {
+                let mut label = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next() {
+                        Some((index, '\'')) if quoted[index + 1..].starts_with('\'') => {
+                            label.push('\'');
+                            chars.next();
+                        }
+                        Some((index, '\'')) => break index + 1,
+                        Some((_, c)) => label.push(c),
+                        None => bail!("Unterminated label: {}", text),
+                    }
+                };
+                (label, &quoted[end..])
+            }
+            None => {
+                let end = text
+                    .find(|c: char| c == '=' || c.is_whitespace())
+                    .unwrap_or(text.len());
+                (text[..end].to_string(), &text[end..])
+            }
+        };
+        if label.is_empty() {
+            bail!("Empty label");
+        }
+        let rest = rest
+            .strip_prefix('=')
+            .ok_or_else(|| anyhow!("Missing '=' after the label '{}'", label))?;
+        Ok((label, rest))
+    }
+
+    fn parse_metric(
+        label: &str,
+        metric: &str,
+        now: SensAppDateTime,
+    ) -> Result<Option<(Sensor, TypedSamples)>> {
+        let mut fields = metric.split(';');
+        let value_and_unit = fields.next().unwrap_or_default();
+        let unit_start = value_and_unit
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+            .unwrap_or(value_and_unit.len());
+        let (value, unit) = value_and_unit.split_at(unit_start);
+        if unit == "U" && value.is_empty() {
+            return Ok(None);
+        }
+        if value.is_empty() {
+            bail!("Missing value");
+        }
+
+        let mut labels = SensAppLabels::new();
+        for (name, threshold) in THRESHOLD_LABELS.iter().zip(fields.by_ref()) {
+            if !threshold.is_empty() {
+                labels.push((name.to_string(), threshold.to_string()));
+            }
+        }
+        if fields.next().is_some() {
+            bail!("Too many fields");
+        }
+
+        let (sensor_type, samples) = match value.parse::<i64>() {
+            Ok(value) => (SensorType::Integer, TypedSamples::one_integer(value, now)),
+            Err(_) => {
+                let value = value
+                    .parse::<f64>()
+                    .with_context(|| format!("Invalid value: {}", value))?;
+                (SensorType::Float, TypedSamples::one_float(value, now))
+            }
+        };
+        let unit = (!unit.is_empty()).then(|| Unit::new(unit.to_string(), None));
+        let sensor = Sensor::new_without_uuid(label.to_string(), sensor_type, unit, Some(labels))?;
+        Ok(Some((sensor, samples)))
+    }
+}
+
+#[async_trait]
+impl ParseData for NagiosPerfdataParser {
+    async fn parse_data(&self, data: &[u8], batch_builder: &mut BatchBuilder) -> Result<()> {
+        let text = from_utf8(data)?;
+        let now = SensAppDateTime::now()?;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let metrics = self
+                .parse_line(line, now)
+                .with_context(|| format!("Invalid perfdata line {}", index + 1))?;
+            for (sensor, samples) in metrics {
+                batch_builder.add(Arc::new(sensor), samples).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::sensapp_datetime::SensAppDateTimeExt;
+
+    fn now() -> SensAppDateTime {
+        SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+    }
+
+    fn sorted_labels(sensor: &Sensor) -> Vec<(&str, &str)> {
+        let mut labels = sensor
+            .labels
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        labels.sort();
+        labels
+    }
+
+    #[test]
+    fn test_multi_metric_line() {
+        let metrics = NagiosPerfdataParser
+            .parse_line(
+                "DISK OK - free space: / 3326 MB (56%); | '/ used'=2643MB;5948;5958;0;5968 load1=0.250;5.000;10.000;0; users=3",
+                now(),
+            )
+            .unwrap();
+        assert_eq!(metrics.len(), 3);
+
+        let (sensor, samples) = &metrics[0];
+        assert_eq!(sensor.name, "/ used");
+        assert_eq!(sensor.sensor_type, SensorType::Integer);
+        assert_eq!(sensor.unit.as_ref().unwrap().name, "MB");
+        assert_eq!(
+            sorted_labels(sensor),
+            vec![
+                ("crit", "5958"),
+                ("max", "5968"),
+                ("min", "0"),
+                ("warn", "5948"),
+            ]
+        );
+        assert_eq!(samples, &TypedSamples::one_integer(2643, now()));
+
+        let (sensor, samples) = &metrics[1];
+        assert_eq!(sensor.name, "load1");
+        assert_eq!(sensor.sensor_type, SensorType::Float);
+        assert!(sensor.unit.is_none());
+        assert_eq!(
+            sorted_labels(sensor),
+            vec![("crit", "10.000"), ("min", "0"), ("warn", "5.000")]
+        );
+        assert_eq!(samples, &TypedSamples::one_float(0.25, now()));
+
+        let (sensor, samples) = &metrics[2];
+        assert_eq!(sensor.name, "users");
+        assert!(sensor.labels.is_empty());
+        assert_eq!(samples, &TypedSamples::one_integer(3, now()));
+    }
+
+    #[test]
+    fn test_units_and_ranges() {
+        let metrics = NagiosPerfdataParser
+            .parse_line(
+                "time=0.012s;@1:2;~:5 'it''s'=-1.5e2% bytes=10B unknown=U;1;2",
+                now(),
+            )
+            .unwrap();
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics[0].0.unit.as_ref().unwrap().name, "s");
+        assert_eq!(
+            sorted_labels(&metrics[0].0),
+            vec![("crit", "~:5"), ("warn", "@1:2")]
+        );
+        assert_eq!(metrics[1].0.name, "it's");
+        assert_eq!(metrics[1].0.unit.as_ref().unwrap().name, "%");
+        assert_eq!(metrics[1].1, TypedSamples::one_float(-150.0, now()));
+        assert_eq!(metrics[2].0.unit.as_ref().unwrap().name, "B");
+
+        for invalid in [
+            "novalue",
+            "=1",
+            "a=",
+            "a=abc",
+            "'unterminated=1",
+            "a=1;2;3;4;5;6",
+        ] {
+            assert!(
+                NagiosPerfdataParser.parse_line(invalid, now()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_data() {
+        _ = crate::config::load_configuration();
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        let data = b"OK | a=1;2;3 b=2.5s\n\nc=3\n";
+        NagiosPerfdataParser
+            .parse_data(data, &mut batch_builder)
+            .await
+            .unwrap();
+
+        let error = NagiosPerfdataParser
+            .parse_data(b"a=1\nb=broken\n", &mut batch_builder)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+    }
+}
@@ -0,0 +1,364 @@
+// Encoder of the Prometheus XOR chunks, the Gorilla compression
+// used by the Prometheus TSDB and the streamed remote read responses.
+//
+// It follows the Prometheus implementation, tsdb/chunkenc/xor.go.
+// The chunk starts with the number of samples as a big endian u16,
+// followed by a bit stream of the timestamps and the values.
+
+use super::remote_read_models::{Chunk, ChunkEncoding};
+
+/// Prometheus doesn't put more than 120 samples in a chunk.
+pub const MAX_SAMPLES_PER_CHUNK: usize = 120;
+
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Number of bits still available in the last byte.
+    free_bits: u8,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.free_bits == 0 {
+            self.bytes.push(0);
+            self.free_bits = 8;
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 1 << (self.free_bits - 1);
+        }
+        self.free_bits -= 1;
+    }
+
+    /// Writes the `nbits` lowest bits of the value.
+    fn write_bits(&mut self, value: u64, nbits: u8) {
+        for index in (0..nbits).rev() {
+            self.write_bit((value >> index) & 1 == 1);
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_bits(*byte as u64, 8);
+        }
+    }
+}
+
+fn varint(value: i64) -> Vec<u8> {
+    // Zigzag encoding, like Go's binary.PutVarint
+    uvarint(((value << 1) ^ (value >> 63)) as u64)
+}
+
+pub fn uvarint(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(10);
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+    bytes
+}
+
+/// Whether the value fits in `nbits` bits, with the Prometheus asymmetric range.
+fn bit_range(value: i64, nbits: u8) -> bool {
+    -((1 << (nbits - 1)) - 1) <= value && value <= 1 << (nbits - 1)
+}
+
+/// Encodes the samples, sorted by timestamp, in one XOR chunk.
+pub struct XorChunkEncoder {
+    writer: BitWriter,
+    count: u16,
+    timestamp: i64,
+    timestamp_delta: u64,
+    value: f64,
+    leading: u8,
+    trailing: u8,
+    min_time_ms: i64,
+}
+
+impl Default for XorChunkEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XorChunkEncoder {
+    pub fn new() -> Self {
+        Self {
+            writer: BitWriter {
+                // The number of samples, written at the end
+                bytes: vec![0, 0],
+                free_bits: 0,
+            },
+            count: 0,
+            timestamp: 0,
+            timestamp_delta: 0,
+            value: 0.0,
+            leading: 0xff,
+            trailing: 0,
+            min_time_ms: 0,
+        }
+    }
+
+    pub fn append(&mut self, timestamp: i64, value: f64) {
+        match self.count {
+            0 => {
+                self.writer.write_bytes(&varint(timestamp));
+                self.writer.write_bits(value.to_bits(), 64);
+                self.min_time_ms = timestamp;
+            }
+            1 => {
+                let timestamp_delta = timestamp.wrapping_sub(self.timestamp) as u64;
+                self.writer.write_bytes(&uvarint(timestamp_delta));
+                self.write_value(value);
+                self.timestamp_delta = timestamp_delta;
+            }
+            _ => {
+                let timestamp_delta = timestamp.wrapping_sub(self.timestamp) as u64;
+                let delta_of_delta = timestamp_delta.wrapping_sub(self.timestamp_delta) as i64;
+                match delta_of_delta {
+                    0 => self.writer.write_bit(false),
+                    dod if bit_range(dod, 14) => {
+                        self.writer.write_bits(0b10, 2);
+                        self.writer.write_bits(dod as u64, 14);
+                    }
+                    dod if bit_range(dod, 17) => {
+                        self.writer.write_bits(0b110, 3);
+                        self.writer.write_bits(dod as u64, 17);
+                    }
+                    dod if bit_range(dod, 20) => {
+                        self.writer.write_bits(0b1110, 4);
+                        self.writer.write_bits(dod as u64, 20);
+                    }
+                    dod => {
+                        self.writer.write_bits(0b1111, 4);
+                        self.writer.write_bits(dod as u64, 64);
+                    }
+                }
+                self.write_value(value);
+                self.timestamp_delta = timestamp_delta;
+            }
+        }
+        self.timestamp = timestamp;
+        self.value = value;
+        self.count += 1;
+    }
+
+    fn write_value(&mut self, value: f64) {
+        let delta = value.to_bits() ^ self.value.to_bits();
+        if delta == 0 {
+            self.writer.write_bit(false);
+            return;
+        }
+        self.writer.write_bit(true);
+
+        // Clamped to fit in 5 bits
+        let leading = (delta.leading_zeros() as u8).min(31);
+        let trailing = delta.trailing_zeros() as u8;
+        if self.leading != 0xff && leading >= self.leading && trailing >= self.trailing {
+            // The meaningful bits fit in the previous window
+            self.writer.write_bit(false);
+            self.writer
+                .write_bits(delta >> self.trailing, 64 - self.leading - self.trailing);
+            return;
+        }
+
+        self.leading = leading;
+        self.trailing = trailing;
+        self.writer.write_bit(true);
+        self.writer.write_bits(leading as u64, 5);
+        // 64 significant bits overflow to 0, and are decoded as 64
+        let significant_bits = 64 - leading - trailing;
+        self.writer.write_bits(significant_bits as u64, 6);
+        self.writer.write_bits(delta >> trailing, significant_bits);
+    }
+
+    pub fn finish(mut self) -> Chunk {
+        self.writer.bytes[..2].copy_from_slice(&self.count.to_be_bytes());
+        Chunk {
+            min_time_ms: self.min_time_ms,
+            max_time_ms: self.timestamp,
+            r#type: ChunkEncoding::Xor as i32,
+            data: self.writer.bytes,
+        }
+    }
+}
+
+/// Encodes the samples, sorted by timestamp, in chunks of at most
+/// [`MAX_SAMPLES_PER_CHUNK`] samples.
+pub fn encode_xor_chunks(samples: &[(i64, f64)]) -> Vec<Chunk> {
+    samples
+        .chunks(MAX_SAMPLES_PER_CHUNK)
+        .map(|samples| {
+            let mut encoder = XorChunkEncoder::new();
+            for (timestamp, value) in samples {
+                encoder.append(*timestamp, *value);
+            }
+            encoder.finish()
+        })
+        .collect()
+}
+
+/// Decoder of the XOR chunks, to check the encoder.
+#[cfg(test)]
+pub mod decoder {
+    use anyhow::{anyhow, Result};
+
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn read_bit(&mut self) -> Result<bool> {
+            let byte = self
+                .bytes
+                .get(self.position / 8)
+                .ok_or_else(|| anyhow!("Unexpected end of chunk"))?;
+            let bit = (byte >> (7 - self.position % 8)) & 1 == 1;
+            self.position += 1;
+            Ok(bit)
+        }
+
+        fn read_bits(&mut self, nbits: u8) -> Result<u64> {
+            let mut value = 0;
+            for _ in 0..nbits {
+                value = (value << 1) | self.read_bit()? as u64;
+            }
+            Ok(value)
+        }
+
+        fn read_uvarint(&mut self) -> Result<u64> {
+            let mut value = 0;
+            for shift in (0..64).step_by(7) {
+                let byte = self.read_bits(8)?;
+                value |= (byte & 0x7f) << shift;
+                if byte < 0x80 {
+                    return Ok(value);
+                }
+            }
+            Err(anyhow!("Invalid varint"))
+        }
+    }
+
+    pub fn decode_xor_chunk(data: &[u8]) -> Result<Vec<(i64, f64)>> {
+        let count = u16::from_be_bytes([data[0], data[1]]) as usize;
+        let mut reader = BitReader {
+            bytes: &data[2..],
+            position: 0,
+        };
+        let mut samples = Vec::with_capacity(count);
+        let (mut timestamp, mut timestamp_delta, mut value) = (0i64, 0u64, 0u64);
+        let (mut leading, mut trailing) = (0u8, 0u8);
+        for index in 0..count {
+            match index {
+                0 => {
+                    let zigzag = reader.read_uvarint()?;
+                    timestamp = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+                    value = reader.read_bits(64)?;
+                    samples.push((timestamp, f64::from_bits(value)));
+                    continue;
+                }
+                1 => timestamp_delta = reader.read_uvarint()?,
+                _ => {
+                    let mut prefix = 0;
+                    while prefix < 4 && reader.read_bit()? {
+                        prefix += 1;
+                    }
+                    let nbits = [0, 14, 17, 20, 64][prefix];
+                    let mut delta_of_delta = reader.read_bits(nbits)? as i64;
+                    if nbits > 0 && nbits < 64 && delta_of_delta > 1 << (nbits - 1) {
+                        delta_of_delta -= 1 << nbits;
+                    }
+                    timestamp_delta = timestamp_delta.wrapping_add(delta_of_delta as u64);
+                }
+            }
+            timestamp = timestamp.wrapping_add(timestamp_delta as i64);
+            if reader.read_bit()? {
+                if reader.read_bit()? {
+                    leading = reader.read_bits(5)? as u8;
+                    let significant_bits = match reader.read_bits(6)? as u8 {
+                        0 => 64,
+                        significant_bits => significant_bits,
+                    };
+                    trailing = 64 - leading - significant_bits;
+                }
+                value ^= reader.read_bits(64 - leading - trailing)? << trailing;
+            }
+            samples.push((timestamp, f64::from_bits(value)));
+        }
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decoder::decode_xor_chunk;
+    use super::*;
+
+    #[test]
+    fn test_uvarint() {
+        assert_eq!(uvarint(0), vec![0]);
+        assert_eq!(uvarint(300), vec![0xac, 0x02]);
+        assert_eq!(varint(-1), vec![1]);
+        assert_eq!(varint(1), vec![2]);
+    }
+
+    #[test]
+    fn test_xor_chunk_roundtrip() {
+        // Regular and irregular intervals, and repeated, small, large,
+        // and special values, to cover all the encoding branches.
+        let mut timestamp = 1_700_000_000_000i64;
+        let mut samples = Vec::new();
+        for (index, value) in [
+            1.0,
+            1.0,
+            1.5,
+            -2.25,
+            1e300,
+            f64::MIN_POSITIVE,
+            0.0,
+            123456.789,
+            123456.788,
+            f64::INFINITY,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            timestamp += [15_000, 15_000, 15_001, 14_000, 30_000, 1_000_000, 1][index % 7];
+            samples.push((timestamp, value));
+        }
+        samples.push((timestamp + (1 << 40), 42.0));
+
+        let chunks = encode_xor_chunks(&samples);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].min_time_ms, samples[0].0);
+        assert_eq!(chunks[0].max_time_ms, samples.last().unwrap().0);
+        assert_eq!(decode_xor_chunk(&chunks[0].data).unwrap(), samples);
+
+        let samples = (0..250)
+            .map(|index| (index * 1000, (index % 7) as f64))
+            .collect::<Vec<_>>();
+        let chunks = encode_xor_chunks(&samples);
+        assert_eq!(chunks.len(), 3);
+        let decoded = chunks
+            .iter()
+            .flat_map(|chunk| decode_xor_chunk(&chunk.data).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn test_known_chunk() {
+        // Two samples, checked against the Prometheus bit layout:
+        // the count, varint(1000) = [0xd0, 0x0f], the 64 bits of 1.0,
+        // uvarint(10) = 0x0a, and a single 0 bit for the same value.
+        let mut encoder = XorChunkEncoder::new();
+        encoder.append(1000, 1.0);
+        encoder.append(1010, 1.0);
+        let chunk = encoder.finish();
+        assert_eq!(
+            chunk.data,
+            vec![0, 2, 0xd0, 0x0f, 0x3f, 0xf0, 0, 0, 0, 0, 0, 0, 0x0a, 0]
+        );
+    }
+}
@@ -1,2 +1,7 @@
+pub mod chunk_encoder;
+pub mod promql;
+pub mod remote_read_models;
 pub mod remote_write_models;
 pub mod remote_write_parser;
+pub mod remote_write_v2_models;
+pub mod stream_writer;
@@ -0,0 +1,350 @@
+use crate::storage::query::{LabelMatcher, LabelMatcherType, NAME_LABEL};
+use anyhow::{anyhow, bail, Result};
+use hifitime::Duration;
+use regex::Regex;
+
+/// A query of the supported PromQL subset.
+///
+/// Either a bare selector, like `temperature{room="kitchen"}`,
+/// or the rate of a range selector, like `rate(requests_total[5m])`.
+#[derive(Debug, Clone)]
+pub struct PromQLQuery {
+    pub matchers: Vec<LabelMatcher>,
+    /// The window of the `rate()`, if any.
+    pub rate: Option<Duration>,
+}
+
+/// Parses a query of the supported PromQL subset.
+pub fn parse_promql(query: &str) -> Result<PromQLQuery> {
+    let mut parser = Parser {
+        input: query,
+        position: 0,
+    };
+    parser.skip_whitespace();
+    let query = if parser.consume_keyword("rate") {
+        parser.expect('(')?;
+        let matchers = parser.selector()?;
+        parser.expect('[')?;
+        let window = parse_promql_duration(parser.until(']')?.trim())?;
+        parser.expect(']')?;
+        parser.expect(')')?;
+        PromQLQuery {
+            matchers,
+            rate: Some(window),
+        }
+    } else {
+        let matchers = parser.selector()?;
+        if parser.peek() == Some('[') {
+            bail!("Range vectors are only supported in rate()");
+        }
+        PromQLQuery {
+            matchers,
+            rate: None,
+        }
+    };
+    if let Some(c) = parser.peek() {
+        bail!(
+            "Unexpected {:?} at position {}, only selectors and rate() are supported",
+            c,
+            parser.position
+        );
+    }
+    Ok(query)
+}
+
+/// Parses a PromQL duration, like `5m` or `1h30m`.
+pub fn parse_promql_duration(s: &str) -> Result<Duration> {
+    let mut rest = s;
+    let mut duration = Duration::ZERO;
+    if rest.is_empty() {
+        bail!("Empty duration");
+    }
+    while !rest.is_empty() {
+        let unit_start = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("Missing unit in duration '{}'", s))?;
+        let (value, remaining) = rest.split_at(unit_start);
+        let value: i64 = value
+            .parse()
+            .map_err(|_| anyhow!("Invalid duration '{}'", s))?;
+        let unit_end = remaining
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(remaining.len());
+        let (unit, remaining) = remaining.split_at(unit_end);
+        let unit_milliseconds: i64 = match unit {
+            "ms" => 1,
+            "s" => 1_000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            "d" => 86_400_000,
+            "w" => 604_800_000,
+            "y" => 31_536_000_000,
+            _ => bail!("Invalid unit '{}' in duration '{}'", unit, s),
+        };
+        duration += Duration::from_milliseconds(value.saturating_mul(unit_milliseconds) as f64);
+        rest = remaining;
+    }
+    if duration <= Duration::ZERO {
+        bail!("The duration '{}' must be positive", s);
+    }
+    Ok(duration)
+}
+
+/// Whether the matcher matches a missing label, like Prometheus
+/// the selectors must have a matcher that doesn't.
+fn matches_empty(matcher: &LabelMatcher) -> bool {
+    let regex_matches_empty =
+        || Regex::new(&format!("^(?:{})$", matcher.value)).is_ok_and(|regex| regex.is_match(""));
+    match matcher.matcher_type {
+        LabelMatcherType::Equal => matcher.value.is_empty(),
+        LabelMatcherType::NotEqual => !matcher.value.is_empty(),
+        LabelMatcherType::Regex => regex_matches_empty(),
+        LabelMatcherType::NotRegex => !regex_matches_empty(),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += c.len_utf8();
+                self.skip_whitespace();
+                Ok(())
+            }
+            Some(c) => bail!(
+                "Expected {:?} at position {}, found {:?}",
+                expected,
+                self.position,
+                c
+            ),
+            None => bail!("Expected {:?} at the end of the query", expected),
+        }
+    }
+
+    /// Consumes the keyword when it's followed by a parenthesis.
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        let rest = &self.input[self.position..];
+        match rest.strip_prefix(keyword) {
+            Some(after) if after.trim_start().starts_with('(') => {
+                self.position += keyword.len();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The text until the character, exclusive.
+    fn until(&mut self, end: char) -> Result<&str> {
+        let rest = &self.input[self.position..];
+        let length = rest
+            .find(end)
+            .ok_or_else(|| anyhow!("Missing {:?} in the query", end))?;
+        self.position += length;
+        Ok(&rest[..length])
+    }
+
+    fn identifier(&mut self, allow_colon: bool) -> &str {
+        let rest = &self.input[self.position..];
+        let length = rest
+            .char_indices()
+            .find(|(index, c)| {
+                !(c.is_ascii_alphabetic()
+                    || *c == '_'
+                    || (allow_colon && *c == ':')
+                    || (*index > 0 && c.is_ascii_digit()))
+            })
+            .map(|(index, _)| index)
+            .unwrap_or(rest.len());
+        self.position += length;
+        &rest[..length]
+    }
+
+    /// A selector, like `name{label="value", other=~"regex"}`.
+    fn selector(&mut self) -> Result<Vec<LabelMatcher>> {
+        self.skip_whitespace();
+        let mut matchers = Vec::new();
+        let name = self.identifier(true);
+        if !name.is_empty() {
+            matchers.push(LabelMatcher::new(
+                NAME_LABEL.to_string(),
+                name.to_string(),
+                LabelMatcherType::Equal,
+            ));
+        }
+        self.skip_whitespace();
+        if self.peek() == Some('{') {
+            self.expect('{')?;
+            while self.peek() != Some('}') {
+                matchers.push(self.matcher()?);
+                if self.peek() != Some('}') {
+                    self.expect(',')?;
+                }
+            }
+            self.expect('}')?;
+        }
+        if matchers.is_empty() {
+            bail!(
+                "Expected a metric name or a selector at position {}",
+                self.position
+            );
+        }
+        if matchers.iter().all(matches_empty) {
+            bail!("The selector must have at least one matcher not matching the empty value");
+        }
+        Ok(matchers)
+    }
+
+    fn matcher(&mut self) -> Result<LabelMatcher> {
+        let name = self.identifier(false).to_string();
+        if name.is_empty() {
+            bail!("Expected a label name at position {}", self.position);
+        }
+        self.skip_whitespace();
+        let rest = &self.input[self.position..];
+        let (matcher_type, length) = if rest.starts_with("=~") {
+            (LabelMatcherType::Regex, 2)
+        } else if rest.starts_with("!~") {
+            (LabelMatcherType::NotRegex, 2)
+        } else if rest.starts_with("!=") {
+            (LabelMatcherType::NotEqual, 2)
+        } else if rest.starts_with('=') {
+            (LabelMatcherType::Equal, 1)
+        } else {
+            bail!(
+                "Expected a matcher operator after {:?} at position {}",
+                name,
+                self.position
+            );
+        };
+        self.position += length;
+        self.skip_whitespace();
+        let value = self.string()?;
+        self.skip_whitespace();
+        Ok(LabelMatcher::new(name, value, matcher_type))
+    }
+
+    /// A double or single quoted string, with backslash escapes.
+    fn string(&mut self) -> Result<String> {
+        let quote = match self.peek() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => bail!("Expected a quoted string at position {}", self.position),
+        };
+        let mut value = String::new();
+        let mut chars = self.input[self.position + 1..].char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                c if c == quote => {
+                    self.position += 1 + index + 1;
+                    return Ok(value);
+                }
+                c => value.push(c),
+            }
+        }
+        bail!("Unterminated string at position {}", self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matchers(query: &PromQLQuery) -> Vec<(&str, &str, LabelMatcherType)> {
+        query
+            .matchers
+            .iter()
+            .map(|matcher| {
+                (
+                    matcher.name.as_str(),
+                    matcher.value.as_str(),
+                    matcher.matcher_type,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_promql() {
+        let query = parse_promql("temperature").unwrap();
+        assert_eq!(
+            matchers(&query),
+            vec![(NAME_LABEL, "temperature", LabelMatcherType::Equal)]
+        );
+        assert!(query.rate.is_none());
+
+        let query =
+            parse_promql(r#" node:cpu { room = "kitchen", host!~'db-\\d+', job=~"a|b", } "#)
+                .unwrap();
+        assert_eq!(
+            matchers(&query),
+            vec![
+                (NAME_LABEL, "node:cpu", LabelMatcherType::Equal),
+                ("room", "kitchen", LabelMatcherType::Equal),
+                ("host", "db-\\d+", LabelMatcherType::NotRegex),
+                ("job", "a|b", LabelMatcherType::Regex),
+            ]
+        );
+
+        let query = parse_promql(r#"{__name__="requests_total",code!="500"}"#).unwrap();
+        assert_eq!(query.matchers.len(), 2);
+
+        let query = parse_promql(r#"rate(requests_total{code="200"}[1m30s])"#).unwrap();
+        assert_eq!(query.matchers.len(), 2);
+        assert_eq!(query.rate, Some(Duration::from_seconds(90.0)));
+
+        for invalid in [
+            "",
+            "{}",
+            r#"{job=""}"#,
+            r#"{job=~".*"}"#,
+            "requests_total[5m]",
+            "sum(requests_total)",
+            "requests_total + 1",
+            r#"temperature{room="kitchen"#,
+            r#"temperature{room=kitchen}"#,
+            "rate(requests_total[5x])",
+            "rate(requests_total)",
+        ] {
+            assert!(parse_promql(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_promql_duration() {
+        assert_eq!(
+            parse_promql_duration("5m").unwrap(),
+            Duration::from_seconds(300.0)
+        );
+        assert_eq!(
+            parse_promql_duration("1h30m").unwrap(),
+            Duration::from_seconds(5400.0)
+        );
+        assert_eq!(
+            parse_promql_duration("250ms").unwrap(),
+            Duration::from_milliseconds(250.0)
+        );
+        assert!(parse_promql_duration("5").is_err());
+        assert!(parse_promql_duration("0s").is_err());
+        assert!(parse_promql_duration("m").is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use super::remote_write_models::WriteRequest;
+use super::remote_write_v2_models::Request;
 use anyhow::Result;
 use prost::Message;
 use snap::raw::Decoder;
@@ -26,6 +27,12 @@ pub fn parse_remote_write_request(input: &[u8]) -> Result<WriteRequest> {
     parse_protobuf(&decompressed)
 }
 
+/// Parses a Remote Write 2.0 request.
+pub fn parse_remote_write_v2_request(input: &[u8]) -> Result<Request> {
+    let decompressed = decompress_snappy(input)?;
+    Ok(Request::decode(&mut Cursor::new(decompressed))?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::remote_write_models::{Label, Sample, TimeSeries};
@@ -80,4 +87,40 @@ mod tests {
         assert_eq!(output.timeseries[0].labels.len(), 1);
         assert_eq!(output.timeseries[0].samples.len(), 1);
     }
+
+    #[test]
+    fn test_parse_remote_write_v2_request() {
+        use super::super::remote_write_v2_models::{self as v2, MetricType};
+        let input_data = v2::Request {
+            symbols: vec![
+                "".to_string(),
+                "__name__".to_string(),
+                "test".to_string(),
+                "Help".to_string(),
+                "seconds".to_string(),
+            ],
+            timeseries: vec![v2::TimeSeries {
+                labels_refs: vec![1, 2],
+                samples: vec![v2::Sample {
+                    value: 1.0,
+                    timestamp: 1,
+                }],
+                metadata: Some(v2::Metadata {
+                    r#type: MetricType::Gauge as i32,
+                    help_ref: 3,
+                    unit_ref: 4,
+                }),
+            }],
+        };
+        let compressed = snap::raw::Encoder::new()
+            .compress_vec(&input_data.encode_to_vec())
+            .unwrap();
+
+        let output = parse_remote_write_v2_request(&compressed).unwrap();
+        assert_eq!(output.timeseries.len(), 1);
+        let metadata = output.timeseries[0].metadata.as_ref().unwrap();
+        assert_eq!(metadata.r#type(), MetricType::Gauge);
+        assert_eq!(output.symbol(metadata.unit_ref).unwrap(), "seconds");
+        assert!(output.symbol(42).is_err());
+    }
 }
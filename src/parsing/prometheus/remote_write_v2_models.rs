@@ -0,0 +1,83 @@
+// Remote Write 2.0 messages, manually written like the 1.0 messages.
+//
+// Only the fields used by SensApp are declared, the native histograms
+// and the exemplars are skipped by the decoder.
+//
+// Check https://prometheus.io/docs/specs/remote_write_spec_2_0/
+// for more information.
+
+#[derive(prost::Message)]
+pub struct Request {
+    /// The strings referenced by the time series, the first one is empty.
+    #[prost(string, repeated, tag = "4")]
+    pub symbols: Vec<String>,
+    #[prost(message, repeated, tag = "5")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(prost::Message)]
+pub struct TimeSeries {
+    /// Pairs of references to the name and the value of the labels.
+    #[prost(uint32, repeated, tag = "1")]
+    pub labels_refs: Vec<u32>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+    #[prost(message, optional, tag = "5")]
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(prost::Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+#[derive(prost::Message)]
+pub struct Metadata {
+    #[prost(enumeration = "MetricType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "3")]
+    pub help_ref: u32,
+    #[prost(uint32, tag = "4")]
+    pub unit_ref: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+pub enum MetricType {
+    Unspecified = 0,
+    Counter = 1,
+    Gauge = 2,
+    Histogram = 3,
+    Gaugehistogram = 4,
+    Summary = 5,
+    Info = 6,
+    Stateset = 7,
+}
+
+impl MetricType {
+    /// Returns the name of the metric type, as in the Prometheus exposition format.
+    pub fn as_str(&self) -> Option<&'static str> {
+        match self {
+            MetricType::Unspecified => None,
+            MetricType::Counter => Some("counter"),
+            MetricType::Gauge => Some("gauge"),
+            MetricType::Histogram => Some("histogram"),
+            MetricType::Gaugehistogram => Some("gaugehistogram"),
+            MetricType::Summary => Some("summary"),
+            MetricType::Info => Some("info"),
+            MetricType::Stateset => Some("stateset"),
+        }
+    }
+}
+
+impl Request {
+    /// Returns the symbol of the reference, or an error when it's out of bounds.
+    pub fn symbol(&self, reference: u32) -> anyhow::Result<&str> {
+        self.symbols
+            .get(reference as usize)
+            .map(|symbol| symbol.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid symbol reference: {}", reference))
+    }
+}
@@ -0,0 +1,85 @@
+// Framing of the streamed remote read responses.
+//
+// Each frame is the uvarint size of the message, the CRC32 Castagnoli
+// checksum of the message as a big endian u32, and the message itself.
+// It follows the Prometheus implementation, storage/remote/chunked.go.
+
+use super::chunk_encoder::uvarint;
+use super::remote_read_models::ChunkedReadResponse;
+use crc::{Crc, CRC_32_ISCSI};
+use prost::Message;
+
+/// The content type of the streamed remote read responses.
+pub const STREAMED_CONTENT_TYPE: &str =
+    "application/x-streamed-protobuf; proto=prometheus.ChunkedReadResponse";
+
+/// Prometheus refuses frames larger than 50MB by default,
+/// and Prometheus itself sends frames of about 1MB.
+pub const MAX_FRAME_BYTES: usize = 1024 * 1024;
+
+const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+pub fn encode_frame(response: &ChunkedReadResponse) -> Vec<u8> {
+    let message = response.encode_to_vec();
+    let mut frame = uvarint(message.len() as u64);
+    frame.reserve(4 + message.len());
+    frame.extend_from_slice(&CASTAGNOLI.checksum(&message).to_be_bytes());
+    frame.extend_from_slice(&message);
+    frame
+}
+
+/// Decoder of the frames, to check the writer.
+#[cfg(test)]
+pub fn decode_frames(mut data: &[u8]) -> anyhow::Result<Vec<ChunkedReadResponse>> {
+    use anyhow::bail;
+    let mut responses = Vec::new();
+    while !data.is_empty() {
+        let size = prost::encoding::decode_varint(&mut data)? as usize;
+        if data.len() < 4 + size {
+            bail!("Truncated frame");
+        }
+        let checksum = u32::from_be_bytes(data[..4].try_into()?);
+        let message = &data[4..4 + size];
+        if CASTAGNOLI.checksum(message) != checksum {
+            bail!("Invalid frame checksum");
+        }
+        responses.push(ChunkedReadResponse::decode(message)?);
+        data = &data[4 + size..];
+    }
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::prometheus::{
+        chunk_encoder::encode_xor_chunks, remote_read_models::ChunkedSeries,
+        remote_write_models::Label,
+    };
+
+    #[test]
+    fn test_frames_roundtrip() {
+        let response = |query_index| ChunkedReadResponse {
+            chunked_series: vec![ChunkedSeries {
+                labels: vec![Label {
+                    name: "__name__".to_string(),
+                    value: "up".to_string(),
+                }],
+                chunks: encode_xor_chunks(&[(1000, 1.0), (2000, 0.0)]),
+            }],
+            query_index,
+        };
+        let mut data = encode_frame(&response(0));
+        data.extend(encode_frame(&response(1)));
+
+        let responses = decode_frames(&data).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[1].query_index, 1);
+        assert_eq!(responses[0].chunked_series[0].labels[0].value, "up");
+
+        // A corrupted message fails the checksum
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        assert!(decode_frames(&data).is_err());
+    }
+}
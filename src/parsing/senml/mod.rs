@@ -0,0 +1,324 @@
+use super::ParseData;
+use crate::datamodel::{
+    batch_builder::BatchBuilder, sensapp_datetime::SensAppDateTimeExt, unit::Unit, SensAppDateTime,
+    Sensor, SensorType, TypedSamples,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use sindit_senml::{parse_json, SenMLResolvedRecord, SenMLValueField};
+use std::{str::from_utf8, sync::Arc};
+
+/// Parses a SenML JSON pack.
+pub struct SenMLParser;
+
+/// Parses newline-delimited SenML JSON packs.
+///
+/// Each non-empty line is an independent SenML pack,
+/// so base fields do not carry over from one line to the next.
+pub struct SenMLNdjsonParser;
+
+/// The SenML version of RFC 8428, the default when `bver` is absent.
+pub const SUPPORTED_SENML_VERSION: u64 = 10;
+
+/// What to do with the records of a SenML version above the supported one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SenMLVersionPolicy {
+    /// Reject the pack with an error.
+    Reject,
+    /// Log a warning and parse the records as the supported version.
+    Warn,
+}
+
+/// Checks the version of the record against the supported SenML version.
+///
+/// The fields ending with `_` must be understood, as per RFC 8428, so the
+/// records using such fields of a newer version are always rejected.
+fn check_senml_version(record: &SenMLResolvedRecord, policy: SenMLVersionPolicy) -> Result<()> {
+    if let Some(field) = record
+        .extra_fields
+        .iter()
+        .flat_map(|fields| fields.keys())
+        .find(|field| field.ends_with('_'))
+    {
+        bail!(
+            "Record {} uses the unsupported must-understand field {}",
+            record.name,
+            field
+        );
+    }
+    match record.base_version {
+        Some(version) if version > SUPPORTED_SENML_VERSION => match policy {
+            SenMLVersionPolicy::Reject => bail!(
+                "Record {} uses the SenML version {}, above the supported version {}",
+                record.name,
+                version,
+                SUPPORTED_SENML_VERSION
+            ),
+            SenMLVersionPolicy::Warn => {
+                tracing::warn!(
+                    "Record {} uses the SenML version {}, parsed as version {}",
+                    record.name,
+                    version,
+                    SUPPORTED_SENML_VERSION
+                );
+                Ok(())
+            }
+        },
+        _ => Ok(()),
+    }
+}
+
+fn senml_datetime(record: &SenMLResolvedRecord) -> Result<SensAppDateTime> {
+    let nanoseconds = record
+        .time
+        .timestamp_nanos_opt()
+        .ok_or_else(|| anyhow!("Time is out of range for record {}", record.name))?;
+    Ok(SensAppDateTime::from_unix_nanoseconds_i64(nanoseconds))
+}
+
+/// Suffix of the companion sensor storing the sums of the records.
+const SUM_SENSOR_SUFFIX: &str = "__sum__";
+
+/// Converts a record to its sensors and samples.
+///
+/// The sum is an integrated total, distinct from the value, so it's
+/// stored in a companion sensor named after the record, with the
+/// `__sum__` suffix. A record with both a value and a sum gives two sensors.
+fn senml_record_to_sensapp(record: SenMLResolvedRecord) -> Result<Vec<(Sensor, TypedSamples)>> {
+    let datetime = senml_datetime(&record)?;
+    let value = match record.value {
+        Some(SenMLValueField::FloatingPoint(value)) => {
+            Some((SensorType::Float, TypedSamples::one_float(value, datetime)))
+        }
+        Some(SenMLValueField::StringValue(value)) => Some((
+            SensorType::String,
+            TypedSamples::one_string(value, datetime),
+        )),
+        Some(SenMLValueField::BooleanValue(value)) => Some((
+            SensorType::Boolean,
+            TypedSamples::one_boolean(value, datetime),
+        )),
+        Some(SenMLValueField::DataValue(value)) => {
+            Some((SensorType::Blob, TypedSamples::one_blob(value, datetime)))
+        }
+        None => None,
+    };
+    if value.is_none() && record.sum.is_none() {
+        bail!("Record {} has no value", record.name);
+    }
+
+    let unit = record.unit.map(|unit| Unit::new(unit, None));
+    let mut sensors = Vec::with_capacity(2);
+    if let Some(sum) = record.sum {
+        let sensor = Sensor::new_without_uuid(
+            format!("{}{}", record.name, SUM_SENSOR_SUFFIX),
+            SensorType::Float,
+            unit.clone(),
+            None,
+        )?;
+        sensors.push((sensor, TypedSamples::one_float(sum, datetime)));
+    }
+    if let Some((sensor_type, samples)) = value {
+        let sensor = Sensor::new_without_uuid(record.name, sensor_type, unit, None)?;
+        sensors.push((sensor, samples));
+    }
+    Ok(sensors)
+}
+
+async fn add_senml_pack(
+    json: &str,
+    batch_builder: &mut BatchBuilder,
+    version_policy: SenMLVersionPolicy,
+) -> Result<()> {
+    let records = parse_json(json, None)?;
+    for record in records {
+        check_senml_version(&record, version_policy)?;
+        for (sensor, samples) in senml_record_to_sensapp(record)? {
+            batch_builder.add(Arc::new(sensor), samples).await?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl ParseData for SenMLParser {
+    async fn parse_data(&self, data: &[u8], batch_builder: &mut BatchBuilder) -> Result<()> {
+        let json = from_utf8(data)?;
+        let version_policy = crate::config::get()?.senml_version_policy;
+        add_senml_pack(json, batch_builder, version_policy).await
+    }
+}
+
+#[async_trait]
+impl ParseData for SenMLNdjsonParser {
+    async fn parse_data(&self, data: &[u8], batch_builder: &mut BatchBuilder) -> Result<()> {
+        let ndjson = from_utf8(data)?;
+        let version_policy = crate::config::get()?.senml_version_policy;
+        for (index, line) in ndjson.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            add_senml_pack(line, batch_builder, version_policy)
+                .await
+                .with_context(|| format!("Invalid SenML pack on line {}", index + 1))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{message::Message, EventBus};
+    use crate::config::load_configuration;
+    use crate::datamodel::batch::Batch;
+
+    async fn parse_to_batch(parser: &dyn ParseData, data: &[u8]) -> Result<Arc<Batch>> {
+        _ = load_configuration();
+        let event_bus = Arc::new(EventBus::init("test".to_string()));
+        let mut receiver = event_bus.main_bus_receiver.activate_cloned();
+        let mut batch_builder = BatchBuilder::new()?;
+        parser.parse_data(data, &mut batch_builder).await?;
+        batch_builder.send_what_is_left(event_bus).await?;
+        match receiver.recv().await? {
+            Message::Publish(publish_message) => Ok(publish_message.batch),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_senml_json() {
+        let data = br#"[
+            {"bn": "urn:dev:ow:10e2073a01080063:", "bt": 1320067464, "bu": "Cel", "n": "temp", "v": 23.1},
+            {"n": "temp", "v": 23.2, "t": 10},
+            {"n": "open", "vb": true}
+        ]"#;
+        let batch = parse_to_batch(&SenMLParser, data).await.unwrap();
+        assert_eq!(batch.sensors.len(), 2);
+        assert_eq!(batch.len().await, 3);
+
+        let temperature = batch
+            .sensors
+            .iter()
+            .find(|s| s.sensor.name == "urn:dev:ow:10e2073a01080063:temp")
+            .unwrap();
+        assert_eq!(temperature.sensor.sensor_type, SensorType::Float);
+        assert_eq!(temperature.sensor.unit.as_ref().unwrap().name, "Cel");
+    }
+
+    #[tokio::test]
+    async fn test_senml_ndjson() {
+        let data = b"[{\"n\": \"a\", \"v\": 1.0, \"t\": 1}]
+[{\"bn\": \"b:\", \"n\": \"c\", \"vs\": \"hello\", \"t\": 2}, {\"n\": \"c\", \"vs\": \"world\", \"t\": 3}]
+
+[{\"n\": \"a\", \"v\": 3.0, \"t\": 4}]
+";
+        let batch = parse_to_batch(&SenMLNdjsonParser, data).await.unwrap();
+        assert_eq!(batch.sensors.len(), 2);
+        assert_eq!(batch.len().await, 4);
+
+        // The base name from the second line must not leak to the third one
+        let a = batch.sensors.iter().find(|s| s.sensor.name == "a").unwrap();
+        assert_eq!(a.len().await, 2);
+        let c = batch
+            .sensors
+            .iter()
+            .find(|s| s.sensor.name == "b:c")
+            .unwrap();
+        assert_eq!(c.sensor.sensor_type, SensorType::String);
+    }
+
+    #[tokio::test]
+    async fn test_senml_ndjson_error_line_number() {
+        let data = b"[{\"n\": \"a\", \"v\": 1.0}]
+[{\"n\": \"a\", \"v\": 2.0}]
+[{\"n\": \"a\", \"v\": }]
+";
+        let result = parse_to_batch(&SenMLNdjsonParser, data).await;
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("line 3"));
+
+        // The regular SenML parser doesn't accept NDJSON
+        let data = b"[{\"n\": \"a\", \"v\": 1.0}]
+[{\"n\": \"a\", \"v\": 2.0}]
+";
+        assert!(parse_to_batch(&SenMLParser, data).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_senml_value_and_sum() {
+        let data = br#"[{"bn": "meter:", "bu": "W", "n": "energy", "v": 1.5, "s": 42.0, "t": 1}]"#;
+        let batch = parse_to_batch(&SenMLParser, data).await.unwrap();
+        assert_eq!(batch.sensors.len(), 2);
+
+        let value = batch
+            .sensors
+            .iter()
+            .find(|s| s.sensor.name == "meter:energy")
+            .unwrap();
+        let sum = batch
+            .sensors
+            .iter()
+            .find(|s| s.sensor.name == "meter:energy__sum__")
+            .unwrap();
+        assert_eq!(sum.sensor.sensor_type, SensorType::Float);
+        assert_eq!(sum.sensor.unit.as_ref().unwrap().name, "W");
+        let TypedSamples::Float(value_samples) = &*value.samples.read().await else {
+            panic!("Expected float samples");
+        };
+        let TypedSamples::Float(sum_samples) = &*sum.samples.read().await else {
+            panic!("Expected float samples");
+        };
+        assert_eq!(value_samples[0].value, 1.5);
+        assert_eq!(sum_samples[0].value, 42.0);
+        assert_eq!(value_samples[0].datetime, sum_samples[0].datetime);
+
+        // Without a value, the sum still goes to the companion sensor
+        let data = br#"[{"n": "energy", "s": 42.0}]"#;
+        let batch = parse_to_batch(&SenMLParser, data).await.unwrap();
+        assert_eq!(batch.len().await, 1);
+        assert_eq!(batch.sensors[0].sensor.name, "energy__sum__");
+    }
+
+    #[tokio::test]
+    async fn test_senml_version() {
+        _ = load_configuration();
+        let newer = r#"[{"bver": 11, "n": "temp", "v": 21.5}, {"n": "temp", "v": 21.6, "t": 1}]"#;
+
+        // Rejected by default
+        let error = parse_to_batch(&SenMLParser, newer.as_bytes())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("SenML version 11"));
+
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        assert!(
+            add_senml_pack(newer, &mut batch_builder, SenMLVersionPolicy::Reject)
+                .await
+                .is_err()
+        );
+        add_senml_pack(newer, &mut batch_builder, SenMLVersionPolicy::Warn)
+            .await
+            .unwrap();
+        assert_eq!(batch_builder.len().await, 2);
+
+        // The supported version is fine with both policies
+        let current = r#"[{"bver": 10, "n": "temp", "v": 21.5}]"#;
+        add_senml_pack(current, &mut batch_builder, SenMLVersionPolicy::Reject)
+            .await
+            .unwrap();
+
+        // The must-understand fields are always rejected
+        let must_understand = r#"[{"n": "temp", "v": 21.5, "rt_": "future"}]"#;
+        let error = add_senml_pack(
+            must_understand,
+            &mut batch_builder,
+            SenMLVersionPolicy::Warn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("rt_"));
+    }
+}
@@ -0,0 +1,276 @@
+use super::ParseData;
+use crate::datamodel::{
+    batch_builder::BatchBuilder, sensapp_vec::SensAppLabels, unit::Unit, SensAppDateTime, Sensor,
+    SensorType, TypedSamples,
+};
+use crate::infer::parsing::{parse_iso8601_datetime, InferedValue};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+/// Parses a JSON array, or a single object, of simple records:
+/// `{"name": "temperature", "value": 21.5, "time": "2024-01-01T00:00:00Z"}`.
+///
+/// The sensor type follows the JSON type of the value: integer, float,
+/// string, boolean, an object for JSON, and an array of two numbers,
+/// longitude then latitude, for a location. The optional time is an
+/// ISO 8601 datetime or unix seconds, now by default. The optional
+/// `unit` and `labels` describe the sensor.
+pub struct SimpleJsonParser;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SimpleJsonRecord {
+    name: String,
+    value: Value,
+    #[serde(default)]
+    time: Option<Value>,
+    #[serde(default)]
+    unit: Option<String>,
+    #[serde(default)]
+    labels: Option<Map<String, Value>>,
+}
+
+fn simple_json_datetime(time: Option<Value>, now: SensAppDateTime) -> Result<SensAppDateTime> {
+    match time {
+        None | Some(Value::Null) => Ok(now),
+        Some(Value::Number(seconds)) => seconds
+            .as_f64()
+            .map(SensAppDateTime::from_unix_seconds)
+            .ok_or_else(|| anyhow!("Invalid time: {}", seconds)),
+        Some(Value::String(datetime)) => match parse_iso8601_datetime(&datetime) {
+            Ok((_, InferedValue::DateTime(datetime))) => Ok(datetime),
+            _ => bail!("Invalid time: {}", datetime),
+        },
+        Some(time) => bail!("Invalid time: {}", time),
+    }
+}
+
+fn simple_json_samples(value: Value, datetime: SensAppDateTime) -> Result<TypedSamples> {
+    Ok(match value {
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => TypedSamples::one_integer(value, datetime),
+            None => TypedSamples::one_float(
+                number
+                    .as_f64()
+                    .ok_or_else(|| anyhow!("Invalid number: {}", number))?,
+                datetime,
+            ),
+        },
+        Value::String(value) => TypedSamples::one_string(value, datetime),
+        Value::Bool(value) => TypedSamples::one_boolean(value, datetime),
+        Value::Array(coordinates) => match coordinates.as_slice() {
+            [Value::Number(longitude), Value::Number(latitude)] => {
+                let (longitude, latitude) = longitude
+                    .as_f64()
+                    .zip(latitude.as_f64())
+                    .ok_or_else(|| anyhow!("Invalid location"))?;
+                TypedSamples::one_location(geo::Point::new(longitude, latitude), datetime)
+            }
+            _ => bail!("Only the arrays of two numbers are supported, as locations"),
+        },
+        Value::Object(_) => TypedSamples::one_json(value, datetime),
+        Value::Null => bail!("Missing value"),
+    })
+}
+
+fn sensor_type_of(samples: &TypedSamples) -> SensorType {
+    match samples {
+        TypedSamples::Integer(_) => SensorType::Integer,
+        TypedSamples::Numeric(_) => SensorType::Numeric,
+        TypedSamples::Float(_) => SensorType::Float,
+        TypedSamples::String(_) => SensorType::String,
+        TypedSamples::Boolean(_) => SensorType::Boolean,
+        TypedSamples::Location(_) => SensorType::Location,
+        TypedSamples::Blob(_) => SensorType::Blob,
+        TypedSamples::Json(_) => SensorType::Json,
+    }
+}
+
+fn simple_json_labels(labels: Map<String, Value>) -> Result<SensAppLabels> {
+    labels
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::String(value) => Ok((key, value)),
+            Value::Number(_) | Value::Bool(_) => Ok((key, value.to_string())),
+            _ => bail!("Invalid value of the label {}", key),
+        })
+        .collect()
+}
+
+fn simple_json_record_to_sensapp(
+    record: Value,
+    now: SensAppDateTime,
+) -> Result<(Sensor, TypedSamples)> {
+    let record: SimpleJsonRecord = serde_json::from_value(record)?;
+    let datetime = simple_json_datetime(record.time, now)?;
+    let samples = simple_json_samples(record.value, datetime)?;
+    let labels = record.labels.map(simple_json_labels).transpose()?;
+    let unit = record.unit.map(|unit| Unit::new(unit, None));
+    let sensor = Sensor::new_without_uuid(record.name, sensor_type_of(&samples), unit, labels)?;
+    Ok((sensor, samples))
+}
+
+#[async_trait]
+impl ParseData for SimpleJsonParser {
+    async fn parse_data(&self, data: &[u8], batch_builder: &mut BatchBuilder) -> Result<()> {
+        let records = match serde_json::from_slice(data)? {
+            Value::Array(records) => records,
+            record @ Value::Object(_) => vec![record],
+            _ => bail!("Expected an array of records or a single record"),
+        };
+        let now = SensAppDateTime::now()?;
+        for (index, record) in records.into_iter().enumerate() {
+            let (sensor, samples) = simple_json_record_to_sensapp(record, now)
+                .with_context(|| format!("Invalid record {}", index + 1))?;
+            batch_builder.add(Arc::new(sensor), samples).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::sensapp_datetime::SensAppDateTimeExt;
+    use serde_json::json;
+
+    fn now() -> SensAppDateTime {
+        SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+    }
+
+    fn parse(record: Value) -> Result<(Sensor, TypedSamples)> {
+        simple_json_record_to_sensapp(record, now())
+    }
+
+    #[test]
+    fn test_value_types() {
+        _ = load_configuration();
+        let cases = [
+            (json!(42), TypedSamples::one_integer(42, now())),
+            (json!(-7), TypedSamples::one_integer(-7, now())),
+            (json!(21.5), TypedSamples::one_float(21.5, now())),
+            (
+                json!("open"),
+                TypedSamples::one_string("open".to_string(), now()),
+            ),
+            (json!(true), TypedSamples::one_boolean(true, now())),
+            (
+                json!({"state": "idle"}),
+                TypedSamples::one_json(json!({"state": "idle"}), now()),
+            ),
+            (
+                json!([10.75, 59.91]),
+                TypedSamples::one_location(geo::Point::new(10.75, 59.91), now()),
+            ),
+        ];
+        for (value, expected) in cases {
+            let (sensor, samples) = parse(json!({"name": "sensor", "value": value})).unwrap();
+            assert_eq!(sensor.sensor_type, sensor_type_of(&expected));
+            assert_eq!(samples, expected);
+        }
+        assert_eq!(
+            parse(json!({"name": "sensor", "value": 42}))
+                .unwrap()
+                .0
+                .sensor_type,
+            SensorType::Integer
+        );
+        assert_eq!(
+            parse(json!({"name": "sensor", "value": 21.5}))
+                .unwrap()
+                .0
+                .sensor_type,
+            SensorType::Float
+        );
+
+        for value in [json!(null), json!([1, 2, 3]), json!(["a", "b"])] {
+            assert!(parse(json!({"name": "sensor", "value": value})).is_err());
+        }
+        assert!(parse(json!({"name": "sensor"})).is_err());
+        assert!(parse(json!({"value": 1})).is_err());
+        assert!(parse(json!({"name": "sensor", "value": 1, "unknown": 1})).is_err());
+    }
+
+    #[test]
+    fn test_time_unit_and_labels() {
+        _ = load_configuration();
+        let (sensor, samples) = parse(json!({
+            "name": "temperature",
+            "value": 21.5,
+            "time": "2024-01-01T00:00:00Z",
+            "unit": "Cel",
+            "labels": {"room": "kitchen", "floor": 1}
+        }))
+        .unwrap();
+        assert_eq!(
+            samples,
+            TypedSamples::one_float(21.5, SensAppDateTime::from_unix_seconds_i64(1_704_067_200))
+        );
+        assert_eq!(sensor.unit.unwrap().name, "Cel");
+        let mut labels = sensor.labels.to_vec();
+        labels.sort();
+        assert_eq!(
+            labels,
+            vec![
+                ("floor".to_string(), "1".to_string()),
+                ("room".to_string(), "kitchen".to_string()),
+            ]
+        );
+
+        let (_, samples) =
+            parse(json!({"name": "temperature", "value": 1, "time": 1_600_000_000.5})).unwrap();
+        assert_eq!(
+            samples,
+            TypedSamples::one_integer(1, SensAppDateTime::from_unix_seconds(1_600_000_000.5))
+        );
+
+        assert!(parse(json!({"name": "t", "value": 1, "time": "yesterday"})).is_err());
+        assert!(parse(json!({"name": "t", "value": 1, "time": true})).is_err());
+        assert!(parse(json!({"name": "t", "value": 1, "labels": {"a": [1]}})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_data() {
+        _ = load_configuration();
+        let parser = SimpleJsonParser;
+
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        parser
+            .parse_data(
+                br#"[{"name":"temp","value":21.5,"time":"2024-01-01T00:00:00Z"},
+                     {"name":"temp","value":22.5,"time":"2024-01-01T00:01:00Z"},
+                     {"name":"door","value":"open"}]"#,
+                &mut batch_builder,
+            )
+            .await
+            .unwrap();
+        assert_eq!(batch_builder.len().await, 3);
+        assert_eq!(batch_builder.sensors_len().await, 2);
+
+        // A single record
+        let mut batch_builder = BatchBuilder::new().unwrap();
+        parser
+            .parse_data(br#"{"name":"temp","value":21.5}"#, &mut batch_builder)
+            .await
+            .unwrap();
+        assert_eq!(batch_builder.len().await, 1);
+
+        let error = parser
+            .parse_data(
+                br#"[{"name":"temp","value":1},{"name":"temp"}]"#,
+                &mut batch_builder,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid record 2");
+        assert!(parser.parse_data(b"42", &mut batch_builder).await.is_err());
+        assert!(parser
+            .parse_data(b"not json", &mut batch_builder)
+            .await
+            .is_err());
+    }
+}
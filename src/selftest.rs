@@ -0,0 +1,185 @@
+use crate::datamodel::{
+    arrow_converter::ArrowConverter,
+    batch::{Batch, SingleSensorBatch},
+    batch_builder::BatchBuilder,
+    sensapp_datetime::SensAppDateTimeExt,
+    unit::Unit,
+    SensAppDateTime, Sensor, SensorData, TypedSamples,
+};
+use crate::exporters::ExportFormat;
+use crate::parsing::get_parser_from_name;
+use anyhow::{bail, Result};
+use serde_json::json;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The parsers, and the export format they read back.
+pub const ROUND_TRIP_PAIRS: [(&str, ExportFormat); 2] = [
+    ("senml_json", ExportFormat::SenML),
+    ("arrow", ExportFormat::Arrow),
+];
+
+/// The round-trip of a parser and export format.
+#[derive(Debug)]
+pub struct SelfTestResult {
+    pub parser: &'static str,
+    pub export_format: ExportFormat,
+    /// The number of samples that went through, or why it failed.
+    pub result: Result<usize>,
+}
+
+impl SelfTestResult {
+    pub fn summary(&self) -> String {
+        let pair = format!("{} -> {}", self.parser, self.export_format.name());
+        match &self.result {
+            Ok(samples) => format!("{}: ok, {} samples", pair, samples),
+            Err(error) => format!("{}: failed, {:#}", pair, error),
+        }
+    }
+}
+
+fn fixture_datetime(second: i64) -> SensAppDateTime {
+    SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + second)
+}
+
+/// The payloads of the known fixture, in the format of the parser.
+///
+/// The Arrow fixture is built from known samples, as it's a binary format.
+fn fixture_payloads(parser: &str) -> Result<Vec<Vec<u8>>> {
+    match parser {
+        "senml_json" => Ok(vec![json!([
+            {"bn": "selftest_", "bt": 1_700_000_000, "bu": "Cel", "n": "temperature", "v": 21.5},
+            {"n": "temperature", "v": 22.25, "t": 1},
+            {"n": "door", "vs": "open"},
+            {"n": "door", "vs": "closed", "t": 1},
+            {"n": "alarm", "vb": true},
+            {"n": "firmware", "vd": "AAEC_w"},
+        ])
+        .to_string()
+        .into_bytes()]),
+        "arrow" => {
+            let fixtures = [
+                TypedSamples::one_integer(42, fixture_datetime(0)),
+                TypedSamples::one_float(21.5, fixture_datetime(0)),
+                TypedSamples::one_numeric(
+                    rust_decimal::Decimal::new(12345, 2),
+                    fixture_datetime(0),
+                ),
+                TypedSamples::one_string("open".to_string(), fixture_datetime(0)),
+                TypedSamples::one_boolean(true, fixture_datetime(0)),
+                TypedSamples::one_location(geo::Point::new(10.75, 59.91), fixture_datetime(0)),
+                TypedSamples::one_blob(vec![0, 1, 2, 255], fixture_datetime(0)),
+                TypedSamples::one_json(json!({"state": "idle"}), fixture_datetime(0)),
+            ];
+            fixtures
+                .into_iter()
+                .enumerate()
+                .map(|(index, samples)| {
+                    let sensor = Sensor::new_without_uuid(
+                        format!("selftest_{}", index),
+                        ArrowConverter::sensor_type_of(&samples),
+                        Some(Unit::new("Cel".to_string(), None)),
+                        None,
+                    )?;
+                    ExportFormat::Arrow.export(&SensorData::new(sensor, samples), &[])
+                })
+                .collect()
+        }
+        parser => bail!("No self-test fixture for the parser {}", parser),
+    }
+}
+
+async fn parse(parser: &str, payloads: &[Vec<u8>]) -> Result<Batch> {
+    let parser = get_parser_from_name(parser)?;
+    let mut batch_builder = BatchBuilder::without_policies();
+    for payload in payloads {
+        parser.parse_data(payload, &mut batch_builder).await?;
+    }
+    Ok(batch_builder.build_batch().await)
+}
+
+/// Parses the fixture, exports the parsed samples, parses the exports,
+/// and checks that the sensors and samples are the same.
+async fn round_trip(parser: &str, export_format: ExportFormat) -> Result<usize> {
+    let mut batch = parse(parser, &fixture_payloads(parser)?).await?;
+    if batch.sensors.is_empty() {
+        bail!("The fixture gave no samples");
+    }
+
+    let mut parsed = Vec::with_capacity(batch.sensors.len());
+    let mut exports = Vec::with_capacity(batch.sensors.len());
+    for single_sensor_batch in batch.sensors.iter_mut() {
+        let sensor = &single_sensor_batch.sensor;
+        let sensor_data = SensorData::new(
+            Sensor::new(
+                sensor.uuid,
+                sensor.name.clone(),
+                sensor.sensor_type,
+                sensor.unit.clone(),
+                Some(sensor.labels.clone()),
+            ),
+            single_sensor_batch.take_samples().await,
+        );
+        exports.push(export_format.export(&sensor_data, &[])?);
+        parsed.push(sensor_data);
+    }
+
+    let reparsed = parse(parser, &exports).await?;
+    let mut reparsed_sensors: HashMap<Uuid, &SingleSensorBatch> = reparsed
+        .sensors
+        .iter()
+        .map(|single_sensor_batch| (single_sensor_batch.sensor.uuid, single_sensor_batch))
+        .collect();
+    let mut samples_count = 0;
+    for sensor_data in parsed {
+        let sensor = &sensor_data.sensor;
+        let Some(reparsed) = reparsed_sensors.remove(&sensor.uuid) else {
+            bail!("The sensor {} is missing after the round-trip", sensor.name);
+        };
+        if reparsed.sensor.sensor_type != sensor.sensor_type {
+            bail!("The sensor {} changed of type", sensor.name);
+        }
+        if *reparsed.samples.read().await != sensor_data.samples {
+            bail!("The samples of the sensor {} changed", sensor.name);
+        }
+        samples_count += sensor_data.samples.len();
+    }
+    if let Some(extra) = reparsed_sensors.values().next() {
+        bail!(
+            "Unexpected sensor {} after the round-trip",
+            extra.sensor.name
+        );
+    }
+    Ok(samples_count)
+}
+
+/// Round-trips the known fixture through each parser and its export format.
+pub async fn run_selftest() -> Vec<SelfTestResult> {
+    let mut results = Vec::with_capacity(ROUND_TRIP_PAIRS.len());
+    for (parser, export_format) in ROUND_TRIP_PAIRS {
+        results.push(SelfTestResult {
+            parser,
+            export_format,
+            result: round_trip(parser, export_format).await,
+        });
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+
+    #[tokio::test]
+    async fn test_selftest() {
+        _ = load_configuration();
+        let results = run_selftest().await;
+        assert_eq!(results.len(), ROUND_TRIP_PAIRS.len());
+        for result in &results {
+            assert!(result.result.is_ok(), "{}", result.summary());
+        }
+        assert_eq!(results[0].summary(), "senml_json -> senml: ok, 6 samples");
+        assert_eq!(results[1].summary(), "arrow -> arrow: ok, 8 samples");
+    }
+}
@@ -0,0 +1,419 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use gcp_bigquery_client::model::{
+    get_query_results_parameters::GetQueryResultsParameters, query_parameter::QueryParameter,
+    query_parameter_type::QueryParameterType, query_parameter_value::QueryParameterValue,
+    query_request::QueryRequest, query_response::ResultSet,
+};
+use std::str::FromStr;
+use uuid::Uuid;
+
+use super::BigQueryStorage;
+use crate::datamodel::{
+    sensapp_datetime::SensAppDateTimeExt, sensapp_vec::SensAppLabels, unit::Unit, Sample,
+    SensAppDateTime, SensAppVec, Sensor, SensorType, TypedSamples,
+};
+
+// All the values are sent as query parameters, only the dataset id
+// and fixed SQL fragments are part of the query strings.
+
+fn scalar_parameter(name: &str, parameter_type: &str, value: String) -> QueryParameter {
+    QueryParameter {
+        name: Some(name.to_string()),
+        parameter_type: Some(QueryParameterType {
+            r#type: parameter_type.to_string(),
+            struct_types: None,
+            array_type: None,
+        }),
+        parameter_value: Some(QueryParameterValue {
+            value: Some(value),
+            struct_values: None,
+            array_values: None,
+        }),
+    }
+}
+
+pub fn string_parameter(name: &str, value: &str) -> QueryParameter {
+    scalar_parameter(name, "STRING", value.to_string())
+}
+
+pub fn int64_parameter(name: &str, value: i64) -> QueryParameter {
+    scalar_parameter(name, "INT64", value.to_string())
+}
+
+/// The smallest number of microseconds not before the datetime,
+/// as BigQuery timestamps have a microsecond precision.
+fn to_unix_microseconds_ceil(datetime: &SensAppDateTime) -> i64 {
+    -(-datetime.to_unix_nanoseconds_i64()).div_euclid(1000)
+}
+
+/// How long BigQuery may wait for the job to complete before answering
+/// a request for its results.
+const QUERY_RESULTS_TIMEOUT_MS: i32 = 10_000;
+
+/// Runs the query and collects all its rows.
+///
+/// The first response may come before the job is complete, or hold only
+/// the first page of the rows, so the job results are polled until the job
+/// is complete and every page is fetched.
+async fn run_query(bqs: &BigQueryStorage, query_request: QueryRequest) -> Result<ResultSet> {
+    let client = bqs.client();
+    let client = client.read().await;
+    let mut response = client
+        .job()
+        .query(bqs.project_id(), query_request)
+        .await?
+        .query_response()
+        .clone();
+    let job_reference = response
+        .job_reference
+        .clone()
+        .ok_or_else(|| anyhow!("BigQuery query has no job reference"))?;
+    let job_id = job_reference
+        .job_id
+        .ok_or_else(|| anyhow!("BigQuery query has no job id"))?;
+
+    let mut rows = Vec::new();
+    loop {
+        let job_complete = response.job_complete.unwrap_or(false);
+        if job_complete {
+            rows.extend(response.rows.take().unwrap_or_default());
+            if response.page_token.is_none() {
+                break;
+            }
+        }
+        let parameters = GetQueryResultsParameters {
+            location: job_reference.location.clone(),
+            page_token: response.page_token.take().filter(|_| job_complete),
+            timeout_ms: Some(QUERY_RESULTS_TIMEOUT_MS),
+            ..Default::default()
+        };
+        let schema = response.schema.take();
+        response = client
+            .job()
+            .get_query_results(bqs.project_id(), &job_id, parameters)
+            .await?
+            .into();
+        response.schema = response.schema.or(schema);
+    }
+
+    response.rows = Some(rows);
+    Ok(ResultSet::new(response))
+}
+
+pub async fn get_sensor_by_uuid(
+    bqs: &BigQueryStorage,
+    uuid: Uuid,
+) -> Result<Option<(i64, Sensor)>> {
+    let mut query_request = QueryRequest::new(
+        r#"
+        SELECT s.sensor_id, s.name, s.type, u.name, u.description
+        FROM `{dataset_id}.sensors` s
+        LEFT JOIN `{dataset_id}.units` u ON s.unit = u.id
+        WHERE s.uuid = @sensor_uuid
+        LIMIT 1
+    "#
+        .replace("{dataset_id}", bqs.dataset_id()),
+    );
+    query_request.query_parameters = Some(vec![string_parameter(
+        "sensor_uuid",
+        &uuid.to_string(),
+    )]);
+
+    let mut result = run_query(bqs, query_request).await?;
+    if !result.next_row() {
+        return Ok(None);
+    }
+    let sensor_id = result
+        .get_i64(0)?
+        .ok_or_else(|| anyhow!("sensor_id is null"))?;
+    let name = result
+        .get_string(1)?
+        .ok_or_else(|| anyhow!("name is null"))?;
+    let sensor_type = SensorType::from_str(
+        &result
+            .get_string(2)?
+            .ok_or_else(|| anyhow!("type is null"))?,
+    )?;
+    let unit = result
+        .get_string(3)?
+        .map(|unit_name| -> Result<Unit> { Ok(Unit::new(unit_name, result.get_string(4)?)) })
+        .transpose()?;
+
+    let mut query_request = QueryRequest::new(
+        r#"
+        SELECT lnd.name, ldd.description
+        FROM `{dataset_id}.labels` l
+        JOIN `{dataset_id}.labels_name_dictionary` lnd ON l.name = lnd.id
+        LEFT JOIN `{dataset_id}.labels_description_dictionary` ldd ON l.description = ldd.id
+        WHERE l.sensor_id = @sensor_id
+    "#
+        .replace("{dataset_id}", bqs.dataset_id()),
+    );
+    query_request.query_parameters = Some(vec![int64_parameter("sensor_id", sensor_id)]);
+
+    let mut result = run_query(bqs, query_request).await?;
+    let mut labels = SensAppLabels::with_capacity(result.row_count());
+    while result.next_row() {
+        let name = result
+            .get_string(0)?
+            .ok_or_else(|| anyhow!("label name is null"))?;
+        labels.push((name, result.get_string(1)?.unwrap_or_default()));
+    }
+
+    Ok(Some((
+        sensor_id,
+        Sensor::new(uuid, name, sensor_type, unit, Some(labels)),
+    )))
+}
+
+/// Builds the query of the samples of a sensor, sorted by timestamp.
+///
+/// The start is inclusive and the end exclusive.
+pub fn samples_query_request(
+    dataset_id: &str,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    start: Option<SensAppDateTime>,
+    end: Option<SensAppDateTime>,
+    limit: Option<usize>,
+) -> QueryRequest {
+    let (columns, table, join) = match sensor_type {
+        SensorType::Integer => ("v.value", "integer_values", ""),
+        SensorType::Numeric => ("CAST(v.value AS STRING)", "numeric_values", ""),
+        SensorType::Float => ("v.value", "float_values", ""),
+        SensorType::String => (
+            "d.value",
+            "string_values",
+            "JOIN `{dataset_id}.strings_values_dictionary` d ON v.value = d.id",
+        ),
+        SensorType::Boolean => ("v.value", "boolean_values", ""),
+        SensorType::Location => ("v.latitude, v.longitude", "location_values", ""),
+        SensorType::Json => ("TO_JSON_STRING(v.value)", "json_values", ""),
+        SensorType::Blob => ("TO_BASE64(v.value)", "blob_values", ""),
+    };
+
+    let mut sql = format!(
+        "SELECT UNIX_MICROS(v.timestamp), {} FROM `{{dataset_id}}.{}` v {} WHERE v.sensor_id = @sensor_id",
+        columns, table, join
+    );
+    let mut parameters = vec![int64_parameter("sensor_id", sensor_id)];
+    if let Some(start) = start {
+        sql.push_str(" AND v.timestamp >= TIMESTAMP_MICROS(@start_us)");
+        parameters.push(int64_parameter(
+            "start_us",
+            to_unix_microseconds_ceil(&start),
+        ));
+    }
+    if let Some(end) = end {
+        sql.push_str(" AND v.timestamp < TIMESTAMP_MICROS(@end_us)");
+        parameters.push(int64_parameter("end_us", to_unix_microseconds_ceil(&end)));
+    }
+    sql.push_str(" ORDER BY v.timestamp");
+    if let Some(limit) = limit {
+        sql.push_str(" LIMIT @limit");
+        parameters.push(int64_parameter(
+            "limit",
+            limit.min(i64::MAX as usize) as i64,
+        ));
+    }
+
+    let mut query_request = QueryRequest::new(sql.replace("{dataset_id}", dataset_id));
+    query_request.query_parameters = Some(parameters);
+    query_request
+}
+
+fn read_samples<T>(
+    result: &mut ResultSet,
+    read_value: impl Fn(&ResultSet) -> Result<T>,
+) -> Result<SensAppVec<Sample<T>>> {
+    let mut samples = SensAppVec::with_capacity(result.row_count());
+    while result.next_row() {
+        let timestamp = result
+            .get_i64(0)?
+            .ok_or_else(|| anyhow!("timestamp is null"))?;
+        samples.push(Sample {
+            datetime: SensAppDateTime::from_unix_microseconds_i64(timestamp),
+            value: read_value(result)?,
+        });
+    }
+    Ok(samples)
+}
+
+fn not_null<T>(value: Option<T>) -> Result<T> {
+    value.ok_or_else(|| anyhow!("value is null"))
+}
+
+pub async fn query_samples(
+    bqs: &BigQueryStorage,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    start: Option<SensAppDateTime>,
+    end: Option<SensAppDateTime>,
+    limit: Option<usize>,
+) -> Result<TypedSamples> {
+    let query_request =
+        samples_query_request(bqs.dataset_id(), sensor_id, sensor_type, start, end, limit);
+    let mut result = run_query(bqs, query_request).await?;
+
+    Ok(match sensor_type {
+        SensorType::Integer => {
+            TypedSamples::Integer(read_samples(&mut result, |row| not_null(row.get_i64(1)?))?)
+        }
+        SensorType::Numeric => TypedSamples::Numeric(read_samples(&mut result, |row| {
+            Ok(rust_decimal::Decimal::from_str(&not_null(
+                row.get_string(1)?,
+            )?)?)
+        })?),
+        SensorType::Float => {
+            TypedSamples::Float(read_samples(&mut result, |row| not_null(row.get_f64(1)?))?)
+        }
+        SensorType::String => TypedSamples::String(read_samples(&mut result, |row| {
+            not_null(row.get_string(1)?)
+        })?),
+        SensorType::Boolean => {
+            TypedSamples::Boolean(read_samples(&mut result, |row| not_null(row.get_bool(1)?))?)
+        }
+        SensorType::Location => TypedSamples::Location(read_samples(&mut result, |row| {
+            let latitude = not_null(row.get_f64(1)?)?;
+            let longitude = not_null(row.get_f64(2)?)?;
+            Ok(geo::Point::new(longitude, latitude))
+        })?),
+        SensorType::Json => TypedSamples::Json(read_samples(&mut result, |row| {
+            Ok(serde_json::from_str(&not_null(row.get_string(1)?)?)?)
+        })?),
+        SensorType::Blob => TypedSamples::Blob(read_samples(&mut result, |row| {
+            Ok(STANDARD.decode(not_null(row.get_string(1)?)?)?)
+        })?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameter_value<'a>(query_request: &'a QueryRequest, name: &str) -> Option<&'a str> {
+        query_request
+            .query_parameters
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|parameter| parameter.name.as_deref() == Some(name))
+            .and_then(|parameter| parameter.parameter_value.as_ref())
+            .and_then(|value| value.value.as_deref())
+    }
+
+    #[test]
+    fn test_samples_query_request() {
+        let query_request = samples_query_request(
+            "sensapp",
+            42,
+            SensorType::String,
+            Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_000)),
+            Some(SensAppDateTime::from_unix_nanoseconds_i64(
+                1_700_000_060_000_000_500,
+            )),
+            Some(100),
+        );
+        assert_eq!(
+            query_request.query,
+            "SELECT UNIX_MICROS(v.timestamp), d.value FROM `sensapp.string_values` v \
+             JOIN `sensapp.strings_values_dictionary` d ON v.value = d.id \
+             WHERE v.sensor_id = @sensor_id \
+             AND v.timestamp >= TIMESTAMP_MICROS(@start_us) \
+             AND v.timestamp < TIMESTAMP_MICROS(@end_us) \
+             ORDER BY v.timestamp LIMIT @limit"
+        );
+        assert_eq!(parameter_value(&query_request, "sensor_id"), Some("42"));
+        assert_eq!(
+            parameter_value(&query_request, "start_us"),
+            Some("1700000000000000")
+        );
+        // The exclusive end is rounded up to the next microsecond
+        assert_eq!(
+            parameter_value(&query_request, "end_us"),
+            Some("1700000060000001")
+        );
+        assert_eq!(parameter_value(&query_request, "limit"), Some("100"));
+
+        // Without bounds
+        let query_request =
+            samples_query_request("sensapp", 42, SensorType::Integer, None, None, None);
+        assert!(!query_request.query.contains("@start_us"));
+        assert!(!query_request.query.contains("LIMIT"));
+        assert_eq!(query_request.query_parameters.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_string_parameter_with_quotes() {
+        let value = "x' OR '1'='1`; DROP TABLE sensors; --";
+        let mut query_request =
+            QueryRequest::new("SELECT sensor_id FROM `sensapp.sensors` WHERE name = @name");
+        query_request.query_parameters = Some(vec![string_parameter("name", value)]);
+
+        // The value is sent as is, next to the untouched query
+        let json = serde_json::to_value(&query_request).unwrap();
+        assert_eq!(
+            json["query"],
+            "SELECT sensor_id FROM `sensapp.sensors` WHERE name = @name"
+        );
+        assert_eq!(json["queryParameters"][0]["parameterValue"]["value"], value);
+        assert_eq!(
+            json["queryParameters"][0]["parameterType"]["type"],
+            "STRING"
+        );
+    }
+
+    /// Needs a BigQuery dataset, set with `SENSAPP_TEST_BIGQUERY_CONNECTION_STRING`.
+    #[tokio::test]
+    async fn test_query_integer_samples() {
+        let Ok(connection_string) = std::env::var("SENSAPP_TEST_BIGQUERY_CONNECTION_STRING") else {
+            return;
+        };
+        use crate::datamodel::batch::{Batch, SingleSensorBatch};
+        use crate::storage::storage::StorageInstance;
+        use smallvec::smallvec;
+        use std::sync::Arc;
+
+        let storage = BigQueryStorage::connect(&connection_string).await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_bigquery_query_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let samples = |range: std::ops::Range<i64>| {
+            TypedSamples::Integer(
+                range
+                    .map(|i| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                        value: i,
+                    })
+                    .collect(),
+            )
+        };
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            samples(0..10)
+        )]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let sensor_data = storage
+            .query_sensor_data(
+                sensor.uuid,
+                Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_002)),
+                Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_008)),
+                Some(4),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.sensor.name, sensor.name);
+        assert_eq!(sensor_data.samples, samples(2..6));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::datamodel::{SensAppDateTime, SensorData};
+use crate::storage::query::sensor_query_limit;
 use crate::storage::storage::StorageInstance;
 use anyhow::{bail, Result};
 use async_trait::async_trait;
@@ -5,6 +7,7 @@ use bigquery_publishers::{
     publish_blob_values, publish_boolean_values, publish_float_values, publish_integer_values,
     publish_json_values, publish_location_values, publish_numeric_values, publish_string_values,
 };
+use bigquery_queries::{get_sensor_by_uuid, query_samples};
 use bigquery_sensors_utilities::get_sensor_ids_or_create_sensors;
 use futures::future::try_join_all;
 use gcp_bigquery_client::{
@@ -17,10 +20,12 @@ use regex::Regex;
 use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
 use tokio::{sync::RwLock, time::timeout};
 use url::Url;
+use uuid::Uuid;
 
 mod bigquery_labels_utilities;
 mod bigquery_prost_structs;
 mod bigquery_publishers;
+mod bigquery_queries;
 mod bigquery_sensors_utilities;
 mod bigquery_string_values_utilities;
 mod bigquery_table_descriptors;
@@ -77,6 +82,19 @@ fn parse_connection_string(connection_string: &str) -> Result<(String, String, S
         bail!("dataset_id is required in connection string");
     }
 
+    // The dataset and project ids are part of the SQL queries,
+    // as table names can't be query parameters.
+    static DATASET_ID_REX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^[A-Za-z0-9_]{1,1024}$").expect("Failed to compile regex"));
+    static PROJECT_ID_REX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^[A-Za-z0-9_\-.:]{1,128}$").expect("Failed to compile regex"));
+    if !DATASET_ID_REX.is_match(&dataset_id) {
+        bail!("Invalid dataset_id in connection string: {:?}", dataset_id);
+    }
+    if !PROJECT_ID_REX.is_match(&project_id) {
+        bail!("Invalid project_id in connection string: {:?}", project_id);
+    }
+
     Ok((gcp_sa_key, project_id, dataset_id))
 }
 
@@ -84,11 +102,11 @@ impl BigQueryStorage {
     pub async fn connect(connection_string: &str) -> Result<Self> {
         let (gcp_sa_key, project_id, dataset_id) = parse_connection_string(connection_string)?;
 
-        println!(
+        tracing::info!(
             "Connecting to BigQuery with project_id: {}, dataset_id: {}",
-            project_id, dataset_id
+            project_id,
+            dataset_id
         );
-        println!("File: {}", gcp_sa_key);
         let client = Arc::new(RwLock::new(
             gcp_bigquery_client::Client::from_service_account_key_file(&gcp_sa_key).await?,
         ));
@@ -236,4 +254,45 @@ impl StorageInstance for BigQueryStorage {
     async fn list_sensors(&self) -> Result<Vec<String>> {
         unimplemented!();
     }
+
+    async fn query_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let (sensor_id, sensor) = match get_sensor_by_uuid(self, sensor_uuid).await? {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let limit = sensor_query_limit(limit, sensor.sensor_type)?;
+        let samples = query_samples(self, sensor_id, sensor.sensor_type, start, end, limit).await?;
+        Ok(Some(SensorData::new(sensor, samples)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_connection_string() {
+        let (gcp_sa_key, project_id, dataset_id) = parse_connection_string(
+            "bigquery://key.json?project_id=example.com:sensapp-42&dataset_id=sensapp_dev",
+        )
+        .unwrap();
+        assert_eq!(gcp_sa_key, "key.json");
+        assert_eq!(project_id, "example.com:sensapp-42");
+        assert_eq!(dataset_id, "sensapp_dev");
+
+        // The ids end up in the queries, so quotes are refused
+        for connection_string in [
+            "bigquery://key.json?project_id=sensapp&dataset_id=sensapp`.sensors;%20DROP%20TABLE%20x;--",
+            "bigquery://key.json?project_id=sens'app&dataset_id=sensapp",
+            "bigquery://key.json?project_id=sensapp",
+        ] {
+            assert!(parse_connection_string(connection_string).is_err());
+        }
+    }
 }
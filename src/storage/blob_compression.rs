@@ -0,0 +1,114 @@
+use anyhow::{bail, Result};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Marker byte of the blobs compressed with zstd, before the zstd frame.
+const ZSTD_MARKER: u8 = 1;
+/// Marker byte of the blobs compressed with lz4, before the lz4 frame.
+const LZ4_MARKER: u8 = 2;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+
+/// Compression of the stored blob samples.
+///
+/// The compressed blobs start with a marker byte followed by the frame
+/// of the codec, so the uncompressed blobs stored before, or with `none`,
+/// are read back as they are. An uncompressed blob could only be mistaken
+/// for a compressed one if it started with a marker and a frame magic number.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlobCompression {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl FromStr for BlobCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(BlobCompression::None),
+            "zstd" => Ok(BlobCompression::Zstd),
+            "lz4" => Ok(BlobCompression::Lz4),
+            _ => bail!(
+                "Invalid blob compression: {}, expected none, zstd or lz4",
+                s
+            ),
+        }
+    }
+}
+
+impl BlobCompression {
+    /// Reads the configured compression, none when the configuration isn't loaded.
+    pub fn from_config() -> Result<Self> {
+        match crate::config::get() {
+            Ok(config) => config.blob_compression.parse(),
+            Err(_) => Ok(BlobCompression::None),
+        }
+    }
+
+    /// Compresses a blob before storing it.
+    pub fn compress<'a>(&self, blob: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        Ok(match self {
+            BlobCompression::None => Cow::Borrowed(blob),
+            BlobCompression::Zstd => {
+                let mut compressed = vec![ZSTD_MARKER];
+                zstd::stream::copy_encode(blob, &mut compressed, 0)?;
+                Cow::Owned(compressed)
+            }
+            BlobCompression::Lz4 => {
+                let mut encoder = lz4::EncoderBuilder::new().build(vec![LZ4_MARKER])?;
+                encoder.write_all(blob)?;
+                let (compressed, result) = encoder.finish();
+                result?;
+                Cow::Owned(compressed)
+            }
+        })
+    }
+}
+
+/// Decompresses a stored blob, whatever the configured compression.
+pub fn decompress_blob(blob: Vec<u8>) -> Result<Vec<u8>> {
+    match blob.split_first() {
+        Some((&ZSTD_MARKER, frame)) if frame.starts_with(&ZSTD_MAGIC) => {
+            Ok(zstd::decode_all(frame)?)
+        }
+        Some((&LZ4_MARKER, frame)) if frame.starts_with(&LZ4_MAGIC) => {
+            let mut decompressed = Vec::new();
+            lz4::Decoder::new(frame)?.read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+        _ => Ok(blob),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_compression() {
+        let blob = b"sensapp ".repeat(1000);
+        for compression in [
+            BlobCompression::None,
+            BlobCompression::Zstd,
+            BlobCompression::Lz4,
+        ] {
+            let compressed = compression.compress(&blob).unwrap().into_owned();
+            if compression != BlobCompression::None {
+                assert!(compressed.len() < blob.len() / 10);
+            }
+            assert_eq!(decompress_blob(compressed).unwrap(), blob);
+        }
+
+        // The blobs stored without compression are read as they are
+        let raw = vec![ZSTD_MARKER, 0, 1, 2];
+        assert_eq!(decompress_blob(raw.clone()).unwrap(), raw);
+        assert!(decompress_blob(Vec::new()).unwrap().is_empty());
+
+        assert!("brotli".parse::<BlobCompression>().is_err());
+    }
+}
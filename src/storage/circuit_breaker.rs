@@ -0,0 +1,626 @@
+use super::query::{AggregateBucket, InvalidQueryError, SensorSelector, SortOrder, TimeRange};
+use super::raw_sql::{RawSqlResult, RawSqlTimeoutError};
+use super::storage::{StorageInstance, UnsupportedError};
+use super::strict_sensors::UnknownSensorError;
+use super::type_conflict::TypeConflictError;
+use super::verify::VerifyReport;
+use super::write_buffer::WriteBufferStatus;
+use crate::datamodel::{
+    batch::Batch, sensor::LabelTooLongError, sensor_metadata::SensorMetadata, Annotation,
+    SensAppDateTime, Sensor, SensorData,
+};
+use anyhow::Result;
+use async_broadcast::Sender;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// The error returned while the circuit breaker is open.
+#[derive(Debug)]
+pub struct CircuitOpenError;
+
+impl std::fmt::Display for CircuitOpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Storage backend unavailable, the circuit breaker is open"
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpenError {}
+
+/// Whether the error is caused by the request of a client, such as
+/// rejected samples, an invalid or too slow query, or an operation
+/// the backend doesn't implement, rather than by a failing backend.
+pub fn is_client_error(error: &anyhow::Error) -> bool {
+    error.is::<UnknownSensorError>()
+        || error.is::<LabelTooLongError>()
+        || error.is::<TypeConflictError>()
+        || error.is::<InvalidQueryError>()
+        || error.is::<UnsupportedError>()
+        || error.is::<RawSqlTimeoutError>()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitBreakerState {
+    /// The calls go through.
+    Closed,
+    /// The calls fail fast until the end of the cooldown.
+    Open,
+    /// The cooldown is over, the next call probes the backend.
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerOptions {
+    /// Number of consecutive failures opening the circuit.
+    pub failure_threshold: usize,
+    /// The failures must happen within this window.
+    pub window: Duration,
+    /// How long the circuit stays open before probing the backend.
+    pub cooldown: Duration,
+}
+
+impl CircuitBreakerOptions {
+    pub fn from_config() -> Result<Self> {
+        let config = crate::config::get()?;
+        Ok(Self {
+            failure_threshold: config.circuit_breaker_failure_threshold,
+            window: Duration::from_secs(config.circuit_breaker_window_seconds),
+            cooldown: Duration::from_secs(config.circuit_breaker_cooldown_seconds),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    Closed {
+        failures: usize,
+        first_failure: Option<Instant>,
+    },
+    Open {
+        until: Instant,
+    },
+}
+
+/// Ends the probe, even when the probing call is cancelled.
+struct ProbingGuard<'a>(&'a AtomicBool);
+
+impl Drop for ProbingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Wraps a storage backend to fail fast when it keeps failing.
+///
+/// After `failure_threshold` consecutive failures within the window,
+/// the publish and query calls fail with a [`CircuitOpenError`] for the
+/// cooldown period. Then the next call probes the backend with
+/// [`StorageInstance::health_check`] and closes the circuit if it succeeds,
+/// while the concurrent calls keep failing fast during the probe.
+#[derive(Debug)]
+pub struct CircuitBreakerStorage {
+    inner: Arc<dyn StorageInstance>,
+    options: CircuitBreakerOptions,
+    state: Mutex<BreakerState>,
+    probing: AtomicBool,
+}
+
+impl CircuitBreakerStorage {
+    pub fn new(inner: Arc<dyn StorageInstance>, options: CircuitBreakerOptions) -> Self {
+        Self {
+            inner,
+            options,
+            state: Mutex::new(BreakerState::Closed {
+                failures: 0,
+                first_failure: None,
+            }),
+            probing: AtomicBool::new(false),
+        }
+    }
+
+    fn current_state(&self) -> CircuitBreakerState {
+        match *self.state.lock().unwrap() {
+            BreakerState::Closed { .. } => CircuitBreakerState::Closed,
+            BreakerState::Open { until } if Instant::now() < until => CircuitBreakerState::Open,
+            BreakerState::Open { .. } => CircuitBreakerState::HalfOpen,
+        }
+    }
+
+    fn close(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed {
+            failures: 0,
+            first_failure: None,
+        };
+    }
+
+    fn open(&self) {
+        *self.state.lock().unwrap() = BreakerState::Open {
+            until: Instant::now() + self.options.cooldown,
+        };
+    }
+
+    fn record_failure(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if let BreakerState::Closed {
+            failures,
+            first_failure,
+        } = &mut *state
+        {
+            match first_failure {
+                Some(first) if now.duration_since(*first) <= self.options.window => {
+                    *failures += 1;
+                }
+                _ => {
+                    *failures = 1;
+                    *first_failure = Some(now);
+                }
+            }
+            if *failures >= self.options.failure_threshold {
+                *state = BreakerState::Open {
+                    until: now + self.options.cooldown,
+                };
+            }
+        }
+    }
+
+    /// Fails fast when the circuit is open,
+    /// and probes the backend when the cooldown is over.
+    async fn before_call(&self) -> Result<()> {
+        match self.current_state() {
+            CircuitBreakerState::Closed => Ok(()),
+            CircuitBreakerState::Open => Err(CircuitOpenError.into()),
+            CircuitBreakerState::HalfOpen => {
+                // A single call probes the backend
+                if self
+                    .probing
+                    .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    return Err(CircuitOpenError.into());
+                }
+                let _probing = ProbingGuard(&self.probing);
+                match self.inner.health_check().await {
+                    Ok(()) => {
+                        self.close();
+                        Ok(())
+                    }
+                    Err(_) => {
+                        self.open();
+                        Err(CircuitOpenError.into())
+                    }
+                }
+            }
+        }
+    }
+
+    /// The client errors don't count as failures, so a few bad
+    /// payloads can't open the circuit for everyone.
+    fn after_call<T>(&self, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.close(),
+            Err(error) if is_client_error(error) => {}
+            Err(_) => self.record_failure(),
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl StorageInstance for CircuitBreakerStorage {
+    async fn create_or_migrate(&self) -> Result<()> {
+        self.inner.create_or_migrate().await
+    }
+
+    async fn check_schema(&self) -> Result<()> {
+        self.inner.check_schema().await
+    }
+
+    async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
+        self.before_call().await?;
+        let result = self.inner.publish(batch, sync_sender).await;
+        self.after_call(result)
+    }
+
+    async fn sync(&self, sync_sender: Sender<()>) -> Result<()> {
+        self.inner.sync(sync_sender).await
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        self.inner.vacuum().await
+    }
+
+    async fn list_sensors(&self) -> Result<Vec<String>> {
+        self.before_call().await?;
+        let result = self.inner.list_sensors().await;
+        self.after_call(result)
+    }
+
+    async fn get_sensor(&self, sensor_uuid: Uuid) -> Result<Option<Sensor>> {
+        self.before_call().await?;
+        let result = self.inner.get_sensor(sensor_uuid).await;
+        self.after_call(result)
+    }
+
+    async fn update_sensor_metadata(
+        &self,
+        sensor_uuid: Uuid,
+        metadata: &SensorMetadata,
+    ) -> Result<bool> {
+        self.before_call().await?;
+        let result = self
+            .inner
+            .update_sensor_metadata(sensor_uuid, metadata)
+            .await;
+        self.after_call(result)
+    }
+
+    async fn query_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.before_call().await?;
+        let result = self
+            .inner
+            .query_sensor_data(sensor_uuid, start, end, limit)
+            .await;
+        self.after_call(result)
+    }
+
+    async fn query_sensor_data_window(
+        &self,
+        sensor_uuid: Uuid,
+        window: hifitime::Duration,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.before_call().await?;
+        let result = self
+            .inner
+            .query_sensor_data_window(sensor_uuid, window, limit)
+            .await;
+        self.after_call(result)
+    }
+
+    async fn query_sensor_data_by_value(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        order: SortOrder,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.before_call().await?;
+        let result = self
+            .inner
+            .query_sensor_data_by_value(sensor_uuid, start, end, order, limit)
+            .await;
+        self.after_call(result)
+    }
+
+    async fn query_sensor_data_by_id(
+        &self,
+        sensor_id: i64,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.before_call().await?;
+        let result = self
+            .inner
+            .query_sensor_data_by_id(sensor_id, start, end, limit)
+            .await;
+        self.after_call(result)
+    }
+
+    async fn query(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorData>> {
+        self.before_call().await?;
+        let result = self.inner.query(selector, time_range, limit).await;
+        self.after_call(result)
+    }
+
+    async fn unknown_sensors(&self, sensor_uuids: &[Uuid]) -> Result<Vec<Uuid>> {
+        self.before_call().await?;
+        let result = self.inner.unknown_sensors(sensor_uuids).await;
+        self.after_call(result)
+    }
+
+    async fn apply_retention(&self, older_than: SensAppDateTime) -> Result<()> {
+        self.before_call().await?;
+        let result = self.inner.apply_retention(older_than).await;
+        self.after_call(result)
+    }
+
+    async fn delete_metric(&self, name: &str) -> Result<u64> {
+        self.before_call().await?;
+        let result = self.inner.delete_metric(name).await;
+        self.after_call(result)
+    }
+
+    async fn latest_samples_all(&self, metric_filter: Option<&str>) -> Result<Vec<SensorData>> {
+        self.before_call().await?;
+        let result = self.inner.latest_samples_all(metric_filter).await;
+        self.after_call(result)
+    }
+
+    async fn count_series_by_labels(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+    ) -> Result<Vec<(Sensor, u64)>> {
+        self.before_call().await?;
+        let result = self
+            .inner
+            .count_series_by_labels(selector, time_range)
+            .await;
+        self.after_call(result)
+    }
+
+    async fn aggregate_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+        step: hifitime::Duration,
+    ) -> Result<Option<Vec<AggregateBucket>>> {
+        self.before_call().await?;
+        let result = self
+            .inner
+            .aggregate_sensor_data(sensor_uuid, time_range, step)
+            .await;
+        self.after_call(result)
+    }
+
+    async fn verify_sensor(&self, sensor_uuid: Uuid) -> Result<Option<VerifyReport>> {
+        self.before_call().await?;
+        let result = self.inner.verify_sensor(sensor_uuid).await;
+        self.after_call(result)
+    }
+
+    async fn add_annotation(&self, sensor_uuid: Uuid, annotation: &Annotation) -> Result<bool> {
+        self.before_call().await?;
+        let result = self.inner.add_annotation(sensor_uuid, annotation).await;
+        self.after_call(result)
+    }
+
+    async fn query_annotations(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+    ) -> Result<Option<Vec<Annotation>>> {
+        self.before_call().await?;
+        let result = self.inner.query_annotations(sensor_uuid, time_range).await;
+        self.after_call(result)
+    }
+
+    async fn raw_sql_query(
+        &self,
+        sql: &str,
+        max_rows: usize,
+        timeout: Duration,
+    ) -> Result<RawSqlResult> {
+        self.before_call().await?;
+        let result = self.inner.raw_sql_query(sql, max_rows, timeout).await;
+        self.after_call(result)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    fn circuit_breaker_state(&self) -> Option<CircuitBreakerState> {
+        Some(self.current_state())
+    }
+
+    fn write_buffer_status(&self) -> Option<WriteBufferStatus> {
+        self.inner.write_buffer_status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Debug, Default)]
+    struct MockStorage {
+        failing: AtomicBool,
+        rejecting: AtomicBool,
+        health_checks: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StorageInstance for MockStorage {
+        async fn create_or_migrate(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn publish(&self, _batch: Arc<Batch>, _sync_sender: Sender<()>) -> Result<()> {
+            if self.rejecting.load(Ordering::SeqCst) {
+                return Err(UnknownSensorError {
+                    names: vec!["unknown".to_string()],
+                }
+                .into());
+            }
+            Ok(())
+        }
+        async fn sync(&self, _sync_sender: Sender<()>) -> Result<()> {
+            Ok(())
+        }
+        async fn vacuum(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn list_sensors(&self) -> Result<Vec<String>> {
+            if self.failing.load(Ordering::SeqCst) {
+                bail!("Connection refused");
+            }
+            Ok(vec!["sensor".to_string()])
+        }
+        async fn health_check(&self) -> Result<()> {
+            self.health_checks.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            if self.failing.load(Ordering::SeqCst) {
+                bail!("Connection refused");
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        let mock = Arc::new(MockStorage::default());
+        let storage = CircuitBreakerStorage::new(
+            mock.clone(),
+            CircuitBreakerOptions {
+                failure_threshold: 3,
+                window: Duration::from_secs(60),
+                cooldown: Duration::from_millis(50),
+            },
+        );
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::Closed)
+        );
+        storage.list_sensors().await.unwrap();
+
+        // The backend goes down
+        mock.failing.store(true, Ordering::SeqCst);
+        for _ in 0..3 {
+            let error = storage.list_sensors().await.unwrap_err();
+            assert!(error.downcast_ref::<CircuitOpenError>().is_none());
+        }
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::Open)
+        );
+        let error = storage.list_sensors().await.unwrap_err();
+        assert!(error.downcast_ref::<CircuitOpenError>().is_some());
+
+        // The probe fails, so the circuit opens again
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::HalfOpen)
+        );
+        let error = storage.list_sensors().await.unwrap_err();
+        assert!(error.downcast_ref::<CircuitOpenError>().is_some());
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::Open)
+        );
+
+        // The backend recovers
+        mock.failing.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        storage.list_sensors().await.unwrap();
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::Closed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_errors() {
+        let mock = Arc::new(MockStorage::default());
+        let storage = CircuitBreakerStorage::new(
+            mock.clone(),
+            CircuitBreakerOptions {
+                failure_threshold: 3,
+                window: Duration::from_secs(60),
+                cooldown: Duration::from_millis(50),
+            },
+        );
+
+        // The rejected samples don't open the circuit
+        mock.rejecting.store(true, Ordering::SeqCst);
+        for _ in 0..5 {
+            let error = storage
+                .publish(Arc::new(Batch::default()), async_broadcast::broadcast(1).0)
+                .await
+                .unwrap_err();
+            assert!(error.is::<UnknownSensorError>());
+        }
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::Closed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_operations() {
+        let mock = Arc::new(MockStorage::default());
+        let storage = CircuitBreakerStorage::new(
+            mock.clone(),
+            CircuitBreakerOptions {
+                failure_threshold: 3,
+                window: Duration::from_secs(60),
+                cooldown: Duration::from_millis(50),
+            },
+        );
+
+        // The mock doesn't implement the retention nor the pages
+        for _ in 0..10 {
+            let error = storage
+                .apply_retention(SensAppDateTime::from_unix_seconds(0.0))
+                .await
+                .unwrap_err();
+            assert!(error.is::<UnsupportedError>());
+            let error = storage
+                .query_sensor_data_page(Uuid::nil(), None, 0)
+                .await
+                .unwrap_err();
+            assert!(error.is::<InvalidQueryError>());
+        }
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::Closed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_probe() {
+        let mock = Arc::new(MockStorage::default());
+        let storage = Arc::new(CircuitBreakerStorage::new(
+            mock.clone(),
+            CircuitBreakerOptions {
+                failure_threshold: 1,
+                window: Duration::from_secs(60),
+                cooldown: Duration::from_millis(20),
+            },
+        ));
+        mock.failing.store(true, Ordering::SeqCst);
+        storage.list_sensors().await.unwrap_err();
+        mock.failing.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        // Only one of the concurrent calls probes the backend
+        let calls = (0..5).map(|_| {
+            let storage = storage.clone();
+            tokio::spawn(async move { storage.list_sensors().await })
+        });
+        let results = futures::future::join_all(calls).await;
+        let succeeded = results
+            .into_iter()
+            .filter(|result| result.as_ref().unwrap().is_ok())
+            .count();
+        assert_eq!(mock.health_checks.load(Ordering::SeqCst), 1);
+        assert!(succeeded >= 1);
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::Closed)
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::datamodel::{sensapp_datetime::SensAppDateTimeExt, SensAppDateTime};
+use crate::storage::query::InvalidQueryError;
+use crate::storage::raw_sql::{RawSqlResult, RawSqlTimeoutError};
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use duckdb::types::ValueRef;
+use duckdb::{Connection, Row};
+use rust_decimal::prelude::ToPrimitive;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+/// Runs a validated raw SQL query in a transaction that is rolled back,
+/// converting the DuckDB values to JSON.
+///
+/// The DuckDB bindings can't interrupt a statement, so the deadline
+/// is checked between the rows. The blobs are base64 encoded.
+pub fn query_raw_sql(
+    connection: &mut Connection,
+    sql: &str,
+    max_rows: usize,
+    timeout: Duration,
+) -> Result<RawSqlResult> {
+    let deadline = Instant::now() + timeout;
+    // Rolled back when dropped, nothing is committed.
+    let transaction = connection.transaction()?;
+    let mut statement = transaction.prepare(sql).map_err(invalid_query)?;
+    let mut rows = statement.query([]).map_err(invalid_query)?;
+    let columns = rows
+        .as_ref()
+        .map(|statement| statement.column_names())
+        .unwrap_or_default();
+    let mut result = RawSqlResult {
+        columns,
+        ..Default::default()
+    };
+    while let Some(row) = rows.next().map_err(invalid_query)? {
+        if Instant::now() >= deadline {
+            return Err(RawSqlTimeoutError(timeout).into());
+        }
+        if result.rows.len() >= max_rows {
+            result.truncated = true;
+            break;
+        }
+        result
+            .rows
+            .push(raw_sql_row_to_json(row, result.columns.len())?);
+    }
+    Ok(result)
+}
+
+fn invalid_query(error: duckdb::Error) -> anyhow::Error {
+    match error {
+        duckdb::Error::DuckDBFailure(_, Some(message)) => InvalidQueryError(message).into(),
+        error => error.into(),
+    }
+}
+
+fn raw_sql_row_to_json(row: &Row, columns: usize) -> Result<Vec<Value>> {
+    (0..columns)
+        .map(|index| {
+            Ok(match row.get_ref(index)? {
+                ValueRef::Null => Value::Null,
+                ValueRef::Boolean(value) => Value::from(value),
+                ValueRef::TinyInt(value) => Value::from(value),
+                ValueRef::SmallInt(value) => Value::from(value),
+                ValueRef::Int(value) => Value::from(value),
+                ValueRef::BigInt(value) => Value::from(value),
+                ValueRef::HugeInt(value) => Value::from(value.to_string()),
+                ValueRef::UTinyInt(value) => Value::from(value),
+                ValueRef::USmallInt(value) => Value::from(value),
+                ValueRef::UInt(value) => Value::from(value),
+                ValueRef::UBigInt(value) => Value::from(value),
+                ValueRef::Float(value) => Value::from(value),
+                ValueRef::Double(value) => Value::from(value),
+                ValueRef::Decimal(value) => Value::from(value.to_f64()),
+                ValueRef::Timestamp(unit, value) => Value::from(
+                    SensAppDateTime::from_unix_microseconds_i64(unit.to_micros(value)).to_rfc3339(),
+                ),
+                ValueRef::Text(value) => Value::from(String::from_utf8_lossy(value)),
+                ValueRef::Blob(value) => Value::from(STANDARD.encode(value)),
+                value => {
+                    return Err(InvalidQueryError(format!(
+                        "Unsupported {:?} column, cast it in the query",
+                        value.data_type()
+                    ))
+                    .into())
+                }
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,3 @@
+-- Precision and scale of the numeric values, from SENSAPP_NUMERIC_PRECISION
+-- and SENSAPP_NUMERIC_SCALE. Only applied when they differ from the column.
+ALTER TABLE numeric_values ALTER COLUMN value TYPE DECIMAL({numeric_precision},{numeric_scale});
@@ -1,10 +1,12 @@
 use crate::datamodel::batch::{Batch, SingleSensorBatch};
+use crate::datamodel::numeric_precision::NumericPrecision;
 use crate::datamodel::TypedSamples;
 use anyhow::{bail, Context, Result};
 use async_broadcast::Sender;
 use async_trait::async_trait;
 use duckdb::Connection;
 use duckdb_publishers::*;
+use duckdb_queries::query_raw_sql;
 use duckdb_utilities::get_sensor_id_or_create_sensor;
 use std::sync::Arc;
 use std::time::Duration;
@@ -12,9 +14,11 @@ use tokio::sync::Mutex;
 use tokio::task::spawn_blocking;
 use tokio::time::timeout;
 
+use super::raw_sql::{validate_read_only_select, RawSqlResult};
 use super::storage::StorageInstance;
 
 mod duckdb_publishers;
+mod duckdb_queries;
 mod duckdb_utilities;
 
 #[derive(Debug)]
@@ -23,6 +27,20 @@ pub struct DuckDBStorage {
 }
 
 const INIT_SQL: &str = include_str!("./migrations/20240223133248_init.sql");
+const NUMERIC_PRECISION_SQL: &str =
+    include_str!("./migrations/20241001000000_numeric_precision.sql");
+
+/// The tables of the samples, and their value columns.
+const VALUE_TABLES: [(&str, &str); 8] = [
+    ("integer_values", "value"),
+    ("numeric_values", "value"),
+    ("float_values", "value"),
+    ("string_values", "value"),
+    ("boolean_values", "value"),
+    ("location_values", "latitude, longitude"),
+    ("json_values", "value"),
+    ("blob_values", "value"),
+];
 
 impl DuckDBStorage {
     pub async fn connect(connection_string: &str) -> Result<Self> {
@@ -37,15 +55,74 @@ impl DuckDBStorage {
         let connection = Arc::new(Mutex::new(connection));
         Ok(Self { connection })
     }
+
+    /// Removes the duplicate samples, with the same sensor, timestamp,
+    /// and value, and keeps one of each. Returns the number of deleted rows.
+    ///
+    /// It rewrites the value tables in a transaction,
+    /// so it's separate from the cheap vacuum.
+    pub async fn deduplicate(&self) -> Result<u64> {
+        let connection = Arc::clone(&self.connection);
+        spawn_blocking(move || -> Result<u64> {
+            let mut connection = connection.blocking_lock();
+            let transaction = connection.transaction()?;
+            let mut deleted = 0;
+            for (table, value_columns) in VALUE_TABLES {
+                deleted += transaction.execute(
+                    &format!(
+                        r#"
+                        DELETE FROM {table} WHERE rowid NOT IN (
+                            SELECT MIN(rowid) FROM {table}
+                            GROUP BY sensor_id, timestamp_ms, {value_columns}
+                        )
+                        "#
+                    ),
+                    [],
+                )? as u64;
+            }
+            transaction.commit()?;
+            Ok(deleted)
+        })
+        .await?
+    }
 }
 
 #[async_trait]
 impl StorageInstance for DuckDBStorage {
     async fn create_or_migrate(&self) -> Result<()> {
+        let numeric_precision = NumericPrecision::from_config()?;
         let connection = self.connection.lock().await;
         connection
             .execute_batch(INIT_SQL)
             .context("Failed to initialise database")?;
+
+        // The numeric column is only altered when the configured type differs,
+        // as it rewrites the table.
+        let numeric_type: String = connection.query_row(
+            r#"
+            SELECT data_type FROM information_schema.columns
+            WHERE table_name = 'numeric_values' AND column_name = 'value'
+            "#,
+            [],
+            |row| row.get(0),
+        )?;
+        let configured_type = format!(
+            "DECIMAL({},{})",
+            numeric_precision.precision, numeric_precision.scale
+        );
+        if numeric_type != configured_type {
+            let numeric_precision_sql = NUMERIC_PRECISION_SQL
+                .replace(
+                    "{numeric_precision}",
+                    &numeric_precision.precision.to_string(),
+                )
+                .replace("{numeric_scale}", &numeric_precision.scale.to_string());
+            connection
+                .execute_batch(&numeric_precision_sql)
+                .with_context(|| {
+                    format!("Failed to change the numeric values to {}", configured_type)
+                })?;
+        }
         Ok(())
     }
     async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
@@ -75,19 +152,9 @@ impl StorageInstance for DuckDBStorage {
     }
 
     async fn vacuum(&self) -> Result<()> {
+        // The duplicates are removed by the explicit deduplicate,
+        // as it rewrites the value tables.
         let connection = self.connection.lock().await;
-        /*let transaction = connection.transaction()?;
-
-        transaction.execute(
-            r#"
-            DELETE FROM integer_values WHERE rowid NOT IN (
-                SELECT MIN(rowid) FROM integer_values GROUP BY sensor_id, timestamp_ms, value
-            )
-            "#,
-            [],
-        )?;
-        transaction.commit()?;*/
-
         connection.execute("VACUUM ANALYZE", [])?;
         Ok(())
     }
@@ -95,6 +162,24 @@ impl StorageInstance for DuckDBStorage {
     async fn list_sensors(&self) -> Result<Vec<String>> {
         unimplemented!();
     }
+
+    async fn health_check(&self) -> Result<()> {
+        let connection = self.connection.lock().await;
+        connection.execute_batch("SELECT 1")?;
+        Ok(())
+    }
+
+    async fn raw_sql_query(
+        &self,
+        sql: &str,
+        max_rows: usize,
+        timeout: Duration,
+    ) -> Result<RawSqlResult> {
+        let sql = validate_read_only_select(sql)?.to_string();
+        // On its own connection, so the query doesn't block the ingestion.
+        let mut connection = self.connection.lock().await.try_clone()?;
+        spawn_blocking(move || query_raw_sql(&mut connection, &sql, max_rows, timeout)).await?
+    }
 }
 
 fn publish_single_sensor_batch(
@@ -133,3 +218,144 @@ fn publish_single_sensor_batch(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        sensapp_datetime::SensAppDateTimeExt, Sample, SensAppDateTime, Sensor, SensorType,
+    };
+    use smallvec::smallvec;
+
+    fn integer_batch(sensor: &Arc<Sensor>, samples: &[(i64, i64)]) -> Arc<Batch> {
+        let samples = TypedSamples::Integer(
+            samples
+                .iter()
+                .map(|(second, value)| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(*second),
+                    value: *value,
+                })
+                .collect(),
+        );
+        Arc::new(Batch::new(smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            samples
+        )]))
+    }
+
+    /// It needs the DuckDB json extension, downloaded on its first use:
+    /// `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_deduplicate() {
+        _ = load_configuration();
+        let storage = DuckDBStorage::connect("duckdb://:memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                "test_deduplicate".to_string(),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        // The same batch published twice, and a sample with the same
+        // timestamp but another value
+        let samples = [(1_700_000_000, 1), (1_700_000_001, 2), (1_700_000_002, 3)];
+        for batch in [
+            integer_batch(&sensor, &samples),
+            integer_batch(&sensor, &samples),
+            integer_batch(&sensor, &[(1_700_000_000, 42)]),
+        ] {
+            storage
+                .publish(batch, async_broadcast::broadcast(1).0)
+                .await
+                .unwrap();
+        }
+
+        let rows = || async {
+            let connection = storage.connection.lock().await;
+            connection
+                .query_row("SELECT COUNT(*) FROM integer_values", [], |row| {
+                    row.get::<_, i64>(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(rows().await, 7);
+
+        assert_eq!(storage.deduplicate().await.unwrap(), 3);
+        assert_eq!(rows().await, 4);
+        // Nothing left to remove
+        assert_eq!(storage.deduplicate().await.unwrap(), 0);
+        storage.vacuum().await.unwrap();
+    }
+
+    /// It needs the DuckDB json extension, downloaded on its first use:
+    /// `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_numeric_precision_migration() {
+        _ = load_configuration();
+        let storage = DuckDBStorage::connect("duckdb://:memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+        // Idempotent
+        storage.create_or_migrate().await.unwrap();
+
+        let connection = storage.connection.lock().await;
+        let numeric_type: String = connection
+            .query_row(
+                "SELECT data_type FROM information_schema.columns WHERE table_name = 'numeric_values' AND column_name = 'value'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let numeric_precision = NumericPrecision::from_config().unwrap();
+        assert_eq!(
+            numeric_type,
+            format!(
+                "DECIMAL({},{})",
+                numeric_precision.precision, numeric_precision.scale
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_raw_sql_query() {
+        let storage = DuckDBStorage::connect("duckdb://:memory:").await.unwrap();
+        let timeout = Duration::from_secs(5);
+        let result = storage
+            .raw_sql_query(
+                "SELECT 1 AS one, 'two' AS two, NULL AS three, 1.5::DECIMAL(4,2) AS four",
+                10,
+                timeout,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.columns, vec!["one", "two", "three", "four"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                serde_json::json!(1),
+                serde_json::json!("two"),
+                serde_json::Value::Null,
+                serde_json::json!(1.5)
+            ]]
+        );
+
+        let result = storage
+            .raw_sql_query("SELECT * FROM range(5)", 3, timeout)
+            .await
+            .unwrap();
+        assert_eq!(result.rows.len(), 3);
+        assert!(result.truncated);
+
+        let error = storage
+            .raw_sql_query("SELECT * FROM missing_table", 10, timeout)
+            .await
+            .unwrap_err();
+        assert!(error.is::<crate::storage::query::InvalidQueryError>());
+    }
+}
@@ -0,0 +1,180 @@
+use super::query::{SensorSelector, TimeRange};
+use super::storage::StorageInstance;
+use crate::datamodel::batch::{Batch, SingleSensorBatch};
+use crate::datamodel::{SensAppDateTime, SensorData};
+use anyhow::Result;
+use futures::{Stream, TryStreamExt};
+use smallvec::smallvec;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Streams all the samples of a sensor, page by page, in chronological order.
+///
+/// The stream is empty when the sensor doesn't exist.
+pub fn stream_sensor_data(
+    storage: Arc<dyn StorageInstance>,
+    sensor_uuid: Uuid,
+    page_size: usize,
+) -> impl Stream<Item = Result<SensorData>> {
+    futures::stream::try_unfold(Some(None::<SensAppDateTime>), move |cursor| {
+        let storage = storage.clone();
+        async move {
+            let after = match cursor {
+                Some(after) => after,
+                None => return Ok(None),
+            };
+            let page = match storage
+                .query_sensor_data_page(sensor_uuid, after, page_size)
+                .await?
+            {
+                Some(page) if page.samples.len() > 0 => page,
+                _ => return Ok(None),
+            };
+            // The stream ends with the first empty page.
+            let next_cursor = Some(page.samples.last_datetime());
+            Ok(Some((page, next_cursor)))
+        }
+    })
+}
+
+/// Copies all the samples of a sensor from the source to the target storage.
+///
+/// Returns the number of copied samples.
+pub async fn migrate_sensor(
+    source: Arc<dyn StorageInstance>,
+    target: Arc<dyn StorageInstance>,
+    sensor_uuid: Uuid,
+    page_size: usize,
+) -> Result<usize> {
+    let mut pages = std::pin::pin!(stream_sensor_data(source, sensor_uuid, page_size));
+    let mut sample_count = 0;
+    while let Some(page) = pages.try_next().await? {
+        sample_count += page.samples.len();
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(
+            Arc::new(page.sensor),
+            page.samples,
+        )]);
+        let (sync_sender, mut sync_receiver) = async_broadcast::broadcast(1);
+        target.publish(Arc::new(batch), sync_sender).await?;
+        // Drain the sync notification, nobody else is listening.
+        _ = sync_receiver.try_recv();
+    }
+    Ok(sample_count)
+}
+
+/// Copies all the sensors from the source to the target storage.
+///
+/// Returns the number of copied sensors and samples.
+pub async fn migrate_all(
+    source: Arc<dyn StorageInstance>,
+    target: Arc<dyn StorageInstance>,
+    page_size: usize,
+) -> Result<(usize, usize)> {
+    let sensors = source
+        .count_series_by_labels(&SensorSelector::default(), TimeRange::default())
+        .await?;
+    let mut sample_count = 0;
+    for (sensor, _) in &sensors {
+        let copied = migrate_sensor(source.clone(), target.clone(), sensor.uuid, page_size).await?;
+        tracing::info!("Migrated {} samples of the sensor {}", copied, sensor.name);
+        sample_count += copied;
+    }
+    target.sync(async_broadcast::broadcast(1).0).await?;
+    Ok((sensors.len(), sample_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{Sample, Sensor, SensorType, TypedSamples};
+    use crate::storage::sqlite::sqlite;
+
+    async fn create_test_storage() -> Arc<dyn StorageInstance> {
+        _ = load_configuration();
+        Arc::new(sqlite::tests::create_test_storage().await)
+    }
+
+    #[tokio::test]
+    async fn test_migrate_sqlite_to_sqlite() {
+        let source = create_test_storage().await;
+        let target = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid("migrate_test".to_string(), SensorType::Float, None, None)
+                .unwrap(),
+        );
+        let samples = TypedSamples::Float(
+            (0..1000)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds(1_700_000_000.0 + i as f64),
+                    value: i as f64 / 2.0,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        source
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let pages: Vec<SensorData> = stream_sensor_data(source.clone(), sensor.uuid, 64)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(pages.len(), 16);
+
+        let (sensor_count, sample_count) = migrate_all(source.clone(), target.clone(), 64)
+            .await
+            .unwrap();
+        assert_eq!(sensor_count, 1);
+        assert_eq!(sample_count, 1000);
+
+        let expected = source
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let migrated = target
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(migrated.sensor.name, expected.sensor.name);
+        assert_eq!(migrated.sensor.sensor_type, expected.sensor.sensor_type);
+        assert_eq!(migrated.samples, expected.samples);
+        assert_eq!(migrated.samples.len(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_page_keeps_equal_datetimes_together() {
+        let storage = create_test_storage().await;
+        let sensor = Arc::new(
+            Sensor::new_without_uuid("page_test".to_string(), SensorType::Integer, None, None)
+                .unwrap(),
+        );
+        // Three samples share the second datetime.
+        let samples = TypedSamples::Integer(
+            [0, 1, 1, 1, 2]
+                .iter()
+                .enumerate()
+                .map(|(i, second)| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds(1_700_000_000.0 + *second as f64),
+                    value: i as i64,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let pages: Vec<SensorData> = stream_sensor_data(storage, sensor.uuid, 2)
+            .try_collect()
+            .await
+            .unwrap();
+        let page_lengths: Vec<usize> = pages.iter().map(|page| page.samples.len()).collect();
+        assert_eq!(page_lengths, vec![1, 3, 1]);
+    }
+}
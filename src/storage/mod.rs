@@ -1,8 +1,25 @@
 pub mod bigquery;
+pub mod blob_compression;
+pub mod circuit_breaker;
 pub mod duckdb;
+pub mod parquet;
 pub mod postgresql;
+pub mod query;
+pub mod migrate;
+pub mod multi;
+pub mod raw_sql;
+pub mod redact;
+pub mod retry;
+pub mod rollup;
 pub mod rrdcached;
+pub mod schema;
 pub mod sqlite;
 pub mod storage;
 pub mod storage_factory;
+pub mod strict_sensors;
+pub mod sync_timeout;
+pub mod tiered;
 pub mod timescaledb;
+pub mod type_conflict;
+pub mod verify;
+pub mod write_buffer;
@@ -0,0 +1,527 @@
+use super::circuit_breaker::CircuitBreakerState;
+use super::query::{AggregateBucket, SensorSelector, SortOrder, TimeRange};
+use super::raw_sql::RawSqlResult;
+use super::storage::StorageInstance;
+use super::storage_factory::create_storage_from_connection_string;
+use super::verify::VerifyReport;
+use super::write_buffer::WriteBufferStatus;
+use crate::datamodel::{
+    batch::Batch, sensor_metadata::SensorMetadata, Annotation, SensAppDateTime, Sensor, SensorData,
+};
+use anyhow::{anyhow, bail, Result};
+use async_broadcast::Sender;
+use async_trait::async_trait;
+use futures::future::{join_all, try_join_all};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+/// How the writes handle the failure of a backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiStorageMode {
+    /// A write fails as soon as one backend fails.
+    FailFast,
+    /// A write succeeds when at least one backend succeeds,
+    /// and the failures of the others are logged.
+    BestEffort,
+}
+
+/// Mirrors the writes to several storage backends, such as a
+/// TimescaleDB database and a Parquet archive during a migration.
+///
+/// The writes go to all the backends at the same time, and the reads
+/// come from the first backend, the primary.
+///
+/// The connection string is `multi:<primary>|<mirror>[|<mirror>...]`,
+/// failing fast by default, or `multi:best_effort:<primary>|<mirror>`.
+/// The backends can't be tiered storages, as they use `|` too.
+#[derive(Debug)]
+pub struct MultiStorage {
+    backends: Vec<Arc<dyn StorageInstance>>,
+    mode: MultiStorageMode,
+}
+
+impl MultiStorage {
+    pub fn new(backends: Vec<Arc<dyn StorageInstance>>, mode: MultiStorageMode) -> Result<Self> {
+        if backends.is_empty() {
+            bail!("The multi storage requires at least one backend");
+        }
+        Ok(Self { backends, mode })
+    }
+
+    pub async fn connect(connection_string: &str) -> Result<Self> {
+        let (mode, connection_strings) = parse_multi_connection_string(connection_string)?;
+        let mut backends = Vec::with_capacity(connection_strings.len());
+        for connection_string in connection_strings {
+            // Boxed, as the storage factory is calling this function.
+            backends
+                .push(Box::pin(create_storage_from_connection_string(connection_string)).await?);
+        }
+        Self::new(backends, mode)
+    }
+
+    fn primary(&self) -> &Arc<dyn StorageInstance> {
+        &self.backends[0]
+    }
+
+    /// Runs the operation on all the backends, and returns
+    /// the result of the primary, or of the first successful backend
+    /// in best effort mode.
+    async fn fan_out<T, F, Fut>(&self, operation: &str, f: F) -> Result<T>
+    where
+        F: Fn(Arc<dyn StorageInstance>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let futures = self.backends.iter().cloned().map(f);
+        match self.mode {
+            MultiStorageMode::FailFast => Ok(try_join_all(futures)
+                .await?
+                .into_iter()
+                .next()
+                .expect("The multi storage has at least one backend")),
+            MultiStorageMode::BestEffort => {
+                let mut first_success = None;
+                let mut last_error = None;
+                for (index, result) in join_all(futures).await.into_iter().enumerate() {
+                    match result {
+                        Ok(value) => {
+                            first_success.get_or_insert(value);
+                        }
+                        Err(error) => {
+                            tracing::warn!(
+                                "The {} failed on the storage backend {}: {:?}",
+                                operation,
+                                index,
+                                error
+                            );
+                            last_error = Some(error);
+                        }
+                    }
+                }
+                match (first_success, last_error) {
+                    (Some(value), _) => Ok(value),
+                    (None, Some(error)) => Err(error),
+                    (None, None) => unreachable!("The multi storage has at least one backend"),
+                }
+            }
+        }
+    }
+}
+
+/// A sync sender without receivers, for the backends.
+///
+/// The caller is notified once all the backends are synced,
+/// and not by the first one.
+fn backend_sync_sender() -> Sender<()> {
+    async_broadcast::broadcast(1).0
+}
+
+async fn notify_sync(sync_sender: Sender<()>) -> Result<()> {
+    if sync_sender.receiver_count() > 0 && !sync_sender.is_closed() {
+        let _ = timeout(Duration::from_secs(15), sync_sender.broadcast(())).await?;
+    }
+    Ok(())
+}
+
+/// Splits a `multi:[best_effort:|fail_fast:]<primary>|<mirror>...` connection string.
+pub fn parse_multi_connection_string(
+    connection_string: &str,
+) -> Result<(MultiStorageMode, Vec<&str>)> {
+    let backends = connection_string
+        .strip_prefix("multi:")
+        .ok_or_else(|| anyhow!("The multi connection string must start with multi:"))?;
+    let (mode, backends) = if let Some(backends) = backends.strip_prefix("best_effort:") {
+        (MultiStorageMode::BestEffort, backends)
+    } else if let Some(backends) = backends.strip_prefix("fail_fast:") {
+        (MultiStorageMode::FailFast, backends)
+    } else {
+        (MultiStorageMode::FailFast, backends)
+    };
+    let backends = backends.split('|').collect::<Vec<_>>();
+    if backends.iter().any(|backend| backend.is_empty()) {
+        bail!("The multi connection string must be multi:<primary>|<mirror>...");
+    }
+    Ok((mode, backends))
+}
+
+#[async_trait]
+impl StorageInstance for MultiStorage {
+    async fn create_or_migrate(&self) -> Result<()> {
+        self.fan_out("migration", |backend| async move {
+            backend.create_or_migrate().await
+        })
+        .await
+    }
+
+    async fn check_schema(&self) -> Result<()> {
+        self.fan_out("schema check", |backend| async move {
+            backend.check_schema().await
+        })
+        .await
+    }
+
+    async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
+        self.fan_out("publish", |backend| {
+            let batch = batch.clone();
+            async move { backend.publish(batch, backend_sync_sender()).await }
+        })
+        .await?;
+        notify_sync(sync_sender).await
+    }
+
+    async fn sync(&self, sync_sender: Sender<()>) -> Result<()> {
+        self.fan_out("sync", |backend| async move {
+            backend.sync(backend_sync_sender()).await
+        })
+        .await?;
+        notify_sync(sync_sender).await
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        self.fan_out("vacuum", |backend| async move { backend.vacuum().await })
+            .await
+    }
+
+    async fn list_sensors(&self) -> Result<Vec<String>> {
+        self.primary().list_sensors().await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.fan_out("health check", |backend| async move {
+            backend.health_check().await
+        })
+        .await
+    }
+
+    fn circuit_breaker_state(&self) -> Option<CircuitBreakerState> {
+        self.primary().circuit_breaker_state()
+    }
+
+    fn write_buffer_status(&self) -> Option<WriteBufferStatus> {
+        self.primary().write_buffer_status()
+    }
+
+    async fn get_sensor(&self, sensor_uuid: Uuid) -> Result<Option<Sensor>> {
+        self.primary().get_sensor(sensor_uuid).await
+    }
+
+    async fn update_sensor_metadata(
+        &self,
+        sensor_uuid: Uuid,
+        metadata: &SensorMetadata,
+    ) -> Result<bool> {
+        self.fan_out("sensor metadata update", |backend| async move {
+            backend.update_sensor_metadata(sensor_uuid, metadata).await
+        })
+        .await
+    }
+
+    async fn query_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.primary()
+            .query_sensor_data(sensor_uuid, start, end, limit)
+            .await
+    }
+
+    async fn query_sensor_data_by_id(
+        &self,
+        sensor_id: i64,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.primary()
+            .query_sensor_data_by_id(sensor_id, start, end, limit)
+            .await
+    }
+
+    async fn query_sensor_data_window(
+        &self,
+        sensor_uuid: Uuid,
+        window: hifitime::Duration,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.primary()
+            .query_sensor_data_window(sensor_uuid, window, limit)
+            .await
+    }
+
+    async fn query_sensor_data_by_value(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        order: SortOrder,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.primary()
+            .query_sensor_data_by_value(sensor_uuid, start, end, order, limit)
+            .await
+    }
+
+    async fn query_sensor_data_page(
+        &self,
+        sensor_uuid: Uuid,
+        after: Option<SensAppDateTime>,
+        limit: usize,
+    ) -> Result<Option<SensorData>> {
+        self.primary()
+            .query_sensor_data_page(sensor_uuid, after, limit)
+            .await
+    }
+
+    async fn apply_retention(&self, older_than: SensAppDateTime) -> Result<()> {
+        self.fan_out("retention", |backend| async move {
+            backend.apply_retention(older_than).await
+        })
+        .await
+    }
+
+    async fn delete_metric(&self, name: &str) -> Result<u64> {
+        self.fan_out("metric deletion", |backend| async move {
+            backend.delete_metric(name).await
+        })
+        .await
+    }
+
+    async fn query(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorData>> {
+        self.primary().query(selector, time_range, limit).await
+    }
+
+    async fn unknown_sensors(&self, sensor_uuids: &[Uuid]) -> Result<Vec<Uuid>> {
+        self.primary().unknown_sensors(sensor_uuids).await
+    }
+
+    async fn latest_samples_all(&self, metric_filter: Option<&str>) -> Result<Vec<SensorData>> {
+        self.primary().latest_samples_all(metric_filter).await
+    }
+
+    async fn count_series_by_labels(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+    ) -> Result<Vec<(Sensor, u64)>> {
+        self.primary()
+            .count_series_by_labels(selector, time_range)
+            .await
+    }
+
+    async fn aggregate_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+        step: hifitime::Duration,
+    ) -> Result<Option<Vec<AggregateBucket>>> {
+        self.primary()
+            .aggregate_sensor_data(sensor_uuid, time_range, step)
+            .await
+    }
+
+    async fn verify_sensor(&self, sensor_uuid: Uuid) -> Result<Option<VerifyReport>> {
+        self.primary().verify_sensor(sensor_uuid).await
+    }
+
+    async fn add_annotation(&self, sensor_uuid: Uuid, annotation: &Annotation) -> Result<bool> {
+        self.fan_out("annotation", |backend| async move {
+            backend.add_annotation(sensor_uuid, annotation).await
+        })
+        .await
+    }
+
+    async fn query_annotations(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+    ) -> Result<Option<Vec<Annotation>>> {
+        self.primary()
+            .query_annotations(sensor_uuid, time_range)
+            .await
+    }
+
+    async fn raw_sql_query(
+        &self,
+        sql: &str,
+        max_rows: usize,
+        timeout: Duration,
+    ) -> Result<RawSqlResult> {
+        self.primary().raw_sql_query(sql, max_rows, timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::SingleSensorBatch, sensapp_datetime::SensAppDateTimeExt, Sample, SensorType,
+        TypedSamples,
+    };
+    use crate::storage::sqlite::sqlite::tests::memory_connection_string;
+    use crate::storage::sqlite::SqliteStorage;
+    use smallvec::smallvec;
+
+    /// A storage whose writes always fail.
+    #[derive(Debug)]
+    struct FailingStorage;
+
+    #[async_trait]
+    impl StorageInstance for FailingStorage {
+        async fn create_or_migrate(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn publish(&self, _batch: Arc<Batch>, _sync_sender: Sender<()>) -> Result<()> {
+            bail!("The storage is down")
+        }
+        async fn sync(&self, _sync_sender: Sender<()>) -> Result<()> {
+            bail!("The storage is down")
+        }
+        async fn vacuum(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn list_sensors(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn test_batch() -> (Arc<Sensor>, Arc<Batch>) {
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_multi_storage_{}", Uuid::new_v4()),
+                SensorType::Float,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Float(
+            (0..5)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                    value: i as f64,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        (sensor, Arc::new(batch))
+    }
+
+    async fn count_samples(storage: &dyn StorageInstance, sensor_uuid: Uuid) -> Option<usize> {
+        storage
+            .query_sensor_data(sensor_uuid, None, None, None)
+            .await
+            .unwrap()
+            .map(|sensor_data| sensor_data.samples.len())
+    }
+
+    #[test]
+    fn test_parse_multi_connection_string() {
+        assert_eq!(
+            parse_multi_connection_string("multi:sqlite://a.db|parquet:///archive").unwrap(),
+            (
+                MultiStorageMode::FailFast,
+                vec!["sqlite://a.db", "parquet:///archive"]
+            )
+        );
+        assert_eq!(
+            parse_multi_connection_string(
+                "multi:best_effort:sqlite://a.db|sqlite://b.db|sqlite://c.db"
+            )
+            .unwrap(),
+            (
+                MultiStorageMode::BestEffort,
+                vec!["sqlite://a.db", "sqlite://b.db", "sqlite://c.db"]
+            )
+        );
+        assert_eq!(
+            parse_multi_connection_string("multi:fail_fast:sqlite://a.db")
+                .unwrap()
+                .0,
+            MultiStorageMode::FailFast
+        );
+        assert!(parse_multi_connection_string("sqlite://a.db|sqlite://b.db").is_err());
+        assert!(parse_multi_connection_string("multi:sqlite://a.db|").is_err());
+        assert!(parse_multi_connection_string("multi:").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_multi_storage_publish() {
+        _ = load_configuration();
+        let (primary, mirror) = (memory_connection_string(), memory_connection_string());
+        let storage =
+            create_storage_from_connection_string(&format!("multi:{}|{}", primary, mirror))
+                .await
+                .unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let (sensor, batch) = test_batch();
+        storage
+            .publish(batch, async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        // In both backends, and read from the primary
+        for connection_string in [primary, mirror] {
+            let backend = SqliteStorage::connect(&connection_string).await.unwrap();
+            assert_eq!(count_samples(&backend, sensor.uuid).await, Some(5));
+        }
+        assert_eq!(count_samples(storage.as_ref(), sensor.uuid).await, Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_multi_storage_best_effort() {
+        _ = load_configuration();
+        let connection_string = memory_connection_string();
+        let sqlite: Arc<dyn StorageInstance> =
+            Arc::new(SqliteStorage::connect(&connection_string).await.unwrap());
+        sqlite.create_or_migrate().await.unwrap();
+
+        let best_effort = MultiStorage::new(
+            vec![sqlite.clone(), Arc::new(FailingStorage)],
+            MultiStorageMode::BestEffort,
+        )
+        .unwrap();
+        let (sensor, batch) = test_batch();
+        best_effort
+            .publish(batch, async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        assert_eq!(count_samples(sqlite.as_ref(), sensor.uuid).await, Some(5));
+
+        // Failing when all the backends fail
+        let all_failing = MultiStorage::new(
+            vec![Arc::new(FailingStorage), Arc::new(FailingStorage)],
+            MultiStorageMode::BestEffort,
+        )
+        .unwrap();
+        let (_, batch) = test_batch();
+        assert!(all_failing
+            .publish(batch, async_broadcast::broadcast(1).0)
+            .await
+            .is_err());
+
+        let fail_fast = MultiStorage::new(
+            vec![sqlite.clone(), Arc::new(FailingStorage)],
+            MultiStorageMode::FailFast,
+        )
+        .unwrap();
+        let (_, batch) = test_batch();
+        assert!(fail_fast
+            .publish(batch, async_broadcast::broadcast(1).0)
+            .await
+            .is_err());
+
+        assert!(MultiStorage::new(Vec::new(), MultiStorageMode::FailFast).is_err());
+    }
+}
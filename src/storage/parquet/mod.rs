@@ -0,0 +1,716 @@
+use super::query::sensor_query_limit;
+use super::storage::StorageInstance;
+use crate::datamodel::{
+    arrow_converter::ArrowConverter, batch::Batch, sensapp_vec::SensAppLabels, unit::Unit,
+    SensAppDateTime, Sensor, SensorData, SensorType,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use arrow::array::{AsArray, BooleanArray};
+use arrow::compute::{concat_batches, filter_record_batch, sort_to_indices, take_record_batch};
+use arrow::datatypes::{SchemaRef, TimestampMicrosecondType};
+use arrow::record_batch::RecordBatch;
+use async_broadcast::Sender;
+use async_trait::async_trait;
+use hifitime::Unit as TimeUnit;
+use parquet::arrow::arrow_reader::{ArrowPredicateFn, ParquetRecordBatchReaderBuilder, RowFilter};
+use parquet::arrow::{ArrowWriter, ProjectionMask};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::task::spawn_blocking;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+const MICROSECONDS_PER_DAY: i64 = 86_400_000_000;
+const SENSOR_METADATA_FILE: &str = "sensor.json";
+/// The key of the day file metadata listing the parts merged into it.
+const MERGED_PARTS_KEY: &str = "sensapp.merged_parts";
+
+/// Stores the samples in Parquet files, partitioned by sensor and by day.
+///
+/// Each day of a sensor is stored in `<directory>/<sensor_uuid>/<yyyy-mm-dd>.parquet`,
+/// and each sensor directory has a `sensor.json` file with the sensor metadata.
+///
+/// Parquet files can't be appended to, and rewriting the day file on every
+/// publish would rewrite the whole day each time. So the publishes add parts
+/// in `<directory>/<sensor_uuid>/<yyyy-mm-dd>/<part>.parquet`, read with the
+/// day file, and the vacuum merges them into the day file.
+/// The day file lists the parts merged into it, and the queries skip them,
+/// so a vacuum interrupted before removing the parts doesn't duplicate samples.
+#[derive(Debug)]
+pub struct ParquetStorage {
+    directory: PathBuf,
+    // The publishes and the queries share it, the vacuum replaces the parts
+    parts_lock: Arc<RwLock<()>>,
+    sync_timeout: Duration,
+}
+
+impl ParquetStorage {
+    pub async fn connect(connection_string: &str) -> Result<Self> {
+        const PREFIX: &str = "parquet://";
+
+        if !connection_string.starts_with(PREFIX) {
+            bail!("Invalid connection string, must start with {}", PREFIX);
+        }
+
+        let directory = PathBuf::from(&connection_string[PREFIX.len()..]);
+        if directory.as_os_str().is_empty() {
+            bail!("Invalid connection string, the directory is missing");
+        }
+
+        // Zero waits forever, like the ingestion requests
+        let sync_timeout_seconds = crate::config::get()
+            .map(|config| config.storage_sync_timeout_seconds)
+            .unwrap_or_default();
+
+        Ok(Self {
+            directory,
+            parts_lock: Arc::new(RwLock::new(())),
+            sync_timeout: Duration::from_secs(sync_timeout_seconds),
+        })
+    }
+}
+
+fn datetime_to_microseconds(datetime: &SensAppDateTime) -> i64 {
+    datetime.to_unix(TimeUnit::Microsecond).floor() as i64
+}
+
+/// The day file of a partition directory, `<yyyy-mm-dd>.parquet` next to it.
+fn day_file_path(partition_directory: &Path) -> PathBuf {
+    partition_directory.with_extension("parquet")
+}
+
+fn day_to_partition_name(day: i64) -> String {
+    let datetime = SensAppDateTime::from_unix_seconds((day * 86_400) as f64);
+    let (year, month, day, _, _, _, _) = datetime.to_gregorian_utc();
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Returns the day, in days since the unix epoch, of a partition directory
+/// or day file name.
+fn partition_name_to_day(name: &str) -> Option<i64> {
+    let name = name.strip_suffix(".parquet").unwrap_or(name);
+    let mut parts = name.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    let datetime = SensAppDateTime::maybe_from_gregorian_utc(year, month, day, 0, 0, 0, 0).ok()?;
+    Some(datetime_to_microseconds(&datetime).div_euclid(MICROSECONDS_PER_DAY))
+}
+
+fn sensor_to_metadata(sensor: &Sensor) -> Value {
+    json!({
+        "uuid": sensor.uuid.to_string(),
+        "name": sensor.name,
+        "type": sensor.sensor_type.to_string(),
+        "unit": sensor.unit.as_ref().map(|unit| json!({
+            "name": unit.name,
+            "description": unit.description,
+        })),
+        "labels": sensor.labels.iter().collect::<Vec<_>>(),
+    })
+}
+
+fn metadata_to_sensor(metadata: &Value) -> Result<Sensor> {
+    let invalid = || anyhow!("Invalid sensor metadata");
+    let uuid = Uuid::parse_str(metadata["uuid"].as_str().ok_or_else(invalid)?)?;
+    let name = metadata["name"].as_str().ok_or_else(invalid)?.to_string();
+    let sensor_type = SensorType::from_str(metadata["type"].as_str().ok_or_else(invalid)?)?;
+    let unit = match &metadata["unit"] {
+        Value::Null => None,
+        unit => Some(Unit::new(
+            unit["name"].as_str().ok_or_else(invalid)?.to_string(),
+            unit["description"].as_str().map(|s| s.to_string()),
+        )),
+    };
+    let labels = serde_json::from_value::<Vec<(String, String)>>(metadata["labels"].clone())?
+        .into_iter()
+        .collect::<SensAppLabels>();
+    Ok(Sensor::new(uuid, name, sensor_type, unit, Some(labels)))
+}
+
+fn read_sensor_metadata(sensor_directory: &Path) -> Result<Sensor> {
+    let metadata = fs::read(sensor_directory.join(SENSOR_METADATA_FILE))?;
+    metadata_to_sensor(&serde_json::from_slice(&metadata)?)
+}
+
+fn sort_by_datetime(record_batch: &RecordBatch) -> Result<RecordBatch> {
+    let indices = sort_to_indices(record_batch.column(0), None, None)?;
+    Ok(take_record_batch(record_batch, &indices)?)
+}
+
+/// Reads a part of a partition, keeping only the rows within the time range.
+///
+/// The time range is pushed down to the Parquet reader as a row filter
+/// on the datetime column, so the other columns are only decoded
+/// for the matching rows.
+fn read_part(path: &Path, start_us: i64, end_us: i64) -> Result<Vec<RecordBatch>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+    let datetime_mask = ProjectionMask::roots(builder.parquet_schema(), [0]);
+    let predicate = ArrowPredicateFn::new(datetime_mask, move |batch: RecordBatch| {
+        let datetimes = batch.column(0).as_primitive::<TimestampMicrosecondType>();
+        Ok(BooleanArray::from_iter(datetimes.values().iter().map(
+            |datetime| Some(*datetime >= start_us && *datetime < end_us),
+        )))
+    });
+    let reader = builder
+        .with_row_filter(RowFilter::new(vec![Box::new(predicate)]))
+        .build()?;
+    Ok(reader.collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Returns the parts of a partition. The parts being written,
+/// still with a temporary extension, are left out.
+fn list_parts(partition_directory: &Path) -> Result<Vec<PathBuf>> {
+    if !partition_directory.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut parts = fs::read_dir(partition_directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "parquet")
+        })
+        .collect::<Vec<_>>();
+    parts.sort();
+    Ok(parts)
+}
+
+/// Returns the names of the parts merged into the day file,
+/// empty without a day file.
+fn read_merged_parts(day_file: &Path) -> Result<BTreeSet<String>> {
+    if !day_file.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let file =
+        File::open(day_file).with_context(|| format!("Failed to open {}", day_file.display()))?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+    let merged_parts = builder
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|key_values| {
+            key_values
+                .iter()
+                .find(|key_value| key_value.key == MERGED_PARTS_KEY)
+        })
+        .and_then(|key_value| key_value.value.as_deref())
+        .map(serde_json::from_str::<BTreeSet<String>>)
+        .transpose()?;
+    Ok(merged_parts.unwrap_or_default())
+}
+
+fn part_name(part: &Path) -> String {
+    part.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Returns the parts of a partition not merged into its day file yet.
+fn list_unmerged_parts(partition_directory: &Path) -> Result<Vec<PathBuf>> {
+    let merged_parts = read_merged_parts(&day_file_path(partition_directory))?;
+    Ok(list_parts(partition_directory)?
+        .into_iter()
+        .filter(|part| !merged_parts.contains(&part_name(part)))
+        .collect())
+}
+
+/// Reads the day file and the unmerged parts of a partition
+/// within the time range, sorted by datetime.
+fn read_partition(
+    partition_directory: &Path,
+    schema: &SchemaRef,
+    start_us: i64,
+    end_us: i64,
+) -> Result<RecordBatch> {
+    let mut record_batches = Vec::new();
+    let day_file = day_file_path(partition_directory);
+    if day_file.exists() {
+        record_batches.extend(read_part(&day_file, start_us, end_us)?);
+    }
+    for part in list_unmerged_parts(partition_directory)? {
+        record_batches.extend(read_part(&part, start_us, end_us)?);
+    }
+    sort_by_datetime(&concat_batches(schema, &record_batches)?)
+}
+
+/// Writes a Parquet file sorted by datetime, with a temporary extension
+/// first, so the queries never read a partially written file.
+fn write_parquet_file(
+    path: &Path,
+    record_batch: &RecordBatch,
+    properties: Option<WriterProperties>,
+) -> Result<()> {
+    let sorted = sort_by_datetime(record_batch)?;
+    let temporary_path = path.with_extension("parquet.tmp");
+    let file = File::create(&temporary_path)?;
+    let mut writer = ArrowWriter::try_new(file, sorted.schema(), properties)?;
+    writer.write(&sorted)?;
+    writer.close()?;
+    fs::rename(&temporary_path, path)?;
+    Ok(())
+}
+
+/// Writes a new part in a partition.
+fn write_part(partition_directory: &Path, record_batch: &RecordBatch) -> Result<()> {
+    fs::create_dir_all(partition_directory)?;
+    let path = partition_directory.join(format!("{}.parquet", Uuid::new_v4()));
+    write_parquet_file(&path, record_batch, None)
+}
+
+/// Merges the unmerged parts of a partition into its day file.
+///
+/// The new day file lists the merged parts, and replaces the previous one
+/// in a single rename, so the parts are skipped by the queries
+/// from then on, and removed afterwards.
+fn merge_partition(partition_directory: &Path, schema: &SchemaRef) -> Result<()> {
+    let parts = list_unmerged_parts(partition_directory)?;
+    if parts.is_empty() {
+        return Ok(());
+    }
+    let merged = read_partition(partition_directory, schema, i64::MIN, i64::MAX)?;
+    let merged_parts = parts.iter().map(|part| part_name(part)).collect::<Vec<_>>();
+    let properties = WriterProperties::builder()
+        .set_key_value_metadata(Some(vec![KeyValue::new(
+            MERGED_PARTS_KEY.to_string(),
+            serde_json::to_string(&merged_parts)?,
+        )]))
+        .build();
+    write_parquet_file(
+        &day_file_path(partition_directory),
+        &merged,
+        Some(properties),
+    )
+}
+
+/// Removes the parts merged into the day file, and the partition
+/// directory once empty.
+fn remove_merged_parts(partition_directory: &Path) -> Result<()> {
+    let merged_parts = read_merged_parts(&day_file_path(partition_directory))?;
+    for part in list_parts(partition_directory)? {
+        if merged_parts.contains(&part_name(&part)) {
+            fs::remove_file(part)?;
+        }
+    }
+    if partition_directory.is_dir() && fs::read_dir(partition_directory)?.next().is_none() {
+        fs::remove_dir(partition_directory)?;
+    }
+    Ok(())
+}
+
+/// Merges the parts of a partition into its day file, and removes them.
+///
+/// The parts left by an interrupted compaction are removed first,
+/// so the day file only lists the parts of the last merge.
+fn compact_partition(partition_directory: &Path, schema: &SchemaRef) -> Result<()> {
+    remove_merged_parts(partition_directory)?;
+    merge_partition(partition_directory, schema)?;
+    remove_merged_parts(partition_directory)
+}
+
+fn write_sensor_batch(
+    directory: &Path,
+    sensor_metadata: &Value,
+    sensor_uuid: Uuid,
+    record_batch: RecordBatch,
+) -> Result<()> {
+    let sensor_directory = directory.join(sensor_uuid.to_string());
+    fs::create_dir_all(&sensor_directory)?;
+
+    let metadata_path = sensor_directory.join(SENSOR_METADATA_FILE);
+    if !metadata_path.exists() {
+        // Renamed, as another publish may write it at the same time
+        let temporary_path = sensor_directory.join(format!("{}.json.tmp", Uuid::new_v4()));
+        fs::write(&temporary_path, serde_json::to_vec(sensor_metadata)?)?;
+        fs::rename(&temporary_path, &metadata_path)?;
+    }
+
+    let days = record_batch
+        .column(0)
+        .as_primitive::<TimestampMicrosecondType>()
+        .values()
+        .iter()
+        .map(|datetime| datetime.div_euclid(MICROSECONDS_PER_DAY))
+        .collect::<Vec<_>>();
+
+    for day in days.iter().collect::<BTreeSet<_>>() {
+        let mask = BooleanArray::from_iter(days.iter().map(|d| Some(d == day)));
+        let partition = filter_record_batch(&record_batch, &mask)?;
+        write_part(
+            &sensor_directory.join(day_to_partition_name(*day)),
+            &partition,
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the partition directories of a sensor overlapping the days, sorted by day.
+///
+/// A partition has a day file, a directory of parts, or both,
+/// and the directory path is returned either way.
+fn list_partitions(sensor_directory: &Path, start_day: i64, end_day: i64) -> Result<Vec<PathBuf>> {
+    let days = fs::read_dir(sensor_directory)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let day = partition_name_to_day(&name)?;
+            let is_partition = entry.path().is_dir() || name.ends_with(".parquet");
+            (day >= start_day && day <= end_day && is_partition).then_some(day)
+        })
+        .collect::<BTreeSet<_>>();
+    Ok(days
+        .into_iter()
+        .map(|day| sensor_directory.join(day_to_partition_name(day)))
+        .collect())
+}
+
+fn query_sensor_directory(
+    sensor_directory: &Path,
+    start_us: i64,
+    end_us: i64,
+    limit: Option<usize>,
+) -> Result<Option<SensorData>> {
+    if !sensor_directory.exists() {
+        return Ok(None);
+    }
+    let sensor = read_sensor_metadata(sensor_directory)?;
+    let schema = ArrowConverter::schema(sensor.sensor_type);
+    let limit = sensor_query_limit(limit, sensor.sensor_type)?;
+
+    // Only the partitions overlapping the time range are read
+    let start_day = start_us.div_euclid(MICROSECONDS_PER_DAY);
+    let end_day = end_us.saturating_sub(1).div_euclid(MICROSECONDS_PER_DAY);
+
+    let mut record_batches = Vec::new();
+    let mut rows = 0;
+    for path in list_partitions(sensor_directory, start_day, end_day)? {
+        if limit.is_some_and(|limit| rows >= limit) {
+            break;
+        }
+        let record_batch = read_partition(&path, &schema, start_us, end_us)?;
+        rows += record_batch.num_rows();
+        record_batches.push(record_batch);
+    }
+
+    let mut record_batch = concat_batches(&schema, &record_batches)?;
+    if let Some(limit) = limit {
+        record_batch = record_batch.slice(0, limit.min(record_batch.num_rows()));
+    }
+    let samples = ArrowConverter::record_batch_to_typed_samples(sensor.sensor_type, &record_batch)?;
+    Ok(Some(SensorData::new(sensor, samples)))
+}
+
+#[async_trait]
+impl StorageInstance for ParquetStorage {
+    async fn create_or_migrate(&self) -> Result<()> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .with_context(|| format!("Failed to create {}", self.directory.display()))?;
+        Ok(())
+    }
+
+    async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
+        let mut sensor_batches = Vec::with_capacity(batch.sensors.len());
+        for single_sensor_batch in batch.sensors.as_ref() {
+            let samples_guard = single_sensor_batch.samples.read().await;
+            let record_batch = ArrowConverter::typed_samples_to_record_batch(&samples_guard)?;
+            let sensor = &single_sensor_batch.sensor;
+            sensor_batches.push((sensor_to_metadata(sensor), sensor.uuid, record_batch));
+        }
+
+        let directory = self.directory.clone();
+        let parts_lock = Arc::clone(&self.parts_lock);
+        spawn_blocking(move || -> Result<()> {
+            let _guard = parts_lock.blocking_read();
+            for (sensor_metadata, sensor_uuid, record_batch) in sensor_batches {
+                write_sensor_batch(&directory, &sensor_metadata, sensor_uuid, record_batch)?;
+            }
+            Ok(())
+        })
+        .await??;
+
+        self.sync(sync_sender).await?;
+        Ok(())
+    }
+
+    async fn sync(&self, sync_sender: Sender<()>) -> Result<()> {
+        // The files are closed after each publish, nothing to sync.
+        if sync_sender.receiver_count() > 0 && !sync_sender.is_closed() {
+            if self.sync_timeout.is_zero() {
+                let _ = sync_sender.broadcast(()).await;
+            } else {
+                let _ = timeout(self.sync_timeout, sync_sender.broadcast(())).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        let directory = self.directory.clone();
+        let parts_lock = Arc::clone(&self.parts_lock);
+        spawn_blocking(move || -> Result<()> {
+            let _guard = parts_lock.blocking_write();
+            for entry in fs::read_dir(&directory)? {
+                let sensor_directory = entry?.path();
+                if !sensor_directory.join(SENSOR_METADATA_FILE).exists() {
+                    continue;
+                }
+                let sensor = read_sensor_metadata(&sensor_directory)?;
+                let schema = ArrowConverter::schema(sensor.sensor_type);
+                for partition in list_partitions(&sensor_directory, i64::MIN, i64::MAX)? {
+                    compact_partition(&partition, &schema)?;
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn list_sensors(&self) -> Result<Vec<String>> {
+        let directory = self.directory.clone();
+        spawn_blocking(move || -> Result<Vec<String>> {
+            let mut names = Vec::new();
+            for entry in fs::read_dir(&directory)? {
+                let path = entry?.path();
+                if path.join(SENSOR_METADATA_FILE).exists() {
+                    names.push(read_sensor_metadata(&path)?.name);
+                }
+            }
+            names.sort();
+            Ok(names)
+        })
+        .await?
+    }
+
+    async fn query_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let sensor_directory = self.directory.join(sensor_uuid.to_string());
+        let start_us = start.as_ref().map_or(i64::MIN, datetime_to_microseconds);
+        let end_us = end.as_ref().map_or(i64::MAX, datetime_to_microseconds);
+        let parts_lock = Arc::clone(&self.parts_lock);
+        spawn_blocking(move || {
+            let _guard = parts_lock.blocking_read();
+            query_sensor_directory(&sensor_directory, start_us, end_us, limit)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::SingleSensorBatch, sensapp_datetime::SensAppDateTimeExt, Sample, TypedSamples,
+    };
+    use smallvec::smallvec;
+
+    #[test]
+    fn test_partition_names() {
+        assert_eq!(day_to_partition_name(0), "1970-01-01");
+        assert_eq!(day_to_partition_name(19675), "2023-11-14");
+        assert_eq!(partition_name_to_day("2023-11-14"), Some(19675));
+        assert_eq!(partition_name_to_day("2023-11-14.parquet"), Some(19675));
+        assert_eq!(partition_name_to_day("2023-11-14.parquet.tmp"), None);
+        assert_eq!(partition_name_to_day("sensor.json"), None);
+    }
+
+    #[tokio::test]
+    async fn test_publish_and_query_across_partitions() {
+        _ = load_configuration();
+        let directory = std::env::temp_dir().join(format!("sensapp-parquet-{}", Uuid::new_v4()));
+        let storage = ParquetStorage::connect(&format!("parquet://{}", directory.display()))
+            .await
+            .unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                "parquet_temperature".to_string(),
+                SensorType::Float,
+                Some(Unit::new("Cel".to_string(), None)),
+                Some(smallvec![("room".to_string(), "kitchen".to_string())]),
+            )
+            .unwrap(),
+        );
+
+        // 2023-11-14T22:13:20Z, every hour for 6 hours, so over two days.
+        // Published in two batches, so the second day has two parts.
+        let start = 1_700_000_000;
+        for hours in [[3, 4, 5], [0, 1, 2]] {
+            let samples = TypedSamples::Float(
+                hours
+                    .iter()
+                    .map(|hour| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(start + hour * 3600),
+                        value: *hour as f64,
+                    })
+                    .collect(),
+            );
+            let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+            let (sync_sender, _) = async_broadcast::broadcast(1);
+            storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+        }
+
+        let sensor_directory = directory.join(sensor.uuid.to_string());
+        let parts = |day: &str| list_parts(&sensor_directory.join(day)).unwrap().len();
+        assert_eq!(parts("2023-11-14"), 1);
+        assert_eq!(parts("2023-11-15"), 2);
+
+        // From 23:13:20 the first day to 02:13:20 (exclusive) the second day
+        let sensor_data = storage
+            .query_sensor_data(
+                sensor.uuid,
+                Some(SensAppDateTime::from_unix_seconds_i64(start + 3600)),
+                Some(SensAppDateTime::from_unix_seconds_i64(start + 4 * 3600)),
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.sensor.name, "parquet_temperature");
+        assert_eq!(sensor_data.sensor.unit.as_ref().unwrap().name, "Cel");
+        assert_eq!(sensor_data.sensor.labels, sensor.labels);
+        match &sensor_data.samples {
+            TypedSamples::Float(samples) => {
+                assert_eq!(
+                    samples.iter().map(|s| s.value).collect::<Vec<_>>(),
+                    vec![1.0, 2.0, 3.0]
+                );
+                assert_eq!(
+                    samples[0].datetime,
+                    SensAppDateTime::from_unix_seconds_i64(start + 3600)
+                );
+            }
+            _ => panic!("Expected float samples"),
+        }
+
+        let sensor_data = storage
+            .query_sensor_data(sensor.uuid, None, None, Some(4))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.samples.len(), 4);
+
+        // A known sensor without samples in the time range
+        let sensor_data = storage
+            .query_sensor_data(
+                sensor.uuid,
+                Some(SensAppDateTime::from_unix_seconds_i64(1_800_000_000)),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.sensor.uuid, sensor.uuid);
+        assert_eq!(sensor_data.samples, TypedSamples::Float(smallvec![]));
+
+        assert!(storage
+            .query_sensor_data(Uuid::new_v4(), None, None, None)
+            .await
+            .unwrap()
+            .is_none());
+
+        assert_eq!(
+            storage.list_sensors().await.unwrap(),
+            vec!["parquet_temperature".to_string()]
+        );
+
+        // The vacuum merges the parts into the day files,
+        // without changing the samples
+        let before_vacuum = storage
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        storage.vacuum().await.unwrap();
+        assert_eq!(parts("2023-11-15"), 0);
+        assert!(sensor_directory.join("2023-11-14.parquet").is_file());
+        assert!(sensor_directory.join("2023-11-15.parquet").is_file());
+        assert!(!sensor_directory.join("2023-11-15").exists());
+        let after_vacuum = storage
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(after_vacuum.samples, before_vacuum.samples);
+        assert_eq!(after_vacuum.samples.len(), 6);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_compaction() {
+        _ = load_configuration();
+        let directory = std::env::temp_dir().join(format!("sensapp-parquet-{}", Uuid::new_v4()));
+        let storage = ParquetStorage::connect(&format!("parquet://{}", directory.display()))
+            .await
+            .unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                "parquet_compaction".to_string(),
+                SensorType::Float,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let publish = |seconds: Vec<i64>| {
+            let samples = TypedSamples::Float(
+                seconds
+                    .iter()
+                    .map(|second| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + second),
+                        value: *second as f64,
+                    })
+                    .collect(),
+            );
+            let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+            storage.publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+        };
+        let count = || async {
+            storage
+                .query_sensor_data(sensor.uuid, None, None, None)
+                .await
+                .unwrap()
+                .unwrap()
+                .samples
+                .len()
+        };
+        publish(vec![0, 1]).await.unwrap();
+        publish(vec![2, 3]).await.unwrap();
+
+        // Interrupted after writing the day file, before removing the parts
+        let partition = directory.join(sensor.uuid.to_string()).join("2023-11-14");
+        let schema = ArrowConverter::schema(SensorType::Float);
+        merge_partition(&partition, &schema).unwrap();
+        assert_eq!(list_parts(&partition).unwrap().len(), 2);
+        assert_eq!(count().await, 4);
+
+        // The parts published after are still read, and merged later
+        publish(vec![4]).await.unwrap();
+        assert_eq!(count().await, 5);
+        storage.vacuum().await.unwrap();
+        assert!(!partition.exists());
+        assert_eq!(count().await, 5);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use crate::storage::query::{LabelMatcher, LabelMatcherType, NAME_LABEL};
+use sqlx::{Postgres, QueryBuilder};
+
+/// Adds the exact label matchers to a query on the `sensors` table.
+///
+/// Only the `=` matchers with a value are pushed down to PostgreSQL,
+/// as they can use the indexes of the dictionaries and of the labels.
+/// The columns are compared as they are, without functions,
+/// so the indexes stay usable. The other matchers, and the `=`
+/// matchers on an empty value that also match the missing labels,
+/// must still be checked on the loaded sensors.
+pub fn push_exact_label_matchers(
+    query_builder: &mut QueryBuilder<'_, Postgres>,
+    matchers: &[LabelMatcher],
+) {
+    for matcher in matchers {
+        if matcher.matcher_type != LabelMatcherType::Equal || matcher.value.is_empty() {
+            continue;
+        }
+        if matcher.name == NAME_LABEL {
+            query_builder.push(" AND sensors.name = ");
+            query_builder.push_bind(matcher.value.clone());
+            continue;
+        }
+        query_builder.push(
+            r#" AND sensors.sensor_id IN (
+            SELECT labels.sensor_id
+            FROM labels
+            JOIN labels_name_dictionary ON labels.name = labels_name_dictionary.id
+            JOIN labels_description_dictionary ON labels.description = labels_description_dictionary.id
+            WHERE labels_name_dictionary.name = "#,
+        );
+        query_builder.push_bind(matcher.name.clone());
+        query_builder.push(" AND labels_description_dictionary.description = ");
+        query_builder.push_bind(matcher.value.clone());
+        query_builder.push(")");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Execute, PgPool, Row};
+
+    fn matchers() -> Vec<LabelMatcher> {
+        vec![
+            LabelMatcher::new(
+                "room".to_string(),
+                "kitchen".to_string(),
+                LabelMatcherType::Equal,
+            ),
+            LabelMatcher::new("floor".to_string(), "".to_string(), LabelMatcherType::Equal),
+            LabelMatcher::new(
+                "zone".to_string(),
+                "north".to_string(),
+                LabelMatcherType::NotEqual,
+            ),
+            LabelMatcher::new(
+                NAME_LABEL.to_string(),
+                "temperature".to_string(),
+                LabelMatcherType::Equal,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_push_exact_label_matchers() {
+        let mut query_builder =
+            QueryBuilder::<Postgres>::new("SELECT sensors.sensor_id FROM sensors WHERE 1 = 1");
+        push_exact_label_matchers(&mut query_builder, &matchers());
+        let mut query = query_builder.build();
+        let sql = query.sql().to_string();
+
+        // The empty and the not equal matchers aren't pushed down
+        assert_eq!(sql.matches("sensors.sensor_id IN (").count(), 1);
+        assert!(sql.contains("WHERE labels_name_dictionary.name = $1"));
+        assert!(sql.contains("labels_description_dictionary.description = $2"));
+        assert!(sql.contains("AND sensors.name = $3"));
+        assert!(!sql.to_lowercase().contains("lower("));
+        assert!(query.take_arguments().is_some());
+    }
+
+    /// Checks with `EXPLAIN` that an exact label query uses the indexes.
+    ///
+    /// It needs a PostgreSQL database with the SensApp schema:
+    /// `SENSAPP_TEST_POSTGRES_CONNECTION_STRING=postgres://... cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_exact_label_query_uses_indexes() {
+        let connection_string = std::env::var("SENSAPP_TEST_POSTGRES_CONNECTION_STRING")
+            .expect("SENSAPP_TEST_POSTGRES_CONNECTION_STRING is not set");
+        let pool = PgPool::connect(&connection_string).await.unwrap();
+        sqlx::migrate!("src/storage/postgresql/migrations")
+            .run(&pool)
+            .await
+            .unwrap();
+
+        let mut transaction = pool.begin().await.unwrap();
+        // Sequential scans are cheaper on small tables, so they are
+        // disabled to check that the indexes can serve the query.
+        sqlx::query("SET LOCAL enable_seqscan = off")
+            .execute(&mut *transaction)
+            .await
+            .unwrap();
+
+        let mut query_builder = QueryBuilder::<Postgres>::new(
+            "EXPLAIN SELECT sensors.sensor_id FROM sensors WHERE 1 = 1",
+        );
+        push_exact_label_matchers(&mut query_builder, &matchers()[..1]);
+        let plan = query_builder
+            .build()
+            .fetch_all(&mut *transaction)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get::<String, _>(0))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        assert!(!plan.contains("Seq Scan"), "{}", plan);
+        assert!(plan.contains("labels_name_dictionary_name"), "{}", plan);
+        assert!(plan.contains("index_labels_name_description"), "{}", plan);
+    }
+}
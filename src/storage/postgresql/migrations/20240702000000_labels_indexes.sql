@@ -0,0 +1,6 @@
+-- Indexes of the label queries, from the dictionaries to the sensors.
+-- The dictionaries are already indexed by their unique constraints.
+CREATE INDEX IF NOT EXISTS index_labels_name_description ON labels USING btree (name, description);
+CREATE INDEX IF NOT EXISTS index_labels_description ON labels USING btree (description);
+CREATE INDEX IF NOT EXISTS index_labels_name_dictionary_name ON labels_name_dictionary USING btree (name);
+CREATE INDEX IF NOT EXISTS index_sensors_name ON sensors USING btree (name);
@@ -0,0 +1,12 @@
+-- Create the 'annotations' table, for the events on the timeline of a sensor
+CREATE TABLE annotations (
+    id BIGSERIAL PRIMARY KEY,
+    sensor_id BIGINT NOT NULL,
+    timestamp_ms BIGINT NOT NULL,
+    title TEXT NOT NULL,
+    text TEXT,
+    tags TEXT[] NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+CREATE INDEX index_annotations ON annotations USING btree (sensor_id, timestamp_ms);
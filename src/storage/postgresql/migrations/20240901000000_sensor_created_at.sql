@@ -0,0 +1,2 @@
+-- When the sensors were first stored.
+ALTER TABLE sensors ADD COLUMN created_at TIMESTAMPTZ DEFAULT now();
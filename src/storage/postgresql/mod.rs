@@ -1,5 +1,7 @@
+pub mod matchers;
 pub mod postgresql;
 pub mod postgresql_publishers;
+pub mod postgresql_queries;
 pub mod postgresql_utilities;
 
 pub use postgresql::PostgresStorage;
@@ -1,19 +1,51 @@
 use super::{
-    super::storage::StorageInstance, postgresql_publishers::*,
-    postgresql_utilities::get_sensor_id_or_create_sensor,
+    super::storage::StorageInstance,
+    postgresql_publishers::*,
+    postgresql_queries::{
+        aggregate_samples, count_samples, delete_metric, delete_samples_older_than, get_sensor_id,
+        list_sensors, query_annotations, query_latest_samples, query_raw_sql,
+        query_samples_of_sensors, verify_samples,
+    },
+    postgresql_utilities::{
+        clear_caches, get_sensor_id_or_create_sensor, touch_sensor, update_sensor_metadata,
+    },
 };
-use crate::datamodel::{batch::Batch, TypedSamples};
+use crate::datamodel::{
+    batch::{Batch, SingleSensorBatch},
+    sensor_metadata::SensorMetadata,
+    Annotation, SensAppDateTime, Sensor, SensorData, SensorType, TypedSamples,
+};
+use crate::storage::blob_compression::BlobCompression;
+use crate::storage::query::{query_samples_in_batches, AggregateBucket, SensorSelector, TimeRange};
+use crate::storage::raw_sql::{validate_read_only_select, RawSqlResult};
+use crate::storage::retry::{with_retries, RetryOptions};
+use crate::storage::schema::{check_schema, enabled_sample_types, MIGRATIONS_TABLE, VALUE_TABLES};
+use crate::storage::strict_sensors::strict_sensors;
+use crate::storage::type_conflict::{coerce_samples, type_conflict_policy, TypeConflictPolicy};
+use crate::storage::verify::VerifyReport;
 use anyhow::{Context, Result};
 use async_broadcast::Sender;
 use async_trait::async_trait;
 use sqlx::{postgres::PgConnectOptions, PgPool};
+use std::collections::HashMap;
 use std::time::Duration;
 use std::{str::FromStr, sync::Arc};
 use tokio::time::timeout;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct PostgresStorage {
     pool: PgPool,
+    /// Sensors per transaction when publishing, 0 for the whole batch.
+    sensors_per_transaction: usize,
+    retry_options: RetryOptions,
+    blob_compression: BlobCompression,
+    /// Rejects the samples of the unknown sensors instead of creating them.
+    strict_sensors: bool,
+    /// For the sensors of an existing name and labels, but of another type.
+    type_conflict_policy: TypeConflictPolicy,
+    /// Only their value tables exist once migrated.
+    enabled_sample_types: Vec<SensorType>,
 }
 
 impl PostgresStorage {
@@ -25,7 +57,64 @@ impl PostgresStorage {
             .await
             .context("Failed to create postgres pool")?;
 
-        Ok(Self { pool })
+        let sensors_per_transaction = crate::config::get()
+            .map(|config| config.publish_sensors_per_tx)
+            .unwrap_or_default();
+
+        Ok(Self {
+            pool,
+            sensors_per_transaction,
+            retry_options: RetryOptions::from_config(),
+            blob_compression: BlobCompression::from_config()?,
+            strict_sensors: strict_sensors(),
+            type_conflict_policy: type_conflict_policy(),
+            enabled_sample_types: enabled_sample_types()?,
+        })
+    }
+
+    /// Creates the value tables of the enabled sample types, and drops
+    /// the empty ones of the disabled types, as the migrations create
+    /// them all. The value tables with samples are kept.
+    async fn sync_value_tables(&self) -> Result<()> {
+        let tables: Vec<String> = sqlx::query_scalar(
+            "SELECT table_name::TEXT FROM information_schema.tables WHERE table_schema = current_schema()",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for (sensor_type, table) in VALUE_TABLES {
+            if self.enabled_sample_types.contains(&sensor_type) {
+                sqlx::raw_sql(value_table_ddl(sensor_type))
+                    .execute(&self.pool)
+                    .await
+                    .with_context(|| format!("Failed to create the {} table", table))?;
+            } else if tables.iter().any(|name| name == table) {
+                let has_samples: bool =
+                    sqlx::query_scalar(&format!("SELECT EXISTS (SELECT 1 FROM {})", table))
+                        .fetch_one(&self.pool)
+                        .await?;
+                if !has_samples {
+                    sqlx::query(&format!("DROP TABLE {}", table))
+                        .execute(&self.pool)
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The schema of the value table of the sample type,
+/// with the changes of all the migrations.
+fn value_table_ddl(sensor_type: SensorType) -> &'static str {
+    match sensor_type {
+        SensorType::Integer => include_str!("value_tables/integer_values.sql"),
+        SensorType::Numeric => include_str!("value_tables/numeric_values.sql"),
+        SensorType::Float => include_str!("value_tables/float_values.sql"),
+        SensorType::String => include_str!("value_tables/string_values.sql"),
+        SensorType::Boolean => include_str!("value_tables/boolean_values.sql"),
+        SensorType::Location => include_str!("value_tables/location_values.sql"),
+        SensorType::Json => include_str!("value_tables/json_values.sql"),
+        SensorType::Blob => include_str!("value_tables/blob_values.sql"),
     }
 }
 
@@ -36,16 +125,37 @@ impl StorageInstance for PostgresStorage {
             .run(&self.pool)
             .await
             .context("Failed to migrate database")?;
+        self.sync_value_tables().await?;
 
         Ok(())
     }
+
+    async fn check_schema(&self) -> Result<()> {
+        let tables: Vec<String> = sqlx::query_scalar(
+            "SELECT table_name::TEXT FROM information_schema.tables WHERE table_schema = current_schema()",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let applied_version = if tables.iter().any(|table| table == MIGRATIONS_TABLE) {
+            sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+                .fetch_one(&self.pool)
+                .await?
+        } else {
+            None
+        };
+        check_schema(
+            &sqlx::migrate!("src/storage/postgresql/migrations"),
+            applied_version,
+            &tables,
+            &self.enabled_sample_types,
+        )
+    }
     async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
-        let mut transaction = self.pool.begin().await?;
-        for single_sensor_batch in batch.sensors.as_ref() {
-            self.publish_single_sensor_batch(&mut transaction, single_sensor_batch)
-                .await?;
+        // The chunks are committed one by one, so a failure
+        // keeps the previous chunks of the batch.
+        for sensors in batch.sensor_chunks(self.sensors_per_transaction) {
+            with_retries(self.retry_options, || self.publish_transaction(sensors)).await?;
         }
-        transaction.commit().await?;
         self.sync(sync_sender).await?;
         Ok(())
     }
@@ -67,19 +177,267 @@ impl StorageInstance for PostgresStorage {
     async fn list_sensors(&self) -> Result<Vec<String>> {
         unimplemented!();
     }
+
+    async fn health_check(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_sensor(&self, sensor_uuid: Uuid) -> Result<Option<Sensor>> {
+        Ok(list_sensors(&self.pool, Some(&[sensor_uuid]), None, &[])
+            .await?
+            .into_iter()
+            .next()
+            .map(|(_, sensor)| sensor))
+    }
+
+    async fn update_sensor_metadata(
+        &self,
+        sensor_uuid: Uuid,
+        metadata: &SensorMetadata,
+    ) -> Result<bool> {
+        update_sensor_metadata(&self.pool, sensor_uuid, metadata).await
+    }
+
+    async fn apply_retention(&self, older_than: SensAppDateTime) -> Result<()> {
+        delete_samples_older_than(&self.pool, older_than, &self.enabled_sample_types).await
+    }
+
+    async fn delete_metric(&self, name: &str) -> Result<u64> {
+        let deleted = delete_metric(&self.pool, name, &self.enabled_sample_types).await?;
+        // The cached sensor identifiers may point to the deleted sensors.
+        clear_caches().await;
+        Ok(deleted)
+    }
+
+    async fn unknown_sensors(&self, sensor_uuids: &[Uuid]) -> Result<Vec<Uuid>> {
+        let known_uuids = list_sensors(&self.pool, Some(sensor_uuids), None, &[])
+            .await?
+            .into_iter()
+            .map(|(_, sensor)| sensor.uuid)
+            .collect::<Vec<_>>();
+        Ok(sensor_uuids
+            .iter()
+            .filter(|uuid| !known_uuids.contains(uuid))
+            .copied()
+            .collect())
+    }
+
+    async fn latest_samples_all(&self, metric_filter: Option<&str>) -> Result<Vec<SensorData>> {
+        let mut latest_samples = Vec::new();
+        for &sensor_type in &self.enabled_sample_types {
+            latest_samples
+                .extend(query_latest_samples(&self.pool, sensor_type, metric_filter).await?);
+        }
+        latest_samples.sort_by_key(|(sensor_id, _)| *sensor_id);
+
+        let mut sensors = list_sensors(&self.pool, None, None, &[])
+            .await?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        Ok(latest_samples
+            .into_iter()
+            .filter_map(|(sensor_id, samples)| {
+                sensors
+                    .remove(&sensor_id)
+                    .map(|sensor| SensorData::new(sensor, samples))
+            })
+            .collect())
+    }
+
+    async fn count_series_by_labels(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+    ) -> Result<Vec<(Sensor, u64)>> {
+        let numeric_types = [SensorType::Integer, SensorType::Numeric, SensorType::Float];
+        let sensor_types = selector.numeric_only.then_some(&numeric_types[..]);
+        let sensors = list_sensors(
+            &self.pool,
+            selector.uuids.as_deref(),
+            sensor_types,
+            &selector.matchers,
+        )
+        .await?;
+
+        let mut results = Vec::new();
+        for (sensor_id, sensor) in sensors {
+            if !selector.matches(&sensor)? {
+                continue;
+            }
+            let count = count_samples(
+                &self.pool,
+                sensor_id,
+                sensor.sensor_type,
+                time_range.start,
+                time_range.end,
+            )
+            .await?;
+            results.push((sensor, count));
+        }
+        Ok(results)
+    }
+
+    async fn query(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorData>> {
+        // The UUIDs, the types and the exact label matchers are filtered
+        // by PostgreSQL, and the other matchers on the loaded sensors.
+        let numeric_types = [SensorType::Integer, SensorType::Numeric, SensorType::Float];
+        let sensor_types = selector.numeric_only.then_some(&numeric_types[..]);
+        let sensors = list_sensors(
+            &self.pool,
+            selector.uuids.as_deref(),
+            sensor_types,
+            &selector.matchers,
+        )
+        .await?;
+
+        let mut matching_sensors = Vec::with_capacity(sensors.len());
+        for (sensor_id, sensor) in sensors {
+            if selector.matches(&sensor)? {
+                matching_sensors.push((sensor_id, sensor));
+            }
+        }
+
+        let pool = &self.pool;
+        query_samples_in_batches(matching_sensors, |sensor_ids, sensor_type| async move {
+            query_samples_of_sensors(
+                pool,
+                &sensor_ids,
+                sensor_type,
+                time_range.start,
+                time_range.end,
+                limit,
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn aggregate_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+        step: hifitime::Duration,
+    ) -> Result<Option<Vec<AggregateBucket>>> {
+        let (sensor_id, sensor) = match list_sensors(&self.pool, Some(&[sensor_uuid]), None, &[])
+            .await?
+            .into_iter()
+            .next()
+        {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let step_ms = (step.total_nanoseconds() / 1_000_000).max(1) as i64;
+        let buckets = aggregate_samples(
+            &self.pool,
+            sensor_id,
+            sensor.sensor_type,
+            time_range.start,
+            time_range.end,
+            step_ms,
+        )
+        .await?;
+        Ok(Some(buckets))
+    }
+
+    async fn verify_sensor(&self, sensor_uuid: Uuid) -> Result<Option<VerifyReport>> {
+        let sensor = list_sensors(&self.pool, Some(&[sensor_uuid]), None, &[])
+            .await?
+            .into_iter()
+            .next();
+        let (sensor_id, sensor) = match sensor {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let report = verify_samples(&self.pool, sensor_id, sensor.sensor_type).await?;
+        Ok(Some(report))
+    }
+
+    async fn add_annotation(&self, sensor_uuid: Uuid, annotation: &Annotation) -> Result<bool> {
+        let sensor_id = match get_sensor_id(&self.pool, sensor_uuid).await? {
+            Some(sensor_id) => sensor_id,
+            None => return Ok(false),
+        };
+        let mut transaction = self.pool.begin().await?;
+        publish_annotation(&mut transaction, sensor_id, annotation).await?;
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    async fn query_annotations(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+    ) -> Result<Option<Vec<Annotation>>> {
+        let sensor_id = match get_sensor_id(&self.pool, sensor_uuid).await? {
+            Some(sensor_id) => sensor_id,
+            None => return Ok(None),
+        };
+        let annotations =
+            query_annotations(&self.pool, sensor_id, time_range.start, time_range.end).await?;
+        Ok(Some(annotations))
+    }
+
+    async fn raw_sql_query(
+        &self,
+        sql: &str,
+        max_rows: usize,
+        timeout: Duration,
+    ) -> Result<RawSqlResult> {
+        let sql = validate_read_only_select(sql)?;
+        query_raw_sql(&self.pool, sql, max_rows, timeout).await
+    }
 }
 
 impl PostgresStorage {
+    /// Publishes the sensors in a single transaction.
+    async fn publish_transaction(&self, sensors: &[SingleSensorBatch]) -> Result<()> {
+        let result = async {
+            let mut transaction = self.pool.begin().await?;
+            for single_sensor_batch in sensors {
+                self.publish_single_sensor_batch(&mut transaction, single_sensor_batch)
+                    .await?;
+            }
+            transaction.commit().await?;
+            Ok(())
+        }
+        .await;
+        if result.is_err() {
+            clear_caches().await;
+        }
+        result
+    }
+
     async fn publish_single_sensor_batch(
         &self,
         transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
         single_sensor_batch: &crate::datamodel::batch::SingleSensorBatch,
     ) -> Result<()> {
-        let sensor_id =
-            get_sensor_id_or_create_sensor(transaction, &single_sensor_batch.sensor).await?;
+        let sensor = &single_sensor_batch.sensor;
+        let (sensor_id, sensor_type) = get_sensor_id_or_create_sensor(
+            transaction,
+            sensor,
+            self.strict_sensors,
+            self.type_conflict_policy,
+        )
+        .await?;
+        touch_sensor(transaction, sensor_id).await?;
 
         let samples_guard = single_sensor_batch.samples.read().await;
-        match &*samples_guard {
+        // Published to an existing sensor of another type, by the coerce policy.
+        let coerced_samples;
+        let samples = if sensor_type == sensor.sensor_type {
+            &*samples_guard
+        } else {
+            coerced_samples = coerce_samples(&sensor.name, &samples_guard, sensor_type)?;
+            &coerced_samples
+        };
+        match samples {
             TypedSamples::Integer(values) => {
                 publish_integer_values(transaction, sensor_id, values).await?;
             }
@@ -99,7 +457,7 @@ impl PostgresStorage {
                 publish_location_values(transaction, sensor_id, values).await?;
             }
             TypedSamples::Blob(values) => {
-                publish_blob_values(transaction, sensor_id, values).await?;
+                publish_blob_values(transaction, sensor_id, values, self.blob_compression).await?;
             }
             TypedSamples::Json(values) => {
                 publish_json_values(transaction, sensor_id, values).await?;
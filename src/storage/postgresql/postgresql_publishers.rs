@@ -1,5 +1,6 @@
 use super::postgresql_utilities::get_string_value_id_or_create;
-use crate::datamodel::Sample;
+use crate::datamodel::{Annotation, Sample};
+use crate::storage::blob_compression::BlobCompression;
 use anyhow::Result;
 use sqlx::{prelude::*, Postgres, Transaction};
 
@@ -138,9 +139,11 @@ pub async fn publish_blob_values(
     transaction: &mut Transaction<'_, Postgres>,
     sensor_id: i64,
     values: &[Sample<Vec<u8>>],
+    blob_compression: BlobCompression,
 ) -> Result<()> {
     for value in values {
         let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
+        let stored_value = blob_compression.compress(&value.value)?.into_owned();
         let query = sqlx::query(
             r#"
             INSERT INTO blob_values (sensor_id, timestamp_ms, value)
@@ -149,7 +152,7 @@ pub async fn publish_blob_values(
         )
         .bind(sensor_id)
         .bind(timestamp_ms)
-        .bind(value.value.clone());
+        .bind(stored_value);
         transaction.execute(query).await?;
     }
     Ok(())
@@ -176,3 +179,24 @@ pub async fn publish_json_values(
     }
     Ok(())
 }
+
+pub async fn publish_annotation(
+    transaction: &mut Transaction<'_, Postgres>,
+    sensor_id: i64,
+    annotation: &Annotation,
+) -> Result<()> {
+    let timestamp_ms = annotation.datetime.to_unix_milliseconds().floor() as i64;
+    let query = sqlx::query(
+        r#"
+        INSERT INTO annotations (sensor_id, timestamp_ms, title, text, tags)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(timestamp_ms)
+    .bind(&annotation.title)
+    .bind(&annotation.text)
+    .bind(&annotation.tags);
+    transaction.execute(query).await?;
+    Ok(())
+}
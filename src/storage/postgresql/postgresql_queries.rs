@@ -0,0 +1,797 @@
+use super::matchers::push_exact_label_matchers;
+use crate::datamodel::{
+    downsampling_policy::DownsamplingPolicy, sensapp_datetime::SensAppDateTimeExt,
+    sensapp_vec::SensAppLabels, unit::Unit, Annotation, Sample, SensAppDateTime, Sensor,
+    SensorType, TypedSamples,
+};
+use crate::storage::blob_compression::decompress_blob;
+use crate::storage::query::{AggregateBucket, InvalidQueryError, LabelMatcher, SensorSelector};
+use crate::storage::raw_sql::{RawSqlResult, RawSqlTimeoutError};
+use crate::storage::verify::VerifyReport;
+use anyhow::{bail, Result};
+use futures::TryStreamExt;
+use serde_json::Value;
+use sqlx::{postgres::PgRow, Column, Executor, PgPool, Postgres, QueryBuilder, Row};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Returns the internal sensor_id and the sensors, optionally restricted
+/// to a set of UUIDs and a set of sensor types.
+///
+/// The exact label matchers are filtered by PostgreSQL, the other
+/// matchers must be checked on the returned sensors.
+pub async fn list_sensors(
+    pool: &PgPool,
+    uuids: Option<&[Uuid]>,
+    sensor_types: Option<&[SensorType]>,
+    matchers: &[LabelMatcher],
+) -> Result<Vec<(i64, Sensor)>> {
+    let mut query_builder = QueryBuilder::<Postgres>::new(
+        r#"
+        SELECT sensors.sensor_id, sensors.uuid, sensors.name, sensors.type,
+            units.name AS unit_name, units.description AS unit_description,
+            sensors.min_value, sensors.max_value, sensors.nominal_interval_seconds,
+            sensors.raw_retention_days, sensors.rollup_interval_seconds,
+            (EXTRACT(EPOCH FROM sensors.created_at) * 1000000)::BIGINT AS created_at,
+            (EXTRACT(EPOCH FROM sensors.last_seen) * 1000000)::BIGINT AS last_seen
+        FROM sensors
+        LEFT JOIN units ON sensors.unit = units.id
+        WHERE 1 = 1"#,
+    );
+    if let Some(uuids) = uuids {
+        if uuids.is_empty() {
+            return Ok(Vec::new());
+        }
+        query_builder.push(" AND sensors.uuid = ANY(");
+        query_builder.push_bind(uuids.to_vec());
+        query_builder.push(")");
+    }
+    if let Some(sensor_types) = sensor_types {
+        if sensor_types.is_empty() {
+            return Ok(Vec::new());
+        }
+        query_builder.push(" AND sensors.type = ANY(");
+        query_builder.push_bind(
+            sensor_types
+                .iter()
+                .map(|sensor_type| sensor_type.to_string())
+                .collect::<Vec<_>>(),
+        );
+        query_builder.push(")");
+    }
+    push_exact_label_matchers(&mut query_builder, matchers);
+    query_builder.push(" ORDER BY sensors.sensor_id");
+    let sensor_rows = query_builder.build().fetch_all(pool).await?;
+
+    let mut labels: HashMap<i64, SensAppLabels> = HashMap::new();
+    let label_rows = sqlx::query(
+        r#"
+        SELECT labels.sensor_id, labels_name_dictionary.name, labels_description_dictionary.description
+        FROM labels
+        JOIN labels_name_dictionary ON labels.name = labels_name_dictionary.id
+        LEFT JOIN labels_description_dictionary ON labels.description = labels_description_dictionary.id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    for row in label_rows {
+        let description: Option<String> = row.try_get("description")?;
+        labels
+            .entry(row.try_get("sensor_id")?)
+            .or_default()
+            .push((row.try_get("name")?, description.unwrap_or_default()));
+    }
+
+    sensor_rows
+        .into_iter()
+        .map(|row| {
+            let sensor_id: i64 = row.try_get("sensor_id")?;
+            let sensor_type = SensorType::from_str(row.try_get("type")?)?;
+            let unit_name: Option<String> = row.try_get("unit_name")?;
+            let unit = unit_name.map(|name| Unit::new(name, row.get("unit_description")));
+            let sensor = Sensor::new(
+                row.try_get("uuid")?,
+                row.try_get("name")?,
+                sensor_type,
+                unit,
+                labels.remove(&sensor_id),
+            )
+            .with_value_range(row.try_get("min_value")?, row.try_get("max_value")?)
+            .with_nominal_interval(row.try_get("nominal_interval_seconds")?)
+            .with_downsampling(DownsamplingPolicy::from_columns(
+                row.try_get("raw_retention_days")?,
+                row.try_get("rollup_interval_seconds")?,
+            ))
+            .with_sensor_id(sensor_id)
+            .with_created_at(
+                row.try_get::<Option<i64>, _>("created_at")?
+                    .map(SensAppDateTime::from_unix_microseconds_i64),
+            )
+            .with_last_seen(
+                row.try_get::<Option<i64>, _>("last_seen")?
+                    .map(SensAppDateTime::from_unix_microseconds_i64),
+            );
+            Ok((sensor_id, sensor))
+        })
+        .collect()
+}
+
+/// Returns the internal sensor_id of the sensor with the given UUID, if it exists.
+pub async fn get_sensor_id(pool: &PgPool, uuid: Uuid) -> Result<Option<i64>> {
+    let sensor_id = sqlx::query_scalar("SELECT sensor_id FROM sensors WHERE uuid = $1")
+        .bind(uuid)
+        .fetch_optional(pool)
+        .await?;
+    Ok(sensor_id)
+}
+
+/// Returns the annotations of a sensor, sorted by datetime,
+/// with an inclusive start and an exclusive end.
+pub async fn query_annotations(
+    pool: &PgPool,
+    sensor_id: i64,
+    start: Option<SensAppDateTime>,
+    end: Option<SensAppDateTime>,
+) -> Result<Vec<Annotation>> {
+    // Rounded up, as the timestamps are in whole milliseconds
+    let start_ms = start
+        .map(|start| start.to_unix_milliseconds().ceil() as i64)
+        .unwrap_or(i64::MIN);
+    let end_ms = end
+        .map(|end| end.to_unix_milliseconds().ceil() as i64)
+        .unwrap_or(i64::MAX);
+    let rows = sqlx::query(
+        r#"
+        SELECT timestamp_ms, title, text, tags FROM annotations
+        WHERE sensor_id = $1 AND timestamp_ms >= $2 AND timestamp_ms < $3
+        ORDER BY timestamp_ms ASC, id ASC
+        "#,
+    )
+    .bind(sensor_id)
+    .bind(start_ms)
+    .bind(end_ms)
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(Annotation::new(
+                SensAppDateTime::from_unix_milliseconds_i64(row.try_get("timestamp_ms")?),
+                row.try_get("title")?,
+                row.try_get("text")?,
+                row.try_get("tags")?,
+            ))
+        })
+        .collect()
+}
+
+/// Returns the table of the samples of the sensor type.
+///
+/// The table names come from the sensor type, not from the user,
+/// so they can be used in the queries.
+pub fn values_table(sensor_type: SensorType) -> &'static str {
+    match sensor_type {
+        SensorType::Integer => "integer_values",
+        SensorType::Numeric => "numeric_values",
+        SensorType::Float => "float_values",
+        SensorType::String => "string_values",
+        SensorType::Boolean => "boolean_values",
+        SensorType::Location => "location_values",
+        SensorType::Json => "json_values",
+        SensorType::Blob => "blob_values",
+    }
+}
+
+/// Returns the value columns of the samples table under the alias,
+/// and the join to decode the strings.
+///
+/// The TimescaleDB samples tables have the same value columns.
+pub fn value_columns(sensor_type: SensorType, alias: &str) -> (String, String) {
+    match sensor_type {
+        SensorType::Numeric => (format!("{}.value::TEXT AS value", alias), String::new()),
+        SensorType::String => (
+            "strings_values_dictionary.value".to_string(),
+            format!(
+                "JOIN strings_values_dictionary ON {}.value = strings_values_dictionary.id",
+                alias
+            ),
+        ),
+        SensorType::Location => (format!("{0}.latitude, {0}.longitude", alias), String::new()),
+        _ => (format!("{}.value", alias), String::new()),
+    }
+}
+
+/// Decodes the samples rows selected with [`value_columns`],
+/// in the order of the rows, with the datetime read by `datetime`.
+pub fn decode_samples(
+    sensor_type: SensorType,
+    rows: &[PgRow],
+    datetime: fn(&PgRow) -> Result<SensAppDateTime>,
+) -> Result<TypedSamples> {
+    Ok(match sensor_type {
+        SensorType::Integer => TypedSamples::Integer(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get("value")?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Numeric => TypedSamples::Numeric(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: rust_decimal::Decimal::from_str(row.try_get("value")?)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Float => TypedSamples::Float(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get("value")?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::String => TypedSamples::String(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get("value")?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Boolean => TypedSamples::Boolean(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get("value")?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Location => TypedSamples::Location(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: geo::Point::new(row.try_get("longitude")?, row.try_get("latitude")?),
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Json => TypedSamples::Json(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get("value")?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Blob => TypedSamples::Blob(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: decompress_blob(row.try_get("value")?)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+/// Decodes the samples rows of several sensors, selected with
+/// [`value_columns`] and a `sensor_id` column, in the order of the rows.
+///
+/// Every sensor_id has its samples, empty when the sensor has none.
+pub fn decode_samples_of_sensors(
+    sensor_type: SensorType,
+    sensor_ids: &[i64],
+    rows: Vec<PgRow>,
+    datetime: fn(&PgRow) -> Result<SensAppDateTime>,
+) -> Result<HashMap<i64, TypedSamples>> {
+    let mut rows_by_sensor: HashMap<i64, Vec<PgRow>> = sensor_ids
+        .iter()
+        .map(|sensor_id| (*sensor_id, Vec::new()))
+        .collect();
+    for row in rows {
+        rows_by_sensor
+            .entry(row.try_get("sensor_id")?)
+            .or_default()
+            .push(row);
+    }
+    rows_by_sensor
+        .into_iter()
+        .map(|(sensor_id, rows)| Ok((sensor_id, decode_samples(sensor_type, &rows, datetime)?)))
+        .collect()
+}
+
+/// Reads the datetime of the `timestamp_ms` column.
+fn timestamp_ms_datetime(row: &PgRow) -> Result<SensAppDateTime> {
+    Ok(SensAppDateTime::from_unix_milliseconds_i64(
+        row.try_get("timestamp_ms")?,
+    ))
+}
+
+/// Returns the most recent sample of each sensor of the type,
+/// with the internal sensor_id of the sensor.
+///
+/// The sensors can be restricted to a name, and the sensors without
+/// samples are left out. One query per type, with `DISTINCT ON`.
+pub async fn query_latest_samples(
+    pool: &PgPool,
+    sensor_type: SensorType,
+    metric_filter: Option<&str>,
+) -> Result<Vec<(i64, TypedSamples)>> {
+    let (value_columns, value_join) = value_columns(sensor_type, "latest");
+    let sql = format!(
+        r#"
+        SELECT DISTINCT ON (latest.sensor_id) latest.sensor_id, latest.timestamp_ms, {}
+        FROM {} AS latest
+        {}
+        WHERE $1::TEXT IS NULL
+            OR latest.sensor_id IN (SELECT sensor_id FROM sensors WHERE name = $1)
+        ORDER BY latest.sensor_id, latest.timestamp_ms DESC
+        "#,
+        value_columns,
+        values_table(sensor_type),
+        value_join
+    );
+    let rows = sqlx::query(&sql)
+        .bind(metric_filter)
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| {
+            let samples = decode_samples(
+                sensor_type,
+                std::slice::from_ref(row),
+                timestamp_ms_datetime,
+            )?;
+            Ok((row.try_get("sensor_id")?, samples))
+        })
+        .collect()
+}
+
+/// Returns the samples of several sensors of the same type, sorted by
+/// datetime, in one query with a window function.
+///
+/// The limit applies to each sensor.
+pub async fn query_samples_of_sensors(
+    pool: &PgPool,
+    sensor_ids: &[i64],
+    sensor_type: SensorType,
+    start: Option<SensAppDateTime>,
+    end: Option<SensAppDateTime>,
+    limit: Option<usize>,
+) -> Result<HashMap<i64, TypedSamples>> {
+    let (value_columns, value_join) = value_columns(sensor_type, "samples");
+    // Rounded up, as the timestamps are in whole milliseconds
+    let start_ms = start
+        .map(|start| start.to_unix_milliseconds().ceil() as i64)
+        .unwrap_or(i64::MIN);
+    let end_ms = end
+        .map(|end| end.to_unix_milliseconds().ceil() as i64)
+        .unwrap_or(i64::MAX);
+    let sql = format!(
+        r#"
+        SELECT samples.sensor_id, samples.timestamp_ms, {}
+        FROM (
+            SELECT *, ROW_NUMBER() OVER (
+                PARTITION BY sensor_id ORDER BY timestamp_ms
+            ) AS row_number
+            FROM {}
+            WHERE sensor_id = ANY($1) AND timestamp_ms >= $2 AND timestamp_ms < $3
+        ) AS samples
+        {}
+        WHERE $4::BIGINT IS NULL OR samples.row_number <= $4
+        ORDER BY samples.sensor_id, samples.timestamp_ms
+        "#,
+        value_columns,
+        values_table(sensor_type),
+        value_join
+    );
+    let rows = sqlx::query(&sql)
+        .bind(sensor_ids)
+        .bind(start_ms)
+        .bind(end_ms)
+        .bind(limit.map(|limit| limit as i64))
+        .fetch_all(pool)
+        .await?;
+    decode_samples_of_sensors(sensor_type, sensor_ids, rows, timestamp_ms_datetime)
+}
+
+/// Aggregates the numeric samples of a sensor in buckets of `step_ms`
+/// aligned on the unix epoch, with an inclusive start and an exclusive end.
+pub async fn aggregate_samples(
+    pool: &PgPool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    start: Option<SensAppDateTime>,
+    end: Option<SensAppDateTime>,
+    step_ms: i64,
+) -> Result<Vec<AggregateBucket>> {
+    if !SensorSelector::is_numeric(sensor_type) {
+        bail!("Only the numeric sensors can be aggregated");
+    }
+    // Rounded up, as the timestamps are in whole milliseconds
+    let start_ms = start
+        .map(|start| start.to_unix_milliseconds().ceil() as i64)
+        .unwrap_or(i64::MIN);
+    let end_ms = end
+        .map(|end| end.to_unix_milliseconds().ceil() as i64)
+        .unwrap_or(i64::MAX);
+    let rows: Vec<(i64, i64, f64, f64, f64)> = sqlx::query_as(&format!(
+        r#"
+        SELECT timestamp_ms - ((timestamp_ms % $1) + $1) % $1 AS bucket, COUNT(*),
+            MIN(value::DOUBLE PRECISION), MAX(value::DOUBLE PRECISION),
+            AVG(value::DOUBLE PRECISION)
+        FROM {}
+        WHERE sensor_id = $2 AND timestamp_ms >= $3 AND timestamp_ms < $4
+        GROUP BY bucket
+        ORDER BY bucket ASC
+        "#,
+        values_table(sensor_type)
+    ))
+    .bind(step_ms)
+    .bind(sensor_id)
+    .bind(start_ms)
+    .bind(end_ms)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(bucket, count, min, max, avg)| AggregateBucket {
+            start: SensAppDateTime::from_unix_milliseconds_i64(bucket),
+            count: count as u64,
+            min,
+            max,
+            avg,
+        })
+        .collect())
+}
+
+/// Deletes the samples of all the sensors older than the cutoff, exclusive,
+/// in the value tables of the sample types.
+pub async fn delete_samples_older_than(
+    pool: &PgPool,
+    older_than: SensAppDateTime,
+    sample_types: &[SensorType],
+) -> Result<()> {
+    // Rounded up, as the timestamps are in whole milliseconds
+    let older_than_ms = older_than.to_unix_milliseconds().ceil() as i64;
+    let mut transaction = pool.begin().await?;
+    for &sensor_type in sample_types {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE timestamp_ms < $1",
+            values_table(sensor_type)
+        ))
+        .bind(older_than_ms)
+        .execute(&mut *transaction)
+        .await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Deletes the sensors with the given name, with their samples in the value
+/// tables of the sample types, their labels, and annotations, and returns
+/// the number of deleted sensors.
+pub async fn delete_metric(pool: &PgPool, name: &str, sample_types: &[SensorType]) -> Result<u64> {
+    let mut transaction = pool.begin().await?;
+    let sensor_ids: Vec<i64> = sqlx::query_scalar("SELECT sensor_id FROM sensors WHERE name = $1")
+        .bind(name)
+        .fetch_all(&mut *transaction)
+        .await?;
+    if sensor_ids.is_empty() {
+        return Ok(0);
+    }
+    let tables = sample_types
+        .iter()
+        .map(|&sensor_type| values_table(sensor_type))
+        .chain(["labels", "annotations"]);
+    for table in tables {
+        sqlx::query(&format!("DELETE FROM {} WHERE sensor_id = ANY($1)", table))
+            .bind(&sensor_ids)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    let deleted = sqlx::query("DELETE FROM sensors WHERE sensor_id = ANY($1)")
+        .bind(&sensor_ids)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    transaction.commit().await?;
+    Ok(deleted)
+}
+
+/// Scans all the samples of a sensor, in the physical order of the table.
+///
+/// PostgreSQL rejects the invalid UTF-8 in the text columns,
+/// so the strings are never counted as invalid.
+pub async fn verify_samples(
+    pool: &PgPool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+) -> Result<VerifyReport> {
+    let value_column = match sensor_type {
+        SensorType::Float => "value",
+        _ => "NULL::DOUBLE PRECISION",
+    };
+    let sql = format!(
+        r#"
+        SELECT timestamp_ms, {} AS value FROM {}
+        WHERE sensor_id = $1
+        ORDER BY ctid
+        "#,
+        value_column,
+        values_table(sensor_type)
+    );
+
+    let mut report = VerifyReport::default();
+    let mut rows = sqlx::query(&sql).bind(sensor_id).fetch(pool);
+    while let Some(row) = rows.try_next().await? {
+        let timestamp_ms: i64 = row.try_get(0)?;
+        report.check_timestamp(timestamp_ms as i128 * 1_000_000);
+        let value: Option<f64> = row.try_get(1)?;
+        if let Some(value) = value {
+            report.check_float(value);
+        }
+    }
+    Ok(report)
+}
+
+/// Returns the number of samples of a sensor, with an inclusive start
+/// and an exclusive end.
+pub async fn count_samples(
+    pool: &PgPool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    start: Option<SensAppDateTime>,
+    end: Option<SensAppDateTime>,
+) -> Result<u64> {
+    let table = values_table(sensor_type);
+    // Rounded up, as the timestamps are in whole milliseconds
+    let start_ms = start
+        .map(|start| start.to_unix_milliseconds().ceil() as i64)
+        .unwrap_or(i64::MIN);
+    let end_ms = end
+        .map(|end| end.to_unix_milliseconds().ceil() as i64)
+        .unwrap_or(i64::MAX);
+    let count: i64 = sqlx::query_scalar(&format!(
+        r#"
+        SELECT COUNT(*) FROM {}
+        WHERE sensor_id = $1 AND timestamp_ms >= $2 AND timestamp_ms < $3
+        "#,
+        table
+    ))
+    .bind(sensor_id)
+    .bind(start_ms)
+    .bind(end_ms)
+    .fetch_one(pool)
+    .await?;
+    Ok(count as u64)
+}
+
+/// The SQLSTATE of a statement cancelled by the statement timeout.
+const QUERY_CANCELED: &str = "57014";
+
+/// Runs a validated raw SQL query in a read-only transaction,
+/// cancelled by PostgreSQL after the timeout.
+///
+/// PostgreSQL converts each row to a JSON array, which keeps the column
+/// order and the duplicate column names, and the types without a Rust
+/// decoder such as the numerics and the timestamps.
+pub async fn query_raw_sql(
+    pool: &PgPool,
+    sql: &str,
+    max_rows: usize,
+    timeout: Duration,
+) -> Result<RawSqlResult> {
+    // Rolled back when dropped, nothing is committed.
+    let mut transaction = pool.begin().await?;
+    sqlx::query("SET TRANSACTION READ ONLY")
+        .execute(&mut *transaction)
+        .await?;
+    // SET doesn't take bind parameters, and 0 disables the timeout.
+    sqlx::query(&format!(
+        "SET LOCAL statement_timeout = {}",
+        timeout.as_millis().max(1)
+    ))
+    .execute(&mut *transaction)
+    .await?;
+
+    let result = async {
+        let columns = (&mut *transaction)
+            .describe(sql)
+            .await?
+            .columns()
+            .iter()
+            .map(|column| column.name().to_string())
+            .collect();
+        let rows: Vec<Option<Value>> = sqlx::query_scalar(&format!(
+            r#"
+            SELECT (
+                SELECT json_agg(value ORDER BY ordinality)
+                FROM json_each(to_json(raw_sql_query)) WITH ORDINALITY
+            )
+            FROM ({}) AS raw_sql_query
+            LIMIT $1
+            "#,
+            sql
+        ))
+        .bind(max_rows as i64 + 1)
+        .fetch_all(&mut *transaction)
+        .await?;
+        let mut result = RawSqlResult {
+            columns,
+            truncated: rows.len() > max_rows,
+            ..Default::default()
+        };
+        result.rows = rows
+            .into_iter()
+            .take(max_rows)
+            .map(|row| match row {
+                Some(Value::Array(values)) => values,
+                _ => Vec::new(),
+            })
+            .collect();
+        Ok::<_, sqlx::Error>(result)
+    }
+    .await;
+
+    result.map_err(|error| match error {
+        sqlx::Error::Database(database_error)
+            if database_error.code().as_deref() == Some(QUERY_CANCELED) =>
+        {
+            RawSqlTimeoutError(timeout).into()
+        }
+        sqlx::Error::Database(database_error) => {
+            InvalidQueryError(database_error.message().to_string()).into()
+        }
+        error => error.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::batch::{Batch, SingleSensorBatch};
+    use crate::storage::postgresql::PostgresStorage;
+    use crate::storage::storage::StorageInstance;
+    use serde_json::json;
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    /// It needs a PostgreSQL database with the SensApp schema:
+    /// `SENSAPP_TEST_POSTGRES_CONNECTION_STRING=postgres://... cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_query_raw_sql() {
+        let connection_string = std::env::var("SENSAPP_TEST_POSTGRES_CONNECTION_STRING")
+            .expect("SENSAPP_TEST_POSTGRES_CONNECTION_STRING is not set");
+        let pool = PgPool::connect(&connection_string).await.unwrap();
+        sqlx::migrate!("src/storage/postgresql/migrations")
+            .run(&pool)
+            .await
+            .unwrap();
+        let timeout = Duration::from_secs(5);
+
+        let result = query_raw_sql(
+            &pool,
+            "SELECT 1 AS one, 'two' AS two, NULL AS three, 1.5::NUMERIC AS four, 2 AS one",
+            10,
+            timeout,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.columns, vec!["one", "two", "three", "four", "one"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                json!(1),
+                json!("two"),
+                json!(null),
+                json!(1.5),
+                json!(2)
+            ]]
+        );
+        assert!(!result.truncated);
+
+        let result = query_raw_sql(&pool, "SELECT generate_series(1, 5)", 3, timeout)
+            .await
+            .unwrap();
+        assert_eq!(result.rows.len(), 3);
+        assert!(result.truncated);
+
+        // The transaction is read-only, even if a write passes the validation
+        let error = query_raw_sql(
+            &pool,
+            "SELECT nextval('sensors_sensor_id_seq')",
+            10,
+            timeout,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<InvalidQueryError>(), "{}", error);
+
+        let error = query_raw_sql(&pool, "SELECT pg_sleep(5)", 10, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(error.is::<RawSqlTimeoutError>(), "{}", error);
+    }
+
+    /// It needs a PostgreSQL database, like [`test_query_raw_sql`].
+    #[tokio::test]
+    #[ignore]
+    async fn test_query_samples_of_sensors() {
+        _ = crate::config::load_configuration();
+        let connection_string = std::env::var("SENSAPP_TEST_POSTGRES_CONNECTION_STRING")
+            .expect("SENSAPP_TEST_POSTGRES_CONNECTION_STRING is not set");
+        let storage = PostgresStorage::connect(&connection_string).await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+        let pool = PgPool::connect(&connection_string).await.unwrap();
+
+        let sensors = ["first", "second"]
+            .into_iter()
+            .map(|name| {
+                Arc::new(
+                    Sensor::new_without_uuid(
+                        format!("test_query_samples_of_sensors_{}_{}", name, Uuid::new_v4()),
+                        SensorType::String,
+                        None,
+                        None,
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let samples = |count: i64| {
+            TypedSamples::String(
+                (0..count)
+                    .map(|i| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                        value: i.to_string(),
+                    })
+                    .collect(),
+            )
+        };
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(sensors[0].clone(), samples(3)),
+            SingleSensorBatch::new(sensors[1].clone(), samples(1)),
+        ]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let mut sensor_ids = Vec::new();
+        for sensor in &sensors {
+            sensor_ids.push(get_sensor_id(&pool, sensor.uuid).await.unwrap().unwrap());
+        }
+        // And a sensor without samples
+        sensor_ids.push(i64::MAX);
+        let mut results =
+            query_samples_of_sensors(&pool, &sensor_ids, SensorType::String, None, None, Some(2))
+                .await
+                .unwrap();
+        assert_eq!(results.remove(&sensor_ids[0]), Some(samples(2)));
+        assert_eq!(results.remove(&sensor_ids[1]), Some(samples(1)));
+        assert_eq!(results.remove(&sensor_ids[2]), Some(samples(0)));
+    }
+}
@@ -1,8 +1,12 @@
 use crate::datamodel::unit::Unit;
-use crate::datamodel::Sensor;
+use crate::datamodel::{sensor_metadata::SensorMetadata, Sensor, SensorType};
+use crate::storage::strict_sensors::UnknownSensorError;
+use crate::storage::type_conflict::{TypeConflictError, TypeConflictPolicy};
 use anyhow::Result;
 use cached::proc_macro::cached;
-use sqlx::{Executor, Postgres, Row, Transaction};
+use cached::Cached;
+use sqlx::{Executor, PgPool, Postgres, Row, Transaction};
+use std::str::FromStr;
 use uuid::Uuid;
 
 #[cached(
@@ -98,6 +102,47 @@ pub async fn get_unit_id_or_create(
     Ok(unit_id)
 }
 
+/// Records that the sensor was published now, even without new samples.
+pub async fn touch_sensor(
+    transaction: &mut Transaction<'_, Postgres>,
+    sensor_id: i64,
+) -> Result<()> {
+    let query =
+        sqlx::query("UPDATE sensors SET last_seen = now() WHERE sensor_id = $1").bind(sensor_id);
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Replaces the metadata of the sensor, returns `false` when it doesn't exist.
+pub async fn update_sensor_metadata(
+    pool: &PgPool,
+    uuid: Uuid,
+    metadata: &SensorMetadata,
+) -> Result<bool> {
+    let query = sqlx::query(
+        r#"
+            UPDATE sensors SET min_value = $1, max_value = $2, nominal_interval_seconds = $3,
+                raw_retention_days = $4, rollup_interval_seconds = $5
+            WHERE uuid = $6
+            "#,
+    )
+    .bind(metadata.min_value)
+    .bind(metadata.max_value)
+    .bind(metadata.nominal_interval_seconds)
+    .bind(
+        metadata
+            .downsampling
+            .map(|policy| policy.raw_retention_days),
+    )
+    .bind(
+        metadata
+            .downsampling
+            .map(|policy| policy.rollup_interval_seconds as i64),
+    )
+    .bind(uuid);
+    Ok(pool.execute(query).await?.rows_affected() > 0)
+}
+
 #[cached(
     time = 120,
     result = true,
@@ -108,7 +153,9 @@ pub async fn get_unit_id_or_create(
 pub async fn get_sensor_id_or_create_sensor(
     transaction: &mut Transaction<'_, Postgres>,
     sensor: &Sensor,
-) -> Result<i64> {
+    strict_sensors: bool,
+    type_conflict_policy: TypeConflictPolicy,
+) -> Result<(i64, SensorType)> {
     let sqlx_uuid = sensor.uuid;
     let sensor_id_query = sqlx::query(
         r#"
@@ -123,7 +170,28 @@ pub async fn get_sensor_id_or_create_sensor(
         .map(|row| row.get("sensor_id"));
 
     if let Some(Some(sensor_id)) = sensor_id {
-        return Ok(sensor_id);
+        return Ok((sensor_id, sensor.sensor_type));
+    }
+    if type_conflict_policy != TypeConflictPolicy::NewSeries {
+        if let Some((sensor_id, existing_type)) =
+            find_sensor_with_another_type(transaction, sensor).await?
+        {
+            if type_conflict_policy == TypeConflictPolicy::Reject {
+                return Err(TypeConflictError {
+                    name: sensor.name.clone(),
+                    existing_type,
+                    new_type: sensor.sensor_type,
+                }
+                .into());
+            }
+            return Ok((sensor_id, existing_type));
+        }
+    }
+    if strict_sensors {
+        return Err(UnknownSensorError {
+            names: vec![sensor.name.clone()],
+        }
+        .into());
     }
 
     let sensor_type_string = sensor.sensor_type.to_string();
@@ -135,15 +203,25 @@ pub async fn get_sensor_id_or_create_sensor(
 
     let create_sensor_query = sqlx::query(
         r#"
-            INSERT INTO sensors (uuid, name, type, unit)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO sensors (uuid, name, type, unit, min_value, max_value, nominal_interval_seconds,
+                raw_retention_days, rollup_interval_seconds)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
             RETURNING sensor_id
             "#,
     )
     .bind(sensor.uuid)
     .bind(sensor.name.to_string())
     .bind(sensor_type_string)
-    .bind(unit_id);
+    .bind(unit_id)
+    .bind(sensor.min_value)
+    .bind(sensor.max_value)
+    .bind(sensor.nominal_interval_seconds)
+    .bind(sensor.downsampling.map(|policy| policy.raw_retention_days))
+    .bind(
+        sensor
+            .downsampling
+            .map(|policy| policy.rollup_interval_seconds as i64),
+    );
 
     let sensor_id = transaction
         .fetch_one(create_sensor_query)
@@ -166,7 +244,53 @@ pub async fn get_sensor_id_or_create_sensor(
         transaction.execute(create_label_query).await?;
     }
 
-    Ok(sensor_id)
+    Ok((sensor_id, sensor.sensor_type))
+}
+
+/// Finds the sensor of the same name and labels, but of another type.
+async fn find_sensor_with_another_type(
+    transaction: &mut Transaction<'_, Postgres>,
+    sensor: &Sensor,
+) -> Result<Option<(i64, SensorType)>> {
+    let candidates_query = sqlx::query(
+        r#"
+            SELECT sensor_id, type FROM sensors WHERE name = $1 AND type != $2
+            "#,
+    )
+    .bind(&sensor.name)
+    .bind(sensor.sensor_type.to_string());
+    let candidates = transaction.fetch_all(candidates_query).await?;
+
+    let mut labels = sensor.labels.to_vec();
+    labels.sort();
+    for candidate in candidates {
+        let sensor_id: i64 = candidate.get("sensor_id");
+        let labels_query = sqlx::query(
+            r#"
+                SELECT labels_name_dictionary.name, labels_description_dictionary.description
+                FROM labels
+                JOIN labels_name_dictionary ON labels.name = labels_name_dictionary.id
+                LEFT JOIN labels_description_dictionary ON labels.description = labels_description_dictionary.id
+                WHERE labels.sensor_id = $1
+                "#,
+        )
+        .bind(sensor_id);
+        let mut candidate_labels = transaction
+            .fetch_all(labels_query)
+            .await?
+            .into_iter()
+            .map(|row| {
+                let description: Option<String> = row.get("description");
+                (row.get("name"), description.unwrap_or_default())
+            })
+            .collect::<Vec<(String, String)>>();
+        candidate_labels.sort();
+        if candidate_labels == labels {
+            let sensor_type: String = candidate.get("type");
+            return Ok(Some((sensor_id, SensorType::from_str(&sensor_type)?)));
+        }
+    }
+    Ok(None)
 }
 
 #[cached(
@@ -199,3 +323,15 @@ pub async fn get_string_value_id_or_create(
     let string_value_id = transaction.fetch_one(query).await?.get("id");
     Ok(string_value_id)
 }
+
+/// Clears the cached identifiers, as they may come from a rolled back transaction.
+pub async fn clear_caches() {
+    GET_LABEL_NAME_ID_OR_CREATE.lock().await.cache_clear();
+    GET_LABEL_DESCRIPTION_ID_OR_CREATE
+        .lock()
+        .await
+        .cache_clear();
+    GET_UNIT_ID_OR_CREATE.lock().await.cache_clear();
+    GET_SENSOR_ID_OR_CREATE_SENSOR.lock().await.cache_clear();
+    GET_STRING_VALUE_ID_OR_CREATE.lock().await.cache_clear();
+}
@@ -0,0 +1,9 @@
+-- The 'boolean_values' table, as created by the migrations
+CREATE TABLE IF NOT EXISTS boolean_values (
+    sensor_id BIGINT NOT NULL,
+    timestamp_ms BIGINT NOT NULL,
+    value BOOLEAN NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+CREATE INDEX IF NOT EXISTS index_boolean_values ON boolean_values USING brin (sensor_id, timestamp_ms) WITH (pages_per_range = 32);
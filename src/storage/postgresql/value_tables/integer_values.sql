@@ -0,0 +1,9 @@
+-- The 'integer_values' table, as created by the migrations
+CREATE TABLE IF NOT EXISTS integer_values (
+    sensor_id BIGINT NOT NULL,
+    timestamp_ms BIGINT NOT NULL,
+    value BIGINT NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+CREATE INDEX IF NOT EXISTS index_integer_values ON integer_values USING brin (sensor_id, timestamp_ms) WITH (pages_per_range = 32);
@@ -0,0 +1,10 @@
+-- The 'location_values' table, as created by the migrations
+CREATE TABLE IF NOT EXISTS location_values (
+    sensor_id BIGINT NOT NULL,
+    timestamp_ms BIGINT NOT NULL,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+CREATE INDEX IF NOT EXISTS index_location_values ON location_values USING brin (sensor_id, timestamp_ms) WITH (pages_per_range = 32);
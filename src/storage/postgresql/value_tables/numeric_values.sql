@@ -0,0 +1,9 @@
+-- The 'numeric_values' table, as created by the migrations
+CREATE TABLE IF NOT EXISTS numeric_values (
+    sensor_id BIGINT NOT NULL,
+    timestamp_ms BIGINT NOT NULL,
+    value NUMERIC NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+CREATE INDEX IF NOT EXISTS index_numeric_values ON numeric_values USING brin (sensor_id, timestamp_ms) WITH (pages_per_range = 32);
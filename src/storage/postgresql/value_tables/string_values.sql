@@ -0,0 +1,10 @@
+-- The 'string_values' table, as created by the migrations
+CREATE TABLE IF NOT EXISTS string_values (
+    sensor_id BIGINT NOT NULL,
+    timestamp_ms BIGINT NOT NULL,
+    value BIGINT NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id),
+    FOREIGN KEY (value) REFERENCES strings_values_dictionary(id)
+);
+
+CREATE INDEX IF NOT EXISTS index_string_values ON string_values USING brin (sensor_id, timestamp_ms) WITH (pages_per_range = 32);
@@ -0,0 +1,387 @@
+use crate::datamodel::{SensAppDateTime, Sensor, SensorData, SensorType, TypedSamples};
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+/// The label name matching the sensor name, like in Prometheus.
+pub const NAME_LABEL: &str = "__name__";
+
+/// The error returned for the queries the storage can't answer as asked,
+/// such as an invalid regular expression.
+#[derive(Debug)]
+pub struct InvalidQueryError(pub String);
+
+impl std::fmt::Display for InvalidQueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidQueryError {}
+
+/// How a label matcher compares the label value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum LabelMatcherType {
+    #[serde(rename = "=")]
+    Equal,
+    #[serde(rename = "!=")]
+    NotEqual,
+    #[serde(rename = "=~")]
+    Regex,
+    #[serde(rename = "!~")]
+    NotRegex,
+}
+
+/// A Prometheus style label matcher.
+///
+/// A missing label is matched as an empty value,
+/// and regular expressions are anchored on both sides.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LabelMatcher {
+    pub name: String,
+    pub value: String,
+    #[serde(rename = "type")]
+    pub matcher_type: LabelMatcherType,
+    /// The regular expression, compiled on first use.
+    #[serde(skip)]
+    regex: OnceLock<Regex>,
+}
+
+impl LabelMatcher {
+    pub fn new(name: String, value: String, matcher_type: LabelMatcherType) -> Self {
+        Self {
+            name,
+            value,
+            matcher_type,
+            regex: OnceLock::new(),
+        }
+    }
+
+    pub fn matches(&self, sensor: &Sensor) -> Result<bool> {
+        let label_value = if self.name == NAME_LABEL {
+            sensor.name.as_str()
+        } else {
+            sensor
+                .labels
+                .iter()
+                .find(|(name, _)| *name == self.name)
+                .map(|(_, value)| value.as_str())
+                .unwrap_or_default()
+        };
+        Ok(match self.matcher_type {
+            LabelMatcherType::Equal => label_value == self.value,
+            LabelMatcherType::NotEqual => label_value != self.value,
+            LabelMatcherType::Regex => self.regex()?.is_match(label_value),
+            LabelMatcherType::NotRegex => !self.regex()?.is_match(label_value),
+        })
+    }
+
+    fn regex(&self) -> Result<&Regex> {
+        if let Some(regex) = self.regex.get() {
+            return Ok(regex);
+        }
+        let regex = Regex::new(&format!("^(?:{})$", self.value)).map_err(|error| {
+            InvalidQueryError(format!("Invalid regex '{}': {}", self.value, error))
+        })?;
+        Ok(self.regex.get_or_init(|| regex))
+    }
+}
+
+/// The order of the samples of a sensor sorted by value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(order: &str) -> Result<Self> {
+        match order {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => bail!("Unknown order: {}, use asc or desc", order),
+        }
+    }
+}
+
+/// The limit of a sensor query, or the default limit of the sensor type
+/// when the query doesn't give one.
+pub fn sensor_query_limit(limit: Option<usize>, sensor_type: SensorType) -> Result<Option<usize>> {
+    match limit {
+        Some(limit) => Ok(Some(limit)),
+        None => Ok(crate::config::get()?.default_query_limit(sensor_type)),
+    }
+}
+
+/// Selects the sensors of a query.
+///
+/// All the constraints must match.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SensorSelector {
+    /// Only the sensors with one of these UUIDs, or all the sensors if `None`.
+    pub uuids: Option<Vec<Uuid>>,
+    #[serde(default)]
+    pub matchers: Vec<LabelMatcher>,
+    /// Only the integer, numeric, and float sensors.
+    #[serde(default)]
+    pub numeric_only: bool,
+    /// Only the sensors created after this unix time, in seconds.
+    /// The sensors without a known creation time don't match.
+    #[serde(default)]
+    pub created_after: Option<f64>,
+    /// Only the sensors last seen before this unix time, in seconds,
+    /// to find the quiet sensors. The sensors without a known
+    /// last seen time match, as they may never have been published.
+    #[serde(default)]
+    pub last_seen_before: Option<f64>,
+}
+
+impl SensorSelector {
+    pub fn is_numeric(sensor_type: SensorType) -> bool {
+        matches!(
+            sensor_type,
+            SensorType::Integer | SensorType::Numeric | SensorType::Float
+        )
+    }
+
+    /// Checks that the selector selects some sensors, by UUIDs or label
+    /// matchers, and that the regular expressions of the matchers are valid.
+    pub fn validate(&self) -> Result<()> {
+        if self.uuids.is_none() && self.matchers.is_empty() {
+            return Err(InvalidQueryError(
+                "The selector needs sensor UUIDs or label matchers".to_string(),
+            )
+            .into());
+        }
+        for matcher in &self.matchers {
+            if matches!(
+                matcher.matcher_type,
+                LabelMatcherType::Regex | LabelMatcherType::NotRegex
+            ) {
+                matcher.regex()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, sensor: &Sensor) -> Result<bool> {
+        if let Some(uuids) = &self.uuids {
+            if !uuids.contains(&sensor.uuid) {
+                return Ok(false);
+            }
+        }
+        if self.numeric_only && !Self::is_numeric(sensor.sensor_type) {
+            return Ok(false);
+        }
+        if let Some(created_after) = self.created_after {
+            let created_after = SensAppDateTime::from_unix_seconds(created_after);
+            if sensor
+                .created_at
+                .is_none_or(|created_at| created_at <= created_after)
+            {
+                return Ok(false);
+            }
+        }
+        if let Some(last_seen_before) = self.last_seen_before {
+            let last_seen_before = SensAppDateTime::from_unix_seconds(last_seen_before);
+            if sensor
+                .last_seen
+                .is_some_and(|last_seen| last_seen >= last_seen_before)
+            {
+                return Ok(false);
+            }
+        }
+        for matcher in &self.matchers {
+            if !matcher.matches(sensor)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The sensors whose samples are fetched by the same query.
+pub const SENSORS_PER_SAMPLES_QUERY: usize = 256;
+
+/// Fetches the samples of the sensors with one query per batch of sensors
+/// of the same type, instead of one query per sensor.
+///
+/// `query_batch` returns the samples of each sensor_id of the batch,
+/// and the sensors are returned in the given order.
+pub async fn query_samples_in_batches<F, Fut>(
+    sensors: Vec<(i64, Sensor)>,
+    mut query_batch: F,
+) -> Result<Vec<SensorData>>
+where
+    F: FnMut(Vec<i64>, SensorType) -> Fut,
+    Fut: Future<Output = Result<HashMap<i64, TypedSamples>>>,
+{
+    let mut sensor_ids_by_type: HashMap<SensorType, Vec<i64>> = HashMap::new();
+    for (sensor_id, sensor) in &sensors {
+        sensor_ids_by_type
+            .entry(sensor.sensor_type)
+            .or_default()
+            .push(*sensor_id);
+    }
+    let mut samples = HashMap::with_capacity(sensors.len());
+    for (sensor_type, sensor_ids) in sensor_ids_by_type {
+        for batch in sensor_ids.chunks(SENSORS_PER_SAMPLES_QUERY) {
+            samples.extend(query_batch(batch.to_vec(), sensor_type).await?);
+        }
+    }
+    sensors
+        .into_iter()
+        .map(|(sensor_id, sensor)| {
+            let samples = samples
+                .remove(&sensor_id)
+                .ok_or_else(|| anyhow!("The samples of sensor {} are missing", sensor_id))?;
+            Ok(SensorData::new(sensor, samples))
+        })
+        .collect()
+}
+
+/// A time range, with an inclusive start and an exclusive end.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeRange {
+    pub start: Option<SensAppDateTime>,
+    pub end: Option<SensAppDateTime>,
+}
+
+impl TimeRange {
+    pub fn new(start: Option<SensAppDateTime>, end: Option<SensAppDateTime>) -> Self {
+        Self { start, end }
+    }
+}
+
+/// How the samples of a time bucket are aggregated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AggregateFunction {
+    Avg,
+    Min,
+    Max,
+    Count,
+    /// Both the min and the max, so the charts keep the spikes.
+    MinMax,
+}
+
+impl AggregateFunction {
+    /// The values of a bucket, by name.
+    pub fn values(&self, bucket: &AggregateBucket) -> Vec<(&'static str, f64)> {
+        match self {
+            AggregateFunction::Avg => vec![("avg", bucket.avg)],
+            AggregateFunction::Min => vec![("min", bucket.min)],
+            AggregateFunction::Max => vec![("max", bucket.max)],
+            AggregateFunction::Count => vec![("count", bucket.count as f64)],
+            AggregateFunction::MinMax => vec![("min", bucket.min), ("max", bucket.max)],
+        }
+    }
+}
+
+/// The aggregates of the samples of a time bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AggregateBucket {
+    /// Start of the bucket, aligned on the unix epoch.
+    pub start: SensAppDateTime,
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smallvec::smallvec;
+
+    fn sensor(sensor_type: SensorType) -> Sensor {
+        Sensor::new(
+            Uuid::new_v4(),
+            "temperature".to_string(),
+            sensor_type,
+            None,
+            Some(smallvec![("room".to_string(), "kitchen".to_string())]),
+        )
+    }
+
+    #[test]
+    fn test_label_matcher() {
+        let sensor = sensor(SensorType::Float);
+        let matcher = |name: &str, value: &str, matcher_type| {
+            LabelMatcher::new(name.to_string(), value.to_string(), matcher_type)
+                .matches(&sensor)
+                .unwrap()
+        };
+        assert!(matcher("room", "kitchen", LabelMatcherType::Equal));
+        assert!(matcher("room", "garage", LabelMatcherType::NotEqual));
+        assert!(matcher("room", "kit.*", LabelMatcherType::Regex));
+        assert!(!matcher("room", "kit", LabelMatcherType::Regex));
+        assert!(matcher("__name__", "temp.*", LabelMatcherType::Regex));
+        assert!(matcher("floor", "", LabelMatcherType::Equal));
+        assert!(matcher("floor", "1", LabelMatcherType::NotRegex));
+    }
+
+    #[test]
+    fn test_sensor_selector() {
+        let float_sensor = sensor(SensorType::Float);
+        let string_sensor = sensor(SensorType::String);
+
+        let selector = SensorSelector {
+            uuids: Some(vec![float_sensor.uuid, string_sensor.uuid]),
+            matchers: vec![],
+            numeric_only: true,
+            created_after: None,
+            last_seen_before: None,
+        };
+        assert!(selector.matches(&float_sensor).unwrap());
+        assert!(!selector.matches(&string_sensor).unwrap());
+
+        let selector = SensorSelector {
+            uuids: Some(vec![string_sensor.uuid]),
+            matchers: vec![LabelMatcher::new(
+                "room".to_string(),
+                "kitchen".to_string(),
+                LabelMatcherType::Equal,
+            )],
+            numeric_only: false,
+            created_after: None,
+            last_seen_before: None,
+        };
+        assert!(!selector.matches(&float_sensor).unwrap());
+        assert!(selector.matches(&string_sensor).unwrap());
+
+        let selector = SensorSelector {
+            created_after: Some(1_700_000_000.0),
+            ..Default::default()
+        };
+        let created_at = |seconds| {
+            sensor(SensorType::Float)
+                .with_created_at(Some(SensAppDateTime::from_unix_seconds(seconds)))
+        };
+        assert!(selector.matches(&created_at(1_700_000_001.0)).unwrap());
+        assert!(!selector.matches(&created_at(1_699_999_999.0)).unwrap());
+        // The creation time of the sensor isn't known
+        assert!(!selector.matches(&float_sensor).unwrap());
+
+        let selector = SensorSelector {
+            last_seen_before: Some(1_700_000_000.0),
+            ..Default::default()
+        };
+        let last_seen = |seconds| {
+            sensor(SensorType::Float)
+                .with_last_seen(Some(SensAppDateTime::from_unix_seconds(seconds)))
+        };
+        assert!(selector.matches(&last_seen(1_699_999_999.0)).unwrap());
+        assert!(!selector.matches(&last_seen(1_700_000_001.0)).unwrap());
+        // Never seen
+        assert!(selector.matches(&float_sensor).unwrap());
+    }
+}
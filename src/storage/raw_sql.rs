@@ -0,0 +1,180 @@
+use super::query::InvalidQueryError;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+
+/// The keywords and functions refused in the raw SQL queries.
+///
+/// The backends also run the queries on a read-only connection,
+/// this is only the first guard.
+const FORBIDDEN_KEYWORDS: &[&str] = &[
+    "INSERT",
+    "UPDATE",
+    "DELETE",
+    "REPLACE",
+    "UPSERT",
+    "MERGE",
+    "CREATE",
+    "DROP",
+    "ALTER",
+    "TRUNCATE",
+    "ATTACH",
+    "DETACH",
+    "PRAGMA",
+    "VACUUM",
+    "REINDEX",
+    "ANALYZE",
+    "GRANT",
+    "REVOKE",
+    "COPY",
+    "INTO",
+    "CALL",
+    "EXECUTE",
+    "LOCK",
+    "LOAD_EXTENSION",
+    "PG_READ_FILE",
+    "PG_READ_BINARY_FILE",
+    "LO_IMPORT",
+    "LO_EXPORT",
+];
+
+/// The result of a raw SQL query, with the rows in the column order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RawSqlResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    /// Whether rows were left out because of the row limit.
+    pub truncated: bool,
+}
+
+/// The error returned when a raw SQL query is interrupted by its timeout.
+#[derive(Debug)]
+pub struct RawSqlTimeoutError(pub Duration);
+
+impl std::fmt::Display for RawSqlTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The query took more than {:?}", self.0)
+    }
+}
+
+impl std::error::Error for RawSqlTimeoutError {}
+
+fn invalid(message: impl Into<String>) -> anyhow::Error {
+    InvalidQueryError(message.into()).into()
+}
+
+/// Checks that the query is a single `SELECT` statement,
+/// and returns it without the trailing semicolon.
+///
+/// Comments are refused, and the keywords are only searched
+/// outside of the string literals and the quoted identifiers.
+/// The refused queries fail with an [`InvalidQueryError`].
+pub fn validate_read_only_select(sql: &str) -> Result<&str> {
+    let sql = sql.trim();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote: Option<char> = None;
+    let mut end = sql.len();
+    let mut chars = sql.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        if let Some(closing) = quote {
+            if c == closing {
+                quote = None;
+            }
+            continue;
+        }
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c.to_ascii_uppercase());
+            continue;
+        }
+        if !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        match c {
+            '\'' | '"' | '`' => quote = Some(c),
+            '[' => quote = Some(']'),
+            '-' if chars.peek().is_some_and(|(_, next)| *next == '-') => {
+                return Err(invalid("Comments are not allowed"));
+            }
+            '/' if chars.peek().is_some_and(|(_, next)| *next == '*') => {
+                return Err(invalid("Comments are not allowed"));
+            }
+            ';' => {
+                if !sql[index + 1..].trim().is_empty() {
+                    return Err(invalid("Only a single statement is allowed"));
+                }
+                end = index;
+                break;
+            }
+            _ => {}
+        }
+    }
+    if quote.is_some() {
+        return Err(invalid("Unterminated quote"));
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    match words.first().map(String::as_str) {
+        Some("SELECT") | Some("WITH") => {}
+        _ => return Err(invalid("Only SELECT queries are allowed")),
+    }
+    if let Some(keyword) = words
+        .iter()
+        .find(|word| FORBIDDEN_KEYWORDS.contains(&word.as_str()))
+    {
+        return Err(invalid(format!(
+            "{} is not allowed in a read-only query",
+            keyword
+        )));
+    }
+
+    Ok(sql[..end].trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_read_only_select() {
+        assert_eq!(
+            validate_read_only_select(" SELECT * FROM sensors; ").unwrap(),
+            "SELECT * FROM sensors"
+        );
+        assert!(validate_read_only_select(
+            "WITH s AS (SELECT sensor_id FROM sensors) SELECT COUNT(*) FROM s"
+        )
+        .is_ok());
+        // The keywords in strings and quoted identifiers are fine
+        assert!(validate_read_only_select(
+            "SELECT \"delete\", 'drop table; --' FROM sensors WHERE name = 'it''s'"
+        )
+        .is_ok());
+
+        for sql in [
+            "DELETE FROM sensors",
+            "UPDATE sensors SET name = 'a'",
+            "select 1; delete from sensors",
+            "WITH s AS (SELECT 1) DELETE FROM sensors",
+            "SELECT * INTO backup FROM sensors",
+            "SELECT 1 -- comment",
+            "SELECT /* comment */ 1",
+            "PRAGMA table_info(sensors)",
+            "SELECT load_extension('evil')",
+            "SELECT 'unterminated",
+            "",
+        ] {
+            assert!(
+                validate_read_only_select(sql)
+                    .unwrap_err()
+                    .is::<InvalidQueryError>(),
+                "{}",
+                sql
+            );
+        }
+    }
+}
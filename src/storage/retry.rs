@@ -0,0 +1,176 @@
+use anyhow::Result;
+use std::future::Future;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryOptions {
+    /// Retries after the first attempt, 0 to disable them.
+    pub retries: usize,
+    /// Delay before the first retry, doubled at each retry.
+    pub backoff: Duration,
+}
+
+impl RetryOptions {
+    /// Reads the options, without retries when the configuration isn't loaded.
+    pub fn from_config() -> Self {
+        crate::config::get()
+            .map(|config| Self {
+                retries: config.publish_retries,
+                backoff: Duration::from_millis(config.publish_retry_backoff_ms),
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Returns whether a retry may succeed where the call failed.
+///
+/// The serialization failures and the deadlocks of PostgreSQL, the busy
+/// and locked SQLite databases, and the connection issues are transient.
+/// The other errors, like constraint violations, are data errors.
+pub fn is_transient_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| match cause.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::Database(database_error)) => {
+                match database_error.code().as_deref() {
+                    // PostgreSQL serialization_failure and deadlock_detected
+                    Some("40001") | Some("40P01") => true,
+                    // SQLite SQLITE_BUSY and SQLITE_LOCKED, with their extended codes
+                    Some(code) => code
+                        .parse::<i32>()
+                        .map(|code| matches!(code & 0xff, 5 | 6))
+                        .unwrap_or(false),
+                    None => false,
+                }
+            }
+            Some(sqlx::Error::Io(_)) | Some(sqlx::Error::PoolTimedOut) => true,
+            _ => false,
+        })
+}
+
+/// Runs the call, and retries it with an exponential backoff
+/// while it fails with a transient error.
+pub async fn with_retries<T, F, Fut>(options: RetryOptions, mut call: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut backoff = options.backoff;
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Err(error) if attempt < options.retries && is_transient_error(&error) => {
+                attempt += 1;
+                tracing::warn!(
+                    "Transient storage error, retrying ({}/{}): {:?}",
+                    attempt,
+                    options.retries,
+                    error
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::batch::Batch;
+    use crate::storage::storage::StorageInstance;
+    use anyhow::bail;
+    use async_broadcast::Sender;
+    use async_trait::async_trait;
+    use smallvec::smallvec;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Fails the first publications with a transient error,
+    /// retrying like the SQL backends do around their transactions.
+    #[derive(Debug)]
+    struct FlakyStorage {
+        options: RetryOptions,
+        transient_failures: usize,
+        attempts: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StorageInstance for FlakyStorage {
+        async fn create_or_migrate(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn publish(&self, _batch: Arc<Batch>, _sync_sender: Sender<()>) -> Result<()> {
+            with_retries(self.options, || async {
+                let attempt = self.attempts.fetch_add(1, Ordering::SeqCst);
+                if attempt < self.transient_failures {
+                    return Err(sqlx::Error::PoolTimedOut.into());
+                }
+                Ok(())
+            })
+            .await
+        }
+        async fn sync(&self, _sync_sender: Sender<()>) -> Result<()> {
+            Ok(())
+        }
+        async fn vacuum(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn list_sensors(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn flaky_storage(retries: usize, transient_failures: usize) -> FlakyStorage {
+        FlakyStorage {
+            options: RetryOptions {
+                retries,
+                backoff: Duration::from_millis(1),
+            },
+            transient_failures,
+            attempts: AtomicUsize::new(0),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_retries() {
+        let batch = Arc::new(Batch::new(smallvec![]));
+
+        let storage = flaky_storage(3, 2);
+        storage
+            .publish(batch.clone(), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        assert_eq!(storage.attempts.load(Ordering::SeqCst), 3);
+
+        // Not enough retries
+        let storage = flaky_storage(1, 2);
+        assert!(storage
+            .publish(batch, async_broadcast::broadcast(1).0)
+            .await
+            .is_err());
+        assert_eq!(storage.attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_data_errors_are_not_retried() {
+        let attempts = AtomicUsize::new(0);
+        let options = RetryOptions {
+            retries: 3,
+            backoff: Duration::from_millis(1),
+        };
+        let result: Result<()> = with_retries(options, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            bail!("Invalid value")
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        assert!(is_transient_error(
+            &anyhow::Error::from(sqlx::Error::PoolTimedOut).context("Failed to publish")
+        ));
+        assert!(!is_transient_error(&sqlx::Error::RowNotFound.into()));
+    }
+}
@@ -0,0 +1,245 @@
+use super::{
+    query::{SensorSelector, TimeRange},
+    storage::StorageInstance,
+};
+use crate::datamodel::{
+    batch::{Batch, SingleSensorBatch},
+    downsampling_policy::DownsamplingPolicy,
+    Sample, SensAppDateTime, Sensor, SensorType, TypedSamples,
+};
+use anyhow::Result;
+use hifitime::Duration;
+use serde::Serialize;
+use smallvec::smallvec;
+use std::sync::Arc;
+
+/// What a rollup run wrote.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RollupReport {
+    /// The sensors with new rollup buckets.
+    pub sensors: usize,
+    pub buckets: usize,
+}
+
+/// The companion sensor of the rollups, a float sensor
+/// with the labels and the unit of the sensor.
+pub fn rollup_sensor(sensor: &Sensor, policy: &DownsamplingPolicy) -> Result<Sensor> {
+    Sensor::new_without_uuid(
+        policy.rollup_name(&sensor.name),
+        SensorType::Float,
+        sensor.unit.clone(),
+        Some(sensor.labels.clone()),
+    )
+}
+
+/// Rolls up the samples of the sensors with a downsampling policy,
+/// from their last rollup bucket to the end of their raw retention window.
+///
+/// The averages of the complete buckets are published in the companion
+/// sensors, so running it again only adds the new buckets. The raw samples
+/// are kept, they are deleted by the retention.
+pub async fn apply_rollups(
+    storage: &dyn StorageInstance,
+    now: SensAppDateTime,
+) -> Result<RollupReport> {
+    let sensors = storage
+        .query(
+            &SensorSelector {
+                numeric_only: true,
+                ..Default::default()
+            },
+            TimeRange::default(),
+            Some(0),
+        )
+        .await?;
+
+    let mut report = RollupReport::default();
+    for sensor_data in sensors {
+        let sensor = sensor_data.sensor;
+        let Some(policy) = sensor.downsampling else {
+            continue;
+        };
+        if !SensorSelector::is_numeric(sensor.sensor_type) {
+            continue;
+        }
+        let buckets = rollup(storage, &sensor, &policy, now).await?;
+        if buckets > 0 {
+            report.sensors += 1;
+            report.buckets += buckets;
+        }
+    }
+    Ok(report)
+}
+
+async fn rollup(
+    storage: &dyn StorageInstance,
+    sensor: &Sensor,
+    policy: &DownsamplingPolicy,
+    now: SensAppDateTime,
+) -> Result<usize> {
+    let rollup_sensor = rollup_sensor(sensor, policy)?;
+    let interval = Duration::from_seconds(policy.rollup_interval_seconds as f64);
+
+    // After the last bucket, the buckets are complete once rolled up
+    let start = storage
+        .latest_samples_all(Some(&rollup_sensor.name))
+        .await?
+        .into_iter()
+        .find(|sensor_data| sensor_data.sensor.uuid == rollup_sensor.uuid)
+        .and_then(|sensor_data| match sensor_data.samples {
+            TypedSamples::Float(samples) => samples.last().map(|sample| sample.datetime),
+            _ => None,
+        })
+        .map(|last_bucket| last_bucket + interval);
+    let end = policy.cutoff(now);
+    if start.is_some_and(|start| start >= end) {
+        return Ok(0);
+    }
+
+    let Some(buckets) = storage
+        .aggregate_sensor_data(sensor.uuid, TimeRange::new(start, Some(end)), interval)
+        .await?
+    else {
+        return Ok(0);
+    };
+    if buckets.is_empty() {
+        return Ok(0);
+    }
+
+    let samples = buckets
+        .iter()
+        .map(|bucket| Sample {
+            datetime: bucket.start,
+            value: bucket.avg,
+        })
+        .collect();
+    let batch = Batch::new(smallvec![SingleSensorBatch::new(
+        Arc::new(rollup_sensor),
+        TypedSamples::Float(samples),
+    )]);
+    storage
+        .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+        .await?;
+    Ok(buckets.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{sensapp_datetime::SensAppDateTimeExt, sensapp_vec::SensAppLabels};
+    use crate::storage::sqlite::SqliteStorage;
+
+    const START: i64 = 1_699_999_200;
+
+    fn samples(minutes: std::ops::Range<i64>) -> TypedSamples {
+        TypedSamples::Float(
+            minutes
+                .map(|minute| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(START + minute * 60),
+                    value: minute as f64,
+                })
+                .collect(),
+        )
+    }
+
+    async fn publish(storage: &SqliteStorage, sensor: &Arc<Sensor>, samples: TypedSamples) {
+        storage
+            .publish(
+                Arc::new(Batch::new(smallvec![SingleSensorBatch::new(
+                    sensor.clone(),
+                    samples
+                )])),
+                async_broadcast::broadcast(1).0,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_apply_rollups() {
+        _ = load_configuration();
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let policy = DownsamplingPolicy::new(1.0, 3600).unwrap();
+        let mut labels = SensAppLabels::new();
+        labels.push(("room".to_string(), "kitchen".to_string()));
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                "temperature".to_string(),
+                SensorType::Float,
+                None,
+                Some(labels),
+            )
+            .unwrap()
+            .with_downsampling(Some(policy)),
+        );
+        let without_policy = Arc::new(
+            Sensor::new_without_uuid("humidity".to_string(), SensorType::Float, None, None)
+                .unwrap(),
+        );
+        // Three hours of samples every minute, and a last one in the raw window
+        publish(&storage, &sensor, samples(0..180)).await;
+        publish(&storage, &without_policy, samples(0..180)).await;
+        let now = SensAppDateTime::from_unix_seconds_i64(START + 86400 + 3 * 3600 + 1800);
+        publish(&storage, &sensor, samples(1500..1501)).await;
+        assert_eq!(
+            storage
+                .get_sensor(sensor.uuid)
+                .await
+                .unwrap()
+                .unwrap()
+                .downsampling,
+            Some(policy)
+        );
+
+        let report = apply_rollups(&storage, now).await.unwrap();
+        assert_eq!(
+            report,
+            RollupReport {
+                sensors: 1,
+                buckets: 3
+            }
+        );
+
+        let rollup_sensor = rollup_sensor(&sensor, &policy).unwrap();
+        assert_eq!(rollup_sensor.name, "temperature__rollup_1h__");
+        let rollups = storage
+            .query_sensor_data(rollup_sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rollups.sensor.labels, sensor.labels);
+        assert_eq!(rollups.sensor.downsampling, None);
+        let hour = |hour: i64, value: f64| Sample {
+            datetime: SensAppDateTime::from_unix_seconds_i64(START + hour * 3600),
+            value,
+        };
+        assert_eq!(
+            rollups.samples,
+            TypedSamples::Float(smallvec![hour(0, 29.5), hour(1, 89.5), hour(2, 149.5)])
+        );
+
+        // Only the new complete buckets are rolled up again
+        assert_eq!(
+            apply_rollups(&storage, now).await.unwrap(),
+            RollupReport::default()
+        );
+        publish(&storage, &sensor, samples(180..240)).await;
+        let now = now + Duration::from_seconds(3600.0);
+        assert_eq!(
+            apply_rollups(&storage, now).await.unwrap(),
+            RollupReport {
+                sensors: 1,
+                buckets: 1
+            }
+        );
+        let rollups = storage
+            .query_sensor_data(rollup_sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rollups.samples.len(), 4);
+    }
+}
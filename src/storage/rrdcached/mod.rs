@@ -2,7 +2,7 @@ use crate::{
     datamodel::{Sensor, SensorType, TypedSamples},
     storage::storage::StorageInstance,
 };
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use axum::async_trait;
 use rrdcached_client::{
     batch_update::BatchUpdate,
@@ -16,13 +16,314 @@ use tokio::{sync::RwLock, time::timeout};
 use url::Url;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug)]
 pub enum Preset {
     Munin,
     Hoarder,
+    Custom(Vec<CreateRoundRobinArchive>),
+}
+
+const DEFAULT_STEP_SECONDS: u64 = 10;
+const DEFAULT_HEARTBEAT: i64 = 20;
+
+fn parse_consolidation_function(s: &str) -> Result<ConsolidationFunction> {
+    match s.to_uppercase().as_str() {
+        "AVERAGE" | "AVG" => Ok(ConsolidationFunction::Average),
+        "MIN" => Ok(ConsolidationFunction::Min),
+        "MAX" => Ok(ConsolidationFunction::Max),
+        "LAST" => Ok(ConsolidationFunction::Last),
+        _ => bail!("Invalid consolidation function: {}", s),
+    }
+}
+
+/// Parses a single round robin archive definition, such as `AVERAGE:0.5:1:8640`.
+///
+/// The format is `consolidation function:xfiles factor:steps:rows`,
+/// like the rrdtool RRA definitions without the `RRA:` prefix.
+fn parse_round_robin_archive(definition: &str) -> Result<CreateRoundRobinArchive> {
+    let parts = definition.split(':').map(str::trim).collect::<Vec<_>>();
+    if parts.len() != 4 {
+        bail!(
+            "Invalid RRA definition '{}', expected CF:xfiles_factor:steps:rows",
+            definition
+        );
+    }
+
+    let consolidation_function = parse_consolidation_function(parts[0])
+        .with_context(|| format!("Invalid RRA definition '{}'", definition))?;
+    let xfiles_factor: f64 = parts[1].parse().with_context(|| {
+        format!(
+            "Invalid xfiles_factor '{}' in RRA definition '{}'",
+            parts[1], definition
+        )
+    })?;
+    // rrdtool accepts 0, which means that any unknown data point
+    // makes the consolidated value unknown, but not 1 or more.
+    if !(0.0..1.0).contains(&xfiles_factor) {
+        bail!(
+            "Invalid xfiles_factor '{}' in RRA definition '{}', it must be between 0 (inclusive) and 1 (exclusive)",
+            parts[1],
+            definition
+        );
+    }
+    let steps: i64 = parts[2].parse().with_context(|| {
+        format!(
+            "Invalid steps '{}' in RRA definition '{}'",
+            parts[2], definition
+        )
+    })?;
+    if steps <= 0 {
+        bail!(
+            "Invalid steps '{}' in RRA definition '{}', it must be positive",
+            parts[2],
+            definition
+        );
+    }
+    let rows: i64 = parts[3].parse().with_context(|| {
+        format!(
+            "Invalid rows '{}' in RRA definition '{}'",
+            parts[3], definition
+        )
+    })?;
+    if rows <= 0 {
+        bail!(
+            "Invalid rows '{}' in RRA definition '{}', it must be positive",
+            parts[3],
+            definition
+        );
+    }
+
+    Ok(CreateRoundRobinArchive {
+        consolidation_function,
+        xfiles_factor,
+        steps,
+        rows,
+    })
+}
+
+/// Parses a duration in seconds, either short like `90s`, `5m`, `12h`, `30d`
+/// and `1w`, or ISO-8601 like `PT5M` and `P30D`.
+pub fn parse_duration_seconds(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let upper = s.to_uppercase();
+    if let Some(iso) = upper.strip_prefix('P') {
+        let (date, time) = match iso.split_once('T') {
+            Some((date, time)) => (date, Some(time)),
+            None => (iso, None),
+        };
+        let mut seconds = 0;
+        let mut components = 0;
+        for (part, units) in [
+            (date, &[('W', 604_800), ('D', 86_400)][..]),
+            (
+                time.unwrap_or_default(),
+                &[('H', 3_600), ('M', 60), ('S', 1)][..],
+            ),
+        ] {
+            let mut rest = part;
+            for (unit, unit_seconds) in units {
+                if let Some((value, remaining)) = rest.split_once(*unit) {
+                    let value: u64 = value
+                        .parse()
+                        .with_context(|| format!("Invalid ISO-8601 duration '{}'", s))?;
+                    seconds += value * unit_seconds;
+                    components += 1;
+                    rest = remaining;
+                }
+            }
+            if !rest.is_empty() {
+                bail!("Invalid ISO-8601 duration '{}'", s);
+            }
+        }
+        if components == 0 || time == Some("") {
+            bail!("Invalid ISO-8601 duration '{}'", s);
+        }
+        return Ok(seconds);
+    }
+
+    let unit_start = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("Missing unit in duration '{}'", s))?;
+    let (value, unit) = s.split_at(unit_start);
+    let value: u64 = value
+        .parse()
+        .with_context(|| format!("Invalid duration '{}'", s))?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        "w" => 604_800,
+        _ => bail!("Invalid unit '{}' in duration '{}'", unit, s),
+    };
+    Ok(value * unit_seconds)
+}
+
+/// Parses a human readable round robin archive definition, such as `avg/5m/30d`.
+///
+/// The format is `consolidation function/resolution/retention`, where the
+/// resolution must be a multiple of the step. The xfiles factor is 0.5,
+/// like in the presets.
+fn parse_human_round_robin_archive(
+    definition: &str,
+    step_seconds: u64,
+) -> Result<CreateRoundRobinArchive> {
+    let parts = definition.split('/').map(str::trim).collect::<Vec<_>>();
+    if parts.len() != 3 {
+        bail!(
+            "Invalid RRA definition '{}', expected CF/resolution/retention",
+            definition
+        );
+    }
+
+    let consolidation_function = parse_consolidation_function(parts[0])
+        .with_context(|| format!("Invalid RRA definition '{}'", definition))?;
+    let resolution = parse_duration_seconds(parts[1])
+        .with_context(|| format!("Invalid resolution in RRA definition '{}'", definition))?;
+    if resolution == 0 || resolution % step_seconds != 0 {
+        bail!(
+            "Invalid resolution '{}' in RRA definition '{}', it must be a multiple of the {} seconds step",
+            parts[1],
+            definition,
+            step_seconds
+        );
+    }
+    let retention = parse_duration_seconds(parts[2])
+        .with_context(|| format!("Invalid retention in RRA definition '{}'", definition))?;
+    if retention < resolution {
+        bail!(
+            "Invalid retention '{}' in RRA definition '{}', it must be at least the resolution",
+            parts[2],
+            definition
+        );
+    }
+
+    Ok(CreateRoundRobinArchive {
+        consolidation_function,
+        xfiles_factor: 0.5,
+        steps: (resolution / step_seconds) as i64,
+        rows: retention.div_ceil(resolution) as i64,
+    })
+}
+
+/// Parses a list of round robin archives separated by semicolons,
+/// such as `AVERAGE:0.5:1:8640;AVERAGE:0.5:60:1008` or `avg/5m/30d;max/1h/52w`.
+///
+/// The human readable definitions are converted to steps and rows using `step_seconds`.
+pub fn parse_round_robin_archives(
+    spec: &str,
+    step_seconds: u64,
+) -> Result<Vec<CreateRoundRobinArchive>> {
+    let archives = spec
+        .split(';')
+        .filter(|definition| !definition.trim().is_empty())
+        .map(|definition| {
+            if definition.contains('/') {
+                parse_human_round_robin_archive(definition, step_seconds)
+            } else {
+                parse_round_robin_archive(definition)
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if archives.is_empty() {
+        bail!("The RRA specification must contain at least one archive");
+    }
+    Ok(archives)
+}
+
+/// The samples as RRD values, by unix timestamp in seconds,
+/// or `None` for the types RRD can't store.
+fn rrd_values(samples: &TypedSamples) -> Option<Vec<(usize, f64)>> {
+    use rust_decimal::prelude::ToPrimitive;
+    let timestamp =
+        |datetime: &crate::datamodel::SensAppDateTime| datetime.to_unix_seconds().floor() as usize;
+    Some(match samples {
+        TypedSamples::Float(samples) => samples
+            .iter()
+            .map(|sample| (timestamp(&sample.datetime), sample.value))
+            .collect(),
+        TypedSamples::Numeric(samples) => samples
+            .iter()
+            .map(|sample| {
+                (
+                    timestamp(&sample.datetime),
+                    sample.value.to_f64().unwrap_or(f64::NAN),
+                )
+            })
+            .collect(),
+        TypedSamples::Integer(samples) => samples
+            .iter()
+            .map(|sample| (timestamp(&sample.datetime), sample.value as f64))
+            .collect(),
+        TypedSamples::Boolean(samples) => samples
+            .iter()
+            .map(|sample| {
+                let value = if sample.value { 1.0 } else { 0.0 };
+                (timestamp(&sample.datetime), value)
+            })
+            .collect(),
+        _ => return None,
+    })
+}
+
+/// Coalesces the values sharing an RRD step into a single value,
+/// consolidated with the consolidation function, at the timestamp
+/// of the latest value of the step.
+///
+/// RRD rejects the updates that aren't after the previous update,
+/// so the dense sub-step samples would otherwise fail to update.
+/// Returns the values sorted by timestamp, and the number of values
+/// merged into another value.
+fn coalesce_steps(
+    mut values: Vec<(usize, f64)>,
+    step_seconds: u64,
+    consolidation_function: ConsolidationFunction,
+) -> (Vec<(usize, f64)>, usize) {
+    let step_seconds = step_seconds.max(1) as usize;
+    values.sort_by_key(|(timestamp, _)| *timestamp);
+    let count = values.len();
+    let mut coalesced: Vec<(usize, f64)> = Vec::with_capacity(count);
+    let mut step_counts: Vec<usize> = Vec::with_capacity(count);
+    for (timestamp, value) in values {
+        match (coalesced.last_mut(), step_counts.last_mut()) {
+            (Some((last_timestamp, last_value)), Some(step_count))
+                if *last_timestamp / step_seconds == timestamp / step_seconds =>
+            {
+                *last_timestamp = timestamp;
+                *last_value = match consolidation_function {
+                    ConsolidationFunction::Average => *last_value + value,
+                    ConsolidationFunction::Min => last_value.min(value),
+                    ConsolidationFunction::Max => last_value.max(value),
+                    ConsolidationFunction::Last => value,
+                };
+                *step_count += 1;
+            }
+            _ => {
+                coalesced.push((timestamp, value));
+                step_counts.push(1);
+            }
+        }
+    }
+    if consolidation_function == ConsolidationFunction::Average {
+        // The values were summed
+        for ((_, value), step_count) in coalesced.iter_mut().zip(step_counts) {
+            *value /= step_count as f64;
+        }
+    }
+    let merged = count - coalesced.len();
+    (coalesced, merged)
 }
 
 impl Preset {
+    /// The consolidation function of the first archive, to consolidate
+    /// the samples sharing a step.
+    pub fn step_consolidation_function(&self) -> ConsolidationFunction {
+        self.get_round_robin_archives()
+            .first()
+            .map(|archive| archive.consolidation_function)
+            .unwrap_or(ConsolidationFunction::Average)
+    }
+
     pub fn get_round_robin_archives(&self) -> Vec<CreateRoundRobinArchive> {
         match self {
             Preset::Munin => vec![
@@ -92,6 +393,15 @@ impl Preset {
                     rows: 3650,
                 },
             ],
+            Preset::Custom(archives) => archives
+                .iter()
+                .map(|archive| CreateRoundRobinArchive {
+                    consolidation_function: archive.consolidation_function,
+                    xfiles_factor: archive.xfiles_factor,
+                    steps: archive.steps,
+                    rows: archive.rows,
+                })
+                .collect(),
         }
     }
 }
@@ -115,6 +425,62 @@ pub struct RrdCachedStorage {
     created_sensors: Arc<RwLock<HashSet<Uuid>>>,
 
     preset: Preset,
+
+    step_seconds: u64,
+
+    heartbeat: i64,
+}
+
+/// The options of the RRDCached storage, parsed from the connection string query.
+#[derive(Debug)]
+struct RrdCachedOptions {
+    preset: Preset,
+    step_seconds: u64,
+    heartbeat: i64,
+}
+
+impl RrdCachedOptions {
+    fn from_url(url: &Url) -> Result<Self> {
+        let query_value = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+
+        let step_seconds = match query_value("step_seconds") {
+            Some(step_seconds) => step_seconds
+                .parse()
+                .with_context(|| format!("Invalid step_seconds: {}", step_seconds))?,
+            None => DEFAULT_STEP_SECONDS,
+        };
+        if step_seconds == 0 {
+            bail!("step_seconds must be positive");
+        }
+
+        let preset = match (query_value("preset"), query_value("rra")) {
+            (Some(_), Some(_)) => bail!("The preset and rra options cannot be used together"),
+            (Some(preset), None) => preset.parse()?,
+            (None, Some(rra)) => Preset::Custom(parse_round_robin_archives(&rra, step_seconds)?),
+            // Default to Hoarder if not specified
+            (None, None) => Preset::Hoarder,
+        };
+
+        let heartbeat = match query_value("heartbeat") {
+            Some(heartbeat) => heartbeat
+                .parse()
+                .with_context(|| format!("Invalid heartbeat: {}", heartbeat))?,
+            None => DEFAULT_HEARTBEAT,
+        };
+        if heartbeat <= 0 {
+            bail!("heartbeat must be positive");
+        }
+
+        Ok(Self {
+            preset,
+            step_seconds,
+            heartbeat,
+        })
+    }
 }
 
 impl RrdCachedStorage {
@@ -122,12 +488,11 @@ impl RrdCachedStorage {
         let url = Url::parse(connection_string)?;
         let scheme = url.scheme();
 
-        let preset = url
-            .query_pairs()
-            .find(|(key, _)| key == "preset")
-            .map(|(_, value)| value.parse())
-            .transpose()?
-            .unwrap_or(Preset::Hoarder); // Default to Hoarder if not specified
+        let RrdCachedOptions {
+            preset,
+            step_seconds,
+            heartbeat,
+        } = RrdCachedOptions::from_url(&url)?;
 
         match scheme {
             "rrdcached" | "rrdcached+tcp" => {
@@ -140,6 +505,8 @@ impl RrdCachedStorage {
                     client: Arc::new(RwLock::new(client)),
                     created_sensors: Arc::new(RwLock::new(HashSet::new())),
                     preset,
+                    step_seconds,
+                    heartbeat,
                 })
             }
             "rrdcached+unix" => {
@@ -163,12 +530,12 @@ impl RrdCachedStorage {
                         name: "sensapp".to_string(),
                         minimum: None,
                         maximum: None,
-                        heartbeat: 20,
+                        heartbeat: self.heartbeat,
                         serie_type: CreateDataSourceType::Gauge,
                     }],
                     round_robin_archives: self.preset.get_round_robin_archives(),
                     start_timestamp,
-                    step_seconds: 10,
+                    step_seconds: self.step_seconds,
                 })
                 .await?;
             created_sensors.insert(sensor.uuid);
@@ -182,6 +549,11 @@ impl StorageInstance for RrdCachedStorage {
     async fn create_or_migrate(&self) -> Result<()> {
         Ok(())
     }
+
+    async fn check_schema(&self) -> Result<()> {
+        // The RRD files are created with the sensors, there is no schema
+        Ok(())
+    }
     async fn publish(
         &self,
         batch: std::sync::Arc<crate::datamodel::batch::Batch>,
@@ -193,70 +565,30 @@ impl StorageInstance for RrdCachedStorage {
 
         let mut batch_updates = vec![];
         let mut min_timestamp = usize::MAX;
+        let mut coalesced = 0;
+        let consolidation_function = self.preset.step_consolidation_function();
 
         for single_sensor_batch in batch.sensors.as_ref() {
             let samples_guard = single_sensor_batch.samples.read().await;
-            let uuid = single_sensor_batch.sensor.uuid;
-            let name = uuid.to_string();
-            match &*samples_guard {
-                TypedSamples::Float(samples) => {
-                    for value in samples {
-                        let timestamp = value.datetime.to_unix_seconds().floor() as usize;
-                        if timestamp < min_timestamp {
-                            min_timestamp = timestamp;
-                        }
-                        batch_updates.push(BatchUpdate::new(
-                            &name,
-                            Some(timestamp),
-                            vec![value.value],
-                        )?);
-                    }
-                }
-                TypedSamples::Numeric(samples) => {
-                    for value in samples {
-                        let timestamp = value.datetime.to_unix_seconds().floor() as usize;
-                        if timestamp < min_timestamp {
-                            min_timestamp = timestamp;
-                        }
-                        use rust_decimal::prelude::ToPrimitive;
-                        batch_updates.push(BatchUpdate::new(
-                            &name,
-                            Some(timestamp),
-                            vec![value.value.to_f64().unwrap_or(f64::NAN)],
-                        )?);
-                    }
-                }
-                TypedSamples::Integer(samples) => {
-                    for value in samples {
-                        let timestamp = value.datetime.to_unix_seconds().floor() as usize;
-                        if timestamp < min_timestamp {
-                            min_timestamp = timestamp;
-                        }
-                        batch_updates.push(BatchUpdate::new(
-                            &name,
-                            Some(timestamp),
-                            vec![value.value as f64],
-                        )?);
-                    }
-                }
-                TypedSamples::Boolean(samples) => {
-                    for value in samples {
-                        let timestamp = value.datetime.to_unix_seconds().floor() as usize;
-                        if timestamp < min_timestamp {
-                            min_timestamp = timestamp;
-                        }
-                        batch_updates.push(BatchUpdate::new(
-                            &name,
-                            Some(timestamp),
-                            vec![if value.value { 1.0 } else { 0.0 }],
-                        )?);
-                    }
-                }
-                _ => {
-                    print!("Unsupported type");
-                }
+            let Some(values) = rrd_values(&samples_guard) else {
+                print!("Unsupported type");
+                continue;
+            };
+            let (values, sensor_coalesced) =
+                coalesce_steps(values, self.step_seconds, consolidation_function);
+            coalesced += sensor_coalesced;
+            let name = single_sensor_batch.sensor.uuid.to_string();
+            for (timestamp, value) in values {
+                min_timestamp = min_timestamp.min(timestamp);
+                batch_updates.push(BatchUpdate::new(&name, Some(timestamp), vec![value])?);
             }
         }
+        if coalesced > 0 {
+            tracing::debug!(
+                "Coalesced {} samples sharing an RRD step with other samples",
+                coalesced
+            );
+        }
 
         // Find the sensors that need to be created
         let sensors_to_create: Vec<Arc<Sensor>>;
@@ -277,7 +609,7 @@ impl StorageInstance for RrdCachedStorage {
                 .collect::<Vec<_>>();
         }
         if !sensors_to_create.is_empty() {
-            self.create_sensors(&sensors_to_create, min_timestamp as u64 - 10)
+            self.create_sensors(&sensors_to_create, min_timestamp as u64 - self.step_seconds)
                 .await?;
         }
 
@@ -327,3 +659,181 @@ impl StorageInstance for RrdCachedStorage {
         unimplemented!();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rra_strings(archives: &[CreateRoundRobinArchive]) -> Vec<String> {
+        archives.iter().map(|archive| archive.to_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_round_robin_archives() {
+        let archives =
+            parse_round_robin_archives("AVERAGE:0.5:1:8640;max:0:60:1008;", DEFAULT_STEP_SECONDS)
+                .unwrap();
+        assert_eq!(
+            rra_strings(&archives),
+            vec!["RRA:AVERAGE:0.5:1:8640", "RRA:MAX:0:60:1008"]
+        );
+        assert_eq!(
+            archives[1].consolidation_function,
+            ConsolidationFunction::Max
+        );
+        assert_eq!(archives[1].xfiles_factor, 0.0);
+
+        let preset = Preset::Custom(archives);
+        assert_eq!(
+            rra_strings(&preset.get_round_robin_archives()),
+            vec!["RRA:AVERAGE:0.5:1:8640", "RRA:MAX:0:60:1008"]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_round_robin_archives() {
+        assert!(parse_round_robin_archives("", DEFAULT_STEP_SECONDS).is_err());
+        assert!(parse_round_robin_archives("AVERAGE:0.5:1", DEFAULT_STEP_SECONDS).is_err());
+        assert!(parse_round_robin_archives("MEDIAN:0.5:1:8640", DEFAULT_STEP_SECONDS).is_err());
+        assert!(parse_round_robin_archives("AVERAGE:-0.1:1:8640", DEFAULT_STEP_SECONDS).is_err());
+        assert!(parse_round_robin_archives("AVERAGE:1:1:8640", DEFAULT_STEP_SECONDS).is_err());
+        assert!(parse_round_robin_archives("AVERAGE:0.5:0:8640", DEFAULT_STEP_SECONDS).is_err());
+        assert!(parse_round_robin_archives("AVERAGE:0.5:1:-5", DEFAULT_STEP_SECONDS).is_err());
+        assert!(parse_round_robin_archives(
+            "AVERAGE:0.5:1:8640;AVERAGE:abc:1:1",
+            DEFAULT_STEP_SECONDS
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_human_round_robin_archives() {
+        let archives =
+            parse_round_robin_archives("avg/5m/30d;max/1h/52w;last/PT10S/P1D", 10).unwrap();
+        assert_eq!(
+            rra_strings(&archives),
+            vec![
+                "RRA:AVERAGE:0.5:30:8640",
+                "RRA:MAX:0.5:360:8736",
+                "RRA:LAST:0.5:1:8640"
+            ]
+        );
+
+        // Mixed with the rrdtool definitions and another step
+        let archives = parse_round_robin_archives("AVERAGE:0:1:1440;min/P1DT12H/P1W", 60).unwrap();
+        assert_eq!(
+            rra_strings(&archives),
+            vec!["RRA:AVERAGE:0:1:1440", "RRA:MIN:0.5:2160:5"]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_human_round_robin_archives() {
+        // Not a multiple of the step
+        assert!(parse_round_robin_archives("avg/15s/1d", 10).is_err());
+        assert!(parse_round_robin_archives("avg/90s/1d", 60).is_err());
+        assert!(parse_round_robin_archives("avg/0s/1d", 10).is_err());
+        assert!(parse_round_robin_archives("avg/5m", 10).is_err());
+        assert!(parse_round_robin_archives("median/5m/30d", 10).is_err());
+        assert!(parse_round_robin_archives("avg/5/30d", 10).is_err());
+        assert!(parse_round_robin_archives("avg/5y/30d", 10).is_err());
+        assert!(parse_round_robin_archives("avg/PT/P30D", 10).is_err());
+        assert!(parse_round_robin_archives("avg/P5X/P30D", 10).is_err());
+        assert!(parse_round_robin_archives("avg/1h/5m", 10).is_err());
+    }
+
+    #[test]
+    fn test_coalesce_steps() {
+        // Four samples in the 1700000000 step of 10 seconds, one in the next
+        let values = vec![
+            (1_700_000_004, 4.0),
+            (1_700_000_001, 1.0),
+            (1_700_000_009, 9.0),
+            (1_700_000_002, 2.0),
+            (1_700_000_010, 10.0),
+        ];
+        let (average, coalesced) =
+            coalesce_steps(values.clone(), 10, ConsolidationFunction::Average);
+        assert_eq!(average, vec![(1_700_000_009, 4.0), (1_700_000_010, 10.0)]);
+        assert_eq!(coalesced, 3);
+
+        for (consolidation_function, expected) in [
+            (ConsolidationFunction::Min, 1.0),
+            (ConsolidationFunction::Max, 9.0),
+            (ConsolidationFunction::Last, 9.0),
+        ] {
+            let (values, _) = coalesce_steps(values.clone(), 10, consolidation_function);
+            assert_eq!(values[0], (1_700_000_009, expected));
+        }
+
+        // A single update is sent for the step
+        let samples = TypedSamples::Integer(
+            [1_700_000_001, 1_700_000_003, 1_700_000_007]
+                .into_iter()
+                .map(|timestamp| crate::datamodel::Sample {
+                    datetime: crate::datamodel::SensAppDateTime::from_unix_seconds(
+                        timestamp as f64,
+                    ),
+                    value: timestamp % 10,
+                })
+                .collect(),
+        );
+        let (values, coalesced) = coalesce_steps(
+            rrd_values(&samples).unwrap(),
+            10,
+            Preset::Hoarder.step_consolidation_function(),
+        );
+        assert_eq!(coalesced, 2);
+        let updates = values
+            .into_iter()
+            .map(|(timestamp, value)| {
+                BatchUpdate::new("sensor", Some(timestamp), vec![value])
+                    .unwrap()
+                    .to_command_string()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            updates,
+            vec!["UPDATE sensor.rrd 1700000007:3.6666666666666665\n"]
+        );
+    }
+
+    #[test]
+    fn test_options_from_url() {
+        let url = Url::parse("rrdcached://localhost:42217").unwrap();
+        let options = RrdCachedOptions::from_url(&url).unwrap();
+        assert!(matches!(options.preset, Preset::Hoarder));
+        assert_eq!(options.step_seconds, 10);
+        assert_eq!(options.heartbeat, 20);
+
+        let url = Url::parse(
+            "rrdcached://localhost:42217?rra=AVERAGE:0.5:1:8640;AVERAGE:0.5:60:1008&step_seconds=60&heartbeat=120",
+        )
+        .unwrap();
+        let options = RrdCachedOptions::from_url(&url).unwrap();
+        assert_eq!(
+            rra_strings(&options.preset.get_round_robin_archives()),
+            vec!["RRA:AVERAGE:0.5:1:8640", "RRA:AVERAGE:0.5:60:1008"]
+        );
+        assert_eq!(options.step_seconds, 60);
+        assert_eq!(options.heartbeat, 120);
+
+        let url =
+            Url::parse("rrdcached://localhost:42217?preset=munin&rra=AVERAGE:0.5:1:10").unwrap();
+        assert!(RrdCachedOptions::from_url(&url).is_err());
+
+        let url = Url::parse("rrdcached://localhost:42217?rra=avg/5m/30d&step_seconds=60").unwrap();
+        let options = RrdCachedOptions::from_url(&url).unwrap();
+        assert_eq!(
+            rra_strings(&options.preset.get_round_robin_archives()),
+            vec!["RRA:AVERAGE:0.5:5:8640"]
+        );
+
+        let url = Url::parse("rrdcached://localhost:42217?rra=avg/5m/30d&step_seconds=7").unwrap();
+        assert!(RrdCachedOptions::from_url(&url).is_err());
+
+        let url = Url::parse("rrdcached://localhost:42217?step_seconds=0").unwrap();
+        assert!(RrdCachedOptions::from_url(&url).is_err());
+    }
+}
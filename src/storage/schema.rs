@@ -0,0 +1,160 @@
+use super::storage::StorageInstance;
+use crate::datamodel::SensorType;
+use anyhow::{bail, Context, Result};
+use sqlx::migrate::Migrator;
+
+/// The tables of the SQL storages, created by their migrations.
+pub const EXPECTED_TABLES: [&str; 15] = [
+    "units",
+    "sensors",
+    "labels_name_dictionary",
+    "labels_description_dictionary",
+    "labels",
+    "strings_values_dictionary",
+    "integer_values",
+    "numeric_values",
+    "float_values",
+    "string_values",
+    "boolean_values",
+    "location_values",
+    "json_values",
+    "blob_values",
+    "annotations",
+];
+
+/// The value table of each sample type.
+///
+/// The storages drop the empty value tables of the disabled sample types
+/// after migrating, so the migrations altering them must not expect them.
+pub const VALUE_TABLES: [(SensorType, &str); 8] = [
+    (SensorType::Integer, "integer_values"),
+    (SensorType::Numeric, "numeric_values"),
+    (SensorType::Float, "float_values"),
+    (SensorType::String, "string_values"),
+    (SensorType::Boolean, "boolean_values"),
+    (SensorType::Location, "location_values"),
+    (SensorType::Json, "json_values"),
+    (SensorType::Blob, "blob_values"),
+];
+
+/// The table where sqlx records the applied migrations.
+pub const MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+
+/// The enabled sample types of the configuration,
+/// all of them when the configuration isn't loaded.
+pub fn enabled_sample_types() -> Result<Vec<SensorType>> {
+    match crate::config::get() {
+        Ok(config) => config.enabled_sample_types(),
+        Err(_) => Ok(SensorType::ALL.to_vec()),
+    }
+}
+
+/// Checks that the expected tables exist, and that the latest applied
+/// migration is at least the latest migration of the migrator.
+///
+/// The value tables of the disabled sample types may be missing.
+pub fn check_schema(
+    migrator: &Migrator,
+    applied_version: Option<i64>,
+    tables: &[String],
+    enabled_sample_types: &[SensorType],
+) -> Result<()> {
+    let disabled_table = |table: &str| {
+        VALUE_TABLES.iter().any(|(sensor_type, value_table)| {
+            *value_table == table && !enabled_sample_types.contains(sensor_type)
+        })
+    };
+    let missing_tables = EXPECTED_TABLES
+        .iter()
+        .filter(|table| !disabled_table(table))
+        .filter(|table| !tables.iter().any(|name| name == *table))
+        .copied()
+        .collect::<Vec<_>>();
+    if !missing_tables.is_empty() {
+        bail!(
+            "The database schema is missing the tables: {}",
+            missing_tables.join(", ")
+        );
+    }
+
+    let required_version = migrator
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default();
+    match applied_version {
+        Some(applied_version) if applied_version >= required_version => Ok(()),
+        Some(applied_version) => bail!(
+            "The database schema is out of date, at migration {} while {} is required",
+            applied_version,
+            required_version
+        ),
+        None => bail!(
+            "The database schema has no applied migrations, {} is required",
+            required_version
+        ),
+    }
+}
+
+/// Creates or migrates the storage schema, or with `skip_migrations`,
+/// only checks that the schema provisioned separately is compatible.
+pub async fn prepare_storage(storage: &dyn StorageInstance, skip_migrations: bool) -> Result<()> {
+    if skip_migrations {
+        return storage
+            .check_schema()
+            .await
+            .context("The storage schema isn't compatible, and SENSAPP_SKIP_MIGRATIONS is set");
+    }
+    storage
+        .create_or_migrate()
+        .await
+        .context("Failed to create or migrate the storage")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::sqlite::SqliteStorage;
+
+    #[tokio::test]
+    async fn test_prepare_storage_skip_migrations() {
+        let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+        let error = prepare_storage(&storage, true).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("missing the tables"));
+
+        prepare_storage(&storage, false).await.unwrap();
+        prepare_storage(&storage, true).await.unwrap();
+    }
+
+    #[test]
+    fn test_check_schema() {
+        let migrator = sqlx::migrate!("src/storage/sqlite/migrations");
+        let latest = migrator.iter().map(|migration| migration.version).max();
+        let tables = EXPECTED_TABLES
+            .iter()
+            .map(|table| table.to_string())
+            .collect::<Vec<_>>();
+
+        let all = SensorType::ALL;
+
+        assert!(check_schema(&migrator, latest, &tables, &all).is_ok());
+        let error = check_schema(&migrator, Some(20240110093153), &tables, &all).unwrap_err();
+        assert!(error.to_string().contains("out of date"));
+        assert!(check_schema(&migrator, None, &tables, &all).is_err());
+        let error = check_schema(&migrator, latest, &tables[1..], &all).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The database schema is missing the tables: units"
+        );
+
+        // Only the float values are required when only the floats are enabled
+        let floats_only = tables
+            .iter()
+            .filter(|table| !table.ends_with("_values") || *table == "float_values")
+            .cloned()
+            .collect::<Vec<_>>();
+        assert!(check_schema(&migrator, latest, &floats_only, &[SensorType::Float]).is_ok());
+        let error = check_schema(&migrator, latest, &floats_only, &all).unwrap_err();
+        assert!(error.to_string().contains("integer_values"));
+    }
+}
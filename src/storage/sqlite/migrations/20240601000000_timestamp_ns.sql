@@ -0,0 +1,10 @@
+-- Optional nanosecond timestamps, used when SENSAPP_SQLITE_NANOSECOND_TIME is enabled.
+-- timestamp_ms is still filled, and indexed, in both modes.
+ALTER TABLE integer_values ADD COLUMN timestamp_ns INTEGER; -- Unix timestamp in nanoseconds, null in millisecond mode
+ALTER TABLE numeric_values ADD COLUMN timestamp_ns INTEGER; -- Unix timestamp in nanoseconds, null in millisecond mode
+ALTER TABLE float_values ADD COLUMN timestamp_ns INTEGER; -- Unix timestamp in nanoseconds, null in millisecond mode
+ALTER TABLE string_values ADD COLUMN timestamp_ns INTEGER; -- Unix timestamp in nanoseconds, null in millisecond mode
+ALTER TABLE boolean_values ADD COLUMN timestamp_ns INTEGER; -- Unix timestamp in nanoseconds, null in millisecond mode
+ALTER TABLE location_values ADD COLUMN timestamp_ns INTEGER; -- Unix timestamp in nanoseconds, null in millisecond mode
+ALTER TABLE json_values ADD COLUMN timestamp_ns INTEGER; -- Unix timestamp in nanoseconds, null in millisecond mode
+ALTER TABLE blob_values ADD COLUMN timestamp_ns INTEGER; -- Unix timestamp in nanoseconds, null in millisecond mode
@@ -0,0 +1,3 @@
+-- Optional physical bounds of the sensor values, for the range violation policy.
+ALTER TABLE sensors ADD COLUMN min_value REAL; -- Minimum value, null when unbounded
+ALTER TABLE sensors ADD COLUMN max_value REAL; -- Maximum value, null when unbounded
@@ -0,0 +1,20 @@
+-- String values can be stored inline, without the dictionary.
+-- SQLite can't drop the NOT NULL constraint, so the table is rebuilt.
+CREATE TABLE string_values_new (
+    sensor_id INTEGER NOT NULL, -- References 'sensors' (sensor_id), cannot be null
+    timestamp_ms INTEGER NOT NULL, -- Unix timestamp in milliseconds, cannot be null
+    value INTEGER, -- References 'strings_values_dictionary', null when inline
+    timestamp_ns INTEGER, -- Unix timestamp in nanoseconds, null in millisecond mode
+    inline_value TEXT, -- The value itself, null when in the dictionary
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id), -- Foreign key to 'sensors' table
+    FOREIGN KEY (value) REFERENCES strings_values_dictionary(id), -- Foreign key to 'strings_values_dictionary'
+    CHECK ((value IS NULL) <> (inline_value IS NULL)) -- Exactly one of the layouts
+) STRICT;
+
+INSERT INTO string_values_new (sensor_id, timestamp_ms, value, timestamp_ns)
+SELECT sensor_id, timestamp_ms, value, timestamp_ns FROM string_values;
+
+DROP TABLE string_values;
+ALTER TABLE string_values_new RENAME TO string_values;
+
+CREATE INDEX index_string_values ON string_values(sensor_id, timestamp_ms);
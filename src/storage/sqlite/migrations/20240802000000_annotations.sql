@@ -0,0 +1,13 @@
+-- Create the 'annotations' table, for the events on the timeline of a sensor
+CREATE TABLE annotations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT, -- Auto-incrementing primary key
+    sensor_id INTEGER NOT NULL, -- References 'sensors' (sensor_id), cannot be null
+    timestamp_ms INTEGER NOT NULL, -- Unix timestamp in milliseconds, cannot be null
+    timestamp_ns INTEGER, -- Unix timestamp in nanoseconds, null in millisecond mode
+    title TEXT NOT NULL, -- Short title, cannot be null
+    text TEXT, -- Optional description
+    tags TEXT NOT NULL, -- JSON array of strings, cannot be null
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id) -- Foreign key to 'sensors' table
+) STRICT;
+
+CREATE INDEX index_annotations ON annotations(sensor_id, timestamp_ms);
@@ -0,0 +1,2 @@
+-- When the sensors were first stored, null for the sensors created before.
+ALTER TABLE sensors ADD COLUMN created_at INTEGER; -- Unix timestamp in milliseconds
@@ -0,0 +1,2 @@
+-- Expected time between two samples of the evenly sampled sensors, to detect the gaps.
+ALTER TABLE sensors ADD COLUMN nominal_interval_seconds REAL; -- Seconds, null when unknown
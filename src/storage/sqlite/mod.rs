@@ -1,5 +1,6 @@
 pub mod sqlite;
 pub mod sqlite_publishers;
+pub mod sqlite_queries;
 pub mod sqlite_utilities;
 
 // rexport SqliteStorage
@@ -1,27 +1,119 @@
 use super::sqlite_publishers::*;
-use super::sqlite_utilities::get_sensor_id_or_create_sensor;
+use super::sqlite_queries::*;
+use super::sqlite_utilities::{
+    clear_caches, get_sensor_id_or_create_sensor, touch_sensor, update_sensor_metadata,
+};
 use crate::datamodel::batch::{Batch, SingleSensorBatch};
-use crate::datamodel::TypedSamples;
+use crate::datamodel::{
+    sensor_metadata::SensorMetadata, Annotation, SensAppDateTime, Sensor, SensorData, SensorType,
+    TypedSamples,
+};
+use crate::storage::blob_compression::BlobCompression;
+use crate::storage::query::{
+    query_samples_in_batches, sensor_query_limit, AggregateBucket, InvalidQueryError,
+    SensorSelector, SortOrder, TimeRange,
+};
+use crate::storage::raw_sql::{validate_read_only_select, RawSqlResult, RawSqlTimeoutError};
+use crate::storage::schema::{check_schema, enabled_sample_types, MIGRATIONS_TABLE, VALUE_TABLES};
 use crate::storage::storage::StorageInstance;
+use crate::storage::strict_sensors::strict_sensors;
+use crate::storage::type_conflict::{coerce_samples, type_conflict_policy, TypeConflictPolicy};
+use crate::storage::verify::VerifyReport;
 use anyhow::{Context, Result};
 use async_broadcast::Sender;
 use async_trait::async_trait;
 use sqlx::{prelude::*, Sqlite, Transaction};
-use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteSynchronous},
+    SqlitePool,
+};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::timeout;
+use uuid::Uuid;
 
 // SQLite implementation
 #[derive(Debug)]
 pub struct SqliteStorage {
     pool: SqlitePool,
+    /// Store the nanosecond timestamps next to the millisecond timestamps.
+    nanosecond_time: bool,
+    /// Identifies the database in the process-wide caches.
+    database_id: Uuid,
+    /// Sensors per transaction when publishing, 0 for the whole batch.
+    sensors_per_transaction: usize,
+    /// Dictionary-encoded or inline string values.
+    string_storage: StringStorage,
+    /// Compression of the stored blob values.
+    blob_compression: BlobCompression,
+    /// Rejects the samples of the unknown sensors instead of creating them.
+    strict_sensors: bool,
+    /// For the sensors of an existing name and labels, but of another type.
+    type_conflict_policy: TypeConflictPolicy,
+    /// Only their value tables exist once migrated.
+    enabled_sample_types: Vec<SensorType>,
+}
+
+/// The pragmas of the connection string that sqlx doesn't parse, like
+/// `sqlite://sensapp.db?busy_timeout=10000&synchronous=normal&cache_size=-64000`.
+#[derive(Debug, Default)]
+struct SqlitePragmas {
+    /// In milliseconds.
+    busy_timeout: Option<Duration>,
+    synchronous: Option<SqliteSynchronous>,
+    /// In pages, or in KiB when negative.
+    cache_size: Option<i64>,
+}
+
+impl SqlitePragmas {
+    /// Splits the pragmas from the connection string, and returns
+    /// the connection string without them.
+    fn split(connection_string: &str) -> Result<(String, Self)> {
+        let mut pragmas = Self::default();
+        let Some((base, query)) = connection_string.split_once('?') else {
+            return Ok((connection_string.to_string(), pragmas));
+        };
+        let mut parameters = Vec::new();
+        for parameter in query.split('&') {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            match key {
+                "busy_timeout" => {
+                    let milliseconds = value
+                        .parse()
+                        .with_context(|| format!("Invalid SQLite busy_timeout: {}", value))?;
+                    pragmas.busy_timeout = Some(Duration::from_millis(milliseconds));
+                }
+                "synchronous" => {
+                    pragmas.synchronous = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("Invalid SQLite synchronous: {}", value))?,
+                    );
+                }
+                "cache_size" => {
+                    pragmas.cache_size = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("Invalid SQLite cache_size: {}", value))?,
+                    );
+                }
+                _ => parameters.push(parameter),
+            }
+        }
+        if parameters.is_empty() {
+            return Ok((base.to_string(), pragmas));
+        }
+        Ok((format!("{}?{}", base, parameters.join("&")), pragmas))
+    }
 }
 
 impl SqliteStorage {
+    /// Connects to the database, with the optional `busy_timeout`, `synchronous`,
+    /// and `cache_size` pragmas as parameters of the connection string.
     pub async fn connect(connection_string: &str) -> Result<Self> {
-        let connect_options = SqliteConnectOptions::from_str(connection_string)
+        let (connection_string, pragmas) = SqlitePragmas::split(connection_string)?;
+        let mut connect_options = SqliteConnectOptions::from_str(&connection_string)
             .context("Failed to create sqlite connection options")?
             // Create the database file if it doesn't exist
             .create_if_missing(true)
@@ -31,14 +123,108 @@ impl SqliteStorage {
             // Foreign keys have a performance impact, they are disabled by default
             // in SQLite, but we want to make sure they stay disabled.
             .foreign_keys(false)
-            // Set a busy timeout of 5 seconds
-            .busy_timeout(Duration::from_secs(5));
+            // Set a busy timeout of 5 seconds by default
+            .busy_timeout(pragmas.busy_timeout.unwrap_or(Duration::from_secs(5)));
+        if let Some(synchronous) = pragmas.synchronous {
+            connect_options = connect_options.synchronous(synchronous);
+        }
+        if let Some(cache_size) = pragmas.cache_size {
+            connect_options = connect_options.pragma("cache_size", cache_size.to_string());
+        }
 
         let pool = sqlx::SqlitePool::connect_with(connect_options)
             .await
             .context("Failed to create sqlite pool")?;
 
-        Ok(Self { pool })
+        // Milliseconds by default, also when the configuration isn't loaded.
+        let nanosecond_time = crate::config::get()
+            .map(|config| config.sqlite_nanosecond_time)
+            .unwrap_or_default();
+        let sensors_per_transaction = crate::config::get()
+            .map(|config| config.publish_sensors_per_tx)
+            .unwrap_or_default();
+        let string_storage = match crate::config::get() {
+            Ok(config) => config.string_storage.parse()?,
+            Err(_) => StringStorage::default(),
+        };
+
+        Ok(Self {
+            pool,
+            nanosecond_time,
+            database_id: Uuid::new_v4(),
+            sensors_per_transaction,
+            string_storage,
+            blob_compression: BlobCompression::from_config()?,
+            strict_sensors: strict_sensors(),
+            type_conflict_policy: type_conflict_policy(),
+            enabled_sample_types: enabled_sample_types()?,
+        })
+    }
+
+    /// Creates the value tables of the enabled sample types, and drops
+    /// the empty ones of the disabled types, as the migrations create
+    /// them all. The value tables with samples are kept.
+    async fn sync_value_tables(&self) -> Result<()> {
+        let tables: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table'")
+                .fetch_all(&self.pool)
+                .await?;
+        for (sensor_type, table) in VALUE_TABLES {
+            if self.enabled_sample_types.contains(&sensor_type) {
+                sqlx::raw_sql(value_table_ddl(sensor_type))
+                    .execute(&self.pool)
+                    .await
+                    .with_context(|| format!("Failed to create the {} table", table))?;
+            } else if tables.iter().any(|name| name == table) {
+                let has_samples: bool =
+                    sqlx::query_scalar(&format!("SELECT EXISTS (SELECT 1 FROM {})", table))
+                        .fetch_one(&self.pool)
+                        .await?;
+                if !has_samples {
+                    sqlx::query(&format!("DROP TABLE {}", table))
+                        .execute(&self.pool)
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The virtual machine instructions between two deadline checks
+/// of the raw SQL queries.
+const RAW_SQL_PROGRESS_OPS: i32 = 10_000;
+
+/// The result code of an interrupted statement.
+const SQLITE_INTERRUPT: &str = "9";
+
+/// Types the errors of a raw SQL query, interrupted or invalid.
+fn raw_sql_error(error: anyhow::Error, timeout: Duration) -> anyhow::Error {
+    match error.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(database_error))
+            if database_error.code().as_deref() == Some(SQLITE_INTERRUPT) =>
+        {
+            RawSqlTimeoutError(timeout).into()
+        }
+        Some(sqlx::Error::Database(database_error)) => {
+            InvalidQueryError(database_error.message().to_string()).into()
+        }
+        _ => error,
+    }
+}
+
+/// The schema of the value table of the sample type,
+/// with the changes of all the migrations.
+fn value_table_ddl(sensor_type: SensorType) -> &'static str {
+    match sensor_type {
+        SensorType::Integer => include_str!("value_tables/integer_values.sql"),
+        SensorType::Numeric => include_str!("value_tables/numeric_values.sql"),
+        SensorType::Float => include_str!("value_tables/float_values.sql"),
+        SensorType::String => include_str!("value_tables/string_values.sql"),
+        SensorType::Boolean => include_str!("value_tables/boolean_values.sql"),
+        SensorType::Location => include_str!("value_tables/location_values.sql"),
+        SensorType::Json => include_str!("value_tables/json_values.sql"),
+        SensorType::Blob => include_str!("value_tables/blob_values.sql"),
     }
 }
 
@@ -50,16 +236,41 @@ impl StorageInstance for SqliteStorage {
             .run(&self.pool)
             .await
             .context("Failed to migrate database")?;
+        self.sync_value_tables().await?;
 
         Ok(())
     }
-    async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
-        let mut transaction = self.pool.begin().await?;
-        for single_sensor_batch in batch.sensors.as_ref() {
-            self.publish_single_sensor_batch(&mut transaction, single_sensor_batch)
+
+    async fn check_schema(&self) -> Result<()> {
+        let tables: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type IN ('table', 'view')")
+                .fetch_all(&self.pool)
                 .await?;
+        let applied_version = if tables.iter().any(|table| table == MIGRATIONS_TABLE) {
+            sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+                .fetch_one(&self.pool)
+                .await?
+        } else {
+            None
+        };
+        check_schema(
+            &sqlx::migrate!("src/storage/sqlite/migrations"),
+            applied_version,
+            &tables,
+            &self.enabled_sample_types,
+        )
+    }
+    async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
+        // The chunks are committed one by one, so a failure
+        // keeps the previous chunks of the batch.
+        for sensors in batch.sensor_chunks(self.sensors_per_transaction) {
+            let mut transaction = self.pool.begin().await?;
+            for single_sensor_batch in sensors {
+                self.publish_single_sensor_batch(&mut transaction, single_sensor_batch)
+                    .await?;
+            }
+            transaction.commit().await?;
         }
-        transaction.commit().await?;
         self.sync(sync_sender).await?;
         Ok(())
     }
@@ -81,6 +292,286 @@ impl StorageInstance for SqliteStorage {
     async fn list_sensors(&self) -> Result<Vec<String>> {
         unimplemented!();
     }
+
+    async fn health_check(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_sensor(&self, sensor_uuid: Uuid) -> Result<Option<Sensor>> {
+        Ok(get_sensor_by_uuid(&self.pool, sensor_uuid)
+            .await?
+            .map(|(_, sensor)| sensor))
+    }
+
+    async fn update_sensor_metadata(
+        &self,
+        sensor_uuid: Uuid,
+        metadata: &SensorMetadata,
+    ) -> Result<bool> {
+        update_sensor_metadata(&self.pool, sensor_uuid, metadata).await
+    }
+
+    async fn query_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let (sensor_id, sensor) = match get_sensor_by_uuid(&self.pool, sensor_uuid).await? {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let limit = sensor_query_limit(limit, sensor.sensor_type)?;
+        let bounds = QueryBounds::new(start, end, limit);
+        let samples = query_samples(&self.pool, sensor_id, sensor.sensor_type, &bounds).await?;
+        Ok(Some(SensorData::new(sensor, samples)))
+    }
+
+    async fn query_sensor_data_window(
+        &self,
+        sensor_uuid: Uuid,
+        window: hifitime::Duration,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let (sensor_id, sensor) = match get_sensor_by_uuid(&self.pool, sensor_uuid).await? {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let limit = sensor_query_limit(limit, sensor.sensor_type)?;
+        let latest = latest_sample_datetime(&self.pool, sensor_id, sensor.sensor_type).await?;
+        // Without samples, the bounds are empty to keep the sensor type
+        let bounds = match latest {
+            Some(latest) => QueryBounds::new(
+                Some(latest - window),
+                Some(latest + hifitime::Duration::from_nanoseconds(1.0)),
+                limit,
+            ),
+            None => QueryBounds::new(None, None, Some(0)),
+        };
+        let samples = query_samples(&self.pool, sensor_id, sensor.sensor_type, &bounds).await?;
+        Ok(Some(SensorData::new(sensor, samples)))
+    }
+
+    async fn query_sensor_data_by_value(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        order: SortOrder,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let (sensor_id, sensor) = match get_sensor_by_uuid(&self.pool, sensor_uuid).await? {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let limit = sensor_query_limit(limit, sensor.sensor_type)?;
+        let bounds = QueryBounds::new(start, end, limit);
+        let samples =
+            query_samples_by_value(&self.pool, sensor_id, sensor.sensor_type, &bounds, order)
+                .await?;
+        Ok(Some(SensorData::new(sensor, samples)))
+    }
+
+    async fn query_sensor_data_by_id(
+        &self,
+        sensor_id: i64,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let sensor = match get_sensor_by_id(&self.pool, sensor_id).await? {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let limit = sensor_query_limit(limit, sensor.sensor_type)?;
+        let bounds = QueryBounds::new(start, end, limit);
+        let samples = query_samples(&self.pool, sensor_id, sensor.sensor_type, &bounds).await?;
+        Ok(Some(SensorData::new(sensor, samples)))
+    }
+
+    async fn query(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorData>> {
+        // The UUIDs and the types are filtered by SQLite,
+        // and the label matchers on the loaded sensors.
+        let numeric_types = [SensorType::Integer, SensorType::Numeric, SensorType::Float];
+        let sensor_types = selector.numeric_only.then_some(&numeric_types[..]);
+        let uuids = list_sensor_uuids(&self.pool, selector.uuids.as_deref(), sensor_types).await?;
+
+        let mut sensors = Vec::new();
+        for uuid in uuids {
+            let (sensor_id, sensor) = match get_sensor_by_uuid(&self.pool, uuid).await? {
+                Some(sensor) => sensor,
+                None => continue,
+            };
+            if selector.matches(&sensor)? {
+                sensors.push((sensor_id, sensor));
+            }
+        }
+
+        let bounds = &QueryBounds::new(time_range.start, time_range.end, limit);
+        let pool = &self.pool;
+        query_samples_in_batches(sensors, |sensor_ids, sensor_type| async move {
+            query_samples_of_sensors(pool, &sensor_ids, sensor_type, bounds).await
+        })
+        .await
+    }
+
+    async fn apply_retention(&self, older_than: SensAppDateTime) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        delete_samples_older_than(&mut transaction, older_than, &self.enabled_sample_types).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_metric(&self, name: &str) -> Result<u64> {
+        let mut transaction = self.pool.begin().await?;
+        let deleted = delete_metric(&mut transaction, name, &self.enabled_sample_types).await?;
+        transaction.commit().await?;
+        // The cached sensor identifiers may point to the deleted sensors.
+        clear_caches().await;
+        Ok(deleted)
+    }
+
+    async fn unknown_sensors(&self, sensor_uuids: &[Uuid]) -> Result<Vec<Uuid>> {
+        let known_uuids = list_sensor_uuids(&self.pool, Some(sensor_uuids), None).await?;
+        Ok(sensor_uuids
+            .iter()
+            .filter(|uuid| !known_uuids.contains(uuid))
+            .copied()
+            .collect())
+    }
+
+    async fn latest_samples_all(&self, metric_filter: Option<&str>) -> Result<Vec<SensorData>> {
+        let mut latest_samples = Vec::new();
+        for &sensor_type in &self.enabled_sample_types {
+            latest_samples
+                .extend(query_latest_samples(&self.pool, sensor_type, metric_filter).await?);
+        }
+        latest_samples.sort_by_key(|(sensor_id, _, _)| *sensor_id);
+
+        let mut results = Vec::with_capacity(latest_samples.len());
+        for (_, uuid, samples) in latest_samples {
+            if let Some((_, sensor)) = get_sensor_by_uuid(&self.pool, uuid).await? {
+                results.push(SensorData::new(sensor, samples));
+            }
+        }
+        Ok(results)
+    }
+
+    async fn count_series_by_labels(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+    ) -> Result<Vec<(Sensor, u64)>> {
+        let numeric_types = [SensorType::Integer, SensorType::Numeric, SensorType::Float];
+        let sensor_types = selector.numeric_only.then_some(&numeric_types[..]);
+        let uuids = list_sensor_uuids(&self.pool, selector.uuids.as_deref(), sensor_types).await?;
+
+        let bounds = QueryBounds::new(time_range.start, time_range.end, None);
+        let mut results = Vec::new();
+        for uuid in uuids {
+            let (sensor_id, sensor) = match get_sensor_by_uuid(&self.pool, uuid).await? {
+                Some(sensor) => sensor,
+                None => continue,
+            };
+            if !selector.matches(&sensor)? {
+                continue;
+            }
+            let count = count_samples(&self.pool, sensor_id, sensor.sensor_type, &bounds).await?;
+            results.push((sensor, count));
+        }
+        Ok(results)
+    }
+
+    async fn aggregate_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+        step: hifitime::Duration,
+    ) -> Result<Option<Vec<AggregateBucket>>> {
+        let (sensor_id, sensor) = match get_sensor_by_uuid(&self.pool, sensor_uuid).await? {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let step_ms = (step.total_nanoseconds() / 1_000_000).max(1) as i64;
+        let bounds = QueryBounds::new(time_range.start, time_range.end, None);
+        let buckets =
+            aggregate_samples(&self.pool, sensor_id, sensor.sensor_type, &bounds, step_ms).await?;
+        Ok(Some(buckets))
+    }
+
+    async fn verify_sensor(&self, sensor_uuid: Uuid) -> Result<Option<VerifyReport>> {
+        let (sensor_id, sensor) = match get_sensor_by_uuid(&self.pool, sensor_uuid).await? {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let report = verify_samples(&self.pool, sensor_id, sensor.sensor_type).await?;
+        Ok(Some(report))
+    }
+
+    async fn add_annotation(&self, sensor_uuid: Uuid, annotation: &Annotation) -> Result<bool> {
+        let (sensor_id, _) = match get_sensor_by_uuid(&self.pool, sensor_uuid).await? {
+            Some(sensor) => sensor,
+            None => return Ok(false),
+        };
+        let mut transaction = self.pool.begin().await?;
+        publish_annotation(
+            &mut transaction,
+            sensor_id,
+            annotation,
+            self.nanosecond_time,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    async fn query_annotations(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+    ) -> Result<Option<Vec<Annotation>>> {
+        let (sensor_id, _) = match get_sensor_by_uuid(&self.pool, sensor_uuid).await? {
+            Some(sensor) => sensor,
+            None => return Ok(None),
+        };
+        let bounds = QueryBounds::new(time_range.start, time_range.end, None);
+        Ok(Some(
+            query_annotations(&self.pool, sensor_id, &bounds).await?,
+        ))
+    }
+
+    async fn raw_sql_query(
+        &self,
+        sql: &str,
+        max_rows: usize,
+        timeout: Duration,
+    ) -> Result<RawSqlResult> {
+        let sql = validate_read_only_select(sql)?;
+        // The connection is detached from the pool, and closed when dropped,
+        // so the query_only mode and the progress handler never leak
+        // to the other queries.
+        let mut connection = self.pool.acquire().await?.detach();
+        sqlx::query("PRAGMA query_only = ON")
+            .execute(&mut connection)
+            .await?;
+        // SQLite interrupts the statement once the handler returns false,
+        // instead of running it to the end in the background.
+        let deadline = Instant::now() + timeout;
+        connection
+            .lock_handle()
+            .await?
+            .set_progress_handler(RAW_SQL_PROGRESS_OPS, move || Instant::now() < deadline);
+        query_raw_sql(&mut connection, sql, max_rows)
+            .await
+            .map_err(|error| raw_sql_error(error, timeout))
+    }
 }
 
 impl SqliteStorage {
@@ -89,34 +580,71 @@ impl SqliteStorage {
         transaction: &mut Transaction<'_, Sqlite>,
         single_sensor_batch: &SingleSensorBatch,
     ) -> Result<()> {
-        let sensor_id =
-            get_sensor_id_or_create_sensor(transaction, &single_sensor_batch.sensor).await?;
+        let sensor = &single_sensor_batch.sensor;
+        let (sensor_id, sensor_type) = get_sensor_id_or_create_sensor(
+            transaction,
+            self.database_id,
+            sensor,
+            self.strict_sensors,
+            self.type_conflict_policy,
+        )
+        .await?;
+        touch_sensor(transaction, sensor_id).await?;
         {
             let samples_guard = single_sensor_batch.samples.read().await;
-            match &*samples_guard {
+            // Published to an existing sensor of another type, by the coerce policy.
+            let coerced_samples;
+            let samples = if sensor_type == sensor.sensor_type {
+                &*samples_guard
+            } else {
+                coerced_samples = coerce_samples(&sensor.name, &samples_guard, sensor_type)?;
+                &coerced_samples
+            };
+            match samples {
                 TypedSamples::Integer(samples) => {
-                    publish_integer_values(transaction, sensor_id, samples).await?;
+                    publish_integer_values(transaction, sensor_id, samples, self.nanosecond_time)
+                        .await?;
                 }
                 TypedSamples::Numeric(samples) => {
-                    publish_numeric_values(transaction, sensor_id, samples).await?;
+                    publish_numeric_values(transaction, sensor_id, samples, self.nanosecond_time)
+                        .await?;
                 }
                 TypedSamples::Float(samples) => {
-                    publish_float_values(transaction, sensor_id, samples).await?;
+                    publish_float_values(transaction, sensor_id, samples, self.nanosecond_time)
+                        .await?;
                 }
                 TypedSamples::String(samples) => {
-                    publish_string_values(transaction, sensor_id, samples).await?;
+                    publish_string_values(
+                        transaction,
+                        self.database_id,
+                        sensor_id,
+                        samples,
+                        self.nanosecond_time,
+                        self.string_storage,
+                    )
+                    .await?;
                 }
                 TypedSamples::Boolean(samples) => {
-                    publish_boolean_values(transaction, sensor_id, samples).await?;
+                    publish_boolean_values(transaction, sensor_id, samples, self.nanosecond_time)
+                        .await?;
                 }
                 TypedSamples::Location(samples) => {
-                    publish_location_values(transaction, sensor_id, samples).await?;
+                    publish_location_values(transaction, sensor_id, samples, self.nanosecond_time)
+                        .await?;
                 }
                 TypedSamples::Blob(samples) => {
-                    publish_blob_values(transaction, sensor_id, samples).await?;
+                    publish_blob_values(
+                        transaction,
+                        sensor_id,
+                        samples,
+                        self.nanosecond_time,
+                        self.blob_compression,
+                    )
+                    .await?;
                 }
                 TypedSamples::Json(samples) => {
-                    publish_json_values(transaction, sensor_id, samples).await?;
+                    publish_json_values(transaction, sensor_id, samples, self.nanosecond_time)
+                        .await?;
                 }
             }
         }
@@ -129,7 +657,7 @@ impl SqliteStorage {
             .execute(sqlx::query!(
                 r#"
             DELETE FROM integer_values WHERE rowid NOT IN (
-                SELECT MIN(rowid) FROM integer_values GROUP BY sensor_id, timestamp_ms, value
+                SELECT MIN(rowid) FROM integer_values GROUP BY sensor_id, timestamp_ms, timestamp_ns, value
             )
             "#
             ))
@@ -139,7 +667,7 @@ impl SqliteStorage {
             .execute(sqlx::query!(
                 r#"
             DELETE FROM float_values WHERE rowid NOT IN (
-                SELECT MIN(rowid) FROM float_values GROUP BY sensor_id, timestamp_ms, value
+                SELECT MIN(rowid) FROM float_values GROUP BY sensor_id, timestamp_ms, timestamp_ns, value
             )
             "#
             ))
@@ -153,3 +681,1272 @@ impl SqliteStorage {
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{sensapp_datetime::SensAppDateTimeExt, unit::Unit, Sample, Sensor};
+    use crate::storage::query::{LabelMatcher, LabelMatcherType, SENSORS_PER_SAMPLES_QUERY};
+    use crate::storage::strict_sensors::UnknownSensorError;
+    use crate::storage::type_conflict::TypeConflictError;
+    use smallvec::smallvec;
+
+    /// A connection string to a new in-memory database, shared by
+    /// the connections to it and gone with the last one.
+    pub fn memory_connection_string() -> String {
+        format!("sqlite:file:sensapp-test-{}?mode=memory", Uuid::new_v4())
+    }
+
+    /// A new migrated in-memory database.
+    pub async fn create_test_storage() -> SqliteStorage {
+        let storage = SqliteStorage::connect(&memory_connection_string())
+            .await
+            .unwrap();
+        storage.create_or_migrate().await.unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn test_connection_string_pragmas() {
+        let pragma = |storage: &SqliteStorage, name: &'static str| {
+            let pool = storage.pool.clone();
+            async move {
+                sqlx::query_scalar::<_, i64>(&format!("PRAGMA {}", name))
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+        // A file, as the in-memory databases don't use the WAL
+        let directory = std::env::temp_dir().join(format!("sensapp-test-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&directory).await.unwrap();
+        let path = directory.join("sensapp.db");
+
+        // The defaults
+        let storage = SqliteStorage::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        assert_eq!(pragma(&storage, "busy_timeout").await, 5000);
+        assert_eq!(pragma(&storage, "synchronous").await, 2);
+
+        // The other parameters are kept for sqlx
+        let storage = SqliteStorage::connect(&format!(
+            "sqlite://{}?busy_timeout=12000&mode=rwc&synchronous=normal&cache_size=-4000",
+            path.display()
+        ))
+        .await
+        .unwrap();
+        assert_eq!(pragma(&storage, "busy_timeout").await, 12000);
+        assert_eq!(pragma(&storage, "synchronous").await, 1);
+        assert_eq!(pragma(&storage, "cache_size").await, -4000);
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        for invalid in [
+            "busy_timeout=soon",
+            "synchronous=sometimes",
+            "cache_size=big",
+        ] {
+            assert!(
+                SqliteStorage::connect(&format!("sqlite://{}?{}", path.display(), invalid))
+                    .await
+                    .is_err(),
+                "{}",
+                invalid
+            );
+        }
+
+        storage.pool.close().await;
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_query_sensor_data() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_query_sensor_data_{}", Uuid::new_v4()),
+                SensorType::Float,
+                Some(Unit::new("Cel".to_string(), None)),
+                Some(smallvec![("room".to_string(), "kitchen".to_string())]),
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Float(
+            (0..5)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                    value: i as f64,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let sensor_data = storage
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.sensor.name, sensor.name);
+        assert_eq!(sensor_data.sensor.sensor_type, SensorType::Float);
+        assert_eq!(sensor_data.sensor.unit.unwrap().name, "Cel");
+        assert_eq!(sensor_data.sensor.labels, sensor.labels);
+        assert_eq!(sensor_data.samples.len(), 5);
+
+        let sensor_data = storage
+            .query_sensor_data(
+                sensor.uuid,
+                Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_001)),
+                Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_004)),
+                Some(2),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        match sensor_data.samples {
+            TypedSamples::Float(samples) => {
+                assert_eq!(
+                    samples.iter().map(|s| s.value).collect::<Vec<_>>(),
+                    vec![1.0, 2.0]
+                );
+            }
+            _ => panic!("Expected float samples"),
+        }
+
+        // A known sensor without samples in the time range
+        let sensor_data = storage
+            .query_sensor_data(
+                sensor.uuid,
+                Some(SensAppDateTime::from_unix_seconds_i64(1_800_000_000)),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.sensor.uuid, sensor.uuid);
+        assert_eq!(sensor_data.samples, TypedSamples::Float(smallvec![]));
+
+        assert!(storage
+            .query_sensor_data(Uuid::new_v4(), None, None, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_query_sensor_data_by_value() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_query_by_value_{}", Uuid::new_v4()),
+                SensorType::Float,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let values = [3.5, -1.0, 12.0, 7.25, 12.0, 0.0, 9.5];
+        let samples = TypedSamples::Float(
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i as i64),
+                    value: *value,
+                })
+                .collect(),
+        );
+        let string_sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_query_by_value_string_{}", Uuid::new_v4()),
+                SensorType::String,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(sensor.clone(), samples),
+            SingleSensorBatch::new(
+                string_sensor.clone(),
+                TypedSamples::one_string(
+                    "high".to_string(),
+                    SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+                )
+            ),
+        ]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let uuid = sensor.uuid;
+        let query = |order, limit| {
+            let storage = &storage;
+            async move {
+                match storage
+                    .query_sensor_data_by_value(uuid, None, None, order, limit)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .samples
+                {
+                    TypedSamples::Float(samples) => samples
+                        .iter()
+                        .map(|s| (s.datetime.to_unix_seconds() as i64 - 1_700_000_000, s.value))
+                        .collect::<Vec<_>>(),
+                    _ => panic!("Expected float samples"),
+                }
+            }
+        };
+
+        // The ties are sorted by datetime
+        assert_eq!(
+            query(SortOrder::Desc, Some(3)).await,
+            vec![(2, 12.0), (4, 12.0), (6, 9.5)]
+        );
+        assert_eq!(
+            query(SortOrder::Asc, Some(3)).await,
+            vec![(1, -1.0), (5, 0.0), (0, 3.5)]
+        );
+        assert_eq!(query(SortOrder::Desc, None).await.len(), values.len());
+
+        // Within the time range
+        let sensor_data = storage
+            .query_sensor_data_by_value(
+                sensor.uuid,
+                Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_003)),
+                Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_006)),
+                SortOrder::Desc,
+                Some(2),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            sensor_data.samples,
+            TypedSamples::Float(smallvec![
+                Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_004),
+                    value: 12.0,
+                },
+                Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_003),
+                    value: 7.25,
+                },
+            ])
+        );
+
+        assert!(storage
+            .query_sensor_data_by_value(string_sensor.uuid, None, None, SortOrder::Desc, Some(3))
+            .await
+            .is_err());
+        assert!(storage
+            .query_sensor_data_by_value(Uuid::new_v4(), None, None, SortOrder::Desc, Some(3))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_query_empty_string_sensor() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_query_empty_string_sensor_{}", Uuid::new_v4()),
+                SensorType::String,
+                None,
+                Some(smallvec![("floor".to_string(), "basement".to_string())]),
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::String(smallvec![Sample {
+            datetime: SensAppDateTime::from_unix_seconds_i64(1_600_000_000),
+            value: "open".to_string(),
+        }]);
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        // The sensor matches, but its only sample is before the time range
+        let selector = SensorSelector {
+            uuids: Some(vec![sensor.uuid]),
+            matchers: vec![LabelMatcher::new(
+                "floor".to_string(),
+                "basement".to_string(),
+                LabelMatcherType::Equal,
+            )],
+            numeric_only: false,
+            created_after: None,
+            last_seen_before: None,
+        };
+        let time_range = TimeRange::new(
+            Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_000)),
+            None,
+        );
+        let results = storage.query(&selector, time_range, None).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].sensor.uuid, sensor.uuid);
+        assert_eq!(results[0].samples, TypedSamples::String(smallvec![]));
+    }
+
+    #[tokio::test]
+    async fn test_query_in_batches() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        // More sensors than a batch, of two types, 3 samples each
+        let datetime = |i: i64| SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i);
+        let sensors = (0..SENSORS_PER_SAMPLES_QUERY + 2)
+            .map(|index| {
+                let sensor_type = match index % 2 {
+                    0 => SensorType::Integer,
+                    _ => SensorType::String,
+                };
+                Arc::new(
+                    Sensor::new_without_uuid(
+                        format!("test_query_in_batches_{}", Uuid::new_v4()),
+                        sensor_type,
+                        None,
+                        Some(smallvec![("building".to_string(), "a".to_string())]),
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let samples = |sensor: &Sensor| match sensor.sensor_type {
+            SensorType::Integer => TypedSamples::Integer(
+                (0..3)
+                    .map(|i| Sample {
+                        datetime: datetime(i),
+                        value: i,
+                    })
+                    .collect(),
+            ),
+            _ => TypedSamples::String(
+                (0..3)
+                    .map(|i| Sample {
+                        datetime: datetime(i),
+                        value: i.to_string(),
+                    })
+                    .collect(),
+            ),
+        };
+        let batch = Batch::new(
+            sensors
+                .iter()
+                .map(|sensor| SingleSensorBatch::new(sensor.clone(), samples(sensor)))
+                .collect(),
+        );
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let selector = SensorSelector {
+            matchers: vec![LabelMatcher::new(
+                "building".to_string(),
+                "a".to_string(),
+                LabelMatcherType::Equal,
+            )],
+            ..Default::default()
+        };
+        // The limit applies to each sensor
+        let results = storage
+            .query(&selector, TimeRange::default(), Some(2))
+            .await
+            .unwrap();
+        assert_eq!(results.len(), sensors.len());
+        for (sensor_data, sensor) in results.iter().zip(&sensors) {
+            assert_eq!(sensor_data.sensor.uuid, sensor.uuid);
+            let mut expected = samples(sensor);
+            match &mut expected {
+                TypedSamples::Integer(samples) => samples.truncate(2),
+                TypedSamples::String(samples) => samples.truncate(2),
+                _ => unreachable!(),
+            }
+            assert_eq!(sensor_data.samples, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nanosecond_time() {
+        _ = load_configuration();
+        let first = SensAppDateTime::from_unix_nanoseconds_i64(1_700_000_000_123_456_700);
+        let second = SensAppDateTime::from_unix_nanoseconds_i64(1_700_000_000_123_456_800);
+
+        for nanosecond_time in [false, true] {
+            let mut storage = create_test_storage().await;
+            storage.nanosecond_time = nanosecond_time;
+
+            let sensor = Arc::new(
+                Sensor::new_without_uuid(
+                    format!("test_nanosecond_time_{}", Uuid::new_v4()),
+                    SensorType::Integer,
+                    None,
+                    None,
+                )
+                .unwrap(),
+            );
+            let samples = TypedSamples::Integer(smallvec![
+                Sample {
+                    datetime: first,
+                    value: 1,
+                },
+                Sample {
+                    datetime: second,
+                    value: 2,
+                },
+            ]);
+            let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+            let (sync_sender, _) = async_broadcast::broadcast(1);
+            storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+            let sensor_data = storage
+                .query_sensor_data(sensor.uuid, None, None, None)
+                .await
+                .unwrap()
+                .unwrap();
+            let datetimes = match sensor_data.samples {
+                TypedSamples::Integer(samples) => {
+                    samples.iter().map(|s| s.datetime).collect::<Vec<_>>()
+                }
+                _ => panic!("Expected integer samples"),
+            };
+            if nanosecond_time {
+                assert_eq!(datetimes, vec![first, second]);
+            } else {
+                let millisecond = SensAppDateTime::from_unix_milliseconds_i64(1_700_000_000_123);
+                assert_eq!(datetimes, vec![millisecond, millisecond]);
+            }
+
+            // The end of the range is exclusive at the nanosecond
+            if nanosecond_time {
+                let sensor_data = storage
+                    .query_sensor_data(sensor.uuid, Some(first), Some(second), None)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(sensor_data.samples.len(), 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_count_series_by_labels() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let mut sensor_batches = smallvec![];
+        let mut uuids = Vec::new();
+        for (wing, count) in [("east", 3), ("east", 5), ("west", 2)] {
+            let sensor = Arc::new(
+                Sensor::new_without_uuid(
+                    format!("test_count_series_by_labels_{}", Uuid::new_v4()),
+                    SensorType::Integer,
+                    None,
+                    Some(smallvec![("wing".to_string(), wing.to_string())]),
+                )
+                .unwrap(),
+            );
+            uuids.push(sensor.uuid);
+            let samples = TypedSamples::Integer(
+                (0..count)
+                    .map(|i| Sample {
+                        datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                        value: i,
+                    })
+                    .collect(),
+            );
+            sensor_batches.push(SingleSensorBatch::new(sensor, samples));
+        }
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage
+            .publish(Arc::new(Batch::new(sensor_batches)), sync_sender)
+            .await
+            .unwrap();
+
+        let selector = SensorSelector {
+            matchers: vec![LabelMatcher::new(
+                "wing".to_string(),
+                "east".to_string(),
+                LabelMatcherType::Equal,
+            )],
+            ..Default::default()
+        };
+        let counts = storage
+            .count_series_by_labels(&selector, TimeRange::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            counts
+                .iter()
+                .map(|(sensor, count)| (sensor.uuid, *count))
+                .collect::<Vec<_>>(),
+            vec![(uuids[0], 3), (uuids[1], 5)]
+        );
+
+        // Within a time range
+        let counts = storage
+            .count_series_by_labels(
+                &SensorSelector::default(),
+                TimeRange::new(
+                    Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_001)),
+                    Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_004)),
+                ),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            counts.iter().map(|(_, count)| *count).collect::<Vec<_>>(),
+            vec![2, 3, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_sensors_per_transaction() {
+        _ = load_configuration();
+        let mut storage = create_test_storage().await;
+        storage.sensors_per_transaction = 3;
+
+        let sensors = (0..20)
+            .map(|i| {
+                Arc::new(
+                    Sensor::new_without_uuid(
+                        format!("test_sensors_per_transaction_{}", i),
+                        SensorType::Integer,
+                        None,
+                        None,
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let batch = Batch::new(
+            sensors
+                .iter()
+                .enumerate()
+                .map(|(i, sensor)| {
+                    SingleSensorBatch::new(
+                        sensor.clone(),
+                        TypedSamples::Integer(
+                            (0..5)
+                                .map(|j| Sample {
+                                    datetime: SensAppDateTime::from_unix_seconds_i64(
+                                        1_700_000_000 + j,
+                                    ),
+                                    value: i as i64 * 10 + j,
+                                })
+                                .collect(),
+                        ),
+                    )
+                })
+                .collect(),
+        );
+        assert_eq!(batch.sensor_chunks(3).count(), 7);
+        assert_eq!(batch.sensor_chunks(0).count(), 1);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        for (i, sensor) in sensors.iter().enumerate() {
+            let sensor_data = storage
+                .query_sensor_data(sensor.uuid, None, None, None)
+                .await
+                .unwrap()
+                .unwrap();
+            let TypedSamples::Integer(samples) = sensor_data.samples else {
+                panic!("Expected integer samples");
+            };
+            assert_eq!(
+                samples
+                    .iter()
+                    .map(|sample| sample.value)
+                    .collect::<Vec<_>>(),
+                (0..5).map(|j| i as i64 * 10 + j).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_sensor() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        // A duplicate, an out of order timestamp, and an infinite value.
+        let float_sensor = Arc::new(
+            Sensor::new_without_uuid("verify_float".to_string(), SensorType::Float, None, None)
+                .unwrap(),
+        );
+        let float_samples = TypedSamples::Float(
+            [(1, 1.0), (2, 2.0), (2, 2.5), (0, f64::INFINITY), (3, 3.0)]
+                .into_iter()
+                .map(|(second, value)| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + second),
+                    value,
+                })
+                .collect(),
+        );
+        let string_sensor = Arc::new(
+            Sensor::new_without_uuid("verify_string".to_string(), SensorType::String, None, None)
+                .unwrap(),
+        );
+        let string_samples = TypedSamples::String(smallvec![Sample {
+            datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+            value: "valid".to_string(),
+        }]);
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(float_sensor.clone(), float_samples),
+            SingleSensorBatch::new(string_sensor.clone(), string_samples),
+        ]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        // Invalid UTF-8 can't be published, so it's inserted directly.
+        let (string_sensor_id, _) = get_sensor_by_uuid(&storage.pool, string_sensor.uuid)
+            .await
+            .unwrap()
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO strings_values_dictionary (id, value) VALUES (1000, CAST(X'80ff' AS TEXT))",
+        )
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO string_values (sensor_id, timestamp_ms, value) VALUES (?, 1700000001000, 1000)",
+        )
+        .bind(string_sensor_id)
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+
+        let report = storage
+            .verify_sensor(float_sensor.uuid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.sample_count, 5);
+        assert_eq!(report.duplicate_timestamps, 1);
+        assert_eq!(report.out_of_order_timestamps, 1);
+        assert_eq!(report.non_finite_floats, 1);
+        assert_eq!(report.invalid_utf8_strings, 0);
+
+        let report = storage
+            .verify_sensor(string_sensor.uuid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.sample_count, 2);
+        assert_eq!(report.invalid_utf8_strings, 1);
+
+        assert!(storage
+            .verify_sensor(Uuid::new_v4())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_string_storage() {
+        _ = load_configuration();
+        for string_storage in [StringStorage::Dictionary, StringStorage::Inline] {
+            let mut storage = create_test_storage().await;
+            storage.string_storage = string_storage;
+
+            // Every value is unique, like log lines.
+            let sensor = Arc::new(
+                Sensor::new_without_uuid("logs".to_string(), SensorType::String, None, None)
+                    .unwrap(),
+            );
+            let values = (0..100)
+                .map(|_| format!("request {} served", Uuid::new_v4()))
+                .collect::<Vec<_>>();
+            let samples = || {
+                TypedSamples::String(
+                    values
+                        .iter()
+                        .enumerate()
+                        .map(|(i, value)| Sample {
+                            datetime: SensAppDateTime::from_unix_seconds_i64(
+                                1_700_000_000 + i as i64,
+                            ),
+                            value: value.clone(),
+                        })
+                        .collect(),
+                )
+            };
+            let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples())]);
+            storage
+                .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+                .await
+                .unwrap();
+
+            let sensor_data = storage
+                .query_sensor_data(sensor.uuid, None, None, None)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(sensor_data.samples, samples());
+
+            let dictionary_size: i64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM strings_values_dictionary")
+                    .fetch_one(&storage.pool)
+                    .await
+                    .unwrap();
+            let expected_size = match string_storage {
+                StringStorage::Dictionary => 100,
+                StringStorage::Inline => 0,
+            };
+            assert_eq!(dictionary_size, expected_size);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blob_compression() {
+        _ = load_configuration();
+        let mut stored_sizes = Vec::new();
+        for blob_compression in [
+            BlobCompression::None,
+            BlobCompression::Zstd,
+            BlobCompression::Lz4,
+        ] {
+            let mut storage = create_test_storage().await;
+            storage.blob_compression = blob_compression;
+
+            let sensor = Arc::new(
+                Sensor::new_without_uuid("firmware".to_string(), SensorType::Blob, None, None)
+                    .unwrap(),
+            );
+            let samples = || {
+                TypedSamples::Blob(
+                    (0..10)
+                        .map(|i| Sample {
+                            datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                            value: b"sensapp ".repeat(512),
+                        })
+                        .collect(),
+                )
+            };
+            let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples())]);
+            storage
+                .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+                .await
+                .unwrap();
+
+            let sensor_data = storage
+                .query_sensor_data(sensor.uuid, None, None, None)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(sensor_data.samples, samples());
+
+            let stored_size: i64 = sqlx::query_scalar("SELECT SUM(LENGTH(value)) FROM blob_values")
+                .fetch_one(&storage.pool)
+                .await
+                .unwrap();
+            stored_sizes.push(stored_size);
+        }
+        assert_eq!(stored_sizes[0], 10 * 8 * 512);
+        assert!(stored_sizes[1] < stored_sizes[0]);
+        assert!(stored_sizes[2] < stored_sizes[0]);
+    }
+
+    #[tokio::test]
+    async fn test_strict_sensors() {
+        _ = load_configuration();
+        let mut storage = create_test_storage().await;
+
+        let new_batch = |sensor: &Arc<Sensor>| {
+            Arc::new(Batch::new(smallvec![SingleSensorBatch::new(
+                sensor.clone(),
+                TypedSamples::Integer(smallvec![Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                    value: 42,
+                }]),
+            )]))
+        };
+        let known_sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_strict_sensors_known_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let unknown_sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_strict_sensors_unknown_{}", Uuid::new_v4()),
+                SensorType::Integer,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        storage
+            .publish(new_batch(&known_sensor), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        storage.strict_sensors = true;
+        assert_eq!(
+            storage
+                .unknown_sensors(&[known_sensor.uuid, unknown_sensor.uuid])
+                .await
+                .unwrap(),
+            vec![unknown_sensor.uuid]
+        );
+        storage
+            .publish(new_batch(&known_sensor), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        let error = storage
+            .publish(new_batch(&unknown_sensor), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap_err();
+        assert!(error.is::<UnknownSensorError>());
+        assert!(storage
+            .query_sensor_data(unknown_sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_query_sensor_data_by_id() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_query_sensor_data_by_id_{}", Uuid::new_v4()),
+                SensorType::Float,
+                Some(Unit::new("Cel".to_string(), None)),
+                Some(smallvec![("room".to_string(), "kitchen".to_string())]),
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Float(
+            (0..5)
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                    value: i as f64,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(sensor.clone(), samples)]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let start = Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_001));
+        let by_uuid = storage
+            .query_sensor_data(sensor.uuid, start, None, Some(3))
+            .await
+            .unwrap()
+            .unwrap();
+        let sensor_id = by_uuid.sensor.sensor_id.unwrap();
+        let by_id = storage
+            .query_sensor_data_by_id(sensor_id, start, None, Some(3))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(by_id.sensor.uuid, by_uuid.sensor.uuid);
+        assert_eq!(by_id.sensor.name, by_uuid.sensor.name);
+        assert_eq!(by_id.sensor.sensor_type, by_uuid.sensor.sensor_type);
+        assert_eq!(
+            by_id.sensor.unit.unwrap().name,
+            by_uuid.sensor.unit.unwrap().name
+        );
+        assert_eq!(by_id.sensor.labels, by_uuid.sensor.labels);
+        assert_eq!(by_id.sensor.sensor_id, Some(sensor_id));
+        assert_eq!(by_id.samples, by_uuid.samples);
+        assert_eq!(by_id.samples.len(), 3);
+
+        assert!(storage
+            .query_sensor_data_by_id(sensor_id + 1, None, None, None)
+            .await
+            .unwrap()
+            .is_none());
+
+        let metadata = storage.get_sensor(sensor.uuid).await.unwrap().unwrap();
+        assert_eq!(metadata.name, sensor.name);
+        assert_eq!(metadata.labels, by_uuid.sensor.labels);
+        assert_eq!(metadata.sensor_id, Some(sensor_id));
+        assert!(storage.get_sensor(Uuid::new_v4()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_default_query_limit_by_type() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+        let config = crate::config::get().unwrap();
+        let blob_limit = config.default_query_limit(SensorType::Blob).unwrap();
+        assert_eq!(config.default_query_limit(SensorType::Float), None);
+
+        let count = blob_limit + 5;
+        let datetime = |i: usize| SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i as i64);
+        let sensor = |name: &str, sensor_type| {
+            Arc::new(Sensor::new_without_uuid(name.to_string(), sensor_type, None, None).unwrap())
+        };
+        let blob_sensor = sensor("test_default_limit_blob", SensorType::Blob);
+        let float_sensor = sensor("test_default_limit_float", SensorType::Float);
+        let batch = Batch::new(smallvec![
+            SingleSensorBatch::new(
+                blob_sensor.clone(),
+                TypedSamples::Blob(
+                    (0..count)
+                        .map(|i| Sample {
+                            datetime: datetime(i),
+                            value: vec![i as u8; 16],
+                        })
+                        .collect(),
+                ),
+            ),
+            SingleSensorBatch::new(
+                float_sensor.clone(),
+                TypedSamples::Float(
+                    (0..count)
+                        .map(|i| Sample {
+                            datetime: datetime(i),
+                            value: i as f64,
+                        })
+                        .collect(),
+                ),
+            ),
+        ]);
+        let (sync_sender, _) = async_broadcast::broadcast(1);
+        storage.publish(Arc::new(batch), sync_sender).await.unwrap();
+
+        let query = |uuid, limit| storage.query_sensor_data(uuid, None, None, limit);
+        let blob_data = query(blob_sensor.uuid, None).await.unwrap().unwrap();
+        assert_eq!(blob_data.samples.len(), blob_limit);
+        let float_data = query(float_sensor.uuid, None).await.unwrap().unwrap();
+        assert_eq!(float_data.samples.len(), count);
+
+        // An explicit limit wins over the default limit of the type
+        let blob_data = query(blob_sensor.uuid, Some(count)).await.unwrap().unwrap();
+        assert_eq!(blob_data.samples.len(), count);
+    }
+
+    #[tokio::test]
+    async fn test_delete_metric() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let name = format!("test_delete_metric_{}", Uuid::new_v4());
+        let sensors = ["kitchen", "bedroom", "garage"].map(|room| {
+            Arc::new(
+                Sensor::new_without_uuid(
+                    name.clone(),
+                    SensorType::Float,
+                    None,
+                    Some(smallvec![("room".to_string(), room.to_string())]),
+                )
+                .unwrap(),
+            )
+        });
+        let new_batch = || {
+            Arc::new(Batch::new(
+                sensors
+                    .iter()
+                    .map(|sensor| {
+                        SingleSensorBatch::new(
+                            sensor.clone(),
+                            TypedSamples::one_float(
+                                21.5,
+                                SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+                            ),
+                        )
+                    })
+                    .collect(),
+            ))
+        };
+        storage
+            .publish(new_batch(), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        let uuids = sensors.iter().map(|sensor| sensor.uuid).collect::<Vec<_>>();
+        assert!(storage.unknown_sensors(&uuids).await.unwrap().is_empty());
+
+        assert_eq!(storage.delete_metric(&name).await.unwrap(), 3);
+        assert_eq!(storage.unknown_sensors(&uuids).await.unwrap(), uuids);
+        for table in ["labels", "float_values"] {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&storage.pool)
+                .await
+                .unwrap();
+            assert_eq!(count, 0);
+        }
+        assert_eq!(storage.delete_metric(&name).await.unwrap(), 0);
+
+        // The sensors are created again, not found in the caches.
+        storage
+            .publish(new_batch(), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        assert!(storage.unknown_sensors(&uuids).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_float_only_schema() {
+        _ = load_configuration();
+        let mut storage = create_test_storage().await;
+        storage.enabled_sample_types = vec![SensorType::Float];
+        storage.create_or_migrate().await.unwrap();
+        storage.check_schema().await.unwrap();
+
+        let value_tables = |storage: &SqliteStorage| {
+            let pool = storage.pool.clone();
+            async move {
+                let tables: Vec<String> = sqlx::query_scalar(
+                    "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE '%_values' ORDER BY name",
+                )
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+                tables
+            }
+        };
+        assert_eq!(value_tables(&storage).await, vec!["float_values"]);
+
+        let name = format!("test_float_only_schema_{}", Uuid::new_v4());
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(name.clone(), SensorType::Float, None, None).unwrap(),
+        );
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            TypedSamples::one_float(21.5, SensAppDateTime::from_unix_seconds_i64(1_700_000_000)),
+        )]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        storage
+            .apply_retention(SensAppDateTime::from_unix_seconds_i64(1_800_000_000))
+            .await
+            .unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM float_values")
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+        assert_eq!(storage.delete_metric(&name).await.unwrap(), 1);
+        assert!(storage.latest_samples_all(None).await.unwrap().is_empty());
+
+        // Enabling the types again creates their value tables.
+        storage.enabled_sample_types = SensorType::ALL.to_vec();
+        storage.create_or_migrate().await.unwrap();
+        storage.check_schema().await.unwrap();
+        assert_eq!(value_tables(&storage).await.len(), SensorType::ALL.len());
+    }
+
+    #[tokio::test]
+    async fn test_last_seen() {
+        _ = load_configuration();
+        let storage = create_test_storage().await;
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_last_seen_{}", Uuid::new_v4()),
+                SensorType::Float,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let publish = |samples: TypedSamples| {
+            storage.publish(
+                Arc::new(Batch::new(smallvec![SingleSensorBatch::new(
+                    sensor.clone(),
+                    samples,
+                )])),
+                async_broadcast::broadcast(1).0,
+            )
+        };
+        let last_seen = || async {
+            storage
+                .get_sensor(sensor.uuid)
+                .await
+                .unwrap()
+                .unwrap()
+                .last_seen
+                .unwrap()
+        };
+
+        let before = SensAppDateTime::now().unwrap();
+        publish(TypedSamples::one_float(
+            21.5,
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_000),
+        ))
+        .await
+        .unwrap();
+        let first_seen = last_seen().await;
+        // Stored in milliseconds
+        assert!(first_seen.to_unix_seconds() > before.to_unix_seconds() - 0.001);
+
+        // Without any new sample, the sensor is still seen
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        publish(TypedSamples::Float(smallvec![])).await.unwrap();
+        assert!(last_seen().await > first_seen);
+        let sensor_data = storage
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.samples.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_type_conflict_policy() {
+        _ = load_configuration();
+        let name = format!("test_type_conflict_policy_{}", Uuid::new_v4());
+        let labels = || Some(smallvec![("room".to_string(), "kitchen".to_string())]);
+        let float_sensor = Arc::new(
+            Sensor::new_without_uuid(name.clone(), SensorType::Float, None, labels()).unwrap(),
+        );
+        let string_sensor = Arc::new(
+            Sensor::new_without_uuid(name.clone(), SensorType::String, None, labels()).unwrap(),
+        );
+        let datetime = |i: i64| SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i);
+        async fn publish(
+            storage: &SqliteStorage,
+            sensor: &Arc<Sensor>,
+            samples: TypedSamples,
+        ) -> Result<()> {
+            storage
+                .publish(
+                    Arc::new(Batch::new(smallvec![SingleSensorBatch::new(
+                        sensor.clone(),
+                        samples,
+                    )])),
+                    async_broadcast::broadcast(1).0,
+                )
+                .await
+        }
+        let new_storage = |policy: TypeConflictPolicy| {
+            let float_sensor = float_sensor.clone();
+            async move {
+                let mut storage = create_test_storage().await;
+                storage.type_conflict_policy = policy;
+                publish(
+                    &storage,
+                    &float_sensor,
+                    TypedSamples::one_float(21.5, datetime(0)),
+                )
+                .await
+                .unwrap();
+                storage
+            }
+        };
+
+        // A distinct series per type
+        let storage = new_storage(TypeConflictPolicy::NewSeries).await;
+        publish(
+            &storage,
+            &string_sensor,
+            TypedSamples::one_string("22.5".to_string(), datetime(1)),
+        )
+        .await
+        .unwrap();
+        assert!(storage
+            .unknown_sensors(&[float_sensor.uuid, string_sensor.uuid])
+            .await
+            .unwrap()
+            .is_empty());
+
+        let storage = new_storage(TypeConflictPolicy::Reject).await;
+        let error = publish(
+            &storage,
+            &string_sensor,
+            TypedSamples::one_string("22.5".to_string(), datetime(1)),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<TypeConflictError>());
+        assert_eq!(
+            storage
+                .unknown_sensors(&[string_sensor.uuid])
+                .await
+                .unwrap(),
+            vec![string_sensor.uuid]
+        );
+        // Other labels aren't a conflict
+        let bedroom_sensor = Arc::new(
+            Sensor::new_without_uuid(
+                name.clone(),
+                SensorType::String,
+                None,
+                Some(smallvec![("room".to_string(), "bedroom".to_string())]),
+            )
+            .unwrap(),
+        );
+        publish(
+            &storage,
+            &bedroom_sensor,
+            TypedSamples::one_string("warm".to_string(), datetime(1)),
+        )
+        .await
+        .unwrap();
+
+        // Stored in the existing float sensor
+        let storage = new_storage(TypeConflictPolicy::Coerce).await;
+        publish(
+            &storage,
+            &string_sensor,
+            TypedSamples::one_string("22.5".to_string(), datetime(1)),
+        )
+        .await
+        .unwrap();
+        let error = publish(
+            &storage,
+            &string_sensor,
+            TypedSamples::one_string("warm".to_string(), datetime(2)),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<TypeConflictError>());
+        assert_eq!(
+            storage
+                .unknown_sensors(&[string_sensor.uuid])
+                .await
+                .unwrap(),
+            vec![string_sensor.uuid]
+        );
+        let sensor_data = storage
+            .query_sensor_data(float_sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            sensor_data.samples,
+            TypedSamples::Float(smallvec![
+                Sample {
+                    datetime: datetime(0),
+                    value: 21.5,
+                },
+                Sample {
+                    datetime: datetime(1),
+                    value: 22.5,
+                },
+            ])
+        );
+    }
+}
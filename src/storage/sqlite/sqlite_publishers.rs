@@ -1,7 +1,10 @@
-use super::sqlite_utilities::get_string_value_id_or_create;
-use crate::datamodel::Sample;
-use anyhow::Result;
+use super::sqlite_utilities::{get_string_value_id_or_create, sqlite_timestamps};
+use crate::datamodel::{Annotation, Sample};
+use crate::storage::blob_compression::BlobCompression;
+use anyhow::{bail, Result};
 use sqlx::{prelude::*, Sqlite, Transaction};
+use std::str::FromStr;
+use uuid::Uuid;
 
 /*
 Obviously not the most beautiful code,
@@ -13,16 +16,18 @@ pub async fn publish_integer_values(
     transaction: &mut Transaction<'_, Sqlite>,
     sensor_id: i64,
     values: &[Sample<i64>],
+    nanosecond_time: bool,
 ) -> Result<()> {
     for value in values {
-        let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
+        let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&value.datetime, nanosecond_time);
         let query = sqlx::query!(
             r#"
-            INSERT INTO integer_values (sensor_id, timestamp_ms, value)
-            VALUES (?, ?, ?)
+            INSERT INTO integer_values (sensor_id, timestamp_ms, timestamp_ns, value)
+            VALUES (?, ?, ?, ?)
             "#,
             sensor_id,
             timestamp_ms,
+            timestamp_ns,
             value.value
         );
         transaction.execute(query).await?;
@@ -34,17 +39,19 @@ pub async fn publish_numeric_values(
     transaction: &mut Transaction<'_, Sqlite>,
     sensor_id: i64,
     values: &[Sample<rust_decimal::Decimal>],
+    nanosecond_time: bool,
 ) -> Result<()> {
     for value in values {
-        let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
+        let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&value.datetime, nanosecond_time);
         let string_value = value.value.to_string();
         let query = sqlx::query!(
             r#"
-            INSERT INTO numeric_values (sensor_id, timestamp_ms, value)
-            VALUES (?, ?, ?)
+            INSERT INTO numeric_values (sensor_id, timestamp_ms, timestamp_ns, value)
+            VALUES (?, ?, ?, ?)
             "#,
             sensor_id,
             timestamp_ms,
+            timestamp_ns,
             string_value
         );
         transaction.execute(query).await?;
@@ -56,16 +63,18 @@ pub async fn publish_float_values(
     transaction: &mut Transaction<'_, Sqlite>,
     sensor_id: i64,
     values: &[Sample<f64>],
+    nanosecond_time: bool,
 ) -> Result<()> {
     for value in values {
-        let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
+        let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&value.datetime, nanosecond_time);
         let query = sqlx::query!(
             r#"
-            INSERT INTO float_values (sensor_id, timestamp_ms, value)
-            VALUES (?, ?, ?)
+            INSERT INTO float_values (sensor_id, timestamp_ms, timestamp_ns, value)
+            VALUES (?, ?, ?, ?)
             "#,
             sensor_id,
             timestamp_ms,
+            timestamp_ns,
             value.value
         );
         transaction.execute(query).await?;
@@ -73,24 +82,73 @@ pub async fn publish_float_values(
     Ok(())
 }
 
+/// How the string values are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringStorage {
+    /// Deduplicated in the `strings_values_dictionary` table,
+    /// for the repetitive strings.
+    #[default]
+    Dictionary,
+    /// In the `string_values` table, for the high-cardinality strings.
+    Inline,
+}
+
+impl FromStr for StringStorage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dictionary" => Ok(StringStorage::Dictionary),
+            "inline" => Ok(StringStorage::Inline),
+            _ => bail!(
+                "Invalid string storage: {}, expected dictionary or inline",
+                s
+            ),
+        }
+    }
+}
+
 pub async fn publish_string_values(
     transaction: &mut Transaction<'_, Sqlite>,
+    database_id: Uuid,
     sensor_id: i64,
     values: &[Sample<String>],
+    nanosecond_time: bool,
+    string_storage: StringStorage,
 ) -> Result<()> {
     for value in values {
-        let string_id = get_string_value_id_or_create(transaction, &value.value).await?;
-        let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
-        let query = sqlx::query!(
-            r#"
-            INSERT INTO string_values (sensor_id, timestamp_ms, value)
-            VALUES (?, ?, ?)
-            "#,
-            sensor_id,
-            timestamp_ms,
-            string_id,
-        );
-        transaction.execute(query).await?;
+        let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&value.datetime, nanosecond_time);
+        // The queries have different types, so they're executed in each arm.
+        match string_storage {
+            StringStorage::Dictionary => {
+                let string_id =
+                    get_string_value_id_or_create(transaction, database_id, &value.value).await?;
+                let query = sqlx::query!(
+                    r#"
+                    INSERT INTO string_values (sensor_id, timestamp_ms, timestamp_ns, value)
+                    VALUES (?, ?, ?, ?)
+                    "#,
+                    sensor_id,
+                    timestamp_ms,
+                    timestamp_ns,
+                    string_id,
+                );
+                transaction.execute(query).await?;
+            }
+            StringStorage::Inline => {
+                let query = sqlx::query!(
+                    r#"
+                INSERT INTO string_values (sensor_id, timestamp_ms, timestamp_ns, inline_value)
+                VALUES (?, ?, ?, ?)
+                "#,
+                    sensor_id,
+                    timestamp_ms,
+                    timestamp_ns,
+                    value.value,
+                );
+                transaction.execute(query).await?;
+            }
+        }
     }
     Ok(())
 }
@@ -99,16 +157,18 @@ pub async fn publish_boolean_values(
     transaction: &mut Transaction<'_, Sqlite>,
     sensor_id: i64,
     values: &[Sample<bool>],
+    nanosecond_time: bool,
 ) -> Result<()> {
     for value in values {
-        let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
+        let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&value.datetime, nanosecond_time);
         let query = sqlx::query!(
             r#"
-            INSERT INTO boolean_values (sensor_id, timestamp_ms, value)
-            VALUES (?, ?, ?)
+            INSERT INTO boolean_values (sensor_id, timestamp_ms, timestamp_ns, value)
+            VALUES (?, ?, ?, ?)
             "#,
             sensor_id,
             timestamp_ms,
+            timestamp_ns,
             value.value
         );
         transaction.execute(query).await?;
@@ -120,18 +180,20 @@ pub async fn publish_location_values(
     transaction: &mut Transaction<'_, Sqlite>,
     sensor_id: i64,
     values: &[Sample<geo::Point>],
+    nanosecond_time: bool,
 ) -> Result<()> {
     for value in values {
-        let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
+        let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&value.datetime, nanosecond_time);
         let lat = value.value.y();
         let lon = value.value.x();
         let query = sqlx::query!(
             r#"
-            INSERT INTO location_values (sensor_id, timestamp_ms, latitude, longitude)
-            VALUES (?, ?, ?, ?)
+            INSERT INTO location_values (sensor_id, timestamp_ms, timestamp_ns, latitude, longitude)
+            VALUES (?, ?, ?, ?, ?)
             "#,
             sensor_id,
             timestamp_ms,
+            timestamp_ns,
             lat,
             lon
         );
@@ -144,17 +206,22 @@ pub async fn publish_blob_values(
     transaction: &mut Transaction<'_, Sqlite>,
     sensor_id: i64,
     values: &[Sample<Vec<u8>>],
+    nanosecond_time: bool,
+    blob_compression: BlobCompression,
 ) -> Result<()> {
     for value in values {
-        let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
+        let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&value.datetime, nanosecond_time);
+        let stored_value = blob_compression.compress(&value.value)?;
+        let stored_value = stored_value.as_ref();
         let query = sqlx::query!(
             r#"
-            INSERT INTO blob_values (sensor_id, timestamp_ms, value)
-            VALUES (?, ?, ?)
+            INSERT INTO blob_values (sensor_id, timestamp_ms, timestamp_ns, value)
+            VALUES (?, ?, ?, ?)
             "#,
             sensor_id,
             timestamp_ms,
-            value.value
+            timestamp_ns,
+            stored_value
         );
         transaction.execute(query).await?;
     }
@@ -165,20 +232,46 @@ pub async fn publish_json_values(
     transaction: &mut Transaction<'_, Sqlite>,
     sensor_id: i64,
     values: &[Sample<serde_json::Value>],
+    nanosecond_time: bool,
 ) -> Result<()> {
     for value in values {
-        let timestamp_ms = value.datetime.to_unix_milliseconds().floor() as i64;
+        let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&value.datetime, nanosecond_time);
         let string_value = value.value.to_string();
         let query = sqlx::query!(
             r#"
-            INSERT INTO json_values (sensor_id, timestamp_ms, value)
-            VALUES (?, ?, ?)
+            INSERT INTO json_values (sensor_id, timestamp_ms, timestamp_ns, value)
+            VALUES (?, ?, ?, ?)
             "#,
             sensor_id,
             timestamp_ms,
+            timestamp_ns,
             string_value
         );
         transaction.execute(query).await?;
     }
     Ok(())
 }
+
+pub async fn publish_annotation(
+    transaction: &mut Transaction<'_, Sqlite>,
+    sensor_id: i64,
+    annotation: &Annotation,
+    nanosecond_time: bool,
+) -> Result<()> {
+    let (timestamp_ms, timestamp_ns) = sqlite_timestamps(&annotation.datetime, nanosecond_time);
+    let tags = serde_json::to_string(&annotation.tags)?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO annotations (sensor_id, timestamp_ms, timestamp_ns, title, text, tags)
+        VALUES (?, ?, ?, ?, ?, ?)
+        "#,
+        sensor_id,
+        timestamp_ms,
+        timestamp_ns,
+        annotation.title,
+        annotation.text,
+        tags,
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
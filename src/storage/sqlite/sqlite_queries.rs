@@ -0,0 +1,1205 @@
+use super::sqlite_utilities::sqlite_datetime;
+use crate::datamodel::{
+    downsampling_policy::DownsamplingPolicy, sensapp_datetime::SensAppDateTimeExt,
+    sensapp_vec::SensAppLabels, unit::Unit, Annotation, Sample, SensAppDateTime, SensAppVec,
+    Sensor, SensorType, TypedSamples,
+};
+use crate::storage::blob_compression::decompress_blob;
+use crate::storage::query::{AggregateBucket, InvalidQueryError, SensorSelector, SortOrder};
+use crate::storage::raw_sql::RawSqlResult;
+use crate::storage::verify::VerifyReport;
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::TryStreamExt;
+use serde_json::Value;
+use sqlx::{
+    sqlite::SqliteRow, Column, Executor, QueryBuilder, Row, Sqlite, SqliteConnection, SqlitePool,
+    Transaction, TypeInfo, ValueRef,
+};
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/*
+Like the publishers, one function per type
+to keep the sqlx validation of the queries.
+ */
+
+/// Returns the internal sensor_id and the sensor with the given UUID, if it exists.
+pub async fn get_sensor_by_uuid(pool: &SqlitePool, uuid: Uuid) -> Result<Option<(i64, Sensor)>> {
+    let uuid_string = uuid.to_string();
+    let sensor_row = sqlx::query!(
+        r#"
+        SELECT sensors.sensor_id AS "sensor_id!", sensors.name, sensors.type, units.name AS "unit_name?", units.description AS unit_description,
+            sensors.min_value, sensors.max_value, sensors.nominal_interval_seconds, sensors.created_at,
+            sensors.last_seen, sensors.raw_retention_days, sensors.rollup_interval_seconds
+        FROM sensors
+        LEFT JOIN units ON sensors.unit = units.id
+        WHERE sensors.uuid = ?
+        "#,
+        uuid_string
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let sensor_row = match sensor_row {
+        Some(sensor_row) => sensor_row,
+        None => return Ok(None),
+    };
+
+    let labels = query_sensor_labels(pool, sensor_row.sensor_id).await?;
+
+    let sensor_type = SensorType::from_str(&sensor_row.r#type)?;
+    let unit = sensor_row
+        .unit_name
+        .map(|name| Unit::new(name, sensor_row.unit_description));
+
+    Ok(Some((
+        sensor_row.sensor_id,
+        Sensor::new(uuid, sensor_row.name, sensor_type, unit, Some(labels))
+            .with_value_range(sensor_row.min_value, sensor_row.max_value)
+            .with_nominal_interval(sensor_row.nominal_interval_seconds)
+            .with_downsampling(DownsamplingPolicy::from_columns(
+                sensor_row.raw_retention_days,
+                sensor_row.rollup_interval_seconds,
+            ))
+            .with_sensor_id(sensor_row.sensor_id)
+            .with_created_at(
+                sensor_row
+                    .created_at
+                    .map(SensAppDateTime::from_unix_milliseconds_i64),
+            )
+            .with_last_seen(
+                sensor_row
+                    .last_seen
+                    .map(SensAppDateTime::from_unix_milliseconds_i64),
+            ),
+    )))
+}
+
+/// Returns a sensor by its integer id, without looking up its UUID.
+pub async fn get_sensor_by_id(pool: &SqlitePool, sensor_id: i64) -> Result<Option<Sensor>> {
+    let sensor_row = sqlx::query!(
+        r#"
+        SELECT sensors.uuid, sensors.name, sensors.type, units.name AS "unit_name?", units.description AS unit_description,
+            sensors.min_value, sensors.max_value, sensors.nominal_interval_seconds, sensors.created_at,
+            sensors.last_seen, sensors.raw_retention_days, sensors.rollup_interval_seconds
+        FROM sensors
+        LEFT JOIN units ON sensors.unit = units.id
+        WHERE sensors.sensor_id = ?
+        "#,
+        sensor_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let sensor_row = match sensor_row {
+        Some(sensor_row) => sensor_row,
+        None => return Ok(None),
+    };
+
+    let labels = query_sensor_labels(pool, sensor_id).await?;
+    let uuid = Uuid::parse_str(&sensor_row.uuid)?;
+    let sensor_type = SensorType::from_str(&sensor_row.r#type)?;
+    let unit = sensor_row
+        .unit_name
+        .map(|name| Unit::new(name, sensor_row.unit_description));
+
+    Ok(Some(
+        Sensor::new(uuid, sensor_row.name, sensor_type, unit, Some(labels))
+            .with_value_range(sensor_row.min_value, sensor_row.max_value)
+            .with_nominal_interval(sensor_row.nominal_interval_seconds)
+            .with_downsampling(DownsamplingPolicy::from_columns(
+                sensor_row.raw_retention_days,
+                sensor_row.rollup_interval_seconds,
+            ))
+            .with_sensor_id(sensor_id)
+            .with_created_at(
+                sensor_row
+                    .created_at
+                    .map(SensAppDateTime::from_unix_milliseconds_i64),
+            )
+            .with_last_seen(
+                sensor_row
+                    .last_seen
+                    .map(SensAppDateTime::from_unix_milliseconds_i64),
+            ),
+    ))
+}
+
+async fn query_sensor_labels(pool: &SqlitePool, sensor_id: i64) -> Result<SensAppLabels> {
+    Ok(sqlx::query!(
+        r#"
+        SELECT labels_name_dictionary.name, labels_description_dictionary.description AS "description?"
+        FROM labels
+        JOIN labels_name_dictionary ON labels.name = labels_name_dictionary.id
+        LEFT JOIN labels_description_dictionary ON labels.description = labels_description_dictionary.id
+        WHERE labels.sensor_id = ?
+        "#,
+        sensor_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.name, row.description.unwrap_or_default()))
+    .collect())
+}
+
+/// Returns the UUIDs of the sensors, optionally restricted to a set of UUIDs
+/// and a set of sensor types.
+pub async fn list_sensor_uuids(
+    pool: &SqlitePool,
+    uuids: Option<&[Uuid]>,
+    sensor_types: Option<&[SensorType]>,
+) -> Result<Vec<Uuid>> {
+    let mut query_builder = QueryBuilder::<Sqlite>::new("SELECT uuid FROM sensors WHERE 1 = 1");
+    if let Some(uuids) = uuids {
+        if uuids.is_empty() {
+            return Ok(Vec::new());
+        }
+        query_builder.push(" AND uuid IN (");
+        let mut separated = query_builder.separated(", ");
+        for uuid in uuids {
+            separated.push_bind(uuid.to_string());
+        }
+        separated.push_unseparated(")");
+    }
+    if let Some(sensor_types) = sensor_types {
+        if sensor_types.is_empty() {
+            return Ok(Vec::new());
+        }
+        query_builder.push(" AND type IN (");
+        let mut separated = query_builder.separated(", ");
+        for sensor_type in sensor_types {
+            separated.push_bind(sensor_type.to_string());
+        }
+        separated.push_unseparated(")");
+    }
+    query_builder.push(" ORDER BY sensor_id");
+
+    query_builder
+        .build_query_scalar::<String>()
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|uuid| Ok(Uuid::parse_str(&uuid)?))
+        .collect()
+}
+
+/// Returns the samples of a sensor within the bounds.
+pub async fn query_samples(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    match sensor_type {
+        SensorType::Integer => query_integer_values(pool, sensor_id, bounds).await,
+        SensorType::Numeric => query_numeric_values(pool, sensor_id, bounds).await,
+        SensorType::Float => query_float_values(pool, sensor_id, bounds).await,
+        SensorType::String => query_string_values(pool, sensor_id, bounds).await,
+        SensorType::Boolean => query_boolean_values(pool, sensor_id, bounds).await,
+        SensorType::Location => query_location_values(pool, sensor_id, bounds).await,
+        SensorType::Json => query_json_values(pool, sensor_id, bounds).await,
+        SensorType::Blob => query_blob_values(pool, sensor_id, bounds).await,
+    }
+}
+
+/// Returns the samples of a sensor within the bounds, sorted by value.
+///
+/// The samples with the same value are sorted by datetime.
+pub async fn query_samples_by_value(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    bounds: &QueryBounds,
+    order: SortOrder,
+) -> Result<TypedSamples> {
+    // Multiplying the values by -1 sorts them in descending order
+    let direction: i64 = match order {
+        SortOrder::Asc => 1,
+        SortOrder::Desc => -1,
+    };
+    match sensor_type {
+        SensorType::Integer => {
+            query_integer_values_by_value(pool, sensor_id, bounds, direction).await
+        }
+        SensorType::Numeric => {
+            query_numeric_values_by_value(pool, sensor_id, bounds, direction).await
+        }
+        SensorType::Float => query_float_values_by_value(pool, sensor_id, bounds, direction).await,
+        _ => Err(InvalidQueryError(
+            "Only the integer, numeric and float samples can be sorted by value".to_string(),
+        )
+        .into()),
+    }
+}
+
+/// Returns the datetime of the latest sample of a sensor, if it has any.
+///
+/// Sorted on the `(sensor_id, timestamp_ms)` index,
+/// so it doesn't scan the samples of the sensor.
+pub async fn latest_sample_datetime(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+) -> Result<Option<SensAppDateTime>> {
+    let latest: Option<(i64, Option<i64>)> = sqlx::query_as(&format!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns FROM {}
+        WHERE sensor_id = ?
+        ORDER BY timestamp_ms DESC, timestamp_ns DESC
+        LIMIT 1
+        "#,
+        values_table(sensor_type)
+    ))
+    .bind(sensor_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(latest.map(|(timestamp_ms, timestamp_ns)| sqlite_datetime(timestamp_ms, timestamp_ns)))
+}
+
+/// Returns the annotations of a sensor within the bounds, sorted by datetime.
+pub async fn query_annotations(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<Vec<Annotation>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, title, text, tags FROM annotations
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY timestamp_ms ASC, timestamp_ns ASC, id ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(Annotation::new(
+                sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                row.title,
+                row.text,
+                serde_json::from_str(&row.tags)?,
+            ))
+        })
+        .collect()
+}
+
+/// Returns the number of samples of a sensor within the bounds,
+/// ignoring the limit.
+pub async fn count_samples(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    bounds: &QueryBounds,
+) -> Result<u64> {
+    let table = values_table(sensor_type);
+    let count: i64 = sqlx::query_scalar(&format!(
+        r#"
+        SELECT COUNT(*) FROM {}
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        "#,
+        table
+    ))
+    .bind(sensor_id)
+    .bind(bounds.start_ms)
+    .bind(bounds.end_ms)
+    .bind(bounds.start_ns)
+    .bind(bounds.end_ns)
+    .fetch_one(pool)
+    .await?;
+    Ok(count as u64)
+}
+
+/// Aggregates the numeric samples of a sensor within the bounds, ignoring the limit,
+/// in buckets of `step_ms` milliseconds aligned on the unix epoch.
+///
+/// The numeric values are aggregated as floats.
+pub async fn aggregate_samples(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    bounds: &QueryBounds,
+    step_ms: i64,
+) -> Result<Vec<AggregateBucket>> {
+    if !SensorSelector::is_numeric(sensor_type) {
+        return Err(
+            InvalidQueryError("Only the numeric sensors can be aggregated".to_string()).into(),
+        );
+    }
+    let rows: Vec<(i64, i64, f64, f64, f64)> = sqlx::query_as(&format!(
+        r#"
+        SELECT timestamp_ms - ((timestamp_ms % ?) + ?) % ? AS bucket, COUNT(*),
+        MIN(CAST(value AS REAL)), MAX(CAST(value AS REAL)), AVG(CAST(value AS REAL))
+        FROM {}
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        GROUP BY bucket
+        ORDER BY bucket ASC
+        "#,
+        values_table(sensor_type)
+    ))
+    .bind(step_ms)
+    .bind(step_ms)
+    .bind(step_ms)
+    .bind(sensor_id)
+    .bind(bounds.start_ms)
+    .bind(bounds.end_ms)
+    .bind(bounds.start_ns)
+    .bind(bounds.end_ns)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(bucket, count, min, max, avg)| AggregateBucket {
+            start: SensAppDateTime::from_unix_milliseconds_i64(bucket),
+            count: count as u64,
+            min,
+            max,
+            avg,
+        })
+        .collect())
+}
+
+/// Deletes the samples of all the sensors older than the cutoff, exclusive,
+/// in the value tables of the sample types.
+pub async fn delete_samples_older_than(
+    transaction: &mut Transaction<'_, Sqlite>,
+    older_than: SensAppDateTime,
+    sample_types: &[SensorType],
+) -> Result<()> {
+    let bounds = QueryBounds::new(None, Some(older_than), None);
+    for &sensor_type in sample_types {
+        sqlx::query(&format!(
+            r#"
+            DELETE FROM {}
+            WHERE timestamp_ms <= ? AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+            "#,
+            values_table(sensor_type)
+        ))
+        .bind(bounds.end_ms)
+        .bind(bounds.end_ns)
+        .execute(&mut **transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Deletes the sensors with the given name, with their samples in the value
+/// tables of the sample types, their labels, and annotations, and returns
+/// the number of deleted sensors.
+pub async fn delete_metric(
+    transaction: &mut Transaction<'_, Sqlite>,
+    name: &str,
+    sample_types: &[SensorType],
+) -> Result<u64> {
+    let sensor_ids: Vec<i64> = sqlx::query_scalar("SELECT sensor_id FROM sensors WHERE name = ?")
+        .bind(name)
+        .fetch_all(&mut **transaction)
+        .await?;
+    let tables = sample_types
+        .iter()
+        .map(|&sensor_type| values_table(sensor_type))
+        .chain(["labels", "annotations", "sensors"]);
+    for table in tables {
+        for sensor_id in &sensor_ids {
+            sqlx::query(&format!("DELETE FROM {} WHERE sensor_id = ?", table))
+                .bind(sensor_id)
+                .execute(&mut **transaction)
+                .await?;
+        }
+    }
+    Ok(sensor_ids.len() as u64)
+}
+
+/// Returns the table of the samples of the sensor type.
+///
+/// The table names come from the sensor type, not from the user,
+/// so they can be used in the queries.
+fn values_table(sensor_type: SensorType) -> &'static str {
+    match sensor_type {
+        SensorType::Integer => "integer_values",
+        SensorType::Numeric => "numeric_values",
+        SensorType::Float => "float_values",
+        SensorType::String => "string_values",
+        SensorType::Boolean => "boolean_values",
+        SensorType::Location => "location_values",
+        SensorType::Json => "json_values",
+        SensorType::Blob => "blob_values",
+    }
+}
+
+/// Scans all the samples of a sensor, in the insertion order.
+pub async fn verify_samples(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+) -> Result<VerifyReport> {
+    // The value column is only loaded when it's checked.
+    let value_column = match sensor_type {
+        SensorType::Float => "value",
+        SensorType::String => {
+            "CAST(COALESCE(string_values.inline_value, strings_values_dictionary.value) AS BLOB)"
+        }
+        _ => "NULL",
+    };
+    let join = match sensor_type {
+        SensorType::String => {
+            "LEFT JOIN strings_values_dictionary ON string_values.value = strings_values_dictionary.id"
+        }
+        _ => "",
+    };
+    let table = values_table(sensor_type);
+    let sql = format!(
+        r#"
+        SELECT {table}.timestamp_ms, {table}.timestamp_ns, {value_column} AS value
+        FROM {table} {join}
+        WHERE {table}.sensor_id = ?
+        ORDER BY {table}.rowid
+        "#
+    );
+
+    let mut report = VerifyReport::default();
+    let mut rows = sqlx::query(&sql).bind(sensor_id).fetch(pool);
+    while let Some(row) = rows.try_next().await? {
+        let timestamp_ms: i64 = row.try_get(0)?;
+        let timestamp_ns: Option<i64> = row.try_get(1)?;
+        report.check_timestamp(
+            timestamp_ns
+                .map(i128::from)
+                .unwrap_or(timestamp_ms as i128 * 1_000_000),
+        );
+        match sensor_type {
+            // SQLite can't store NaN in the NOT NULL column,
+            // but the infinite values are stored as is.
+            SensorType::Float => report.check_float(row.try_get(2)?),
+            SensorType::String => {
+                let value: Option<Vec<u8>> = row.try_get(2)?;
+                if let Some(value) = value {
+                    report.check_string(&value);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(report)
+}
+
+/// The bounds of a query, as used in the SQL queries.
+///
+/// The millisecond bounds use the index, and the nanosecond bounds
+/// are exact for the samples stored in nanosecond mode.
+#[derive(Debug, Clone, Copy)]
+pub struct QueryBounds {
+    pub start_ms: i64,
+    /// Inclusive, as the end is rounded down to the millisecond.
+    pub end_ms: i64,
+    pub start_ns: i64,
+    pub end_ns: i64,
+    /// SQLite accepts a negative limit as no limit.
+    pub limit: i64,
+}
+
+impl QueryBounds {
+    pub fn new(
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Self {
+        Self {
+            start_ms: start
+                .map(|start| start.to_unix_milliseconds().floor() as i64)
+                .unwrap_or(i64::MIN),
+            end_ms: end
+                .map(|end| end.to_unix_milliseconds().floor() as i64)
+                .unwrap_or(i64::MAX),
+            start_ns: start
+                .map(|start| start.to_unix_nanoseconds_i64())
+                .unwrap_or(i64::MIN),
+            end_ns: end
+                .map(|end| end.to_unix_nanoseconds_i64())
+                .unwrap_or(i64::MAX),
+            limit: limit.map(|limit| limit as i64).unwrap_or(-1),
+        }
+    }
+}
+
+pub async fn query_integer_values(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM integer_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Integer(
+        rows.into_iter()
+            .map(|row| Sample {
+                datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                value: row.value,
+            })
+            .collect::<SensAppVec<_>>(),
+    ))
+}
+
+pub async fn query_numeric_values(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM numeric_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Numeric(
+        rows.into_iter()
+            .map(|row| {
+                Ok(Sample {
+                    datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                    value: rust_decimal::Decimal::from_str(&row.value)?,
+                })
+            })
+            .collect::<Result<SensAppVec<_>>>()?,
+    ))
+}
+
+pub async fn query_float_values(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM float_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Float(
+        rows.into_iter()
+            .map(|row| Sample {
+                datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                value: row.value,
+            })
+            .collect::<SensAppVec<_>>(),
+    ))
+}
+
+async fn query_integer_values_by_value(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+    direction: i64,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM integer_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY value * ? ASC, timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        direction,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Integer(
+        rows.into_iter()
+            .map(|row| Sample {
+                datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                value: row.value,
+            })
+            .collect::<SensAppVec<_>>(),
+    ))
+}
+
+/// The numeric values are stored as text, so they are sorted as reals.
+async fn query_numeric_values_by_value(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+    direction: i64,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM numeric_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY CAST(value AS REAL) * ? ASC, timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        direction,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Numeric(
+        rows.into_iter()
+            .map(|row| {
+                Ok(Sample {
+                    datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                    value: rust_decimal::Decimal::from_str(&row.value)?,
+                })
+            })
+            .collect::<Result<SensAppVec<_>>>()?,
+    ))
+}
+
+async fn query_float_values_by_value(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+    direction: i64,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM float_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY value * ? ASC, timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        direction,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Float(
+        rows.into_iter()
+            .map(|row| Sample {
+                datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                value: row.value,
+            })
+            .collect::<SensAppVec<_>>(),
+    ))
+}
+
+pub async fn query_string_values(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT string_values.timestamp_ms, string_values.timestamp_ns,
+            COALESCE(string_values.inline_value, strings_values_dictionary.value) AS "value!: String"
+        FROM string_values
+        LEFT JOIN strings_values_dictionary ON string_values.value = strings_values_dictionary.id
+        WHERE string_values.sensor_id = ? AND string_values.timestamp_ms >= ? AND string_values.timestamp_ms <= ?
+        AND COALESCE(string_values.timestamp_ns, string_values.timestamp_ms * 1000000) >= ?
+        AND COALESCE(string_values.timestamp_ns, string_values.timestamp_ms * 1000000) < ?
+        ORDER BY string_values.timestamp_ms ASC, string_values.timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::String(
+        rows.into_iter()
+            .map(|row| Sample {
+                datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                value: row.value,
+            })
+            .collect::<SensAppVec<_>>(),
+    ))
+}
+
+pub async fn query_boolean_values(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM boolean_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Boolean(
+        rows.into_iter()
+            .map(|row| Sample {
+                datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                value: row.value != 0,
+            })
+            .collect::<SensAppVec<_>>(),
+    ))
+}
+
+pub async fn query_location_values(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, latitude, longitude FROM location_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Location(
+        rows.into_iter()
+            .map(|row| Sample {
+                datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                value: geo::Point::new(row.longitude, row.latitude),
+            })
+            .collect::<SensAppVec<_>>(),
+    ))
+}
+
+pub async fn query_blob_values(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM blob_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Blob(
+        rows.into_iter()
+            .map(|row| {
+                Ok(Sample {
+                    datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                    value: decompress_blob(row.value)?,
+                })
+            })
+            .collect::<Result<SensAppVec<_>>>()?,
+    ))
+}
+
+pub async fn query_json_values(
+    pool: &SqlitePool,
+    sensor_id: i64,
+    bounds: &QueryBounds,
+) -> Result<TypedSamples> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp_ms, timestamp_ns, value FROM json_values
+        WHERE sensor_id = ? AND timestamp_ms >= ? AND timestamp_ms <= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ?
+        AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ?
+        ORDER BY timestamp_ms ASC, timestamp_ns ASC
+        LIMIT ?
+        "#,
+        sensor_id,
+        bounds.start_ms,
+        bounds.end_ms,
+        bounds.start_ns,
+        bounds.end_ns,
+        bounds.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(TypedSamples::Json(
+        rows.into_iter()
+            .map(|row| {
+                Ok(Sample {
+                    datetime: sqlite_datetime(row.timestamp_ms, row.timestamp_ns),
+                    value: serde_json::from_slice(&row.value)?,
+                })
+            })
+            .collect::<Result<SensAppVec<_>>>()?,
+    ))
+}
+
+/// Returns the value columns of the samples table under the alias,
+/// and the join to decode the strings.
+fn value_columns(sensor_type: SensorType, alias: &str) -> (String, String) {
+    match sensor_type {
+        SensorType::String => (
+            format!(
+                "COALESCE({}.inline_value, strings_values_dictionary.value) AS value",
+                alias
+            ),
+            format!(
+                "LEFT JOIN strings_values_dictionary ON {}.value = strings_values_dictionary.id",
+                alias
+            ),
+        ),
+        SensorType::Location => (format!("{0}.latitude, {0}.longitude", alias), String::new()),
+        _ => (format!("{}.value", alias), String::new()),
+    }
+}
+
+/// Decodes the samples rows selected with [`value_columns`],
+/// in the order of the rows.
+fn decode_samples(sensor_type: SensorType, rows: &[SqliteRow]) -> Result<TypedSamples> {
+    let datetime = |row: &SqliteRow| -> Result<SensAppDateTime> {
+        Ok(sqlite_datetime(
+            row.try_get("timestamp_ms")?,
+            row.try_get("timestamp_ns")?,
+        ))
+    };
+    Ok(match sensor_type {
+        SensorType::Integer => TypedSamples::Integer(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get("value")?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Numeric => TypedSamples::Numeric(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: rust_decimal::Decimal::from_str(row.try_get("value")?)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Float => TypedSamples::Float(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get("value")?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::String => TypedSamples::String(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get("value")?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Boolean => TypedSamples::Boolean(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: row.try_get::<i64, _>("value")? != 0,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Location => TypedSamples::Location(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: geo::Point::new(row.try_get("longitude")?, row.try_get("latitude")?),
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Json => TypedSamples::Json(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: serde_json::from_slice(row.try_get("value")?)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+        SensorType::Blob => TypedSamples::Blob(
+            rows.iter()
+                .map(|row| {
+                    Ok(Sample {
+                        datetime: datetime(row)?,
+                        value: decompress_blob(row.try_get("value")?)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+/// Returns the samples of several sensors of the same type within the bounds,
+/// sorted by datetime, in one query with a window function.
+///
+/// The limit applies to each sensor, and every sensor_id has its samples,
+/// empty when the sensor has none.
+pub async fn query_samples_of_sensors(
+    pool: &SqlitePool,
+    sensor_ids: &[i64],
+    sensor_type: SensorType,
+    bounds: &QueryBounds,
+) -> Result<HashMap<i64, TypedSamples>> {
+    let (value_columns, value_join) = value_columns(sensor_type, "samples");
+    let mut query_builder = QueryBuilder::<Sqlite>::new(format!(
+        r#"
+        SELECT samples.sensor_id, samples.timestamp_ms, samples.timestamp_ns, {}
+        FROM (
+            SELECT *, ROW_NUMBER() OVER (
+                PARTITION BY sensor_id
+                ORDER BY timestamp_ms ASC, timestamp_ns ASC
+            ) AS row_number
+            FROM {}
+            WHERE sensor_id IN ("#,
+        value_columns,
+        values_table(sensor_type)
+    ));
+    let mut separated = query_builder.separated(", ");
+    for sensor_id in sensor_ids {
+        separated.push_bind(*sensor_id);
+    }
+    query_builder.push(") AND timestamp_ms >= ");
+    query_builder.push_bind(bounds.start_ms);
+    query_builder.push(" AND timestamp_ms <= ");
+    query_builder.push_bind(bounds.end_ms);
+    query_builder.push(" AND COALESCE(timestamp_ns, timestamp_ms * 1000000) >= ");
+    query_builder.push_bind(bounds.start_ns);
+    query_builder.push(" AND COALESCE(timestamp_ns, timestamp_ms * 1000000) < ");
+    query_builder.push_bind(bounds.end_ns);
+    query_builder.push(format!(
+        r#"
+        ) AS samples
+        {}
+        WHERE "#,
+        value_join
+    ));
+    query_builder.push_bind(bounds.limit);
+    query_builder.push(" < 0 OR samples.row_number <= ");
+    query_builder.push_bind(bounds.limit);
+    query_builder
+        .push(" ORDER BY samples.sensor_id, samples.timestamp_ms ASC, samples.timestamp_ns ASC");
+    let rows = query_builder.build().fetch_all(pool).await?;
+
+    let mut rows_by_sensor: HashMap<i64, Vec<SqliteRow>> = sensor_ids
+        .iter()
+        .map(|sensor_id| (*sensor_id, Vec::new()))
+        .collect();
+    for row in rows {
+        rows_by_sensor
+            .entry(row.try_get("sensor_id")?)
+            .or_default()
+            .push(row);
+    }
+    rows_by_sensor
+        .into_iter()
+        .map(|(sensor_id, rows)| Ok((sensor_id, decode_samples(sensor_type, &rows)?)))
+        .collect()
+}
+
+/// Returns the most recent sample of each sensor of the type,
+/// with the internal sensor_id and the UUID of the sensor.
+///
+/// The sensors can be restricted to a name, and the sensors without
+/// samples are left out. One query per type, with a window function.
+pub async fn query_latest_samples(
+    pool: &SqlitePool,
+    sensor_type: SensorType,
+    metric_filter: Option<&str>,
+) -> Result<Vec<(i64, Uuid, TypedSamples)>> {
+    let (value_columns, value_join) = value_columns(sensor_type, "latest");
+    let sql = format!(
+        r#"
+        SELECT latest.sensor_id, sensors.uuid, latest.timestamp_ms, latest.timestamp_ns, {}
+        FROM (
+            SELECT *, ROW_NUMBER() OVER (
+                PARTITION BY sensor_id
+                ORDER BY timestamp_ms DESC, timestamp_ns DESC, rowid DESC
+            ) AS row_number
+            FROM {}
+            WHERE ? IS NULL OR sensor_id IN (SELECT sensor_id FROM sensors WHERE name = ?)
+        ) AS latest
+        JOIN sensors ON latest.sensor_id = sensors.sensor_id
+        {}
+        WHERE latest.row_number = 1
+        ORDER BY latest.sensor_id
+        "#,
+        value_columns,
+        values_table(sensor_type),
+        value_join
+    );
+    let rows = sqlx::query(&sql)
+        .bind(metric_filter)
+        .bind(metric_filter)
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| {
+            let samples = decode_samples(sensor_type, std::slice::from_ref(row))?;
+            let uuid: String = row.try_get("uuid")?;
+            Ok((row.try_get("sensor_id")?, Uuid::parse_str(&uuid)?, samples))
+        })
+        .collect()
+}
+
+/// Runs a validated raw SQL query, converting the SQLite values to JSON.
+///
+/// The blobs are base64 encoded.
+pub async fn query_raw_sql(
+    connection: &mut SqliteConnection,
+    sql: &str,
+    max_rows: usize,
+) -> Result<RawSqlResult> {
+    let columns = (&mut *connection)
+        .describe(sql)
+        .await?
+        .columns()
+        .iter()
+        .map(|column| column.name().to_string())
+        .collect();
+    let mut result = RawSqlResult {
+        columns,
+        ..Default::default()
+    };
+    let mut rows = sqlx::query(sql).fetch(connection);
+    while let Some(row) = rows.try_next().await? {
+        if result.rows.len() >= max_rows {
+            result.truncated = true;
+            break;
+        }
+        result.rows.push(raw_sql_row_to_json(&row)?);
+    }
+    Ok(result)
+}
+
+fn raw_sql_row_to_json(row: &SqliteRow) -> Result<Vec<Value>> {
+    (0..row.len())
+        .map(|index| {
+            let value = row.try_get_raw(index)?;
+            if value.is_null() {
+                return Ok(Value::Null);
+            }
+            // The type of the value, not of the column, as SQLite is dynamically typed
+            let type_name = value.type_info().name().to_string();
+            Ok(match type_name.as_str() {
+                "INTEGER" => Value::from(row.try_get_unchecked::<i64, _>(index)?),
+                "REAL" => Value::from(row.try_get_unchecked::<f64, _>(index)?),
+                "BLOB" => Value::from(STANDARD.encode(row.try_get_unchecked::<Vec<u8>, _>(index)?)),
+                _ => Value::from(row.try_get_unchecked::<String, _>(index)?),
+            })
+        })
+        .collect()
+}
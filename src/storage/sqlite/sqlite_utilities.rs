@@ -1,19 +1,29 @@
+use crate::datamodel::sensapp_datetime::SensAppDateTimeExt;
 use crate::datamodel::unit::Unit;
-use crate::datamodel::Sensor;
+use crate::datamodel::{sensor_metadata::SensorMetadata, SensAppDateTime, Sensor, SensorType};
+use crate::storage::strict_sensors::UnknownSensorError;
+use crate::storage::type_conflict::{TypeConflictError, TypeConflictPolicy};
 use anyhow::Result;
 use cached::proc_macro::cached;
-use sqlx::{prelude::*, Sqlite, Transaction};
+use cached::Cached;
+use sqlx::{prelude::*, Sqlite, SqlitePool, Transaction};
+use std::str::FromStr;
 use uuid::Uuid;
 
+// The caches are shared by the process, so their keys include the identifier
+// of the database, generated when the storage connects. The identifier is
+// only used in the keys.
+
 #[cached(
     time = 120,
     result = true,
     sync_writes = true,
-    key = "String",
-    convert = r#"{ label_name.to_string() }"#
+    key = "(Uuid, String)",
+    convert = r#"{ (_database_id, label_name.to_string()) }"#
 )]
 pub async fn get_label_name_id_or_create(
     transaction: &mut Transaction<'_, Sqlite>,
+    _database_id: Uuid,
     label_name: &str,
 ) -> Result<i64> {
     let label_name_id_query = sqlx::query!(
@@ -54,11 +64,12 @@ pub async fn get_label_name_id_or_create(
     time = 120,
     result = true,
     sync_writes = true,
-    key = "String",
-    convert = r#"{ label_description.to_string() }"#
+    key = "(Uuid, String)",
+    convert = r#"{ (_database_id, label_description.to_string()) }"#
 )]
 pub async fn get_label_description_id_or_create(
     transaction: &mut Transaction<'_, Sqlite>,
+    _database_id: Uuid,
     label_description: &str,
 ) -> Result<i64> {
     let label_description_id_query = sqlx::query!(
@@ -99,11 +110,12 @@ pub async fn get_label_description_id_or_create(
     time = 120,
     result = true,
     sync_writes = true,
-    key = "String",
-    convert = r#"{ unit.name.clone() }"#
+    key = "(Uuid, String)",
+    convert = r#"{ (_database_id, unit.name.clone()) }"#
 )]
 pub async fn get_unit_id_or_create(
     transaction: &mut Transaction<'_, Sqlite>,
+    _database_id: Uuid,
     unit: &Unit,
 ) -> Result<i64> {
     let unit_id_query = sqlx::query!(
@@ -141,18 +153,61 @@ pub async fn get_unit_id_or_create(
     Ok(unit_id)
 }
 
+/// Records that the sensor was published now, even without new samples.
+pub async fn touch_sensor(transaction: &mut Transaction<'_, Sqlite>, sensor_id: i64) -> Result<()> {
+    let last_seen = SensAppDateTime::now()?.to_unix_milliseconds().floor() as i64;
+    let query = sqlx::query!(
+        "UPDATE sensors SET last_seen = ? WHERE sensor_id = ?",
+        last_seen,
+        sensor_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Replaces the metadata of the sensor, returns `false` when it doesn't exist.
+pub async fn update_sensor_metadata(
+    pool: &SqlitePool,
+    uuid: Uuid,
+    metadata: &SensorMetadata,
+) -> Result<bool> {
+    let uuid_string = uuid.to_string();
+    let raw_retention_days = metadata
+        .downsampling
+        .map(|policy| policy.raw_retention_days);
+    let rollup_interval_seconds = metadata
+        .downsampling
+        .map(|policy| policy.rollup_interval_seconds as i64);
+    let query = sqlx::query!(
+        r#"
+            UPDATE sensors SET min_value = ?, max_value = ?, nominal_interval_seconds = ?,
+                raw_retention_days = ?, rollup_interval_seconds = ?
+            WHERE uuid = ?
+            "#,
+        metadata.min_value,
+        metadata.max_value,
+        metadata.nominal_interval_seconds,
+        raw_retention_days,
+        rollup_interval_seconds,
+        uuid_string
+    );
+    Ok(pool.execute(query).await?.rows_affected() > 0)
+}
+
 #[cached(
     time = 120,
     result = true,
     sync_writes = true,
-    key = "Uuid",
-    convert = r#"{ sensor.uuid }"#
+    key = "(Uuid, Uuid)",
+    convert = r#"{ (database_id, sensor.uuid) }"#
 )]
 pub async fn get_sensor_id_or_create_sensor(
     transaction: &mut Transaction<'_, Sqlite>,
+    database_id: Uuid,
     sensor: &Sensor,
-) -> Result<i64> {
-    println!("aaah");
+    strict_sensors: bool,
+    type_conflict_policy: TypeConflictPolicy,
+) -> Result<(i64, SensorType)> {
     let uuid_string = sensor.uuid.to_string();
     let sensor_id_query = sqlx::query!(
         r#"
@@ -168,25 +223,59 @@ pub async fn get_sensor_id_or_create_sensor(
 
     // If the sensor exists, it's returned
     if let Some(Some(sensor_id)) = sensor_id {
-        return Ok(sensor_id);
+        return Ok((sensor_id, sensor.sensor_type));
+    }
+    if type_conflict_policy != TypeConflictPolicy::NewSeries {
+        if let Some((sensor_id, existing_type)) =
+            find_sensor_with_another_type(transaction, sensor).await?
+        {
+            if type_conflict_policy == TypeConflictPolicy::Reject {
+                return Err(TypeConflictError {
+                    name: sensor.name.clone(),
+                    existing_type,
+                    new_type: sensor.sensor_type,
+                }
+                .into());
+            }
+            return Ok((sensor_id, existing_type));
+        }
+    }
+    if strict_sensors {
+        return Err(UnknownSensorError {
+            names: vec![sensor.name.clone()],
+        }
+        .into());
     }
 
     let sensor_type_string = sensor.sensor_type.to_string();
 
     let unit_id = match sensor.unit {
-        Some(ref unit) => Some(get_unit_id_or_create(transaction, unit).await?),
+        Some(ref unit) => Some(get_unit_id_or_create(transaction, database_id, unit).await?),
         None => None,
     };
 
+    let created_at = SensAppDateTime::now()?.to_unix_milliseconds().floor() as i64;
+    let raw_retention_days = sensor.downsampling.map(|policy| policy.raw_retention_days);
+    let rollup_interval_seconds = sensor
+        .downsampling
+        .map(|policy| policy.rollup_interval_seconds as i64);
+
     let create_sensor_query = sqlx::query!(
         r#"
-            INSERT INTO sensors (uuid, name, type, unit)
-            VALUES (?, ?, ?, ?)
+            INSERT INTO sensors (uuid, name, type, unit, min_value, max_value, nominal_interval_seconds,
+                raw_retention_days, rollup_interval_seconds, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         uuid_string,
         sensor.name,
         sensor_type_string,
-        unit_id
+        unit_id,
+        sensor.min_value,
+        sensor.max_value,
+        sensor.nominal_interval_seconds,
+        raw_retention_days,
+        rollup_interval_seconds,
+        created_at
     );
 
     // Execute the query
@@ -197,8 +286,9 @@ pub async fn get_sensor_id_or_create_sensor(
 
     // Add the labels
     for (key, value) in sensor.labels.iter() {
-        let label_name_id = get_label_name_id_or_create(transaction, key).await?;
-        let label_description_id = get_label_description_id_or_create(transaction, value).await?;
+        let label_name_id = get_label_name_id_or_create(transaction, database_id, key).await?;
+        let label_description_id =
+            get_label_description_id_or_create(transaction, database_id, value).await?;
         let label_query = sqlx::query!(
             r#"
                 INSERT INTO labels (sensor_id, name, description)
@@ -211,18 +301,64 @@ pub async fn get_sensor_id_or_create_sensor(
         transaction.execute(label_query).await?;
     }
 
-    Ok(sensor_id)
+    Ok((sensor_id, sensor.sensor_type))
+}
+
+/// Finds the sensor of the same name and labels, but of another type.
+async fn find_sensor_with_another_type(
+    transaction: &mut Transaction<'_, Sqlite>,
+    sensor: &Sensor,
+) -> Result<Option<(i64, SensorType)>> {
+    let sensor_type_string = sensor.sensor_type.to_string();
+    let candidates = sqlx::query!(
+        r#"
+            SELECT sensor_id AS "sensor_id!", type FROM sensors WHERE name = ? AND type != ?
+            "#,
+        sensor.name,
+        sensor_type_string
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    let mut labels = sensor.labels.to_vec();
+    labels.sort();
+    for candidate in candidates {
+        let mut candidate_labels = sqlx::query!(
+            r#"
+                SELECT labels_name_dictionary.name, labels_description_dictionary.description AS "description?"
+                FROM labels
+                JOIN labels_name_dictionary ON labels.name = labels_name_dictionary.id
+                LEFT JOIN labels_description_dictionary ON labels.description = labels_description_dictionary.id
+                WHERE labels.sensor_id = ?
+                "#,
+            candidate.sensor_id
+        )
+        .fetch_all(&mut **transaction)
+        .await?
+        .into_iter()
+        .map(|row| (row.name, row.description.unwrap_or_default()))
+        .collect::<Vec<_>>();
+        candidate_labels.sort();
+        if candidate_labels == labels {
+            return Ok(Some((
+                candidate.sensor_id,
+                SensorType::from_str(&candidate.r#type)?,
+            )));
+        }
+    }
+    Ok(None)
 }
 
 #[cached(
     time = 120,
     result = true,
     sync_writes = true,
-    key = "String",
-    convert = r#"{ string_value.to_string() }"#
+    key = "(Uuid, String)",
+    convert = r#"{ (_database_id, string_value.to_string()) }"#
 )]
 pub async fn get_string_value_id_or_create(
     transaction: &mut Transaction<'_, Sqlite>,
+    _database_id: Uuid,
     string_value: &str,
 ) -> Result<i64> {
     let get_query = sqlx::query!(
@@ -247,3 +383,24 @@ pub async fn get_string_value_id_or_create(
     let string_id = transaction.execute(create_query).await?.last_insert_rowid();
     Ok(string_id)
 }
+
+/// Returns the millisecond timestamp, and the nanosecond timestamp
+/// when the nanosecond mode is enabled.
+pub fn sqlite_timestamps(datetime: &SensAppDateTime, nanosecond_time: bool) -> (i64, Option<i64>) {
+    let timestamp_ms = datetime.to_unix_milliseconds().floor() as i64;
+    let timestamp_ns = nanosecond_time.then(|| datetime.to_unix_nanoseconds_i64());
+    (timestamp_ms, timestamp_ns)
+}
+
+/// Clears the cached sensor identifiers, after deleting sensors.
+pub async fn clear_caches() {
+    GET_SENSOR_ID_OR_CREATE_SENSOR.lock().await.cache_clear();
+}
+
+/// Reads a sample datetime, preferring the nanosecond timestamp when present.
+pub fn sqlite_datetime(timestamp_ms: i64, timestamp_ns: Option<i64>) -> SensAppDateTime {
+    match timestamp_ns {
+        Some(timestamp_ns) => SensAppDateTime::from_unix_nanoseconds_i64(timestamp_ns),
+        None => SensAppDateTime::from_unix_milliseconds_i64(timestamp_ms),
+    }
+}
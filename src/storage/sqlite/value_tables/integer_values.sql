@@ -0,0 +1,10 @@
+-- The 'integer_values' table, as created by the migrations
+CREATE TABLE IF NOT EXISTS integer_values (
+    sensor_id INTEGER NOT NULL, -- References 'sensors' (sensor_id), cannot be null
+    timestamp_ms INTEGER NOT NULL, -- Unix timestamp in milliseconds, cannot be null
+    value INTEGER NOT NULL, -- Integer value, cannot be null
+    timestamp_ns INTEGER, -- Unix timestamp in nanoseconds, null in millisecond mode
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id) -- Foreign key to 'sensors' table
+) STRICT;
+
+CREATE INDEX IF NOT EXISTS index_integer_values ON integer_values(sensor_id, timestamp_ms);
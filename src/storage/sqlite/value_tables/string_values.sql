@@ -0,0 +1,13 @@
+-- The 'string_values' table, as created by the migrations
+CREATE TABLE IF NOT EXISTS string_values (
+    sensor_id INTEGER NOT NULL, -- References 'sensors' (sensor_id), cannot be null
+    timestamp_ms INTEGER NOT NULL, -- Unix timestamp in milliseconds, cannot be null
+    value INTEGER, -- References 'strings_values_dictionary', null when inline
+    timestamp_ns INTEGER, -- Unix timestamp in nanoseconds, null in millisecond mode
+    inline_value TEXT, -- The value itself, null when in the dictionary
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id), -- Foreign key to 'sensors' table
+    FOREIGN KEY (value) REFERENCES strings_values_dictionary(id), -- Foreign key to 'strings_values_dictionary'
+    CHECK ((value IS NULL) <> (inline_value IS NULL)) -- Exactly one of the layouts
+) STRICT;
+
+CREATE INDEX IF NOT EXISTS index_string_values ON string_values(sensor_id, timestamp_ms);
@@ -1,10 +1,40 @@
+use super::circuit_breaker::CircuitBreakerState;
+use super::query::InvalidQueryError;
+use super::query::{AggregateBucket, SensorSelector, SortOrder, TimeRange};
+use super::raw_sql::RawSqlResult;
+use super::verify::VerifyReport;
+use super::write_buffer::WriteBufferStatus;
+use crate::datamodel::{
+    sensor_metadata::SensorMetadata, Annotation, SensAppDateTime, Sensor, SensorData,
+};
 use anyhow::Result;
 use async_trait::async_trait;
+use hifitime::Duration;
 use std::fmt::Debug;
+use uuid::Uuid;
+
+/// The error returned by the operations a storage backend doesn't implement.
+#[derive(Debug)]
+pub struct UnsupportedError(pub &'static str);
+
+impl std::fmt::Display for UnsupportedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not supported by this storage backend", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedError {}
 
 #[async_trait]
 pub trait StorageInstance: Send + Sync + Debug {
     async fn create_or_migrate(&self) -> Result<()>;
+
+    /// Checks that the schema is up to date, without migrating it,
+    /// when the schema is provisioned separately.
+    async fn check_schema(&self) -> Result<()> {
+        Err(UnsupportedError("Checking the schema").into())
+    }
+
     async fn publish(
         &self,
         batch: std::sync::Arc<crate::datamodel::batch::Batch>,
@@ -14,4 +44,246 @@ pub trait StorageInstance: Send + Sync + Debug {
     async fn vacuum(&self) -> Result<()>;
 
     async fn list_sensors(&self) -> Result<Vec<String>>;
+
+    /// Checks that the backend is reachable.
+    ///
+    /// The default implementation doesn't check anything.
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The state of the circuit breaker, if the storage is wrapped in one.
+    fn circuit_breaker_state(&self) -> Option<CircuitBreakerState> {
+        None
+    }
+
+    /// The metrics of the write buffer, if the storage is buffered.
+    fn write_buffer_status(&self) -> Option<WriteBufferStatus> {
+        None
+    }
+
+    /// Returns the sensor with the given UUID, without its samples.
+    async fn get_sensor(&self, _sensor_uuid: Uuid) -> Result<Option<Sensor>> {
+        Err(UnsupportedError("Getting sensors").into())
+    }
+
+    /// Replaces the metadata of an existing sensor.
+    ///
+    /// Returns `false` when the sensor doesn't exist.
+    async fn update_sensor_metadata(
+        &self,
+        _sensor_uuid: Uuid,
+        _metadata: &SensorMetadata,
+    ) -> Result<bool> {
+        Err(UnsupportedError("Updating the sensor metadata").into())
+    }
+
+    /// Returns the samples of a sensor, sorted by datetime.
+    ///
+    /// `start` is inclusive and `end` is exclusive. Returns `None`
+    /// when the sensor doesn't exist, and the sensor with empty samples
+    /// when it exists but has no samples in the time range.
+    async fn query_sensor_data(
+        &self,
+        _sensor_uuid: Uuid,
+        _start: Option<SensAppDateTime>,
+        _end: Option<SensAppDateTime>,
+        _limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        Err(UnsupportedError("Querying sensor data").into())
+    }
+
+    /// Returns the samples of a sensor by its integer id, like
+    /// [`query_sensor_data`](Self::query_sensor_data) but without looking up the UUID.
+    ///
+    /// The integer ids are internal to a storage, the UUIDs remain the public identifiers.
+    async fn query_sensor_data_by_id(
+        &self,
+        _sensor_id: i64,
+        _start: Option<SensAppDateTime>,
+        _end: Option<SensAppDateTime>,
+        _limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        Err(UnsupportedError("Querying sensor data by id").into())
+    }
+
+    /// Returns the samples of a sensor in the `window` before its latest
+    /// sample, inclusive, so a sensor that stopped reporting still has
+    /// its last samples. Sorted by datetime.
+    ///
+    /// Returns `None` when the sensor doesn't exist.
+    async fn query_sensor_data_window(
+        &self,
+        _sensor_uuid: Uuid,
+        _window: Duration,
+        _limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        Err(UnsupportedError("Querying the latest window").into())
+    }
+
+    /// Returns the samples of a sensor within the time range,
+    /// sorted by value, such as the `limit` highest samples.
+    /// The samples with the same value are sorted by datetime.
+    ///
+    /// Only the integer, numeric and float samples can be sorted by value.
+    /// Returns `None` when the sensor doesn't exist.
+    async fn query_sensor_data_by_value(
+        &self,
+        _sensor_uuid: Uuid,
+        _start: Option<SensAppDateTime>,
+        _end: Option<SensAppDateTime>,
+        _order: SortOrder,
+        _limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        Err(UnsupportedError("Sorting the samples by value").into())
+    }
+
+    /// Returns a page of the samples of a sensor, for keyset pagination.
+    ///
+    /// The page has the samples after the `after` datetime, exclusive,
+    /// and the next page starts after the datetime of its last sample.
+    /// The samples sharing a datetime are never split across pages,
+    /// so a page can be a bit shorter, or longer, than the `limit`.
+    /// The page is empty once all the samples are read.
+    async fn query_sensor_data_page(
+        &self,
+        sensor_uuid: Uuid,
+        after: Option<SensAppDateTime>,
+        limit: usize,
+    ) -> Result<Option<SensorData>> {
+        if limit == 0 {
+            return Err(InvalidQueryError("The page limit must be positive".to_string()).into());
+        }
+        let one_nanosecond = Duration::from_nanoseconds(1.0);
+        let start = after.map(|after| after + one_nanosecond);
+        let mut sensor_data = match self
+            .query_sensor_data(sensor_uuid, start, None, Some(limit))
+            .await?
+        {
+            Some(sensor_data) => sensor_data,
+            None => return Ok(None),
+        };
+        let last_datetime = match sensor_data.samples.last_datetime() {
+            Some(last_datetime) if sensor_data.samples.len() >= limit => last_datetime,
+            _ => return Ok(Some(sensor_data)),
+        };
+
+        // The next page starts after the last datetime, so the samples
+        // sharing it are either all in this page, or all in the next one.
+        sensor_data
+            .samples
+            .retain_by_datetime(|datetime| *datetime < last_datetime);
+        if sensor_data.samples.len() == 0 {
+            return self
+                .query_sensor_data(
+                    sensor_uuid,
+                    Some(last_datetime),
+                    Some(last_datetime + one_nanosecond),
+                    None,
+                )
+                .await;
+        }
+        Ok(Some(sensor_data))
+    }
+
+    /// Deletes the samples of all the sensors older than the cutoff, exclusive.
+    async fn apply_retention(&self, _older_than: SensAppDateTime) -> Result<()> {
+        Err(UnsupportedError("Applying the retention").into())
+    }
+
+    /// Deletes all the sensors with the given name, with their samples,
+    /// and returns the number of deleted sensors.
+    async fn delete_metric(&self, _name: &str) -> Result<u64> {
+        Err(UnsupportedError("Deleting metrics").into())
+    }
+
+    /// Returns the samples of the sensors matching the selector.
+    ///
+    /// The limit applies to each sensor.
+    async fn query(
+        &self,
+        _selector: &SensorSelector,
+        _time_range: TimeRange,
+        _limit: Option<usize>,
+    ) -> Result<Vec<SensorData>> {
+        Err(UnsupportedError("Querying sensors").into())
+    }
+
+    /// Returns the UUIDs of the given sensors that don't exist in the storage.
+    async fn unknown_sensors(&self, _sensor_uuids: &[Uuid]) -> Result<Vec<Uuid>> {
+        Err(UnsupportedError("Checking the sensors").into())
+    }
+
+    /// Returns the most recent sample of each sensor, for the overviews.
+    ///
+    /// The sensors can be restricted to a name with the metric filter.
+    /// The sensors without samples are left out.
+    async fn latest_samples_all(&self, _metric_filter: Option<&str>) -> Result<Vec<SensorData>> {
+        Err(UnsupportedError("Querying the latest samples").into())
+    }
+
+    /// Returns the sensors matching the selector, with their number
+    /// of samples in the time range, without loading the samples.
+    async fn count_series_by_labels(
+        &self,
+        _selector: &SensorSelector,
+        _time_range: TimeRange,
+    ) -> Result<Vec<(Sensor, u64)>> {
+        Err(UnsupportedError("Counting samples").into())
+    }
+
+    /// Aggregates the numeric samples of a sensor in time buckets of `step`,
+    /// aligned on the unix epoch. The empty buckets are left out.
+    ///
+    /// Returns `None` when the sensor doesn't exist.
+    async fn aggregate_sensor_data(
+        &self,
+        _sensor_uuid: Uuid,
+        _time_range: TimeRange,
+        _step: Duration,
+    ) -> Result<Option<Vec<AggregateBucket>>> {
+        Err(UnsupportedError("Aggregating samples").into())
+    }
+
+    /// Scans the samples of a sensor for duplicated or out of order
+    /// timestamps, non finite floats, and invalid UTF-8 strings.
+    ///
+    /// Returns `None` when the sensor doesn't exist.
+    async fn verify_sensor(&self, _sensor_uuid: Uuid) -> Result<Option<VerifyReport>> {
+        Err(UnsupportedError("Verifying sensors").into())
+    }
+
+    /// Adds an annotation to the timeline of a sensor.
+    ///
+    /// Returns `false` when the sensor doesn't exist.
+    async fn add_annotation(&self, _sensor_uuid: Uuid, _annotation: &Annotation) -> Result<bool> {
+        Err(UnsupportedError("Adding annotations").into())
+    }
+
+    /// Returns the annotations of a sensor in the time range, sorted by datetime.
+    ///
+    /// Returns `None` when the sensor doesn't exist.
+    async fn query_annotations(
+        &self,
+        _sensor_uuid: Uuid,
+        _time_range: TimeRange,
+    ) -> Result<Option<Vec<Annotation>>> {
+        Err(UnsupportedError("Querying annotations").into())
+    }
+
+    /// Runs a user supplied `SELECT` query on a read-only connection.
+    ///
+    /// The backends must validate the query with
+    /// [`validate_read_only_select`](super::raw_sql::validate_read_only_select),
+    /// and return at most `max_rows` rows. They stop the query itself after
+    /// the timeout, failing with a [`RawSqlTimeoutError`](super::raw_sql::RawSqlTimeoutError),
+    /// and return the errors of the query as [`InvalidQueryError`]s.
+    async fn raw_sql_query(
+        &self,
+        _sql: &str,
+        _max_rows: usize,
+        _timeout: std::time::Duration,
+    ) -> Result<RawSqlResult> {
+        Err(UnsupportedError("Running raw SQL queries").into())
+    }
 }
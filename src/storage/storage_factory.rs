@@ -3,8 +3,9 @@ use std::sync::Arc;
 use anyhow::{bail, Result};
 
 use super::{
-    bigquery::BigQueryStorage, duckdb::DuckDBStorage, postgresql::PostgresStorage,
-    rrdcached::RrdCachedStorage, sqlite::SqliteStorage, storage::StorageInstance,
+    bigquery::BigQueryStorage, duckdb::DuckDBStorage, multi::MultiStorage, parquet::ParquetStorage,
+    postgresql::PostgresStorage, redact::redact_connection_string, rrdcached::RrdCachedStorage,
+    sqlite::SqliteStorage, storage::StorageInstance, tiered::TieredStorage,
     timescaledb::TimeScaleDBStorage,
 };
 
@@ -29,14 +30,24 @@ pub async fn create_storage_from_connection_string(
 pub async fn create_storage_from_connection_string(
     connection_string: &str,
 ) -> Result<Arc<dyn StorageInstance>> {
+    tracing::info!(
+        "Connecting to the storage: {}",
+        redact_connection_string(connection_string)
+    );
     Ok(match connection_string {
         // Ascending order, no favoritisim
         s if s.starts_with("bigquery:") => Arc::new(BigQueryStorage::connect(s).await?),
         s if s.starts_with("duckdb:") => Arc::new(DuckDBStorage::connect(s).await?),
+        s if s.starts_with("multi:") => Arc::new(MultiStorage::connect(s).await?),
+        s if s.starts_with("parquet:") => Arc::new(ParquetStorage::connect(s).await?),
         s if s.starts_with("postgres:") => Arc::new(PostgresStorage::connect(s).await?),
         s if s.starts_with("sqlite:") => Arc::new(SqliteStorage::connect(s).await?),
+        s if s.starts_with("tiered:") => Arc::new(TieredStorage::connect(s).await?),
         s if s.starts_with("timescaledb:") => Arc::new(TimeScaleDBStorage::connect(s).await?),
         s if s.starts_with("rrdcached:") => Arc::new(RrdCachedStorage::connect(s).await?),
-        _ => bail!("Unsupported storage type: {}", connection_string),
+        _ => bail!(
+            "Unsupported storage type: {}",
+            redact_connection_string(connection_string)
+        ),
     })
 }
@@ -0,0 +1,49 @@
+use super::storage::StorageInstance;
+use crate::datamodel::Sensor;
+use anyhow::Result;
+use std::sync::Arc;
+
+/// The error returned in strict mode for the samples of unknown sensors.
+#[derive(Debug)]
+pub struct UnknownSensorError {
+    pub names: Vec<String>,
+}
+
+impl std::fmt::Display for UnknownSensorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unknown sensors, they must be created beforehand in strict mode: {}",
+            self.names.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownSensorError {}
+
+/// Whether the ingestion only writes to the existing sensors,
+/// false when the configuration isn't loaded.
+pub fn strict_sensors() -> bool {
+    crate::config::get()
+        .map(|config| config.strict_sensors)
+        .unwrap_or_default()
+}
+
+/// Fails with an [`UnknownSensorError`] when some sensors don't exist
+/// in the storage, before publishing their samples in strict mode.
+pub async fn check_known_sensors(
+    storage: &dyn StorageInstance,
+    sensors: &[Arc<Sensor>],
+) -> Result<()> {
+    let uuids = sensors.iter().map(|sensor| sensor.uuid).collect::<Vec<_>>();
+    let unknown_uuids = storage.unknown_sensors(&uuids).await?;
+    if unknown_uuids.is_empty() {
+        return Ok(());
+    }
+    let names = sensors
+        .iter()
+        .filter(|sensor| unknown_uuids.contains(&sensor.uuid))
+        .map(|sensor| sensor.name.clone())
+        .collect();
+    Err(UnknownSensorError { names }.into())
+}
@@ -0,0 +1,122 @@
+use crate::bus::wait_for_all::WaitForAll;
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// What to do when the storage takes too long to store the published samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncTimeoutPolicy {
+    /// Fail with a [`StorageError::SyncTimeout`].
+    Error,
+    /// Log a warning and carry on, the samples may still be stored later.
+    Warn,
+}
+
+/// The storage errors the callers tell apart from the other failures.
+#[derive(Debug)]
+pub enum StorageError {
+    /// The storage didn't sync within the timeout.
+    SyncTimeout(Duration),
+    /// The storage failed to store the published samples,
+    /// or rejected them as its write buffer is full.
+    NotStored,
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::SyncTimeout(timeout) => write!(
+                f,
+                "The storage didn't sync within {} seconds",
+                timeout.as_secs_f64()
+            ),
+            StorageError::NotStored => write!(f, "The storage failed to store the samples"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+/// Waits for the storage to sync the published samples, for at most
+/// `SENSAPP_STORAGE_SYNC_TIMEOUT_SECONDS`, and applies the
+/// `SENSAPP_SYNC_TIMEOUT_POLICY` when it takes longer.
+pub async fn sync_with_timeout(waiter: &mut WaitForAll) -> Result<()> {
+    let config = crate::config::get()?;
+    wait_with_policy(
+        waiter,
+        Duration::from_secs(config.storage_sync_timeout_seconds),
+        config.sync_timeout_policy,
+    )
+    .await
+}
+
+/// A zero timeout waits forever.
+async fn wait_with_policy(
+    waiter: &mut WaitForAll,
+    timeout: Duration,
+    policy: SyncTimeoutPolicy,
+) -> Result<()> {
+    if timeout.is_zero() {
+        return waiter.wait().await;
+    }
+    match tokio::time::timeout(timeout, waiter.wait()).await {
+        Ok(result) => result,
+        Err(_) => match policy {
+            SyncTimeoutPolicy::Error => Err(StorageError::SyncTimeout(timeout).into()),
+            SyncTimeoutPolicy::Warn => {
+                tracing::warn!(
+                    "The storage didn't sync within {} seconds, carrying on",
+                    timeout.as_secs_f64()
+                );
+                Ok(())
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingestors::http::app_error::AppError;
+
+    #[tokio::test]
+    async fn test_sync_timeout_policy() {
+        // The sender is kept, but never sends the sync
+        let (_sender, receiver) = async_broadcast::broadcast::<()>(1);
+        let mut waiter = WaitForAll::new();
+        waiter.add(receiver).await;
+        let timeout = Duration::from_millis(20);
+
+        let error = wait_with_policy(&mut waiter, timeout, SyncTimeoutPolicy::Error)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StorageError>(),
+            Some(StorageError::SyncTimeout(duration)) if *duration == timeout
+        ));
+        // Answered with a 504 Gateway Timeout
+        assert!(matches!(AppError::from(error), AppError::GatewayTimeout(_)));
+        assert!(
+            wait_with_policy(&mut waiter, timeout, SyncTimeoutPolicy::Warn)
+                .await
+                .is_ok()
+        );
+
+        // A sync in time is fine with both policies
+        let (sender, receiver) = async_broadcast::broadcast::<()>(1);
+        let mut waiter = WaitForAll::new();
+        waiter.add(receiver).await;
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            sender.broadcast(()).await.unwrap();
+        });
+        assert!(wait_with_policy(
+            &mut waiter,
+            Duration::from_secs(5),
+            SyncTimeoutPolicy::Error
+        )
+        .await
+        .is_ok());
+    }
+}
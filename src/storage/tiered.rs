@@ -0,0 +1,698 @@
+use super::circuit_breaker::CircuitBreakerState;
+use super::query::{AggregateBucket, InvalidQueryError, SensorSelector, SortOrder, TimeRange};
+use super::raw_sql::RawSqlResult;
+use super::storage::StorageInstance;
+use super::storage_factory::create_storage_from_connection_string;
+use super::verify::VerifyReport;
+use super::write_buffer::WriteBufferStatus;
+use crate::datamodel::{
+    batch::Batch, sensor_metadata::SensorMetadata, Annotation, Sample, SensAppDateTime, SensAppVec,
+    Sensor, SensorData, TypedSamples,
+};
+use anyhow::{anyhow, bail, Result};
+use async_broadcast::Sender;
+use async_trait::async_trait;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Merges the samples of the two tiers, sorted by datetime.
+///
+/// The secondary samples sharing a datetime with a primary sample
+/// are left out, as they were archived while still in the primary.
+fn merge_samples(primary: TypedSamples, secondary: TypedSamples) -> Result<TypedSamples> {
+    macro_rules! merge {
+        ($($variant:ident),*) => {
+            match (primary, secondary) {
+                $((TypedSamples::$variant(mut primary), TypedSamples::$variant(secondary)) => {
+                    let datetimes = primary
+                        .iter()
+                        .map(|sample| sample.datetime)
+                        .collect::<BTreeSet<_>>();
+                    primary.extend(
+                        secondary
+                            .into_iter()
+                            .filter(|sample| !datetimes.contains(&sample.datetime)),
+                    );
+                    primary.sort_by_key(|sample| sample.datetime);
+                    TypedSamples::$variant(primary)
+                })*
+                _ => bail!("The tiers have different sample types for the same sensor"),
+            }
+        };
+    }
+    Ok(merge!(
+        Integer, Numeric, Float, String, Boolean, Location, Blob, Json
+    ))
+}
+
+/// Merges the data of a sensor from the two tiers, keeping the first `limit` samples.
+fn merge_sensor_data(
+    primary: Option<SensorData>,
+    secondary: Option<SensorData>,
+    limit: Option<usize>,
+) -> Result<Option<SensorData>> {
+    let mut sensor_data = match (primary, secondary) {
+        (Some(primary), Some(secondary)) => SensorData::new(
+            primary.sensor,
+            merge_samples(primary.samples, secondary.samples)?,
+        ),
+        (Some(sensor_data), None) | (None, Some(sensor_data)) => sensor_data,
+        (None, None) => return Ok(None),
+    };
+    if let Some(limit) = limit {
+        let mut count = 0;
+        sensor_data.samples.retain_by_datetime(|_| {
+            count += 1;
+            count <= limit
+        });
+    }
+    Ok(Some(sensor_data))
+}
+
+/// Merges the aggregates of the two tiers, bucket by bucket.
+///
+/// The tiers must aggregate distinct samples, see
+/// [`TieredStorage::aggregate_sensor_data`].
+fn merge_buckets(
+    primary: Vec<AggregateBucket>,
+    secondary: Vec<AggregateBucket>,
+) -> Vec<AggregateBucket> {
+    let mut buckets = BTreeMap::new();
+    for bucket in primary.into_iter().chain(secondary) {
+        buckets
+            .entry(bucket.start)
+            .and_modify(|merged: &mut AggregateBucket| {
+                let count = merged.count + bucket.count;
+                merged.avg = (merged.avg * merged.count as f64 + bucket.avg * bucket.count as f64)
+                    / count as f64;
+                merged.count = count;
+                merged.min = merged.min.min(bucket.min);
+                merged.max = merged.max.max(bucket.max);
+            })
+            .or_insert(bucket);
+    }
+    buckets.into_values().collect()
+}
+
+/// Sorts the samples by value, stable so the samples
+/// with the same value stay sorted by datetime.
+fn sort_samples_by_value(samples: &mut TypedSamples, order: SortOrder) -> Result<()> {
+    fn sort<T>(
+        samples: &mut SensAppVec<Sample<T>>,
+        order: SortOrder,
+        compare: impl Fn(&T, &T) -> Ordering,
+    ) {
+        samples.sort_by(|a, b| match order {
+            SortOrder::Asc => compare(&a.value, &b.value),
+            SortOrder::Desc => compare(&b.value, &a.value),
+        });
+    }
+    match samples {
+        TypedSamples::Integer(samples) => sort(samples, order, Ord::cmp),
+        TypedSamples::Numeric(samples) => sort(samples, order, Ord::cmp),
+        TypedSamples::Float(samples) => sort(samples, order, f64::total_cmp),
+        _ => {
+            return Err(InvalidQueryError(
+                "Only the integer, numeric and float samples can be sorted by value".to_string(),
+            )
+            .into())
+        }
+    }
+    Ok(())
+}
+
+/// Spans the reads over a primary storage, with the recent data,
+/// and a secondary storage, with the older data archived from the primary.
+///
+/// The writes go to the primary, and the reads merge the results of
+/// both tiers by datetime, the primary winning when both have a sample
+/// at the same datetime. The annotations, the raw SQL queries and the
+/// integer sensor ids only concern the primary.
+///
+/// The connection string is `tiered:<primary>|<secondary>`, such as
+/// `tiered:timescaledb://localhost/sensapp|parquet:///var/lib/sensapp/archive`.
+#[derive(Debug)]
+pub struct TieredStorage {
+    primary: Arc<dyn StorageInstance>,
+    secondary: Arc<dyn StorageInstance>,
+}
+
+impl TieredStorage {
+    pub fn new(primary: Arc<dyn StorageInstance>, secondary: Arc<dyn StorageInstance>) -> Self {
+        Self { primary, secondary }
+    }
+
+    pub async fn connect(connection_string: &str) -> Result<Self> {
+        let (primary, secondary) = parse_tiered_connection_string(connection_string)?;
+        // Boxed, as the storage factory is calling this function.
+        let primary = Box::pin(create_storage_from_connection_string(primary)).await?;
+        let secondary = Box::pin(create_storage_from_connection_string(secondary)).await?;
+        Ok(Self::new(primary, secondary))
+    }
+}
+
+/// Splits a `tiered:<primary>|<secondary>` connection string.
+pub fn parse_tiered_connection_string(connection_string: &str) -> Result<(&str, &str)> {
+    let tiers = connection_string
+        .strip_prefix("tiered:")
+        .ok_or_else(|| anyhow!("The tiered connection string must start with tiered:"))?;
+    match tiers.split_once('|') {
+        Some((primary, secondary)) if !primary.is_empty() && !secondary.is_empty() => {
+            Ok((primary, secondary))
+        }
+        _ => bail!("The tiered connection string must be tiered:<primary>|<secondary>"),
+    }
+}
+
+#[async_trait]
+impl StorageInstance for TieredStorage {
+    async fn create_or_migrate(&self) -> Result<()> {
+        self.primary.create_or_migrate().await?;
+        self.secondary.create_or_migrate().await
+    }
+
+    fn circuit_breaker_state(&self) -> Option<CircuitBreakerState> {
+        self.primary.circuit_breaker_state()
+    }
+
+    fn write_buffer_status(&self) -> Option<WriteBufferStatus> {
+        self.primary.write_buffer_status()
+    }
+
+    async fn check_schema(&self) -> Result<()> {
+        self.primary.check_schema().await?;
+        self.secondary.check_schema().await
+    }
+
+    async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
+        self.primary.publish(batch, sync_sender).await
+    }
+
+    async fn sync(&self, sync_sender: Sender<()>) -> Result<()> {
+        self.primary.sync(sync_sender).await
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        self.primary.vacuum().await
+    }
+
+    async fn list_sensors(&self) -> Result<Vec<String>> {
+        let mut sensors = self.primary.list_sensors().await?;
+        for sensor in self.secondary.list_sensors().await? {
+            if !sensors.contains(&sensor) {
+                sensors.push(sensor);
+            }
+        }
+        Ok(sensors)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.primary.health_check().await?;
+        self.secondary.health_check().await
+    }
+
+    async fn get_sensor(&self, sensor_uuid: Uuid) -> Result<Option<Sensor>> {
+        match self.primary.get_sensor(sensor_uuid).await? {
+            Some(sensor) => Ok(Some(sensor)),
+            None => self.secondary.get_sensor(sensor_uuid).await,
+        }
+    }
+
+    /// Updated in both tiers, as the archived sensors are created in both.
+    async fn update_sensor_metadata(
+        &self,
+        sensor_uuid: Uuid,
+        metadata: &SensorMetadata,
+    ) -> Result<bool> {
+        let primary = self
+            .primary
+            .update_sensor_metadata(sensor_uuid, metadata)
+            .await?;
+        let secondary = self
+            .secondary
+            .update_sensor_metadata(sensor_uuid, metadata)
+            .await?;
+        Ok(primary || secondary)
+    }
+
+    async fn query_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let primary = self
+            .primary
+            .query_sensor_data(sensor_uuid, start, end, limit)
+            .await?;
+        let secondary = self
+            .secondary
+            .query_sensor_data(sensor_uuid, start, end, limit)
+            .await?;
+        merge_sensor_data(primary, secondary, limit)
+    }
+
+    async fn query_sensor_data_by_value(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        order: SortOrder,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let primary = self
+            .primary
+            .query_sensor_data_by_value(sensor_uuid, start, end, order, limit)
+            .await?;
+        let secondary = self
+            .secondary
+            .query_sensor_data_by_value(sensor_uuid, start, end, order, limit)
+            .await?;
+        // Each tier has its own top samples, merged by datetime
+        // then sorted again by value before the limit.
+        let mut sensor_data = match merge_sensor_data(primary, secondary, None)? {
+            Some(sensor_data) => sensor_data,
+            None => return Ok(None),
+        };
+        sort_samples_by_value(&mut sensor_data.samples, order)?;
+        if let Some(limit) = limit {
+            let mut count = 0;
+            sensor_data.samples.retain_by_datetime(|_| {
+                count += 1;
+                count <= limit
+            });
+        }
+        Ok(Some(sensor_data))
+    }
+
+    async fn query_sensor_data_window(
+        &self,
+        sensor_uuid: Uuid,
+        window: hifitime::Duration,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        let primary = self
+            .primary
+            .query_sensor_data_window(sensor_uuid, window, None)
+            .await?;
+        let secondary = self
+            .secondary
+            .query_sensor_data_window(sensor_uuid, window, None)
+            .await?;
+        // Each tier has the window before its own latest sample,
+        // so the merged samples are trimmed to the most recent window.
+        let mut sensor_data = match merge_sensor_data(primary, secondary, None)? {
+            Some(sensor_data) => sensor_data,
+            None => return Ok(None),
+        };
+        if let Some(latest) = sensor_data.samples.last_datetime() {
+            let start = latest - window;
+            sensor_data
+                .samples
+                .retain_by_datetime(|datetime| *datetime >= start);
+        }
+        merge_sensor_data(Some(sensor_data), None, limit)
+    }
+
+    async fn query(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorData>> {
+        let primary = self.primary.query(selector, time_range, limit).await?;
+        let mut secondary = self
+            .secondary
+            .query(selector, time_range, limit)
+            .await?
+            .into_iter()
+            .map(|sensor_data| (sensor_data.sensor.uuid, sensor_data))
+            .collect::<HashMap<_, _>>();
+
+        let mut results = Vec::with_capacity(primary.len() + secondary.len());
+        for sensor_data in primary {
+            let secondary_data = secondary.remove(&sensor_data.sensor.uuid);
+            results.extend(merge_sensor_data(Some(sensor_data), secondary_data, limit)?);
+        }
+        results.extend(secondary.into_values());
+        Ok(results)
+    }
+
+    async fn query_sensor_data_by_id(
+        &self,
+        sensor_id: i64,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        // The ids are internal to each tier, the secondary ones may
+        // point to other sensors.
+        self.primary
+            .query_sensor_data_by_id(sensor_id, start, end, limit)
+            .await
+    }
+
+    /// The secondary samples from the first primary sample on were
+    /// archived while still in the primary, so only the secondary samples
+    /// before it are aggregated, and the overlap isn't counted twice.
+    async fn aggregate_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+        step: hifitime::Duration,
+    ) -> Result<Option<Vec<AggregateBucket>>> {
+        let primary = self
+            .primary
+            .aggregate_sensor_data(sensor_uuid, time_range, step)
+            .await?;
+        let primary_start = self
+            .primary
+            .query_sensor_data(sensor_uuid, time_range.start, time_range.end, Some(1))
+            .await?
+            .and_then(|sensor_data| sensor_data.samples.first_datetime());
+        let secondary_range = match primary_start {
+            Some(primary_start) => TimeRange::new(time_range.start, Some(primary_start)),
+            None => time_range,
+        };
+        let secondary = self
+            .secondary
+            .aggregate_sensor_data(sensor_uuid, secondary_range, step)
+            .await?;
+        Ok(match (primary, secondary) {
+            (Some(primary), Some(secondary)) => Some(merge_buckets(primary, secondary)),
+            (Some(buckets), None) | (None, Some(buckets)) => Some(buckets),
+            (None, None) => None,
+        })
+    }
+
+    async fn verify_sensor(&self, sensor_uuid: Uuid) -> Result<Option<VerifyReport>> {
+        // Each tier is verified in its own storage order, and the anomalies added up.
+        let primary = self.primary.verify_sensor(sensor_uuid).await?;
+        let secondary = self.secondary.verify_sensor(sensor_uuid).await?;
+        Ok(match (primary, secondary) {
+            (Some(mut report), Some(secondary)) => {
+                report.sample_count += secondary.sample_count;
+                report.duplicate_timestamps += secondary.duplicate_timestamps;
+                report.out_of_order_timestamps += secondary.out_of_order_timestamps;
+                report.non_finite_floats += secondary.non_finite_floats;
+                report.invalid_utf8_strings += secondary.invalid_utf8_strings;
+                Some(report)
+            }
+            (Some(report), None) | (None, Some(report)) => Some(report),
+            (None, None) => None,
+        })
+    }
+
+    async fn add_annotation(&self, sensor_uuid: Uuid, annotation: &Annotation) -> Result<bool> {
+        self.primary.add_annotation(sensor_uuid, annotation).await
+    }
+
+    async fn query_annotations(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+    ) -> Result<Option<Vec<Annotation>>> {
+        self.primary
+            .query_annotations(sensor_uuid, time_range)
+            .await
+    }
+
+    async fn raw_sql_query(
+        &self,
+        sql: &str,
+        max_rows: usize,
+        timeout: Duration,
+    ) -> Result<RawSqlResult> {
+        self.primary.raw_sql_query(sql, max_rows, timeout).await
+    }
+
+    async fn unknown_sensors(&self, sensor_uuids: &[Uuid]) -> Result<Vec<Uuid>> {
+        let unknown_sensors = self.primary.unknown_sensors(sensor_uuids).await?;
+        if unknown_sensors.is_empty() {
+            return Ok(unknown_sensors);
+        }
+        self.secondary.unknown_sensors(&unknown_sensors).await
+    }
+
+    async fn apply_retention(&self, older_than: SensAppDateTime) -> Result<()> {
+        self.primary.apply_retention(older_than).await?;
+        self.secondary.apply_retention(older_than).await
+    }
+
+    async fn delete_metric(&self, name: &str) -> Result<u64> {
+        // The archived sensors are in both tiers, and counted once.
+        let primary = self.primary.delete_metric(name).await?;
+        let secondary = self.secondary.delete_metric(name).await?;
+        Ok(primary.max(secondary))
+    }
+
+    async fn latest_samples_all(&self, metric_filter: Option<&str>) -> Result<Vec<SensorData>> {
+        let mut results = self.primary.latest_samples_all(metric_filter).await?;
+        // The older tier only matters for the sensors without recent samples.
+        for sensor_data in self.secondary.latest_samples_all(metric_filter).await? {
+            if !results
+                .iter()
+                .any(|result| result.sensor.uuid == sensor_data.sensor.uuid)
+            {
+                results.push(sensor_data);
+            }
+        }
+        Ok(results)
+    }
+
+    async fn count_series_by_labels(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+    ) -> Result<Vec<(Sensor, u64)>> {
+        // The samples in both tiers, while they are archived, are counted twice.
+        let mut counts = self
+            .primary
+            .count_series_by_labels(selector, time_range)
+            .await?;
+        for (sensor, count) in self
+            .secondary
+            .count_series_by_labels(selector, time_range)
+            .await?
+        {
+            match counts
+                .iter_mut()
+                .find(|(primary_sensor, _)| primary_sensor.uuid == sensor.uuid)
+            {
+                Some((_, primary_count)) => *primary_count += count,
+                None => counts.push((sensor, count)),
+            }
+        }
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_configuration;
+    use crate::datamodel::{
+        batch::SingleSensorBatch, sensapp_datetime::SensAppDateTimeExt, Sample, SensorType,
+    };
+    use crate::storage::circuit_breaker::{CircuitBreakerOptions, CircuitBreakerStorage};
+    use crate::storage::sqlite::sqlite::tests::memory_connection_string;
+    use crate::storage::sqlite::SqliteStorage;
+    use crate::storage::write_buffer::{WriteBufferOptions, WriteBufferStorage};
+    use smallvec::smallvec;
+
+    fn float_samples(seconds: std::ops::Range<i64>) -> TypedSamples {
+        TypedSamples::Float(
+            seconds
+                .map(|i| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(1_700_000_000 + i),
+                    value: i as f64,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse_tiered_connection_string() {
+        assert_eq!(
+            parse_tiered_connection_string("tiered:sqlite://hot.db|parquet:///archive").unwrap(),
+            ("sqlite://hot.db", "parquet:///archive")
+        );
+        assert!(parse_tiered_connection_string("sqlite://hot.db|parquet:///archive").is_err());
+        assert!(parse_tiered_connection_string("tiered:sqlite://hot.db").is_err());
+        assert!(parse_tiered_connection_string("tiered:sqlite://hot.db|").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tiered_storage() {
+        _ = load_configuration();
+        let secondary_connection_string = memory_connection_string();
+        let storage = TieredStorage::connect(&format!(
+            "tiered:{}|{}",
+            memory_connection_string(),
+            secondary_connection_string
+        ))
+        .await
+        .unwrap();
+        storage.create_or_migrate().await.unwrap();
+
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("test_tiered_storage_{}", Uuid::new_v4()),
+                SensorType::Float,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        // The recent samples, written through the tiered storage
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            float_samples(5..10)
+        )]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        // The cold samples, archived in the secondary storage,
+        // the last one still being in the primary storage.
+        let secondary = SqliteStorage::connect(&secondary_connection_string)
+            .await
+            .unwrap();
+        let batch = Batch::new(smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            float_samples(0..6)
+        )]);
+        secondary
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+
+        let sensor_data = storage
+            .query_sensor_data(sensor.uuid, None, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.sensor.name, sensor.name);
+        assert_eq!(sensor_data.samples, float_samples(0..10));
+
+        let sensor_data = storage
+            .query_sensor_data(
+                sensor.uuid,
+                Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_003)),
+                Some(SensAppDateTime::from_unix_seconds_i64(1_700_000_008)),
+                Some(4),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sensor_data.samples, float_samples(3..7));
+
+        // Buckets of 5 seconds, the second one in both tiers,
+        // with the archived sample counted once
+        let buckets = storage
+            .aggregate_sensor_data(
+                sensor.uuid,
+                TimeRange::default(),
+                hifitime::Duration::from_seconds(5.0),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(
+            (
+                buckets[0].count,
+                buckets[0].min,
+                buckets[0].max,
+                buckets[0].avg
+            ),
+            (5, 0.0, 4.0, 2.0)
+        );
+        assert_eq!(
+            (
+                buckets[1].count,
+                buckets[1].min,
+                buckets[1].max,
+                buckets[1].avg
+            ),
+            (5, 5.0, 9.0, 7.0)
+        );
+
+        let report = storage.verify_sensor(sensor.uuid).await.unwrap().unwrap();
+        assert_eq!(report.sample_count, 11);
+
+        let annotation = Annotation::new(
+            SensAppDateTime::from_unix_seconds_i64(1_700_000_002),
+            "Calibrated".to_string(),
+            None,
+            Vec::new(),
+        );
+        assert!(storage
+            .add_annotation(sensor.uuid, &annotation)
+            .await
+            .unwrap());
+        assert_eq!(
+            storage
+                .query_annotations(sensor.uuid, TimeRange::default())
+                .await
+                .unwrap()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        assert!(storage
+            .query_sensor_data(Uuid::new_v4(), None, None, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_merge_samples_type_mismatch() {
+        assert!(merge_samples(
+            float_samples(0..1),
+            TypedSamples::one_integer(1, SensAppDateTime::from_unix_seconds_i64(0))
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tiered_storage_status() {
+        _ = load_configuration();
+        let primary: Arc<dyn StorageInstance> = Arc::new(CircuitBreakerStorage::new(
+            Arc::new(WriteBufferStorage::new(
+                Arc::new(SqliteStorage::connect("sqlite::memory:").await.unwrap()),
+                WriteBufferOptions {
+                    capacity: 4,
+                    high_water_mark: 2,
+                },
+            )),
+            CircuitBreakerOptions {
+                failure_threshold: 3,
+                window: Duration::from_secs(60),
+                cooldown: Duration::from_secs(60),
+            },
+        ));
+        let secondary: Arc<dyn StorageInstance> =
+            Arc::new(SqliteStorage::connect("sqlite::memory:").await.unwrap());
+
+        // The health of the primary tier, which takes the writes
+        let storage = TieredStorage::new(primary.clone(), secondary.clone());
+        assert_eq!(
+            storage.circuit_breaker_state(),
+            Some(CircuitBreakerState::Closed)
+        );
+        assert_eq!(storage.write_buffer_status(), primary.write_buffer_status());
+        assert!(storage.write_buffer_status().is_some());
+
+        let storage = TieredStorage::new(secondary, primary);
+        assert_eq!(storage.circuit_breaker_state(), None);
+        assert_eq!(storage.write_buffer_status(), None);
+    }
+}
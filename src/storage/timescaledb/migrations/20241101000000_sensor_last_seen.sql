@@ -0,0 +1,2 @@
+-- When the sensors were last published, even without new samples.
+ALTER TABLE sensors ADD COLUMN last_seen TIMESTAMPTZ;
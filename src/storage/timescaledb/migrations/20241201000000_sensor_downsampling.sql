@@ -0,0 +1,4 @@
+-- Downsampling policy of the numeric sensors, the samples older than the raw retention
+-- are averaged in buckets of the rollup interval. Both are null without a policy.
+ALTER TABLE sensors ADD COLUMN raw_retention_days DOUBLE PRECISION;
+ALTER TABLE sensors ADD COLUMN rollup_interval_seconds BIGINT;
@@ -1,5 +1,7 @@
 pub mod timescaledb;
 pub mod timescaledb_publishers;
+pub mod timescaledb_queries;
+pub mod timescaledb_retention;
 pub mod timescaledb_utilities;
 
 pub use timescaledb::TimeScaleDBStorage;
@@ -1,5 +1,6 @@
 use super::timescaledb_utilities::get_string_value_id_or_create;
 use crate::datamodel::{sensapp_datetime::sensapp_datetime_to_offset_datetime, Sample};
+use crate::storage::blob_compression::BlobCompression;
 use anyhow::Result;
 use sqlx::{prelude::*, Postgres, Transaction};
 
@@ -138,9 +139,11 @@ pub async fn publish_blob_values(
     transaction: &mut Transaction<'_, Postgres>,
     sensor_id: i64,
     values: &[Sample<Vec<u8>>],
+    blob_compression: BlobCompression,
 ) -> Result<()> {
     for value in values {
         let time = sensapp_datetime_to_offset_datetime(&value.datetime)?;
+        let stored_value = blob_compression.compress(&value.value)?.into_owned();
         let query = sqlx::query(
             r#"
             INSERT INTO blob_values (sensor_id, time, value)
@@ -149,7 +152,7 @@ pub async fn publish_blob_values(
         )
         .bind(sensor_id)
         .bind(time)
-        .bind(value.value.clone());
+        .bind(stored_value);
         transaction.execute(query).await?;
     }
     Ok(())
@@ -0,0 +1,166 @@
+use crate::datamodel::{
+    sensapp_datetime::{sensapp_datetime_to_offset_datetime, SensAppDateTimeExt},
+    SensAppDateTime, SensorType, TypedSamples,
+};
+use crate::storage::postgresql::postgresql_queries::{
+    decode_samples, decode_samples_of_sensors, value_columns, values_table,
+};
+use crate::storage::query::{AggregateBucket, InvalidQueryError, SensorSelector};
+use anyhow::Result;
+use sqlx::{postgres::PgRow, PgPool, Row};
+use std::collections::HashMap;
+
+/// Reads the datetime of the `timestamp_us` column.
+fn timestamp_us_datetime(row: &PgRow) -> Result<SensAppDateTime> {
+    Ok(SensAppDateTime::from_unix_microseconds_i64(
+        row.try_get("timestamp_us")?,
+    ))
+}
+
+/// Returns the most recent sample of each sensor of the type,
+/// with the internal sensor_id of the sensor.
+///
+/// The sensors can be restricted to a name, and the sensors without
+/// samples are left out. One query per type, with `DISTINCT ON`.
+pub async fn query_latest_samples(
+    pool: &PgPool,
+    sensor_type: SensorType,
+    metric_filter: Option<&str>,
+) -> Result<Vec<(i64, TypedSamples)>> {
+    let (value_columns, value_join) = value_columns(sensor_type, "latest");
+    let sql = format!(
+        r#"
+        SELECT DISTINCT ON (latest.sensor_id) latest.sensor_id,
+            (EXTRACT(EPOCH FROM latest.time) * 1000000)::BIGINT AS timestamp_us, {}
+        FROM {} AS latest
+        {}
+        WHERE $1::TEXT IS NULL
+            OR latest.sensor_id IN (SELECT sensor_id FROM sensors WHERE name = $1)
+        ORDER BY latest.sensor_id, latest.time DESC
+        "#,
+        value_columns,
+        values_table(sensor_type),
+        value_join
+    );
+    let rows = sqlx::query(&sql)
+        .bind(metric_filter)
+        .fetch_all(pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| {
+            let samples = decode_samples(
+                sensor_type,
+                std::slice::from_ref(row),
+                timestamp_us_datetime,
+            )?;
+            Ok((row.try_get("sensor_id")?, samples))
+        })
+        .collect()
+}
+
+/// Returns the samples of several sensors of the same type, sorted by
+/// datetime, in one query with a window function.
+///
+/// The limit applies to each sensor.
+pub async fn query_samples_of_sensors(
+    pool: &PgPool,
+    sensor_ids: &[i64],
+    sensor_type: SensorType,
+    start: Option<SensAppDateTime>,
+    end: Option<SensAppDateTime>,
+    limit: Option<usize>,
+) -> Result<HashMap<i64, TypedSamples>> {
+    let (value_columns, value_join) = value_columns(sensor_type, "samples");
+    let start = start
+        .map(|start| sensapp_datetime_to_offset_datetime(&start))
+        .transpose()?;
+    let end = end
+        .map(|end| sensapp_datetime_to_offset_datetime(&end))
+        .transpose()?;
+    let sql = format!(
+        r#"
+        SELECT samples.sensor_id,
+            (EXTRACT(EPOCH FROM samples.time) * 1000000)::BIGINT AS timestamp_us, {}
+        FROM (
+            SELECT *, ROW_NUMBER() OVER (PARTITION BY sensor_id ORDER BY time) AS row_number
+            FROM {}
+            WHERE sensor_id = ANY($1)
+                AND ($2::TIMESTAMPTZ IS NULL OR time >= $2)
+                AND ($3::TIMESTAMPTZ IS NULL OR time < $3)
+        ) AS samples
+        {}
+        WHERE $4::BIGINT IS NULL OR samples.row_number <= $4
+        ORDER BY samples.sensor_id, samples.time
+        "#,
+        value_columns,
+        values_table(sensor_type),
+        value_join
+    );
+    let rows = sqlx::query(&sql)
+        .bind(sensor_ids)
+        .bind(start)
+        .bind(end)
+        .bind(limit.map(|limit| limit as i64))
+        .fetch_all(pool)
+        .await?;
+    decode_samples_of_sensors(sensor_type, sensor_ids, rows, timestamp_us_datetime)
+}
+
+/// Aggregates the numeric samples of a sensor in buckets of `step_seconds`
+/// aligned on the unix epoch, with an inclusive start and an exclusive end.
+///
+/// The buckets are computed by `time_bucket`, with the unix epoch as origin
+/// instead of its default of 2000-01-03.
+pub async fn aggregate_samples(
+    pool: &PgPool,
+    sensor_id: i64,
+    sensor_type: SensorType,
+    start: Option<SensAppDateTime>,
+    end: Option<SensAppDateTime>,
+    step_seconds: f64,
+) -> Result<Vec<AggregateBucket>> {
+    if !SensorSelector::is_numeric(sensor_type) {
+        return Err(
+            InvalidQueryError("Only the numeric sensors can be aggregated".to_string()).into(),
+        );
+    }
+    let start = start
+        .map(|start| sensapp_datetime_to_offset_datetime(&start))
+        .transpose()?;
+    let end = end
+        .map(|end| sensapp_datetime_to_offset_datetime(&end))
+        .transpose()?;
+    let rows: Vec<(i64, i64, f64, f64, f64)> = sqlx::query_as(&format!(
+        r#"
+        SELECT (EXTRACT(EPOCH FROM time_bucket(
+                make_interval(secs => $1), time, TIMESTAMPTZ '1970-01-01 00:00:00+00'
+            )) * 1000000)::BIGINT AS bucket, COUNT(*),
+            MIN(value::DOUBLE PRECISION), MAX(value::DOUBLE PRECISION),
+            AVG(value::DOUBLE PRECISION)
+        FROM {}
+        WHERE sensor_id = $2
+            AND ($3::TIMESTAMPTZ IS NULL OR time >= $3)
+            AND ($4::TIMESTAMPTZ IS NULL OR time < $4)
+        GROUP BY bucket
+        ORDER BY bucket ASC
+        "#,
+        values_table(sensor_type)
+    ))
+    .bind(step_seconds)
+    .bind(sensor_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(bucket, count, min, max, avg)| AggregateBucket {
+            start: SensAppDateTime::from_unix_microseconds_i64(bucket),
+            count: count as u64,
+            min,
+            max,
+            avg,
+        })
+        .collect())
+}
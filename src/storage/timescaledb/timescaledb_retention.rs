@@ -0,0 +1,118 @@
+use crate::datamodel::{
+    sensapp_datetime::sensapp_datetime_to_offset_datetime, SensAppDateTime, SensorType,
+};
+use crate::storage::schema::VALUE_TABLES;
+use anyhow::Result;
+use sqlx::PgPool;
+
+/// Deletes the samples older than the cutoff, in the hypertables
+/// of the sample types.
+///
+/// The chunks entirely before the cutoff are dropped with `drop_chunks`,
+/// which is instant, and the samples left in the chunk overlapping
+/// the cutoff are deleted row by row.
+pub async fn delete_samples_older_than(
+    pool: &PgPool,
+    older_than: SensAppDateTime,
+    sample_types: &[SensorType],
+) -> Result<()> {
+    let older_than = sensapp_datetime_to_offset_datetime(&older_than)?;
+    let tables = VALUE_TABLES
+        .iter()
+        .filter(|(sensor_type, _)| sample_types.contains(sensor_type))
+        .map(|(_, table)| *table);
+    for table in tables {
+        let mut transaction = pool.begin().await?;
+        sqlx::query("SELECT drop_chunks($1::REGCLASS, older_than => $2)")
+            .bind(table)
+            .bind(older_than)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(&format!("DELETE FROM {} WHERE time < $1", table))
+            .bind(older_than)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{
+        batch::{Batch, SingleSensorBatch},
+        sensapp_datetime::SensAppDateTimeExt,
+        Sample, Sensor, TypedSamples,
+    };
+    use crate::storage::storage::StorageInstance;
+    use crate::storage::timescaledb::TimeScaleDBStorage;
+    use std::sync::Arc;
+
+    async fn count_chunks(pool: &PgPool) -> i64 {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM timescaledb_information.chunks WHERE hypertable_name = 'float_values'",
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    /// It needs a TimescaleDB database:
+    /// `SENSAPP_TEST_TIMESCALEDB_CONNECTION_STRING=postgres://... cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn test_retention_drops_chunks() {
+        let connection_string = std::env::var("SENSAPP_TEST_TIMESCALEDB_CONNECTION_STRING")
+            .expect("SENSAPP_TEST_TIMESCALEDB_CONNECTION_STRING is not set");
+        let storage = TimeScaleDBStorage::connect(&connection_string)
+            .await
+            .unwrap();
+        storage.create_or_migrate().await.unwrap();
+        let pool = PgPool::connect(&connection_string).await.unwrap();
+
+        // Far in the past, one sample a day over 5 chunks of 7 days.
+        let start = 86_400 * 365;
+        let sensor = Arc::new(
+            Sensor::new_without_uuid(
+                format!("retention_{}", uuid::Uuid::new_v4()),
+                SensorType::Float,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let samples = TypedSamples::Float(
+            (0..35)
+                .map(|day| Sample {
+                    datetime: SensAppDateTime::from_unix_seconds_i64(start + day * 86_400),
+                    value: day as f64,
+                })
+                .collect(),
+        );
+        let batch = Batch::new(smallvec::smallvec![SingleSensorBatch::new(
+            sensor.clone(),
+            samples
+        )]);
+        storage
+            .publish(Arc::new(batch), async_broadcast::broadcast(1).0)
+            .await
+            .unwrap();
+        let chunks_before = count_chunks(&pool).await;
+
+        // In the middle of a chunk, so it is partially deleted.
+        let cutoff = SensAppDateTime::from_unix_seconds_i64(start + 17 * 86_400);
+        storage.apply_retention(cutoff).await.unwrap();
+
+        assert!(count_chunks(&pool).await < chunks_before);
+        let remaining: Vec<(f64,)> = sqlx::query_as(
+            "SELECT value FROM float_values JOIN sensors USING (sensor_id) WHERE sensors.uuid = $1 ORDER BY time",
+        )
+        .bind(sensor.uuid)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(remaining.len(), 35 - 17);
+        assert_eq!(remaining[0].0, 17.0);
+    }
+}
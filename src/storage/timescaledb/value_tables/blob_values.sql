@@ -0,0 +1,15 @@
+-- The 'blob_values' hypertable, as created by the migrations
+CREATE TABLE blob_values (
+    sensor_id BIGINT NOT NULL,
+    time TIMESTAMPTZ NOT NULL,
+    value BYTEA NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+SELECT create_hypertable('blob_values', by_range('time', INTERVAL '7 days'));
+ALTER TABLE blob_values SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'sensor_id'
+);
+SELECT add_compression_policy('blob_values', INTERVAL '7 days');
+SELECT add_dimension('blob_values', by_hash('sensor_id', 2));
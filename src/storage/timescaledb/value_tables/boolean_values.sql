@@ -0,0 +1,15 @@
+-- The 'boolean_values' hypertable, as created by the migrations
+CREATE TABLE boolean_values (
+    sensor_id BIGINT NOT NULL,
+    time TIMESTAMPTZ NOT NULL,
+    value BOOLEAN NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+SELECT create_hypertable('boolean_values', by_range('time', INTERVAL '7 days'));
+ALTER TABLE boolean_values SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'sensor_id'
+);
+SELECT add_compression_policy('boolean_values', INTERVAL '7 days');
+SELECT add_dimension('boolean_values', by_hash('sensor_id', 2));
@@ -0,0 +1,15 @@
+-- The 'float_values' hypertable, as created by the migrations
+CREATE TABLE float_values (
+    sensor_id BIGINT NOT NULL,
+    time TIMESTAMPTZ NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+SELECT create_hypertable('float_values', by_range('time', INTERVAL '7 days'));
+ALTER TABLE float_values SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'sensor_id'
+);
+SELECT add_compression_policy('float_values', INTERVAL '7 days');
+SELECT add_dimension('float_values', by_hash('sensor_id', 2));
@@ -0,0 +1,15 @@
+-- The 'integer_values' hypertable, as created by the migrations
+CREATE TABLE integer_values (
+    sensor_id BIGINT NOT NULL,
+    time TIMESTAMPTZ NOT NULL,
+    value BIGINT NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+SELECT create_hypertable('integer_values', by_range('time', INTERVAL '7 days'));
+ALTER TABLE integer_values SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'sensor_id'
+);
+SELECT add_compression_policy('integer_values', INTERVAL '7 days');
+SELECT add_dimension('integer_values', by_hash('sensor_id', 2));
@@ -0,0 +1,15 @@
+-- The 'json_values' hypertable, as created by the migrations
+CREATE TABLE json_values (
+    sensor_id BIGINT NOT NULL,
+    time TIMESTAMPTZ NOT NULL,
+    value JSONB NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+SELECT create_hypertable('json_values', by_range('time', INTERVAL '7 days'));
+ALTER TABLE json_values SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'sensor_id'
+);
+SELECT add_compression_policy('json_values', INTERVAL '7 days');
+SELECT add_dimension('json_values', by_hash('sensor_id', 2));
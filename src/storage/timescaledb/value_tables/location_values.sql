@@ -0,0 +1,16 @@
+-- The 'location_values' hypertable, as created by the migrations
+CREATE TABLE location_values (
+    sensor_id BIGINT NOT NULL,
+    time TIMESTAMPTZ NOT NULL,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+SELECT create_hypertable('location_values', by_range('time', INTERVAL '7 days'));
+ALTER TABLE location_values SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'sensor_id'
+);
+SELECT add_compression_policy('location_values', INTERVAL '7 days');
+SELECT add_dimension('location_values', by_hash('sensor_id', 2));
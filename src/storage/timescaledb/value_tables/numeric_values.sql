@@ -0,0 +1,15 @@
+-- The 'numeric_values' hypertable, as created by the migrations
+CREATE TABLE numeric_values (
+    sensor_id BIGINT NOT NULL,
+    time TIMESTAMPTZ NOT NULL,
+    value NUMERIC NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+);
+
+SELECT create_hypertable('numeric_values', by_range('time', INTERVAL '7 days'));
+ALTER TABLE numeric_values SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'sensor_id'
+);
+SELECT add_compression_policy('numeric_values', INTERVAL '7 days');
+SELECT add_dimension('numeric_values', by_hash('sensor_id', 2));
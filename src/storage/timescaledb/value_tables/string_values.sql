@@ -0,0 +1,17 @@
+-- The 'string_values' hypertable, as created by the migrations
+CREATE TABLE string_values (
+    sensor_id BIGINT NOT NULL,
+    time TIMESTAMPTZ NOT NULL,
+    value BIGINT NOT NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors(sensor_id)
+    -- foreign key disabled to allow the hypertable performance boost
+    -- FOREIGN KEY (value) REFERENCES strings_values_dictionary(id)
+);
+
+SELECT create_hypertable('string_values', by_range('time', INTERVAL '7 days'));
+ALTER TABLE string_values SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'sensor_id'
+);
+SELECT add_compression_policy('string_values', INTERVAL '7 days');
+SELECT add_dimension('string_values', by_hash('sensor_id', 2));
@@ -0,0 +1,257 @@
+use super::storage::StorageInstance;
+use crate::datamodel::{
+    arrow_converter::ArrowConverter, Sample, SensAppVec, Sensor, SensorType, TypedSamples,
+};
+use anyhow::Result;
+use serde::Deserialize;
+use std::{collections::HashSet, sync::Arc};
+
+/// What to do when a sensor is published with a new type, while a sensor
+/// with the same name and labels already exists with another type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TypeConflictPolicy {
+    /// Fail with a [`TypeConflictError`].
+    Reject,
+    /// Create a distinct series for the new type, as the type is part
+    /// of the sensor UUID.
+    #[default]
+    NewSeries,
+    /// Convert the samples to the numeric type of the existing sensor,
+    /// and fail with a [`TypeConflictError`] when they can't be.
+    Coerce,
+}
+
+/// The error returned for the samples of a sensor conflicting
+/// with the type of an existing sensor.
+#[derive(Debug)]
+pub struct TypeConflictError {
+    pub name: String,
+    pub existing_type: SensorType,
+    pub new_type: SensorType,
+}
+
+impl std::fmt::Display for TypeConflictError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The sensor {} already exists with the type {}, not {}",
+            self.name,
+            self.existing_type.to_string(),
+            self.new_type.to_string()
+        )
+    }
+}
+
+impl std::error::Error for TypeConflictError {}
+
+/// The configured policy, the default one when the configuration isn't loaded.
+pub fn type_conflict_policy() -> TypeConflictPolicy {
+    crate::config::get()
+        .map(|config| config.type_conflict_policy)
+        .unwrap_or_default()
+}
+
+/// Fails with a [`TypeConflictError`] when some new sensors already exist
+/// with another type, before publishing their samples with the reject policy.
+///
+/// The existing sensors are found by the UUID they would have with the other
+/// types, so only the ones with the same unit. The storage checks them all.
+pub async fn check_type_conflicts(
+    storage: &dyn StorageInstance,
+    sensors: &[Arc<Sensor>],
+) -> Result<()> {
+    let uuids = sensors.iter().map(|sensor| sensor.uuid).collect::<Vec<_>>();
+    let new_uuids = storage
+        .unknown_sensors(&uuids)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let mut candidates = Vec::new();
+    for sensor in sensors
+        .iter()
+        .filter(|sensor| new_uuids.contains(&sensor.uuid))
+    {
+        for sensor_type in SensorType::ALL {
+            if sensor_type == sensor.sensor_type {
+                continue;
+            }
+            let candidate = Sensor::new_without_uuid(
+                sensor.name.clone(),
+                sensor_type,
+                sensor.unit.clone(),
+                Some(sensor.labels.clone()),
+            )?;
+            candidates.push((sensor, sensor_type, candidate.uuid));
+        }
+    }
+    if candidates.is_empty() {
+        return Ok(());
+    }
+
+    let candidate_uuids = candidates
+        .iter()
+        .map(|(_, _, uuid)| *uuid)
+        .collect::<Vec<_>>();
+    let unknown_uuids = storage
+        .unknown_sensors(&candidate_uuids)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    match candidates
+        .into_iter()
+        .find(|(_, _, uuid)| !unknown_uuids.contains(uuid))
+    {
+        Some((sensor, existing_type, _)) => Err(TypeConflictError {
+            name: sensor.name.clone(),
+            existing_type,
+            new_type: sensor.sensor_type,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Converts the samples to the numeric type of an existing sensor.
+///
+/// The values are converted through their textual form, so the string `"21.5"`
+/// becomes the float 21.5 and the float 21.0 the integer 21, but the float 21.5
+/// isn't an integer.
+pub fn coerce_samples(
+    name: &str,
+    samples: &TypedSamples,
+    existing_type: SensorType,
+) -> Result<TypedSamples> {
+    let conflict = || TypeConflictError {
+        name: name.to_string(),
+        existing_type,
+        new_type: ArrowConverter::sensor_type_of(samples),
+    };
+    let coerced = match existing_type {
+        SensorType::Integer => coerce_values(samples).map(TypedSamples::Integer),
+        SensorType::Numeric => coerce_values(samples).map(TypedSamples::Numeric),
+        SensorType::Float => coerce_values(samples).map(TypedSamples::Float),
+        _ => None,
+    };
+    coerced.ok_or_else(|| conflict().into())
+}
+
+fn coerce_values<T: std::str::FromStr>(samples: &TypedSamples) -> Option<SensAppVec<Sample<T>>> {
+    fn parse<V: ToString, T: std::str::FromStr>(samples: &[Sample<V>]) -> Option<Vec<Sample<T>>> {
+        samples
+            .iter()
+            .map(|sample| {
+                Some(Sample {
+                    datetime: sample.datetime,
+                    value: sample.value.to_string().trim().parse().ok()?,
+                })
+            })
+            .collect()
+    }
+    let values = match samples {
+        TypedSamples::Integer(samples) => parse(samples),
+        TypedSamples::Numeric(samples) => parse(samples),
+        TypedSamples::Float(samples) => parse(samples),
+        TypedSamples::String(samples) => parse(samples),
+        _ => None,
+    }?;
+    Some(values.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datamodel::{sensapp_datetime::SensAppDateTimeExt, SensAppDateTime};
+    use smallvec::smallvec;
+
+    fn datetime() -> SensAppDateTime {
+        SensAppDateTime::from_unix_seconds_i64(1_700_000_000)
+    }
+
+    #[test]
+    fn test_policy_names() {
+        for (name, policy) in [
+            ("reject", TypeConflictPolicy::Reject),
+            ("new_series", TypeConflictPolicy::NewSeries),
+            ("coerce", TypeConflictPolicy::Coerce),
+        ] {
+            assert_eq!(
+                serde_json::from_value::<TypeConflictPolicy>(serde_json::json!(name)).unwrap(),
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn test_coerce_samples() {
+        let strings = TypedSamples::String(smallvec![
+            Sample {
+                datetime: datetime(),
+                value: "21.5".to_string(),
+            },
+            Sample {
+                datetime: datetime(),
+                value: " 22 ".to_string(),
+            },
+        ]);
+        assert_eq!(
+            coerce_samples("temp", &strings, SensorType::Float).unwrap(),
+            TypedSamples::Float(smallvec![
+                Sample {
+                    datetime: datetime(),
+                    value: 21.5,
+                },
+                Sample {
+                    datetime: datetime(),
+                    value: 22.0,
+                },
+            ])
+        );
+        assert_eq!(
+            coerce_samples(
+                "temp",
+                &TypedSamples::one_float(21.0, datetime()),
+                SensorType::Integer
+            )
+            .unwrap(),
+            TypedSamples::one_integer(21, datetime())
+        );
+        assert_eq!(
+            coerce_samples(
+                "temp",
+                &TypedSamples::one_integer(21, datetime()),
+                SensorType::Numeric
+            )
+            .unwrap(),
+            TypedSamples::one_numeric(rust_decimal::Decimal::from(21), datetime())
+        );
+
+        let error = coerce_samples(
+            "temp",
+            &TypedSamples::one_float(21.5, datetime()),
+            SensorType::Integer,
+        )
+        .unwrap_err();
+        assert!(error.is::<TypeConflictError>());
+        assert!(coerce_samples(
+            "temp",
+            &TypedSamples::one_string("warm".to_string(), datetime()),
+            SensorType::Float
+        )
+        .is_err());
+        // Only towards the numeric types
+        assert!(coerce_samples(
+            "temp",
+            &TypedSamples::one_float(21.5, datetime()),
+            SensorType::String
+        )
+        .is_err());
+        assert!(coerce_samples(
+            "temp",
+            &TypedSamples::one_boolean(true, datetime()),
+            SensorType::Float
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use serde::Serialize;
+
+/// The anomalies found in the stored samples of a sensor.
+///
+/// The timestamps are checked in the storage order, so a sample stored
+/// with a timestamp before the previous sample is out of order, and a
+/// sample with the same timestamp as the previous sample is a duplicate.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    pub sample_count: u64,
+    pub duplicate_timestamps: u64,
+    pub out_of_order_timestamps: u64,
+    /// NaN and infinite values, in the float sensors only.
+    pub non_finite_floats: u64,
+    /// Invalid UTF-8 values, in the string sensors only.
+    pub invalid_utf8_strings: u64,
+    #[serde(skip)]
+    previous_timestamp_ns: Option<i128>,
+}
+
+impl VerifyReport {
+    /// Checks the next sample timestamp, in nanoseconds, in the storage order.
+    pub fn check_timestamp(&mut self, timestamp_ns: i128) {
+        self.sample_count += 1;
+        if let Some(previous_timestamp_ns) = self.previous_timestamp_ns {
+            if timestamp_ns == previous_timestamp_ns {
+                self.duplicate_timestamps += 1;
+            } else if timestamp_ns < previous_timestamp_ns {
+                self.out_of_order_timestamps += 1;
+            }
+        }
+        self.previous_timestamp_ns = Some(timestamp_ns);
+    }
+
+    pub fn check_float(&mut self, value: f64) {
+        if !value.is_finite() {
+            self.non_finite_floats += 1;
+        }
+    }
+
+    pub fn check_string(&mut self, value: &[u8]) {
+        if std::str::from_utf8(value).is_err() {
+            self.invalid_utf8_strings += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_report() {
+        let mut report = VerifyReport::default();
+        for timestamp in [1, 2, 2, 5, 3, 6] {
+            report.check_timestamp(timestamp);
+        }
+        report.check_float(1.0);
+        report.check_float(f64::NAN);
+        report.check_float(f64::NEG_INFINITY);
+        report.check_string(b"hello");
+        report.check_string(&[0x80, 0xff]);
+
+        assert_eq!(report.sample_count, 6);
+        assert_eq!(report.duplicate_timestamps, 1);
+        assert_eq!(report.out_of_order_timestamps, 1);
+        assert_eq!(report.non_finite_floats, 2);
+        assert_eq!(report.invalid_utf8_strings, 1);
+    }
+}
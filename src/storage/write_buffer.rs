@@ -0,0 +1,431 @@
+use super::circuit_breaker::CircuitBreakerState;
+use super::query::{AggregateBucket, SensorSelector, SortOrder, TimeRange};
+use super::raw_sql::RawSqlResult;
+use super::storage::StorageInstance;
+use super::verify::VerifyReport;
+use crate::datamodel::{
+    batch::Batch, sensor_metadata::SensorMetadata, Annotation, SensAppDateTime, Sensor, SensorData,
+};
+use anyhow::{anyhow, Result};
+use async_broadcast::Sender;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WriteBufferOptions {
+    /// Number of batches waiting for the storage, 0 to disable the buffer.
+    pub capacity: usize,
+    /// Depth above which the buffer is backing up.
+    pub high_water_mark: usize,
+}
+
+impl WriteBufferOptions {
+    pub fn from_config() -> Result<Self> {
+        let config = crate::config::get()?;
+        let capacity = config.write_buffer_capacity;
+        let high_water_mark = match config.write_buffer_high_water_mark {
+            0 => capacity * 3 / 4,
+            high_water_mark => high_water_mark,
+        };
+        Ok(Self {
+            capacity,
+            high_water_mark,
+        })
+    }
+}
+
+/// The metrics of the write buffer, as reported by the health endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WriteBufferStatus {
+    pub depth: usize,
+    pub max_depth: usize,
+    pub high_water_mark: usize,
+    /// Batches rejected because the buffer was full.
+    pub dropped: u64,
+}
+
+impl WriteBufferStatus {
+    /// The storage doesn't keep up with the publications.
+    pub fn is_degraded(&self) -> bool {
+        self.depth > self.high_water_mark
+    }
+}
+
+#[derive(Debug, Default)]
+struct WriteBufferMetrics {
+    depth: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug)]
+enum BufferedMessage {
+    Publish(Arc<Batch>, Sender<()>),
+    /// Queued behind the batches, so they are published before the sync.
+    Sync(Sender<()>),
+}
+
+/// Wraps a storage backend to publish the batches in the background.
+///
+/// The publications return as soon as the batch is buffered, and a
+/// single task publishes the batches to the backend in order. The sync
+/// notifications are sent once the batches reach the backend.
+/// When the buffer is full, the batch is dropped and the publication fails.
+/// The syncs wait for the buffered batches before syncing the backend.
+/// The other calls go directly to the backend.
+#[derive(Debug)]
+pub struct WriteBufferStorage {
+    inner: Arc<dyn StorageInstance>,
+    options: WriteBufferOptions,
+    sender: mpsc::Sender<BufferedMessage>,
+    metrics: Arc<WriteBufferMetrics>,
+}
+
+impl WriteBufferStorage {
+    pub fn new(inner: Arc<dyn StorageInstance>, options: WriteBufferOptions) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<BufferedMessage>(options.capacity.max(1));
+        let metrics = Arc::new(WriteBufferMetrics::default());
+
+        let worker_inner = inner.clone();
+        let worker_metrics = metrics.clone();
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                match message {
+                    BufferedMessage::Publish(batch, sync_sender) => {
+                        worker_metrics.depth.fetch_sub(1, Ordering::SeqCst);
+                        if let Err(error) = worker_inner.publish(batch, sync_sender).await {
+                            tracing::error!("Failed to publish a buffered batch: {:?}", error);
+                        }
+                    }
+                    BufferedMessage::Sync(sync_sender) => {
+                        if let Err(error) = worker_inner.sync(sync_sender).await {
+                            tracing::error!("Failed to sync the storage: {:?}", error);
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            inner,
+            options,
+            sender,
+            metrics,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageInstance for WriteBufferStorage {
+    async fn create_or_migrate(&self) -> Result<()> {
+        self.inner.create_or_migrate().await
+    }
+
+    async fn check_schema(&self) -> Result<()> {
+        self.inner.check_schema().await
+    }
+
+    async fn publish(&self, batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
+        // Counted before sending, so the worker never decrements first.
+        // A rejected batch drops its sync sender, and the waiting
+        // publication fails.
+        self.metrics.depth.fetch_add(1, Ordering::SeqCst);
+        if self
+            .sender
+            .try_send(BufferedMessage::Publish(batch, sync_sender))
+            .is_err()
+        {
+            self.metrics.depth.fetch_sub(1, Ordering::SeqCst);
+            self.metrics.dropped.fetch_add(1, Ordering::SeqCst);
+            return Err(anyhow!("The write buffer is full"));
+        }
+        Ok(())
+    }
+
+    async fn sync(&self, sync_sender: Sender<()>) -> Result<()> {
+        self.sender
+            .send(BufferedMessage::Sync(sync_sender))
+            .await
+            .map_err(|_| anyhow!("The write buffer is closed"))
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        self.inner.vacuum().await
+    }
+
+    async fn list_sensors(&self) -> Result<Vec<String>> {
+        self.inner.list_sensors().await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    fn circuit_breaker_state(&self) -> Option<CircuitBreakerState> {
+        self.inner.circuit_breaker_state()
+    }
+
+    fn write_buffer_status(&self) -> Option<WriteBufferStatus> {
+        Some(WriteBufferStatus {
+            depth: self.metrics.depth.load(Ordering::SeqCst),
+            max_depth: self.options.capacity,
+            high_water_mark: self.options.high_water_mark,
+            dropped: self.metrics.dropped.load(Ordering::SeqCst),
+        })
+    }
+
+    async fn get_sensor(&self, sensor_uuid: Uuid) -> Result<Option<Sensor>> {
+        self.inner.get_sensor(sensor_uuid).await
+    }
+
+    async fn update_sensor_metadata(
+        &self,
+        sensor_uuid: Uuid,
+        metadata: &SensorMetadata,
+    ) -> Result<bool> {
+        self.inner
+            .update_sensor_metadata(sensor_uuid, metadata)
+            .await
+    }
+
+    async fn query_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.inner
+            .query_sensor_data(sensor_uuid, start, end, limit)
+            .await
+    }
+
+    async fn query_sensor_data_window(
+        &self,
+        sensor_uuid: Uuid,
+        window: hifitime::Duration,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.inner
+            .query_sensor_data_window(sensor_uuid, window, limit)
+            .await
+    }
+
+    async fn query_sensor_data_by_value(
+        &self,
+        sensor_uuid: Uuid,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        order: SortOrder,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.inner
+            .query_sensor_data_by_value(sensor_uuid, start, end, order, limit)
+            .await
+    }
+
+    async fn query_sensor_data_by_id(
+        &self,
+        sensor_id: i64,
+        start: Option<SensAppDateTime>,
+        end: Option<SensAppDateTime>,
+        limit: Option<usize>,
+    ) -> Result<Option<SensorData>> {
+        self.inner
+            .query_sensor_data_by_id(sensor_id, start, end, limit)
+            .await
+    }
+
+    async fn query(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+        limit: Option<usize>,
+    ) -> Result<Vec<SensorData>> {
+        self.inner.query(selector, time_range, limit).await
+    }
+
+    async fn unknown_sensors(&self, sensor_uuids: &[Uuid]) -> Result<Vec<Uuid>> {
+        self.inner.unknown_sensors(sensor_uuids).await
+    }
+
+    async fn apply_retention(&self, older_than: SensAppDateTime) -> Result<()> {
+        self.inner.apply_retention(older_than).await
+    }
+
+    async fn delete_metric(&self, name: &str) -> Result<u64> {
+        self.inner.delete_metric(name).await
+    }
+
+    async fn latest_samples_all(&self, metric_filter: Option<&str>) -> Result<Vec<SensorData>> {
+        self.inner.latest_samples_all(metric_filter).await
+    }
+
+    async fn count_series_by_labels(
+        &self,
+        selector: &SensorSelector,
+        time_range: TimeRange,
+    ) -> Result<Vec<(Sensor, u64)>> {
+        self.inner
+            .count_series_by_labels(selector, time_range)
+            .await
+    }
+
+    async fn aggregate_sensor_data(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+        step: hifitime::Duration,
+    ) -> Result<Option<Vec<AggregateBucket>>> {
+        self.inner
+            .aggregate_sensor_data(sensor_uuid, time_range, step)
+            .await
+    }
+
+    async fn verify_sensor(&self, sensor_uuid: Uuid) -> Result<Option<VerifyReport>> {
+        self.inner.verify_sensor(sensor_uuid).await
+    }
+
+    async fn add_annotation(&self, sensor_uuid: Uuid, annotation: &Annotation) -> Result<bool> {
+        self.inner.add_annotation(sensor_uuid, annotation).await
+    }
+
+    async fn query_annotations(
+        &self,
+        sensor_uuid: Uuid,
+        time_range: TimeRange,
+    ) -> Result<Option<Vec<Annotation>>> {
+        self.inner.query_annotations(sensor_uuid, time_range).await
+    }
+
+    async fn raw_sql_query(
+        &self,
+        sql: &str,
+        max_rows: usize,
+        timeout: Duration,
+    ) -> Result<RawSqlResult> {
+        self.inner.raw_sql_query(sql, max_rows, timeout).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::Semaphore;
+
+    /// A storage publishing a batch each time a permit is added.
+    #[derive(Debug)]
+    pub(crate) struct SlowStorage {
+        pub permits: Semaphore,
+    }
+
+    #[async_trait]
+    impl StorageInstance for SlowStorage {
+        async fn create_or_migrate(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn publish(&self, _batch: Arc<Batch>, sync_sender: Sender<()>) -> Result<()> {
+            self.permits.acquire().await?.forget();
+            _ = sync_sender.try_broadcast(());
+            Ok(())
+        }
+        async fn sync(&self, sync_sender: Sender<()>) -> Result<()> {
+            _ = sync_sender.try_broadcast(());
+            Ok(())
+        }
+        async fn vacuum(&self) -> Result<()> {
+            Ok(())
+        }
+        async fn list_sensors(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    pub(crate) async fn publish_batches(storage: &WriteBufferStorage, count: usize) -> usize {
+        let mut failures = 0;
+        for _ in 0..count {
+            let batch = Arc::new(Batch::new(smallvec::smallvec![]));
+            if storage
+                .publish(batch, async_broadcast::broadcast(1).0)
+                .await
+                .is_err()
+            {
+                failures += 1;
+            }
+        }
+        failures
+    }
+
+    #[tokio::test]
+    async fn test_write_buffer() {
+        let slow_storage = Arc::new(SlowStorage {
+            permits: Semaphore::new(0),
+        });
+        let storage = WriteBufferStorage::new(
+            slow_storage.clone(),
+            WriteBufferOptions {
+                capacity: 4,
+                high_water_mark: 2,
+            },
+        );
+
+        // The worker takes one batch out of the buffer and waits on it.
+        assert_eq!(publish_batches(&storage, 1).await, 0);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(publish_batches(&storage, 6).await, 2);
+        let status = storage.write_buffer_status().unwrap();
+        assert_eq!(status.depth, 4);
+        assert_eq!(status.max_depth, 4);
+        assert_eq!(status.dropped, 2);
+        assert!(status.is_degraded());
+
+        // The storage catches up.
+        slow_storage.permits.add_permits(5);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let status = storage.write_buffer_status().unwrap();
+        assert_eq!(status.depth, 0);
+        assert!(!status.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn test_write_buffer_sync() {
+        let slow_storage = Arc::new(SlowStorage {
+            permits: Semaphore::new(0),
+        });
+        let storage = WriteBufferStorage::new(
+            slow_storage.clone(),
+            WriteBufferOptions {
+                capacity: 1,
+                high_water_mark: 1,
+            },
+        );
+
+        // The worker waits on the first batch, the second one fills the buffer.
+        assert_eq!(publish_batches(&storage, 1).await, 0);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(publish_batches(&storage, 1).await, 0);
+
+        // The rejected batch closes its sync channel.
+        let (sync_sender, mut sync_receiver) = async_broadcast::broadcast(1);
+        let batch = Arc::new(Batch::new(smallvec::smallvec![]));
+        assert!(storage.publish(batch, sync_sender).await.is_err());
+        assert!(sync_receiver.recv().await.is_err());
+
+        // The sync waits for the buffered batches.
+        let (sync_sender, mut sync_receiver) = async_broadcast::broadcast(1);
+        let sync = tokio::spawn(async move { storage.sync(sync_sender).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(sync_receiver.try_recv().is_err());
+        slow_storage.permits.add_permits(2);
+        sync.await.unwrap().unwrap();
+        tokio::time::timeout(Duration::from_secs(1), sync_receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}